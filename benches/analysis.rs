@@ -0,0 +1,170 @@
+//! CFG construction, liveness, dominator tree and register allocation on
+//! synthetic functions: straight-line code from 1k to 100k instructions
+//! with a sliding window of live values, chains of up to 10k if/else
+//! diamonds, and counted loop nests up to 32 deep. Inputs are built once
+//! and lowered (SSA destruction, ABI) the way `compile_function` hands
+//! them to the allocator; only the measured stage runs inside the timing
+//! loop.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use lancy::codegen::analysis::cfg::CFG;
+use lancy::codegen::analysis::dom_tree::DomTree;
+use lancy::codegen::analysis::layout::BlockLayout;
+use lancy::codegen::analysis::liveness::LiveRanges;
+use lancy::codegen::isa::x64::builder::FuncBuilder;
+use lancy::codegen::isa::x64::inst::{Cond, X64Inst};
+use lancy::codegen::isa::x64::passes::abi_lower::SysVAmd64Lowering;
+use lancy::codegen::isa::x64::pipeline::default_ra_config;
+use lancy::codegen::passes::{AbiLowering, destroy_ssa};
+use lancy::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocator};
+use lancy::codegen::tir::{Func, Reg};
+
+/// Values kept live at once by `straight_line`; more than the allocatable
+/// pool, so the allocator splits and spills.
+const WINDOW: usize = 16;
+
+/// One block of roughly `insts` instructions. Each new value combines the
+/// newest value with the one `WINDOW` back, so `WINDOW` values are live
+/// at every point.
+fn straight_line(insts: usize) -> FuncBuilder {
+    let mut b = FuncBuilder::new("straight");
+    let x = b.arg();
+    let mut live: Vec<Reg> = (0..WINDOW as i64).map(|i| b.iconst64(i)).collect();
+    live.push(x);
+    // Each `add`/`xor` is a copy plus the op.
+    for i in 0..insts / 2 {
+        let (a, c) = (live[live.len() - 1], live[live.len() - WINDOW]);
+        let v = if i % 2 == 0 { b.add(a, c) } else { b.xor(a, c) };
+        live.push(v);
+    }
+    let r = live[live.len() - 1];
+    b.ret(r);
+    b
+}
+
+/// `n` if/else diamonds in sequence, each merging its arms' values with a
+/// phi: `3n + 1` blocks.
+fn diamond_chain(n: usize) -> FuncBuilder {
+    let mut b = FuncBuilder::new("diamonds");
+    let x = b.arg();
+    let mut acc = b.iconst64(0);
+    for _ in 0..n {
+        let (then_blk, else_blk, join) = (b.new_block(), b.new_block(), b.new_block());
+        b.branch_icmp(Cond::L, acc, x, then_blk, else_blk);
+        b.switch_to_block(then_blk);
+        let t = b.add(acc, x);
+        b.jmp(join);
+        b.switch_to_block(else_blk);
+        let e = b.sub(acc, x);
+        b.jmp(join);
+        b.switch_to_block(join);
+        acc = b.phi(vec![(then_blk, t), (else_blk, e)]);
+    }
+    b.ret(acc);
+    b
+}
+
+/// `depth` nested counted loops, each running to the argument and adding
+/// `body` values into an accumulator carried through every level's phis.
+fn loop_nest(depth: usize, body: usize) -> FuncBuilder {
+    let mut b = FuncBuilder::new("nest");
+    let n = b.arg();
+    let acc = b.iconst64(0);
+    let acc = nest_level(&mut b, n, acc, depth, body);
+    b.ret(acc);
+    b
+}
+
+fn nest_level(b: &mut FuncBuilder, n: Reg, acc: Reg, depth: usize, body: usize) -> Reg {
+    let pre = b.current_block();
+    let header = b.new_block();
+    let body_blk = b.new_block();
+    let exit = b.new_block();
+    let zero = b.iconst64(0);
+    b.jmp(header);
+
+    b.switch_to_block(header);
+    let (i, i_phi) = b.phi_with_id(vec![(pre, zero)]);
+    let (a, a_phi) = b.phi_with_id(vec![(pre, acc)]);
+    b.branch_icmp(Cond::L, i, n, body_blk, exit);
+
+    b.switch_to_block(body_blk);
+    let mut a_next = a;
+    if depth > 1 {
+        a_next = nest_level(b, n, a_next, depth - 1, body);
+    }
+    for _ in 0..body {
+        a_next = b.add(a_next, i);
+    }
+    let one = b.iconst64(1);
+    let i_next = b.add(i, one);
+    let latch = b.current_block();
+    b.jmp(header);
+    b.set_phi_incoming(i_phi, vec![(pre, zero), (latch, i_next)]);
+    b.set_phi_incoming(a_phi, vec![(pre, acc), (latch, a_next)]);
+
+    b.switch_to_block(exit);
+    a
+}
+
+/// The function and allocator config as `compile_function` would pass
+/// them to regalloc.
+fn lowered(b: FuncBuilder) -> (Func<X64Inst>, RegAllocConfig) {
+    let mut func = b.build();
+    destroy_ssa(&mut func);
+    let abi = SysVAmd64Lowering.lower(&mut func).expect("lowers");
+    (func, default_ra_config(abi.reg_bind))
+}
+
+fn inputs() -> Vec<(String, Func<X64Inst>, RegAllocConfig)> {
+    let mut out = Vec::new();
+    for insts in [1_000, 10_000, 100_000] {
+        let (f, c) = lowered(straight_line(insts));
+        out.push((format!("straight/{insts}"), f, c));
+    }
+    for n in [100, 1_000, 10_000] {
+        let (f, c) = lowered(diamond_chain(n));
+        out.push((format!("diamonds/{n}"), f, c));
+    }
+    for depth in [4, 16, 32] {
+        let (f, c) = lowered(loop_nest(depth, 32));
+        out.push((format!("nest/{depth}"), f, c));
+    }
+    out
+}
+
+fn analyses(c: &mut Criterion) {
+    let inputs = inputs();
+    let mut group = c.benchmark_group("analysis");
+    for (name, func, ra_cfg) in &inputs {
+        let insts: usize = func.blocks_iter().map(|(_, bd)| bd.len()).sum();
+        group.throughput(Throughput::Elements(insts as u64));
+        let cfg = CFG::compute(func).expect("terminated");
+        let layout = BlockLayout::compute(func);
+
+        group.bench_with_input(BenchmarkId::new("cfg", name), func, |b, f| {
+            b.iter(|| CFG::compute(black_box(f)));
+        });
+        group.bench_with_input(BenchmarkId::new("dom_tree", name), &cfg, |b, cfg| {
+            b.iter(|| DomTree::compute(black_box(cfg)).expect("consistent CFG"));
+        });
+        group.bench_with_input(BenchmarkId::new("liveness", name), func, |b, f| {
+            b.iter(|| LiveRanges::compute(black_box(f), &cfg, &layout).expect("fresh side tables"));
+        });
+        group.bench_with_input(BenchmarkId::new("regalloc", name), func, |b, f| {
+            b.iter(|| LinearScan::allocate(black_box(f), &cfg, ra_cfg));
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    // The 100k-instruction allocations take long enough that criterion's
+    // default 100 samples would dominate a full run.
+    config = Criterion::default().sample_size(10);
+    targets = analyses
+}
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! `FixedBitSet` iteration and whole-set operations on sets the size of a
+//! large function's vreg space, at the densities liveness and regalloc
+//! actually see.
+
+use std::hint::black_box;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use lancy::support::bitset::FixedBitSet;
+
+const SIZE: usize = 8192;
+
+/// Every `stride`-th bit set.
+fn strided(stride: usize) -> FixedBitSet {
+    let mut bs = FixedBitSet::zeroes(SIZE);
+    for i in (0..SIZE).step_by(stride) {
+        bs.add(i);
+    }
+    bs
+}
+
+fn iteration(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitset_iter");
+    for stride in [1, 7, 97, 1031] {
+        let bs = strided(stride);
+        group.bench_with_input(BenchmarkId::new("ones", stride), &bs, |b, bs| {
+            b.iter(|| black_box(bs).iter_ones().sum::<usize>());
+        });
+        group.bench_with_input(BenchmarkId::new("zeroes", stride), &bs, |b, bs| {
+            b.iter(|| black_box(bs).iter_zeroes().sum::<usize>());
+        });
+    }
+    group.finish();
+}
+
+/// The word-wise operations liveness's dataflow spends its time in.
+fn operations(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bitset_ops");
+    let (a, b) = (strided(7), strided(97));
+    group.bench_function("union", |bench| {
+        let mut x = a.clone();
+        bench.iter(|| x.union(black_box(&b)));
+    });
+    group.bench_function("intersect", |bench| {
+        let mut x = a.clone();
+        bench.iter(|| x.intersect(black_box(&b)));
+    });
+    group.bench_function("difference", |bench| {
+        let mut x = a.clone();
+        bench.iter(|| x.difference(black_box(&b)));
+    });
+    group.bench_function("union_with", |bench| {
+        let mut x = a.clone();
+        bench.iter(|| x.union_with(black_box(&b)));
+    });
+    group.bench_function("is_subset_of", |bench| {
+        bench.iter(|| black_box(&b).is_subset_of(black_box(&a)));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, iteration, operations);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+//! Generate a function from the fuzz input, compile it with the IR
+//! verifier and the symbolic allocation checker on, and on failure report
+//! a shrunk text-IR reproducer.
+//!
+//! Run with `cargo fuzz run regalloc` from the repository root.
+
+#![no_main]
+
+use lancy::codegen::isa::x64::regalloc_fuzz::{check, failure_kind, generate, shrink};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let src = generate(data);
+    if let Err(msg) = check(&src) {
+        let kind = failure_kind(&msg).to_string();
+        let small = shrink(&src, |s| check(s).is_err_and(|m| failure_kind(&m) == kind));
+        panic!("{msg}\n--- reproducer ---\n{small}");
+    }
+});
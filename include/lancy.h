@@ -0,0 +1,109 @@
+/*
+ * C API for the lancy x64 code generator (Rust feature `capi`).
+ *
+ * Build:  cargo rustc -p lancy --lib --release --features capi --crate-type cdylib
+ *
+ * Functions are built with a lancy_func_t, moved into a lancy_module_t,
+ * and compiled to machine code plus call-site relocations. Vregs and
+ * blocks are uint32_t ids; block 0 is the entry. A failing call returns
+ * LANCY_INVALID, -1 or NULL and leaves a message for lancy_last_error().
+ * Handles are not thread-safe; the error message is per thread.
+ */
+#ifndef LANCY_H
+#define LANCY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LancyModule lancy_module_t;
+typedef struct LancyFunc lancy_func_t;
+typedef struct LancyCompiled lancy_compiled_t;
+
+#define LANCY_INVALID UINT32_MAX
+
+/* lancy_func_binop ops, on 64-bit integers. */
+#define LANCY_ADD 0
+#define LANCY_SUB 1
+#define LANCY_MUL 2
+#define LANCY_AND 3
+#define LANCY_OR 4
+#define LANCY_XOR 5
+#define LANCY_SHL 6
+#define LANCY_SHR 7
+#define LANCY_SAR 8
+#define LANCY_SDIV 9
+#define LANCY_SREM 10
+#define LANCY_UDIV 11
+#define LANCY_UREM 12
+
+/* Comparison conditions. */
+#define LANCY_EQ 0
+#define LANCY_NE 1
+#define LANCY_SLT 2
+#define LANCY_SLE 3
+#define LANCY_SGT 4
+#define LANCY_SGE 5
+#define LANCY_ULT 6
+#define LANCY_ULE 7
+#define LANCY_UGT 8
+#define LANCY_UGE 9
+
+/* lancy_module_compile optimization levels. */
+#define LANCY_OPT_NONE 0
+#define LANCY_OPT_DEFAULT 1
+#define LANCY_OPT_SIZE 2
+
+/* Message of the last failed call on this thread, or NULL. */
+const char *lancy_last_error(void);
+
+lancy_module_t *lancy_module_new(void);
+void lancy_module_free(lancy_module_t *m);
+/* Parse every `func` in text IR into m. */
+int lancy_module_parse(lancy_module_t *m, const char *src);
+/* Move f into m; f is freed either way. */
+int lancy_module_add_func(lancy_module_t *m, lancy_func_t *f);
+/* Compile and remove every function in m. */
+lancy_compiled_t *lancy_module_compile(lancy_module_t *m, uint32_t opt_level);
+
+lancy_func_t *lancy_func_new(const char *name);
+/* Discard a function never added to a module. */
+void lancy_func_free(lancy_func_t *f);
+uint32_t lancy_func_arg(lancy_func_t *f);
+uint32_t lancy_func_iconst(lancy_func_t *f, int64_t imm);
+uint32_t lancy_func_binop(lancy_func_t *f, uint32_t op, uint32_t a, uint32_t b);
+/* 1 if a <cond> b, else 0. */
+uint32_t lancy_func_icmp(lancy_func_t *f, uint32_t cond, uint32_t a, uint32_t b);
+uint32_t lancy_func_load_i64(lancy_func_t *f, uint32_t base, int32_t disp);
+int lancy_func_store_i64(lancy_func_t *f, uint32_t base, int32_t disp, uint32_t val);
+uint32_t lancy_func_call(lancy_func_t *f, const char *symbol, const uint32_t *args,
+                         size_t nargs);
+uint32_t lancy_func_new_block(lancy_func_t *f);
+int lancy_func_switch_to_block(lancy_func_t *f, uint32_t block);
+/* A phi; must precede every non-phi instruction of its block. */
+uint32_t lancy_func_phi(lancy_func_t *f);
+int lancy_func_phi_add_incoming(lancy_func_t *f, uint32_t phi, uint32_t pred, uint32_t val);
+int lancy_func_jmp(lancy_func_t *f, uint32_t dst);
+int lancy_func_branch_icmp(lancy_func_t *f, uint32_t cond, uint32_t a, uint32_t b,
+                           uint32_t taken, uint32_t not_taken);
+int lancy_func_ret(lancy_func_t *f, uint32_t val);
+
+void lancy_compiled_free(lancy_compiled_t *c);
+size_t lancy_compiled_count(const lancy_compiled_t *c);
+/* Strings and code are owned by c. */
+const char *lancy_compiled_name(const lancy_compiled_t *c, size_t i);
+const uint8_t *lancy_compiled_code(const lancy_compiled_t *c, size_t i, size_t *len);
+size_t lancy_compiled_reloc_count(const lancy_compiled_t *c, size_t i);
+/* Callee symbol of relocation j; *offset is where its 8-byte absolute
+ * address goes in the code. */
+const char *lancy_compiled_reloc(const lancy_compiled_t *c, size_t i, size_t j,
+                                 size_t *offset);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LANCY_H */
@@ -1,21 +1,351 @@
-use lancy::codegen::isa::x64::builder::FuncBuilder;
+//! `lancy` command-line driver: compile a text-IR file and print its IR,
+//! disassembly or assembler source, or write a relocatable object.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use lancy::codegen::analysis::verify::verify;
+use lancy::codegen::isa::Target;
+use lancy::codegen::isa::x64::mc::disasm::write_disassembly;
+use lancy::codegen::isa::x64::mc::gas::write_gas_to;
+use lancy::codegen::isa::x64::parser::parse_module;
 use lancy::codegen::isa::x64::pipeline;
+use lancy::codegen::module::ModuleDecls;
+use lancy::codegen::object::write_object;
+use lancy::codegen::options::{
+    CodegenOptions, FramePointer, OptLevel, RegAllocKind, SpeculationHardening, SwitchLowering,
+};
+use lancy::codegen::stats;
+use lancy::codegen::timing::PassTimings;
+use lancy::codegen::tir::{ModuleProfile, PrintOptions};
+
+const USAGE: &str = "\
+usage: lancy [options] <input>
+
+Compiles every `func` in <input> (`-` for stdin).
+
+options:
+  --emit=<kind>       tir: the parsed IR; asm: disassembly (default);
+                      gas: GNU assembler source; obj: a relocatable
+                      object (needs -o)
+  -o <path>           write output to <path> instead of stdout
+  --target=<name>     x64-sysv (default)
+  --func=<name>       only compile the named function
+  --profile=<path>    attach block and edge counts from <path> (see
+                      `ModuleProfile`) to the functions they name
+  -O0                 required passes only
+  -O                  run the optimization passes (default)
+  -Os                 optimize for code size over speed
+  --no-coalesce       keep every copy as a real mov
+  --regalloc=<kind>   linear-scan (default); regalloc2: the regalloc2
+                      crate's allocator (needs the `regalloc2` feature)
+  --plan-spills       decide spills from next-use distances before allocation
+  --cpu-features=<set>
+                      x86-64-v2 (default), x86-64, x86-64-v3, native, or
+                      a comma list of sse4.1, popcnt, avx, avx2
+  --switch-lowering=<kind>
+                      auto: pick per switch (default); table: a jump
+                      table where the range allows; tree: compare trees
+  --speculation-hardening=<kind>
+                      off (default); retpoline: indirect calls and jumps
+                      go through retpoline thunks; lfence: each is
+                      preceded by an lfence
+  --no-stack-probes   allocate frames past the guard page without probing
+  --no-red-zone       never keep a leaf function's frame below rsp
+  --frame-pointer=<kind>
+                      always (default): every function sets up rbp;
+                      omit: leaf functions address their frame off rsp
+  --stack-protector[=<handler>]
+                      check a stack canary before returning from functions
+                      with stack buffers; on a mismatch call <handler>
+                      (default __stack_chk_fail)
+  --cet               emit endbr64 landing pads and mark objects CET
+                      (IBT and shadow stack) compatible
+  --coverage          count entries into every block in a per-function
+                      __lancy_cov_<func> table
+  --verify            verify the IR after every pass
+  --check-regalloc    replay the register allocation and check every use
+  --time-passes       report per-pass wall time on stderr
+  --stats             report pass statistics on stderr (needs the
+                      `stats` feature)
+  --print-after-all   dump the IR after every pass to stderr
+  --dump-dir=<dir>    write --print-after-all and --dump-dot dumps into <dir>
+  --dump-dot          write GraphViz CFG, dominator-tree and interference
+                      graphs (coloured by allocation) as <func>.<graph>.dot
+  -h, --help          show this message
+
+environment:
+  LANCY_PRINT_AFTER_ALL   same as --print-after-all
+  LANCY_TRACE=<level>     with the `tracing` feature: log allocator and
+                          pass decisions at error|warn|info|debug|trace";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Tir,
+    Asm,
+    Gas,
+    Obj,
+}
+
+struct Args {
+    input: String,
+    output: Option<PathBuf>,
+    emit: Emit,
+    target: Target,
+    func: Option<String>,
+    profile: Option<PathBuf>,
+    stats: bool,
+    options: CodegenOptions,
+}
+
+fn parse_args(mut argv: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut input = None;
+    let mut args = Args {
+        input: String::new(),
+        output: None,
+        emit: Emit::Asm,
+        target: Target::X64SysV,
+        func: None,
+        profile: None,
+        stats: false,
+        options: CodegenOptions::default(),
+    };
+    while let Some(arg) = argv.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f, Some(v.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match flag {
+            "--emit" => {
+                args.emit = match value.as_deref() {
+                    Some("tir") => Emit::Tir,
+                    Some("asm") => Emit::Asm,
+                    Some("gas") => Emit::Gas,
+                    Some("obj") => Emit::Obj,
+                    other => return Err(format!("unknown --emit kind {other:?}")),
+                }
+            }
+            "-o" => {
+                let path = argv.next().ok_or("-o needs a path")?;
+                args.output = Some(PathBuf::from(path));
+            }
+            "--target" => {
+                args.target = match value.as_deref() {
+                    Some("x64-sysv") => Target::X64SysV,
+                    other => return Err(format!("unknown target {other:?}")),
+                }
+            }
+            "--func" => args.func = Some(value.ok_or("--func needs a name")?),
+            "--profile" => {
+                args.profile = Some(PathBuf::from(value.ok_or("--profile needs a path")?));
+            }
+            "-O0" => args.options.opt_level = OptLevel::None,
+            "-O" => args.options.opt_level = OptLevel::Default,
+            "-Os" => args.options.opt_level = OptLevel::Size,
+            "--no-coalesce" => args.options.coalesce = false,
+            "--plan-spills" => args.options.plan_spills = true,
+            "--cpu-features" => {
+                args.options.cpu_features =
+                    value.ok_or("--cpu-features needs a set")?.parse()?;
+            }
+            "--regalloc" => {
+                args.options.regalloc = match value.as_deref() {
+                    Some("linear-scan") => RegAllocKind::LinearScan,
+                    #[cfg(feature = "regalloc2")]
+                    Some("regalloc2") => RegAllocKind::Regalloc2,
+                    #[cfg(not(feature = "regalloc2"))]
+                    Some("regalloc2") => {
+                        return Err("--regalloc=regalloc2 needs lancy built with the `regalloc2` \
+                                    feature"
+                            .into());
+                    }
+                    other => return Err(format!("unknown --regalloc kind {other:?}")),
+                }
+            }
+            "--switch-lowering" => {
+                args.options.switch_lowering = match value.as_deref() {
+                    Some("auto") => SwitchLowering::Auto,
+                    Some("table") => SwitchLowering::JumpTable,
+                    Some("tree") => SwitchLowering::CompareTree,
+                    other => return Err(format!("unknown --switch-lowering kind {other:?}")),
+                }
+            }
+            "--speculation-hardening" => {
+                args.options.speculation_hardening = match value.as_deref() {
+                    Some("off") => SpeculationHardening::Off,
+                    Some("retpoline") => SpeculationHardening::Retpoline,
+                    Some("lfence") => SpeculationHardening::Lfence,
+                    other => return Err(format!("unknown --speculation-hardening kind {other:?}")),
+                }
+            }
+            "--no-stack-probes" => args.options.stack_probes = false,
+            "--no-red-zone" => args.options.red_zone = false,
+            "--frame-pointer" => {
+                args.options.frame_pointer = match value.as_deref() {
+                    Some("always") => FramePointer::Always,
+                    Some("omit") => FramePointer::Omit,
+                    other => return Err(format!("unknown --frame-pointer kind {other:?}")),
+                }
+            }
+            "--stack-protector" => {
+                args.options.stack_protector =
+                    Some(value.unwrap_or_else(|| "__stack_chk_fail".to_string()));
+            }
+            "--cet" => args.options.cet = true,
+            "--coverage" => args.options.coverage = true,
+            "--verify" => args.options.verify = true,
+            "--check-regalloc" => args.options.check_regalloc = true,
+            "--time-passes" => args.options.time_passes = true,
+            "--stats" if cfg!(feature = "stats") => args.stats = true,
+            "--stats" => return Err("--stats needs lancy built with the `stats` feature".into()),
+            "--print-after-all" => args.options.print_after_all = true,
+            "--dump-dot" => args.options.dump_dot = true,
+            "--dump-dir" => {
+                args.options.dump_dir =
+                    Some(PathBuf::from(value.ok_or("--dump-dir needs a path")?));
+            }
+            "-h" | "--help" => return Err(String::new()),
+            _ if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option `{flag}`"));
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    args.input = input.ok_or("no input file")?;
+    if args.emit == Emit::Obj && args.output.is_none() {
+        return Err("--emit=obj needs -o <path>".into());
+    }
+    Ok(args)
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let src = if args.input == "-" {
+        std::io::read_to_string(std::io::stdin()).map_err(|e| format!("stdin: {e}"))?
+    } else {
+        std::fs::read_to_string(&args.input).map_err(|e| format!("{}: {e}", args.input))?
+    };
+    let mut funcs = parse_module(&src).map_err(|e| format!("{}:{e}", args.input))?;
+    if let Some(name) = &args.func {
+        funcs.retain(|f| f.name() == name);
+        if funcs.is_empty() {
+            return Err(format!("no function named `{name}`"));
+        }
+    }
+    if let Some(path) = &args.profile {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let profile =
+            ModuleProfile::parse(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        profile.apply(&mut funcs);
+    }
+
+    // Text outputs stream straight into the destination rather than
+    // building the whole listing first.
+    let write = |body: &dyn Fn(&mut dyn Write) -> io::Result<()>| {
+        let (sink, what): (Box<dyn Write>, String) = match &args.output {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+                (Box::new(file), path.display().to_string())
+            }
+            None => (Box::new(io::stdout().lock()), "stdout".to_string()),
+        };
+        let mut w = BufWriter::new(sink);
+        body(&mut w)
+            .and_then(|()| w.flush())
+            .map_err(|e| format!("{what}: {e}"))
+    };
+
+    if args.emit == Emit::Tir {
+        return write(&|mut w| {
+            for f in &funcs {
+                f.write_to(&mut w, &PrintOptions::default())?;
+                writeln!(w)?;
+            }
+            Ok(())
+        });
+    }
+
+    // Malformed input is the user's error, not a pipeline panic.
+    for f in &funcs {
+        verify(f).map_err(|e| format!("`{}`: {e}", f.name()))?;
+    }
+    let mut timings = PassTimings::new(args.options.time_passes);
+    let compiled = pipeline::try_compile_module(funcs, args.target, &args.options)
+        .map_err(|e| e.to_string())?;
+    for code in &compiled {
+        timings.merge(&code.timings);
+    }
+    if timings.is_enabled() {
+        eprint!("{}", timings.report());
+    }
+    if args.stats {
+        eprint!("{}", stats::report());
+    }
+
+    match args.emit {
+        Emit::Asm => write(&|mut w| {
+            for (i, code) in compiled.iter().enumerate() {
+                if i > 0 {
+                    writeln!(w)?;
+                }
+                write_disassembly(&mut w, code)?;
+            }
+            Ok(())
+        }),
+        Emit::Gas => write(&|mut w| write_gas_to(&mut w, &compiled, &ModuleDecls::default())),
+        Emit::Obj => {
+            let bytes = write_object(args.target, &compiled, &ModuleDecls::default())
+                .map_err(|e| e.to_string())?;
+            write(&|w| w.write_all(&bytes))
+        }
+        Emit::Tir => unreachable!("handled before compilation"),
+    }
+}
+
+/// Print `tracing` events to stderr when `LANCY_TRACE` names a level.
+#[cfg(feature = "tracing")]
+fn init_tracing() -> Result<(), String> {
+    let Ok(level) = std::env::var("LANCY_TRACE") else {
+        return Ok(());
+    };
+    let level: tracing::Level = level
+        .parse()
+        .map_err(|_| format!("LANCY_TRACE: unknown level `{level}`"))?;
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(())
+}
 
-fn main() {
-    let mut b = FuncBuilder::new("add");
-    let x = b.arg();
-    let y = b.arg();
-    let s = b.add(x, y);
-    b.ret(s);
-    let func = b.build();
-    println!("TIR:\n{func}");
-
-    let module = pipeline::jit(func).expect("JIT compile+load");
-    println!("code @ {:p}, {} bytes mapped", module.code_ptr(), module.size());
-
-    type Add = unsafe extern "sysv64" fn(i64, i64) -> i64;
-    // SAFETY: the compiled function has exactly this signature.
-    let f: Add = unsafe { module.entry() };
-    println!("add(2, 3) = {}", unsafe { f(2, 3) });
-    println!("add(100, -5) = {}", unsafe { f(100, -5) });
+fn main() -> ExitCode {
+    #[cfg(feature = "tracing")]
+    if let Err(msg) = init_tracing() {
+        eprintln!("lancy: {msg}");
+        return ExitCode::FAILURE;
+    }
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            if !msg.is_empty() {
+                eprintln!("lancy: {msg}");
+            }
+            eprintln!("{USAGE}");
+            return if msg.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            };
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("lancy: {msg}");
+            ExitCode::FAILURE
+        }
+    }
 }
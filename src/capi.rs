@@ -0,0 +1,768 @@
+//! C API over the x64 pipeline, declared in `include/lancy.h`.
+//!
+//! A `LancyModule` collects functions, either built op by op through a
+//! `LancyFunc` (a `FuncBuilder`) or parsed from text IR. Compiling a module
+//! yields a `LancyCompiled` holding each function's bytes and call-site
+//! relocations. Vregs and blocks cross the boundary as `uint32_t` ids;
+//! ops and conditions as the `LANCY_*` codes, so an out-of-range value is
+//! an error rather than undefined behaviour.
+//!
+//! Nothing unwinds into C: a failing call returns `LANCY_INVALID`, `-1` or
+//! null and leaves a message for `lancy_last_error` on the calling thread.
+//! Malformed IR that only the pipeline catches (it panics) is reported the
+//! same way by `lancy_module_compile`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::builder::FuncBuilder;
+use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+use crate::codegen::isa::x64::parser::parse_module;
+use crate::codegen::isa::x64::pipeline::{CompiledCode, compile_module};
+use crate::codegen::options::{CodegenOptions, OptLevel};
+use crate::codegen::tir::{Block, Func, PhiId, Reg};
+use crate::support::slotmap::Key;
+
+/// Returned in place of a vreg or block id when a call fails.
+pub const LANCY_INVALID: u32 = u32::MAX;
+
+pub const LANCY_ADD: u32 = 0;
+pub const LANCY_SUB: u32 = 1;
+pub const LANCY_MUL: u32 = 2;
+pub const LANCY_AND: u32 = 3;
+pub const LANCY_OR: u32 = 4;
+pub const LANCY_XOR: u32 = 5;
+pub const LANCY_SHL: u32 = 6;
+pub const LANCY_SHR: u32 = 7;
+pub const LANCY_SAR: u32 = 8;
+pub const LANCY_SDIV: u32 = 9;
+pub const LANCY_SREM: u32 = 10;
+pub const LANCY_UDIV: u32 = 11;
+pub const LANCY_UREM: u32 = 12;
+
+pub const LANCY_EQ: u32 = 0;
+pub const LANCY_NE: u32 = 1;
+pub const LANCY_SLT: u32 = 2;
+pub const LANCY_SLE: u32 = 3;
+pub const LANCY_SGT: u32 = 4;
+pub const LANCY_SGE: u32 = 5;
+pub const LANCY_ULT: u32 = 6;
+pub const LANCY_ULE: u32 = 7;
+pub const LANCY_UGT: u32 = 8;
+pub const LANCY_UGE: u32 = 9;
+
+pub const LANCY_OPT_NONE: u32 = 0;
+pub const LANCY_OPT_DEFAULT: u32 = 1;
+pub const LANCY_OPT_SIZE: u32 = 2;
+
+/// Functions waiting to be compiled.
+#[derive(Default)]
+pub struct LancyModule {
+    funcs: Vec<Func<X64Inst>>,
+}
+
+/// A function under construction.
+pub struct LancyFunc {
+    builder: FuncBuilder,
+    /// Incoming pairs of each phi, keyed by its vreg; `FuncBuilder` only
+    /// replaces the whole list.
+    phis: HashMap<Reg, (PhiId, Vec<(Block, Reg)>)>,
+}
+
+/// Compiled output of a module, in the module's function order.
+pub struct LancyCompiled {
+    funcs: Vec<CompiledFunc>,
+}
+
+struct CompiledFunc {
+    name: CString,
+    code: CompiledCode,
+    symbols: Vec<CString>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: impl Into<String>) {
+    let msg = CString::new(msg.into().replace('\0', "\\0")).expect("NULs escaped");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+/// Run `f`, turning an `Err` or a panic into `fallback` plus a
+/// `lancy_last_error` message.
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => v,
+        Ok(Err(msg)) => {
+            set_error(msg);
+            fallback
+        }
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| (*s).to_string()))
+                .unwrap_or_else(|| "non-string panic".to_string());
+            set_error(msg);
+            fallback
+        }
+    }
+}
+
+/// `ptr` as a `&str`, or an error naming `what`.
+///
+/// # Safety
+/// `ptr` must be null or a NUL-terminated string.
+unsafe fn str_arg<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, String> {
+    if ptr.is_null() {
+        return Err(format!("{what} is null"));
+    }
+    // SAFETY: non-null and NUL-terminated per the caller's contract.
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|_| format!("{what} is not UTF-8"))
+}
+
+/// `ptr` as a reference, or an error naming `what`.
+///
+/// # Safety
+/// `ptr` must be null or point to a live `T`.
+unsafe fn handle<'a, T>(ptr: *mut T, what: &str) -> Result<&'a mut T, String> {
+    // SAFETY: null or live and unaliased for the call per the contract.
+    unsafe { ptr.as_mut() }.ok_or_else(|| format!("{what} is null"))
+}
+
+/// Status-returning calls: `0` on success, `-1` with an error set.
+fn status(f: impl FnOnce() -> Result<(), String>) -> i32 {
+    guard(-1, || f().map(|()| 0))
+}
+
+impl LancyFunc {
+    fn vreg(&self, v: u32) -> Result<Reg, String> {
+        if (v as usize) < self.builder.func().get_regs_count() {
+            Ok(v)
+        } else {
+            Err(format!("unknown vreg {v}"))
+        }
+    }
+
+    fn block(&self, b: u32) -> Result<Block, String> {
+        if (b as usize) < self.builder.func().blocks_count() {
+            Ok(Block::new(b as usize))
+        } else {
+            Err(format!("unknown block {b}"))
+        }
+    }
+}
+
+fn cond(code: u32) -> Result<Cond, String> {
+    Ok(match code {
+        LANCY_EQ => Cond::Z,
+        LANCY_NE => Cond::NZ,
+        LANCY_SLT => Cond::L,
+        LANCY_SLE => Cond::LE,
+        LANCY_SGT => Cond::G,
+        LANCY_SGE => Cond::GE,
+        LANCY_ULT => Cond::B,
+        LANCY_ULE => Cond::BE,
+        LANCY_UGT => Cond::A,
+        LANCY_UGE => Cond::AE,
+        _ => return Err(format!("unknown condition code {code}")),
+    })
+}
+
+/// The message of the last failed call on this thread, or null. Valid
+/// until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn lancy_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |s| s.as_ptr()))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn lancy_module_new() -> *mut LancyModule {
+    Box::into_raw(Box::default())
+}
+
+/// # Safety
+/// `m` must be null or a module from `lancy_module_new` not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_module_free(m: *mut LancyModule) {
+    if !m.is_null() {
+        // SAFETY: allocated by `lancy_module_new` per the contract.
+        drop(unsafe { Box::from_raw(m) });
+    }
+}
+
+/// Parse every `func` in the text IR `src` into `m`.
+///
+/// # Safety
+/// `m` must be a live module and `src` a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_module_parse(m: *mut LancyModule, src: *const c_char) -> i32 {
+    status(|| {
+        // SAFETY: per the contract.
+        let (m, src) = unsafe { (handle(m, "module")?, str_arg(src, "source")?) };
+        let funcs = parse_module(src).map_err(|e| e.to_string())?;
+        m.funcs.extend(funcs);
+        Ok(())
+    })
+}
+
+/// Move the function built in `f` into `m`. `f` is freed either way.
+///
+/// # Safety
+/// `m` must be a live module and `f` a live function from
+/// `lancy_func_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_module_add_func(m: *mut LancyModule, f: *mut LancyFunc) -> i32 {
+    status(|| {
+        if f.is_null() {
+            return Err("function is null".into());
+        }
+        // SAFETY: allocated by `lancy_func_new` per the contract.
+        let f = unsafe { Box::from_raw(f) };
+        // SAFETY: per the contract.
+        let m = unsafe { handle(m, "module")? };
+        m.funcs.push(f.builder.build());
+        Ok(())
+    })
+}
+
+/// Compile and remove every function in `m`. `opt_level` is a
+/// `LANCY_OPT_*` code. Returns null on failure.
+///
+/// # Safety
+/// `m` must be a live module.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_module_compile(
+    m: *mut LancyModule,
+    opt_level: u32,
+) -> *mut LancyCompiled {
+    guard(std::ptr::null_mut(), || {
+        // SAFETY: per the contract.
+        let m = unsafe { handle(m, "module")? };
+        let opt_level = match opt_level {
+            LANCY_OPT_NONE => OptLevel::None,
+            LANCY_OPT_DEFAULT => OptLevel::Default,
+            LANCY_OPT_SIZE => OptLevel::Size,
+            _ => return Err(format!("unknown opt level {opt_level}")),
+        };
+        let options = CodegenOptions {
+            opt_level,
+            ..CodegenOptions::default()
+        };
+        let compiled = compile_module(std::mem::take(&mut m.funcs), Target::X64SysV, &options);
+        let mut funcs = Vec::with_capacity(compiled.len());
+        for code in compiled {
+            funcs.push(CompiledFunc {
+                name: CString::new(code.name.clone()).map_err(|e| e.to_string())?,
+                symbols: code
+                    .relocations
+                    .iter()
+                    .map(|r| CString::new(r.symbol.clone()).map_err(|e| e.to_string()))
+                    .collect::<Result<_, _>>()?,
+                code,
+            });
+        }
+        Ok(Box::into_raw(Box::new(LancyCompiled { funcs })))
+    })
+}
+
+/// Start a function named `name`; its entry block is block 0.
+///
+/// # Safety
+/// `name` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_new(name: *const c_char) -> *mut LancyFunc {
+    guard(std::ptr::null_mut(), || {
+        // SAFETY: per the contract.
+        let name = unsafe { str_arg(name, "name")? };
+        Ok(Box::into_raw(Box::new(LancyFunc {
+            builder: FuncBuilder::new(name),
+            phis: HashMap::new(),
+        })))
+    })
+}
+
+/// Discard a function that will not be added to a module.
+///
+/// # Safety
+/// `f` must be null or a live function from `lancy_func_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_free(f: *mut LancyFunc) {
+    if !f.is_null() {
+        // SAFETY: allocated by `lancy_func_new` per the contract.
+        drop(unsafe { Box::from_raw(f) });
+    }
+}
+
+/// Run `op` on the live function `f`, mapping failure to `fallback`.
+///
+/// # Safety
+/// `f` must be null or a live function from `lancy_func_new`.
+unsafe fn with_func<T>(
+    f: *mut LancyFunc,
+    fallback: T,
+    op: impl FnOnce(&mut LancyFunc) -> Result<T, String>,
+) -> T {
+    guard(fallback, || {
+        // SAFETY: per the caller's contract.
+        op(unsafe { handle(f, "function")? })
+    })
+}
+
+/// The next integer argument, as a vreg.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_arg(f: *mut LancyFunc) -> u32 {
+    // SAFETY: per the contract.
+    unsafe { with_func(f, LANCY_INVALID, |f| Ok(f.builder.arg())) }
+}
+
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_iconst(f: *mut LancyFunc, imm: i64) -> u32 {
+    // SAFETY: per the contract.
+    unsafe { with_func(f, LANCY_INVALID, |f| Ok(f.builder.iconst64(imm))) }
+}
+
+/// `a <op> b` on 64-bit integers; `op` is a `LANCY_ADD`.. code.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_binop(f: *mut LancyFunc, op: u32, a: u32, b: u32) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            let (a, b) = (f.vreg(a)?, f.vreg(b)?);
+            let fb = &mut f.builder;
+            Ok(match op {
+                LANCY_ADD => fb.add(a, b),
+                LANCY_SUB => fb.sub(a, b),
+                LANCY_MUL => fb.imul(a, b),
+                LANCY_AND => fb.and(a, b),
+                LANCY_OR => fb.or(a, b),
+                LANCY_XOR => fb.xor(a, b),
+                LANCY_SHL => fb.shl(a, b),
+                LANCY_SHR => fb.shr(a, b),
+                LANCY_SAR => fb.sar(a, b),
+                LANCY_SDIV => fb.sdiv(a, b),
+                LANCY_SREM => fb.srem(a, b),
+                LANCY_UDIV => fb.udiv(a, b),
+                LANCY_UREM => fb.urem(a, b),
+                _ => return Err(format!("unknown op code {op}")),
+            })
+        })
+    }
+}
+
+/// `1` if `a <cond> b`, else `0`.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_icmp(f: *mut LancyFunc, cc: u32, a: u32, b: u32) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            let (a, b) = (f.vreg(a)?, f.vreg(b)?);
+            Ok(f.builder.icmp_to_i64(cond(cc)?, a, b))
+        })
+    }
+}
+
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_load_i64(f: *mut LancyFunc, base: u32, disp: i32) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            let base = f.vreg(base)?;
+            Ok(f.builder.load_i64(base, disp))
+        })
+    }
+}
+
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_store_i64(
+    f: *mut LancyFunc,
+    base: u32,
+    disp: i32,
+    val: u32,
+) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let (base, val) = (f.vreg(base)?, f.vreg(val)?);
+            f.builder.store_i64(base, disp, val);
+            Ok(0)
+        })
+    }
+}
+
+/// Call the external or module-local function `symbol` with `nargs`
+/// integer arguments; returns its result.
+///
+/// # Safety
+/// `f` must be a live function, `symbol` a NUL-terminated string and
+/// `args` point to `nargs` vreg ids (or be null when `nargs` is 0).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_call(
+    f: *mut LancyFunc,
+    symbol: *const c_char,
+    args: *const u32,
+    nargs: usize,
+) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            let symbol = str_arg(symbol, "symbol")?;
+            let args = if nargs == 0 {
+                &[][..]
+            } else if args.is_null() {
+                return Err("args is null".into());
+            } else {
+                std::slice::from_raw_parts(args, nargs)
+            };
+            let args = args
+                .iter()
+                .map(|&a| f.vreg(a))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(f.builder.call_sym(symbol, &args))
+        })
+    }
+}
+
+/// A new, empty block.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_new_block(f: *mut LancyFunc) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            Ok(f.builder.new_block().index() as u32)
+        })
+    }
+}
+
+/// Append subsequent instructions to `block`.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_switch_to_block(f: *mut LancyFunc, block: u32) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let block = f.block(block)?;
+            f.builder.switch_to_block(block);
+            Ok(0)
+        })
+    }
+}
+
+/// A phi at the current position, which must precede every non-phi
+/// instruction of its block. Give it one incoming value per predecessor
+/// with `lancy_func_phi_add_incoming`, before or after the predecessor is
+/// built.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_phi(f: *mut LancyFunc) -> u32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, LANCY_INVALID, |f| {
+            let (dst, id) = f.builder.phi_with_id(Vec::new());
+            f.phis.insert(dst, (id, Vec::new()));
+            Ok(dst)
+        })
+    }
+}
+
+/// `phi` takes `val` when entered from `pred`.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_phi_add_incoming(
+    f: *mut LancyFunc,
+    phi: u32,
+    pred: u32,
+    val: u32,
+) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let (pred, val) = (f.block(pred)?, f.vreg(val)?);
+            let (id, incoming) = f
+                .phis
+                .get_mut(&phi)
+                .ok_or_else(|| format!("vreg {phi} is not a phi"))?;
+            incoming.push((pred, val));
+            let (id, incoming) = (*id, incoming.clone());
+            f.builder.set_phi_incoming(id, incoming);
+            Ok(0)
+        })
+    }
+}
+
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_jmp(f: *mut LancyFunc, dst: u32) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let dst = f.block(dst)?;
+            f.builder.jmp(dst);
+            Ok(0)
+        })
+    }
+}
+
+/// Branch to `taken` if `a <cond> b`, else to `not_taken`.
+///
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_branch_icmp(
+    f: *mut LancyFunc,
+    cc: u32,
+    a: u32,
+    b: u32,
+    taken: u32,
+    not_taken: u32,
+) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let (a, b) = (f.vreg(a)?, f.vreg(b)?);
+            let (taken, not_taken) = (f.block(taken)?, f.block(not_taken)?);
+            f.builder.branch_icmp(cond(cc)?, a, b, taken, not_taken);
+            Ok(0)
+        })
+    }
+}
+
+/// # Safety
+/// `f` must be a live function.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_func_ret(f: *mut LancyFunc, val: u32) -> i32 {
+    // SAFETY: per the contract.
+    unsafe {
+        with_func(f, -1, |f| {
+            let val = f.vreg(val)?;
+            f.builder.ret(val);
+            Ok(0)
+        })
+    }
+}
+
+/// # Safety
+/// `c` must be null or a result of `lancy_module_compile` not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_free(c: *mut LancyCompiled) {
+    if !c.is_null() {
+        // SAFETY: allocated by `lancy_module_compile` per the contract.
+        drop(unsafe { Box::from_raw(c) });
+    }
+}
+
+/// # Safety
+/// `c` must be a live compile result.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_count(c: *const LancyCompiled) -> usize {
+    // SAFETY: per the contract.
+    unsafe { c.as_ref() }.map_or(0, |c| c.funcs.len())
+}
+
+/// Function `i`'s compiled form, or an error for an out-of-range index.
+///
+/// # Safety
+/// `c` must be null or a live compile result.
+unsafe fn compiled<'a>(c: *const LancyCompiled, i: usize) -> Result<&'a CompiledFunc, String> {
+    // SAFETY: per the caller's contract.
+    let c = unsafe { c.as_ref() }.ok_or("compile result is null")?;
+    c.funcs
+        .get(i)
+        .ok_or_else(|| format!("function index {i} out of range"))
+}
+
+/// Name of function `i`; owned by `c`.
+///
+/// # Safety
+/// `c` must be a live compile result.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_name(c: *const LancyCompiled, i: usize) -> *const c_char {
+    guard(std::ptr::null(), || {
+        // SAFETY: per the contract.
+        Ok(unsafe { compiled(c, i)? }.name.as_ptr())
+    })
+}
+
+/// Machine code of function `i`, `*len` bytes long; owned by `c`.
+///
+/// # Safety
+/// `c` must be a live compile result and `len` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_code(
+    c: *const LancyCompiled,
+    i: usize,
+    len: *mut usize,
+) -> *const u8 {
+    guard(std::ptr::null(), || {
+        // SAFETY: per the contract.
+        let (f, len) = unsafe { (compiled(c, i)?, handle(len, "len")?) };
+        *len = f.code.bytes.len();
+        Ok(f.code.bytes.as_ptr())
+    })
+}
+
+/// Number of call-site relocations in function `i`.
+///
+/// # Safety
+/// `c` must be a live compile result.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_reloc_count(c: *const LancyCompiled, i: usize) -> usize {
+    // SAFETY: per the contract.
+    guard(0, || Ok(unsafe { compiled(c, i)? }.code.relocations.len()))
+}
+
+/// Relocation `j` of function `i`: the callee symbol (owned by `c`), and
+/// in `*offset` the code offset of the 8-byte absolute address to patch.
+///
+/// # Safety
+/// `c` must be a live compile result and `offset` writable.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn lancy_compiled_reloc(
+    c: *const LancyCompiled,
+    i: usize,
+    j: usize,
+    offset: *mut usize,
+) -> *const c_char {
+    guard(std::ptr::null(), || {
+        // SAFETY: per the contract.
+        let (f, offset) = unsafe { (compiled(c, i)?, handle(offset, "offset")?) };
+        let reloc = f
+            .code
+            .relocations
+            .get(j)
+            .ok_or_else(|| format!("relocation index {j} out of range"))?;
+        *offset = reloc.offset;
+        Ok(f.symbols[j].as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::jit::Module;
+
+    fn last_error() -> String {
+        let e = lancy_last_error();
+        assert!(!e.is_null());
+        unsafe { CStr::from_ptr(e) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn loop_built_through_the_c_api_runs() {
+        // sum(n) = 0 + 1 + ... + (n - 1)
+        unsafe {
+            let f = lancy_func_new(c"sum".as_ptr());
+            let n = lancy_func_arg(f);
+            let zero = lancy_func_iconst(f, 0);
+            let one = lancy_func_iconst(f, 1);
+            let (header, body, exit) = (
+                lancy_func_new_block(f),
+                lancy_func_new_block(f),
+                lancy_func_new_block(f),
+            );
+            assert_eq!(lancy_func_jmp(f, header), 0);
+            lancy_func_switch_to_block(f, header);
+            let i = lancy_func_phi(f);
+            let acc = lancy_func_phi(f);
+            lancy_func_branch_icmp(f, LANCY_SLT, i, n, body, exit);
+            lancy_func_switch_to_block(f, body);
+            let acc2 = lancy_func_binop(f, LANCY_ADD, acc, i);
+            let i2 = lancy_func_binop(f, LANCY_ADD, i, one);
+            lancy_func_jmp(f, header);
+            for (phi, init, next) in [(i, zero, i2), (acc, zero, acc2)] {
+                assert_eq!(lancy_func_phi_add_incoming(f, phi, 0, init), 0);
+                assert_eq!(lancy_func_phi_add_incoming(f, phi, body, next), 0);
+            }
+            lancy_func_switch_to_block(f, exit);
+            lancy_func_ret(f, acc);
+
+            let m = lancy_module_new();
+            assert_eq!(lancy_module_add_func(m, f), 0);
+            let c = lancy_module_compile(m, LANCY_OPT_DEFAULT);
+            assert!(!c.is_null(), "{}", last_error());
+            assert_eq!(lancy_compiled_count(c), 1);
+            assert_eq!(CStr::from_ptr(lancy_compiled_name(c, 0)), c"sum");
+            let mut len = 0;
+            let code = lancy_compiled_code(c, 0, &raw mut len);
+            let jit = Module::load(std::slice::from_raw_parts(code, len)).unwrap();
+            let sum: extern "C" fn(i64) -> i64 = jit.entry();
+            assert_eq!(sum(10), 45);
+            lancy_compiled_free(c);
+            lancy_module_free(m);
+        }
+    }
+
+    #[test]
+    fn parsed_functions_report_their_relocations() {
+        let src = c"func @wrap(%x) {\n    %r = call @ext(%x)\n    ret %r\n}\n";
+        unsafe {
+            let m = lancy_module_new();
+            assert_eq!(lancy_module_parse(m, src.as_ptr()), 0, "{}", last_error());
+            let c = lancy_module_compile(m, LANCY_OPT_NONE);
+            assert_eq!(lancy_compiled_reloc_count(c, 0), 1);
+            let mut offset = 0;
+            let sym = lancy_compiled_reloc(c, 0, 0, &raw mut offset);
+            assert_eq!(CStr::from_ptr(sym), c"ext");
+            let mut len = 0;
+            let _ = lancy_compiled_code(c, 0, &raw mut len);
+            assert!(offset + 8 <= len);
+            assert!(lancy_compiled_reloc(c, 0, 1, &raw mut offset).is_null());
+            assert_eq!(last_error(), "relocation index 1 out of range");
+            lancy_compiled_free(c);
+            lancy_module_free(m);
+        }
+    }
+
+    #[test]
+    fn bad_input_fails_with_a_message_instead_of_unwinding() {
+        unsafe {
+            let m = lancy_module_new();
+            assert_eq!(lancy_module_parse(m, c"func f( {".as_ptr()), -1);
+            assert!(last_error().starts_with("line 1:"), "{}", last_error());
+
+            let f = lancy_func_new(c"f".as_ptr());
+            assert_eq!(lancy_func_binop(f, 99, 0, 0), LANCY_INVALID);
+            let x = lancy_func_arg(f);
+            assert_eq!(lancy_func_binop(f, 99, x, x), LANCY_INVALID);
+            assert_eq!(last_error(), "unknown op code 99");
+            assert_eq!(lancy_func_jmp(f, 7), -1);
+            assert_eq!(last_error(), "unknown block 7");
+            // No terminator: the pipeline panics, the API reports it.
+            assert_eq!(lancy_module_add_func(m, f), 0);
+            assert!(lancy_module_compile(m, LANCY_OPT_DEFAULT).is_null());
+            assert!(!last_error().is_empty());
+            lancy_module_free(m);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Static block frequency estimate.
+//!
+//! Without a profile, the only structure that reliably predicts hot code
+//! is the loop nest. Each block's loop depth is the number of natural
+//! loops containing it (a loop per header, unioned over its back edges);
+//! its relative frequency is `LOOP_SCALE ^ depth`, saturating.
+//!
+//! A function carrying a `Profile` has measured frequencies instead:
+//! each block's count divided by the entry's, rounded up so only blocks
+//! that never ran get 0. Blocks the profile has no count for keep the
+//! estimate.
+//!
+//! Frontend `BlockHint`s mark cold code without a profile: a block hinted
+//! `Unlikely`, or one only entered over edges that lose to a `Likely`
+//! sibling, and everything it dominates, gets frequency 0.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::tir::{Annotate, Block, BlockHint, Func, Inst};
+use crate::support::bitset::FixedBitSet;
+use crate::support::slotmap::{Key, SecondaryMap};
+
+/// Assumed trip count of every loop.
+pub const LOOP_SCALE: u32 = 8;
+
+/// Weight of an edge into a `Likely` block, against 1 for an unhinted
+/// block and 0 for an `Unlikely` one.
+pub const HINT_SCALE: u64 = 64;
+
+/// Relative weight of the edge `from → to`: the profile's count if `func`
+/// has one, else derived from `to`'s block hint.
+#[must_use]
+pub fn edge_weight<I: Inst>(func: &Func<I>, from: Block, to: Block) -> u64 {
+    match func.profile() {
+        Some(profile) => profile.edge_weight(from, to).unwrap_or(0),
+        None => hint_weight(func, to),
+    }
+}
+
+fn hint_weight<I: Inst>(func: &Func<I>, to: Block) -> u64 {
+    match func.block_hint(to) {
+        Some(BlockHint::Likely) => HINT_SCALE,
+        Some(BlockHint::Unlikely) => 0,
+        None => 1,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockFrequency {
+    loop_depth: SecondaryMap<Block, u32>,
+    /// Profile-derived frequencies, overriding the estimate.
+    measured: HashMap<Block, u32>,
+    /// Blocks the frontend's hints put on a cold path.
+    unlikely: HashSet<Block>,
+}
+
+impl BlockFrequency {
+    #[must_use]
+    pub fn compute(cfg: &CFG, dt: &DomTree) -> Self {
+        let n = cfg.blocks_count();
+        let mut loop_depth = SecondaryMap::new(n);
+        loop_depth.fill(0);
+        // header -> latches (sources of back edges into it)
+        let mut latches: HashMap<Block, Vec<Block>> = HashMap::new();
+        for i in 0..n {
+            let b = Block::new(i);
+            for &s in cfg.succs(b) {
+                if dt.dominates(s, b) {
+                    latches.entry(s).or_default().push(b);
+                }
+            }
+        }
+        for (header, tails) in latches {
+            // Natural loop: the header plus everything that reaches a
+            // latch without passing through the header.
+            let mut body = FixedBitSet::zeroes(n);
+            body.add(header.index());
+            let mut stack = tails;
+            while let Some(b) = stack.pop() {
+                if body.has(b.index()) {
+                    continue;
+                }
+                body.add(b.index());
+                stack.extend_from_slice(cfg.preds(b));
+            }
+            for i in (0..n).filter(|&i| body.has(i)) {
+                loop_depth[Block::new(i)] += 1;
+            }
+        }
+        Self {
+            loop_depth,
+            measured: HashMap::new(),
+            unlikely: HashSet::new(),
+        }
+    }
+
+    /// `compute`, overridden by `func`'s profile where it has counts and
+    /// by its block hints.
+    #[must_use]
+    pub fn for_func<I: Inst>(func: &Func<I>, cfg: &CFG, dt: &DomTree) -> Self {
+        let mut bf = Self::compute(cfg, dt);
+        if func.has_block_hints() {
+            bf.unlikely = unlikely_blocks(func, cfg, dt);
+        }
+        let Some(profile) = func.profile() else {
+            return bf;
+        };
+        let Some(entry) = profile.block_weight(cfg.get_entry_block()).filter(|&c| c > 0) else {
+            return bf;
+        };
+        for i in 0..cfg.blocks_count() {
+            let b = Block::new(i);
+            if let Some(count) = profile.block_weight(b) {
+                let freq = u32::try_from(count.div_ceil(entry)).unwrap_or(u32::MAX);
+                bf.measured.insert(b, freq);
+            }
+        }
+        bf
+    }
+
+    #[must_use]
+    pub fn loop_depth(&self, b: Block) -> u32 {
+        self.loop_depth[b]
+    }
+
+    /// Relative execution frequency; the entry block is 1.
+    #[must_use]
+    pub fn freq(&self, b: Block) -> u32 {
+        match self.measured.get(&b) {
+            Some(&f) => f,
+            None if self.unlikely.contains(&b) => 0,
+            None => LOOP_SCALE.saturating_pow(self.loop_depth[b]),
+        }
+    }
+}
+
+/// Prints each block's loop depth and frequency on its header line.
+impl<I: Inst> Annotate<I> for BlockFrequency {
+    fn block(&self, block: Block) -> Option<String> {
+        Some(format!("depth {}, freq {}", self.loop_depth(block), self.freq(block)))
+    }
+}
+
+/// Blocks dominated by a cold root: a block hinted `Unlikely`, or one whose
+/// every incoming edge is outweighed by a sibling's hint.
+fn unlikely_blocks<I: Inst>(func: &Func<I>, cfg: &CFG, dt: &DomTree) -> HashSet<Block> {
+    let entry = cfg.get_entry_block();
+    let n = cfg.blocks_count();
+    let loses = |p: Block, b: Block| {
+        let w = hint_weight(func, b);
+        cfg.succs(p).iter().any(|&s| hint_weight(func, s) > w)
+    };
+    let is_root = |b: Block| {
+        let preds = cfg.preds(b);
+        func.block_hint(b) == Some(BlockHint::Unlikely)
+            || (!preds.is_empty() && preds.iter().all(|&p| loses(p, b)))
+    };
+    let roots: Vec<Block> = (0..n)
+        .map(Block::new)
+        .filter(|&b| b != entry && is_root(b))
+        .collect();
+    (0..n)
+        .map(Block::new)
+        .filter(|&b| roots.iter().any(|&r| dt.dominates(r, b)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_loops_scale_the_inner_body_twice() {
+        // 0 -> 1 (outer header) -> 2 (inner header) -> 3 -> 2, 3 -> 1, 1 -> 4
+        let b = |i| Block::new(i);
+        let mut cfg = CFG::new(b(0), 5);
+        for (f, t) in [(0, 1), (1, 2), (2, 3), (3, 2), (3, 1), (1, 4)] {
+            cfg.add_edge(b(f), b(t));
+        }
+        let dt = DomTree::compute(&cfg).unwrap();
+        let bf = BlockFrequency::compute(&cfg, &dt);
+        let depths: Vec<u32> = (0..5).map(|i| bf.loop_depth(b(i))).collect();
+        assert_eq!(depths, [0, 1, 2, 2, 0]);
+        assert_eq!(bf.freq(b(3)), LOOP_SCALE * LOOP_SCALE);
+        assert_eq!(bf.freq(b(4)), 1);
+    }
+
+    #[test]
+    fn annotated_listing_shows_depth_and_frequency_per_block() {
+        use crate::codegen::isa::x64::parser::parse_func_text;
+
+        let func = parse_func_text(
+            "func @f(%n) {
+             entry:
+                 jmp head
+             head:
+                 br ge %n, %n, exit, head
+             exit:
+                 ret %n
+             }",
+        )
+        .expect("parses");
+        let cfg = CFG::compute(&func).unwrap();
+        let dt = DomTree::compute(&cfg).unwrap();
+        let bf = BlockFrequency::for_func(&func, &cfg, &dt);
+        let text = func.printer().with_annotations(&bf).to_string();
+        assert!(text.contains("@0 ; depth 0, freq 1\n"), "{text}");
+        assert!(text.contains(&format!("@1 ; depth 1, freq {LOOP_SCALE}\n")), "{text}");
+    }
+
+    #[test]
+    fn profile_counts_override_the_estimate() {
+        use crate::codegen::isa::x64::builder::FuncBuilder;
+        use crate::codegen::isa::x64::inst::Cond;
+        use crate::codegen::tir::Profile;
+
+        // entry -> {hot, never} -> exit, run 4 times.
+        let mut fb = FuncBuilder::new("f");
+        let x = fb.arg();
+        let zero = fb.iconst64(0);
+        let hot = fb.new_block();
+        let never = fb.new_block();
+        let exit = fb.new_block();
+        fb.branch_icmp(Cond::NZ, x, zero, hot, never);
+        for blk in [hot, never] {
+            fb.switch_to_block(blk);
+            fb.jmp(exit);
+        }
+        fb.switch_to_block(exit);
+        fb.ret(x);
+        let mut func = fb.build();
+        let entry = func.get_entry_block().unwrap();
+        let mut profile = Profile::default();
+        profile.block_counts.insert(entry, 4);
+        profile.edge_counts.insert((entry, hot), 10);
+        profile.block_counts.insert(never, 0);
+        func.set_profile(profile);
+
+        let cfg = CFG::compute(&func).unwrap();
+        let dt = DomTree::compute(&cfg).unwrap();
+        let bf = BlockFrequency::for_func(&func, &cfg, &dt);
+        assert_eq!(bf.freq(entry), 1);
+        assert_eq!(bf.freq(hot), 3, "10 / 4, rounded up");
+        assert_eq!(bf.freq(never), 0);
+        assert_eq!(bf.freq(exit), 1, "no count: the static estimate");
+    }
+
+    #[test]
+    fn hints_cool_the_paths_they_mark() {
+        use crate::codegen::isa::x64::builder::FuncBuilder;
+        use crate::codegen::isa::x64::inst::Cond;
+
+        // entry -> {fast (likely), slow -> slow2}; second -> {err (unlikely), exit}.
+        let mut fb = FuncBuilder::new("f");
+        let x = fb.arg();
+        let zero = fb.iconst64(0);
+        let [fast, slow, slow2, second, err, exit] = [(); 6].map(|()| fb.new_block());
+        fb.branch_icmp(Cond::NZ, x, zero, fast, slow);
+        fb.switch_to_block(slow);
+        fb.jmp(slow2);
+        fb.switch_to_block(slow2);
+        fb.jmp(second);
+        fb.switch_to_block(fast);
+        fb.jmp(second);
+        fb.switch_to_block(second);
+        fb.branch_icmp(Cond::Z, x, zero, err, exit);
+        for blk in [err, exit] {
+            fb.switch_to_block(blk);
+            fb.ret(x);
+        }
+        fb.set_block_hint(fast, Some(BlockHint::Likely));
+        fb.set_block_hint(err, Some(BlockHint::Unlikely));
+        let func = fb.build();
+
+        let cfg = CFG::compute(&func).unwrap();
+        let dt = DomTree::compute(&cfg).unwrap();
+        let bf = BlockFrequency::for_func(&func, &cfg, &dt);
+        let freqs = [fast, slow, slow2, second, err, exit].map(|b| bf.freq(b));
+        assert_eq!(freqs, [1, 0, 0, 1, 0, 1]);
+        assert_eq!(edge_weight(&func, second, err), 0);
+        assert_eq!(edge_weight(&func, func.get_entry_block().unwrap(), fast), HINT_SCALE);
+    }
+}
@@ -0,0 +1,234 @@
+//! Module call graph.
+//!
+//! Nodes are the module's function bodies, by index into the slice the
+//! graph was built from. A direct call to a symbol the slice defines is an
+//! edge; calls to anything else are recorded per caller as external, and
+//! indirect calls only as a flag. Strongly connected components (Tarjan)
+//! are listed bottom-up — every SCC after the SCCs it calls — which is the
+//! order an inliner or an interprocedural summary wants to visit them in.
+
+use std::collections::HashMap;
+
+use crate::codegen::tir::{CallTarget, Func, Inst, Instruction, PseudoInstruction};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallGraph {
+    index: HashMap<String, usize>,
+    /// Defined callees per function, sorted and deduplicated.
+    callees: Vec<Vec<usize>>,
+    callers: Vec<Vec<usize>>,
+    /// Symbols called but not defined in the slice, sorted and deduplicated.
+    external: Vec<Vec<String>>,
+    indirect: Vec<bool>,
+    sccs: Vec<Vec<usize>>,
+    scc_of: Vec<usize>,
+}
+
+impl CallGraph {
+    #[must_use]
+    pub fn compute<I: Inst>(funcs: &[Func<I>]) -> Self {
+        let n = funcs.len();
+        let index: HashMap<String, usize> =
+            funcs.iter().enumerate().map(|(i, f)| (f.name().to_string(), i)).collect();
+        let mut callees = vec![Vec::new(); n];
+        let mut callers = vec![Vec::new(); n];
+        let mut external = vec![Vec::new(); n];
+        let mut indirect = vec![false; n];
+        for (i, func) in funcs.iter().enumerate() {
+            for (_, bd) in func.blocks_iter() {
+                for inst in bd.iter() {
+                    let Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) = inst else {
+                        continue;
+                    };
+                    match &func.call_operands(*id).callee {
+                        CallTarget::Symbol(s) => match index.get(s) {
+                            Some(&callee) => callees[i].push(callee),
+                            None => external[i].push(s.clone()),
+                        },
+                        CallTarget::Indirect(_) => indirect[i] = true,
+                    }
+                }
+            }
+            callees[i].sort_unstable();
+            callees[i].dedup();
+            external[i].sort_unstable();
+            external[i].dedup();
+            for &c in &callees[i] {
+                callers[c].push(i);
+            }
+        }
+        let (sccs, scc_of) = tarjan(&callees);
+        Self {
+            index,
+            callees,
+            callers,
+            external,
+            indirect,
+            sccs,
+            scc_of,
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.callees.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.callees.is_empty()
+    }
+
+    /// Index of the function named `name`, if the graph has its body.
+    #[must_use]
+    pub fn func_index(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    #[must_use]
+    pub fn callees(&self, f: usize) -> &[usize] {
+        &self.callees[f]
+    }
+
+    #[must_use]
+    pub fn callers(&self, f: usize) -> &[usize] {
+        &self.callers[f]
+    }
+
+    /// Symbols `f` calls that the graph has no body for.
+    #[must_use]
+    pub fn external_callees(&self, f: usize) -> &[String] {
+        &self.external[f]
+    }
+
+    /// `true` iff `f` calls through a function pointer.
+    #[must_use]
+    pub fn calls_indirectly(&self, f: usize) -> bool {
+        self.indirect[f]
+    }
+
+    /// Strongly connected components, callees before callers.
+    #[must_use]
+    pub fn sccs(&self) -> &[Vec<usize>] {
+        &self.sccs
+    }
+
+    /// Index into `sccs()` of the component holding `f`.
+    #[must_use]
+    pub fn scc_of(&self, f: usize) -> usize {
+        self.scc_of[f]
+    }
+
+    /// `true` iff `f` can reach itself through direct calls.
+    #[must_use]
+    pub fn is_recursive(&self, f: usize) -> bool {
+        self.sccs[self.scc_of[f]].len() > 1 || self.callees[f].binary_search(&f).is_ok()
+    }
+
+    /// Every function, callees before callers (cycles in arbitrary order).
+    pub fn bottom_up(&self) -> impl Iterator<Item = usize> + '_ {
+        self.sccs.iter().flatten().copied()
+    }
+}
+
+/// Iterative Tarjan. Components come out in reverse topological order of
+/// the condensation, i.e. bottom-up.
+fn tarjan(succs: &[Vec<usize>]) -> (Vec<Vec<usize>>, Vec<usize>) {
+    const UNVISITED: usize = usize::MAX;
+    let n = succs.len();
+    let mut order = vec![UNVISITED; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut sccs = Vec::new();
+    let mut scc_of = vec![0; n];
+    let mut next = 0;
+    // (node, index of the next successor to visit)
+    let mut work: Vec<(usize, usize)> = Vec::new();
+    for root in 0..n {
+        if order[root] != UNVISITED {
+            continue;
+        }
+        work.push((root, 0));
+        while let Some(&mut (v, ref mut i)) = work.last_mut() {
+            if *i == 0 {
+                order[v] = next;
+                low[v] = next;
+                next += 1;
+                stack.push(v);
+                on_stack[v] = true;
+            }
+            if let Some(&w) = succs[v].get(*i) {
+                *i += 1;
+                if order[w] == UNVISITED {
+                    work.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(order[w]);
+                }
+                continue;
+            }
+            work.pop();
+            if let Some(&(parent, _)) = work.last() {
+                low[parent] = low[parent].min(low[v]);
+            }
+            if low[v] == order[v] {
+                let mut scc = Vec::new();
+                loop {
+                    let w = stack.pop().expect("v is on the stack");
+                    on_stack[w] = false;
+                    scc_of[w] = sccs.len();
+                    scc.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                scc.reverse();
+                sccs.push(scc);
+            }
+        }
+    }
+    (sccs, scc_of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::X64Inst;
+
+    fn caller(name: &str, callees: &[&str]) -> Func<X64Inst> {
+        let mut b = FuncBuilder::new(name);
+        let mut v = b.arg();
+        for c in callees {
+            v = b.call_sym(c, &[v]);
+        }
+        b.ret(v);
+        b.build()
+    }
+
+    #[test]
+    fn sccs_come_bottom_up_and_flag_recursion() {
+        // main -> {even, puts}; even <-> odd; fact -> fact; leaf.
+        let funcs = vec![
+            caller("main", &["even", "puts", "even"]),
+            caller("even", &["odd"]),
+            caller("odd", &["even", "leaf"]),
+            caller("fact", &["fact"]),
+            caller("leaf", &[]),
+        ];
+        let cg = CallGraph::compute(&funcs);
+        let [main, even, odd, fact, leaf] = [0, 1, 2, 3, 4];
+
+        assert_eq!(cg.callees(main), &[even]);
+        assert_eq!(cg.external_callees(main), &["puts".to_string()]);
+        assert_eq!(cg.callers(even), &[main, odd]);
+        assert_eq!(cg.scc_of(even), cg.scc_of(odd));
+        assert!(cg.is_recursive(even) && cg.is_recursive(fact));
+        assert!(!cg.is_recursive(main) && !cg.is_recursive(leaf));
+
+        let pos = |f| cg.bottom_up().position(|g| g == f).unwrap();
+        assert!(pos(leaf) < pos(even) && pos(leaf) < pos(odd));
+        assert!(pos(even) < pos(main) && pos(odd) < pos(main));
+        assert_eq!(cg.sccs().len(), 4);
+    }
+}
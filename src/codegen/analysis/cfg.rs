@@ -1,17 +1,20 @@
-use smallvec::SmallVec;
-
+use crate::codegen::error::CodegenError;
 use crate::codegen::tir::{Block, Func, Inst, TirError};
 use crate::support::bitset::FixedBitSet;
+use crate::support::entity_list::{EntityList, ListPool};
 use crate::support::slotmap::{Key, SecondaryMap};
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug)]
 struct CFGNode {
-    successors: SmallVec<[Block; 2]>,
-    predecessors: SmallVec<[Block; 2]>,
+    successors: EntityList<Block>,
+    predecessors: EntityList<Block>,
 }
 
+#[derive(Clone)]
 pub struct CFG {
     nodes: SecondaryMap<Block, CFGNode>,
+    /// Backing storage for every node's edge lists.
+    edges: ListPool<Block>,
     entry: Block,
 }
 
@@ -22,10 +25,14 @@ impl CFG {
         nodes.fill(CFGNode::default());
         Self {
             nodes,
+            edges: ListPool::new(),
             entry,
         }
     }
-    pub fn compute<I: Inst>(func: &Func<I>) -> Result<CFG, TirError> {
+    /// # Errors
+    /// `Tir` if the function is empty, a block lacks a terminator, or a
+    /// branch targets a block the function doesn't have.
+    pub fn compute<I: Inst>(func: &Func<I>) -> Result<CFG, CodegenError> {
         let size = func.blocks_count();
         let entry = func.get_entry_block().ok_or(TirError::EmptyFunctionBody)?;
 
@@ -34,35 +41,47 @@ impl CFG {
         for (block, data) in func.blocks_iter() {
             if let Some(term) = data.get_terminator() {
                 if term.is_branch() {
-                    let targets = term.get_branch_targets();
+                    let targets = func.branch_targets(&term);
                     for t in targets {
+                        if t.index() >= size {
+                            return Err(TirError::InvalidBranchTarget(block, t).into());
+                        }
                         cfg.add_edge(block, t);
                     }
                 }
             } else {
-                return Err(TirError::BlockNotTerminated(block));
+                return Err(TirError::BlockNotTerminated(block).into());
             }
         }
 
         Ok(cfg)
     }
 
-    /// Add a directed edge `from → to`. Updates both the predecessor list of
-    /// `to` (which gains `from`) and the successor list of `from` (which
-    /// gains `to`).
+    /// Add a directed edge `from → to`: `from` joins `to`'s predecessors
+    /// and `to` joins `from`'s successors, each at its sorted position. An
+    /// edge already present is not added again.
     pub fn add_edge(&mut self, from: Block, to: Block) {
-        self.nodes.get_mut(to).unwrap().predecessors.push(from);
-        self.nodes.get_mut(from).unwrap().successors.push(to);
+        let node = self.nodes.get_mut(to).unwrap();
+        if !insert_sorted(&mut node.predecessors, from, &mut self.edges) {
+            return;
+        }
+        let node = self.nodes.get_mut(from).unwrap();
+        insert_sorted(&mut node.successors, to, &mut self.edges);
     }
 
+    /// Blocks with an edge into `block`, ascending and without repeats.
+    /// Block numbers follow layout, so this is layout order too.
     #[must_use] 
     pub fn preds(&self, block: Block) -> &[Block] {
-        &self.nodes[block].predecessors
+        self.nodes[block].predecessors.as_slice(&self.edges)
     }
 
+    /// Blocks `block` has an edge to, ascending and without repeats: a
+    /// branch with both arms on one block, or a jump table naming a
+    /// block for several cases, contributes a single edge.
     #[must_use] 
     pub fn succs(&self, block: Block) -> &[Block] {
-        &self.nodes[block].successors
+        self.nodes[block].successors.as_slice(&self.edges)
     }
 
     #[must_use] 
@@ -74,8 +93,55 @@ impl CFG {
     pub fn get_entry_block(&self) -> Block {
         self.entry
     }
+
+    /// Every edge `(from, to)` once, sorted by `from` and then by `to`.
+    pub fn edges(&self) -> impl Iterator<Item = (Block, Block)> + '_ {
+        (0..self.blocks_count())
+            .map(Block::new)
+            .flat_map(|b| self.succs(b).iter().map(move |&s| (b, s)))
+    }
 }
 
+/// Insert `k` into the sorted `list` unless it is already there; returns
+/// whether it was inserted.
+fn insert_sorted(list: &mut EntityList<Block>, k: Block, pool: &mut ListPool<Block>) -> bool {
+    let Err(at) = list.as_slice(pool).binary_search(&k) else {
+        return false;
+    };
+    list.push(k, pool);
+    list.as_mut_slice(pool)[at..].rotate_right(1);
+    true
+}
+
+/// Prints each block's successor list.
+impl std::fmt::Debug for CFG {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CFG")
+            .field("entry", &self.entry)
+            .field(
+                "succs",
+                &(0..self.blocks_count())
+                    .map(|i| self.succs(Block::new(i)))
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Two CFGs are equal when they have the same entry and the same edges
+/// in the same order, however their edge storage happens to be laid out.
+impl PartialEq for CFG {
+    fn eq(&self, other: &Self) -> bool {
+        self.entry == other.entry
+            && self.blocks_count() == other.blocks_count()
+            && (0..self.blocks_count()).map(Block::new).all(|b| {
+                self.succs(b) == other.succs(b) && self.preds(b) == other.preds(b)
+            })
+    }
+}
+
+impl Eq for CFG {}
+
 /// Reverse post-order traversal of the CFG starting from the entry block.
 ///
 /// The returned vector has the property that every block appears after all
@@ -136,6 +202,24 @@ mod tests {
         assert!(cfg.succs(b2).is_empty());
     }
 
+    #[test]
+    fn adjacency_is_sorted_and_duplicate_free_whatever_the_insertion_order() {
+        let b = |i| Block::new(i);
+        let mut fwd = CFG::new(b(0), 4);
+        for (f, t) in [(0, 1), (0, 3), (0, 1), (1, 3), (2, 3), (0, 2)] {
+            fwd.add_edge(b(f), b(t));
+        }
+        let mut rev = CFG::new(b(0), 4);
+        for (f, t) in [(0, 2), (2, 3), (1, 3), (0, 3), (0, 1)] {
+            rev.add_edge(b(f), b(t));
+        }
+        assert_eq!(fwd, rev);
+        assert_eq!(fwd.succs(b(0)), &[b(1), b(2), b(3)]);
+        assert_eq!(fwd.preds(b(3)), &[b(0), b(1), b(2)]);
+        let edges: Vec<_> = fwd.edges().map(|(f, t)| (f.index(), t.index())).collect();
+        assert_eq!(edges, [(0, 1), (0, 2), (0, 3), (1, 3), (2, 3)]);
+    }
+
     #[test]
     fn test_multiple_edges() {
         let mut cfg = CFG::new(Block::new(0), 4);
@@ -157,6 +241,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn branch_to_a_missing_block_is_an_error() {
+        use crate::codegen::isa::x64::inst::X64Inst;
+
+        let mut func = Func::<X64Inst>::new("dangling".into());
+        let b = func.add_empty_block();
+        func.get_block_data_mut(b)
+            .push_target_inst(X64Inst::Jmp { dst: Block::new(3) });
+        assert!(matches!(
+            CFG::compute(&func),
+            Err(CodegenError::Tir(TirError::InvalidBranchTarget(_, to))) if to == Block::new(3)
+        ));
+    }
+
     #[test]
     fn test_no_edges() {
         let cfg = CFG::new(Block::new(0), 2);
@@ -207,4 +305,24 @@ mod tests {
         assert!(pos(b1) < pos(b2));
         assert!(pos(b2) < pos(b3));
     }
+
+    #[test]
+    fn cfgs_compare_by_edges_not_storage() {
+        let b = |i| Block::new(i);
+        let mut a = CFG::new(b(0), 3);
+        a.add_edge(b(0), b(1));
+        a.add_edge(b(0), b(2));
+        // Grow a list across size classes so the pools differ in layout.
+        let mut c = CFG::new(b(0), 3);
+        for _ in 0..5 {
+            c.add_edge(b(1), b(2));
+        }
+        let mut c2 = CFG::new(b(0), 3);
+        c2.add_edge(b(0), b(1));
+        c2.add_edge(b(0), b(2));
+        assert_eq!(a, c2);
+        assert_ne!(a, c);
+        assert_eq!(a.clone(), a);
+        assert_eq!(format!("{a:?}"), "CFG { entry: @0, succs: [[@1, @2], [], []], .. }");
+    }
 }
@@ -1,43 +1,50 @@
 use crate::{
     codegen::analysis::cfg::{reverse_post_order, CFG},
+    codegen::error::CodegenError,
     codegen::tir::Block,
     support::slotmap::SecondaryMap,
+    support::trace::enter_span,
 };
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
 struct Node {
     rpo: u32,
     idom: Option<Block>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct DomTree {
     nodes: SecondaryMap<Block, Node>,
 }
 
 impl DomTree {
-    #[must_use]
-    pub fn compute(cfg: &CFG) -> Self {
+    /// # Errors
+    /// `NoReachablePredecessor` if the CFG's predecessor lists disagree
+    /// with its successor lists, leaving a reachable block without a
+    /// reachable predecessor.
+    pub fn compute(cfg: &CFG) -> Result<Self, CodegenError> {
+        enter_span!("dom_tree", blocks = cfg.blocks_count());
         let mut nodes = SecondaryMap::new(cfg.blocks_count());
         nodes.fill(Node::default());
         let mut res = Self { nodes };
-        res.compute_domtree(cfg);
-        res
+        res.compute_domtree(cfg)?;
+        Ok(res)
     }
 
-    fn compute_domtree(&mut self, cfg: &CFG) {
+    fn compute_domtree(&mut self, cfg: &CFG) -> Result<(), CodegenError> {
         let rpo = reverse_post_order(cfg);
         const STRIDE: u32 = 4;
         let (entry_block, reverse_postorder) = match rpo.as_slice().split_first()
         {
             Some((&eb, rest)) => (eb, rest),
-            None => return,
+            None => return Ok(()),
         };
 
-        self.nodes.get_mut(entry_block).unwrap().rpo = 2 * STRIDE;
+        self.nodes[entry_block].rpo = 2 * STRIDE;
 
         for (rpo, &block) in reverse_postorder.iter().enumerate() {
             self.nodes.set(block, Node {
-                idom: self.compute_idom(block, cfg).into(),
+                idom: Some(self.compute_idom(block, cfg)?),
                 rpo: (rpo as u32 + 3) * STRIDE,
             });
         }
@@ -47,29 +54,41 @@ impl DomTree {
             changed = false;
 
             for block in reverse_postorder {
-                let new_idom = self.compute_idom(*block, cfg).into();
+                let new_idom = Some(self.compute_idom(*block, cfg)?);
                 if self.nodes[*block].idom != new_idom {
-                    self.nodes.get_mut(*block).unwrap().idom = new_idom;
+                    self.nodes[*block].idom = new_idom;
                     changed = true;
                 }
             }
         }
+
+        #[cfg(feature = "tracing")]
+        for &block in reverse_postorder {
+            crate::support::trace::trace_event!(
+                block = ?block,
+                idom = ?self.nodes[block].idom,
+                "idom"
+            );
+        }
+        Ok(())
     }
 
-    fn compute_idom(&self, block: Block, cfg: &CFG) -> Block {
+    fn compute_idom(&self, block: Block, cfg: &CFG) -> Result<Block, CodegenError> {
         let mut reachable_preds = cfg
             .preds(block)
             .iter()
             .copied()
             .filter(|&pred| self.nodes[pred].rpo > 1);
 
-        let mut idom = reachable_preds.next().unwrap();
+        let mut idom = reachable_preds
+            .next()
+            .ok_or(CodegenError::NoReachablePredecessor(block))?;
 
         for pred in reachable_preds {
             idom = self.common_dominator(idom, pred);
         }
 
-        idom
+        Ok(idom)
     }
 
     fn common_dominator(&self, mut a: Block, mut b: Block) -> Block {
@@ -92,6 +111,13 @@ impl DomTree {
         a
     }
 
+    /// Immediate dominator of `b`; `None` for the entry and unreachable
+    /// blocks.
+    #[must_use]
+    pub fn idom(&self, b: Block) -> Option<Block> {
+        self.nodes[b].idom
+    }
+
     #[must_use]
     pub fn dominates(&self, a: Block, mut b: Block) -> bool {
         if a == b {
@@ -156,7 +182,7 @@ mod tests {
     #[test]
     fn test_simple_cfg_domtree() {
         let cfg = simple_cfg();
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         let b0 = Block(0);
         let b1 = Block(1);
@@ -175,7 +201,7 @@ mod tests {
     #[test]
     fn test_diamond_cfg_domtree() {
         let cfg = diamond_cfg();
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         let b0 = Block(0);
         let b1 = Block(1);
@@ -194,7 +220,7 @@ mod tests {
     #[test]
     fn test_self_dominance() {
         let cfg = simple_cfg();
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         for i in 0..4 {
             let b = Block(i);
@@ -209,7 +235,7 @@ mod tests {
         for i in 0..4 {
             cfg.add_edge(Block(i), Block(i + 1));
         }
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         for i in 0..5 {
             for j in i..5 {
@@ -234,7 +260,7 @@ mod tests {
         cfg.add_edge(Block(2), Block(3));
         cfg.add_edge(Block(3), Block(1)); // back edge
 
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         // 0 dominates all
         for i in 1..4 {
@@ -265,7 +291,7 @@ mod tests {
         cfg.add_edge(Block(4), Block(1)); // back edge (outer loop)
         cfg.add_edge(Block(3), Block(2)); // back edge (inner loop)
 
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         // 0 dominates all
         for i in 1..6 {
@@ -307,7 +333,7 @@ mod tests {
         cfg.add_edge(Block(3), Block(1)); // back edge (outer loop)
         cfg.add_edge(Block(4), Block(3)); // forward edge
 
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         // 0 dominates all
         for i in 1..5 {
@@ -345,7 +371,7 @@ mod tests {
         cfg.add_edge(Block(18), Block(7));
         cfg.add_edge(Block(9), Block(1));
 
-        let domtree = DomTree::compute(&cfg);
+        let domtree = DomTree::compute(&cfg).unwrap();
 
         // Check some dominance relations
         assert!(domtree.dominates(Block(0), Block(5)));
@@ -358,6 +384,25 @@ mod tests {
         assert!(!domtree.dominates(Block(5), Block(0)));
         assert!(!domtree.dominates(Block(10), Block(1)));
     }
-}
-
 
+    #[cfg(not(feature = "block-u16"))]
+    #[test]
+    fn functions_past_65k_blocks_are_analysed() {
+        use crate::codegen::isa::x64::inst::X64Inst;
+        use crate::codegen::tir::Func;
+
+        const N: usize = 70_000;
+        let mut func = Func::<X64Inst>::new("long".into());
+        let blocks: Vec<Block> = (0..N).map(|_| func.add_empty_block()).collect();
+        for w in blocks.windows(2) {
+            func.get_block_data_mut(w[0])
+                .push_target_inst(X64Inst::Jmp { dst: w[1] });
+        }
+        func.get_block_data_mut(blocks[N - 1])
+            .push_target_inst(X64Inst::Ud2);
+        let cfg = CFG::compute(&func).unwrap();
+        let domtree = DomTree::compute(&cfg).unwrap();
+        assert_eq!(domtree.idom(blocks[N - 1]), Some(blocks[N - 2]));
+        assert!(domtree.dominates(blocks[1], blocks[N - 1]));
+    }
+}
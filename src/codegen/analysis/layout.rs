@@ -14,8 +14,13 @@
 //! here produces segments that *just touch* (`v1.end == v2.start`), which is
 //! what enables coalescing: they never overlap at any integer point.
 //!
-//! Block order is `func.blocks_iter()` insertion order — the same order the
-//! MC emitter walks. Any consistent order works; we just need one.
+//! `compute` numbers blocks in `func.blocks_iter()` order — the order the
+//! MC emitter walks, and the one every allocation consumer agrees on, since
+//! assignments are positional. Liveness is exact in any order, but the
+//! allocator's spill and split choices read positions as time, so the
+//! pipeline first makes that order a linearization of the CFG
+//! (`passes::block_layout::linearize_blocks`). `with_order` numbers an
+//! explicit order instead, e.g. to export liveness for another layout.
 
 use crate::codegen::tir::{Block, Func, Inst};
 use crate::support::slotmap::SecondaryMap;
@@ -29,6 +34,7 @@ pub const POINTS_PER_INST: u32 = 2;
 /// For a block with 3 insts and `first_inst[B] = 10`, the instructions have
 /// global indices 10, 11, 12. Early points are 20, 22, 24; late points are
 /// 21, 23, 25. `last_inst[B] = 13`. `end_point(B) = 26`.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockLayout {
     pub order: Vec<Block>,
     first_inst: SecondaryMap<Block, u32>,
@@ -39,15 +45,22 @@ pub struct BlockLayout {
 impl BlockLayout {
     #[must_use]
     pub fn compute<I: Inst>(func: &Func<I>) -> Self {
+        Self::with_order(func, func.blocks_iter().map(|(b, _)| b).collect())
+    }
+
+    /// Number the blocks in `order`, which must list every block of `func`
+    /// once.
+    #[must_use]
+    pub fn with_order<I: Inst>(func: &Func<I>, order: Vec<Block>) -> Self {
+        assert_eq!(order.len(), func.blocks_count(), "order must list every block once");
         let n = func.blocks_count();
         let mut first_inst = SecondaryMap::new(n);
         let mut last_inst = SecondaryMap::new(n);
-        let mut order = Vec::with_capacity(n);
         let mut cursor: u32 = 0;
-        for (b, bd) in func.blocks_iter() {
-            order.push(b);
+        for &b in &order {
+            assert!(!first_inst.contains(b), "order lists {b} twice");
             first_inst.set(b, cursor);
-            cursor += bd.len() as u32;
+            cursor += func.get_block_data(b).len() as u32;
             last_inst.set(b, cursor);
         }
         Self {
@@ -141,5 +154,10 @@ mod tests {
         assert_eq!(layout.block_end_pt(a), 4); // 2 insts * 2 points
         assert_eq!(layout.block_start_pt(b), 4);
         assert_eq!(layout.block_end_pt(b), 6);
+
+        let swapped = BlockLayout::with_order(&func, vec![b, a]);
+        assert_eq!(swapped.block_start_pt(b), 0);
+        assert_eq!(swapped.block_start_pt(a), 2);
+        assert_eq!(swapped.def_pt(a, 1), 5);
     }
 }
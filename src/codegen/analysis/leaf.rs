@@ -0,0 +1,79 @@
+//! Leaf-function analysis.
+//!
+//! A leaf makes no calls and never moves the stack pointer past its
+//! prologue, so nothing pushes below its frame while it runs. Frame
+//! layout uses this to skip the call alignment of `rsp`, to keep a small
+//! frame in the red zone, and to address the frame off `rsp` without
+//! setting up `rbp` (`FramePointer::Omit`). A patch point counts as a
+//! call, since a runtime may patch one in.
+
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LeafInfo {
+    calls: bool,
+    dynamic_stack: bool,
+}
+
+impl LeafInfo {
+    #[must_use]
+    pub fn compute<I: Inst>(func: &Func<I>) -> Self {
+        let mut info = Self::default();
+        for (_, bd) in func.blocks_iter() {
+            for inst in bd.iter() {
+                info.calls |= inst.is_call()
+                    || matches!(inst, Instruction::Pseudo(PseudoInstruction::PatchPoint { .. }));
+                info.dynamic_stack |= inst.moves_stack_pointer();
+            }
+        }
+        info
+    }
+
+    /// Neither calls nor moves the stack pointer.
+    #[must_use]
+    pub fn is_leaf(&self) -> bool {
+        !self.calls && !self.dynamic_stack
+    }
+
+    /// Calls another function, or has a patch point that may.
+    #[must_use]
+    pub fn makes_calls(&self) -> bool {
+        self.calls
+    }
+
+    /// Moves the stack pointer after the prologue, e.g. around a call's
+    /// stack arguments.
+    #[must_use]
+    pub fn has_dynamic_stack(&self) -> bool {
+        self.dynamic_stack
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::tir::PatchKind;
+
+    fn info(insts: Vec<Instruction<X64Inst>>) -> LeafInfo {
+        let mut func = Func::<X64Inst>::new("f".to_string());
+        let b = func.add_empty_block();
+        func.get_block_data_mut(b).set_insts(insts);
+        LeafInfo::compute(&func)
+    }
+
+    #[test]
+    fn calls_patch_points_and_rsp_moves_make_a_non_leaf() {
+        let ret = Instruction::Target(X64Inst::Ud2);
+        assert!(info(vec![ret]).is_leaf());
+
+        let call = info(vec![Instruction::Target(X64Inst::Call64r { target: 0 }), ret]);
+        assert!(call.makes_calls() && !call.is_leaf());
+
+        let patch = PseudoInstruction::PatchPoint { len: 5, kind: PatchKind::Point };
+        assert!(info(vec![Instruction::Pseudo(patch), ret]).makes_calls());
+
+        let adjust = info(vec![Instruction::Target(X64Inst::AdjustRsp { delta: -16 }), ret]);
+        assert!(adjust.has_dynamic_stack() && !adjust.makes_calls() && !adjust.is_leaf());
+    }
+}
@@ -14,19 +14,32 @@
 //! share a preg iff *none* of their segments intersect. A vreg holding a
 //! preg across a hole where another vreg is live simply releases it, gets
 //! it back on the other side.
+//!
+//! `BlockLiveness` exposes the first phase on its own: the sets of vregs
+//! live into and out of every block, for passes that only care what
+//! crosses block boundaries.
+//!
+//! `LazyLiveRanges` answers the same question for one vreg at a time
+//! without the whole-function dataflow: it walks predecessors backwards
+//! from the vreg's upward-exposed uses to find the blocks it's live into,
+//! then builds segments for just those blocks. Worth it when only a few
+//! vregs will ever be asked about.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use smallvec::SmallVec;
 
 use crate::codegen::analysis::cfg::{reverse_post_order, CFG};
-use crate::codegen::analysis::layout::{BlockLayout, ProgramPoint};
-use crate::codegen::tir::{Block, Func, Inst, Reg};
-use crate::support::bitset::FixedBitSet;
+use crate::codegen::analysis::layout::{BlockLayout, POINTS_PER_INST, ProgramPoint};
+use crate::codegen::error::CodegenError;
+use crate::codegen::tir::{Annotate, Block, Func, Inst, Instruction, Reg, TirError, reg_name};
+use crate::support::bitset::{BitSet, FixedBitSet};
+use crate::support::sparse_bitset::SparseBitSet;
+use crate::support::trace::enter_span;
 use crate::support::slotmap::{Key, SecondaryMap};
 
 /// Half-open `[start, end)` interval in flat program-point space.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Segment {
     pub start: ProgramPoint,
     pub end: ProgramPoint,
@@ -54,7 +67,7 @@ impl Segment {
 
 /// A vreg's live range: sorted, non-overlapping, non-adjacent-mergeable
 /// list of `Segment`s.
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct LiveRange {
     segments: SmallVec<[Segment; 2]>,
 }
@@ -153,14 +166,46 @@ impl LiveRange {
     }
 }
 
+/// Above this many bits of dense per-block sets (`blocks × vregs`),
+/// liveness switches to `SparseBitSet`.
+pub const SPARSE_LIVENESS_BITS: usize = 1 << 22;
+
+#[derive(Clone, Debug)]
 pub struct LiveRanges {
     ranges: SecondaryMap<Reg, LiveRange>,
 }
 
 impl LiveRanges {
-    #[must_use]
-    pub fn compute<I: Inst>(func: &Func<I>, cfg: &CFG, layout: &BlockLayout) -> Self {
-        let live_out = compute_live_out(func, cfg);
+    /// Per-block liveness sets are dense `FixedBitSet`s unless
+    /// `blocks × vregs` exceeds `SPARSE_LIVENESS_BITS`, where the chunked
+    /// `SparseBitSet` keeps memory proportional to what's actually live.
+    ///
+    /// # Errors
+    /// `StaleCfg` / `StaleLayout` if `cfg` or `layout` was computed for a
+    /// different shape of `func`; `Tir(VregOutOfRange)` for an operand
+    /// `func` never allocated.
+    pub fn compute<I: Inst>(
+        func: &Func<I>,
+        cfg: &CFG,
+        layout: &BlockLayout,
+    ) -> Result<Self, CodegenError> {
+        enter_span!("liveness", func = func.name());
+        check_side_tables(func, cfg, layout)?;
+        let dense_bits = func.blocks_count().saturating_mul(func.get_regs_count());
+        Ok(if dense_bits > SPARSE_LIVENESS_BITS {
+            let sets = solve_block_liveness::<I, SparseBitSet>(func, cfg)?;
+            Self::from_live_out(func, layout, &sets.live_out)
+        } else {
+            let sets = solve_block_liveness::<I, FixedBitSet>(func, cfg)?;
+            Self::from_live_out(func, layout, &sets.live_out)
+        })
+    }
+
+    fn from_live_out<I: Inst, S: BitSet>(
+        func: &Func<I>,
+        layout: &BlockLayout,
+        live_out: &SecondaryMap<Block, S>,
+    ) -> Self {
         let mut ranges: SecondaryMap<Reg, LiveRange> = SecondaryMap::new(func.get_regs_count());
         ranges.fill(LiveRange::default());
 
@@ -207,12 +252,143 @@ impl LiveRanges {
             }
         }
 
+        #[cfg(feature = "tracing")]
+        for (r, range) in &ranges {
+            if !range.is_empty() {
+                crate::support::trace::trace_event!(
+                    vreg = r,
+                    segments = ?range.segments(),
+                    "live range"
+                );
+            }
+        }
         Self { ranges }
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (Reg, &LiveRange)> {
         self.ranges.iter()
     }
+
+    /// Flatten the ranges into a bitmap per program point of `layout`, the
+    /// layout they were computed for.
+    #[must_use]
+    pub fn live_map(&self, layout: &BlockLayout) -> LiveMap {
+        let stride = self.ranges.capacity().div_ceil(64);
+        let points = (layout.total_insts() * POINTS_PER_INST) as usize;
+        let mut words = vec![0u64; stride * points];
+        for (r, range) in &self.ranges {
+            let (word, bit) = (r as usize / 64, 1u64 << (r % 64));
+            for seg in range.segments() {
+                for pt in seg.start..seg.end.min(points as ProgramPoint) {
+                    words[pt as usize * stride + word] |= bit;
+                }
+            }
+        }
+        LiveMap { stride, words, layout: layout.clone() }
+    }
+}
+
+/// Which vregs are live into and out of each block, as bit sets indexed
+/// by vreg. Blocks unreachable from the entry have empty sets.
+#[derive(Clone, Debug)]
+pub struct BlockLiveness<S: BitSet = FixedBitSet> {
+    live_in: SecondaryMap<Block, S>,
+    live_out: SecondaryMap<Block, S>,
+}
+
+impl<S: BitSet> BlockLiveness<S> {
+    /// # Errors
+    /// `StaleCfg` if `cfg` was computed for a different shape of `func`;
+    /// `Tir(VregOutOfRange)` for an operand `func` never allocated.
+    pub fn compute<I: Inst>(func: &Func<I>, cfg: &CFG) -> Result<Self, CodegenError> {
+        enter_span!("block_liveness", func = func.name());
+        check_cfg(func, cfg)?;
+        solve_block_liveness(func, cfg)
+    }
+
+    /// Vregs live on entry to `b`.
+    #[must_use]
+    pub fn live_in(&self, b: Block) -> &S {
+        &self.live_in[b]
+    }
+
+    /// Vregs live on exit from `b`: the union of its successors' live-ins.
+    #[must_use]
+    pub fn live_out(&self, b: Block) -> &S {
+        &self.live_out[b]
+    }
+
+    /// `live_in(b)`, ascending.
+    pub fn live_in_regs(&self, b: Block) -> impl Iterator<Item = Reg> + '_ {
+        self.live_in[b].iter_ones().map(|r| r as Reg)
+    }
+
+    /// `live_out(b)`, ascending.
+    pub fn live_out_regs(&self, b: Block) -> impl Iterator<Item = Reg> + '_ {
+        self.live_out[b].iter_ones().map(|r| r as Reg)
+    }
+}
+
+/// Which vregs are live at every program point, one bitmap row per point
+/// (`ceil(vregs / 64)` words, bit `r % 64` of word `r / 64` for vreg
+/// `r`). Built once by `LiveRanges::live_map` for consumers — debuggers,
+/// verifiers, GC root maps — that want liveness at arbitrary points
+/// without running the analysis themselves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiveMap {
+    stride: usize,
+    words: Vec<u64>,
+    layout: BlockLayout,
+}
+
+impl LiveMap {
+    /// The layout the points are numbered by.
+    #[must_use]
+    pub fn layout(&self) -> &BlockLayout {
+        &self.layout
+    }
+
+    /// Raw bitmap row of `pt`.
+    #[must_use]
+    pub fn row(&self, pt: ProgramPoint) -> &[u64] {
+        let at = pt as usize * self.stride;
+        &self.words[at..at + self.stride]
+    }
+
+    #[must_use]
+    pub fn is_live_at(&self, r: Reg, pt: ProgramPoint) -> bool {
+        self.row(pt)
+            .get(r as usize / 64)
+            .is_some_and(|w| w & (1 << (r % 64)) != 0)
+    }
+
+    /// Vregs live at `pt`, ascending.
+    pub fn live_at(&self, pt: ProgramPoint) -> impl Iterator<Item = Reg> + '_ {
+        self.row(pt).iter().enumerate().flat_map(|(i, &w)| {
+            (0..64).filter(move |b| w & (1 << b) != 0).map(move |b| (i * 64 + b) as Reg)
+        })
+    }
+
+    /// Vregs live as the `idx`-th instruction of `b` reads its operands:
+    /// its uses, and everything live across it.
+    pub fn live_before(&self, b: Block, idx: u32) -> impl Iterator<Item = Reg> + '_ {
+        self.live_at(self.layout.use_pt(b, idx))
+    }
+
+    /// Vregs live as the `idx`-th instruction of `b` writes its results:
+    /// its defs, and everything live across it.
+    pub fn live_after(&self, b: Block, idx: u32) -> impl Iterator<Item = Reg> + '_ {
+        self.live_at(self.layout.def_pt(b, idx))
+    }
+}
+
+/// Prints the vregs live as each instruction reads its operands.
+impl<I: Inst> Annotate<I> for LiveMap {
+    fn inst(&self, block: Block, idx: usize, _inst: &Instruction<I>) -> Option<String> {
+        let idx = u32::try_from(idx).expect("instruction index fits a program point");
+        let live: Vec<String> = self.live_before(block, idx).map(reg_name).collect();
+        Some(format!("live: {}", live.join(", ")))
+    }
 }
 
 impl std::ops::Index<Reg> for LiveRanges {
@@ -222,23 +398,174 @@ impl std::ops::Index<Reg> for LiveRanges {
     }
 }
 
+/// Live ranges computed per vreg on first request, each identical to what
+/// `LiveRanges::compute` would give it.
+pub struct LazyLiveRanges<'a, I: Inst> {
+    func: &'a Func<I>,
+    cfg: &'a CFG,
+    layout: &'a BlockLayout,
+    /// Blocks with an instruction using or defining each vreg, in order.
+    mentions: HashMap<Reg, SmallVec<[Block; 2]>>,
+    cache: HashMap<Reg, LiveRange>,
+}
+
+impl<'a, I: Inst> LazyLiveRanges<'a, I> {
+    /// Index which blocks mention which vregs; no ranges are built yet.
+    ///
+    /// # Errors
+    /// As `LiveRanges::compute`.
+    pub fn new(
+        func: &'a Func<I>,
+        cfg: &'a CFG,
+        layout: &'a BlockLayout,
+    ) -> Result<Self, CodegenError> {
+        check_side_tables(func, cfg, layout)?;
+        let regs_count = func.get_regs_count();
+        let mut mentions: HashMap<Reg, SmallVec<[Block; 2]>> = HashMap::new();
+        for (block, bd) in func.blocks_iter() {
+            for inst in bd.iter() {
+                for r in inst.get_uses().into_iter().chain(inst.get_defs()) {
+                    if r as usize >= regs_count {
+                        return Err(TirError::VregOutOfRange(block, r).into());
+                    }
+                    let blocks = mentions.entry(r).or_default();
+                    if blocks.last() != Some(&block) {
+                        blocks.push(block);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            func,
+            cfg,
+            layout,
+            mentions,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// `r`'s live range, computed on the first call for `r`.
+    pub fn range(&mut self, r: Reg) -> &LiveRange {
+        if !self.cache.contains_key(&r) {
+            let range = self.compute(r);
+            self.cache.insert(r, range);
+        }
+        &self.cache[&r]
+    }
+
+    pub fn is_live_at(&mut self, r: Reg, pt: ProgramPoint) -> bool {
+        self.range(r).covers(pt)
+    }
+
+    fn compute(&self, r: Reg) -> LiveRange {
+        let Some(blocks) = self.mentions.get(&r) else {
+            return LiveRange::default();
+        };
+        // Blocks `r` is live into: upward-exposed uses, then every
+        // predecessor that doesn't define `r` itself, transitively.
+        let mut live_in: HashSet<Block> = HashSet::new();
+        let mut defines: HashSet<Block> = HashSet::new();
+        let mut work: Vec<Block> = Vec::new();
+        for &b in blocks {
+            let mut defined = false;
+            for inst in self.func.get_block_data(b).iter() {
+                if !defined && inst.get_uses().contains(&r) && live_in.insert(b) {
+                    work.push(b);
+                }
+                defined |= inst.get_defs().contains(&r);
+            }
+            if defined {
+                defines.insert(b);
+            }
+        }
+        let mut live_out: HashSet<Block> = HashSet::new();
+        while let Some(b) = work.pop() {
+            for &p in self.cfg.preds(b) {
+                live_out.insert(p);
+                if !defines.contains(&p) && live_in.insert(p) {
+                    work.push(p);
+                }
+            }
+        }
+
+        let mut range = LiveRange::default();
+        let touched: HashSet<Block> =
+            blocks.iter().chain(&live_in).chain(&live_out).copied().collect();
+        for block in touched {
+            let block_start = self.layout.block_start_pt(block);
+            let block_end = self.layout.block_end_pt(block);
+            let mut end = live_out.contains(&block).then_some(block_end);
+            for (idx, inst) in self.func.get_block_data(block).insts().iter().enumerate().rev() {
+                let i = idx as u32;
+                if inst.get_defs().contains(&r) {
+                    let def_pt = self.layout.def_pt(block, i);
+                    range.add(Segment {
+                        start: def_pt,
+                        end: end.take().unwrap_or(def_pt + 1),
+                    });
+                }
+                if inst.get_uses().contains(&r) {
+                    end.get_or_insert(self.layout.use_pt(block, i) + 1);
+                }
+            }
+            if let Some(end) = end {
+                range.add(Segment { start: block_start, end });
+            }
+        }
+        range
+    }
+}
+
+/// `cfg` and `layout` must describe `func`'s current blocks, or the
+/// dataflow and segment walk index past them.
+fn check_side_tables<I: Inst>(
+    func: &Func<I>,
+    cfg: &CFG,
+    layout: &BlockLayout,
+) -> Result<(), CodegenError> {
+    check_cfg(func, cfg)?;
+    for (block, bd) in func.blocks_iter() {
+        let laid_out = if block.index() < layout.order.len() {
+            ((layout.block_end_pt(block) - layout.block_start_pt(block)) / POINTS_PER_INST) as usize
+        } else {
+            0
+        };
+        if laid_out != bd.len() {
+            return Err(CodegenError::StaleLayout {
+                block,
+                insts: bd.len(),
+                laid_out,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn check_cfg<I: Inst>(func: &Func<I>, cfg: &CFG) -> Result<(), CodegenError> {
+    if cfg.blocks_count() != func.blocks_count() {
+        return Err(CodegenError::StaleCfg {
+            cfg: cfg.blocks_count(),
+            func: func.blocks_count(),
+        });
+    }
+    Ok(())
+}
+
 // -----------------------------------------------------------------------
-// Internal: iterative live_in/out dataflow. Only `live_out` escapes;
-// `live_in` is a transient needed to compute successors' `live_out` during
-// the fixpoint.
+// Internal: iterative live_in/out dataflow.
 
-fn compute_live_out<I: Inst>(
+fn solve_block_liveness<I: Inst, S: BitSet>(
     func: &Func<I>,
     cfg: &CFG,
-) -> SecondaryMap<Block, FixedBitSet> {
+) -> Result<BlockLiveness<S>, CodegenError> {
     let regs_count = func.get_regs_count();
     let blocks_count = cfg.blocks_count();
-    let mut live_in: SecondaryMap<Block, FixedBitSet> = SecondaryMap::new(blocks_count);
-    live_in.fill(FixedBitSet::zeroes(regs_count));
-    let mut live_out: SecondaryMap<Block, FixedBitSet> = SecondaryMap::new(blocks_count);
-    live_out.fill(FixedBitSet::zeroes(regs_count));
+    let mut live_in: SecondaryMap<Block, S> = SecondaryMap::new(blocks_count);
+    live_in.fill(S::empty(regs_count));
+    let mut live_out: SecondaryMap<Block, S> = SecondaryMap::new(blocks_count);
+    live_out.fill(S::empty(regs_count));
 
-    let (uses_per_block, defs_per_block) = compute_use_def(func);
+    let (uses_per_block, defs_per_block) = compute_use_def::<I, S>(func)?;
 
     // Worklist seeded with blocks in reverse-post-order (tail first): an
     // acyclic CFG converges in one sweep, loops in a small constant. We
@@ -253,9 +580,6 @@ fn compute_live_out<I: Inst>(
 
     while let Some(block) = worklist.pop() {
         in_worklist.del(block.index());
-        let old_in_count = live_in[block].ones_count();
-        let old_out_count = live_out[block].ones_count();
-
         {
             let out = live_out.get_mut(block).unwrap();
             for &s in cfg.succs(block) {
@@ -263,17 +587,16 @@ fn compute_live_out<I: Inst>(
             }
         }
 
+        // live_in only ever grows across iterations, so merging the new
+        // value in tells us exactly whether it changed.
         let new_in = {
             let mut tmp = live_out[block].clone();
             tmp.difference(&defs_per_block[block]);
             tmp.union(&uses_per_block[block]);
             tmp
         };
-        *live_in.get_mut(block).unwrap() = new_in;
 
-        if live_in[block].ones_count() != old_in_count
-            || live_out[block].ones_count() != old_out_count
-        {
+        if live_in.get_mut(block).unwrap().union_with(&new_in) {
             for &p in cfg.preds(block) {
                 if !in_worklist.has(p.index()) {
                     in_worklist.add(p.index());
@@ -283,23 +606,34 @@ fn compute_live_out<I: Inst>(
         }
     }
 
-    live_out
+    Ok(BlockLiveness { live_in, live_out })
 }
 
-fn compute_use_def<I: Inst>(
+/// Upward-exposed uses and defs per block; fails on a vreg operand
+/// outside the function's range.
+#[allow(clippy::type_complexity)]
+fn compute_use_def<I: Inst, S: BitSet>(
     func: &Func<I>,
-) -> (SecondaryMap<Block, FixedBitSet>, SecondaryMap<Block, FixedBitSet>) {
+) -> Result<(SecondaryMap<Block, S>, SecondaryMap<Block, S>), CodegenError> {
     let regs_count = func.get_regs_count();
     let blocks_count = func.blocks_count();
     let mut uses = SecondaryMap::new(blocks_count);
-    uses.fill(FixedBitSet::zeroes(regs_count));
+    uses.fill(S::empty(regs_count));
     let mut defs = SecondaryMap::new(blocks_count);
-    defs.fill(FixedBitSet::zeroes(regs_count));
+    defs.fill(S::empty(regs_count));
 
     for (block, bd) in func.blocks_iter() {
         let u = uses.get_mut(block).unwrap();
         let d = defs.get_mut(block).unwrap();
         for inst in bd.iter() {
+            if let Some(r) = inst
+                .get_uses()
+                .into_iter()
+                .chain(inst.get_defs())
+                .find(|&r| r as usize >= regs_count)
+            {
+                return Err(TirError::VregOutOfRange(block, r).into());
+            }
             for r in inst.get_uses() {
                 if !d.has(r as usize) {
                     u.add(r as usize);
@@ -311,7 +645,7 @@ fn compute_use_def<I: Inst>(
         }
     }
 
-    (uses, defs)
+    Ok((uses, defs))
 }
 
 #[cfg(test)]
@@ -319,7 +653,8 @@ mod tests {
     use super::*;
     use crate::codegen::analysis::layout::BlockLayout;
     use crate::codegen::isa::x64::inst::X64Inst;
-    use crate::codegen::tir::PseudoInstruction;
+    use crate::codegen::tir::{Instruction, PseudoInstruction};
+    use crate::tir;
 
     #[test]
     fn segment_add_merges_adjacent_and_overlapping() {
@@ -349,20 +684,14 @@ mod tests {
 
     #[test]
     fn straight_line_ranges_span_def_to_last_use() {
-        // arg v1; mov v0, v1; ret v0
-        let mut func = Func::<X64Inst>::new("t".into());
-        let b0 = func.add_empty_block();
-        let v0 = func.new_vreg();
-        let v1 = func.new_vreg();
-        {
-            let bd = func.get_block_data_mut(b0);
-            bd.push_pseudo_inst(PseudoInstruction::Arg { dst: v1, idx: 0 });
-            bd.push_target_inst(X64Inst::Mov64rr { dst: v0, src: v1 });
-            bd.push_pseudo_inst(PseudoInstruction::Return { src: v0 });
+        tir! {
+            let func = "t";
+            vregs v0, v1;
+            b0: [Arg { dst: v1, idx: 0 }, Mov64rr { dst: v0, src: v1 }, Return { src: v0 }];
         }
         let cfg = CFG::compute(&func).unwrap();
         let layout = BlockLayout::compute(&func);
-        let ranges = LiveRanges::compute(&func, &cfg, &layout);
+        let ranges = LiveRanges::compute(&func, &cfg, &layout).unwrap();
 
         // v1 defined at 1 (late of inst 0), last used at 2 (early of inst 1).
         // Segment [1, 3) — half-open end is late(1) = 3.
@@ -372,31 +701,43 @@ mod tests {
     }
 
     #[test]
-    fn value_live_through_a_block_without_using_it_has_a_through_segment() {
-        // b0: mov v0, 42; jmp b1
-        // b1: jmp b2             (v0 live-through)
-        // b2: ret v0
-        let mut func = Func::<X64Inst>::new("t".into());
-        let b0 = func.add_empty_block();
-        let b1 = func.add_empty_block();
-        let b2 = func.add_empty_block();
-        let v0 = func.new_vreg();
-        {
-            let bd = func.get_block_data_mut(b0);
-            bd.push_target_inst(X64Inst::Mov64ri { dst: v0, imm: 42 });
-            bd.push_target_inst(X64Inst::Jmp { dst: b1 });
+    fn live_map_rows_match_the_ranges_at_every_point() {
+        tir! {
+            let func = "t";
+            vregs v0, v1;
+            b0: [Arg { dst: v1, idx: 0 }, Mov64rr { dst: v0, src: v1 }, Return { src: v0 }];
         }
-        {
-            let bd = func.get_block_data_mut(b1);
-            bd.push_target_inst(X64Inst::Jmp { dst: b2 });
+        let cfg = CFG::compute(&func).unwrap();
+        let layout = BlockLayout::compute(&func);
+        let ranges = LiveRanges::compute(&func, &cfg, &layout).unwrap();
+        let map = ranges.live_map(&layout);
+
+        for pt in 0..6 {
+            for r in [v0, v1] {
+                assert_eq!(map.is_live_at(r, pt), ranges[r].covers(pt), "v{r} at {pt}");
+            }
         }
-        {
-            let bd = func.get_block_data_mut(b2);
-            bd.push_pseudo_inst(PseudoInstruction::Return { src: v0 });
+        assert_eq!(map.row(2), &[0b10]);
+        assert_eq!(map.live_before(b0, 1).collect::<Vec<_>>(), vec![v1]);
+        assert_eq!(map.live_after(b0, 1).collect::<Vec<_>>(), vec![v0]);
+        assert_eq!(map.live_after(b0, 2).count(), 0);
+
+        let text = func.printer().with_annotations(&map).to_string();
+        assert!(text.contains(&format!("mov v{v0}, v{v1}  ; live: v{v1}\n")), "{text}");
+    }
+
+    #[test]
+    fn value_live_through_a_block_without_using_it_has_a_through_segment() {
+        tir! {
+            let func = "t";
+            vregs v0;
+            b0: [Mov64ri { dst: v0, imm: 42 }, Jmp { dst: b1 }];
+            b1: [Jmp { dst: b2 }]; // v0 live-through
+            b2: [Return { src: v0 }];
         }
         let cfg = CFG::compute(&func).unwrap();
         let layout = BlockLayout::compute(&func);
-        let ranges = LiveRanges::compute(&func, &cfg, &layout);
+        let ranges = LiveRanges::compute(&func, &cfg, &layout).unwrap();
 
         // b0: 2 insts → points 0..4.  b1: 1 inst → points 4..6.  b2: 1 inst → 6..8.
         // v0: defined late(0)=1 in b0, live-out of b0, live-through b1, used early(0)=6 in b2.
@@ -415,44 +756,26 @@ mod tests {
         //       │        │
         //       └──► b3 ◄┘    (b3 uses v0)
         use crate::codegen::isa::x64::inst::Cond;
-        let mut func = Func::<X64Inst>::new("diamond".into());
-        let b0 = func.add_empty_block();
-        let b1 = func.add_empty_block();
-        let b2 = func.add_empty_block();
-        let b3 = func.add_empty_block();
-        let v0 = func.new_vreg();
-        let z = func.new_vreg();
-        {
-            let bd = func.get_block_data_mut(b0);
-            bd.push_target_inst(X64Inst::Mov64ri { dst: v0, imm: 1 });
-            bd.push_target_inst(X64Inst::Mov64ri { dst: z, imm: 0 });
-            bd.push_target_inst(X64Inst::Cmp64rr { lhs: v0, rhs: z });
-            bd.push_target_inst(X64Inst::CondJmp {
-                cond: Cond::NZ,
-                taken: b1,
-                not_taken: b2,
-            });
-        }
-        {
+        tir! {
+            let func = "diamond";
+            vregs v0, z;
+            b0: [
+                Mov64ri { dst: v0, imm: 1 },
+                Mov64ri { dst: z, imm: 0 },
+                Cmp64rr { lhs: v0, rhs: z },
+                CondJmp { cond: Cond::NZ, taken: b1, not_taken: b2 },
+            ];
             // b1 uses v0 (keeps it live-in).
-            let bd = func.get_block_data_mut(b1);
-            bd.push_target_inst(X64Inst::Add64ri32 { dst: v0, imm: 1 });
-            bd.push_target_inst(X64Inst::Jmp { dst: b3 });
-        }
-        {
+            b1: [Add64ri32 { dst: v0, imm: 1 }, Jmp { dst: b3 }];
             // b2 doesn't touch v0 at all. Along the b0→b2→b3 path, v0 is
             // dead in b2 — yet b3 uses it, so liveness propagates
             // conservatively.
-            let bd = func.get_block_data_mut(b2);
-            bd.push_target_inst(X64Inst::Jmp { dst: b3 });
-        }
-        {
-            let bd = func.get_block_data_mut(b3);
-            bd.push_pseudo_inst(PseudoInstruction::Return { src: v0 });
+            b2: [Jmp { dst: b3 }];
+            b3: [Return { src: v0 }];
         }
         let cfg = CFG::compute(&func).unwrap();
         let layout = BlockLayout::compute(&func);
-        let ranges = LiveRanges::compute(&func, &cfg, &layout);
+        let ranges = LiveRanges::compute(&func, &cfg, &layout).unwrap();
         // v0 is live through b2 along the false path (can't prove otherwise
         // without value tracking). Still, the range covers every block it
         // touches; we just assert it's non-empty and its end reaches the
@@ -464,4 +787,95 @@ mod tests {
         // at early = block_start_pt(b3). use_pt + 1 = block_start(b3) + 1.
         assert_eq!(end, layout.block_start_pt(b3) + 1);
     }
+
+    #[test]
+    fn lazy_ranges_match_the_full_analysis() {
+        use crate::codegen::isa::x64::parser::parse_func_text;
+        use crate::codegen::isa::x64::regalloc_fuzz::generate;
+        for seed in 0u8..32 {
+            let data: Vec<u8> =
+                (0..=255u8).map(|i| i.wrapping_mul(seed | 1) ^ seed).collect();
+            let func = parse_func_text(&generate(&data)).unwrap();
+            let cfg = CFG::compute(&func).unwrap();
+            let layout = BlockLayout::compute(&func);
+            let full = LiveRanges::compute(&func, &cfg, &layout).unwrap();
+            let mut lazy = LazyLiveRanges::new(&func, &cfg, &layout).unwrap();
+            for (r, range) in full.iter() {
+                assert_eq!(lazy.range(r), range, "v{r}, seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_and_dense_dataflow_agree_on_a_loop() {
+        // b0: v0 = 0; v1 = 1; jmp b1
+        // b1: add v0, v1; cmp v0, v1; jnz b1, b2
+        // b2: ret v0
+        use crate::codegen::isa::x64::inst::Cond;
+        tir! {
+            let func = "loop";
+            vregs v0, v1;
+            b0: [Mov64ri { dst: v0, imm: 0 }, Mov64ri { dst: v1, imm: 1 }, Jmp { dst: b1 }];
+            b1: [
+                Add64rr { dst: v0, src: v1 },
+                Cmp64rr { lhs: v0, rhs: v1 },
+                CondJmp { cond: Cond::NZ, taken: b1, not_taken: b2 },
+            ];
+            b2: [Return { src: v0 }];
+        }
+        let cfg = CFG::compute(&func).unwrap();
+        let dense = BlockLiveness::<FixedBitSet>::compute(&func, &cfg).unwrap();
+        let sparse = BlockLiveness::<SparseBitSet>::compute(&func, &cfg).unwrap();
+        for b in [b0, b1, b2] {
+            assert!(dense.live_in_regs(b).eq(sparse.live_in_regs(b)), "live-in of {b}");
+            assert!(dense.live_out_regs(b).eq(sparse.live_out_regs(b)), "live-out of {b}");
+        }
+        assert_eq!(sparse.live_out_regs(b1).collect::<Vec<_>>(), [v0, v1]);
+        assert_eq!(dense.live_in_regs(b1).collect::<Vec<_>>(), [v0, v1]);
+        assert_eq!(dense.live_out_regs(b2).count(), 0);
+        assert!(dense.live_in(b2).has(v0 as usize));
+    }
+
+    #[test]
+    fn stale_side_tables_and_foreign_vregs_are_errors() {
+        let mut func = Func::<X64Inst>::new("t".into());
+        let b0 = func.add_empty_block();
+        let v0 = func.new_vreg();
+        {
+            let bd = func.get_block_data_mut(b0);
+            bd.push_target_inst(X64Inst::Mov64ri { dst: v0, imm: 1 });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: v0 });
+        }
+        let cfg = CFG::compute(&func).unwrap();
+        let layout = BlockLayout::compute(&func);
+
+        let mut grown = Func::<X64Inst>::new("t".into());
+        for _ in 0..2 {
+            let b = grown.add_empty_block();
+            grown
+                .get_block_data_mut(b)
+                .push_target_inst(X64Inst::Ud2);
+        }
+        assert!(matches!(
+            LiveRanges::compute(&grown, &cfg, &layout),
+            Err(CodegenError::StaleCfg { cfg: 1, func: 2 })
+        ));
+
+        let stale_layout = layout.clone();
+        func.get_block_data_mut(b0).insts_mut().insert(
+            0,
+            Instruction::Target(X64Inst::Mov64ri { dst: v0, imm: 2 }),
+        );
+        assert!(matches!(
+            LiveRanges::compute(&func, &cfg, &stale_layout),
+            Err(CodegenError::StaleLayout { insts: 3, laid_out: 2, .. })
+        ));
+
+        func.get_block_data_mut(b0).insts_mut()[0] =
+            Instruction::Target(X64Inst::Mov64ri { dst: 9, imm: 2 });
+        assert!(matches!(
+            LiveRanges::compute(&func, &cfg, &BlockLayout::compute(&func)),
+            Err(CodegenError::Tir(TirError::VregOutOfRange(_, 9)))
+        ));
+    }
 }
@@ -1,7 +1,11 @@
+pub mod block_freq;
+pub mod call_graph;
 pub mod cfg;
 pub mod dom_tree;
 pub mod layout;
+pub mod leaf;
 pub mod liveness;
+pub mod verify;
 pub use dom_tree::*;
 pub use layout::*;
 pub use liveness::*;
@@ -0,0 +1,153 @@
+//! Structural IR verifier. Cheap sanity checks run between passes when
+//! `CodegenOptions::verify` is set, so a pass that breaks an invariant
+//! fails at its own boundary instead of as a miscompile downstream.
+//!
+//! Checks: the function has a body; every block ends in exactly one
+//! terminator (none mid-block); every branch target names a block of
+//! this function; every vreg operand — including phi, call and intrinsic
+//! side-table operands — was allocated by this function; a `noreturn`
+//! function has no return.
+
+use crate::codegen::tir::{
+    Block, Func, Inst, Instruction, PseudoInstruction, Reg, TirError,
+};
+use crate::support::slotmap::Key;
+
+/// Verify the structural invariants listed in the module header.
+pub fn verify<I: Inst>(func: &Func<I>) -> Result<(), TirError> {
+    if func.get_entry_block().is_none() {
+        return Err(TirError::EmptyFunctionBody);
+    }
+    let n_blocks = func.blocks_count();
+    let n_regs = func.get_regs_count();
+    let check_reg = |block: Block, r: Reg| {
+        if (r as usize) < n_regs {
+            Ok(())
+        } else {
+            Err(TirError::VregOutOfRange(block, r))
+        }
+    };
+    let check_target = |block: Block, t: Block| {
+        if t.index() < n_blocks {
+            Ok(())
+        } else {
+            Err(TirError::InvalidBranchTarget(block, t))
+        }
+    };
+
+    for (block, data) in func.blocks_iter() {
+        if data.get_terminator().is_none() {
+            return Err(TirError::BlockNotTerminated(block));
+        }
+        let last = data.len() - 1;
+        for (i, inst) in data.iter().enumerate() {
+            if i != last && inst.is_term() {
+                return Err(TirError::TerminatorNotAtEnd(block));
+            }
+            if func.attrs().noreturn && inst.is_ret() {
+                return Err(TirError::ReturnInNoreturn(block));
+            }
+            for r in inst.get_uses().into_iter().chain(inst.get_defs()) {
+                check_reg(block, r)?;
+            }
+            for t in func.branch_targets(inst) {
+                check_target(block, t)?;
+            }
+            match inst {
+                Instruction::Pseudo(PseudoInstruction::Phi { dst, id }) => {
+                    check_reg(block, *dst)?;
+                    for &(pred, v) in &func.phi_operands(*id).incoming {
+                        check_target(block, pred)?;
+                        check_reg(block, v)?;
+                    }
+                }
+                Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) => {
+                    let call = func.call_operands(*id);
+                    for &r in call.args.iter().chain(&call.rets) {
+                        check_reg(block, r)?;
+                    }
+                }
+                Instruction::Pseudo(PseudoInstruction::RawBytes { id }) => {
+                    let raw = func.raw_bytes_operands(*id);
+                    for &(r, _) in raw.uses.iter().chain(&raw.defs) {
+                        check_reg(block, r)?;
+                    }
+                }
+                Instruction::Pseudo(PseudoInstruction::Intrinsic { id }) => {
+                    let data = func.intrinsic_operands(*id);
+                    for &r in data.args.iter().chain(&data.rets) {
+                        check_reg(block, r)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::X64Inst;
+
+    fn ret_block(func: &mut Func<X64Inst>, b: Block) {
+        let v = func.new_vreg();
+        let bd = func.get_block_data_mut(b);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: v, idx: 0 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: v });
+    }
+
+    #[test]
+    fn well_formed_function_verifies() {
+        let mut func = Func::<X64Inst>::new("ok".to_string());
+        let b = func.add_empty_block();
+        ret_block(&mut func, b);
+        assert!(verify(&func).is_ok());
+    }
+
+    #[test]
+    fn empty_function_is_rejected() {
+        let func = Func::<X64Inst>::new("empty".to_string());
+        assert!(matches!(verify(&func), Err(TirError::EmptyFunctionBody)));
+    }
+
+    #[test]
+    fn terminator_in_the_middle_of_a_block_is_rejected() {
+        let mut func = Func::<X64Inst>::new("mid".to_string());
+        let b = func.add_empty_block();
+        ret_block(&mut func, b);
+        ret_block(&mut func, b);
+        assert!(matches!(verify(&func), Err(TirError::TerminatorNotAtEnd(x)) if x == b));
+    }
+
+    #[test]
+    fn branch_to_a_nonexistent_block_is_rejected() {
+        let mut func = Func::<X64Inst>::new("dangling".to_string());
+        let b = func.add_empty_block();
+        func.get_block_data_mut(b)
+            .push_target_inst(X64Inst::Jmp { dst: Block::new(7) });
+        assert!(matches!(
+            verify(&func),
+            Err(TirError::InvalidBranchTarget(from, to)) if from == b && to == Block::new(7)
+        ));
+    }
+
+    #[test]
+    fn return_from_a_noreturn_function_is_rejected() {
+        let mut func = Func::<X64Inst>::new("exit".to_string());
+        let b = func.add_empty_block();
+        ret_block(&mut func, b);
+        func.attrs_mut().noreturn = true;
+        assert!(matches!(verify(&func), Err(TirError::ReturnInNoreturn(x)) if x == b));
+    }
+
+    #[test]
+    fn foreign_vreg_is_rejected() {
+        let mut func = Func::<X64Inst>::new("foreign".to_string());
+        let b = func.add_empty_block();
+        func.get_block_data_mut(b)
+            .push_pseudo_inst(PseudoInstruction::Return { src: 42 });
+        assert!(matches!(verify(&func), Err(TirError::VregOutOfRange(_, 42))));
+    }
+}
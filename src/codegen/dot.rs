@@ -0,0 +1,250 @@
+//! GraphViz `.dot` dumps of a function's CFG, dominator tree and
+//! interference graph.
+//!
+//! The interference graph has one node per live vreg and an edge between
+//! two vregs of the same register class whose live ranges intersect.
+//! Nodes are filled with their preg's colour, so two adjacent nodes of the
+//! same colour are an allocation bug and a cluster of dashed grey (stack)
+//! nodes shows where pressure forced spills. A vreg that was split lists
+//! every slot it visited and is drawn as a double octagon.
+
+use std::fmt::Write;
+
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::analysis::liveness::LiveRanges;
+use crate::codegen::regalloc::{AllocatedSlot, RegAllocResult};
+use crate::codegen::tir::{Func, Inst, Reg, reg_name};
+use crate::support::bit_matrix::TriangularBitMatrix;
+use crate::support::slotmap::Key;
+
+/// Fill colours for pregs, indexed by `preg % len`.
+const PALETTE: [&str; 16] = [
+    "#8dd3c7", "#ffffb3", "#bebada", "#fb8072", "#80b1d3", "#fdb462", "#b3de69", "#fccde5",
+    "#bc80bd", "#ccebc5", "#ffed6f", "#a6cee3", "#b2df8a", "#fb9a99", "#cab2d6", "#fdbf6f",
+];
+
+/// One box per block listing its instructions, with an edge per successor.
+#[must_use]
+pub fn cfg_to_dot<I: Inst>(func: &Func<I>, cfg: &CFG) -> String {
+    let mut out = header("digraph", func.name(), "cfg");
+    let _ = writeln!(out, "  node [shape=box, fontname=monospace];");
+    for (b, bd) in func.blocks_iter() {
+        let mut label = format!("{b}:\\l");
+        for inst in bd.iter() {
+            let _ = write!(label, "  {}\\l", escape(&inst.to_string()));
+        }
+        let _ = writeln!(out, "  b{} [label=\"{label}\"];", b.index());
+    }
+    for (b, _) in func.blocks_iter() {
+        for s in cfg.succs(b) {
+            let _ = writeln!(out, "  b{} -> b{};", b.index(), s.index());
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// One node per block with an edge from each block's immediate dominator.
+#[must_use]
+pub fn dom_tree_to_dot<I: Inst>(func: &Func<I>, dom: &DomTree) -> String {
+    let mut out = header("digraph", func.name(), "domtree");
+    let _ = writeln!(out, "  node [shape=ellipse];");
+    for (b, _) in func.blocks_iter() {
+        let _ = writeln!(out, "  b{} [label=\"{b}\"];", b.index());
+    }
+    for (b, _) in func.blocks_iter() {
+        if let Some(idom) = dom.idom(b) {
+            let _ = writeln!(out, "  b{} -> b{};", idom.index(), b.index());
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Interference graph of `ranges`, coloured by `ra`. `preg_name` renders a
+/// physical register for the node labels.
+#[must_use]
+pub fn interference_to_dot<I: Inst>(
+    func: &Func<I>,
+    ranges: &LiveRanges,
+    ra: &RegAllocResult,
+    preg_name: impl Fn(Reg) -> String,
+) -> String {
+    let live: Vec<Reg> = ranges
+        .iter()
+        .filter(|(_, r)| !r.is_empty())
+        .map(|(v, _)| v)
+        .collect();
+    let graph = interference(func, ranges, &live);
+
+    let mut out = header("graph", func.name(), "interference");
+    let _ = writeln!(out, "  node [style=filled, fontname=monospace];");
+    for &v in &live {
+        let slots: Vec<AllocatedSlot> = ra
+            .assignments
+            .get(v)
+            .map(|a| a.slots().collect())
+            .unwrap_or_default();
+        let mut label = reg_name(v);
+        let names: Vec<String> = slots
+            .iter()
+            .map(|s| match *s {
+                AllocatedSlot::Reg(p) => preg_name(p),
+                AllocatedSlot::Stack(s) => format!("slot{s}"),
+            })
+            .collect();
+        if !names.is_empty() {
+            let _ = write!(label, "\\n{}", names.join(" → "));
+        }
+        let mut attrs = match slots.first() {
+            Some(AllocatedSlot::Reg(p)) => {
+                format!("fillcolor=\"{}\"", PALETTE[*p as usize % PALETTE.len()])
+            }
+            Some(AllocatedSlot::Stack(_)) => "fillcolor=gray80, style=\"filled,dashed\"".into(),
+            None => "fillcolor=white".into(),
+        };
+        if slots.len() > 1 {
+            attrs.push_str(", shape=doubleoctagon");
+        }
+        let _ = writeln!(out, "  v{v} [label=\"{label}\", {attrs}];");
+    }
+    for (i, &a) in live.iter().enumerate() {
+        for j in graph.row(i).filter(|&j| j < i) {
+            let _ = writeln!(out, "  v{} -- v{a};", live[j]);
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Pairs of `live` (by index) in the same register class whose ranges
+/// intersect.
+fn interference<I: Inst>(func: &Func<I>, ranges: &LiveRanges, live: &[Reg]) -> TriangularBitMatrix {
+    let mut graph = TriangularBitMatrix::new(live.len());
+    for (i, &a) in live.iter().enumerate() {
+        let a_fp = func.vreg_type(a).is_fp_or_vector();
+        for (j, &b) in live[..i].iter().enumerate() {
+            if func.vreg_type(b).is_fp_or_vector() == a_fp
+                && ranges[a]
+                    .next_intersection_at_or_after(&ranges[b], 0)
+                    .is_some()
+            {
+                graph.add(i, j);
+            }
+        }
+    }
+    graph
+}
+
+fn header(keyword: &str, func: &str, kind: &str) -> String {
+    format!("{keyword} \"{}.{kind}\" {{\n", escape(func))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::layout::BlockLayout;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+    use crate::codegen::isa::x64::regs::{RAX, RBX, preg_name};
+    use crate::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocator};
+    use crate::codegen::tir::PseudoInstruction;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn cfg_and_dom_tree_of_a_diamond() {
+        let mut func = Func::<X64Inst>::new("d".into());
+        let bs: Vec<_> = (0..4).map(|_| func.add_empty_block()).collect();
+        let v = func.new_vreg();
+        func.get_block_data_mut(bs[0])
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 1 });
+        func.get_block_data_mut(bs[0])
+            .push_target_inst(X64Inst::CondJmp {
+                cond: Cond::Z,
+                taken: bs[1],
+                not_taken: bs[2],
+            });
+        for &b in &bs[1..3] {
+            func.get_block_data_mut(b)
+                .push_target_inst(X64Inst::Jmp { dst: bs[3] });
+        }
+        func.get_block_data_mut(bs[3])
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        let cfg = CFG::compute(&func).unwrap();
+
+        let dot = cfg_to_dot(&func, &cfg);
+        assert!(dot.starts_with("digraph \"d.cfg\" {"));
+        assert!(dot.contains("b0 [label=\"@0:\\l  mov v0, 1\\l"), "{dot}");
+        for edge in ["b0 -> b1;", "b0 -> b2;", "b1 -> b3;", "b2 -> b3;"] {
+            assert!(dot.contains(edge), "{dot}");
+        }
+
+        let dot = dom_tree_to_dot(&func, &DomTree::compute(&cfg).unwrap());
+        // The join is dominated by the branch, not by either arm.
+        for edge in ["b0 -> b1;", "b0 -> b2;", "b0 -> b3;"] {
+            assert!(dot.contains(edge), "{dot}");
+        }
+        assert!(!dot.contains("b1 -> b3;"), "{dot}");
+    }
+
+    #[test]
+    fn interference_nodes_are_coloured_by_their_allocation() {
+        // v0 and v1 are live together; v2 starts after v0 dies and
+        // interferes only with v1.
+        let mut func = Func::<X64Inst>::new("i".into());
+        let b0 = func.add_empty_block();
+        let vs: Vec<Reg> = (0..3).map(|_| func.new_vreg()).collect();
+        {
+            let bd = func.get_block_data_mut(b0);
+            bd.push_target_inst(X64Inst::Mov64ri { dst: vs[0], imm: 1 });
+            bd.push_target_inst(X64Inst::Mov64ri { dst: vs[1], imm: 2 });
+            bd.push_target_inst(X64Inst::Add64rr {
+                dst: vs[1],
+                src: vs[0],
+            });
+            bd.push_target_inst(X64Inst::Mov64ri { dst: vs[2], imm: 3 });
+            bd.push_target_inst(X64Inst::Add64rr {
+                dst: vs[2],
+                src: vs[1],
+            });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: vs[2] });
+        }
+        let cfg = CFG::compute(&func).unwrap();
+        let config = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RBX],
+            scratch_regs: Vec::new(),
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: false,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let ra = LinearScan::allocate(&func, &cfg, &config);
+        let ranges = LiveRanges::compute(&func, &cfg, &BlockLayout::compute(&func)).unwrap();
+
+        let dot = interference_to_dot(&func, &ranges, &ra, |p| preg_name(p).to_string());
+        assert!(dot.starts_with("graph \"i.interference\" {"));
+        assert!(dot.contains("v0 -- v1;"), "{dot}");
+        assert!(dot.contains("v1 -- v2;"), "{dot}");
+        assert!(!dot.contains("v0 -- v2;"), "{dot}");
+        let v0 = dot
+            .lines()
+            .find(|l| l.trim_start().starts_with("v0 ["))
+            .unwrap();
+        let v2 = dot
+            .lines()
+            .find(|l| l.trim_start().starts_with("v2 ["))
+            .unwrap();
+        // v2 reuses v0's register, so it gets the same colour.
+        let colour = |l: &str| l.split("fillcolor=").nth(1).map(str::to_string);
+        assert_eq!(colour(v0), colour(v2), "{dot}");
+        assert!(v0.contains("\\nrax") || v0.contains("\\nrbx"), "{dot}");
+    }
+}
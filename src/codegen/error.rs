@@ -0,0 +1,156 @@
+//! Errors from analyses handed malformed input — a function that fails
+//! the structural checks, or side tables computed for a different
+//! version of the function — from compiling a function (a pass leaving
+//! broken IR, an allocation that fails its check or can't be satisfied,
+//! code that won't encode), and from assembling a `Module` and writing
+//! it out.
+//!
+//! `CodegenError::in_func` wraps an error with where it arose: the
+//! function, and the block and instruction the error names, if any.
+
+use std::fmt::{self, Display};
+
+use thiserror::Error;
+
+use crate::codegen::isa::x64::features::CpuFeatures;
+use crate::codegen::regalloc::checker::CheckError;
+use crate::codegen::tir::{Block, Reg, SectionFlags, TirError, Type};
+
+/// Where in the input an error arose.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub func: String,
+    pub block: Option<Block>,
+    /// Index of the instruction within `block`.
+    pub inst: Option<usize>,
+}
+
+impl Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`", self.func)?;
+        if let Some(block) = self.block {
+            write!(f, " {block}")?;
+        }
+        if let Some(inst) = self.inst {
+            write!(f, " inst {inst}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CodegenError {
+    #[error(transparent)]
+    Tir(#[from] TirError),
+
+    #[error("{loc}: {error}")]
+    At {
+        loc: SourceLoc,
+        #[source]
+        error: Box<CodegenError>,
+    },
+
+    #[error("IR verification failed after {pass}: {error}")]
+    Verify {
+        pass: String,
+        #[source]
+        error: TirError,
+    },
+
+    #[error("Register allocation check failed: {0}")]
+    Allocation(#[from] CheckError),
+
+    #[error("vreg {vreg} is pre-bound to two different pregs: {first} and {second}")]
+    ConflictingPreBind { vreg: Reg, first: Reg, second: Reg },
+
+    #[error("Block {block} inst {inst} needs CPU features `{needs}` the target may lack")]
+    MissingCpuFeatures { block: Block, inst: usize, needs: CpuFeatures },
+
+    #[error("A `{0}` argument is past the registers; only vectors up to 128 bits fit the stack")]
+    StackVectorArg(Type),
+
+    #[error("Encoding failed: {0}")]
+    Encoding(#[from] iced_x86::IcedError),
+
+    #[error("Block {0} is reachable but has no reachable predecessor in the CFG")]
+    NoReachablePredecessor(Block),
+
+    #[error("CFG has {cfg} blocks but the function has {func}")]
+    StaleCfg { cfg: usize, func: usize },
+
+    #[error("Block {block} has {insts} instructions but its layout numbers {laid_out}")]
+    StaleLayout {
+        block: Block,
+        insts: usize,
+        laid_out: usize,
+    },
+
+    #[error("Symbol `{0}` is already defined in the module")]
+    DuplicateSymbol(String),
+
+    #[error("Function `{0}` is already declared with another signature or linkage")]
+    ConflictingDeclaration(String),
+
+    #[error("Data object `{name}` has alignment {align}, not a power of two")]
+    BadDataAlign { name: String, align: u32 },
+
+    #[error("Data object `{name}` has a relocation at {offset} past its initialized bytes")]
+    DataRelocOutOfBounds { name: String, offset: usize },
+
+    #[error("Data object `{name}` has initialized bytes but section `{section}` is zero-fill")]
+    BytesInBss { name: String, section: String },
+
+    #[error("Section `{section}` is placed with flags `{first}` and `{second}`")]
+    SectionFlagsConflict {
+        section: String,
+        first: SectionFlags,
+        second: SectionFlags,
+    },
+
+    #[error(transparent)]
+    Object(#[from] object::write::Error),
+}
+
+impl CodegenError {
+    /// Attach the location: function `func`, plus the block and
+    /// instruction the error itself names. An error that already has one
+    /// keeps it.
+    #[must_use]
+    pub fn in_func(self, func: &str) -> Self {
+        if matches!(self, Self::At { .. }) {
+            return self;
+        }
+        let (block, inst) = match &self {
+            Self::Tir(e) | Self::Verify { error: e, .. } => (e.block(), None),
+            Self::NoReachablePredecessor(b) | Self::StaleLayout { block: b, .. } => {
+                (Some(*b), None)
+            }
+            Self::MissingCpuFeatures { block, inst, .. } => (Some(*block), Some(*inst)),
+            Self::Allocation(e) => {
+                let (b, i) = e.position();
+                (Some(b), Some(i))
+            }
+            _ => (None, None),
+        };
+        let loc = SourceLoc { func: func.to_string(), block, inst };
+        Self::At { loc, error: Box::new(self) }
+    }
+
+    /// Where the error arose, if `in_func` recorded it.
+    #[must_use]
+    pub fn location(&self) -> Option<&SourceLoc> {
+        match self {
+            Self::At { loc, .. } => Some(loc),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without its location.
+    #[must_use]
+    pub fn root(&self) -> &CodegenError {
+        match self {
+            Self::At { error, .. } => error.root(),
+            e => e,
+        }
+    }
+}
@@ -1 +1,10 @@
 pub mod x64;
+
+/// Code-generation target: ISA plus calling convention. Selects the ABI
+/// lowering and register conventions `compile_function` uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// x86-64 with the System V AMD64 calling convention.
+    #[default]
+    X64SysV,
+}
@@ -0,0 +1,169 @@
+//! Conservative alias analysis over `Mem` operands.
+//!
+//! Two accesses are known apart when
+//! * their addresses share base, index and scale and the byte ranges
+//!   `[disp, disp + size)` don't overlap, or
+//! * their bases resolve to different `stackalloc` slots, or to the same
+//!   slot at disjoint offsets with no index on either side.
+//!
+//! A base "resolves" to a slot if its only def is the `stackalloc`
+//! itself, a `copy` of a resolved vreg, or an index-free `lea` off one.
+//! Vregs with several defs (after SSA destruction) never resolve.
+//! Everything else may alias.
+
+use std::collections::HashMap;
+
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg};
+
+/// A load or store through a `Mem` operand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr: Mem,
+    /// Bytes touched from `addr`.
+    pub size: u32,
+    pub store: bool,
+}
+
+/// The plain load or store `t` performs, if that is all it does to memory.
+/// Atomics and stack-argument traffic are not plain accesses.
+#[must_use]
+pub fn mem_access(t: &X64Inst) -> Option<MemAccess> {
+    let (addr, size, store) = match *t {
+        X64Inst::Movdqurm { src, .. } => (src, 16, false),
+        X64Inst::Mov64rm { src, .. } | X64Inst::Movsdrm { src, .. } => (src, 8, false),
+        X64Inst::Mov32rm { src, .. } | X64Inst::Movssrm { src, .. } => (src, 4, false),
+        X64Inst::Mov16rm { src, .. } => (src, 2, false),
+        X64Inst::Mov8rm { src, .. } => (src, 1, false),
+        X64Inst::Movdqumr { dst, .. } => (dst, 16, true),
+        X64Inst::Mov64mr { dst, .. } | X64Inst::Movsdmr { dst, .. } => (dst, 8, true),
+        X64Inst::Mov32mr { dst, .. } | X64Inst::Movssmr { dst, .. } => (dst, 4, true),
+        X64Inst::Mov16mr { dst, .. } => (dst, 2, true),
+        X64Inst::Mov8mr { dst, .. } => (dst, 1, true),
+        _ => return None,
+    };
+    Some(MemAccess { addr, size, store })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AliasAnalysis {
+    /// Vreg → (`stackalloc` vreg it points into, byte offset).
+    slots: HashMap<Reg, (Reg, i64)>,
+}
+
+impl AliasAnalysis {
+    #[must_use]
+    pub fn compute(func: &Func<X64Inst>) -> Self {
+        let mut defs: HashMap<Reg, Vec<Instruction<X64Inst>>> = HashMap::new();
+        for (_, bd) in func.blocks_iter() {
+            for inst in bd.iter() {
+                for d in inst.get_defs() {
+                    defs.entry(d).or_default().push(*inst);
+                }
+            }
+        }
+        let single: HashMap<Reg, Instruction<X64Inst>> = defs
+            .into_iter()
+            .filter_map(|(r, ds)| (ds.len() == 1).then(|| (r, ds[0])))
+            .collect();
+        let mut slots = HashMap::new();
+        for (&r, inst) in &single {
+            if let Instruction::Pseudo(PseudoInstruction::StackAlloc { .. }) = inst {
+                slots.insert(r, (r, 0));
+            }
+        }
+        // Copies and `lea`s can chain; iterate until no new vreg resolves.
+        loop {
+            let mut grew = false;
+            for (&r, inst) in &single {
+                if slots.contains_key(&r) {
+                    continue;
+                }
+                let derived = match *inst {
+                    Instruction::Pseudo(PseudoInstruction::Copy { src, .. }) => {
+                        slots.get(&src).copied()
+                    }
+                    Instruction::Target(X64Inst::Lea64rm { src, .. }) if src.index.is_none() => {
+                        slots
+                            .get(&src.base)
+                            .map(|&(slot, off)| (slot, off + i64::from(src.disp)))
+                    }
+                    _ => None,
+                };
+                if let Some(s) = derived {
+                    slots.insert(r, s);
+                    grew = true;
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+        Self { slots }
+    }
+
+    /// `true` unless `a` and `b` provably touch disjoint bytes.
+    #[must_use]
+    pub fn may_alias(&self, a: &MemAccess, b: &MemAccess) -> bool {
+        let overlap = |a_lo: i64, b_lo: i64| {
+            a_lo < b_lo + i64::from(b.size) && b_lo < a_lo + i64::from(a.size)
+        };
+        let (x, y) = (&a.addr, &b.addr);
+        if let (Some(&(xs, xo)), Some(&(ys, yo))) =
+            (self.slots.get(&x.base), self.slots.get(&y.base))
+        {
+            if xs != ys {
+                return false;
+            }
+            if x.index.is_none() && y.index.is_none() {
+                return overlap(xo + i64::from(x.disp), yo + i64::from(y.disp));
+            }
+        }
+        let same_form =
+            x.base == y.base && x.index == y.index && (x.index.is_none() || x.scale == y.scale);
+        !same_form || overlap(i64::from(x.disp), i64::from(y.disp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(base: Reg, disp: i32, size: u32) -> MemAccess {
+        MemAccess {
+            addr: Mem::base_disp(base, disp),
+            size,
+            store: true,
+        }
+    }
+
+    #[test]
+    fn stack_slots_and_disjoint_offsets_are_apart() {
+        let mut func = Func::<X64Inst>::new("aa".to_string());
+        let b0 = func.add_empty_block();
+        let (s1, s2, p, q, t) = (
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+        );
+        let bd = func.get_block_data_mut(b0);
+        for dst in [s1, s2] {
+            bd.push_pseudo_inst(PseudoInstruction::StackAlloc { dst, size: 16, align: 8 });
+        }
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: p, idx: 0 });
+        bd.push_target_inst(X64Inst::Lea64rm { dst: q, src: Mem::base_disp(s1, 8) });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: t, src: q });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: p });
+        let aa = AliasAnalysis::compute(&func);
+
+        assert!(!aa.may_alias(&at(s1, 0, 8), &at(s2, 0, 8)));
+        assert!(!aa.may_alias(&at(s1, 0, 8), &at(t, 0, 8)));
+        assert!(aa.may_alias(&at(s1, 8, 8), &at(t, 4, 8)));
+        assert!(!aa.may_alias(&at(p, 0, 8), &at(p, 8, 4)));
+        assert!(aa.may_alias(&at(p, 0, 8), &at(p, 7, 1)));
+        // Unknown pointers may point anywhere, slots included.
+        assert!(aa.may_alias(&at(p, 0, 8), &at(s1, 0, 8)));
+    }
+}
@@ -7,11 +7,40 @@
 //! three-operand illusion.
 
 use crate::codegen::isa::x64::inst::{Cond, Mem, X64Inst};
-use crate::codegen::isa::x64::regs::{RAX, RCX, RDX};
+use crate::codegen::isa::x64::regs::{RAX, RCX, RDI, RDX, RSI, is_xmm};
+use crate::codegen::module::{FuncRef, Module};
 use crate::codegen::tir::{
-    AggregateId, Block, CallData, CallTarget, Func, Inst, PhiId, PseudoInstruction, Reg, Type,
+    AggregateId, Block, BlockHint, CallData, CallTarget, Func, Inst, IntrinsicData, JumpTableData,
+    PatchKind, PhiId, PseudoInstruction, RawBytesData, Reg, ScalarType, TrapCode, Type,
 };
 
+/// A 128-bit integer as a pair of 64-bit vregs. The `_i128` builder
+/// methods expand each operation on it into 64-bit code over the halves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I128 {
+    pub lo: Reg,
+    pub hi: Reg,
+}
+
+/// Longest `memcpy_const` / `memset_const` that unrolls into moves
+/// instead of a `rep` string op.
+pub const INLINE_MEM_BYTES: u64 = 64;
+
+/// `(offset, width)` of the widest moves covering `len` bytes, largest
+/// first: 16-byte ones only when `vector`.
+fn mem_chunks(len: u64, vector: bool) -> Vec<(i32, u64)> {
+    let widths: &[u64] = if vector { &[16, 8, 4, 2, 1] } else { &[8, 4, 2, 1] };
+    let mut chunks = Vec::new();
+    let mut off = 0;
+    for &width in widths {
+        while len - off >= width {
+            chunks.push((i32::try_from(off).expect("inline length fits an i32"), width));
+            off += width;
+        }
+    }
+    chunks
+}
+
 pub struct FuncBuilder {
     func: Func<X64Inst>,
     entry: Block,
@@ -37,6 +66,12 @@ impl FuncBuilder {
         self.entry
     }
 
+    /// The function built so far.
+    #[must_use]
+    pub fn func(&self) -> &Func<X64Inst> {
+        &self.func
+    }
+
     #[must_use]
     pub fn current_block(&self) -> Block {
         self.current
@@ -50,6 +85,14 @@ impl FuncBuilder {
         self.func.add_empty_block()
     }
 
+    /// Mark `block` likely or unlikely to be entered from its branching
+    /// predecessors, e.g. an error path as `Unlikely`. Steers block
+    /// layout, branch direction and spill placement when there is no
+    /// profile. See `Func::set_block_hint`.
+    pub fn set_block_hint(&mut self, block: Block, hint: Option<BlockHint>) {
+        self.func.set_block_hint(block, hint);
+    }
+
     pub fn new_vreg(&mut self) -> Reg {
         self.func.new_vreg()
     }
@@ -317,9 +360,161 @@ impl FuncBuilder {
         self.take_div_result(r, q)
     }
 
-    /// `dst = if (a OP b) { true_val } else { false_val }` via CMP + CMOV.
-    /// The starting value is `false_val` (copied), then CMOV on `cond`
-    /// replaces it with `true_val` if the flag is set.
+    // ---- 128-bit integers. ----
+
+    /// The next two incoming arguments as one `i128`, low half first, the
+    /// way SysV passes an `__int128` that still fits in registers.
+    pub fn arg_i128(&mut self) -> I128 {
+        I128 {
+            lo: self.arg(),
+            hi: self.arg(),
+        }
+    }
+
+    pub fn iconst128(&mut self, imm: i128) -> I128 {
+        I128 {
+            lo: self.iconst64(imm as i64),
+            hi: self.iconst64((imm >> 64) as i64),
+        }
+    }
+
+    /// Return `v` in RAX:RDX, as SysV returns an `__int128`.
+    pub fn ret_i128(&mut self, v: I128) {
+        let pair = self.make_aggregate(vec![v.lo, v.hi]);
+        self.ret(pair);
+    }
+
+    /// `add lo; adc hi`. The halves go through `Copy`s first so nothing
+    /// lands between the carry's producer and its reader.
+    pub fn add_i128(&mut self, a: I128, b: I128) -> I128 {
+        self.carry_op(
+            a,
+            b,
+            |dst, src| X64Inst::Add64rr { dst, src },
+            |dst, src| X64Inst::Adc64rr { dst, src },
+        )
+    }
+
+    /// `sub lo; sbb hi`.
+    pub fn sub_i128(&mut self, a: I128, b: I128) -> I128 {
+        self.carry_op(
+            a,
+            b,
+            |dst, src| X64Inst::Sub64rr { dst, src },
+            |dst, src| X64Inst::Sbb64rr { dst, src },
+        )
+    }
+
+    fn carry_op(
+        &mut self,
+        a: I128,
+        b: I128,
+        lo_op: impl FnOnce(Reg, Reg) -> X64Inst,
+        hi_op: impl FnOnce(Reg, Reg) -> X64Inst,
+    ) -> I128 {
+        let (lo, hi) = (self.func.new_vreg(), self.func.new_vreg());
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: lo, src: a.lo });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: hi, src: a.hi });
+        bd.push_target_inst(lo_op(lo, b.lo));
+        bd.push_target_inst(hi_op(hi, b.hi));
+        I128 { lo, hi }
+    }
+
+    /// Product modulo 2^128: a widening `mul` of the low halves in
+    /// RDX:RAX, plus both cross products (`imul`) into the high half.
+    pub fn mul_i128(&mut self, a: I128, b: I128) -> I128 {
+        let lo_hi = self.imul(a.lo, b.hi);
+        let hi_lo = self.imul(a.hi, b.lo);
+        let lo_in = self.func.new_vreg();
+        self.func.pre_bind(lo_in, RAX);
+        let lo = self.func.new_vreg();
+        self.func.pre_bind(lo, RAX);
+        let hi = self.func.new_vreg();
+        self.func.pre_bind(hi, RDX);
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: lo_in, src: a.lo });
+        bd.push_target_inst(X64Inst::Mul64r { src: b.lo, lo_in, lo, hi });
+        // Copy both halves out at once: RAX and RDX stay reserved only
+        // for as long as the `mul` itself.
+        let (lo_out, hi_out) = (self.func.new_vreg(), self.func.new_vreg());
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: lo_out, src: lo });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: hi_out, src: hi });
+        let hi = self.add(hi_out, lo_hi);
+        let hi = self.add(hi, hi_lo);
+        I128 { lo: lo_out, hi }
+    }
+
+    /// `icmp_to_i64` on `i128`s.
+    pub fn icmp_i128(&mut self, cond: Cond, a: I128, b: I128) -> Reg {
+        let cond = self.cmp_i128(cond, a, b);
+        let byte = self.func.new_vreg();
+        let dst = self.func.new_vreg();
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_target_inst(X64Inst::Setcc8r { cond, dst: byte });
+        bd.push_target_inst(X64Inst::Movzx64r8 { dst, src: byte });
+        dst
+    }
+
+    /// `branch_icmp` on `i128`s.
+    pub fn branch_icmp_i128(
+        &mut self,
+        cond: Cond,
+        a: I128,
+        b: I128,
+        taken: Block,
+        not_taken: Block,
+    ) {
+        let cond = self.cmp_i128(cond, a, b);
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::CondJmp { cond, taken, not_taken });
+    }
+
+    /// Set flags so that the returned condition holds iff `a cond b`.
+    /// Equality ORs the halves' XORs; an ordering is `cmp lo; sbb hi`,
+    /// whose SF/OF/CF are those of the full 128-bit subtraction, with
+    /// the operands swapped for the conditions it can't test directly.
+    fn cmp_i128(&mut self, cond: Cond, a: I128, b: I128) -> Cond {
+        match cond {
+            Cond::Z | Cond::NZ => {
+                let lo = self.xor(a.lo, b.lo);
+                let hi = self.xor(a.hi, b.hi);
+                let either = self.or(lo, hi);
+                self.kill(either);
+                cond
+            }
+            Cond::L | Cond::GE | Cond::B | Cond::AE => {
+                self.sbb_compare(a, b);
+                cond
+            }
+            Cond::G | Cond::LE | Cond::A | Cond::BE => {
+                self.sbb_compare(b, a);
+                cond.swap()
+            }
+        }
+    }
+
+    fn sbb_compare(&mut self, a: I128, b: I128) {
+        let hi = self.func.new_vreg();
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: hi, src: a.hi });
+        bd.push_target_inst(X64Inst::Cmp64rr { lhs: a.lo, rhs: b.lo });
+        bd.push_target_inst(X64Inst::Sbb64rr { dst: hi, src: b.hi });
+        self.kill(hi);
+    }
+
+    /// End `r`'s live range: only the flags its def set are wanted.
+    fn kill(&mut self, r: Reg) {
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::Kill { src: r });
+    }
+
+    /// `dst = if (a OP b) { true_val } else { false_val }`. Emits a
+    /// `Select`, which `lower_selects` turns into CMP + CMOV or a branch
+    /// diamond depending on what its operands cost.
     pub fn select(
         &mut self,
         cond: Cond,
@@ -327,12 +522,33 @@ impl FuncBuilder {
         cmp_rhs: Reg,
         true_val: Reg,
         false_val: Reg,
+    ) -> Reg {
+        self.select_hinted(cond, cmp_lhs, cmp_rhs, true_val, false_val, None)
+    }
+
+    /// `select` whose condition the frontend expects to be predictable:
+    /// `Likely` true or `Unlikely`. A hinted select becomes a branch.
+    pub fn select_hinted(
+        &mut self,
+        cond: Cond,
+        cmp_lhs: Reg,
+        cmp_rhs: Reg,
+        true_val: Reg,
+        false_val: Reg,
+        hint: Option<BlockHint>,
     ) -> Reg {
         let dst = self.func.new_vreg();
-        let bd = self.func.get_block_data_mut(self.current);
-        bd.push_pseudo_inst(PseudoInstruction::Copy { dst, src: false_val });
-        bd.push_target_inst(X64Inst::Cmp64rr { lhs: cmp_lhs, rhs: cmp_rhs });
-        bd.push_target_inst(X64Inst::Cmov64rr { cond, dst, src: true_val });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Select {
+                cond,
+                dst,
+                lhs: cmp_lhs,
+                rhs: cmp_rhs,
+                tval: true_val,
+                fval: false_val,
+                hint,
+            });
         dst
     }
 
@@ -429,6 +645,27 @@ impl FuncBuilder {
             .push_target_inst(X64Inst::Jmp { dst });
     }
 
+    /// End the block with a multi-way branch: to the block of the case
+    /// whose value equals `index`, else to `default`. Cases may come in
+    /// any order. `lower_switches` picks a jump table or a compare tree.
+    ///
+    /// # Panics
+    /// If two cases share a value.
+    pub fn switch(&mut self, index: Reg, cases: &[(i64, Block)], default: Block) {
+        let mut cases = cases.to_vec();
+        cases.sort_unstable_by_key(|&(v, _)| v);
+        if let Some(w) = cases.windows(2).find(|w| w[0].0 == w[1].0) {
+            panic!("switch has two cases for {}", w[0].0);
+        }
+        let table = self.func.new_jump_table(JumpTableData {
+            cases,
+            default: Some(default),
+        });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::Switch { index, table });
+    }
+
     /// Unconditional indirect jump through a register. Used for LLVM's
     /// `indirectbr` — the register holds the target address.
     pub fn jmp_indirect(&mut self, target: Reg) {
@@ -446,6 +683,43 @@ impl FuncBuilder {
             .push_target_inst(X64Inst::Ud2);
     }
 
+    /// End the block with a trap carrying `code` — e.g. the failure arm of
+    /// a bounds check. The compiled code maps the trap's offset back to
+    /// `code` (`CompiledCode::trap_code`).
+    pub fn trap(&mut self, code: TrapCode) {
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::Trap { code });
+    }
+
+    /// Mark a GC safepoint. Emits no code; the compiled code gets a stack
+    /// map listing where every live `Type::Ref` value is at this point.
+    pub fn safepoint(&mut self) {
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::Safepoint);
+    }
+
+    /// Reserve `len` bytes of NOPs here for a runtime to patch, e.g. into
+    /// a call to a tracing hook. The compiled code lists the sled as a
+    /// `PatchKind::Point` site.
+    pub fn patch_point(&mut self, len: u32) {
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::PatchPoint {
+                len,
+                kind: PatchKind::Point,
+            });
+    }
+
+    /// Emit `int3` — a debug breakpoint. Execution continues with the
+    /// next instruction once the debugger resumes.
+    pub fn breakpoint(&mut self) {
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Int3);
+    }
+
     /// Emit `mfence` — a full memory fence. Used for LLVM's `fence`.
     pub fn mfence(&mut self) {
         self.func
@@ -568,15 +842,64 @@ impl FuncBuilder {
         dst
     }
 
+    /// Materialize the address of `symbol` — a function, or a global the
+    /// module or the loader defines. The address is a relocation.
+    pub fn symbol_addr(&mut self, symbol: &str) -> Reg {
+        let dst = self.func.new_typed_vreg(Type::Ptr);
+        let sym = self.func.symbol(symbol);
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Mov64rsym { dst, sym });
+        dst
+    }
+
     /// Emit a direct call to a named symbol. `args` are the user vregs
     /// holding argument values. Returns the user vreg that will hold
     /// the 64-bit return value after the call.
     pub fn call_sym(&mut self, symbol: &str, args: &[Reg]) -> Reg {
+        self.call_sym_with_patch(symbol, args, None)
+    }
+
+    /// `call_sym` with `len` bytes of NOPs right before the `call`, which
+    /// the compiled code lists as a `PatchKind::Call` site for a runtime
+    /// to rewrite.
+    pub fn call_sym_patchable(&mut self, symbol: &str, args: &[Reg], len: u32) -> Reg {
+        self.call_sym_with_patch(symbol, args, Some(len))
+    }
+
+    fn call_sym_with_patch(&mut self, symbol: &str, args: &[Reg], patch: Option<u32>) -> Reg {
         let user_ret = self.func.new_vreg();
         let id = self.func.new_call(CallData {
             callee: CallTarget::Symbol(symbol.to_string()),
             args: args.to_vec(),
             rets: vec![user_ret],
+            patch,
+        });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
+        user_ret
+    }
+
+    /// Emit a direct call to a function `module` declares. The return
+    /// vreg takes the signature's return type, `I64` if it returns
+    /// nothing. Panics if `args` doesn't match the parameter count.
+    pub fn call(&mut self, module: &Module<X64Inst>, callee: FuncRef, args: &[Reg]) -> Reg {
+        let decl = module.func_decl(callee);
+        assert_eq!(
+            args.len(),
+            decl.signature.params.len(),
+            "call to `{}` passes {} arguments, its signature takes {}",
+            decl.name,
+            args.len(),
+            decl.signature.params.len()
+        );
+        let user_ret = self.func.new_typed_vreg(decl.signature.ret.unwrap_or(Type::I64));
+        let id = self.func.new_call(CallData {
+            callee: CallTarget::Symbol(decl.name.clone()),
+            args: args.to_vec(),
+            rets: vec![user_ret],
+            patch: None,
         });
         self.func
             .get_block_data_mut(self.current)
@@ -600,6 +923,7 @@ impl FuncBuilder {
             callee: CallTarget::Indirect(fn_ptr),
             args: args.to_vec(),
             rets: vec![user_ret],
+            patch: None,
         });
         self.func
             .get_block_data_mut(self.current)
@@ -607,6 +931,60 @@ impl FuncBuilder {
         user_ret
     }
 
+    /// Emit `bytes` verbatim, for sequences the ISA doesn't model. Each
+    /// `(vreg, preg)` in `uses` is in `preg` when the bytes run; each preg
+    /// in `defs` yields a fresh vreg holding what the bytes left there
+    /// (`F64` for XMM registers, else `I64`), in order. The bytes may also
+    /// overwrite `clobbers` and the `uses` registers, but nothing else,
+    /// and must not touch the stack pointer or branch out.
+    pub fn raw_bytes(
+        &mut self,
+        bytes: &[u8],
+        uses: &[(Reg, Reg)],
+        defs: &[Reg],
+        clobbers: &[Reg],
+    ) -> Vec<Reg> {
+        let out: Vec<Reg> = defs
+            .iter()
+            .map(|&p| self.func.new_typed_vreg(if is_xmm(p) { Type::F64 } else { Type::I64 }))
+            .collect();
+        let id = self.func.new_raw_bytes(RawBytesData {
+            bytes: bytes.to_vec(),
+            uses: uses.to_vec(),
+            defs: out.iter().copied().zip(defs.iter().copied()).collect(),
+            clobbers: clobbers.to_vec(),
+        });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::RawBytes { id });
+        out
+    }
+
+    /// Use the target intrinsic `name` (see `x64::intrinsics`) on `args`.
+    /// Returns one fresh vreg per declared result.
+    pub fn intrinsic(&mut self, name: &str, args: &[Reg]) -> Vec<Reg> {
+        let decl = X64Inst::intrinsics()
+            .iter()
+            .find(|d| d.name == name)
+            .unwrap_or_else(|| panic!("no intrinsic `{name}`"));
+        assert_eq!(
+            args.len(),
+            decl.params.len(),
+            "`{name}` takes {} arguments",
+            decl.params.len()
+        );
+        let rets: Vec<Reg> = decl.results.iter().map(|&ty| self.func.new_typed_vreg(ty)).collect();
+        let id = self.func.new_intrinsic(IntrinsicData {
+            decl,
+            args: args.to_vec(),
+            rets: rets.clone(),
+        });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::Intrinsic { id });
+        rets
+    }
+
     // ---- Floating-point helpers. ----
 
     fn fp_binop<F>(&mut self, ty: Type, a: Reg, b: Reg, make_inst: F) -> Reg
@@ -646,6 +1024,25 @@ impl FuncBuilder {
         self.fp_binop(Type::F64, a, b, |dst, src| X64Inst::Divsdrr { dst, src })
     }
 
+    /// `f32` constant. Emission picks `xorps` or a GPR round-trip.
+    pub fn fconst_f32(&mut self, v: f32) -> Reg {
+        let dst = self.func.new_typed_vreg(Type::F32);
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Fconst32 { dst, bits: v.to_bits() });
+        dst
+    }
+
+    /// `f64` constant. Emission picks `xorps`, a GPR round-trip or a
+    /// constant-pool load.
+    pub fn fconst_f64(&mut self, v: f64) -> Reg {
+        let dst = self.func.new_typed_vreg(Type::F64);
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Fconst64 { dst, bits: v.to_bits() });
+        dst
+    }
+
     /// `f32` load from `[base + disp]`.
     pub fn load_f32(&mut self, base: Reg, disp: i32) -> Reg {
         let dst = self.func.new_typed_vreg(Type::F32);
@@ -688,6 +1085,112 @@ impl FuncBuilder {
             });
     }
 
+    // ---- 128-bit vector helpers. ----
+
+    /// The lane type of the `V128` vreg `v`.
+    fn v128_lanes(&self, v: Reg) -> ScalarType {
+        match self.func.vreg_type(v) {
+            Type::V128(lanes) => lanes,
+            ty => panic!("{v} is a {ty}, not a v128"),
+        }
+    }
+
+    fn vec_binop<F>(&mut self, a: Reg, b: Reg, make_inst: F) -> Reg
+    where
+        F: FnOnce(ScalarType, Reg, Reg) -> X64Inst,
+    {
+        let lanes = self.v128_lanes(a);
+        assert_eq!(lanes, self.v128_lanes(b), "lane types of {a} and {b} differ");
+        let dst = self.func.new_typed_vreg(Type::V128(lanes));
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst, src: a });
+        bd.push_target_inst(make_inst(lanes, dst, b));
+        dst
+    }
+
+    /// 128-bit load of `lanes` lanes from `[base + disp]`, at any alignment.
+    pub fn load_v128(&mut self, lanes: ScalarType, base: Reg, disp: i32) -> Reg {
+        let dst = self.func.new_typed_vreg(Type::V128(lanes));
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Movdqurm {
+                dst,
+                src: Mem::base_disp(base, disp),
+            });
+        dst
+    }
+
+    pub fn store_v128(&mut self, base: Reg, disp: i32, val: Reg) {
+        self.v128_lanes(val);
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Movdqumr {
+                dst: Mem::base_disp(base, disp),
+                src: val,
+            });
+    }
+
+    /// Lane-wise `a + b`.
+    pub fn vadd(&mut self, a: Reg, b: Reg) -> Reg {
+        self.vec_binop(a, b, |lanes, dst, src| X64Inst::Vaddrr { lanes, dst, src })
+    }
+
+    /// Lane-wise `a * b`, keeping the low half of each integer product.
+    /// `i32` lanes need SSE4.1; SSE has no `i8` or `i64` multiply.
+    pub fn vmul(&mut self, a: Reg, b: Reg) -> Reg {
+        let lanes = self.v128_lanes(a);
+        assert!(
+            matches!(lanes, ScalarType::I16 | ScalarType::I32 | ScalarType::F32 | ScalarType::F64),
+            "no vector multiply for {lanes} lanes"
+        );
+        self.vec_binop(a, b, |lanes, dst, src| X64Inst::Vmulrr { lanes, dst, src })
+    }
+
+    /// `v` with its lanes permuted: lane `i` of the result is lane
+    /// `order[i]` of `v`. Lanes must be 32 or 64 bits wide.
+    pub fn shuffle(&mut self, v: Reg, order: &[u8]) -> Reg {
+        let lanes = self.v128_lanes(v);
+        let count = 128 / lanes.bits() as usize;
+        assert!(count <= 4, "shuffling {lanes} lanes needs pshufb");
+        assert!(
+            order.len() == count && order.iter().all(|&l| usize::from(l) < count),
+            "a shuffle of {count} lanes takes {count} lane indices, got {order:?}"
+        );
+        // Each lane is `4 / count` dwords of the `pshufd` order.
+        let per = 4 / count;
+        let dwords = (0..4).map(|d| order[d / per] * per as u8 + (d % per) as u8);
+        let imm = dwords.enumerate().fold(0, |imm, (d, src)| imm | src << (2 * d));
+        let dst = self.func.new_typed_vreg(Type::V128(lanes));
+        self.func
+            .get_block_data_mut(self.current)
+            .push_target_inst(X64Inst::Pshufdrri { dst, src: v, order: imm });
+        dst
+    }
+
+    /// Lane `lane` of `v` as a scalar: zero-extended into an `I64` for
+    /// integer lanes, an `F32`/`F64` for float ones.
+    pub fn extract_lane(&mut self, v: Reg, lane: u8) -> Reg {
+        let lanes = self.v128_lanes(v);
+        assert!(u32::from(lane) < 128 / lanes.bits(), "{lanes} vector has no lane {lane}");
+        let inst = match lanes {
+            ScalarType::F32 => {
+                let dst = self.func.new_typed_vreg(Type::F32);
+                X64Inst::Pshufdrri { dst, src: v, order: lane }
+            }
+            ScalarType::F64 => {
+                let dst = self.func.new_typed_vreg(Type::F64);
+                X64Inst::Pshufdrri { dst, src: v, order: (2 * lane) | (2 * lane + 1) << 2 }
+            }
+            _ => {
+                let dst = self.func.new_typed_vreg(Type::I64);
+                X64Inst::Pextrrri { lanes, dst, src: v, lane }
+            }
+        };
+        let dst = inst.get_defs()[0];
+        self.func.get_block_data_mut(self.current).push_target_inst(inst);
+        dst
+    }
+
     // ---- Atomic helpers. ----
 
     /// Atomic fetch-and-add on a 64-bit memory location. Returns the
@@ -746,6 +1249,120 @@ impl FuncBuilder {
         (out, success)
     }
 
+    // ---- Memory intrinsics. ----
+
+    /// A fresh `I64` vreg pre-bound to `preg`.
+    fn pinned_vreg(&mut self, preg: Reg) -> Reg {
+        let r = self.func.new_typed_vreg(Type::I64);
+        self.func.pre_bind(r, preg);
+        r
+    }
+
+    /// Release the pinned results of a string op nobody reads.
+    fn kill_all(&mut self, regs: &[Reg]) {
+        let bd = self.func.get_block_data_mut(self.current);
+        for &src in regs {
+            bd.push_pseudo_inst(PseudoInstruction::Kill { src });
+        }
+    }
+
+    /// Copy `len` bytes from `src` to `dst` with `rep movsb`. The ranges
+    /// must not overlap.
+    pub fn memcpy(&mut self, dst: Reg, src: Reg, len: Reg) {
+        let (dst_in, src_in, count_in) =
+            (self.pinned_vreg(RDI), self.pinned_vreg(RSI), self.pinned_vreg(RCX));
+        let (dst_out, src_out, count_out) =
+            (self.pinned_vreg(RDI), self.pinned_vreg(RSI), self.pinned_vreg(RCX));
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: dst_in, src: dst });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: src_in, src });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: count_in, src: len });
+        bd.push_target_inst(X64Inst::RepMovsb {
+            dst_in,
+            src_in,
+            count_in,
+            dst_out,
+            src_out,
+            count_out,
+        });
+        self.kill_all(&[dst_out, src_out, count_out]);
+    }
+
+    /// `memcpy` of a known length. Up to `INLINE_MEM_BYTES` it unrolls
+    /// into 16-byte `movdqu`s and 8/4/2/1-byte moves for the tail, with
+    /// no pinned registers; longer copies go through `rep movsb`.
+    pub fn memcpy_const(&mut self, dst: Reg, src: Reg, len: u64) {
+        if len > INLINE_MEM_BYTES {
+            let len = self.iconst64(len as i64);
+            self.memcpy(dst, src, len);
+            return;
+        }
+        for (disp, width) in mem_chunks(len, true) {
+            match width {
+                16 => {
+                    let v = self.load_v128(ScalarType::I8, src, disp);
+                    self.store_v128(dst, disp, v);
+                }
+                8 => {
+                    let v = self.load_i64(src, disp);
+                    self.store_i64(dst, disp, v);
+                }
+                4 => {
+                    let v = self.load_i32(src, disp);
+                    self.store_i32(dst, disp, v);
+                }
+                2 => {
+                    let v = self.load_i16(src, disp);
+                    self.store_i16(dst, disp, v);
+                }
+                _ => {
+                    let v = self.load_i8(src, disp);
+                    self.store_i8(dst, disp, v);
+                }
+            }
+        }
+    }
+
+    /// Fill `len` bytes at `dst` with the low byte of `byte`, with
+    /// `rep stosb`.
+    pub fn memset(&mut self, dst: Reg, byte: Reg, len: Reg) {
+        let (dst_in, byte_in, count_in) =
+            (self.pinned_vreg(RDI), self.pinned_vreg(RAX), self.pinned_vreg(RCX));
+        let (dst_out, count_out) = (self.pinned_vreg(RDI), self.pinned_vreg(RCX));
+        let bd = self.func.get_block_data_mut(self.current);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: dst_in, src: dst });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: byte_in, src: byte });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: count_in, src: len });
+        bd.push_target_inst(X64Inst::RepStosb {
+            dst_in,
+            byte: byte_in,
+            count_in,
+            dst_out,
+            count_out,
+        });
+        self.kill_all(&[dst_out, count_out]);
+    }
+
+    /// `memset` of a known byte and length. Up to `INLINE_MEM_BYTES` it
+    /// stores the byte splatted across a GPR, 8 bytes at a time, then
+    /// 4/2/1; longer fills go through `rep stosb`.
+    pub fn memset_const(&mut self, dst: Reg, byte: u8, len: u64) {
+        let splat = self.iconst64((u64::from(byte) * 0x0101_0101_0101_0101) as i64);
+        if len > INLINE_MEM_BYTES {
+            let len = self.iconst64(len as i64);
+            self.memset(dst, splat, len);
+            return;
+        }
+        for (disp, width) in mem_chunks(len, false) {
+            match width {
+                8 => self.store_i64(dst, disp, splat),
+                4 => self.store_i32(dst, disp, splat),
+                2 => self.store_i16(dst, disp, splat),
+                _ => self.store_i8(dst, disp, splat),
+            }
+        }
+    }
+
     // ---- Aggregate helpers. ----
 
     /// Register an aggregate with initial `elems` and return its
@@ -785,6 +1402,48 @@ impl FuncBuilder {
         dst
     }
 
+    /// Define the next incoming argument as a by-value C struct with
+    /// scalar `fields`. Returns its aggregate vreg.
+    pub fn arg_struct(&mut self, fields: &[Type]) -> Reg {
+        let ty = self.struct_type(fields);
+        self.arg_typed(ty)
+    }
+
+    /// `call_sym` to a function returning a by-value C struct with scalar
+    /// `fields`. Returns its aggregate vreg. `args` may hold aggregates
+    /// too, passed as structs of their elements.
+    pub fn call_sym_struct(&mut self, symbol: &str, args: &[Reg], fields: &[Type]) -> Reg {
+        self.call_struct(CallTarget::Symbol(symbol.to_string()), args, fields)
+    }
+
+    /// `call_indirect` to a function returning a by-value C struct with
+    /// scalar `fields`.
+    pub fn call_indirect_struct(&mut self, fn_ptr: Reg, args: &[Reg], fields: &[Type]) -> Reg {
+        self.call_struct(CallTarget::Indirect(fn_ptr), args, fields)
+    }
+
+    fn call_struct(&mut self, callee: CallTarget, args: &[Reg], fields: &[Type]) -> Reg {
+        let ty = self.struct_type(fields);
+        let user_ret = self.func.new_typed_vreg(ty);
+        let id = self.func.new_call(CallData {
+            callee,
+            args: args.to_vec(),
+            rets: vec![user_ret],
+            patch: None,
+        });
+        self.func
+            .get_block_data_mut(self.current)
+            .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
+        user_ret
+    }
+
+    /// An aggregate type over fresh element vregs, which ABI lowering
+    /// defines when the value arrives.
+    fn struct_type(&mut self, fields: &[Type]) -> Type {
+        let elems = fields.iter().map(|&ty| self.func.new_typed_vreg(ty)).collect();
+        Type::Agg(self.func.new_aggregate(elems))
+    }
+
     #[must_use]
     pub fn build(self) -> Func<X64Inst> {
         self.func
@@ -794,7 +1453,7 @@ impl FuncBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::codegen::tir::Inst;
+    use crate::codegen::tir::{Inst, Instruction};
 
     #[test]
     fn add_emits_copy_then_add_and_returns_fresh_vreg() {
@@ -816,6 +1475,23 @@ mod tests {
         assert_eq!(add.get_uses().as_slice(), &[s, c]);
     }
 
+    #[test]
+    fn i128_add_puts_the_adc_right_after_its_add() {
+        let mut b = FuncBuilder::new("t");
+        let (x, y) = (b.arg_i128(), b.arg_i128());
+        let s = b.add_i128(x, y);
+        let entry = b.entry_block();
+        let f = b.build();
+        let insts: Vec<_> = f.get_block_data(entry).iter().copied().collect();
+        assert_eq!(
+            insts[insts.len() - 2..],
+            [
+                Instruction::Target(X64Inst::Add64rr { dst: s.lo, src: y.lo }),
+                Instruction::Target(X64Inst::Adc64rr { dst: s.hi, src: y.hi }),
+            ]
+        );
+    }
+
     #[test]
     fn arg_indices_increase_monotonically() {
         let mut b = FuncBuilder::new("t");
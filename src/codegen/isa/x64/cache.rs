@@ -0,0 +1,248 @@
+//! Incremental recompilation of a module.
+//!
+//! `CompileCache` keeps each function's `CompiledCode` under a hash of the
+//! function as the pipeline gets it — after cross-module inlining, so an
+//! edit to an inlined callee reaches its callers — together with the
+//! target and the options. Compiling the module again runs the pipeline
+//! only for functions whose hash changed and hands back the rest as
+//! cached: bytes, relocations, traps, stack maps and all. Dumps and
+//! `stats` counters come from the functions actually compiled. Functions
+//! are cached by name, so a module's names must be distinct.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::isa::x64::pipeline::{CompiledCode, compile_each, inline_module};
+use crate::codegen::options::CodegenOptions;
+use crate::codegen::tir::Func;
+
+/// Compiled functions by name, each with the hash it was compiled from.
+#[derive(Default)]
+pub struct CompileCache {
+    entries: HashMap<String, (u64, CompiledCode)>,
+    hits: usize,
+    misses: usize,
+}
+
+impl CompileCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `compile_module`, reusing the cached code of every function whose
+    /// inlined body, target and options are unchanged since it was last
+    /// compiled. Functions no longer in `funcs` are dropped from the cache.
+    ///
+    /// # Panics
+    /// On any error `try_compile_module` would return.
+    #[must_use]
+    pub fn compile_module(
+        &mut self,
+        funcs: Vec<Func<X64Inst>>,
+        target: Target,
+        options: &CodegenOptions,
+    ) -> Vec<CompiledCode> {
+        self.try_compile_module(funcs, target, options).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// `compile_module`, reporting failures instead of panicking. Functions
+    /// that did compile are cached even when another one fails.
+    ///
+    /// # Errors
+    /// `DuplicateSymbol` if two of `funcs` share a name, else as
+    /// `pipeline::try_compile_module`.
+    pub fn try_compile_module(
+        &mut self,
+        mut funcs: Vec<Func<X64Inst>>,
+        target: Target,
+        options: &CodegenOptions,
+    ) -> Result<Vec<CompiledCode>, CodegenError> {
+        let mut names = HashSet::new();
+        if let Some(dup) = funcs.iter().find(|f| !names.insert(f.name())) {
+            return Err(CodegenError::DuplicateSymbol(dup.name().to_string()));
+        }
+        inline_module(&mut funcs, options)?;
+        let mut config = DefaultHasher::new();
+        target.hash(&mut config);
+        options.hash(&mut config);
+        let keys: Vec<u64> = funcs
+            .iter()
+            .map(|f| {
+                let mut h = config.clone();
+                f.content_hash().hash(&mut h);
+                h.finish()
+            })
+            .collect();
+
+        let mut out: Vec<Option<CompiledCode>> = vec![None; funcs.len()];
+        let (mut stale, mut stale_at) = (Vec::new(), Vec::new());
+        for (i, func) in funcs.into_iter().enumerate() {
+            match self.entries.get(func.name()) {
+                Some((key, code)) if *key == keys[i] => out[i] = Some(code.clone()),
+                _ => {
+                    stale.push(func);
+                    stale_at.push(i);
+                }
+            }
+        }
+        self.misses += stale.len();
+        self.hits += out.len() - stale.len();
+        let mut first_err = None;
+        for (i, code) in stale_at.into_iter().zip(compile_each(stale, target, options)) {
+            match code {
+                Ok(code) => {
+                    self.entries.insert(code.name.clone(), (keys[i], code.clone()));
+                    out[i] = Some(code);
+                }
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+
+        let out: Vec<CompiledCode> =
+            out.into_iter().map(|c| c.expect("every function cached or compiled")).collect();
+        let live: HashSet<&str> = out.iter().map(|c| c.name.as_str()).collect();
+        self.entries.retain(|name, _| live.contains(name.as_str()));
+        Ok(out)
+    }
+
+    /// Functions reused from the cache, over all `compile_module` calls.
+    #[must_use]
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Functions run through the pipeline, over all `compile_module` calls.
+    #[must_use]
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Number of functions cached.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget every compiled function, keeping the counters.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::parser::parse_module;
+    use crate::codegen::isa::x64::pipeline::compile_module;
+    use crate::codegen::options::OptLevel;
+
+    const SRC: &str = "\
+func @twice(%a) {
+    %b = add %a, %a
+    ret %b
+}
+
+func @f(%a) {
+    %b = call @twice(%a)
+    ret %b
+}
+
+func @g(%a, %b) {
+    %c = sub %a, %b
+    ret %c
+}
+";
+
+    fn compile(cache: &mut CompileCache, src: &str, options: &CodegenOptions) -> Vec<Vec<u8>> {
+        let funcs = parse_module(src).expect("parses");
+        cache.compile_module(funcs, Target::X64SysV, options).into_iter().map(|c| c.bytes).collect()
+    }
+
+    #[test]
+    fn recompiling_reuses_unchanged_functions() {
+        let options = CodegenOptions::default();
+        let mut cache = CompileCache::new();
+        let first = compile(&mut cache, SRC, &options);
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 3, 3));
+        let second = compile(&mut cache, SRC, &options);
+        assert_eq!((cache.hits(), cache.misses()), (3, 3));
+        assert_eq!(first, second);
+        let fresh = compile_module(parse_module(SRC).expect("parses"), Target::X64SysV, &options);
+        assert_eq!(second, fresh.into_iter().map(|c| c.bytes).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn edits_recompile_the_function_and_callers_that_inlined_it() {
+        let options = CodegenOptions::default();
+        let mut cache = CompileCache::new();
+        let _ = compile(&mut cache, SRC, &options);
+
+        let edited_g = SRC.replace("%c = sub %a, %b", "%c = xor %a, %b");
+        let _ = compile(&mut cache, &edited_g, &options);
+        assert_eq!((cache.hits(), cache.misses()), (2, 4));
+
+        // `f` inlines `twice`, so it changes with it.
+        let edited_twice = edited_g.replace("%b = add %a, %a", "%b = imul %a, %a");
+        let _ = compile(&mut cache, &edited_twice, &options);
+        assert_eq!((cache.hits(), cache.misses()), (3, 6));
+
+        // Without inlining, `f` only calls `twice` and stays cached.
+        let o0 = CodegenOptions { opt_level: OptLevel::None, ..CodegenOptions::default() };
+        let _ = compile(&mut cache, &edited_twice, &o0);
+        assert_eq!((cache.hits(), cache.misses()), (3, 9));
+        let _ = compile(&mut cache, SRC, &o0);
+        assert_eq!((cache.hits(), cache.misses()), (4, 11));
+    }
+
+    #[test]
+    fn errors_are_returned_and_the_rest_still_cached() {
+        // At `-O0`, so the broken function reaches the pipeline.
+        let o0 = CodegenOptions { opt_level: OptLevel::None, ..CodegenOptions::default() };
+        let mut cache = CompileCache::new();
+        let mut funcs = parse_module(SRC).expect("parses");
+        funcs.push(Func::new("empty".into()));
+        let Err(err) = cache.try_compile_module(funcs, Target::X64SysV, &o0) else {
+            panic!("an empty function fails");
+        };
+        assert_eq!(err.location().expect("located").func, "empty", "{err}");
+        assert_eq!(cache.len(), 3);
+        let _ = compile(&mut cache, SRC, &o0);
+        assert_eq!((cache.hits(), cache.misses()), (3, 4));
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let mut cache = CompileCache::new();
+        let mut funcs = parse_module(SRC).expect("parses");
+        funcs.extend(parse_module(SRC).expect("parses").pop());
+        let result = cache.try_compile_module(funcs, Target::X64SysV, &CodegenOptions::default());
+        assert!(matches!(result, Err(CodegenError::DuplicateSymbol(name)) if name == "g"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn removed_functions_leave_the_cache() {
+        let options = CodegenOptions::default();
+        let mut cache = CompileCache::new();
+        let _ = compile(&mut cache, SRC, &options);
+        let without_g = &SRC[..SRC.find("func @g").expect("has g")];
+        let _ = compile(&mut cache, without_g, &options);
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
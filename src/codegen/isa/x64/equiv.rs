@@ -0,0 +1,269 @@
+//! Pre/post-transformation equivalence checking.
+//!
+//! Runs a function compiled two ways — `before` and `after` a pass, or
+//! under two sets of options (say, with and without `plan_spills`, or
+//! either register allocator) — on the same argument sets and reports the
+//! first argument set whose results differ. `minimize` then deletes lines
+//! from a text-IR input while the difference persists, leaving a small
+//! reproducer.
+//!
+//! There is no TIR interpreter to serve as the reference, so both sides
+//! run as JIT-compiled machine code; `Variant::reference` keeps the
+//! `before` side to the required passes so it leans on as little of the
+//! optimizer as possible. Functions take up to `MAX_ARGS` integer
+//! arguments and return an integer, and must not trap or call symbols the
+//! process can't resolve.
+
+use std::fmt;
+
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::isa::x64::parser::parse_module;
+use crate::codegen::isa::x64::pipeline::try_compile_function;
+use crate::codegen::isa::x64::regalloc_fuzz::{quietly, shrink};
+use crate::codegen::jit::Module;
+use crate::codegen::options::{CodegenOptions, OptLevel};
+use crate::codegen::tir::Func;
+
+/// Integer arguments passed to every function; ones it doesn't take are
+/// ignored.
+pub const MAX_ARGS: usize = 6;
+
+pub type Args = [i64; MAX_ARGS];
+
+type Entry = unsafe extern "sysv64" fn(i64, i64, i64, i64, i64, i64) -> i64;
+
+/// One way of compiling a function: `pass`, if any, rewrites it ahead of
+/// the pipeline, which then runs under `options`.
+#[derive(Clone, Debug)]
+pub struct Variant {
+    pub pass: Option<fn(&mut Func<X64Inst>)>,
+    pub options: CodegenOptions,
+}
+
+impl Variant {
+    /// Required passes only, no coalescing, with the IR verifier and the
+    /// allocation checker on.
+    #[must_use]
+    pub fn reference() -> Self {
+        Self::with_options(CodegenOptions {
+            opt_level: OptLevel::None,
+            coalesce: false,
+            ..CodegenOptions::default()
+        })
+    }
+
+    /// The pipeline under `options`, with the IR verifier and the
+    /// allocation checker forced on.
+    #[must_use]
+    pub fn with_options(options: CodegenOptions) -> Self {
+        let options = CodegenOptions { verify: true, check_regalloc: true, ..options };
+        Self { pass: None, options }
+    }
+
+    /// `pass` ahead of the `reference` pipeline, to pair with `reference`
+    /// itself. It sees the function as built, so it runs whatever its pass
+    /// requires first, e.g. `destroy_ssa`.
+    #[must_use]
+    pub fn with_pass(pass: fn(&mut Func<X64Inst>)) -> Self {
+        Self { pass: Some(pass), ..Self::reference() }
+    }
+
+    /// Compile `func` this way and load it.
+    ///
+    /// # Errors
+    /// The compile error or pipeline panic message, or the loader's error.
+    pub fn load(&self, mut func: Func<X64Inst>) -> Result<Module, String> {
+        if let Some(pass) = self.pass {
+            pass(&mut func);
+        }
+        let compiled = quietly(|| try_compile_function(func, Target::X64SysV, &self.options))?
+            .map_err(|e| e.root().to_string())?;
+        Module::load_with_relocs(&compiled.bytes, &compiled.relocations, &compiled.name)
+            .map_err(|e| format!("load error: {e}"))
+    }
+}
+
+/// Run `module`'s function on each of `args`.
+fn run(module: &Module, args: &[Args]) -> Vec<i64> {
+    // SAFETY: `Variant::load` compiled the function for SysV; callers
+    // promise it takes at most `MAX_ARGS` integers and returns one.
+    let f: Entry = unsafe { module.entry() };
+    args.iter().map(|a| unsafe { f(a[0], a[1], a[2], a[3], a[4], a[5]) }).collect()
+}
+
+/// Arguments on which the two sides returned different results.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub args: Args,
+    pub before: i64,
+    pub after: i64,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "on {:?}: {} before, {} after", self.args, self.before, self.after)
+    }
+}
+
+/// Run the function `make` builds compiled as `before` and as `after`
+/// on each of `args`; `Ok(None)` if every result agrees. `make` is called
+/// once per side, since compiling consumes the function.
+///
+/// # Errors
+/// Either side failing to compile or load.
+pub fn compare(
+    make: impl Fn() -> Func<X64Inst>,
+    before: &Variant,
+    after: &Variant,
+    args: &[Args],
+) -> Result<Option<Mismatch>, String> {
+    let want = run(&before.load(make())?, args);
+    let got = run(&after.load(make())?, args);
+    Ok(args
+        .iter()
+        .zip(want.iter().zip(&got))
+        .find(|(_, (w, g))| w != g)
+        .map(|(&args, (&before, &after))| Mismatch { args, before, after }))
+}
+
+/// `compare` on the single function in text-IR `src`.
+///
+/// # Errors
+/// A parse error, a module with more than one function, or either side
+/// failing to compile or load.
+pub fn compare_text(
+    src: &str,
+    before: &Variant,
+    after: &Variant,
+    args: &[Args],
+) -> Result<Option<Mismatch>, String> {
+    let parse = || {
+        let mut funcs = parse_module(src).map_err(|e| format!("parse error: {e}"))?;
+        match funcs.len() {
+            1 => Ok(funcs.pop().expect("one function")),
+            n => Err(format!("expected one function, found {n}")),
+        }
+    };
+    parse()?;
+    compare(|| parse().expect("parsed once already"), before, after, args)
+}
+
+/// Shrink `src`, which `compare_text` flags, to fewer lines that still
+/// compile on both sides and still mismatch on some of `args`.
+#[must_use]
+pub fn minimize(src: &str, before: &Variant, after: &Variant, args: &[Args]) -> String {
+    shrink(src, |text| matches!(compare_text(text, before, after, args), Ok(Some(_))))
+}
+
+/// `count` argument sets: all zeros, then a mix of boundary values and
+/// pseudo-random ones drawn from `seed`.
+#[must_use]
+pub fn arg_sets(seed: u64, count: usize) -> Vec<Args> {
+    const EDGES: [i64; 8] = [0, 1, -1, 2, 63, i64::MIN, i64::MAX, 0x5555_5555_5555_5555];
+    let mut state = seed;
+    // splitmix64, so nearby seeds give unrelated sets.
+    let mut next = move || {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    let mut sets = vec![[0; MAX_ARGS]];
+    while sets.len() < count {
+        sets.push(std::array::from_fn(|_| {
+            let r = next();
+            if r % 4 == 0 { EDGES[(r >> 8) as usize % EDGES.len()] } else { r.cast_signed() }
+        }));
+    }
+    sets.truncate(count);
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::isa::x64::passes::const_fold::fold_constants;
+    use crate::codegen::isa::x64::passes::load_elim::eliminate_redundant_loads;
+    use crate::codegen::isa::x64::strategy::FuncSpec;
+    use crate::codegen::passes::destroy_ssa;
+    use crate::codegen::tir::Instruction;
+
+    /// A broken "pass": every `sub` becomes an `add`.
+    fn sub_to_add(func: &mut Func<X64Inst>) {
+        let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+        for b in blocks {
+            for inst in func.get_block_data_mut(b).insts_mut() {
+                if let Instruction::Target(X64Inst::Sub64rr { dst, src }) = *inst {
+                    *inst = Instruction::Target(X64Inst::Add64rr { dst, src });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flags_a_miscompiling_pass_and_minimizes_the_reproducer() {
+        let src = "func @f(%a, %b) {\n    %c = iconst 7\n    %d = add %a, %c\n    \
+                   %e = sub %a, %b\n    %g = xor %d, %e\n    %h = imul %g, %c\n    \
+                   %i = or %h, %d\n    ret %e\n}\n";
+        let (before, after) = (Variant::reference(), Variant::with_pass(sub_to_add));
+        let args = arg_sets(1, 16);
+        let mismatch = compare_text(src, &before, &after, &args).expect("compiles");
+        let mismatch = mismatch.expect("sub_to_add changes the result");
+        assert_ne!(mismatch.before, mismatch.after);
+        let small = minimize(src, &before, &after, &args);
+        assert_eq!(small, "func @f(%a, %b) {\n    %e = sub %a, %b\n    ret %e\n}\n");
+        assert!(matches!(compare_text(&small, &before, &after, &args), Ok(Some(_))));
+    }
+
+    #[test]
+    fn identical_variants_agree_and_bad_input_is_an_error() {
+        let src = "func @f(%a) {\n    %b = neg %a\n    ret %b\n}\n";
+        let v = Variant::reference();
+        assert_eq!(compare_text(src, &v, &v, &arg_sets(2, 8)), Ok(None));
+        assert!(compare_text("func @f(", &v, &v, &[]).is_err());
+    }
+
+    #[test]
+    fn arg_sets_start_at_zero_and_depend_on_the_seed() {
+        let sets = arg_sets(3, 10);
+        assert_eq!(sets.len(), 10);
+        assert_eq!(sets[0], [0; MAX_ARGS]);
+        assert_ne!(arg_sets(4, 10), sets);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn optimizations_preserve_results(spec in any::<FuncSpec>(), seed in any::<u64>()) {
+            let args = arg_sets(seed, 8);
+            let make = || spec.build("f");
+            let reference = Variant::reference();
+            let afters = [
+                Variant::with_options(CodegenOptions::default()),
+                Variant::with_options(CodegenOptions {
+                    opt_level: OptLevel::Size,
+                    plan_spills: true,
+                    ..CodegenOptions::default()
+                }),
+                Variant::with_pass(|f| {
+                    destroy_ssa(f);
+                    fold_constants(f);
+                }),
+                Variant::with_pass(|f| {
+                    destroy_ssa(f);
+                    eliminate_redundant_loads(f);
+                }),
+            ];
+            for after in &afters {
+                let mismatch = compare(make, &reference, after, &args).expect("compiles");
+                prop_assert!(mismatch.is_none(), "{after:?}: {}", mismatch.expect("some"));
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! x86-64 CPU features beyond the SSE2 baseline, and what codegen does
+//! with them.
+//!
+//! `CodegenOptions::cpu_features` is the set a compiled function may
+//! assume. `select_for_features` runs first in the pipeline: an intrinsic
+//! with a baseline fallback gets it when its feature is missing, and an
+//! instruction needing a missing feature is an error, so a function
+//! compiled for a set never faults on a CPU that has it.
+
+use std::fmt::{self, Display};
+use std::ops::BitOr;
+use std::str::FromStr;
+
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::isa::x64::intrinsics::POPCNT_SWAR;
+use crate::codegen::tir::{Func, Instruction, PseudoInstruction, ScalarType};
+
+/// A set of CPU features, as bits; `BASELINE` (SSE2) is empty.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CpuFeatures(u32);
+
+/// Name and bit of each feature, in bit order.
+const NAMES: [(&str, CpuFeatures); 4] = [
+    ("sse4.1", CpuFeatures::SSE4_1),
+    ("popcnt", CpuFeatures::POPCNT),
+    ("avx", CpuFeatures::AVX),
+    ("avx2", CpuFeatures::AVX2),
+];
+
+impl CpuFeatures {
+    pub const BASELINE: Self = Self(0);
+    pub const SSE4_1: Self = Self(1 << 0);
+    pub const POPCNT: Self = Self(1 << 1);
+    /// AVX with OS support for saving the `ymm` state.
+    pub const AVX: Self = Self(1 << 2);
+    pub const AVX2: Self = Self(1 << 3);
+    /// The part of x86-64-v2 codegen cares about; what it has always
+    /// assumed.
+    pub const V2: Self = Self(Self::SSE4_1.0 | Self::POPCNT.0);
+    pub const V3: Self = Self(Self::V2.0 | Self::AVX.0 | Self::AVX2.0);
+
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features of the CPU running this process.
+    #[must_use]
+    pub fn host() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let detected = [
+                std::arch::is_x86_feature_detected!("sse4.1"),
+                std::arch::is_x86_feature_detected!("popcnt"),
+                std::arch::is_x86_feature_detected!("avx"),
+                std::arch::is_x86_feature_detected!("avx2"),
+            ];
+            NAMES
+                .iter()
+                .zip(detected)
+                .filter(|&(_, on)| on)
+                .fold(Self::BASELINE, |set, (&(_, f), _)| set | f)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        Self::BASELINE
+    }
+}
+
+impl BitOr for CpuFeatures {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// `sse2` for the baseline, else the feature names joined by `,`.
+impl Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::BASELINE {
+            return f.write_str("sse2");
+        }
+        let names: Vec<&str> =
+            NAMES.iter().filter(|&&(_, bit)| self.contains(bit)).map(|&(n, _)| n).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+/// A level (`x86-64`, `x86-64-v2`, `x86-64-v3`, `native`) or a `,`-list
+/// of feature names.
+impl FromStr for CpuFeatures {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "x86-64" | "sse2" => return Ok(Self::BASELINE),
+            "x86-64-v2" => return Ok(Self::V2),
+            "x86-64-v3" => return Ok(Self::V3),
+            "native" => return Ok(Self::host()),
+            _ => {}
+        }
+        s.split(',').try_fold(Self::BASELINE, |set, name| {
+            let (_, bit) = NAMES
+                .iter()
+                .find(|&&(n, _)| n == name)
+                .ok_or_else(|| format!("unknown CPU feature `{name}`"))?;
+            Ok(set | *bit)
+        })
+    }
+}
+
+/// The features `inst` needs beyond SSE2.
+#[must_use]
+pub fn required_by(inst: &X64Inst) -> CpuFeatures {
+    match inst {
+        // `pmulld`, and `pextrb`/`d`/`q`; `pmullw` and `pextrw` are SSE2.
+        X64Inst::Vmulrr { lanes: ScalarType::I32, .. } => CpuFeatures::SSE4_1,
+        X64Inst::Pextrrri { lanes, .. } if *lanes != ScalarType::I16 => CpuFeatures::SSE4_1,
+        _ => CpuFeatures::BASELINE,
+    }
+}
+
+/// Fit `func` to `features`: `x64.popcnt` becomes its SWAR fallback
+/// without `POPCNT`. Returns whether anything changed.
+///
+/// # Errors
+/// `MissingCpuFeatures` for the first instruction needing a feature
+/// outside `features`.
+pub fn select_for_features(
+    func: &mut Func<X64Inst>,
+    features: CpuFeatures,
+) -> Result<bool, CodegenError> {
+    let mut changed = false;
+    let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+    for block in blocks {
+        let insts = func.get_block_data(block).insts().to_vec();
+        for (i, inst) in insts.iter().enumerate() {
+            match *inst {
+                Instruction::Pseudo(PseudoInstruction::Intrinsic { id })
+                    if func.intrinsic_operands(id).decl.name == "x64.popcnt"
+                        && !features.contains(CpuFeatures::POPCNT) =>
+                {
+                    func.intrinsic_operands_mut(id).decl = &POPCNT_SWAR;
+                    changed = true;
+                }
+                Instruction::Target(ref t) if !features.contains(required_by(t)) => {
+                    return Err(CodegenError::MissingCpuFeatures {
+                        block,
+                        inst: i,
+                        needs: required_by(t),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+
+    #[test]
+    fn parses_levels_and_lists_and_prints_names() {
+        assert_eq!("x86-64".parse(), Ok(CpuFeatures::BASELINE));
+        assert_eq!("x86-64-v3".parse(), Ok(CpuFeatures::V3));
+        assert_eq!("popcnt,sse4.1".parse(), Ok(CpuFeatures::V2));
+        assert!("sse5".parse::<CpuFeatures>().is_err());
+        assert_eq!(CpuFeatures::V3.to_string(), "sse4.1,popcnt,avx,avx2");
+        assert_eq!(CpuFeatures::BASELINE.to_string(), "sse2");
+    }
+
+    #[test]
+    fn popcnt_falls_back_and_sse4_1_ops_are_rejected_on_the_baseline() {
+        let mut b = FuncBuilder::new("f");
+        let x = b.arg();
+        let n = b.intrinsic("x64.popcnt", &[x])[0];
+        b.ret(n);
+        let mut func = b.build();
+        assert_eq!(select_for_features(&mut func, CpuFeatures::V2).ok(), Some(false));
+        assert_eq!(select_for_features(&mut func, CpuFeatures::BASELINE).ok(), Some(true));
+        let entry = func.get_entry_block().expect("entry");
+        let Instruction::Pseudo(PseudoInstruction::Intrinsic { id }) =
+            func.get_block_data(entry).insts()[1]
+        else {
+            panic!("expected the intrinsic");
+        };
+        assert_eq!(func.intrinsic_operands(id).decl.name, "x64.popcnt.swar");
+
+        let mut func = FuncBuilder::new("g").build();
+        let (dst, src) = (func.new_vreg(), func.new_vreg());
+        let entry = func.get_entry_block().expect("entry");
+        func.get_block_data_mut(entry).push_target_inst(X64Inst::Vmulrr {
+            lanes: ScalarType::I32,
+            dst,
+            src,
+        });
+        assert!(matches!(
+            select_for_features(&mut func, CpuFeatures::BASELINE),
+            Err(CodegenError::MissingCpuFeatures { inst: 0, needs: CpuFeatures::SSE4_1, .. })
+        ));
+        assert!(select_for_features(&mut func, CpuFeatures::SSE4_1).is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+//! Allocation-aware IR listings.
+//!
+//! `FormatContext` names operands for `Func::write_with` by where register
+//! allocation put them: a physical register by its assembly name, a spill
+//! slot by its frame address (`[rbp-8]`) once the frame is laid out, or as
+//! `slot{n}` before. Operands the allocation doesn't cover keep `v{n}`.
+
+use crate::codegen::isa::x64::frame::FrameLayout;
+use crate::codegen::isa::x64::regs::preg_name;
+use crate::codegen::regalloc::{AllocatedSlot, RegAllocResult};
+use crate::codegen::tir::{OperandFormat, Reg};
+
+/// What an x64 listing knows beyond the IR. The default knows nothing and
+/// prints plain vregs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FormatContext<'a> {
+    /// The allocation, positional over `BlockLayout::compute` of the
+    /// function being printed.
+    pub alloc: Option<&'a RegAllocResult>,
+    /// Where the allocation's spill slots live.
+    pub frame: Option<&'a FrameLayout>,
+}
+
+impl OperandFormat for FormatContext<'_> {
+    fn operand(&self, reg: Reg, pt: u32) -> Option<String> {
+        Some(match self.alloc?.at(reg, pt)? {
+            AllocatedSlot::Reg(p) => preg_name(p).to_string(),
+            AllocatedSlot::Stack(s) => match self.frame {
+                Some(frame) => format!("[rbp{:+}]", frame.spill_offset(s)),
+                None => format!("slot{s}"),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::pipeline::default_ra_config;
+    use crate::codegen::isa::x64::regs::RAX;
+    use crate::codegen::regalloc::Assignment;
+    use crate::codegen::tir::PrintOptions;
+    use crate::support::slotmap::SecondaryMap;
+
+    #[test]
+    fn spilled_operands_print_as_frame_addresses() {
+        let mut b = FuncBuilder::new("f");
+        let x = b.arg();
+        let y = b.new_vreg();
+        b.copy_into(y, x);
+        b.ret(y);
+        let func = b.build();
+
+        let mut ra = RegAllocResult {
+            assignments: SecondaryMap::new(0),
+            frame_layout: vec![0],
+            frame_size: 8,
+            edits: Vec::new(),
+        };
+        ra.assignments.set(x, Assignment::uniform(AllocatedSlot::Reg(RAX), 1, 3));
+        ra.assignments.set(y, Assignment::uniform(AllocatedSlot::Stack(0), 3, 5));
+        let frame = FrameLayout::compute(&func, &default_ra_config(HashMap::new()), &ra);
+        let print = |ctx: &FormatContext| {
+            let mut out = Vec::new();
+            func.write_with(&mut out, &PrintOptions::default(), ctx).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // The prologue pushes the three spill scratches, so the slot sits
+        // under 8 bytes of alignment padding.
+        let text = print(&FormatContext { alloc: Some(&ra), frame: Some(&frame) });
+        assert!(text.contains("rax = arg 0"), "{text}");
+        assert!(text.contains("[rbp-16] = copy rax"), "{text}");
+        assert!(text.contains("return [rbp-16]"), "{text}");
+
+        let text = print(&FormatContext { alloc: Some(&ra), frame: None });
+        assert!(text.contains("slot0 = copy rax"), "{text}");
+        assert_eq!(print(&FormatContext::default()), func.to_string());
+    }
+}
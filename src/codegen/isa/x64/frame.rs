@@ -0,0 +1,378 @@
+//! Stack frame layout for x86-64.
+//!
+//! `FrameLayout::compute` runs once per function after register allocation
+//! and fixes where everything the function keeps on its stack lives: the
+//! callee-saved save area, spill slots, `StackAlloc` regions and the
+//! outgoing-argument area of its calls. The emitter then resolves every
+//! frame reference through it. From high to low addresses:
+//!
+//! ```text
+//! rbp + 16 + 8K + 8i    incoming stack argument i
+//! rbp + 8 + 8K          return address
+//! rbp + 8K              saved rbp
+//! rbp .. rbp + 8K       K callee-saved registers
+//! rbp - pad             8 bytes if K is odd, so what follows is 16-aligned
+//!                       spill slots, widest class first
+//!                       `StackAlloc` regions, in program order
+//! rsp + 8i              outgoing stack argument i
+//! ```
+//!
+//! Alignment is honored up to `STACK_ALIGN`, the stack's own alignment.
+//!
+//! A leaf function (see `analysis::leaf`) needs no `rsp` alignment, and
+//! can leave `rsp` at `rbp` and keep a frame of up to `RED_ZONE` bytes in
+//! the red zone below it; see `FrameLayout::use_red_zone`. Nor does it
+//! need `rbp`: `rsp` never moves past its prologue, so the same frame can
+//! be addressed off `rsp`; see `FrameLayout::omit_frame_pointer`.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::codegen::analysis::leaf::LeafInfo;
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
+use crate::codegen::isa::x64::regs::{RBP, RSP};
+use crate::codegen::isa::x64::sysv::{CALLEE_SAVED, RED_ZONE, STACK_ALIGN};
+use crate::codegen::regalloc::{AllocatedSlot, RegAllocConfig, RegAllocResult, StackSlot};
+use crate::codegen::tir::{Func, Instruction, PseudoInstruction, Reg, Type};
+
+/// Something the function addresses in its frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FrameRef {
+    /// A register allocator spill slot.
+    Spill(StackSlot),
+    /// The region of the `StackAlloc` defining this vreg.
+    StackAlloc(Reg),
+    /// The `i`th stack-passed argument of the function itself.
+    IncomingArg(u32),
+    /// The `i`th stack-passed argument of a call the function makes.
+    OutgoingArg(u32),
+}
+
+/// Offsets of everything in one function's frame. See the module docs
+/// for the picture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// Callee-saved registers the prologue pushes after `rbp`, in order.
+    pub saved_regs: Vec<Reg>,
+    /// `sub rsp` after the pushes: locals, outgoing arguments and the
+    /// padding that keeps `rsp` 16-aligned. Zero when the locals sit in
+    /// the red zone.
+    pub frame_adjust: u32,
+    /// Whether the locals live in the red zone below `rsp`.
+    pub red_zone: bool,
+    /// Whether the prologue sets up `rbp`. Without it, the frame is
+    /// addressed off `rsp`.
+    pub frame_pointer: bool,
+    /// Whether the function is a leaf (`LeafInfo::is_leaf`): nothing
+    /// below `rsp` changes while it runs.
+    pub leaf: bool,
+    /// Bytes at the bottom of the frame for calls' stack arguments. Zero
+    /// in a naked function, whose calls move `rsp` themselves.
+    pub outgoing_args: u32,
+    spill_offsets: Vec<i32>,
+    spill_sizes: Vec<u32>,
+    stack_alloc_offsets: HashMap<Reg, i32>,
+}
+
+impl FrameLayout {
+    #[must_use]
+    pub fn compute(func: &Func<X64Inst>, ra_cfg: &RegAllocConfig, ra_res: &RegAllocResult) -> Self {
+        let saved_regs = saved_regs(func, ra_cfg, ra_res);
+        // `rbp - pad` is 16-aligned; `locals` counts down from there.
+        let pad = if saved_regs.len() % 2 == 1 { 8 } else { 0 };
+        let mut locals = 0;
+        let mut place = |size: u32, align: u32| {
+            locals = (locals + size).next_multiple_of(align.clamp(1, STACK_ALIGN));
+            -((pad + locals) as i32)
+        };
+
+        // Every spill slot takes the widest type stored in it.
+        let mut slot_sizes = vec![8; ra_res.frame_size.div_ceil(8) as usize];
+        for (vreg, asn) in &ra_res.assignments {
+            for slot in asn.slots() {
+                if let AllocatedSlot::Stack(s) = slot {
+                    let size = spill_size(func.vreg_type(vreg));
+                    if s as usize >= slot_sizes.len() {
+                        slot_sizes.resize(s as usize + 1, 8);
+                    }
+                    slot_sizes[s as usize] = slot_sizes[s as usize].max(size);
+                }
+            }
+        }
+        let mut order: Vec<usize> = (0..slot_sizes.len()).collect();
+        order.sort_by_key(|&s| std::cmp::Reverse(slot_sizes[s]));
+        let mut spill_offsets = vec![0; slot_sizes.len()];
+        for s in order {
+            spill_offsets[s] = place(slot_sizes[s], slot_sizes[s]);
+        }
+
+        let mut stack_alloc_offsets = HashMap::new();
+        let mut outgoing_args = 0;
+        for (_, bd) in func.blocks_iter() {
+            for inst in bd.iter() {
+                match *inst {
+                    Instruction::Pseudo(PseudoInstruction::StackAlloc { dst, size, align }) => {
+                        stack_alloc_offsets.insert(dst, place(size, align));
+                    }
+                    Instruction::Target(X64Inst::AdjustRsp { delta }) if delta < 0 => {
+                        outgoing_args = outgoing_args.max(delta.unsigned_abs());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if func.attrs().naked {
+            outgoing_args = 0;
+        }
+
+        // Only a call needs `rsp` aligned; a leaf just needs it below the
+        // locals, and none at all without them.
+        let leaf = LeafInfo::compute(func).is_leaf();
+        let frame_adjust = if !leaf {
+            (locals + outgoing_args).next_multiple_of(STACK_ALIGN) + pad
+        } else if locals > 0 {
+            locals + pad
+        } else {
+            0
+        };
+        Self {
+            saved_regs,
+            frame_adjust,
+            red_zone: false,
+            frame_pointer: true,
+            leaf,
+            outgoing_args,
+            spill_offsets,
+            spill_sizes: slot_sizes,
+            stack_alloc_offsets,
+        }
+    }
+
+    /// Keep the locals in the red zone if the function is a leaf and they
+    /// fit, so the prologue and epilogue don't move `rsp`. Offsets stay as
+    /// they are: `rsp` is `rbp` after the prologue either way, or stands
+    /// in for it without a frame pointer.
+    pub fn use_red_zone(&mut self) {
+        if self.leaf && self.frame_adjust > 0 && self.frame_adjust <= RED_ZONE {
+            self.frame_adjust = 0;
+            self.red_zone = true;
+        }
+    }
+
+    /// Don't push `rbp` or set it up if the function is a leaf, and
+    /// address its frame off `rsp` instead. Call before `use_red_zone`.
+    pub fn omit_frame_pointer(&mut self) {
+        if !self.leaf || self.red_zone {
+            return;
+        }
+        // One push fewer flips the padding that keeps the locals aligned.
+        let pad = if self.saved_regs.len() % 2 == 1 { 8 } else { 0 };
+        let shift = 8 - 2 * pad;
+        for off in self.spill_offsets.iter_mut().chain(self.stack_alloc_offsets.values_mut()) {
+            *off -= shift;
+        }
+        if self.frame_adjust > 0 {
+            self.frame_adjust = self.frame_adjust.checked_add_signed(shift).expect("locals padded");
+        }
+        self.frame_pointer = false;
+    }
+
+    /// Offset of spill slot `slot` from where `rbp` points, or would
+    /// without a frame pointer.
+    #[must_use]
+    pub fn spill_offset(&self, slot: StackSlot) -> i32 {
+        self.spill_offsets[slot as usize]
+    }
+
+    /// Bytes spill slot `slot` holds: 16 for one a `V128` ever lives in,
+    /// else 8.
+    #[must_use]
+    pub fn spill_slot_size(&self, slot: StackSlot) -> u32 {
+        self.spill_sizes[slot as usize]
+    }
+
+    /// The address of `r`, as a `Mem` on the physical `rbp` or `rsp`.
+    #[must_use]
+    pub fn resolve(&self, r: FrameRef) -> Mem {
+        // Without `rbp`, `rsp` sits `frame_adjust` below where it would
+        // point, and there is no saved `rbp` under the return address.
+        let frame_mem = |disp: i32| {
+            if self.frame_pointer {
+                Mem::base_disp(RBP, disp)
+            } else {
+                Mem::base_disp(RSP, disp + self.frame_adjust as i32)
+            }
+        };
+        match r {
+            FrameRef::Spill(slot) => frame_mem(self.spill_offset(slot)),
+            FrameRef::StackAlloc(dst) => {
+                let disp = *self
+                    .stack_alloc_offsets
+                    .get(&dst)
+                    .unwrap_or_else(|| panic!("StackAlloc for vreg {dst} has no frame offset"));
+                frame_mem(disp)
+            }
+            FrameRef::IncomingArg(i) => {
+                let k = self.saved_regs.len() as i32;
+                let saved_rbp = if self.frame_pointer { 8 } else { 0 };
+                frame_mem(8 + saved_rbp + 8 * k + 8 * i as i32)
+            }
+            FrameRef::OutgoingArg(i) => Mem::base_disp(RSP, 8 * i as i32),
+        }
+    }
+}
+
+/// Bytes a spill slot needs to hold a `ty`.
+fn spill_size(ty: Type) -> u32 {
+    match ty {
+        Type::V128(_) => 16,
+        Type::V256(_) => 32,
+        Type::V512(_) => 64,
+        _ => 8,
+    }
+}
+
+/// The callee-saved registers the function writes, `rbp` aside (the
+/// prologue saves it anyway). A `noreturn` function saves none, since it
+/// never restores them.
+fn saved_regs(func: &Func<X64Inst>, ra_cfg: &RegAllocConfig, ra_res: &RegAllocResult) -> Vec<Reg> {
+    if func.attrs().noreturn {
+        return Vec::new();
+    }
+    let mut used: BTreeSet<Reg> = BTreeSet::new();
+    for asn in ra_res.assignments.values() {
+        for slot in asn.slots() {
+            if let AllocatedSlot::Reg(r) = slot {
+                used.insert(r);
+            }
+        }
+    }
+    // Scratches only stage stack slots, so a frameless function never
+    // touches them; only a naked one relies on that.
+    if !(func.attrs().naked && ra_res.frame_size == 0) {
+        used.extend(&ra_cfg.scratch_regs);
+    }
+    CALLEE_SAVED
+        .iter()
+        .filter(|r| used.contains(r))
+        .filter(|&&r| r != RBP)
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::codegen::isa::x64::regs::{R10, R11, RAX, RBX};
+    use crate::codegen::regalloc::Assignment;
+    use crate::codegen::tir::ScalarType;
+    use crate::support::slotmap::SecondaryMap;
+
+    #[test]
+    fn offsets_are_aligned_below_an_odd_save_area() {
+        let mut func = Func::<X64Inst>::new("f".to_string());
+        let b = func.add_empty_block();
+        let scalar = func.new_vreg();
+        let vector = func.new_typed_vreg(Type::V128(ScalarType::F32));
+        let saved = func.new_vreg();
+        let region = func.new_typed_vreg(Type::Ptr);
+        let bd = func.get_block_data_mut(b);
+        bd.push_pseudo_inst(PseudoInstruction::StackAlloc {
+            dst: region,
+            size: 24,
+            align: 16,
+        });
+        bd.push_target_inst(X64Inst::AdjustRsp { delta: -16 });
+        bd.push_target_inst(X64Inst::AdjustRsp { delta: 16 });
+        bd.push_target_inst(X64Inst::Ud2);
+
+        let cfg = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RBX],
+            scratch_regs: vec![R10, R11],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: false,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let mut ra = RegAllocResult {
+            assignments: SecondaryMap::new(0),
+            frame_layout: vec![0, 8],
+            frame_size: 16,
+            edits: Vec::new(),
+        };
+        for (v, slot) in [
+            (scalar, AllocatedSlot::Stack(0)),
+            (vector, AllocatedSlot::Stack(1)),
+            (saved, AllocatedSlot::Reg(RBX)),
+            (region, AllocatedSlot::Reg(RAX)),
+        ] {
+            ra.assignments.set(v, Assignment::uniform(slot, 0, 10));
+        }
+        let frame = FrameLayout::compute(&func, &cfg, &ra);
+
+        // `rbp` sits 8 bytes off alignment below one push.
+        assert_eq!(frame.saved_regs, [RBX]);
+        let aligned = |off: i32, align: i32| (8 - off).rem_euclid(align) == 0;
+        assert!(aligned(frame.spill_offset(1), 16), "{frame:?}");
+        assert!(aligned(frame.spill_offset(0), 8), "{frame:?}");
+        let region_mem = frame.resolve(FrameRef::StackAlloc(region));
+        assert!(aligned(region_mem.disp, 16), "{frame:?}");
+
+        // The outgoing area sits at `rsp`, under everything else, and
+        // `rsp` ends up 16-aligned.
+        assert_eq!(frame.outgoing_args, 16);
+        assert_eq!(
+            frame.resolve(FrameRef::OutgoingArg(1)),
+            Mem::base_disp(RSP, 8)
+        );
+        let lowest = -(frame.frame_adjust as i32) + frame.outgoing_args as i32;
+        assert!(region_mem.disp >= lowest, "{frame:?}");
+        assert!(aligned(-(frame.frame_adjust as i32), 16), "{frame:?}");
+        assert_eq!(
+            frame.resolve(FrameRef::IncomingArg(0)),
+            Mem::base_disp(RBP, 24)
+        );
+    }
+
+    #[test]
+    fn only_a_small_leaf_frame_moves_into_the_red_zone() {
+        let frame_of = |size: u32, call: bool| {
+            let mut func = Func::<X64Inst>::new("f".to_string());
+            let b = func.add_empty_block();
+            let region = func.new_typed_vreg(Type::Ptr);
+            let target = func.new_vreg();
+            let bd = func.get_block_data_mut(b);
+            bd.push_pseudo_inst(PseudoInstruction::StackAlloc { dst: region, size, align: 8 });
+            if call {
+                bd.push_target_inst(X64Inst::Call64r { target });
+            }
+            bd.push_target_inst(X64Inst::Ud2);
+            let ra = RegAllocResult {
+                assignments: SecondaryMap::new(0),
+                frame_layout: Vec::new(),
+                frame_size: 0,
+                edits: Vec::new(),
+            };
+            let cfg = crate::codegen::isa::x64::pipeline::default_ra_config(HashMap::new());
+            let mut frame = FrameLayout::compute(&func, &cfg, &ra);
+            let mem = frame.resolve(FrameRef::StackAlloc(region));
+            frame.use_red_zone();
+            assert_eq!(frame.resolve(FrameRef::StackAlloc(region)), mem);
+            frame
+        };
+        let leaf = frame_of(64, false);
+        assert!(leaf.red_zone && leaf.frame_adjust == 0, "{leaf:?}");
+        let big = frame_of(RED_ZONE + 8, false);
+        // Three scratch pushes leave 8 bytes of padding; a leaf skips
+        // rounding `rsp` to 16 below that.
+        assert!(!big.red_zone && big.leaf, "{big:?}");
+        assert_eq!(big.frame_adjust, 8 + RED_ZONE + 8);
+        let caller = frame_of(64, true);
+        assert!(!caller.red_zone && caller.frame_adjust > 0, "{caller:?}");
+    }
+}
@@ -1,11 +1,14 @@
 use std::fmt::Display;
 
-use crate::codegen::tir::{self, Block, Inst, Reg};
+use crate::codegen::isa::x64::{intrinsics, size};
+use crate::codegen::tir::{
+    self, Block, BlockHint, Inst, Instruction, JumpTableId, Reg, ScalarType, SymbolId,
+};
 
 use smallvec::{smallvec, SmallVec};
 
 /// x86-64 condition codes. Name matches the suffix used with J/SET/CMOV.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Cond {
     Z,
     NZ,
@@ -19,6 +22,61 @@ pub enum Cond {
     AE,
 }
 
+impl Cond {
+    /// Whether the condition holds on the flags `cmp lhs, rhs` produces.
+    /// `test a, b` sets the same flags as `cmp a & b, 0`.
+    #[must_use]
+    pub fn holds(self, lhs: i64, rhs: i64) -> bool {
+        let (ul, ur) = (lhs.cast_unsigned(), rhs.cast_unsigned());
+        match self {
+            Cond::Z => lhs == rhs,
+            Cond::NZ => lhs != rhs,
+            Cond::L => lhs < rhs,
+            Cond::LE => lhs <= rhs,
+            Cond::G => lhs > rhs,
+            Cond::GE => lhs >= rhs,
+            Cond::B => ul < ur,
+            Cond::BE => ul <= ur,
+            Cond::A => ul > ur,
+            Cond::AE => ul >= ur,
+        }
+    }
+
+    /// The condition that holds exactly when `self` does not.
+    #[must_use]
+    pub fn invert(self) -> Cond {
+        match self {
+            Cond::Z => Cond::NZ,
+            Cond::NZ => Cond::Z,
+            Cond::L => Cond::GE,
+            Cond::GE => Cond::L,
+            Cond::LE => Cond::G,
+            Cond::G => Cond::LE,
+            Cond::B => Cond::AE,
+            Cond::AE => Cond::B,
+            Cond::BE => Cond::A,
+            Cond::A => Cond::BE,
+        }
+    }
+
+    /// The condition that holds for `cmp rhs, lhs` exactly when `self`
+    /// holds for `cmp lhs, rhs`.
+    #[must_use]
+    pub fn swap(self) -> Cond {
+        match self {
+            Cond::Z | Cond::NZ => self,
+            Cond::L => Cond::G,
+            Cond::G => Cond::L,
+            Cond::LE => Cond::GE,
+            Cond::GE => Cond::LE,
+            Cond::B => Cond::A,
+            Cond::A => Cond::B,
+            Cond::BE => Cond::AE,
+            Cond::AE => Cond::BE,
+        }
+    }
+}
+
 impl Display for Cond {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let s = match self {
@@ -39,7 +97,7 @@ impl Display for Cond {
 
 /// `base + (index * scale) + disp`. Shared across every memory-accessing
 /// instruction (MOV of all widths, LEA).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Mem {
     pub base: Reg,
     pub index: Option<Reg>,
@@ -66,6 +124,13 @@ impl Mem {
             smallvec![self.base]
         }
     }
+
+    pub fn map_regs(&mut self, f: &mut dyn FnMut(Reg) -> Reg) {
+        self.base = f(self.base);
+        if let Some(idx) = &mut self.index {
+            *idx = f(*idx);
+        }
+    }
 }
 
 impl Display for Mem {
@@ -101,13 +166,17 @@ impl Display for Mem {
 /// `quotient`/`remainder` are the pre-bound results (RAX/RDX). Modeling
 /// it this way lets liveness see the implicit reads/writes without
 /// special-casing.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum X64Inst {
     // Moves — 64-bit.
     Mov64rr { dst: Reg, src: Reg },
     Mov64ri { dst: Reg, imm: i64 },
     Mov64rm { dst: Reg, src: Mem },
     Mov64mr { dst: Mem, src: Reg },
+    // Absolute address of a symbol (a function or a global), named in
+    // `Func::symbol_name`. Emitted as `movabs` whose immediate is a
+    // relocation, patched at load time or by the linker.
+    Mov64rsym { dst: Reg, sym: SymbolId },
 
     // Moves — 32-bit. A 32-bit write to a GPR zero-extends to 64.
     Mov32rr { dst: Reg, src: Reg },
@@ -147,6 +216,18 @@ pub enum X64Inst {
     Imul64rr { dst: Reg, src: Reg },
     Add64ri32 { dst: Reg, imm: i32 },
     Sub64ri32 { dst: Reg, imm: i32 },
+    // With carry — `dst = dst OP src OP CF`: the high halves of a 128-bit
+    // add/sub, reading the carry its low half's `add`/`sub` left.
+    Adc64rr { dst: Reg, src: Reg },
+    Sbb64rr { dst: Reg, src: Reg },
+    // Unsigned 64×64→128 multiply: `RDX:RAX = RAX * src`. `lo_in` and
+    // `lo` are pre-bound to RAX, `hi` to RDX, as for `Div64r`.
+    Mul64r {
+        src: Reg,
+        lo_in: Reg,
+        lo: Reg,
+        hi: Reg,
+    },
 
     // Signed / unsigned 128-into-64 division. `quotient` ends up in
     // RAX, `remainder` in RDX; `hi_in`/`lo_in` must be pre-bound to
@@ -170,6 +251,28 @@ pub enum X64Inst {
         remainder: Reg,
     },
 
+    // `rep movsb` / `rep stosb`: copy `count` bytes from RSI to RDI, or
+    // fill them with AL. Every operand is pre-bound: `dst_in`/`src_in`/
+    // `count_in` to RDI/RSI/RCX, `byte` to RAX, and the `_out`s to the
+    // same registers as the string op leaves them (pointers advanced,
+    // RCX zero). A frontend `Kill`s the outputs it doesn't read, as for
+    // the division results.
+    RepMovsb {
+        dst_in: Reg,
+        src_in: Reg,
+        count_in: Reg,
+        dst_out: Reg,
+        src_out: Reg,
+        count_out: Reg,
+    },
+    RepStosb {
+        dst_in: Reg,
+        byte: Reg,
+        count_in: Reg,
+        dst_out: Reg,
+        count_out: Reg,
+    },
+
     // Bitwise — `dst = dst OP src`.
     And64rr { dst: Reg, src: Reg },
     Or64rr { dst: Reg, src: Reg },
@@ -205,6 +308,20 @@ pub enum X64Inst {
     // that want `select` without a branch.
     Cmov64rr { cond: Cond, dst: Reg, src: Reg },
 
+    // `dst = if lhs <cond> rhs { tval } else { fval }`, compared as by
+    // `cmp lhs, rhs`. Lowered by `lower_selects` into `cmov` or a branch
+    // diamond before SSA destruction; never reaches emission. `hint`
+    // says the condition is predictably true (`Likely`) or false.
+    Select {
+        cond: Cond,
+        dst: Reg,
+        lhs: Reg,
+        rhs: Reg,
+        tval: Reg,
+        fval: Reg,
+        hint: Option<BlockHint>,
+    },
+
     // Set-byte-on-condition — materializes an `icmp` result into a
     // scalar `i1` (as a byte). The write is 8-bit, so callers usually
     // follow with `Movzx64r8` to widen.
@@ -227,23 +344,35 @@ pub enum X64Inst {
     /// `indirectbr` lowering (no label operand — the target is an
     /// address already in a vreg).
     Jmp64r { target: Reg },
+    /// Indirect jump to entry `index` of the dense jump table `table`:
+    /// its cases from the lowest value up, holes going to the default.
+    /// `index` is already rebased to the lowest case and bounds-checked.
+    /// Emitted as `lea`/`movsxd`/`add`/`jmp` through scratch registers,
+    /// with the table of block offsets after the function's code.
+    JmpTable { index: Reg, table: JumpTableId },
     /// Undefined-instruction trap (`ud2`). Emitted for LLVM IR
     /// `unreachable` — if execution reaches this, it faults.
     Ud2,
+    /// Breakpoint (`int3`). Raises SIGTRAP under a debugger or runtime
+    /// that handles it and falls through to the next instruction once
+    /// resumed, so unlike `Ud2` it doesn't end the block.
+    Int3,
     /// Full memory fence (`mfence`). Lowers LLVM `fence` with seq_cst
     /// semantics. No operands.
     Mfence,
 
     /// Load an incoming stack-passed argument into `dst`.
-    /// `stack_idx` is 0-based within the stack-passed arguments — the
-    /// 7th SysV integer argument has `stack_idx = 0`, the 8th has `1`,
-    /// etc. Emits `mov dst, [rbp + 16 + 8*K + 8*stack_idx]` where `K`
-    /// is the number of callee-saved registers the prologue pushed
-    /// between `push rbp` and `mov rbp, rsp`.
+    /// `stack_idx` is the eightbyte it starts at, 0-based within the
+    /// stack-passed arguments — the 7th SysV integer argument has
+    /// `stack_idx = 0`, the 8th has `1`, etc.; a `V128` takes two,
+    /// starting at an even one. Emits `mov dst, [rbp + 16 + 8*K +
+    /// 8*stack_idx]` (`movsd` or `movups` for XMM) where `K` is the
+    /// number of callee-saved registers the prologue pushed between
+    /// `push rbp` and `mov rbp, rsp`.
     LoadArgFromStack { dst: Reg, stack_idx: u32 },
     /// Store an outgoing argument into the reserved stack slot for a
-    /// call. `stack_idx` counts from the first stack-passed argument
-    /// (0 for the 7th SysV arg). Emits `mov [rsp + 8*stack_idx], src`.
+    /// call. `stack_idx` is the eightbyte it starts at, counted as for
+    /// `LoadArgFromStack`. Emits `mov [rsp + 8*stack_idx], src`.
     /// The caller must have already reserved enough stack (via
     /// `AdjustRsp`) and taken 16-byte alignment into account.
     StoreStackArg { src: Reg, stack_idx: u32 },
@@ -276,6 +405,13 @@ pub enum X64Inst {
     Movsdrm { dst: Reg, src: Mem },
     /// `movsd [mem], xmm_src` — store a 64-bit float to memory.
     Movsdmr { dst: Mem, src: Reg },
+    /// `xmm_dst = f32::from_bits(bits)`. Emitted as `xorps` for +0.0,
+    /// else as a `mov` of the bits into a GPR scratch and a `movd`.
+    Fconst32 { dst: Reg, bits: u32 },
+    /// `xmm_dst = f64::from_bits(bits)`. Emitted as `xorps` for +0.0, a
+    /// GPR round-trip when the bits fit a 32-bit immediate, else a
+    /// RIP-relative `movsd` from the function's constant pool.
+    Fconst64 { dst: Reg, bits: u64 },
 
     Addssrr { dst: Reg, src: Reg },
     Subssrr { dst: Reg, src: Reg },
@@ -291,6 +427,29 @@ pub enum X64Inst {
     /// `ucomisd` — unordered SSE comparison (F64). Sets EFLAGS; no GPR def.
     Ucomisdrr { lhs: Reg, rhs: Reg },
 
+    // ---- 128-bit vectors (SSE2 / SSE4.1). ----
+    //
+    // Vector operands are `Type::V128` vregs, allocated from the XMM pool
+    // like scalar FP. `lanes` is the lane type of the operands' `V128`.
+
+    /// `movaps xmm_dst, xmm_src` — copy all 128 bits.
+    Movapsrr { dst: Reg, src: Reg },
+    /// `movdqu xmm_dst, [mem]` — unaligned 128-bit load.
+    Movdqurm { dst: Reg, src: Mem },
+    /// `movdqu [mem], xmm_src` — unaligned 128-bit store.
+    Movdqumr { dst: Mem, src: Reg },
+    /// Lane-wise `dst = dst + src`: `paddb`/`w`/`d`/`q`, `addps`, `addpd`.
+    Vaddrr { lanes: ScalarType, dst: Reg, src: Reg },
+    /// Lane-wise `dst = dst * src`: `pmullw`, `pmulld`, `mulps`, `mulpd`.
+    /// There is no 8- or 64-bit integer form.
+    Vmulrr { lanes: ScalarType, dst: Reg, src: Reg },
+    /// `pshufd dst, src, order` — 32-bit lane `i` of `dst` is lane
+    /// `(order >> 2i) & 3` of `src`.
+    Pshufdrri { dst: Reg, src: Reg, order: u8 },
+    /// `pextrb`/`w`/`d`/`q dst, src, lane` — integer lane `lane` of `src`,
+    /// zero-extended into the GPR `dst`.
+    Pextrrri { lanes: ScalarType, dst: Reg, src: Reg, lane: u8 },
+
     // ---- Atomic RMW primitives. ----
     //
     // These carry the LOCK prefix and model implicit RAX reads/writes
@@ -316,11 +475,120 @@ pub enum X64Inst {
     },
 }
 
+impl X64Inst {
+    /// Whether the instruction's result depends on `RFLAGS`.
+    #[must_use]
+    pub fn reads_flags(&self) -> bool {
+        matches!(
+            self,
+            X64Inst::CondJmp { .. }
+                | X64Inst::Cmov64rr { .. }
+                | X64Inst::Setcc8r { .. }
+                | X64Inst::Adc64rr { .. }
+                | X64Inst::Sbb64rr { .. }
+        )
+    }
+
+    /// Whether the instruction may change `RFLAGS`. Moves, loads, stores,
+    /// extends, `lea`, `not`, scalar SSE arithmetic, control flow and
+    /// flag readers leave them alone; everything else is assumed to write
+    /// them.
+    #[must_use]
+    pub fn writes_flags(&self) -> bool {
+        !matches!(
+            self,
+            X64Inst::Mov64rm { .. }
+                | X64Inst::Mov32rm { .. }
+                | X64Inst::Mov16rm { .. }
+                | X64Inst::Mov8rm { .. }
+                | X64Inst::Movssrm { .. }
+                | X64Inst::Movsdrm { .. }
+                | X64Inst::Mov64mr { .. }
+                | X64Inst::Mov32mr { .. }
+                | X64Inst::Mov16mr { .. }
+                | X64Inst::Mov8mr { .. }
+                | X64Inst::Movssmr { .. }
+                | X64Inst::Movsdmr { .. }
+                | X64Inst::Mov64rr { .. }
+                | X64Inst::Mov64ri { .. }
+                | X64Inst::Mov32rr { .. }
+                | X64Inst::Mov32ri { .. }
+                | X64Inst::Mov16rr { .. }
+                | X64Inst::Mov16ri { .. }
+                | X64Inst::Mov8rr { .. }
+                | X64Inst::Mov8ri { .. }
+                | X64Inst::Mov64rsym { .. }
+                | X64Inst::Movsx64r8 { .. }
+                | X64Inst::Movsx64r16 { .. }
+                | X64Inst::Movsxd64r32 { .. }
+                | X64Inst::Movzx64r8 { .. }
+                | X64Inst::Movzx64r16 { .. }
+                | X64Inst::Lea64rm { .. }
+                | X64Inst::Not64r { .. }
+                | X64Inst::Movssrr { .. }
+                | X64Inst::Movsdrr { .. }
+                | X64Inst::Fconst32 { .. }
+                | X64Inst::Fconst64 { .. }
+                | X64Inst::Movapsrr { .. }
+                | X64Inst::Movdqurm { .. }
+                | X64Inst::Movdqumr { .. }
+                | X64Inst::Vaddrr { .. }
+                | X64Inst::Vmulrr { .. }
+                | X64Inst::Pshufdrri { .. }
+                | X64Inst::Pextrrri { .. }
+                | X64Inst::Addssrr { .. }
+                | X64Inst::Subssrr { .. }
+                | X64Inst::Mulssrr { .. }
+                | X64Inst::Divssrr { .. }
+                | X64Inst::Addsdrr { .. }
+                | X64Inst::Subsdrr { .. }
+                | X64Inst::Mulsdrr { .. }
+                | X64Inst::Divsdrr { .. }
+                | X64Inst::Cmov64rr { .. }
+                | X64Inst::Setcc8r { .. }
+                | X64Inst::Jmp { .. }
+                | X64Inst::CondJmp { .. }
+                | X64Inst::Jmp64r { .. }
+                | X64Inst::RawRet
+                | X64Inst::Ud2
+                | X64Inst::Int3
+                | X64Inst::Mfence
+                | X64Inst::RepMovsb { .. }
+                | X64Inst::RepStosb { .. }
+                | X64Inst::LoadArgFromStack { .. }
+                | X64Inst::StoreStackArg { .. }
+        )
+    }
+
+    /// Whether the instruction is a `cmp`/`test` that macro-fuses with an
+    /// immediately following `jcc` into one uop. Only the register forms;
+    /// `ucomis*` never fuses.
+    #[must_use]
+    pub fn fuses_with_jcc(&self) -> bool {
+        matches!(
+            self,
+            X64Inst::Cmp64rr { .. }
+                | X64Inst::Cmp64ri32 { .. }
+                | X64Inst::Test64rr { .. }
+                | X64Inst::Test64ri32 { .. }
+        )
+    }
+}
+
+impl From<X64Inst> for Instruction<X64Inst> {
+    fn from(inst: X64Inst) -> Self {
+        Instruction::Target(inst)
+    }
+}
+
 impl Inst for X64Inst {
     fn is_branch(&self) -> bool {
         matches!(
             self,
-            X64Inst::Jmp { .. } | X64Inst::CondJmp { .. } | X64Inst::Jmp64r { .. }
+            X64Inst::Jmp { .. }
+                | X64Inst::CondJmp { .. }
+                | X64Inst::Jmp64r { .. }
+                | X64Inst::JmpTable { .. }
         )
     }
 
@@ -347,11 +615,17 @@ impl Inst for X64Inst {
             | X64Inst::Movzx64r8 { src, .. }
             | X64Inst::Movzx64r16 { src, .. }
             | X64Inst::Movssrr { src, .. }
-            | X64Inst::Movsdrr { src, .. } => smallvec![*src],
+            | X64Inst::Movsdrr { src, .. }
+            | X64Inst::Movapsrr { src, .. }
+            | X64Inst::Pshufdrri { src, .. }
+            | X64Inst::Pextrrri { src, .. } => smallvec![*src],
             X64Inst::Mov64ri { .. }
+            | X64Inst::Mov64rsym { .. }
             | X64Inst::Mov32ri { .. }
             | X64Inst::Mov16ri { .. }
             | X64Inst::Mov8ri { .. }
+            | X64Inst::Fconst32 { .. }
+            | X64Inst::Fconst64 { .. }
             | X64Inst::RawRet => smallvec![],
             X64Inst::Mov64rm { src, .. }
             | X64Inst::Mov32rm { src, .. }
@@ -359,13 +633,15 @@ impl Inst for X64Inst {
             | X64Inst::Mov8rm { src, .. }
             | X64Inst::Movssrm { src, .. }
             | X64Inst::Movsdrm { src, .. }
+            | X64Inst::Movdqurm { src, .. }
             | X64Inst::Lea64rm { src, .. } => src.get_uses(),
             X64Inst::Mov64mr { dst, src }
             | X64Inst::Mov32mr { dst, src }
             | X64Inst::Mov16mr { dst, src }
             | X64Inst::Mov8mr { dst, src }
             | X64Inst::Movssmr { dst, src }
-            | X64Inst::Movsdmr { dst, src } => {
+            | X64Inst::Movsdmr { dst, src }
+            | X64Inst::Movdqumr { dst, src } => {
                 let mut uses: SmallVec<[Reg; 2]> = dst.get_uses();
                 uses.push(*src);
                 uses
@@ -377,7 +653,9 @@ impl Inst for X64Inst {
             | X64Inst::Addsdrr { dst, src }
             | X64Inst::Subsdrr { dst, src }
             | X64Inst::Mulsdrr { dst, src }
-            | X64Inst::Divsdrr { dst, src } => smallvec![*dst, *src],
+            | X64Inst::Divsdrr { dst, src }
+            | X64Inst::Vaddrr { dst, src, .. }
+            | X64Inst::Vmulrr { dst, src, .. } => smallvec![*dst, *src],
             X64Inst::Ucomissrr { lhs, rhs } | X64Inst::Ucomisdrr { lhs, rhs } => {
                 smallvec![*lhs, *rhs]
             }
@@ -393,8 +671,11 @@ impl Inst for X64Inst {
                 uses.push(*rax_in);
                 uses
             }
+            X64Inst::Select { lhs, rhs, tval, fval, .. } => smallvec![*lhs, *rhs, *tval, *fval],
             X64Inst::Add64rr { dst, src }
             | X64Inst::Sub64rr { dst, src }
+            | X64Inst::Adc64rr { dst, src }
+            | X64Inst::Sbb64rr { dst, src }
             | X64Inst::Imul64rr { dst, src }
             | X64Inst::And64rr { dst, src }
             | X64Inst::Or64rr { dst, src }
@@ -415,6 +696,13 @@ impl Inst for X64Inst {
             | X64Inst::Div64r { divisor, hi_in, lo_in, .. } => {
                 smallvec![*divisor, *hi_in, *lo_in]
             }
+            X64Inst::Mul64r { src, lo_in, .. } => smallvec![*src, *lo_in],
+            X64Inst::RepMovsb { dst_in, src_in, count_in, .. } => {
+                smallvec![*dst_in, *src_in, *count_in]
+            }
+            X64Inst::RepStosb { dst_in, byte, count_in, .. } => {
+                smallvec![*dst_in, *byte, *count_in]
+            }
             X64Inst::Cmp64rr { lhs, rhs } | X64Inst::Test64rr { lhs, rhs } => {
                 smallvec![*lhs, *rhs]
             }
@@ -423,11 +711,14 @@ impl Inst for X64Inst {
             }
             X64Inst::Cmov64rr { dst, src, .. } => smallvec![*dst, *src],
             X64Inst::Setcc8r { .. } => smallvec![],
-            X64Inst::Call64r { target } | X64Inst::Jmp64r { target } => smallvec![*target],
+            X64Inst::Call64r { target }
+            | X64Inst::Jmp64r { target }
+            | X64Inst::JmpTable { index: target, .. } => smallvec![*target],
             X64Inst::StoreStackArg { src, .. } => smallvec![*src],
             X64Inst::Jmp { .. }
             | X64Inst::CondJmp { .. }
             | X64Inst::Ud2
+            | X64Inst::Int3
             | X64Inst::Mfence
             | X64Inst::LoadArgFromStack { .. }
             | X64Inst::AdjustRsp { .. } => smallvec![],
@@ -438,6 +729,7 @@ impl Inst for X64Inst {
         match self {
             X64Inst::Mov64rr { dst, .. }
             | X64Inst::Mov64ri { dst, .. }
+            | X64Inst::Mov64rsym { dst, .. }
             | X64Inst::Mov64rm { dst, .. }
             | X64Inst::Mov32rr { dst, .. }
             | X64Inst::Mov32ri { dst, .. }
@@ -456,6 +748,8 @@ impl Inst for X64Inst {
             | X64Inst::Lea64rm { dst, .. }
             | X64Inst::Add64rr { dst, .. }
             | X64Inst::Sub64rr { dst, .. }
+            | X64Inst::Adc64rr { dst, .. }
+            | X64Inst::Sbb64rr { dst, .. }
             | X64Inst::Imul64rr { dst, .. }
             | X64Inst::Add64ri32 { dst, .. }
             | X64Inst::Sub64ri32 { dst, .. }
@@ -480,6 +774,14 @@ impl Inst for X64Inst {
             | X64Inst::Movssrm { dst, .. }
             | X64Inst::Movsdrr { dst, .. }
             | X64Inst::Movsdrm { dst, .. }
+            | X64Inst::Fconst32 { dst, .. }
+            | X64Inst::Fconst64 { dst, .. }
+            | X64Inst::Movapsrr { dst, .. }
+            | X64Inst::Movdqurm { dst, .. }
+            | X64Inst::Vaddrr { dst, .. }
+            | X64Inst::Vmulrr { dst, .. }
+            | X64Inst::Pshufdrri { dst, .. }
+            | X64Inst::Pextrrri { dst, .. }
             | X64Inst::Addssrr { dst, .. }
             | X64Inst::Subssrr { dst, .. }
             | X64Inst::Mulssrr { dst, .. }
@@ -494,6 +796,21 @@ impl Inst for X64Inst {
                 defs.push(*remainder);
                 defs
             }
+            X64Inst::Mul64r { lo, hi, .. } => {
+                let mut defs: SmallVec<[Reg; 1]> = smallvec![*lo];
+                defs.push(*hi);
+                defs
+            }
+            X64Inst::RepMovsb { dst_out, src_out, count_out, .. } => {
+                let mut defs: SmallVec<[Reg; 1]> = smallvec![*dst_out];
+                defs.extend([*src_out, *count_out]);
+                defs
+            }
+            X64Inst::RepStosb { dst_out, count_out, .. } => {
+                let mut defs: SmallVec<[Reg; 1]> = smallvec![*dst_out];
+                defs.push(*count_out);
+                defs
+            }
             // `xadd [mem], src` writes the old memory value back into
             // `src`, so the vreg acts as both a use (pre-op value) and
             // a def (post-op old-mem). Modeling the def is what keeps
@@ -503,12 +820,14 @@ impl Inst for X64Inst {
             // cmpxchg writes RAX unconditionally (the observed [mem]
             // value); model it via `rax_out`.
             X64Inst::LockCmpxchg64mr { rax_out, .. } => smallvec![*rax_out],
+            X64Inst::Select { dst, .. } => smallvec![*dst],
             X64Inst::Mov64mr { .. }
             | X64Inst::Mov32mr { .. }
             | X64Inst::Mov16mr { .. }
             | X64Inst::Mov8mr { .. }
             | X64Inst::Movssmr { .. }
             | X64Inst::Movsdmr { .. }
+            | X64Inst::Movdqumr { .. }
             | X64Inst::Cmp64rr { .. }
             | X64Inst::Cmp64ri32 { .. }
             | X64Inst::Test64rr { .. }
@@ -519,7 +838,9 @@ impl Inst for X64Inst {
             | X64Inst::Jmp { .. }
             | X64Inst::CondJmp { .. }
             | X64Inst::Jmp64r { .. }
+            | X64Inst::JmpTable { .. }
             | X64Inst::Ud2
+            | X64Inst::Int3
             | X64Inst::Mfence
             | X64Inst::StoreStackArg { .. }
             | X64Inst::AdjustRsp { .. }
@@ -527,23 +848,155 @@ impl Inst for X64Inst {
         }
     }
 
+    fn map_regs(&mut self, f: &mut dyn FnMut(Reg) -> Reg) {
+        match self {
+            X64Inst::Mov64rr { dst, src }
+            | X64Inst::Mov32rr { dst, src }
+            | X64Inst::Mov16rr { dst, src }
+            | X64Inst::Mov8rr { dst, src }
+            | X64Inst::Movsx64r8 { dst, src }
+            | X64Inst::Movsx64r16 { dst, src }
+            | X64Inst::Movsxd64r32 { dst, src }
+            | X64Inst::Movzx64r8 { dst, src }
+            | X64Inst::Movzx64r16 { dst, src }
+            | X64Inst::Add64rr { dst, src }
+            | X64Inst::Sub64rr { dst, src }
+            | X64Inst::Adc64rr { dst, src }
+            | X64Inst::Sbb64rr { dst, src }
+            | X64Inst::Imul64rr { dst, src }
+            | X64Inst::And64rr { dst, src }
+            | X64Inst::Or64rr { dst, src }
+            | X64Inst::Xor64rr { dst, src }
+            | X64Inst::Cmov64rr { dst, src, .. }
+            | X64Inst::Movssrr { dst, src }
+            | X64Inst::Movsdrr { dst, src }
+            | X64Inst::Movapsrr { dst, src }
+            | X64Inst::Vaddrr { dst, src, .. }
+            | X64Inst::Vmulrr { dst, src, .. }
+            | X64Inst::Pshufdrri { dst, src, .. }
+            | X64Inst::Pextrrri { dst, src, .. }
+            | X64Inst::Addssrr { dst, src }
+            | X64Inst::Subssrr { dst, src }
+            | X64Inst::Mulssrr { dst, src }
+            | X64Inst::Divssrr { dst, src }
+            | X64Inst::Addsdrr { dst, src }
+            | X64Inst::Subsdrr { dst, src }
+            | X64Inst::Mulsdrr { dst, src }
+            | X64Inst::Divsdrr { dst, src }
+            | X64Inst::Shl64rcl { dst, count: src }
+            | X64Inst::Shr64rcl { dst, count: src }
+            | X64Inst::Sar64rcl { dst, count: src }
+            | X64Inst::Cmp64rr { lhs: dst, rhs: src }
+            | X64Inst::Test64rr { lhs: dst, rhs: src }
+            | X64Inst::Ucomissrr { lhs: dst, rhs: src }
+            | X64Inst::Ucomisdrr { lhs: dst, rhs: src } => {
+                *dst = f(*dst);
+                *src = f(*src);
+            }
+            X64Inst::Mov64ri { dst, .. }
+            | X64Inst::Mov64rsym { dst, .. }
+            | X64Inst::Mov32ri { dst, .. }
+            | X64Inst::Mov16ri { dst, .. }
+            | X64Inst::Mov8ri { dst, .. }
+            | X64Inst::Fconst32 { dst, .. }
+            | X64Inst::Fconst64 { dst, .. }
+            | X64Inst::Add64ri32 { dst, .. }
+            | X64Inst::Sub64ri32 { dst, .. }
+            | X64Inst::And64ri32 { dst, .. }
+            | X64Inst::Or64ri32 { dst, .. }
+            | X64Inst::Xor64ri32 { dst, .. }
+            | X64Inst::Shl64ri8 { dst, .. }
+            | X64Inst::Shr64ri8 { dst, .. }
+            | X64Inst::Sar64ri8 { dst, .. }
+            | X64Inst::Not64r { dst }
+            | X64Inst::Neg64r { dst }
+            | X64Inst::Setcc8r { dst, .. }
+            | X64Inst::LoadArgFromStack { dst, .. }
+            | X64Inst::Cmp64ri32 { lhs: dst, .. }
+            | X64Inst::Test64ri32 { lhs: dst, .. }
+            | X64Inst::Call64r { target: dst }
+            | X64Inst::Jmp64r { target: dst }
+            | X64Inst::JmpTable { index: dst, .. }
+            | X64Inst::StoreStackArg { src: dst, .. } => *dst = f(*dst),
+            X64Inst::Mov64rm { dst, src }
+            | X64Inst::Mov32rm { dst, src }
+            | X64Inst::Mov16rm { dst, src }
+            | X64Inst::Mov8rm { dst, src }
+            | X64Inst::Movssrm { dst, src }
+            | X64Inst::Movsdrm { dst, src }
+            | X64Inst::Movdqurm { dst, src }
+            | X64Inst::Lea64rm { dst, src } => {
+                *dst = f(*dst);
+                src.map_regs(f);
+            }
+            X64Inst::Mov64mr { dst, src }
+            | X64Inst::Mov32mr { dst, src }
+            | X64Inst::Mov16mr { dst, src }
+            | X64Inst::Mov8mr { dst, src }
+            | X64Inst::Movssmr { dst, src }
+            | X64Inst::Movsdmr { dst, src }
+            | X64Inst::Movdqumr { dst, src }
+            | X64Inst::LockXadd64mr { dst, src } => {
+                dst.map_regs(f);
+                *src = f(*src);
+            }
+            X64Inst::LockCmpxchg64mr { dst, src, rax_in, rax_out } => {
+                dst.map_regs(f);
+                *src = f(*src);
+                *rax_in = f(*rax_in);
+                *rax_out = f(*rax_out);
+            }
+            X64Inst::Select { dst, lhs, rhs, tval, fval, .. } => {
+                for r in [lhs, rhs, tval, fval, dst] {
+                    *r = f(*r);
+                }
+            }
+            X64Inst::Idiv64r { divisor, hi_in, lo_in, quotient, remainder }
+            | X64Inst::Div64r { divisor, hi_in, lo_in, quotient, remainder } => {
+                for r in [divisor, hi_in, lo_in, quotient, remainder] {
+                    *r = f(*r);
+                }
+            }
+            X64Inst::Mul64r { src, lo_in, lo, hi } => {
+                for r in [src, lo_in, lo, hi] {
+                    *r = f(*r);
+                }
+            }
+            X64Inst::RepMovsb { dst_in, src_in, count_in, dst_out, src_out, count_out } => {
+                for r in [dst_in, src_in, count_in, dst_out, src_out, count_out] {
+                    *r = f(*r);
+                }
+            }
+            X64Inst::RepStosb { dst_in, byte, count_in, dst_out, count_out } => {
+                for r in [dst_in, byte, count_in, dst_out, count_out] {
+                    *r = f(*r);
+                }
+            }
+            X64Inst::Jmp { .. }
+            | X64Inst::CondJmp { .. }
+            | X64Inst::Ud2
+            | X64Inst::Int3
+            | X64Inst::Mfence
+            | X64Inst::AdjustRsp { .. }
+            | X64Inst::RawRet => {}
+        }
+    }
+
     fn get_branch_targets(&self) -> SmallVec<[Block; 2]> {
         match self {
             X64Inst::Jmp { dst } => smallvec![*dst],
             X64Inst::CondJmp { taken, not_taken, .. } => smallvec![*taken, *not_taken],
             // Indirect jumps and `ud2` have no lancy-level block
             // targets (for Jmp64r, the target is an address, not a
-            // block label we can analyze).
+            // block label we can analyze; JmpTable's are in its table).
             _ => smallvec![],
         }
     }
 
     fn rewrite_branch_target(&mut self, old: Block, new: Block) {
         match self {
-            X64Inst::Jmp { dst } => {
-                if *dst == old {
-                    *dst = new;
-                }
+            X64Inst::Jmp { dst } if *dst == old => {
+                *dst = new;
             }
             X64Inst::CondJmp { taken, not_taken, .. } => {
                 if *taken == old {
@@ -557,6 +1010,68 @@ impl Inst for X64Inst {
         }
     }
 
+    fn map_branch_targets(&mut self, f: &mut dyn FnMut(Block) -> Block) {
+        match self {
+            X64Inst::Jmp { dst } => *dst = f(*dst),
+            X64Inst::CondJmp { taken, not_taken, .. } => {
+                *taken = f(*taken);
+                *not_taken = f(*not_taken);
+            }
+            _ => {}
+        }
+    }
+
+    fn map_symbols(&mut self, f: &mut dyn FnMut(SymbolId) -> SymbolId) {
+        if let X64Inst::Mov64rsym { sym, .. } = self {
+            *sym = f(*sym);
+        }
+    }
+
+    fn jump_table(&self) -> Option<JumpTableId> {
+        match self {
+            X64Inst::JmpTable { table, .. } => Some(*table),
+            _ => None,
+        }
+    }
+
+    fn map_jump_table(&mut self, f: &mut dyn FnMut(JumpTableId) -> JumpTableId) {
+        if let X64Inst::JmpTable { table, .. } = self {
+            *table = f(*table);
+        }
+    }
+
+    fn is_move(&self) -> Option<(Reg, Reg)> {
+        // Only full-width copies count. Narrower moves leave (or zero)
+        // upper bits, and `movss` / `movsd` keep the destination's upper
+        // lanes, so none of them makes `dst` a copy of all of `src`.
+        match self {
+            X64Inst::Mov64rr { dst, src } | X64Inst::Movapsrr { dst, src } => {
+                Some((*dst, *src))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_call(&self) -> bool {
+        matches!(self, X64Inst::Call64r { .. })
+    }
+
+    fn moves_stack_pointer(&self) -> bool {
+        matches!(self, X64Inst::AdjustRsp { .. })
+    }
+
+    fn encoded_size(&self, preg: &dyn Fn(Reg) -> Reg) -> Option<u32> {
+        size::encoded_size(self, preg)
+    }
+
+    fn worst_case_size(&self) -> Option<u32> {
+        size::worst_case_size(self)
+    }
+
+    fn intrinsics() -> &'static [tir::IntrinsicDecl] {
+        intrinsics::INTRINSICS
+    }
+
     fn new_jmp(target: Block) -> Self {
         X64Inst::Jmp { dst: target }
     }
@@ -573,6 +1088,7 @@ impl Display for X64Inst {
                 write!(f, "mov {}, {}", reg_name(*dst), reg_name(*src))
             }
             X64Inst::Mov64ri { dst, imm } => write!(f, "mov {}, {imm}", reg_name(*dst)),
+            X64Inst::Mov64rsym { dst, sym } => write!(f, "mov {}, {sym}", reg_name(*dst)),
             X64Inst::Mov64rm { dst, src } => write!(f, "mov {}, {src}", reg_name(*dst)),
             X64Inst::Mov64mr { dst, src } => write!(f, "mov {dst}, {}", reg_name(*src)),
             X64Inst::Mov32rr { dst, src } => {
@@ -618,6 +1134,12 @@ impl Display for X64Inst {
             X64Inst::Imul64rr { dst, src } => {
                 write!(f, "imul {}, {}", reg_name(*dst), reg_name(*src))
             }
+            X64Inst::Adc64rr { dst, src } => {
+                write!(f, "adc {}, {}", reg_name(*dst), reg_name(*src))
+            }
+            X64Inst::Sbb64rr { dst, src } => {
+                write!(f, "sbb {}, {}", reg_name(*dst), reg_name(*src))
+            }
             X64Inst::Add64ri32 { dst, imm } => write!(f, "add {}, {imm}", reg_name(*dst)),
             X64Inst::Sub64ri32 { dst, imm } => write!(f, "sub {}, {imm}", reg_name(*dst)),
             X64Inst::And64rr { dst, src } => {
@@ -668,6 +1190,39 @@ impl Display for X64Inst {
                     reg_name(*lo_in)
                 )
             }
+            X64Inst::Mul64r { src, lo_in, lo, hi } => {
+                write!(
+                    f,
+                    "{}, {} = mul {} ; lo={}",
+                    reg_name(*hi),
+                    reg_name(*lo),
+                    reg_name(*src),
+                    reg_name(*lo_in)
+                )
+            }
+            X64Inst::RepMovsb { dst_in, src_in, count_in, dst_out, src_out, count_out } => {
+                write!(
+                    f,
+                    "{}, {}, {} = rep movsb {}, {}, {}",
+                    reg_name(*dst_out),
+                    reg_name(*src_out),
+                    reg_name(*count_out),
+                    reg_name(*dst_in),
+                    reg_name(*src_in),
+                    reg_name(*count_in)
+                )
+            }
+            X64Inst::RepStosb { dst_in, byte, count_in, dst_out, count_out } => {
+                write!(
+                    f,
+                    "{}, {} = rep stosb {}, {}, {}",
+                    reg_name(*dst_out),
+                    reg_name(*count_out),
+                    reg_name(*dst_in),
+                    reg_name(*byte),
+                    reg_name(*count_in)
+                )
+            }
             X64Inst::Cmp64rr { lhs, rhs } => {
                 write!(f, "cmp {}, {}", reg_name(*lhs), reg_name(*rhs))
             }
@@ -679,6 +1234,21 @@ impl Display for X64Inst {
             X64Inst::Cmov64rr { cond, dst, src } => {
                 write!(f, "cmov{cond} {}, {}", reg_name(*dst), reg_name(*src))
             }
+            X64Inst::Select { cond, dst, lhs, rhs, tval, fval, hint } => {
+                write!(
+                    f,
+                    "{} = select {cond} {}, {}, {}, {}",
+                    reg_name(*dst),
+                    reg_name(*lhs),
+                    reg_name(*rhs),
+                    reg_name(*tval),
+                    reg_name(*fval)
+                )?;
+                match hint {
+                    Some(h) => write!(f, " ; {h}"),
+                    None => Ok(()),
+                }
+            }
             X64Inst::Setcc8r { cond, dst } => write!(f, "set{cond} {}", reg_name(*dst)),
             X64Inst::Call64r { target } => write!(f, "call {}", reg_name(*target)),
             X64Inst::Jmp { dst } => write!(f, "jmp {dst}"),
@@ -686,7 +1256,11 @@ impl Display for X64Inst {
                 write!(f, "j{cond} {taken} else {not_taken}")
             }
             X64Inst::Jmp64r { target } => write!(f, "jmp {}", reg_name(*target)),
+            X64Inst::JmpTable { index, table } => {
+                write!(f, "jmp {table}[{}]", reg_name(*index))
+            }
             X64Inst::Ud2 => f.write_str("ud2"),
+            X64Inst::Int3 => f.write_str("int3"),
             X64Inst::Mfence => f.write_str("mfence"),
             X64Inst::LoadArgFromStack { dst, stack_idx } => {
                 write!(f, "{} = load_stack_arg #{stack_idx}", reg_name(*dst))
@@ -706,6 +1280,12 @@ impl Display for X64Inst {
             }
             X64Inst::Movsdrm { dst, src } => write!(f, "movsd {}, {src}", reg_name(*dst)),
             X64Inst::Movsdmr { dst, src } => write!(f, "movsd {dst}, {}", reg_name(*src)),
+            X64Inst::Fconst32 { dst, bits } => {
+                write!(f, "fconst.f32 {}, {:?}", reg_name(*dst), f32::from_bits(*bits))
+            }
+            X64Inst::Fconst64 { dst, bits } => {
+                write!(f, "fconst.f64 {}, {:?}", reg_name(*dst), f64::from_bits(*bits))
+            }
             X64Inst::Addssrr { dst, src } => {
                 write!(f, "addss {}, {}", reg_name(*dst), reg_name(*src))
             }
@@ -736,6 +1316,46 @@ impl Display for X64Inst {
             X64Inst::Ucomisdrr { lhs, rhs } => {
                 write!(f, "ucomisd {}, {}", reg_name(*lhs), reg_name(*rhs))
             }
+            X64Inst::Movapsrr { dst, src } => {
+                write!(f, "movaps {}, {}", reg_name(*dst), reg_name(*src))
+            }
+            X64Inst::Movdqurm { dst, src } => write!(f, "movdqu {}, {src}", reg_name(*dst)),
+            X64Inst::Movdqumr { dst, src } => write!(f, "movdqu {dst}, {}", reg_name(*src)),
+            X64Inst::Vaddrr { lanes, dst, src } => {
+                let op = match lanes {
+                    ScalarType::I8 => "paddb",
+                    ScalarType::I16 => "paddw",
+                    ScalarType::I32 => "paddd",
+                    ScalarType::I64 | ScalarType::Ptr => "paddq",
+                    ScalarType::F32 => "addps",
+                    ScalarType::F64 => "addpd",
+                };
+                write!(f, "{op} {}, {}", reg_name(*dst), reg_name(*src))
+            }
+            X64Inst::Vmulrr { lanes, dst, src } => {
+                let op = match lanes {
+                    ScalarType::I16 => "pmullw",
+                    ScalarType::I32 => "pmulld",
+                    ScalarType::F32 => "mulps",
+                    ScalarType::F64 => "mulpd",
+                    ScalarType::I8 | ScalarType::I64 | ScalarType::Ptr => {
+                        return write!(f, "vmul.{lanes} {}, {}", reg_name(*dst), reg_name(*src));
+                    }
+                };
+                write!(f, "{op} {}, {}", reg_name(*dst), reg_name(*src))
+            }
+            X64Inst::Pshufdrri { dst, src, order } => {
+                write!(f, "pshufd {}, {}, {order:#04x}", reg_name(*dst), reg_name(*src))
+            }
+            X64Inst::Pextrrri { lanes, dst, src, lane } => {
+                let op = match lanes {
+                    ScalarType::I8 => "pextrb",
+                    ScalarType::I16 => "pextrw",
+                    ScalarType::I32 | ScalarType::F32 => "pextrd",
+                    ScalarType::I64 | ScalarType::F64 | ScalarType::Ptr => "pextrq",
+                };
+                write!(f, "{op} {}, {}, {lane}", reg_name(*dst), reg_name(*src))
+            }
             X64Inst::LockXadd64mr { dst, src } => {
                 write!(f, "lock xadd {dst}, {}", reg_name(*src))
             }
@@ -754,6 +1374,29 @@ impl Display for X64Inst {
 mod tests {
     use super::*;
 
+    #[test]
+    fn cond_holds_distinguishes_signed_and_unsigned_orderings() {
+        assert!(Cond::L.holds(-1, 0));
+        assert!(!Cond::B.holds(-1, 0));
+        assert!(Cond::A.holds(-1, 0));
+        assert!(Cond::Z.holds(7, 7) && Cond::BE.holds(7, 7) && Cond::GE.holds(7, 7));
+        assert!(!Cond::NZ.holds(7, 7));
+    }
+
+    #[test]
+    fn inverted_cond_holds_on_the_complement() {
+        let all = [
+            Cond::Z, Cond::NZ, Cond::L, Cond::LE, Cond::G,
+            Cond::GE, Cond::B, Cond::BE, Cond::A, Cond::AE,
+        ];
+        for c in all {
+            assert_eq!(c.invert().invert(), c);
+            for (l, r) in [(-1, 0), (0, 0), (3, -2)] {
+                assert_eq!(c.invert().holds(l, r), !c.holds(l, r), "{c} on ({l}, {r})");
+            }
+        }
+    }
+
     #[test]
     fn add64rr_uses_both_operands_and_defs_dst() {
         let inst = X64Inst::Add64rr { dst: 1, src: 2 };
@@ -761,6 +1404,14 @@ mod tests {
         assert_eq!(inst.get_defs().as_slice(), &[1]);
     }
 
+    #[test]
+    fn only_full_width_register_copies_are_moves() {
+        assert_eq!(X64Inst::Mov64rr { dst: 1, src: 2 }.is_move(), Some((1, 2)));
+        assert_eq!(X64Inst::Movapsrr { dst: 1, src: 2 }.is_move(), Some((1, 2)));
+        assert_eq!(X64Inst::Movsdrr { dst: 1, src: 2 }.is_move(), None);
+        assert_eq!(X64Inst::Movssrr { dst: 1, src: 2 }.is_move(), None);
+    }
+
     #[test]
     fn mov64ri_defs_dst_and_uses_nothing() {
         let inst = X64Inst::Mov64ri { dst: 5, imm: 42 };
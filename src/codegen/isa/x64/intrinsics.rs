@@ -0,0 +1,182 @@
+//! The intrinsics x64 implements (`Inst::intrinsics` for `X64Inst`).
+//!
+//! Architecture intrinsics and math builtins with a single-instruction
+//! encoding are literal bytes over fixed registers; runtime helpers and
+//! the rest of libm are calls. Names are `x64.*` for the
+//! architecture-specific ones, `math.*` / `mem.*` for the portable ones.
+
+use crate::codegen::isa::x64::regs::{RAX, RCX, RDI, RDX, RSI, XMM0};
+use crate::codegen::tir::{IntrinsicDecl, IntrinsicEffects, IntrinsicLowering, Reg, Type};
+
+const fn bytes(
+    bytes: &'static [u8],
+    args: &'static [Reg],
+    rets: &'static [Reg],
+    clobbers: &'static [Reg],
+) -> IntrinsicLowering {
+    IntrinsicLowering::Bytes {
+        bytes,
+        args,
+        rets,
+        clobbers,
+    }
+}
+
+/// A libm function over `f64`s. Declared with side effects: it may set
+/// `errno`.
+const fn libm(name: &'static str, symbol: &'static str, params: &'static [Type]) -> IntrinsicDecl {
+    IntrinsicDecl {
+        name,
+        params,
+        results: &[Type::F64],
+        effects: IntrinsicEffects::SideEffects,
+        lowering: IntrinsicLowering::Call { symbol },
+    }
+}
+
+pub static INTRINSICS: &[IntrinsicDecl] = &[
+    // `rdtsc; shl rdx, 32; or rax, rdx`: the full 64-bit timestamp.
+    IntrinsicDecl {
+        name: "x64.rdtsc",
+        params: &[],
+        results: &[Type::I64],
+        effects: IntrinsicEffects::SideEffects,
+        lowering: bytes(
+            &[0x0F, 0x31, 0x48, 0xC1, 0xE2, 0x20, 0x48, 0x09, 0xD0],
+            &[],
+            &[RAX],
+            &[RDX],
+        ),
+    },
+    // `mov rax, fs:[0x28]`: the thread's stack canary under glibc.
+    IntrinsicDecl {
+        name: "x64.stack_guard",
+        params: &[],
+        results: &[Type::I64],
+        effects: IntrinsicEffects::ReadsMemory,
+        lowering: bytes(
+            &[0x64, 0x48, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00],
+            &[],
+            &[RAX],
+            &[],
+        ),
+    },
+    // Spin-wait hint.
+    IntrinsicDecl {
+        name: "x64.pause",
+        params: &[],
+        results: &[],
+        effects: IntrinsicEffects::SideEffects,
+        lowering: bytes(&[0xF3, 0x90], &[], &[], &[]),
+    },
+    // `popcnt rax, rdi`.
+    IntrinsicDecl {
+        name: "x64.popcnt",
+        params: &[Type::I64],
+        results: &[Type::I64],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(&[0xF3, 0x48, 0x0F, 0xB8, 0xC7], &[RDI], &[RAX], &[]),
+    },
+    // `mov rsi, rbx; cpuid; xchg rsi, rbx`: leaf `eax`, subleaf `ecx`,
+    // results `eax`, `ebx`, `ecx`, `edx`. `rbx` is callee-saved, so its
+    // result comes back in `rsi`.
+    IntrinsicDecl {
+        name: "x64.cpuid",
+        params: &[Type::I64, Type::I64],
+        results: &[Type::I64, Type::I64, Type::I64, Type::I64],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(
+            &[0x48, 0x89, 0xDE, 0x0F, 0xA2, 0x48, 0x87, 0xDE],
+            &[RAX, RCX],
+            &[RAX, RSI, RCX, RDX],
+            &[],
+        ),
+    },
+    // `xgetbv`: extended control register `ecx` as `eax`, `edx`. Faults
+    // unless `cpuid` reports OSXSAVE.
+    IntrinsicDecl {
+        name: "x64.xgetbv",
+        params: &[Type::I64],
+        results: &[Type::I64, Type::I64],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(&[0x0F, 0x01, 0xD0], &[RCX], &[RAX, RDX], &[]),
+    },
+    // `bswap rax`.
+    IntrinsicDecl {
+        name: "x64.bswap",
+        params: &[Type::I64],
+        results: &[Type::I64],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(&[0x48, 0x0F, 0xC8], &[RAX], &[RAX], &[]),
+    },
+    // `sqrtsd xmm0, xmm0` / `sqrtss xmm0, xmm0`.
+    IntrinsicDecl {
+        name: "math.sqrt.f64",
+        params: &[Type::F64],
+        results: &[Type::F64],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(&[0xF2, 0x0F, 0x51, 0xC0], &[XMM0], &[XMM0], &[]),
+    },
+    IntrinsicDecl {
+        name: "math.sqrt.f32",
+        params: &[Type::F32],
+        results: &[Type::F32],
+        effects: IntrinsicEffects::Pure,
+        lowering: bytes(&[0xF3, 0x0F, 0x51, 0xC0], &[XMM0], &[XMM0], &[]),
+    },
+    libm("math.sin.f64", "sin", &[Type::F64]),
+    libm("math.cos.f64", "cos", &[Type::F64]),
+    libm("math.exp.f64", "exp", &[Type::F64]),
+    libm("math.log.f64", "log", &[Type::F64]),
+    libm("math.pow.f64", "pow", &[Type::F64, Type::F64]),
+    IntrinsicDecl {
+        name: "mem.memmove",
+        params: &[Type::Ptr, Type::Ptr, Type::I64],
+        results: &[Type::Ptr],
+        effects: IntrinsicEffects::SideEffects,
+        lowering: IntrinsicLowering::Call { symbol: "memmove" },
+    },
+    IntrinsicDecl {
+        name: "mem.memcmp",
+        params: &[Type::Ptr, Type::Ptr, Type::I64],
+        results: &[Type::I32],
+        effects: IntrinsicEffects::ReadsMemory,
+        lowering: IntrinsicLowering::Call { symbol: "memcmp" },
+    },
+];
+
+/// `x64.popcnt` without the `popcnt` instruction, for CPUs that lack it
+/// (see `features::select_for_features`): the SWAR bit count of `rdi`
+/// into `rax`. Not in `INTRINSICS`; frontends ask for `x64.popcnt`.
+pub static POPCNT_SWAR: IntrinsicDecl = IntrinsicDecl {
+    name: "x64.popcnt.swar",
+    params: &[Type::I64],
+    results: &[Type::I64],
+    effects: IntrinsicEffects::Pure,
+    lowering: bytes(
+        &[
+            0x48, 0x89, 0xF8, // mov rax, rdi
+            0x48, 0xD1, 0xE8, // shr rax, 1
+            0x48, 0xB9, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, 0x55, // mov rcx, 0x55..
+            0x48, 0x21, 0xC8, // and rax, rcx
+            0x48, 0x29, 0xC7, // sub rdi, rax
+            0x48, 0xB9, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, // mov rcx, 0x33..
+            0x48, 0x89, 0xF8, // mov rax, rdi
+            0x48, 0x21, 0xC8, // and rax, rcx
+            0x48, 0xC1, 0xEF, 0x02, // shr rdi, 2
+            0x48, 0x21, 0xCF, // and rdi, rcx
+            0x48, 0x01, 0xF8, // add rax, rdi
+            0x48, 0x89, 0xC7, // mov rdi, rax
+            0x48, 0xC1, 0xEF, 0x04, // shr rdi, 4
+            0x48, 0x01, 0xF8, // add rax, rdi
+            0x48, 0xB9, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, 0x0F, // mov rcx, 0x0f..
+            0x48, 0x21, 0xC8, // and rax, rcx
+            0x48, 0xB9, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, // mov rcx, 0x01..
+            0x48, 0x0F, 0xAF, 0xC1, // imul rax, rcx
+            0x48, 0xC1, 0xE8, 0x38, // shr rax, 56
+        ],
+        &[RDI],
+        &[RAX],
+        &[RCX],
+    ),
+};
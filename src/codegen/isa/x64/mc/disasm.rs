@@ -0,0 +1,67 @@
+//! Human-readable listings of emitted machine code.
+
+use std::fmt::Write as _;
+use std::io;
+
+use iced_x86::{Decoder, DecoderOptions, Formatter, IntelFormatter};
+
+use crate::codegen::isa::x64::pipeline::CompiledCode;
+
+/// Intel-syntax listing of `code`: one `offset: bytes  mnemonic` line per
+/// instruction under a `name:` header, with relocated instructions
+/// annotated by their symbol, and traps and patch sleds by their kind.
+/// The constant pool and jump tables after the code are listed one `dq`
+/// or `dd` entry per line.
+#[must_use]
+pub fn disassemble(code: &CompiledCode) -> String {
+    let mut out = Vec::new();
+    write_disassembly(&mut out, code).expect("writing to a Vec");
+    String::from_utf8(out).expect("listings are UTF-8")
+}
+
+/// `disassemble`, streamed into `out` a line at a time.
+pub fn write_disassembly(out: &mut impl io::Write, code: &CompiledCode) -> io::Result<()> {
+    writeln!(out, "{}:", code.name)?;
+    let mut decoder = Decoder::with_ip(64, &code.bytes[..code.code_len()], 0, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let mut text = String::new();
+    for inst in &mut decoder {
+        text.clear();
+        formatter.format(&inst, &mut text);
+        let span = inst.ip() as usize..inst.ip() as usize + inst.len();
+        let bytes = hex(&code.bytes[span.clone()]);
+        write!(out, "  {:6x}:  {bytes:<30} {text}", span.start)?;
+        if let Some(r) = code.relocations.iter().find(|r| span.contains(&r.offset)) {
+            write!(out, "  ; reloc {}", r.symbol)?;
+        }
+        if let Some(code) = code.trap_code(span.start as u32) {
+            write!(out, "  ; trap {code}")?;
+        }
+        for site in code.patch_sites.iter().filter(|s| s.offset as usize == span.start) {
+            write!(out, "  ; patch {} {}", site.kind, site.len)?;
+        }
+        writeln!(out)?;
+    }
+    for c in &code.constants {
+        let bytes = hex(&c.bits.to_le_bytes());
+        let value = f64::from_bits(c.bits);
+        writeln!(out, "  {:6x}:  {bytes:<30} dq {:#x}  ; {value:?}", c.offset, c.bits)?;
+    }
+    for table in &code.jump_tables {
+        for (i, &target) in table.targets.iter().enumerate() {
+            let at = table.offset as usize + 4 * i;
+            let entry: [u8; 4] = code.bytes[at..at + 4].try_into().expect("4-byte entry");
+            let bytes = hex(&entry);
+            let rel = i32::from_le_bytes(entry);
+            writeln!(out, "  {at:6x}:  {bytes:<30} dd {rel}  ; -> {target:x}")?;
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        write!(s, "{b:02x}").expect("writing to a String");
+        s
+    })
+}
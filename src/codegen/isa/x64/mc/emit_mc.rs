@@ -8,30 +8,47 @@
 //!
 //! **Effect:** Emits a flat `Vec<u8>` of x86-64 machine code via iced-x86.
 //! Inserts the prologue (`push rbp; mov rbp, rsp; sub rsp, N`) and
-//! epilogue (`add rsp, N; pop rbp; ret`) around the user body. Injects
-//! spill-store moves at each `SplitMove` point so an evicted value lands
-//! in its stack slot before the new owner takes the preg.
+//! epilogue (`add rsp, N; pop rbp; ret`) around the user body — neither
+//! for a `naked` function, and a `noreturn` one saves no callee-saved
+//! registers since it never restores them. Under `FramePointer::Omit` a
+//! leaf skips the `rbp` half of both. A frame larger than the
+//! guard page is allocated a page at a time, touching each page, so the
+//! stack can't jump past the guard. Pads a `patchable` entry and each
+//! `PatchPoint` with NOPs and records where the sleds are. Injects
+//! the allocator's `Edit`s: a store at each split point so an evicted
+//! value lands in its stack slot before the new owner takes the preg, and
+//! a load where it gets a preg back.
 //!
 //! **Spill handling:** when an operand is stack-allocated at the point of
 //! use, we load into / store out of a scratch register around the
 //! instruction. Scratch registers must be disjoint from the allocatable
 //! pool — the frontend's `RegAllocConfig` is responsible for that.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::codegen::analysis::layout::{BlockLayout, ProgramPoint};
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::x64::frame::{FrameLayout, FrameRef};
 use crate::codegen::isa::x64::inst::{Cond, X64Inst};
 use crate::codegen::isa::x64::regs::{
     R10, R11, R12, R13, R14, R15, R8, R9, RAX, RBP, RBX, RCX, RDI, RDX, RSI, RSP, XMM0, XMM1,
     XMM10, XMM11, XMM12, XMM13, XMM14, XMM15, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7, XMM8, XMM9,
     is_xmm,
 };
-use crate::codegen::isa::x64::sysv::CALLEE_SAVED;
+use crate::codegen::options::{FramePointer, SpeculationHardening};
 use crate::codegen::regalloc::{
-    AllocatedSlot, RegAllocConfig, RegAllocResult, SplitMove, StackSlot,
+    AllocatedSlot, Edit, RegAllocConfig, RegAllocResult, StackSlot,
+};
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{
+    Block, Func, Instruction, JumpTableId, PatchKind, PseudoInstruction, Reg, ScalarType, SymbolId,
+    TrapCode, Type,
+};
+use crate::codegen::value_locations::{
+    StackMap, ValueLocation, ValueLocationMap, live_refs_at_safepoints,
 };
-use crate::codegen::tir::{Func, Instruction, PseudoInstruction, Reg};
 use crate::support::slotmap::Key;
+use crate::support::trace::{debug_event, enter_span, trace_event};
 use iced_x86::code_asm::registers::{
     cl, r10, r10b, r10d, r10w, r11, r11b, r11d, r11w, r12, r12b, r12d, r12w, r13, r13b, r13d,
     r13w, r14, r14b, r14d, r14w, r15, r15b, r15d, r15w, r8, r8b, r8d, r8w, r9, r9b, r9d, r9w,
@@ -40,10 +57,30 @@ use iced_x86::code_asm::registers::{
     xmm14, xmm15, xmm2, xmm3, xmm4, xmm5, xmm6, xmm7, xmm8, xmm9,
 };
 use iced_x86::code_asm::{
-    AsmRegister16, AsmRegister32, AsmRegister64, AsmRegister8, AsmRegisterXmm, CodeAssembler,
-    CodeLabel,
+    AsmMemoryOperand, AsmRegister16, AsmRegister32, AsmRegister64, AsmRegister8, AsmRegisterXmm,
+    CodeAssembler, CodeLabel, dword_ptr, ptr, qword_ptr,
 };
-use std::collections::BTreeSet;
+
+/// Intel's recommended NOP of each length from 1 to 9 bytes.
+const NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0f, 0x1f, 0x00],
+    &[0x0f, 0x1f, 0x40, 0x00],
+    &[0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x44, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
+
+/// Stack guard-page size: a frame this large or smaller can't step over
+/// it with one `sub rsp`.
+pub const GUARD_PAGE_SIZE: u32 = 4096;
+
+/// Frames of up to this many whole pages probe with straight-line code;
+/// larger ones use a loop.
+const PROBE_UNROLL_PAGES: u32 = 4;
 
 /// Maximum simultaneous scratch registers this instruction can demand in the
 /// worst case (all operand vregs spilled). Stays in sync with `emit_inst`.
@@ -79,6 +116,8 @@ fn scratch_demand_of(inst: &X64Inst) -> usize {
         | X64Inst::Mov8mr { .. } => 2,
         X64Inst::Add64rr { .. }
         | X64Inst::Sub64rr { .. }
+        | X64Inst::Adc64rr { .. }
+        | X64Inst::Sbb64rr { .. }
         | X64Inst::Imul64rr { .. }
         | X64Inst::And64rr { .. }
         | X64Inst::Or64rr { .. }
@@ -99,6 +138,7 @@ fn scratch_demand_of(inst: &X64Inst) -> usize {
         | X64Inst::Shr64rcl { .. }
         | X64Inst::Sar64rcl { .. } => 2,
         X64Inst::Mov64ri { .. }
+        | X64Inst::Mov64rsym { .. }
         | X64Inst::Mov32ri { .. }
         | X64Inst::Mov16ri { .. }
         | X64Inst::Mov8ri { .. }
@@ -117,16 +157,25 @@ fn scratch_demand_of(inst: &X64Inst) -> usize {
         | X64Inst::Setcc8r { .. }
         | X64Inst::Call64r { .. }
         | X64Inst::Jmp64r { .. } => 1,
+        // Table base and entry in two scratches, plus a spilled index.
+        X64Inst::JmpTable { .. } => 3,
         // Div/IDiv: divisor is the only vreg that isn't pre-bound. The
         // rest are pinned to RAX/RDX and physically live there at the
         // call site.
-        X64Inst::Idiv64r { .. } | X64Inst::Div64r { .. } => 1,
+        X64Inst::Idiv64r { .. } | X64Inst::Div64r { .. } | X64Inst::Mul64r { .. } => 1,
+        // A float constant's bits pass through a GPR scratch on the way
+        // to their XMM.
+        X64Inst::Fconst32 { .. } | X64Inst::Fconst64 { .. } => 1,
         X64Inst::Jmp { .. }
         | X64Inst::CondJmp { .. }
         | X64Inst::RawRet
         | X64Inst::Ud2
+        | X64Inst::Int3
         | X64Inst::Mfence
-        | X64Inst::AdjustRsp { .. } => 0,
+        | X64Inst::RepMovsb { .. }
+        | X64Inst::RepStosb { .. }
+        | X64Inst::AdjustRsp { .. }
+        | X64Inst::Select { .. } => 0,
         // `LoadArgFromStack` writes to `dst`; if spilled we need one
         // scratch to land the value before storing to the slot.
         X64Inst::LoadArgFromStack { .. } => 1,
@@ -146,14 +195,20 @@ fn scratch_demand_of(inst: &X64Inst) -> usize {
         | X64Inst::Mulsdrr { .. }
         | X64Inst::Divsdrr { .. }
         | X64Inst::Ucomissrr { .. }
-        | X64Inst::Ucomisdrr { .. } => 0,
+        | X64Inst::Ucomisdrr { .. }
+        | X64Inst::Movapsrr { .. }
+        | X64Inst::Vaddrr { .. }
+        | X64Inst::Vmulrr { .. }
+        | X64Inst::Pshufdrri { .. } => 0,
+        // The GPR lane lands in a scratch if `dst` spilled.
+        X64Inst::Pextrrri { .. } => 1,
         // FP memory ops need one GPR scratch if the base operand spills.
-        X64Inst::Movssrm { src, .. } | X64Inst::Movsdrm { src, .. }
+        X64Inst::Movssrm { src, .. } | X64Inst::Movsdrm { src, .. } | X64Inst::Movdqurm { src, .. }
             if src.index.is_some() =>
         {
             2
         }
-        X64Inst::Movssmr { dst, .. } | X64Inst::Movsdmr { dst, .. }
+        X64Inst::Movssmr { dst, .. } | X64Inst::Movsdmr { dst, .. } | X64Inst::Movdqumr { dst, .. }
             if dst.index.is_some() =>
         {
             2
@@ -161,7 +216,9 @@ fn scratch_demand_of(inst: &X64Inst) -> usize {
         X64Inst::Movssrm { .. }
         | X64Inst::Movsdrm { .. }
         | X64Inst::Movssmr { .. }
-        | X64Inst::Movsdmr { .. } => 1,
+        | X64Inst::Movsdmr { .. }
+        | X64Inst::Movdqurm { .. }
+        | X64Inst::Movdqumr { .. } => 1,
         // lock xadd / cmpxchg: the src and the address; up to 2 scratches.
         X64Inst::LockXadd64mr { dst, .. } if dst.index.is_some() => 3,
         X64Inst::LockXadd64mr { .. } => 2,
@@ -281,40 +338,117 @@ fn to_ice_reg8(r: Reg) -> AsmRegister8 {
     }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct FnMCWriter<'i> {
     asm: CodeAssembler,
     func: &'i Func<X64Inst>,
     ra_cfg: &'i RegAllocConfig,
     ra_res: &'i RegAllocResult,
     layout: BlockLayout,
-    frame_adjust: u32,
-    saved_callee_regs: Vec<Reg>,
-    splits_by_point: HashMap<ProgramPoint, Vec<SplitMove>>,
-    /// `addr_vreg -> iced_inst_index`, populated as the emitter
-    /// renders each call-site `Mov64ri`. After assembly we use
-    /// `CodeAssemblerResult::new_instruction_offsets` to look up each
-    /// instruction's final byte offset.
-    call_target_insts: HashMap<Reg, usize>,
-    /// For each `PseudoInstruction::StackAlloc { dst, .. }`, the
-    /// `rbp`-relative displacement at which the allocated region
-    /// begins. Emitting the pseudo materializes `lea dst, [rbp+disp]`.
-    alloca_offsets: HashMap<Reg, i32>,
+    frame: FrameLayout,
+    /// iced index of every `Mov64rsym` rendered so far, with its symbol.
+    /// After assembly `CodeAssemblerResult::new_instruction_offsets`
+    /// turns each into the byte offset of a relocation.
+    symbol_refs: Vec<(usize, SymbolId)>,
+    /// iced index of every `Trap`'s `ud2`, with its code.
+    trap_sites: Vec<(usize, TrapCode)>,
+    /// `Type::Ref` vregs live at each `Safepoint`'s use point.
+    safepoint_refs: HashMap<ProgramPoint, Vec<Reg>>,
+    /// iced index of the instruction after each `Safepoint`, with the
+    /// locations of its live references.
+    stack_map_sites: Vec<(usize, Vec<ValueLocation>)>,
+    /// iced index of the first NOP of every patch sled, with its length
+    /// and kind.
+    patch_sites: Vec<(usize, u32, PatchKind)>,
+    /// Use points of moves proven redundant after allocation
+    /// (`find_redundant_moves`); emission skips them.
+    elided_moves: HashSet<ProgramPoint>,
+    /// Blocks whose terminator falls through to the next block in layout
+    /// (`simplify_branches`); their trailing `jmp` is not emitted.
+    fallthrough: HashSet<Block>,
+    /// Touch every page of a frame larger than `GUARD_PAGE_SIZE`.
+    stack_probes: bool,
+    /// Keep a small leaf frame below `rsp`; see `with_red_zone`.
+    red_zone: bool,
+    /// Whether leaf functions set up `rbp`; see `with_frame_pointer`.
+    frame_pointer: FramePointer,
+    /// Open the function and every indirect-branch target with `endbr64`.
+    endbr: bool,
+    /// How indirect calls and jumps are emitted.
+    hardening: SpeculationHardening,
+    /// Prefer the shortest encoding where two are equivalent.
+    optimize_size: bool,
+    /// The retpoline thunk of each register an indirect branch went
+    /// through, laid out after the code.
+    thunks: Vec<(AsmRegister64, CodeLabel)>,
+    /// Every `JmpTable` emitted so far, with the label its `lea`
+    /// addresses. The tables are laid out after the function's code.
+    jump_tables: Vec<(JumpTableId, CodeLabel)>,
+    /// The constant pool: every distinct `f64` an `Fconst64` loads from
+    /// memory, with the label its `movsd` addresses. Laid out after the
+    /// code, ahead of the jump tables.
+    constants: Vec<(u64, CodeLabel)>,
 }
 
 /// One symbol-patch request: byte offset in the emitted buffer where
 /// an 8-byte placeholder immediate lives, plus the symbol to resolve.
-/// The symbol string is empty for indirect calls (no patching needed).
 #[derive(Clone, Debug)]
 pub struct EmittedCallReloc {
     pub imm_offset: usize,
     pub symbol: String,
 }
 
+/// A `Trap` pseudo's trapping instruction: its byte offset in the emitted
+/// buffer and why it fires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrapSite {
+    pub offset: u32,
+    pub code: TrapCode,
+}
+
+/// A NOP sled reserved for runtime patching: where it starts in the
+/// emitted buffer, how many bytes it spans, and what it sits in front of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PatchSite {
+    pub offset: u32,
+    pub len: u32,
+    pub kind: PatchKind,
+}
+
+/// A jump table laid out in the emitted buffer: where it starts, and the
+/// offset of each entry's target. Entry `i` is the `i32` distance from
+/// the table's start to `targets[i]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JumpTableSite {
+    pub offset: u32,
+    pub targets: Vec<u32>,
+}
+
+/// A constant-pool entry laid out in the emitted buffer: the 8 bytes of
+/// `bits` at `offset`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConstantSite {
+    pub offset: u32,
+    pub bits: u64,
+}
+
 /// Output of `emit_fn`: the raw code bytes plus every call-site
 /// relocation that needs to be patched before the bytes are executed.
 pub struct EmittedFunc {
     pub bytes: Vec<u8>,
     pub relocations: Vec<EmittedCallReloc>,
+    /// Every `Trap` in `bytes`, by offset.
+    pub traps: Vec<TrapSite>,
+    /// One per `Safepoint`, by offset.
+    pub stack_maps: Vec<StackMap>,
+    /// Every patch sled in `bytes`, by offset.
+    pub patch_sites: Vec<PatchSite>,
+    /// Every jump table in `bytes`, by offset.
+    pub jump_tables: Vec<JumpTableSite>,
+    /// Every constant-pool entry in `bytes`, by offset.
+    pub constants: Vec<ConstantSite>,
+    /// Where every vreg lives over `bytes`.
+    pub value_locations: ValueLocationMap,
 }
 
 impl<'i> FnMCWriter<'i> {
@@ -324,93 +458,104 @@ impl<'i> FnMCWriter<'i> {
         ra_cfg: &'i RegAllocConfig,
         ra_res: &'i RegAllocResult,
     ) -> Self {
-        let (alloca_extra, alloca_offsets) =
-            Self::compute_alloca_layout(func, ra_res.frame_size);
-        let raw_frame = ra_res.frame_size + alloca_extra;
-        let frame_adjust = raw_frame.div_ceil(16) * 16;
-        let saved_callee_regs = Self::compute_saved_callee_regs(ra_cfg, ra_res);
+        let frame = FrameLayout::compute(func, ra_cfg, ra_res);
         let layout = BlockLayout::compute(func);
-        let mut splits_by_point: HashMap<ProgramPoint, Vec<SplitMove>> = HashMap::new();
-        for sm in &ra_res.split_moves {
-            splits_by_point.entry(sm.at_point).or_default().push(*sm);
-        }
+        let safepoint_refs = live_refs_at_safepoints(func, &layout);
         Self {
             asm: CodeAssembler::new(64).expect("iced-x86 supports 64-bit"),
             func,
             ra_cfg,
             ra_res,
             layout,
-            frame_adjust,
-            saved_callee_regs,
-            splits_by_point,
-            call_target_insts: HashMap::new(),
-            alloca_offsets,
+            frame,
+            symbol_refs: Vec::new(),
+            trap_sites: Vec::new(),
+            safepoint_refs,
+            stack_map_sites: Vec::new(),
+            patch_sites: Vec::new(),
+            elided_moves: HashSet::new(),
+            fallthrough: HashSet::new(),
+            stack_probes: true,
+            red_zone: false,
+            frame_pointer: FramePointer::Always,
+            endbr: false,
+            hardening: SpeculationHardening::Off,
+            optimize_size: false,
+            thunks: Vec::new(),
+            jump_tables: Vec::new(),
+            constants: Vec::new(),
         }
     }
 
-    /// Scan the func for `StackAlloc` pseudos, pack each below the
-    /// spill region, and return `(extra_frame_bytes, vreg → rbp-disp)`.
-    /// Each alloca's displacement is negative — `rbp + disp` is the
-    /// lowest address of its allocated region, so the pointer is
-    /// properly aligned to the requested `align`.
-    fn compute_alloca_layout(
-        func: &'i Func<X64Inst>,
-        ra_frame_size: u32,
-    ) -> (u32, HashMap<Reg, i32>) {
-        let mut running: u32 = ra_frame_size;
-        let mut offsets: HashMap<Reg, i32> = HashMap::new();
-        for (_b, bd) in func.blocks_iter() {
-            for inst in bd.iter() {
-                if let Instruction::Pseudo(PseudoInstruction::StackAlloc {
-                    dst,
-                    size,
-                    align,
-                }) = inst
-                {
-                    // Grow running offset by `size`, then round up to
-                    // `align` so the allocation's *low* address is
-                    // aligned.
-                    let new_running = (running + *size).next_multiple_of(*align);
-                    offsets.insert(*dst, -(new_running as i32));
-                    running = new_running;
-                }
-            }
-        }
-        (running - ra_frame_size, offsets)
+    /// Whether large frames are probed; on by default.
+    #[must_use]
+    pub fn with_stack_probes(mut self, on: bool) -> Self {
+        self.stack_probes = on;
+        self
     }
 
-    /// Mark vreg `v` as the address operand of an upcoming call-site
-    /// `Mov64ri`. The next time we emit a Mov64ri whose destination is
-    /// `v` we'll record its iced instruction index so we can look up
-    /// its byte offset after assembly.
-    pub fn mark_call_target(&mut self, v: Reg) {
-        // Use `usize::MAX` as "pending" sentinel; replaced with the
-        // real iced index inside `emit_inst`.
-        self.call_target_insts.insert(v, usize::MAX);
+    /// Whether a leaf function with a small frame keeps it in the red
+    /// zone instead of moving `rsp`; off by default. Not when its
+    /// indirect jumps go through retpoline thunks, whose `call` pushes
+    /// over the frame.
+    #[must_use]
+    pub fn with_red_zone(mut self, on: bool) -> Self {
+        self.red_zone = on;
+        self
     }
 
-    fn compute_saved_callee_regs(ra_cfg: &RegAllocConfig, ra_res: &RegAllocResult) -> Vec<Reg> {
-        let mut used: BTreeSet<Reg> = BTreeSet::new();
-        for (_v, asn) in ra_res.assignments.iter() {
-            for slot in asn.slots() {
-                if let AllocatedSlot::Reg(r) = slot {
-                    used.insert(r);
-                }
-            }
-        }
-        for s in &ra_cfg.scratch_regs {
-            used.insert(*s);
-        }
-        CALLEE_SAVED
-            .iter()
-            .filter(|r| used.contains(r))
-            .filter(|&&r| r != RBP)
-            .copied()
-            .collect()
+    /// Whether a leaf function skips setting up `rbp` and addresses its
+    /// frame off `rsp`; `Always` sets it up, the default.
+    #[must_use]
+    pub fn with_frame_pointer(mut self, policy: FramePointer) -> Self {
+        self.frame_pointer = policy;
+        self
     }
 
-    fn slot_offset(slot: StackSlot) -> i32 {
-        -((slot as i32 + 1) * 8)
+    /// Whether the entry and indirect-branch targets get an `endbr64`
+    /// landing pad, for CET indirect branch tracking; off by default.
+    #[must_use]
+    pub fn with_endbr(mut self, on: bool) -> Self {
+        self.endbr = on;
+        self
+    }
+
+    /// How indirect calls and jumps are hardened; `Off` by default.
+    #[must_use]
+    pub fn with_speculation_hardening(mut self, mode: SpeculationHardening) -> Self {
+        self.hardening = mode;
+        self
+    }
+
+    /// Whether equivalent shorter encodings are chosen, e.g. a 32-bit
+    /// `mov` for a 64-bit immediate that zero-extends; off by default.
+    #[must_use]
+    pub fn with_optimize_size(mut self, on: bool) -> Self {
+        self.optimize_size = on;
+        self
+    }
+
+    /// Skip the moves at these use points. Pending split stores at the
+    /// same instruction are still emitted.
+    #[must_use]
+    pub fn with_elided_moves(mut self, moves: HashSet<ProgramPoint>) -> Self {
+        self.elided_moves = moves;
+        self
+    }
+
+    /// Drop the final `jmp` of these blocks: a `Jmp` emits nothing and a
+    /// `CondJmp` emits only its `jcc` to `taken`. Each block's fallthrough
+    /// target must be the next block in `blocks_iter()` order.
+    #[must_use]
+    pub fn with_fallthrough(mut self, blocks: HashSet<Block>) -> Self {
+        self.fallthrough = blocks;
+        self
+    }
+
+    /// The address of `r` in the frame, as an iced operand.
+    fn frame_mem(&self, r: FrameRef) -> AsmMemoryOperand {
+        let m = self.frame.resolve(r);
+        to_ice_reg(m.base) + i64::from(m.disp)
     }
 
     fn check_scratch_budget(&self) {
@@ -471,7 +616,7 @@ impl<'i> FnMCWriter<'i> {
                 let s_preg = self.ra_cfg.scratch_regs[scratch_idx];
                 let s = to_ice_reg(s_preg);
                 self.asm
-                    .mov(s, rbp + i64::from(Self::slot_offset(slot)))
+                    .mov(s, self.frame_mem(FrameRef::Spill(slot)))
                     .expect("mov-load from slot");
                 s_preg
             }
@@ -496,7 +641,7 @@ impl<'i> FnMCWriter<'i> {
     fn store_def(&mut self, vreg: Reg, pt: ProgramPoint, scratch_idx: usize) {
         if let AllocatedSlot::Stack(slot) = self.slot_of(vreg, pt) {
             self.asm
-                .mov(rbp + i64::from(Self::slot_offset(slot)), self.scratch(scratch_idx))
+                .mov(self.frame_mem(FrameRef::Spill(slot)), self.scratch(scratch_idx))
                 .expect("mov-store to slot");
         }
     }
@@ -512,9 +657,7 @@ impl<'i> FnMCWriter<'i> {
     }
 
     /// Load an XMM operand into a physical XMM register. If the vreg is
-    /// on the stack, load into the given scratch XMM via `movsd`
-    /// (64-bit FP load, also valid for 32-bit floats — the upper bytes
-    /// of the stack slot are don't-care for scalar FP arithmetic).
+    /// on the stack, load into the given scratch XMM (`load_xmm_slot`).
     fn load_fp_use(&mut self, vreg: Reg, pt: ProgramPoint, scratch_idx: usize) -> AsmRegisterXmm {
         match self.slot_of(vreg, pt) {
             AllocatedSlot::Reg(r) => {
@@ -526,9 +669,7 @@ impl<'i> FnMCWriter<'i> {
             }
             AllocatedSlot::Stack(slot) => {
                 let s = self.scratch_fp(scratch_idx);
-                self.asm
-                    .movsd_2(s, rbp + i64::from(Self::slot_offset(slot)))
-                    .expect("movsd-load from slot");
+                self.load_xmm_slot(s, slot);
                 s
             }
         }
@@ -551,12 +692,38 @@ impl<'i> FnMCWriter<'i> {
 
     fn store_fp_def(&mut self, vreg: Reg, pt: ProgramPoint, scratch_idx: usize) {
         if let AllocatedSlot::Stack(slot) = self.slot_of(vreg, pt) {
-            self.asm
-                .movsd_2(rbp + i64::from(Self::slot_offset(slot)), self.scratch_fp(scratch_idx))
-                .expect("movsd-store to slot");
+            self.store_xmm_slot(slot, self.scratch_fp(scratch_idx));
+        }
+    }
+
+    /// Reload spill slot `slot` into `r`: all 16 bytes of a slot a vector
+    /// lives in, else the low 8 with `movsd` (also right for `f32` — the
+    /// upper bytes are don't-care for scalar FP).
+    fn load_xmm_slot(&mut self, r: AsmRegisterXmm, slot: StackSlot) {
+        let mem = self.frame_mem(FrameRef::Spill(slot));
+        if self.vector_slot(slot) {
+            self.asm.movups(r, mem).expect("movups-load from slot");
+        } else {
+            self.asm.movsd_2(r, mem).expect("movsd-load from slot");
         }
     }
 
+    /// `load_xmm_slot`'s store counterpart.
+    fn store_xmm_slot(&mut self, slot: StackSlot, r: AsmRegisterXmm) {
+        let mem = self.frame_mem(FrameRef::Spill(slot));
+        if self.vector_slot(slot) {
+            self.asm.movups(mem, r).expect("movups-store to slot");
+        } else {
+            self.asm.movsd_2(mem, r).expect("movsd-store to slot");
+        }
+    }
+
+    fn vector_slot(&self, slot: StackSlot) -> bool {
+        let size = self.frame.spill_slot_size(slot);
+        assert!(size <= 16, "spill slot {slot} holds a {size}-byte vector; only V128 is supported");
+        size == 16
+    }
+
 
     /// `dst` is both read (at `use_pt`) and written (at `def_pt`) — XMM
     /// variant of `emit_rr_op`. Used by the scalar-FP arithmetic ops.
@@ -589,54 +756,193 @@ impl<'i> FnMCWriter<'i> {
         self.store_def(dst, def_pt, 0);
     }
 
+    /// Whether an indirect jump goes through a retpoline thunk. A leaf has
+    /// no indirect calls.
+    fn retpoline_jumps(&self) -> bool {
+        self.hardening == SpeculationHardening::Retpoline
+            && self.func.blocks_iter().any(|(_, bd)| {
+                bd.iter().any(|inst| {
+                    matches!(
+                        inst,
+                        Instruction::Target(X64Inst::Jmp64r { .. } | X64Inst::JmpTable { .. })
+                    )
+                })
+            })
+    }
+
+    /// Blocks an indirect jump may land in, which need an `endbr64` under
+    /// `with_endbr`: the targets of every `JmpTable`, or all blocks in a
+    /// function with a `Jmp64r`, whose targets aren't known.
+    fn landing_pads(&self) -> HashSet<Block> {
+        let mut pads = HashSet::new();
+        if !self.endbr {
+            return pads;
+        }
+        for (_, bd) in self.func.blocks_iter() {
+            for inst in bd.iter() {
+                match *inst {
+                    Instruction::Target(X64Inst::JmpTable { table, .. }) => {
+                        pads.extend(self.func.jump_table(table).targets());
+                    }
+                    Instruction::Target(X64Inst::Jmp64r { .. }) => {
+                        return self.func.blocks_iter().map(|(b, _)| b).collect();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        pads
+    }
+
+    /// `len` bytes of the recommended multi-byte NOPs, longest first, so
+    /// the sled decodes as few instructions as possible.
+    fn emit_patch_sled(&mut self, len: u32, kind: PatchKind) {
+        stat!("emit", "patch_sites", "patchable NOP sleds emitted");
+        self.patch_sites.push((self.asm.instructions().len(), len, kind));
+        let mut left = len as usize;
+        while left > 0 {
+            let nop = NOPS[left.min(NOPS.len()) - 1];
+            self.asm.db(nop).expect("db nop");
+            left -= nop.len();
+        }
+    }
+
     fn emit_prologue(&mut self) {
-        self.asm.push(rbp).expect("push rbp");
-        for &r in &self.saved_callee_regs {
+        if self.func.attrs().naked {
+            assert!(
+                self.frame.frame_adjust == 0 && self.frame.saved_regs.is_empty(),
+                "naked function `{}` needs a frame ({} bytes, callee-saved {:?})",
+                self.func.name(),
+                self.frame.frame_adjust,
+                self.frame.saved_regs,
+            );
+            return;
+        }
+        if self.frame.frame_pointer {
+            self.asm.push(rbp).expect("push rbp");
+        }
+        for &r in &self.frame.saved_regs {
             self.asm.push(to_ice_reg(r)).expect("push callee-saved");
         }
-        let needs_pad_8 = self.saved_callee_regs.len() % 2 == 1;
-        self.asm.mov(rbp, rsp).expect("mov rbp, rsp");
-        let mut adj = self.frame_adjust;
-        if needs_pad_8 {
-            adj += 8;
+        if self.frame.frame_pointer {
+            self.asm.mov(rbp, rsp).expect("mov rbp, rsp");
         }
-        if adj > 0 {
+        let adj = self.frame.frame_adjust;
+        if self.stack_probes && adj > GUARD_PAGE_SIZE {
+            self.emit_probed_frame(adj);
+        } else if adj > 0 {
             self.asm.sub(rsp, adj as i32).expect("sub rsp, N");
         }
     }
 
+    /// `sub rsp, adj` one page at a time, storing to each new page before
+    /// moving past it. Runs before the body, so `r11` — never an argument
+    /// register — is free to hold the loop bound.
+    fn emit_probed_frame(&mut self, adj: u32) {
+        stat!("emit", "probed_frames", "frames allocated with stack probes");
+        let (pages, rest) = (adj / GUARD_PAGE_SIZE, adj % GUARD_PAGE_SIZE);
+        let page = GUARD_PAGE_SIZE as i32;
+        if pages <= PROBE_UNROLL_PAGES {
+            for _ in 0..pages {
+                self.asm.sub(rsp, page).expect("sub rsp, page");
+                self.asm.mov(qword_ptr(rsp), 0).expect("probe");
+            }
+        } else {
+            let bound = i32::try_from(pages * GUARD_PAGE_SIZE).expect("frame fits in i32");
+            self.asm.mov(r11, rsp).expect("mov r11, rsp");
+            self.asm.sub(r11, bound).expect("sub r11, N");
+            let mut top = self.asm.create_label();
+            self.asm.set_label(&mut top).expect("set_label");
+            self.asm.sub(rsp, page).expect("sub rsp, page");
+            self.asm.mov(qword_ptr(rsp), 0).expect("probe");
+            self.asm.cmp(rsp, r11).expect("cmp rsp, r11");
+            self.asm.jne(top).expect("jne");
+        }
+        if rest > 0 {
+            self.asm.sub(rsp, rest as i32).expect("sub rsp, N");
+        }
+    }
+
     fn emit_epilogue(&mut self) {
-        let needs_pad_8 = self.saved_callee_regs.len() % 2 == 1;
-        let adj = self.frame_adjust + if needs_pad_8 { 8 } else { 0 };
+        assert!(
+            !self.func.attrs().noreturn,
+            "noreturn function `{}` returns",
+            self.func.name()
+        );
+        if self.func.attrs().naked {
+            self.asm.ret().expect("ret");
+            return;
+        }
+        let adj = self.frame.frame_adjust;
         if adj > 0 {
             self.asm.add(rsp, adj as i32).expect("add rsp, N");
         }
-        for &r in self.saved_callee_regs.iter().rev() {
+        for &r in self.frame.saved_regs.iter().rev() {
             self.asm.pop(to_ice_reg(r)).expect("pop callee-saved");
         }
-        self.asm.pop(rbp).expect("pop rbp");
+        if self.frame.frame_pointer {
+            self.asm.pop(rbp).expect("pop rbp");
+        }
         self.asm.ret().expect("ret");
     }
 
-    /// Emit any split-store moves pending at this instruction's def-point.
-    /// These preserve the evicted vreg's value before the new owner
-    /// overwrites the preg. Routes by class: GPR pregs use `mov`,
-    /// XMM pregs use `movsd` (64-bit FP store, correct for both F32
-    /// and F64 slots since the allocator reserves 8 bytes either way).
-    fn emit_pending_splits(&mut self, def_pt: ProgramPoint) {
-        let Some(moves) = self.splits_by_point.get(&def_pt).cloned() else { return };
-        for sm in moves {
-            let off = i64::from(Self::slot_offset(sm.to_slot));
-            if is_xmm(sm.from_preg) {
-                let reg = to_ice_xmm(sm.from_preg);
-                self.asm.movsd_2(rbp + off, reg).expect("split-store xmm");
-            } else {
-                let reg = to_ice_reg(sm.from_preg);
-                self.asm.mov(rbp + off, reg).expect("split-store");
+    /// Emit the allocator's edits pending before an instruction, in
+    /// order: reloads at its use point bring a value split to the stack
+    /// around a loop back into its new preg, then split stores at its def
+    /// point preserve the evicted vreg's value before the new owner
+    /// overwrites the preg. Routes by class: GPR pregs use `mov`, XMM
+    /// pregs use `movsd` against a slot (correct for both F32 and F64
+    /// since the allocator reserves 8 bytes either way) and `movaps`
+    /// between registers.
+    fn emit_pending_edits(&mut self, use_pt: ProgramPoint, def_pt: ProgramPoint) {
+        let ra_res = self.ra_res;
+        for &(_, edit) in ra_res.edits_at(use_pt..=def_pt) {
+            match edit {
+                Edit::Store { from, to } => {
+                    trace_event!(at = def_pt, preg = from, slot = to, "split store");
+                    if is_xmm(from) {
+                        self.store_xmm_slot(to, to_ice_xmm(from));
+                    } else {
+                        let slot = self.frame_mem(FrameRef::Spill(to));
+                        self.asm.mov(slot, to_ice_reg(from)).expect("split-store");
+                    }
+                }
+                Edit::Reload { from, to } => {
+                    trace_event!(at = use_pt, slot = from, preg = to, "reload");
+                    if is_xmm(to) {
+                        self.load_xmm_slot(to_ice_xmm(to), from);
+                    } else {
+                        let slot = self.frame_mem(FrameRef::Spill(from));
+                        self.asm.mov(to_ice_reg(to), slot).expect("reload");
+                    }
+                }
+                Edit::Move { from, to } => {
+                    trace_event!(at = use_pt, from, to, "edit move");
+                    if is_xmm(to) {
+                        self.asm.movaps(to_ice_xmm(to), to_ice_xmm(from)).expect("edit: movaps");
+                    } else {
+                        self.asm.mov(to_ice_reg(to), to_ice_reg(from)).expect("edit: mov rr");
+                    }
+                }
             }
         }
     }
 
+    fn emit_jcc(&mut self, cond: Cond, target: CodeLabel) {
+        match cond {
+            Cond::Z => self.asm.jz(target).expect("jz"),
+            Cond::NZ => self.asm.jnz(target).expect("jnz"),
+            Cond::L => self.asm.jl(target).expect("jl"),
+            Cond::LE => self.asm.jle(target).expect("jle"),
+            Cond::G => self.asm.jg(target).expect("jg"),
+            Cond::GE => self.asm.jge(target).expect("jge"),
+            Cond::B => self.asm.jb(target).expect("jb"),
+            Cond::BE => self.asm.jbe(target).expect("jbe"),
+            Cond::A => self.asm.ja(target).expect("ja"),
+            Cond::AE => self.asm.jae(target).expect("jae"),
+        }
+    }
+
     fn emit_inst(
         &mut self,
         inst: &X64Inst,
@@ -661,20 +967,29 @@ impl<'i> FnMCWriter<'i> {
                     }
                     AllocatedSlot::Stack(slot) => {
                         self.asm
-                            .mov(rbp + i64::from(Self::slot_offset(slot)), src_r)
+                            .mov(self.frame_mem(FrameRef::Spill(slot)), src_r)
                             .expect("mov slot, rr");
                     }
                 }
             }
             X64Inst::Mov64ri { dst, imm } => {
-                // Before emitting, if `dst` is a call-target vreg, the
-                // index of the iced instruction we're about to append
-                // is the current length of `self.asm.instructions()`.
-                if let Some(slot) = self.call_target_insts.get_mut(&dst) {
-                    *slot = self.asm.instructions().len();
+                let dst_p = self.prepare_def_preg(dst, def_pt, 0);
+                match u32::try_from(imm) {
+                    // `mov r32, imm32` zero-extends in 5 bytes, against 7
+                    // or 10 for the 64-bit forms.
+                    Ok(imm) if self.optimize_size => {
+                        self.asm.mov(to_ice_reg32(dst_p), imm).expect("mov r32, imm32");
+                    }
+                    _ => self.asm.mov(to_ice_reg(dst_p), imm).expect("mov r, imm64"),
                 }
+                self.store_def(dst, def_pt, 0);
+            }
+            X64Inst::Mov64rsym { dst, sym } => {
                 let dst_r = self.prepare_def(dst, def_pt, 0);
-                self.asm.mov(dst_r, imm).expect("mov r, imm64");
+                // The iced instruction about to be appended; a zero imm64
+                // placeholder, which iced never shrinks.
+                self.symbol_refs.push((self.asm.instructions().len(), sym));
+                self.asm.mov(dst_r, 0i64).expect("mov r, imm64");
                 self.store_def(dst, def_pt, 0);
             }
             X64Inst::Mov64rm { dst, src } => {
@@ -932,6 +1247,18 @@ impl<'i> FnMCWriter<'i> {
                     a.imul_2(d, s).expect("imul rr");
                 });
             }
+            // Spill reloads and stores between these and the `add`/`sub`
+            // feeding them are plain `mov`s, so the carry survives.
+            X64Inst::Adc64rr { dst, src } => {
+                self.emit_rr_op(dst, src, use_pt, def_pt, |a, d, s| {
+                    a.adc(d, s).expect("adc rr");
+                });
+            }
+            X64Inst::Sbb64rr { dst, src } => {
+                self.emit_rr_op(dst, src, use_pt, def_pt, |a, d, s| {
+                    a.sbb(d, s).expect("sbb rr");
+                });
+            }
             X64Inst::Add64ri32 { dst, imm } => {
                 let dst_r = self.load_use(dst, use_pt, 0);
                 self.asm.add(dst_r, imm).expect("add r, imm32");
@@ -1032,6 +1359,24 @@ impl<'i> FnMCWriter<'i> {
                 let div_r = self.load_use(divisor, use_pt, 0);
                 self.asm.div(div_r).expect("div r");
             }
+            X64Inst::Mul64r { src, lo_in, .. } => {
+                assert_preg_pin(self.slot_of(lo_in, use_pt), RAX, "mul lo_in");
+                let src_r = self.load_use(src, use_pt, 0);
+                self.asm.mul(src_r).expect("mul r");
+            }
+            // ----- String ops. Every operand pre-bound. -----
+            X64Inst::RepMovsb { dst_in, src_in, count_in, .. } => {
+                assert_preg_pin(self.slot_of(dst_in, use_pt), RDI, "rep movsb dst");
+                assert_preg_pin(self.slot_of(src_in, use_pt), RSI, "rep movsb src");
+                assert_preg_pin(self.slot_of(count_in, use_pt), RCX, "rep movsb count");
+                self.asm.rep().movsb().expect("rep movsb");
+            }
+            X64Inst::RepStosb { dst_in, byte, count_in, .. } => {
+                assert_preg_pin(self.slot_of(dst_in, use_pt), RDI, "rep stosb dst");
+                assert_preg_pin(self.slot_of(byte, use_pt), RAX, "rep stosb byte");
+                assert_preg_pin(self.slot_of(count_in, use_pt), RCX, "rep stosb count");
+                self.asm.rep().stosb().expect("rep stosb");
+            }
             // ----- Compare / test. -----
             X64Inst::Cmp64rr { lhs, rhs } => {
                 let lhs_r = self.load_use(lhs, use_pt, 0);
@@ -1067,61 +1412,83 @@ impl<'i> FnMCWriter<'i> {
             // ----- Call (indirect). -----
             X64Inst::Call64r { target } => {
                 let tgt_r = self.load_use(target, use_pt, 0);
-                self.asm.call(tgt_r).expect("call r");
+                self.emit_indirect(tgt_r, true);
             }
             // ----- Control flow. -----
             X64Inst::Jmp { dst } => {
                 self.asm.jmp(labels[dst.index()]).expect("jmp label");
             }
             X64Inst::CondJmp { cond, taken, not_taken } => {
-                let taken_lbl = labels[taken.index()];
-                let not_taken_lbl = labels[not_taken.index()];
-                match cond {
-                    Cond::Z => self.asm.jz(taken_lbl).expect("jz"),
-                    Cond::NZ => self.asm.jnz(taken_lbl).expect("jnz"),
-                    Cond::L => self.asm.jl(taken_lbl).expect("jl"),
-                    Cond::LE => self.asm.jle(taken_lbl).expect("jle"),
-                    Cond::G => self.asm.jg(taken_lbl).expect("jg"),
-                    Cond::GE => self.asm.jge(taken_lbl).expect("jge"),
-                    Cond::B => self.asm.jb(taken_lbl).expect("jb"),
-                    Cond::BE => self.asm.jbe(taken_lbl).expect("jbe"),
-                    Cond::A => self.asm.ja(taken_lbl).expect("ja"),
-                    Cond::AE => self.asm.jae(taken_lbl).expect("jae"),
-                }
-                self.asm.jmp(not_taken_lbl).expect("jmp fallthrough");
+                self.emit_jcc(cond, labels[taken.index()]);
+                self.asm.jmp(labels[not_taken.index()]).expect("jmp fallthrough");
             }
             X64Inst::Jmp64r { target } => {
                 let t_r = self.load_use(target, use_pt, 0);
-                self.asm.jmp(t_r).expect("jmp r");
+                self.emit_indirect(t_r, false);
+            }
+            X64Inst::JmpTable { index, table } => {
+                let idx_r = self.load_use(index, use_pt, 2);
+                let (base, entry) = (self.scratch(0), self.scratch(1));
+                let label = self.asm.create_label();
+                self.asm.lea(base, ptr(label)).expect("lea table");
+                self.asm.movsxd(entry, dword_ptr(base + idx_r * 4)).expect("movsxd entry");
+                self.asm.add(base, entry).expect("add entry");
+                self.emit_indirect(base, false);
+                self.jump_tables.push((table, label));
             }
             X64Inst::Ud2 => {
                 self.asm.ud2().expect("ud2");
             }
+            X64Inst::Int3 => {
+                self.asm.int3().expect("int3");
+            }
             X64Inst::Mfence => {
                 self.asm.mfence().expect("mfence");
             }
             X64Inst::LoadArgFromStack { dst, stack_idx } => {
-                // Address of argument on the caller's stack:
-                // [rbp + 8 (saved rbp) + 8 (retaddr) + 8*K (callee-saved pushes)
-                //      + 8*stack_idx], because the current prologue pushes
-                // callee-saved registers between `push rbp` and
-                // `mov rbp, rsp`, which offsets the standard 16-byte rbp-to-arg7
-                // distance by `8*K`.
-                let k = self.saved_callee_regs.len() as i32;
-                let disp = 16 + 8 * k + 8 * (stack_idx as i32);
-                let dst_p = self.prepare_def_preg(dst, def_pt, 0);
-                self.asm
-                    .mov(to_ice_reg(dst_p), rbp + i64::from(disp))
-                    .expect("mov r64, [rbp+arg_disp]");
-                self.store_def(dst, def_pt, 0);
+                assert!(
+                    !self.func.attrs().naked,
+                    "naked function `{}` reads a stack argument",
+                    self.func.name()
+                );
+                let arg = self.frame_mem(FrameRef::IncomingArg(stack_idx));
+                if self.func.vreg_type(dst).is_fp_or_vector() {
+                    // A `V128` takes a 16-aligned pair of eightbytes, a
+                    // scalar one, which `movsd` reads for F32 and F64
+                    // alike (upper bytes are don't-care).
+                    let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                    if matches!(self.func.vreg_type(dst), Type::V128(_)) {
+                        self.asm.movups(dst_r, arg).expect("movups xmm, [rbp+arg_disp]");
+                    } else {
+                        self.asm.movsd_2(dst_r, arg).expect("movsd xmm, [rbp+arg_disp]");
+                    }
+                    self.store_fp_def(dst, def_pt, 0);
+                } else {
+                    let dst_p = self.prepare_def_preg(dst, def_pt, 0);
+                    self.asm
+                        .mov(to_ice_reg(dst_p), arg)
+                        .expect("mov r64, [rbp+arg_disp]");
+                    self.store_def(dst, def_pt, 0);
+                }
             }
             X64Inst::StoreStackArg { src, stack_idx } => {
-                let src_r = self.load_use(src, use_pt, 0);
-                let disp = 8 * i64::from(stack_idx);
-                self.asm
-                    .mov(rsp + disp, src_r)
-                    .expect("mov [rsp+disp], r64");
+                let arg = self.frame_mem(FrameRef::OutgoingArg(stack_idx));
+                if self.func.vreg_type(src).is_fp_or_vector() {
+                    let src_r = self.load_fp_use(src, use_pt, 0);
+                    if matches!(self.func.vreg_type(src), Type::V128(_)) {
+                        self.asm.movups(arg, src_r).expect("movups [rsp+disp], xmm");
+                    } else {
+                        self.asm.movsd_2(arg, src_r).expect("movsd [rsp+disp], xmm");
+                    }
+                } else {
+                    let src_r = self.load_use(src, use_pt, 0);
+                    self.asm
+                        .mov(arg, src_r)
+                        .expect("mov [rsp+disp], r64");
+                }
             }
+            // The frame already holds the outgoing-argument area.
+            X64Inst::AdjustRsp { .. } if self.frame.outgoing_args > 0 => {}
             X64Inst::AdjustRsp { delta } => {
                 if delta > 0 {
                     self.asm.add(rsp, delta).expect("add rsp, imm");
@@ -1130,6 +1497,9 @@ impl<'i> FnMCWriter<'i> {
                 }
             }
             X64Inst::RawRet => self.emit_epilogue(),
+            X64Inst::Select { .. } => {
+                panic!("Select should have been lowered to cmov or a branch before emission")
+            }
 
             // ---- Scalar FP moves. ----
             X64Inst::Movssrr { dst, src } => {
@@ -1215,6 +1585,43 @@ impl<'i> FnMCWriter<'i> {
                         .expect("movsd [mem], r");
                 }
             }
+            X64Inst::Fconst32 { dst, bits } => {
+                let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                if bits == 0 {
+                    self.asm.xorps(dst_r, dst_r).expect("xorps");
+                } else {
+                    let g = to_ice_reg32(self.ra_cfg.scratch_regs[0]);
+                    self.asm.mov(g, bits as i32).expect("mov r32, imm32");
+                    self.asm.movd(dst_r, g).expect("movd xmm, r32");
+                }
+                self.store_fp_def(dst, def_pt, 0);
+            }
+            X64Inst::Fconst64 { dst, bits } => {
+                let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                if bits == 0 {
+                    self.asm.xorps(dst_r, dst_r).expect("xorps");
+                } else if let Ok(imm) = u32::try_from(bits) {
+                    // `mov r32` zero-extends into the full register.
+                    let g = to_ice_reg32(self.ra_cfg.scratch_regs[0]);
+                    self.asm.mov(g, imm as i32).expect("mov r32, imm32");
+                    self.asm.movq(dst_r, self.scratch(0)).expect("movq xmm, r64");
+                } else if let Ok(imm) = i32::try_from(bits as i64) {
+                    // `CodeAssembler::mov` only takes an imm64 for a 64-bit
+                    // register; ask for the sign-extended imm32 form.
+                    let mov = iced_x86::Instruction::with2(
+                        iced_x86::Code::Mov_rm64_imm32,
+                        iced_x86::Register::from(self.scratch(0)),
+                        imm,
+                    )
+                    .expect("mov r64, simm32");
+                    self.asm.add_instruction(mov).expect("mov r64, simm32");
+                    self.asm.movq(dst_r, self.scratch(0)).expect("movq xmm, r64");
+                } else {
+                    let label = self.constant_label(bits);
+                    self.asm.movsd_2(dst_r, qword_ptr(label)).expect("movsd xmm, [rip+c]");
+                }
+                self.store_fp_def(dst, def_pt, 0);
+            }
 
             // ---- Scalar FP arithmetic. ----
             X64Inst::Addssrr { dst, src } => self.emit_fp_rr_op(dst, src, use_pt, def_pt, |a, d, s| {
@@ -1254,6 +1661,93 @@ impl<'i> FnMCWriter<'i> {
                 self.asm.ucomisd(l, r).expect("ucomisd");
             }
 
+            // ---- 128-bit vectors. ----
+            X64Inst::Movapsrr { dst, src } => {
+                if let (AllocatedSlot::Reg(a), AllocatedSlot::Reg(b)) =
+                    (self.slot_of(dst, def_pt), self.slot_of(src, use_pt))
+                    && a == b
+                {
+                    return;
+                }
+                let src_r = self.load_fp_use(src, use_pt, 1);
+                let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                self.asm.movaps(dst_r, src_r).expect("movaps rr");
+                self.store_fp_def(dst, def_pt, 0);
+            }
+            X64Inst::Movdqurm { dst, src } => {
+                let base_r = self.load_use(src.base, use_pt, 1);
+                let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                if let Some(idx) = src.index {
+                    let idx_r = self.load_use(idx, use_pt, 2);
+                    self.asm
+                        .movdqu(dst_r, base_r + idx_r * i32::from(src.scale) + src.disp)
+                        .expect("movdqu r, [mem]");
+                } else {
+                    self.asm
+                        .movdqu(dst_r, base_r + i64::from(src.disp))
+                        .expect("movdqu r, [mem]");
+                }
+                self.store_fp_def(dst, def_pt, 0);
+            }
+            X64Inst::Movdqumr { dst, src } => {
+                let base_r = self.load_use(dst.base, use_pt, 0);
+                let src_r = self.load_fp_use(src, use_pt, 0);
+                if let Some(idx) = dst.index {
+                    let idx_r = self.load_use(idx, use_pt, 2);
+                    self.asm
+                        .movdqu(base_r + idx_r * i32::from(dst.scale) + dst.disp, src_r)
+                        .expect("movdqu [mem], r");
+                } else {
+                    self.asm
+                        .movdqu(base_r + i64::from(dst.disp), src_r)
+                        .expect("movdqu [mem], r");
+                }
+            }
+            X64Inst::Vaddrr { lanes, dst, src } => {
+                self.emit_fp_rr_op(dst, src, use_pt, def_pt, |a, d, s| match lanes {
+                    ScalarType::I8 => a.paddb(d, s).expect("paddb"),
+                    ScalarType::I16 => a.paddw(d, s).expect("paddw"),
+                    ScalarType::I32 => a.paddd(d, s).expect("paddd"),
+                    ScalarType::I64 | ScalarType::Ptr => a.paddq(d, s).expect("paddq"),
+                    ScalarType::F32 => a.addps(d, s).expect("addps"),
+                    ScalarType::F64 => a.addpd(d, s).expect("addpd"),
+                });
+            }
+            X64Inst::Vmulrr { lanes, dst, src } => {
+                self.emit_fp_rr_op(dst, src, use_pt, def_pt, |a, d, s| match lanes {
+                    ScalarType::I16 => a.pmullw(d, s).expect("pmullw"),
+                    ScalarType::I32 => a.pmulld(d, s).expect("pmulld"),
+                    ScalarType::F32 => a.mulps(d, s).expect("mulps"),
+                    ScalarType::F64 => a.mulpd(d, s).expect("mulpd"),
+                    ScalarType::I8 | ScalarType::I64 | ScalarType::Ptr => {
+                        panic!("no SSE multiply for {lanes} lanes")
+                    }
+                });
+            }
+            X64Inst::Pshufdrri { dst, src, order } => {
+                let src_r = self.load_fp_use(src, use_pt, 1);
+                let dst_r = self.prepare_fp_def(dst, def_pt, 0);
+                self.asm.pshufd(dst_r, src_r, u32::from(order)).expect("pshufd");
+                self.store_fp_def(dst, def_pt, 0);
+            }
+            X64Inst::Pextrrri { lanes, dst, src, lane } => {
+                let src_r = self.load_fp_use(src, use_pt, 0);
+                let dst_p = self.prepare_def_preg(dst, def_pt, 0);
+                let lane = u32::from(lane);
+                let (d32, d64) = (to_ice_reg32(dst_p), to_ice_reg(dst_p));
+                match lanes {
+                    ScalarType::I8 => self.asm.pextrb(d32, src_r, lane).expect("pextrb"),
+                    ScalarType::I16 => self.asm.pextrw(d32, src_r, lane).expect("pextrw"),
+                    ScalarType::I32 | ScalarType::F32 => {
+                        self.asm.pextrd(d32, src_r, lane).expect("pextrd");
+                    }
+                    ScalarType::I64 | ScalarType::F64 | ScalarType::Ptr => {
+                        self.asm.pextrq(d64, src_r, lane).expect("pextrq");
+                    }
+                }
+                self.store_def(dst, def_pt, 0);
+            }
+
             // ---- Atomic RMW. ----
             X64Inst::LockXadd64mr { dst, src } => {
                 // xadd rewrites `src` in place with [mem]'s old value.
@@ -1308,7 +1802,7 @@ impl<'i> FnMCWriter<'i> {
         if let AllocatedSlot::Stack(slot) = self.slot_of(vreg, pt) {
             let s = to_ice_reg8(self.ra_cfg.scratch_regs[scratch_idx]);
             self.asm
-                .mov(rbp + i64::from(Self::slot_offset(slot)), s)
+                .mov(self.frame_mem(FrameRef::Spill(slot)), s)
                 .expect("mov-store byte to slot");
         }
     }
@@ -1333,7 +1827,8 @@ impl<'i> FnMCWriter<'i> {
                 {
                     return;
                 }
-                // Route by class: FP Copy → movsd, int Copy → mov.
+                // Route by class: FP Copy → movsd (movaps for a vector),
+                // int Copy → mov.
                 debug_assert_eq!(
                     self.func.vreg_type(dst).is_fp_or_vector(),
                     self.func.vreg_type(src).is_fp_or_vector(),
@@ -1345,15 +1840,13 @@ impl<'i> FnMCWriter<'i> {
                     match dst_slot {
                         AllocatedSlot::Reg(r) => {
                             assert!(is_xmm(r), "FP copy landed in GPR {r}");
-                            self.asm
-                                .movsd_2(to_ice_xmm(r), src_r)
-                                .expect("copy: movsd rr");
-                        }
-                        AllocatedSlot::Stack(slot) => {
-                            self.asm
-                                .movsd_2(rbp + i64::from(Self::slot_offset(slot)), src_r)
-                                .expect("copy: movsd slot, r");
+                            if matches!(self.func.vreg_type(dst), Type::V128(_)) {
+                                self.asm.movaps(to_ice_xmm(r), src_r).expect("copy: movaps rr");
+                            } else {
+                                self.asm.movsd_2(to_ice_xmm(r), src_r).expect("copy: movsd rr");
+                            }
                         }
+                        AllocatedSlot::Stack(slot) => self.store_xmm_slot(slot, src_r),
                     }
                 } else {
                     let src_r = self.load_use(src, use_pt, 1);
@@ -1364,7 +1857,7 @@ impl<'i> FnMCWriter<'i> {
                         }
                         AllocatedSlot::Stack(slot) => {
                             self.asm
-                                .mov(rbp + i64::from(Self::slot_offset(slot)), src_r)
+                                .mov(self.frame_mem(FrameRef::Spill(slot)), src_r)
                                 .expect("copy: mov slot, r");
                         }
                     }
@@ -1379,17 +1872,48 @@ impl<'i> FnMCWriter<'i> {
             PseudoInstruction::CallPseudo { .. } => {
                 panic!("CallPseudo should have been lowered to a target CALL before emission");
             }
+            PseudoInstruction::Switch { .. } => {
+                panic!("Switch should have been lowered by lower_switches before emission");
+            }
+            PseudoInstruction::Intrinsic { .. } => {
+                panic!("Intrinsic should have been lowered by lower_intrinsics before emission");
+            }
+            PseudoInstruction::RawBytes { id } => {
+                // ABI lowering already pinned the operands to their pregs.
+                stat!("emit", "raw_bytes", "raw machine-code sequences emitted");
+                let bytes = &self.func.raw_bytes_operands(id).bytes;
+                self.asm.db(bytes).expect("db raw bytes");
+            }
             PseudoInstruction::StackAlloc { dst, .. } => {
-                let disp = *self.alloca_offsets.get(&dst).unwrap_or_else(|| {
-                    panic!("StackAlloc for vreg {dst} has no computed frame offset")
-                });
+                let region = self.frame_mem(FrameRef::StackAlloc(dst));
                 let dst_preg = self.prepare_def_preg(dst, def_pt, 0);
                 let dst_r = to_ice_reg(dst_preg);
                 self.asm
-                    .lea(dst_r, rbp + i64::from(disp))
+                    .lea(dst_r, region)
                     .expect("lea rbp-rel for stack alloca");
                 self.store_def(dst, def_pt, 0);
             }
+            PseudoInstruction::Safepoint => {
+                let mut refs: Vec<ValueLocation> = self.safepoint_refs[&use_pt]
+                    .iter()
+                    .filter_map(|&v| self.ra_res.at(v, use_pt))
+                    .map(|slot| match slot {
+                        AllocatedSlot::Reg(p) => ValueLocation::Reg(p),
+                        AllocatedSlot::Stack(s) => ValueLocation::Frame(self.frame.spill_offset(s)),
+                    })
+                    .collect();
+                refs.sort_unstable_by_key(|l| match *l {
+                    ValueLocation::Reg(p) => (0, p as i32),
+                    ValueLocation::Frame(off) => (1, off),
+                });
+                refs.dedup();
+                self.stack_map_sites.push((self.asm.instructions().len(), refs));
+            }
+            PseudoInstruction::Trap { code } => {
+                self.trap_sites.push((self.asm.instructions().len(), code));
+                self.asm.ud2().expect("ud2");
+            }
+            PseudoInstruction::PatchPoint { len, kind } => self.emit_patch_sled(len, kind),
             PseudoInstruction::FrameSetup | PseudoInstruction::FrameDestroy => {
                 panic!("Frame markers should have been replaced by prologue/epilogue sequences");
             }
@@ -1411,50 +1935,82 @@ impl<'i> FnMCWriter<'i> {
         }
     }
 
+    /// # Panics
+    /// If the code doesn't encode; see `emit_fn_with_relocs`.
     pub fn emit_fn(&mut self) -> Vec<u8> {
-        self.emit_fn_with_relocs(&[]).bytes
+        self.emit_fn_with_relocs().expect("function encodes").bytes
     }
 
-    /// Full emission path that surfaces call-site relocations so the
-    /// loader can patch the placeholder immediate in each call-target
-    /// `Mov64ri`. Pass `call_sites` from the ABI lowering pass —
-    /// their `addr_vreg` fields mark which `Mov64ri` destinations we
-    /// need to track by iced instruction index, and whose final byte
-    /// offset we compute via `CodeAssemblerResult::new_instruction_offsets`.
-    pub fn emit_fn_with_relocs(
-        &mut self,
-        call_sites: &[crate::codegen::passes::CallSite],
-    ) -> EmittedFunc {
+    /// Full emission path that surfaces symbol relocations so the loader
+    /// can patch the placeholder immediate of each `Mov64rsym`.
+    ///
+    /// # Errors
+    /// `Encoding` if the assembled instructions don't encode.
+    pub fn emit_fn_with_relocs(&mut self) -> Result<EmittedFunc, CodegenError> {
+        enter_span!("emit", func = self.func.name());
         self.check_scratch_budget();
-        self.emit_prologue();
-
-        // Register tracked addr vregs up front.
-        for cs in call_sites {
-            if !cs.symbol.is_empty() {
-                self.mark_call_target(cs.addr_vreg);
-            }
+        // The pad must be the first instruction at the function's address,
+        // ahead of a patchable entry sled.
+        if self.endbr {
+            self.asm.endbr64().expect("endbr64");
+        }
+        if let Some(len) = self.func.attrs().patchable_entry {
+            self.emit_patch_sled(len, PatchKind::Entry);
+        }
+        if self.frame_pointer == FramePointer::Omit {
+            self.frame.omit_frame_pointer();
+        }
+        if self.red_zone && !self.retpoline_jumps() {
+            self.frame.use_red_zone();
         }
+        self.emit_prologue();
 
+        let pads = self.landing_pads();
         let mut labels: Vec<CodeLabel> = (0..self.func.blocks_count())
             .map(|_| self.asm.create_label())
             .collect();
 
+        // iced index of each IR instruction's first machine instruction,
+        // in layout order, for the value-location map.
+        let mut inst_starts: Vec<usize> = Vec::with_capacity(self.layout.total_insts() as usize + 1);
+        // iced index of each block's first machine instruction.
+        let mut block_starts: Vec<usize> = vec![0; self.func.blocks_count()];
         for (block, block_data) in self.func.blocks_iter() {
             self.asm
                 .set_label(&mut labels[block.index()])
                 .expect("set_label");
+            let block_start = self.asm.instructions().len();
+            block_starts[block.index()] = block_start;
+            if pads.contains(&block) {
+                stat!("emit", "landing_pads", "endbr64 landing pads at indirect-branch targets");
+                self.asm.endbr64().expect("endbr64");
+            }
             for (idx, instr) in block_data.iter().enumerate() {
                 let i = idx as u32;
                 let use_pt = self.layout.use_pt(block, i);
                 let def_pt = self.layout.def_pt(block, i);
+                inst_starts.push(self.asm.instructions().len());
 
                 // If the allocator split a vreg's life at this def_pt, save
                 // its preg to the stack slot BEFORE the inst executes. The
                 // inst (or Copy) then freely overwrites the preg for the
-                // new owner.
-                self.emit_pending_splits(def_pt);
+                // new owner. Reloads at the use point come first: they
+                // only ever start a block.
+                self.emit_pending_edits(use_pt, def_pt);
+                if self.elided_moves.contains(&use_pt) {
+                    continue;
+                }
 
+                // iced binds a label to the next instruction, so a block
+                // that would otherwise emit nothing keeps its `jmp`.
+                let falls_through = idx + 1 == block_data.len()
+                    && self.fallthrough.contains(&block)
+                    && self.asm.instructions().len() > block_start;
                 match instr {
+                    Instruction::Target(X64Inst::Jmp { .. }) if falls_through => {}
+                    Instruction::Target(X64Inst::CondJmp { cond, taken, .. }) if falls_through => {
+                        self.emit_jcc(*cond, labels[taken.index()]);
+                    }
                     Instruction::Target(x64_inst) => {
                         self.emit_inst(x64_inst, use_pt, def_pt, &mut labels);
                     }
@@ -1463,39 +2019,211 @@ impl<'i> FnMCWriter<'i> {
             }
         }
 
+        inst_starts.push(self.asm.instructions().len());
+        self.emit_retpoline_thunks();
+        let pool = self.emit_constant_pool();
+        let tables = self.emit_jump_table_placeholders();
+
         use iced_x86::BlockEncoderOptions;
         let res = self
             .asm
-            .assemble_options(0, BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS)
-            .expect("assemble_options");
-
-        // Build relocations. For each call site whose addr_vreg was
-        // tracked, find its iced instruction offset and add 2 (REX +
-        // opcode byte) to reach the 8-byte immediate.
-        let mut relocations = Vec::with_capacity(call_sites.len());
-        for cs in call_sites {
-            if cs.symbol.is_empty() {
-                continue; // indirect call — no patch needed
-            }
-            let Some(&iced_idx) = self.call_target_insts.get(&cs.addr_vreg) else {
-                continue;
-            };
-            assert!(
-                iced_idx != usize::MAX,
-                "call-target Mov64ri for vreg {} was never emitted",
-                cs.addr_vreg
-            );
-            let inst_offset = res.inner.new_instruction_offsets[iced_idx] as usize;
-            relocations.push(EmittedCallReloc {
-                imm_offset: inst_offset + 2, // MOV r64,imm64 = REX(1)+opcode(1)+imm(8)
-                symbol: cs.symbol.clone(),
+            .assemble_options(0, BlockEncoderOptions::RETURN_NEW_INSTRUCTION_OFFSETS)?;
+        let code_len = res.inner.code_buffer.len() as u32;
+        let inst_offsets: Vec<u32> = inst_starts
+            .iter()
+            .map(|&i| {
+                res.inner
+                    .new_instruction_offsets
+                    .get(i)
+                    .copied()
+                    .unwrap_or(code_len)
+            })
+            .collect();
+        let value_locations =
+            ValueLocationMap::build(self.ra_res, &inst_offsets, |s| {
+                self.frame.resolve(FrameRef::Spill(s)).disp
             });
-        }
 
-        EmittedFunc {
-            bytes: res.inner.code_buffer,
+        // The 8-byte immediate sits past REX and the opcode byte.
+        let relocations: Vec<EmittedCallReloc> = self
+            .symbol_refs
+            .iter()
+            .map(|&(iced_idx, sym)| EmittedCallReloc {
+                imm_offset: res.inner.new_instruction_offsets[iced_idx] as usize + 2,
+                symbol: self.func.symbol_name(sym).to_string(),
+            })
+            .collect();
+        let stack_maps = std::mem::take(&mut self.stack_map_sites)
+            .into_iter()
+            .map(|(iced_idx, refs)| StackMap {
+                offset: res
+                    .inner
+                    .new_instruction_offsets
+                    .get(iced_idx)
+                    .copied()
+                    .unwrap_or(code_len),
+                refs,
+            })
+            .collect();
+        let patch_sites = self
+            .patch_sites
+            .iter()
+            .map(|&(iced_idx, len, kind)| PatchSite {
+                offset: res
+                    .inner
+                    .new_instruction_offsets
+                    .get(iced_idx)
+                    .copied()
+                    .unwrap_or(code_len),
+                len,
+                kind,
+            })
+            .collect();
+        let offset_of = |iced_idx: usize| {
+            res.inner.new_instruction_offsets.get(iced_idx).copied().unwrap_or(code_len)
+        };
+        let mut bytes = res.inner.code_buffer;
+        let jump_tables: Vec<JumpTableSite> = tables
+            .into_iter()
+            .map(|entries| {
+                let offset = offset_of(entries[0].0);
+                let targets: Vec<u32> =
+                    entries.iter().map(|&(_, b)| offset_of(block_starts[b.index()])).collect();
+                for (&(iced_idx, _), &t) in entries.iter().zip(&targets) {
+                    let at = offset_of(iced_idx) as usize;
+                    let rel = t.wrapping_sub(offset).to_le_bytes();
+                    bytes[at..at + 4].copy_from_slice(&rel);
+                }
+                JumpTableSite { offset, targets }
+            })
+            .collect();
+        let constants = pool
+            .into_iter()
+            .map(|(iced_idx, bits)| ConstantSite { offset: offset_of(iced_idx), bits })
+            .collect();
+        let traps = self
+            .trap_sites
+            .iter()
+            .map(|&(iced_idx, code)| TrapSite {
+                offset: res.inner.new_instruction_offsets[iced_idx],
+                code,
+            })
+            .collect();
+
+        debug_event!(bytes = bytes.len(), relocations = relocations.len(), "emitted");
+        stat!("emit", "functions", "functions emitted");
+        stat!("emit", "bytes", "bytes of machine code emitted", bytes.len());
+        Ok(EmittedFunc {
+            bytes,
             relocations,
+            traps,
+            stack_maps,
+            patch_sites,
+            jump_tables,
+            constants,
+            value_locations,
+        })
+    }
+
+    /// `call` or `jmp` through `target`, hardened per `with_speculation_hardening`.
+    fn emit_indirect(&mut self, target: AsmRegister64, call: bool) {
+        match self.hardening {
+            SpeculationHardening::Off => {}
+            SpeculationHardening::Lfence => self.asm.lfence().expect("lfence"),
+            SpeculationHardening::Retpoline => {
+                stat!("emit", "retpolines", "indirect branches through a retpoline");
+                let thunk = self.thunk_label(target);
+                if call {
+                    self.asm.call(thunk).expect("call thunk");
+                } else {
+                    self.asm.jmp(thunk).expect("jmp thunk");
+                }
+                return;
+            }
+        }
+        if call {
+            self.asm.call(target).expect("call r");
+        } else {
+            self.asm.jmp(target).expect("jmp r");
+        }
+    }
+
+    /// The label of `target`'s retpoline thunk, adding it on first use.
+    fn thunk_label(&mut self, target: AsmRegister64) -> CodeLabel {
+        if let Some(&(_, label)) = self.thunks.iter().find(|&&(r, _)| r == target) {
+            return label;
+        }
+        let label = self.asm.create_label();
+        self.thunks.push((target, label));
+        label
+    }
+
+    /// Lay out the retpoline thunk of each register `emit_indirect` used:
+    /// the inner `call` pushes a return address into a `pause; lfence`
+    /// capture loop, which is where the `ret` speculates to, then swaps
+    /// the real target in for it.
+    fn emit_retpoline_thunks(&mut self) {
+        for (target, mut label) in std::mem::take(&mut self.thunks) {
+            let (mut capture, mut setup) = (self.asm.create_label(), self.asm.create_label());
+            self.asm.set_label(&mut label).expect("set_label");
+            self.asm.call(setup).expect("call setup");
+            self.asm.set_label(&mut capture).expect("set_label");
+            self.asm.pause().expect("pause");
+            self.asm.lfence().expect("lfence");
+            self.asm.jmp(capture).expect("jmp capture");
+            self.asm.set_label(&mut setup).expect("set_label");
+            self.asm.mov(qword_ptr(rsp), target).expect("mov [rsp], target");
+            self.asm.ret().expect("ret");
+        }
+    }
+
+    /// The label of `bits`' constant-pool entry, adding it on first use.
+    fn constant_label(&mut self, bits: u64) -> CodeLabel {
+        if let Some(&(_, label)) = self.constants.iter().find(|&&(b, _)| b == bits) {
+            return label;
+        }
+        let label = self.asm.create_label();
+        self.constants.push((bits, label));
+        label
+    }
+
+    /// Lay out a `dq` per constant-pool entry at its label. Returns the
+    /// iced index of each with its bits. Entries are not aligned: `movsd`
+    /// accepts any address, and the pool is a handful of qwords.
+    fn emit_constant_pool(&mut self) -> Vec<(usize, u64)> {
+        let mut pool = Vec::with_capacity(self.constants.len());
+        for (bits, mut label) in std::mem::take(&mut self.constants) {
+            self.asm.set_label(&mut label).expect("set_label");
+            pool.push((self.asm.instructions().len(), bits));
+            self.asm.dq(&[bits]).expect("dq constant");
+        }
+        stat!("emit", "constants", "constant-pool entries emitted", pool.len());
+        pool
+    }
+
+    /// Lay out a zeroed `dd` per entry of every table `JmpTable` emission
+    /// asked for, each table at its label. Returns, per table, the iced
+    /// index of each entry with the block it must point at; the entries
+    /// are filled in once the code is assembled and block offsets known.
+    fn emit_jump_table_placeholders(&mut self) -> Vec<Vec<(usize, Block)>> {
+        let mut tables = Vec::with_capacity(self.jump_tables.len());
+        for (id, mut label) in std::mem::take(&mut self.jump_tables) {
+            self.asm.set_label(&mut label).expect("set_label");
+            let entries: Vec<(usize, Block)> = self
+                .func
+                .jump_table(id)
+                .dense_targets()
+                .into_iter()
+                .map(|b| {
+                    let idx = self.asm.instructions().len();
+                    self.asm.dd(&[0]).expect("dd table entry");
+                    (idx, b)
+                })
+                .collect();
+            stat!("emit", "jump_table_entries", "jump-table entries emitted", entries.len());
+            tables.push(entries);
         }
+        tables
     }
 }
 
@@ -1557,7 +2285,7 @@ mod tests {
     use crate::codegen::passes::AbiLowering;
     use crate::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocator};
     use crate::codegen::tir::{Func, PseudoInstruction};
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     fn test_ra_config(reg_bind: HashMap<Reg, Reg>) -> RegAllocConfig {
         use crate::codegen::isa::x64::regs::{
@@ -1574,6 +2302,10 @@ mod tests {
             ],
             scratch_fp_regs: vec![XMM14, XMM15],
             reg_bind,
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         }
     }
 
@@ -1587,7 +2319,7 @@ mod tests {
             bd.push_pseudo_inst(PseudoInstruction::Arg { dst: a, idx: 0 });
             bd.push_pseudo_inst(PseudoInstruction::Return { src: a });
         }
-        let abi = SysVAmd64Lowering.lower(&mut func);
+        let abi = SysVAmd64Lowering.lower(&mut func).expect("lowers");
         let cfg = CFG::compute(&func).unwrap();
         let cfg_cfg = test_ra_config(abi.reg_bind);
         let res = LinearScan::allocate(&func, &cfg, &cfg_cfg);
@@ -1598,6 +2330,33 @@ mod tests {
         assert_eq!(*bytes.last().unwrap(), 0xC3); // ret
     }
 
+    #[test]
+    fn naked_function_has_no_prologue_or_epilogue() {
+        let mut func = Func::<X64Inst>::new("identity".to_string());
+        let b = func.add_empty_block();
+        let a = func.new_vreg();
+        {
+            let bd = func.get_block_data_mut(b);
+            bd.push_pseudo_inst(PseudoInstruction::Arg { dst: a, idx: 0 });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: a });
+        }
+        func.attrs_mut().naked = true;
+        let bytes = emit_with_binds(func, &[]);
+        // mov rax, rdi; ret
+        assert_eq!(bytes, [0x48, 0x89, 0xF8, 0xC3]);
+    }
+
+    #[test]
+    fn noreturn_function_saves_no_callee_saved_registers() {
+        let mut func = Func::<X64Inst>::new("trap".to_string());
+        let b = func.add_empty_block();
+        func.get_block_data_mut(b).push_target_inst(X64Inst::Ud2);
+        func.attrs_mut().noreturn = true;
+        let bytes = emit_with_binds(func, &[]);
+        // push rbp; mov rbp, rsp; ud2 — no scratch pushes.
+        assert_eq!(bytes, [0x55, 0x48, 0x89, 0xE5, 0x0F, 0x0B]);
+    }
+
     #[test]
     fn scratch_index_out_of_range_panics_with_clear_message() {
         use crate::codegen::regalloc::RegAllocResult;
@@ -1611,12 +2370,16 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind: HashMap::new(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         };
         let empty_ra = RegAllocResult {
             assignments: SecondaryMap::new(0),
             frame_layout: Vec::new(),
             frame_size: 0,
-            split_moves: Vec::new(),
+            edits: Vec::new(),
         };
         let w = FnMCWriter::new(&func, &empty_cfg, &empty_ra);
         let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| w.scratch(0)));
@@ -1641,7 +2404,7 @@ mod tests {
         mut func: Func<X64Inst>,
         extra_binds: &[(Reg, Reg)],
     ) -> Vec<u8> {
-        let abi = SysVAmd64Lowering.lower(&mut func);
+        let abi = SysVAmd64Lowering.lower(&mut func).expect("lowers");
         let mut reg_bind = abi.reg_bind;
         for (v, p) in extra_binds {
             reg_bind.insert(*v, *p);
@@ -2113,7 +2876,7 @@ mod tests {
             assignments: SecondaryMap::new(0),
             frame_layout: Vec::new(),
             frame_size: 0,
-            split_moves: Vec::new(),
+            edits: Vec::new(),
         };
         let mut w = FnMCWriter::new(&func, &ra_cfg, &res);
         let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
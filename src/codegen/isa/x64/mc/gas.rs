@@ -0,0 +1,310 @@
+//! GNU assembler (`.s`) output for a compiled module.
+//!
+//! Prints what `write_object` would pack into an object as AT&T-syntax
+//! source `as` accepts: each function and data object under the
+//! `.section` directive for its section and flags, with its alignment,
+//! linkage and size. Branch targets become `.L` labels so the assembler
+//! is free to re-pick branch encodings, and jump tables list label
+//! differences so they follow; constant-pool entries are labelled
+//! `.quad`s their RIP-relative loads name; call-site `movabs` immediates and pointers
+//! inside data name their symbol.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use iced_x86::{
+    Decoder, DecoderOptions, FlowControl, Formatter, GasFormatter, Instruction, OpKind,
+    SymbolResolver, SymbolResult,
+};
+
+use crate::codegen::isa::x64::pipeline::CompiledCode;
+use crate::codegen::module::{DataContents, DataObject, Linkage, ModuleDecls, is_bss_section};
+use crate::codegen::tir::SectionFlags;
+
+/// The assembly source for `funcs` and `decls`' data objects.
+#[must_use]
+pub fn write_gas(funcs: &[CompiledCode], decls: &ModuleDecls) -> String {
+    let mut out = Vec::new();
+    write_gas_to(&mut out, funcs, decls).expect("writing to a Vec");
+    String::from_utf8(out).expect("assembly source is UTF-8")
+}
+
+/// `write_gas`, streamed into `w` a line at a time.
+pub fn write_gas_to(
+    w: &mut impl Write,
+    funcs: &[CompiledCode],
+    decls: &ModuleDecls,
+) -> io::Result<()> {
+    for code in funcs {
+        write_func(w, code, decls.linkage(&code.name))?;
+    }
+    for d in decls.data.iter().chain(funcs.iter().filter_map(|f| f.coverage.as_ref())) {
+        write_data(w, d)?;
+    }
+    if !funcs.is_empty() && funcs.iter().all(|f| f.endbr) {
+        let features = if funcs.iter().any(|f| f.retpolines) { 1 } else { 3 };
+        write!(w, "{CET_NOTE_HEAD}{features}\n\t.balign 8\n")?;
+    }
+    // Without this marker, linkers assume the object needs an executable
+    // stack.
+    w.write_all(b"\t.section .note.GNU-stack,\"\",@progbits\n")
+}
+
+/// The `.note.gnu.property` `write_object` adds for CET-compatible code,
+/// up to the `GNU_PROPERTY_X86_FEATURE_1_AND` bits: IBT (1), plus SHSTK
+/// (2) without retpolines.
+const CET_NOTE_HEAD: &str = "\t.section .note.gnu.property,\"a\"\n\t.balign 8\n\
+                             \t.long 4\n\t.long 16\n\t.long 5\n\t.string \"GNU\"\n\
+                             \t.long 0xc0000002\n\t.long 4\n\t.long ";
+
+fn section_directive(out: &mut impl Write, name: &str, flags: SectionFlags) -> io::Result<()> {
+    let kind = if is_bss_section(name) { "@nobits" } else { "@progbits" };
+    writeln!(out, "\t.section {name},\"{flags}\",{kind}")
+}
+
+fn write_func(out: &mut impl Write, code: &CompiledCode, linkage: Linkage) -> io::Result<()> {
+    let name = &code.name;
+    let align = code.align;
+    section_directive(out, code.attrs.section_name(), code.attrs.section_flags())?;
+    writeln!(out, "\t.balign {align}")?;
+    if linkage != Linkage::Local {
+        writeln!(out, "\t.globl {name}")?;
+    }
+    writeln!(out, "\t.type {name},@function\n{name}:")?;
+
+    let insts: Vec<Instruction> =
+        Decoder::with_ip(64, &code.bytes[..code.code_len()], 0, DecoderOptions::NONE)
+            .into_iter()
+            .collect();
+    let mut resolver = Symbols::default();
+    let label = |at: u32| format!(".L{name}_{at:x}");
+    for table in &code.jump_tables {
+        for &at in table.targets.iter().chain([&table.offset]) {
+            resolver.labels.insert(u64::from(at), label(at));
+        }
+    }
+    for c in &code.constants {
+        resolver.labels.insert(u64::from(c.offset), label(c.offset));
+    }
+    for inst in &insts {
+        // Direct calls only ever target a retpoline thunk in the function.
+        if matches!(
+            inst.flow_control(),
+            FlowControl::UnconditionalBranch | FlowControl::ConditionalBranch | FlowControl::Call
+        ) {
+            let target = inst.near_branch_target();
+            resolver.labels.insert(target, format!(".L{name}_{target:x}"));
+        }
+    }
+    for r in &code.relocations {
+        let inst = insts
+            .iter()
+            .find(|i| (i.ip()..i.ip() + i.len() as u64).contains(&(r.offset as u64)))
+            .expect("relocation inside an instruction");
+        resolver.relocs.insert(inst.ip(), r.symbol.clone());
+    }
+
+    let labels = resolver.labels.clone();
+    let mut formatter = GasFormatter::with_options(Some(Box::new(resolver)), None);
+    // `label(%rip)`: a bare label would be an absolute address.
+    formatter.options_mut().set_rip_relative_addresses(true);
+    let mut text = String::new();
+    for inst in &insts {
+        if let Some(label) = labels.get(&inst.ip()) {
+            writeln!(out, "{label}:")?;
+        }
+        text.clear();
+        formatter.format(inst, &mut text);
+        writeln!(out, "\t{text}")?;
+    }
+    for c in &code.constants {
+        writeln!(out, "{}:\n\t.quad {:#x}", label(c.offset), c.bits)?;
+    }
+    for table in &code.jump_tables {
+        let base = label(table.offset);
+        writeln!(out, "{base}:")?;
+        for &t in &table.targets {
+            writeln!(out, "\t.long {}-{base}", label(t))?;
+        }
+    }
+    writeln!(out, "\t.size {name}, .-{name}\n")
+}
+
+fn write_data(out: &mut impl Write, d: &DataObject) -> io::Result<()> {
+    let name = &d.name;
+    section_directive(out, d.section_name(), d.section_flags())?;
+    writeln!(
+        out,
+        "\t.balign {}\n\t.globl {name}\n\t.type {name},@object\n\t.size {name}, {}\n{name}:",
+        d.align,
+        d.size()
+    )?;
+    let bytes = match &d.contents {
+        DataContents::Zeroed(n) => return writeln!(out, "\t.zero {n}\n"),
+        DataContents::Bytes(b) => b,
+    };
+    let mut relocs: Vec<_> = d.relocs.iter().collect();
+    relocs.sort_by_key(|r| r.offset);
+    let mut pos = 0;
+    for r in relocs {
+        write_bytes(out, &bytes[pos..r.offset])?;
+        match r.addend {
+            0 => writeln!(out, "\t.quad {}", r.symbol),
+            a => writeln!(out, "\t.quad {}{a:+}", r.symbol),
+        }?;
+        pos = r.offset + 8;
+    }
+    write_bytes(out, &bytes[pos..])?;
+    writeln!(out)
+}
+
+/// `.byte` lines of up to 16 values each.
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    for chunk in bytes.chunks(16) {
+        let list: Vec<String> = chunk.iter().map(|b| format!("{b:#04x}")).collect();
+        writeln!(out, "\t.byte {}", list.join(","))?;
+    }
+    Ok(())
+}
+
+/// Names the formatter prints in place of branch targets and relocated
+/// immediates.
+#[derive(Default)]
+struct Symbols {
+    /// Branch target or jump table offset → local label.
+    labels: HashMap<u64, String>,
+    /// Offset of an instruction whose immediate is relocated → symbol.
+    relocs: HashMap<u64, String>,
+}
+
+impl SymbolResolver for Symbols {
+    fn symbol(
+        &mut self,
+        instruction: &Instruction,
+        _operand: u32,
+        instruction_operand: Option<u32>,
+        address: u64,
+        _address_size: u32,
+    ) -> Option<SymbolResult<'_>> {
+        let kind = instruction.op_kind(instruction_operand?);
+        let name = match kind {
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                self.labels.get(&address)?
+            }
+            // A `lea` of a jump table, rip-relative.
+            OpKind::Memory if instruction.is_ip_rel_memory_operand() => {
+                self.labels.get(&address)?
+            }
+            OpKind::Immediate64 => self.relocs.get(&instruction.ip())?,
+            _ => return None,
+        };
+        Some(SymbolResult::with_str(address, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::codegen::isa::x64::pipeline::compile_full;
+    use crate::codegen::module::DataReloc;
+
+    #[test]
+    fn module_prints_as_assembler_source() {
+        let mut b = FuncBuilder::new("pick");
+        let x = b.arg();
+        let y = b.arg();
+        let (t, e) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::L, x, y, t, e);
+        b.switch_to_block(t);
+        let r = b.call_sym("puts", &[x]);
+        b.ret(r);
+        b.switch_to_block(e);
+        b.ret(y);
+        let pick = compile_full(b.build());
+        let decls = ModuleDecls {
+            data: vec![
+                DataObject {
+                    relocs: vec![DataReloc {
+                        offset: 2,
+                        symbol: "pick".into(),
+                        addend: 4,
+                    }],
+                    writable: true,
+                    ..DataObject::bytes("table", vec![7; 12], 8)
+                },
+                DataObject::zeroed("buf", 64, 16),
+            ],
+            ..ModuleDecls::default()
+        };
+        let asm = write_gas(&[pick], &decls);
+
+        assert!(asm.contains("\t.section .text,\"ax\",@progbits\n\t.balign 16\n\t.globl pick\n"));
+        assert!(asm.contains("movabs $puts,%"), "{asm}");
+        let label = asm
+            .lines()
+            .find(|l| l.starts_with(".Lpick_"))
+            .expect("branch target labelled");
+        assert!(asm.contains(&format!(" {}", label.trim_end_matches(':'))), "{asm}");
+        assert!(asm.contains(
+            "table:\n\t.byte 0x07,0x07\n\t.quad pick+4\n\t.byte 0x07,0x07\n"
+        ));
+        assert!(asm.contains("\t.section .bss,\"aw\",@nobits\n\t.balign 16\n"));
+        assert!(asm.contains("buf:\n\t.zero 64\n"));
+        assert!(asm.ends_with("\t.section .note.GNU-stack,\"\",@progbits\n"));
+    }
+
+    #[test]
+    fn cet_code_gets_the_property_note() {
+        use crate::codegen::isa::Target;
+        use crate::codegen::isa::x64::pipeline::compile_function;
+        use crate::codegen::options::CodegenOptions;
+
+        let mut b = FuncBuilder::new("pad");
+        let x = b.arg();
+        b.ret(x);
+        let options = CodegenOptions { cet: true, ..CodegenOptions::default() };
+        let code = compile_function(b.build(), Target::X64SysV, &options);
+        let asm = write_gas(&[code], &ModuleDecls::default());
+        assert!(asm.contains("pad:\n\tendbr64\n"), "{asm}");
+        assert!(asm.contains(&format!("{CET_NOTE_HEAD}3\n")));
+        let mut b = FuncBuilder::new("plain");
+        let x = b.arg();
+        b.ret(x);
+        let plain = write_gas(&[compile_full(b.build())], &ModuleDecls::default());
+        assert!(!plain.contains(".note.gnu.property"));
+    }
+
+    #[test]
+    fn jump_table_prints_as_label_differences() {
+        let mut b = FuncBuilder::new("sw");
+        let x = b.arg();
+        let blocks: Vec<_> = (0..5).map(|_| b.new_block()).collect();
+        let cases: Vec<_> = (0..4).map(|i| (i as i64, blocks[i])).collect();
+        b.switch(x, &cases, blocks[4]);
+        for (i, &blk) in blocks.iter().enumerate() {
+            b.switch_to_block(blk);
+            let r = b.iconst64(i as i64);
+            b.ret(r);
+        }
+        let code = compile_full(b.build());
+        let table = code.jump_tables[0].offset;
+        let asm = write_gas(&[code], &ModuleDecls::default());
+        assert!(asm.contains(&format!("lea .Lsw_{table:x}(%rip),")), "{asm}");
+        let longs = asm.lines().filter(|l| l.ends_with(&format!("-.Lsw_{table:x}"))).count();
+        assert_eq!(longs, 4, "{asm}");
+    }
+
+    #[test]
+    fn pooled_constant_is_a_labelled_quad() {
+        let mut b = FuncBuilder::new("c");
+        let v = b.fconst_f64(1.5);
+        b.ret(v);
+        let code = compile_full(b.build());
+        let at = code.constants[0].offset;
+        let asm = write_gas(&[code], &ModuleDecls::default());
+        assert!(asm.contains(&format!("movsd .Lc_{at:x}(%rip),")), "{asm}");
+        assert!(asm.contains(&format!(".Lc_{at:x}:\n\t.quad 0x3ff8000000000000\n")), "{asm}");
+    }
+}
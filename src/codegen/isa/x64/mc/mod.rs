@@ -1 +1,3 @@
-﻿pub mod emit_mc;
\ No newline at end of file
+﻿pub mod disasm;
+pub mod emit_mc;
+pub mod gas;
\ No newline at end of file
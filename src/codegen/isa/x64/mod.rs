@@ -1,10 +1,26 @@
+pub mod alias;
 pub mod builder;
+pub mod cache;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod equiv;
+pub mod features;
+pub mod format;
+pub mod frame;
 pub mod inst;
+pub mod intrinsics;
 pub mod mc;
+pub mod multiversion;
+pub mod parser;
 pub mod passes;
 pub mod pipeline;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod regalloc_fuzz;
 pub mod regs;
+pub mod size;
+#[cfg(any(test, feature = "test-support"))]
+pub mod strategy;
 pub mod sysv;
+pub mod tir_macro;
 
 #[cfg(test)]
 mod fuzz;
@@ -0,0 +1,240 @@
+//! Multi-versioned functions: one function compiled once per CPU feature
+//! set, and a resolver that picks the version for the CPU it runs on.
+//!
+//! `resolver` builds the resolver as IR. It reads the CPU's features with
+//! `cpuid` (and `xgetbv`, for the OS half of AVX) into `CpuFeatures` bits
+//! and returns the address of the first version all of whose features are
+//! present. Versions are listed best first; the last is the portable
+//! fallback, returned whatever the CPU. Addresses are absolute, for
+//! versions already loaded, or symbols, relocated like any other
+//! reference, for an object file.
+//!
+//! `jit_multiversioned` does the whole job for JIT users: it compiles and
+//! loads every version, then runs the resolver once and keeps its pick.
+
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::builder::FuncBuilder;
+use crate::codegen::isa::x64::features::CpuFeatures;
+use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+use crate::codegen::isa::x64::pipeline::compile_function;
+use crate::codegen::jit::Module;
+use crate::codegen::options::CodegenOptions;
+use crate::codegen::tir::{Func, Reg};
+
+/// Where the resolver finds a version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VersionAddr {
+    Absolute(u64),
+    Symbol(String),
+}
+
+/// Bit `pos` of `word`, moved to `feature`'s bit.
+fn feature_bit(b: &mut FuncBuilder, word: Reg, pos: u8, feature: CpuFeatures) -> Reg {
+    let shifted = b.shr_imm(word, pos);
+    let one = b.iconst64(1);
+    let bit = b.and(shifted, one);
+    let to = u8::try_from(feature.bits().trailing_zeros()).expect("a feature bit below 32");
+    b.shl_imm(bit, to)
+}
+
+/// Emit code computing the running CPU's `CpuFeatures` bits, leaving the
+/// builder in the block after it.
+pub fn detect_features(b: &mut FuncBuilder) -> Reg {
+    let zero = b.iconst64(0);
+    let one = b.iconst64(1);
+    let max_leaf = b.intrinsic("x64.cpuid", &[zero, zero])[0];
+    let ecx = b.intrinsic("x64.cpuid", &[one, zero])[2];
+    let sse4_1 = feature_bit(b, ecx, 19, CpuFeatures::SSE4_1);
+    let popcnt = feature_bit(b, ecx, 23, CpuFeatures::POPCNT);
+    let base = b.or(sse4_1, popcnt);
+
+    // AVX needs the CPU's support (bit 28) and the OS's: `xgetbv` exists
+    // (OSXSAVE, bit 27) and saves both `xmm` and `ymm` state.
+    let (entry, os_check, avx_on, leaf7, done) =
+        (b.current_block(), b.new_block(), b.new_block(), b.new_block(), b.new_block());
+    let high = b.shr_imm(ecx, 27);
+    let three = b.iconst64(3);
+    let osxsave_avx = b.and(high, three);
+    b.branch_icmp(Cond::Z, osxsave_avx, three, os_check, done);
+
+    b.switch_to_block(os_check);
+    let xcr0 = b.intrinsic("x64.xgetbv", &[zero])[0];
+    let six = b.iconst64(6);
+    let ymm_state = b.and(xcr0, six);
+    b.branch_icmp(Cond::Z, ymm_state, six, avx_on, done);
+
+    b.switch_to_block(avx_on);
+    let avx = b.iconst64(i64::from(CpuFeatures::AVX.bits()));
+    let with_avx = b.or(base, avx);
+    let seven = b.iconst64(7);
+    b.branch_icmp(Cond::GE, max_leaf, seven, leaf7, done);
+
+    b.switch_to_block(leaf7);
+    let ebx = b.intrinsic("x64.cpuid", &[seven, zero])[1];
+    let avx2 = feature_bit(b, ebx, 5, CpuFeatures::AVX2);
+    let with_avx2 = b.or(with_avx, avx2);
+    b.jmp(done);
+
+    b.switch_to_block(done);
+    b.phi(vec![(entry, base), (os_check, base), (avx_on, with_avx), (leaf7, with_avx2)])
+}
+
+/// A function `name` taking nothing and returning the address of the
+/// first of `versions` whose features the CPU has, or else the last.
+///
+/// # Panics
+/// If `versions` is empty.
+#[must_use]
+pub fn resolver(name: &str, versions: &[(CpuFeatures, VersionAddr)]) -> Func<X64Inst> {
+    let (fallback, preferred) = versions.split_last().expect("at least one version");
+    let mut b = FuncBuilder::new(name);
+    let detected = detect_features(&mut b);
+    let missing_from = b.not(detected);
+    let zero = b.iconst64(0);
+    let addr = |b: &mut FuncBuilder, at: &VersionAddr| match at {
+        VersionAddr::Absolute(a) => b.iconst64(a.cast_signed()),
+        VersionAddr::Symbol(s) => b.symbol_addr(s),
+    };
+    for (features, at) in preferred {
+        let (pick, next) = (b.new_block(), b.new_block());
+        let wanted = b.iconst64(i64::from(features.bits()));
+        let missing = b.and(wanted, missing_from);
+        b.branch_icmp(Cond::Z, missing, zero, pick, next);
+        b.switch_to_block(pick);
+        let a = addr(&mut b, at);
+        b.ret(a);
+        b.switch_to_block(next);
+    }
+    let a = addr(&mut b, &fallback.1);
+    b.ret(a);
+    b.build()
+}
+
+/// A JIT-loaded function in several versions, one of them chosen for
+/// this CPU.
+pub struct MultiVersioned {
+    versions: Vec<(CpuFeatures, Module)>,
+    chosen: usize,
+}
+
+impl MultiVersioned {
+    /// The features of the chosen version.
+    #[must_use]
+    pub fn chosen(&self) -> CpuFeatures {
+        self.versions[self.chosen].0
+    }
+
+    /// Every version with its features, in the order compiled.
+    pub fn versions(&self) -> impl Iterator<Item = (CpuFeatures, &Module)> {
+        self.versions.iter().map(|(f, m)| (*f, m))
+    }
+
+    /// The chosen version's entry point.
+    ///
+    /// # Safety
+    /// As `Module::entry`: `F` must match the function's signature.
+    #[must_use]
+    pub unsafe fn entry<F: Copy>(&self) -> F {
+        // SAFETY: delegated to the caller.
+        unsafe { self.versions[self.chosen].1.entry() }
+    }
+}
+
+/// Compile `build(features)` under each of `feature_sets` (best first,
+/// the last a fallback every target CPU supports), load them, and pick
+/// one with a resolver run on this CPU.
+///
+/// # Errors
+/// Propagates `io::Error` from loading any version or the resolver.
+///
+/// # Panics
+/// If `feature_sets` is empty or a version fails to compile, e.g. with
+/// an instruction its features don't allow.
+pub fn jit_multiversioned(
+    build: impl Fn(CpuFeatures) -> Func<X64Inst>,
+    feature_sets: &[CpuFeatures],
+    options: &CodegenOptions,
+) -> std::io::Result<MultiVersioned> {
+    let mut versions = Vec::with_capacity(feature_sets.len());
+    for &features in feature_sets {
+        let options = CodegenOptions { cpu_features: features, ..options.clone() };
+        let compiled = compile_function(build(features), Target::X64SysV, &options);
+        let module =
+            Module::load_with_relocs(&compiled.bytes, &compiled.relocations, &compiled.name)?;
+        versions.push((features, module));
+    }
+    let addrs: Vec<_> =
+        versions.iter().map(|(f, m)| (*f, VersionAddr::Absolute(m.code_ptr() as u64))).collect();
+    // The resolver itself must run anywhere.
+    let options = CodegenOptions { cpu_features: CpuFeatures::BASELINE, ..options.clone() };
+    let compiled = compile_function(resolver("resolve", &addrs), Target::X64SysV, &options);
+    let module = Module::load_with_relocs(&compiled.bytes, &compiled.relocations, "")?;
+    // SAFETY: `resolver` builds a SysV function taking nothing and
+    // returning an address.
+    let resolve: unsafe extern "sysv64" fn() -> u64 = unsafe { module.entry() };
+    let picked = unsafe { resolve() };
+    let chosen = versions
+        .iter()
+        .position(|(_, m)| m.code_ptr() as u64 == picked)
+        .expect("the resolver returns a version's address");
+    Ok(MultiVersioned { versions, chosen })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::pipeline::jit;
+
+    type Popcnt = unsafe extern "sysv64" fn(i64) -> i64;
+
+    #[test]
+    fn detected_features_match_the_host() {
+        let mut b = FuncBuilder::new("detect");
+        let f = detect_features(&mut b);
+        b.ret(f);
+        let m = jit(b.build()).unwrap();
+        let detect: unsafe extern "sysv64" fn() -> i64 = unsafe { m.entry() };
+        assert_eq!(unsafe { detect() }, i64::from(CpuFeatures::host().bits()));
+    }
+
+    #[test]
+    fn dispatch_picks_the_best_version_the_host_supports() {
+        let build = |_| {
+            let mut b = FuncBuilder::new("count");
+            let x = b.arg();
+            let n = b.intrinsic("x64.popcnt", &[x])[0];
+            b.ret(n);
+            b.build()
+        };
+        let sets = [CpuFeatures::V3, CpuFeatures::V2, CpuFeatures::BASELINE];
+        let mv = jit_multiversioned(build, &sets, &CodegenOptions::default()).unwrap();
+        let host = CpuFeatures::host();
+        let want = sets[..2].iter().copied().find(|&s| host.contains(s));
+        assert_eq!(mv.chosen(), want.unwrap_or(CpuFeatures::BASELINE));
+
+        let inputs = [0, 1, -1, 0x00F0_0F00_1234_5678, i64::MIN];
+        let f: Popcnt = unsafe { mv.entry() };
+        for x in inputs {
+            assert_eq!(unsafe { f(x) }, i64::from(x.count_ones()), "{x:#x}");
+        }
+        // The SWAR fallback runs anywhere.
+        let (_, baseline) = mv.versions().last().unwrap();
+        let f: Popcnt = unsafe { baseline.entry() };
+        for x in inputs {
+            assert_eq!(unsafe { f(x) }, i64::from(x.count_ones()), "{x:#x}");
+        }
+    }
+
+    #[test]
+    fn symbol_versions_become_relocations() {
+        let versions = [
+            (CpuFeatures::V3, VersionAddr::Symbol("f.avx2".into())),
+            (CpuFeatures::BASELINE, VersionAddr::Symbol("f.sse2".into())),
+        ];
+        let options = CodegenOptions { cpu_features: CpuFeatures::BASELINE, ..Default::default() };
+        let code = compile_function(resolver("f", &versions), Target::X64SysV, &options);
+        let mut symbols: Vec<_> = code.relocations.iter().map(|r| r.symbol.as_str()).collect();
+        symbols.sort_unstable();
+        assert_eq!(symbols, ["f.avx2", "f.sse2"]);
+    }
+}
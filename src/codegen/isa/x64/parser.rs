@@ -0,0 +1,1093 @@
+//! Text frontend for the x64 builder.
+//!
+//! Parses a small line-oriented language whose operations map one-to-one
+//! onto `FuncBuilder` methods, so a function can be written in a file
+//! instead of in Rust:
+//!
+//! ```text
+//! ; sum of 0..n
+//! func @sum(%n) {
+//! entry:
+//!     %zero = iconst 0
+//!     jmp head
+//! head:
+//!     %i = phi [entry, %zero], [body, %next]
+//!     %acc = phi [entry, %zero], [body, %acc2]
+//!     br ge %i, %n, exit, body
+//! body:
+//!     %acc2 = add %acc, %i
+//!     %one = iconst 1
+//!     %next = add %i, %one
+//!     jmp head
+//! exit:
+//!     ret %acc
+//! }
+//! ```
+//!
+//! Values are `%name`, blocks are bare labels, symbols are `@name`, and
+//! `;` starts a comment. A label may carry a `likely` or `unlikely` hint
+//! (`slow: unlikely`), and so may a select (`select.unlikely l ...`).
+//! A switch lists its default, then its cases (`switch %x, other, 0: a, 7: b`).
+//! Intrinsics are called by name (`%n = intrinsic x64.popcnt(%x)`).
+//! Arguments may carry a type (`%x: f64`, `%v: v128<i32>`), and function attributes follow
+//! the argument list (`func @f(%x) cold align(32) {`; see `parse_attrs`).
+//! The first label names the entry block. Phi operands may refer to values
+//! defined later; every other operand must already be defined.
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::codegen::isa::x64::builder::FuncBuilder;
+use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+use crate::codegen::tir::{
+    Block, BlockHint, Func, FuncAttrs, Inst, PhiId, Reg, ScalarType, SectionFlags, TrapCode,
+    Type,
+};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("line {line}: {msg}")]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: String,
+}
+
+fn err<T>(line: usize, msg: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError {
+        line,
+        msg: msg.into(),
+    })
+}
+
+/// Parse every `func` in `src`.
+pub fn parse_module(src: &str) -> Result<Vec<Func<X64Inst>>, ParseError> {
+    let lines: Vec<(usize, &str)> = src
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split(';').next().unwrap_or("").trim()))
+        .filter(|(_, l)| !l.is_empty())
+        .collect();
+    let mut funcs = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (line, header) = lines[i];
+        let end = lines[i..]
+            .iter()
+            .position(|&(_, l)| l == "}")
+            .map(|p| i + p)
+            .ok_or(ParseError {
+                line,
+                msg: "function is missing its closing `}`".into(),
+            })?;
+        funcs.push(parse_func(line, header, &lines[i + 1..end])?);
+        i = end + 1;
+    }
+    Ok(funcs)
+}
+
+/// Parse a module holding exactly one function.
+pub fn parse_func_text(src: &str) -> Result<Func<X64Inst>, ParseError> {
+    let mut funcs = parse_module(src)?;
+    if funcs.len() != 1 {
+        return err(1, format!("expected one function, found {}", funcs.len()));
+    }
+    Ok(funcs.pop().expect("length checked above"))
+}
+
+fn parse_func(
+    line: usize,
+    header: &str,
+    body: &[(usize, &str)],
+) -> Result<Func<X64Inst>, ParseError> {
+    let Some(rest) = header
+        .strip_prefix("func @")
+        .and_then(|r| r.strip_suffix('{'))
+    else {
+        return err(
+            line,
+            format!("expected `func @name(...) {{`, found `{header}`"),
+        );
+    };
+    let Some((name, (args, attrs))) = rest
+        .split_once('(')
+        .and_then(|(name, r)| Some((name, r.split_once(')')?)))
+    else {
+        return err(line, "malformed argument list");
+    };
+    let attrs = parse_attrs(line, attrs)?;
+
+    let mut p = FuncParser {
+        b: FuncBuilder::new(name),
+        values: HashMap::new(),
+        blocks: HashMap::new(),
+        phis: Vec::new(),
+        line,
+    };
+    for arg in split_operands(args) {
+        let (v, ty) = match arg.split_once(':') {
+            Some((v, ty)) => (v.trim(), parse_type(line, ty.trim())?),
+            None => (arg, Type::I64),
+        };
+        let r = p.b.arg_typed(ty);
+        p.define(v, r)?;
+    }
+
+    // Labels first, so branches can target blocks defined further down.
+    let mut first = true;
+    for &(line, text) in body {
+        if let Some((label, hint)) = parse_label(line, text)? {
+            let block = if first {
+                p.b.entry_block()
+            } else {
+                p.b.new_block()
+            };
+            if p.blocks.insert(label, block).is_some() {
+                return err(line, format!("label `{label}` defined twice"));
+            }
+            p.b.set_block_hint(block, hint);
+        }
+        first = false;
+    }
+
+    for &(line, text) in body {
+        p.line = line;
+        if let Some((label, _)) = parse_label(line, text)? {
+            p.b.switch_to_block(p.blocks[label]);
+        } else {
+            p.inst(text)?;
+        }
+    }
+    p.resolve_phis()?;
+    let mut func = p.b.build();
+    *func.attrs_mut() = attrs;
+    Ok(func)
+}
+
+/// A block label, `name:`, optionally followed by a `likely` or
+/// `unlikely` hint. `None` if `text` is an instruction.
+fn parse_label(line: usize, text: &str) -> Result<Option<(&str, Option<BlockHint>)>, ParseError> {
+    let Some((label, hint)) = text.split_once(':') else {
+        return Ok(None);
+    };
+    if label.is_empty() || label.contains(|c: char| c.is_whitespace() || c == '%' || c == '=') {
+        return Ok(None);
+    }
+    let hint = match hint.trim() {
+        "" => None,
+        "likely" => Some(BlockHint::Likely),
+        "unlikely" => Some(BlockHint::Unlikely),
+        other => return err(line, format!("unknown block hint `{other}`")),
+    };
+    Ok(Some((label, hint)))
+}
+
+/// Attributes between the argument list and `{`: `cold`, `noreturn`,
+/// `naked`, `align(N)`, `patchable(N)` and `section("name")`.
+fn parse_attrs(line: usize, s: &str) -> Result<FuncAttrs, ParseError> {
+    let mut attrs = FuncAttrs::default();
+    for word in s.split_whitespace() {
+        match word {
+            "cold" => attrs.cold = true,
+            "noreturn" => attrs.noreturn = true,
+            "naked" => attrs.naked = true,
+            _ => {
+                if let Some(n) = word.strip_prefix("align(").and_then(|r| r.strip_suffix(')')) {
+                    match n.parse::<u32>() {
+                        Ok(a) if a.is_power_of_two() => attrs.align = Some(a),
+                        _ => return err(line, format!("alignment `{n}` is not a power of two")),
+                    }
+                } else if let Some(n) =
+                    word.strip_prefix("patchable(").and_then(|r| r.strip_suffix(')'))
+                {
+                    match n.parse::<u32>() {
+                        Ok(n) => attrs.patchable_entry = Some(n),
+                        Err(_) => return err(line, format!("bad patchable size `{n}`")),
+                    }
+                } else if let Some(inner) = word
+                    .strip_prefix("section(\"")
+                    .and_then(|r| r.strip_suffix("\")"))
+                {
+                    // `section("name")` or `section("name","flags")`.
+                    let (name, flags) = match inner.split_once("\",\"") {
+                        Some((name, flags)) => match SectionFlags::parse(flags) {
+                            Some(f) => (name, Some(f)),
+                            None => return err(line, format!("bad section flags `{flags}`")),
+                        },
+                        None => (inner, None),
+                    };
+                    attrs.section = Some(name.to_string());
+                    attrs.section_flags = flags;
+                } else {
+                    return err(line, format!("unknown function attribute `{word}`"));
+                }
+            }
+        }
+    }
+    Ok(attrs)
+}
+
+fn parse_type(line: usize, s: &str) -> Result<Type, ParseError> {
+    if let Some(lanes) = s.strip_prefix("v128<").and_then(|l| l.strip_suffix('>')) {
+        return Ok(Type::V128(parse_lanes(line, lanes)?));
+    }
+    Ok(match s {
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "ptr" => Type::Ptr,
+        "ref" => Type::Ref,
+        _ => return err(line, format!("unknown type `{s}`")),
+    })
+}
+
+fn parse_lanes(line: usize, s: &str) -> Result<ScalarType, ParseError> {
+    Ok(match s {
+        "i8" => ScalarType::I8,
+        "i16" => ScalarType::I16,
+        "i32" => ScalarType::I32,
+        "i64" => ScalarType::I64,
+        "f32" => ScalarType::F32,
+        "f64" => ScalarType::F64,
+        _ => return err(line, format!("unknown lane type `{s}`")),
+    })
+}
+
+fn parse_cond(line: usize, s: &str) -> Result<Cond, ParseError> {
+    Ok(match s {
+        "z" => Cond::Z,
+        "nz" => Cond::NZ,
+        "l" => Cond::L,
+        "le" => Cond::LE,
+        "g" => Cond::G,
+        "ge" => Cond::GE,
+        "b" => Cond::B,
+        "be" => Cond::BE,
+        "a" => Cond::A,
+        "ae" => Cond::AE,
+        _ => return err(line, format!("unknown condition `{s}`")),
+    })
+}
+
+/// Comma-separated operands, ignoring commas nested in `(...)` / `[...]`.
+fn split_operands(s: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                out.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() || !out.is_empty() {
+        out.push(last);
+    }
+    out
+}
+
+/// The register class an op needs an operand in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Int,
+    F32,
+    F64,
+    Vector,
+}
+
+impl Class {
+    fn of(ty: Type) -> Option<Self> {
+        match ty {
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::Ptr | Type::Ref => Some(Self::Int),
+            Type::F32 => Some(Self::F32),
+            Type::F64 => Some(Self::F64),
+            Type::V128(_) | Type::V256(_) | Type::V512(_) => Some(Self::Vector),
+            Type::Agg(_) => None,
+        }
+    }
+
+    /// `.f32` and `.f64` ops take floats, the rest integers.
+    fn of_op(op: &str) -> Self {
+        match op.rsplit_once('.') {
+            Some((_, "f32")) => Self::F32,
+            Some((_, "f64")) => Self::F64,
+            _ => Self::Int,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Int => "an integer",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::Vector => "a vector",
+        }
+    }
+}
+
+/// A phi whose incoming list is filled in once every value is defined.
+type PendingPhi<'a> = (usize, PhiId, Vec<(&'a str, &'a str)>);
+
+struct FuncParser<'a> {
+    b: FuncBuilder,
+    values: HashMap<&'a str, Reg>,
+    blocks: HashMap<&'a str, Block>,
+    phis: Vec<PendingPhi<'a>>,
+    line: usize,
+}
+
+impl<'a> FuncParser<'a> {
+    fn define(&mut self, name: &'a str, r: Reg) -> Result<(), ParseError> {
+        if !name.starts_with('%') {
+            return err(self.line, format!("expected a `%value`, found `{name}`"));
+        }
+        if self.values.insert(name, r).is_some() {
+            return err(self.line, format!("value `{name}` defined twice"));
+        }
+        Ok(())
+    }
+
+    fn value(&self, name: &str) -> Result<Reg, ParseError> {
+        match self.values.get(name) {
+            Some(&r) => Ok(r),
+            None => err(self.line, format!("undefined value `{name}`")),
+        }
+    }
+
+    /// `value`, which must be in register class `want`.
+    fn value_in(&self, name: &str, want: Class) -> Result<Reg, ParseError> {
+        let r = self.value(name)?;
+        let ty = self.b.func().vreg_type(r);
+        if Class::of(ty) == Some(want) {
+            Ok(r)
+        } else {
+            err(self.line, format!("`{name}` is {ty}, not {}", want.name()))
+        }
+    }
+
+    fn block(&self, name: &str) -> Result<Block, ParseError> {
+        match self.blocks.get(name) {
+            Some(&b) => Ok(b),
+            None => err(self.line, format!("undefined label `{name}`")),
+        }
+    }
+
+    fn int<T: std::str::FromStr>(&self, s: &str) -> Result<T, ParseError> {
+        match s.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => err(self.line, format!("expected an integer, found `{s}`")),
+        }
+    }
+
+    fn float<T: std::str::FromStr>(&self, s: &str) -> Result<T, ParseError> {
+        match s.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => err(self.line, format!("expected a float, found `{s}`")),
+        }
+    }
+
+    fn inst(&mut self, text: &'a str) -> Result<(), ParseError> {
+        let (dst, rest) = match text.split_once('=') {
+            Some((d, r)) => (Some(d.trim()), r.trim()),
+            None => (None, text),
+        };
+        let (op, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let ops = split_operands(operands);
+        let line = self.line;
+        let arity = |n: usize| {
+            if ops.len() == n {
+                Ok(())
+            } else {
+                err(
+                    line,
+                    format!("`{op}` takes {n} operands, got {}", ops.len()),
+                )
+            }
+        };
+
+        let result = match op {
+            "iconst" => {
+                arity(1)?;
+                let imm = self.int(ops[0])?;
+                Some(self.b.iconst64(imm))
+            }
+            "fconst.f32" => {
+                arity(1)?;
+                let v = self.float(ops[0])?;
+                Some(self.b.fconst_f32(v))
+            }
+            "fconst.f64" => {
+                arity(1)?;
+                let v = self.float(ops[0])?;
+                Some(self.b.fconst_f64(v))
+            }
+            "add" | "sub" | "imul" | "and" | "or" | "xor" | "sdiv" | "srem" | "udiv" | "urem"
+            | "fadd.f32" | "fsub.f32" | "fmul.f32" | "fdiv.f32" | "fadd.f64" | "fsub.f64"
+            | "fmul.f64" | "fdiv.f64" => {
+                arity(2)?;
+                let class = Class::of_op(op);
+                let (a, c) = (self.value_in(ops[0], class)?, self.value_in(ops[1], class)?);
+                let b = &mut self.b;
+                Some(match op {
+                    "add" => b.add(a, c),
+                    "sub" => b.sub(a, c),
+                    "imul" => b.imul(a, c),
+                    "and" => b.and(a, c),
+                    "or" => b.or(a, c),
+                    "xor" => b.xor(a, c),
+                    "sdiv" => b.sdiv(a, c),
+                    "srem" => b.srem(a, c),
+                    "udiv" => b.udiv(a, c),
+                    "urem" => b.urem(a, c),
+                    "fadd.f32" => b.fadd_f32(a, c),
+                    "fsub.f32" => b.fsub_f32(a, c),
+                    "fmul.f32" => b.fmul_f32(a, c),
+                    "fdiv.f32" => b.fdiv_f32(a, c),
+                    "fadd.f64" => b.fadd_f64(a, c),
+                    "fsub.f64" => b.fsub_f64(a, c),
+                    "fmul.f64" => b.fmul_f64(a, c),
+                    _ => b.fdiv_f64(a, c),
+                })
+            }
+            "shl" | "shr" | "sar" => {
+                arity(2)?;
+                let a = self.value_in(ops[0], Class::Int)?;
+                Some(if ops[1].starts_with('%') {
+                    let c = self.value_in(ops[1], Class::Int)?;
+                    match op {
+                        "shl" => self.b.shl(a, c),
+                        "shr" => self.b.shr(a, c),
+                        _ => self.b.sar(a, c),
+                    }
+                } else {
+                    let imm = self.int(ops[1])?;
+                    match op {
+                        "shl" => self.b.shl_imm(a, imm),
+                        "shr" => self.b.shr_imm(a, imm),
+                        _ => self.b.sar_imm(a, imm),
+                    }
+                })
+            }
+            "not" | "neg" | "sext.i32" | "sext.i16" | "sext.i8" | "zext.i32" | "zext.i16"
+            | "zext.i8" | "trunc.i32" | "trunc.i16" | "trunc.i8" | "trunc.i1" => {
+                arity(1)?;
+                let a = self.value_in(ops[0], Class::Int)?;
+                let b = &mut self.b;
+                Some(match op {
+                    "not" => b.not(a),
+                    "neg" => b.neg(a),
+                    "sext.i32" => b.sext_i32_to_i64(a),
+                    "sext.i16" => b.sext_i16_to_i64(a),
+                    "sext.i8" => b.sext_i8_to_i64(a),
+                    "zext.i32" => b.zext_i32_to_i64(a),
+                    "zext.i16" => b.zext_i16_to_i64(a),
+                    "zext.i8" => b.zext_i8_to_i64(a),
+                    "trunc.i32" => b.trunc_to_i32(a),
+                    "trunc.i16" => b.trunc_to_i16(a),
+                    "trunc.i8" => b.trunc_to_i8(a),
+                    _ => b.trunc_to_i1(a),
+                })
+            }
+            "icmp" | "select" | "select.likely" | "select.unlikely" | "br" => {
+                let (cond, rest) = operands
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((operands, ""));
+                let cond = parse_cond(self.line, cond)?;
+                let ops = split_operands(rest);
+                let n = if op == "icmp" { 2 } else { 4 };
+                if ops.len() != n {
+                    return err(
+                        self.line,
+                        format!("`{op}` takes a condition and {n} operands"),
+                    );
+                }
+                let a = self.value_in(ops[0], Class::Int)?;
+                let c = self.value_in(ops[1], Class::Int)?;
+                match op {
+                    "icmp" => Some(self.b.icmp_to_i64(cond, a, c)),
+                    "br" => {
+                        let (t, f) = (self.block(ops[2])?, self.block(ops[3])?);
+                        self.b.branch_icmp(cond, a, c, t, f);
+                        None
+                    }
+                    _ => {
+                        let (t, f) = (self.value(ops[2])?, self.value(ops[3])?);
+                        let hint = match op {
+                            "select.likely" => Some(BlockHint::Likely),
+                            "select.unlikely" => Some(BlockHint::Unlikely),
+                            _ => None,
+                        };
+                        Some(self.b.select_hinted(cond, a, c, t, f, hint))
+                    }
+                }
+            }
+            "load.i64" | "load.i32" | "load.i16" | "load.i8" | "load.f32" | "load.f64" => {
+                arity(2)?;
+                let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                let b = &mut self.b;
+                Some(match op {
+                    "load.i64" => b.load_i64(base, disp),
+                    "load.i32" => b.load_i32(base, disp),
+                    "load.i16" => b.load_i16(base, disp),
+                    "load.i8" => b.load_i8(base, disp),
+                    "load.f32" => b.load_f32(base, disp),
+                    _ => b.load_f64(base, disp),
+                })
+            }
+            "store.i64" | "store.i32" | "store.i16" | "store.i8" | "store.f32" | "store.f64" => {
+                arity(3)?;
+                let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                let val = self.value_in(ops[2], Class::of_op(op))?;
+                let b = &mut self.b;
+                match op {
+                    "store.i64" => b.store_i64(base, disp, val),
+                    "store.i32" => b.store_i32(base, disp, val),
+                    "store.i16" => b.store_i16(base, disp, val),
+                    "store.i8" => b.store_i8(base, disp, val),
+                    "store.f32" => b.store_f32(base, disp, val),
+                    _ => b.store_f64(base, disp, val),
+                }
+                None
+            }
+            _ if op.starts_with("load.v128.") => {
+                arity(2)?;
+                let lanes = parse_lanes(line, &op["load.v128.".len()..])?;
+                let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                Some(self.b.load_v128(lanes, base, disp))
+            }
+            "store.v128" => {
+                arity(3)?;
+                let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                let val = self.value_in(ops[2], Class::Vector)?;
+                self.b.store_v128(base, disp, val);
+                None
+            }
+            "vadd" | "vmul" => {
+                arity(2)?;
+                let a = self.value_in(ops[0], Class::Vector)?;
+                let c = self.value_in(ops[1], Class::Vector)?;
+                Some(if op == "vadd" { self.b.vadd(a, c) } else { self.b.vmul(a, c) })
+            }
+            "shuffle" => {
+                let Some((&v, lanes)) = ops.split_first() else {
+                    return err(line, "`shuffle` needs a vector");
+                };
+                let v = self.value_in(v, Class::Vector)?;
+                let order = lanes.iter().map(|l| self.int(l)).collect::<Result<Vec<u8>, _>>()?;
+                Some(self.b.shuffle(v, &order))
+            }
+            "extractlane" => {
+                arity(2)?;
+                let (v, lane) = (self.value_in(ops[0], Class::Vector)?, self.int(ops[1])?);
+                Some(self.b.extract_lane(v, lane))
+            }
+            // A literal length (and, for `memset`, fill byte) picks the
+            // constant form, which unrolls short lengths.
+            "memcpy" => {
+                arity(3)?;
+                let dst = self.value_in(ops[0], Class::Int)?;
+                let src = self.value_in(ops[1], Class::Int)?;
+                if ops[2].starts_with('%') {
+                    let len = self.value_in(ops[2], Class::Int)?;
+                    self.b.memcpy(dst, src, len);
+                } else {
+                    let len = self.int(ops[2])?;
+                    self.b.memcpy_const(dst, src, len);
+                }
+                None
+            }
+            "memset" => {
+                arity(3)?;
+                let dst = self.value_in(ops[0], Class::Int)?;
+                match (ops[1].starts_with('%'), ops[2].starts_with('%')) {
+                    (false, false) => {
+                        let (byte, len) = (self.int(ops[1])?, self.int(ops[2])?);
+                        self.b.memset_const(dst, byte, len);
+                    }
+                    (true, true) => {
+                        let byte = self.value_in(ops[1], Class::Int)?;
+                        let len = self.value_in(ops[2], Class::Int)?;
+                        self.b.memset(dst, byte, len);
+                    }
+                    _ => return err(line, "`memset` takes a constant byte and length or neither"),
+                }
+                None
+            }
+            "stackalloc" => {
+                arity(2)?;
+                let (size, align) = (self.int(ops[0])?, self.int(ops[1])?);
+                Some(self.b.stack_alloc(size, align))
+            }
+            "gep" => match ops.len() {
+                2 => {
+                    let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                    Some(self.b.gep_const(base, disp))
+                }
+                4 => {
+                    let (base, index) =
+                        (self.value_in(ops[0], Class::Int)?, self.value_in(ops[1], Class::Int)?);
+                    let (scale, disp) = (self.int(ops[2])?, self.int(ops[3])?);
+                    Some(self.b.gep_indexed(base, index, scale, disp))
+                }
+                n => return err(self.line, format!("`gep` takes 2 or 4 operands, got {n}")),
+            },
+            "addr" => {
+                arity(1)?;
+                let Some(sym) = ops[0].strip_prefix('@') else {
+                    return err(self.line, "expected `addr @symbol`");
+                };
+                Some(self.b.symbol_addr(sym))
+            }
+            "atomic_add" => {
+                arity(3)?;
+                let (base, disp) = (self.value_in(ops[0], Class::Int)?, self.int(ops[1])?);
+                let delta = self.value_in(ops[2], Class::Int)?;
+                Some(self.b.atomic_fetch_add_i64(base, disp, delta))
+            }
+            "intrinsic" => {
+                let Some((name, args)) =
+                    operands.trim().strip_suffix(')').and_then(|c| c.split_once('('))
+                else {
+                    return err(self.line, "expected `intrinsic name(args)`");
+                };
+                let Some(decl) = X64Inst::intrinsics().iter().find(|d| d.name == name) else {
+                    return err(self.line, format!("unknown intrinsic `{name}`"));
+                };
+                let args = split_operands(args);
+                let arity = decl.params.len();
+                if args.len() != arity {
+                    return err(
+                        self.line,
+                        format!("`{name}` takes {arity} arguments, got {}", args.len()),
+                    );
+                }
+                let args = args
+                    .into_iter()
+                    .zip(decl.params)
+                    .map(|(a, &ty)| match Class::of(ty) {
+                        Some(class) => self.value_in(a, class),
+                        None => self.value(a),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.b.intrinsic(name, &args).first().copied()
+            }
+            "call" => {
+                // A direct call may end in ` patchable(N)`.
+                let (call, patch) = match operands.trim().rsplit_once(" patchable(") {
+                    Some((call, n)) => match n.strip_suffix(')').map(str::parse::<u32>) {
+                        Some(Ok(n)) => (call, Some(n)),
+                        _ => return err(self.line, format!("bad patchable size `{n}`")),
+                    },
+                    None => (operands.trim(), None),
+                };
+                let Some((callee, args)) = call
+                    .strip_suffix(')')
+                    .and_then(|c| c.split_once('('))
+                else {
+                    return err(
+                        self.line,
+                        "expected `call @symbol(args)` or `call %ptr(args)`",
+                    );
+                };
+                let args = split_operands(args)
+                    .into_iter()
+                    .map(|a| self.value(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Some(if let Some(sym) = callee.strip_prefix('@') {
+                    match patch {
+                        Some(n) => self.b.call_sym_patchable(sym, &args, n),
+                        None => self.b.call_sym(sym, &args),
+                    }
+                } else if patch.is_some() {
+                    return err(self.line, "only direct calls can be patchable");
+                } else {
+                    let ptr = self.value(callee)?;
+                    self.b.call_indirect(ptr, &args)
+                })
+            }
+            "phi" => {
+                let mut incoming = Vec::new();
+                for pair in &ops {
+                    let Some((label, v)) = pair
+                        .strip_prefix('[')
+                        .and_then(|p| p.strip_suffix(']'))
+                        .and_then(|p| p.split_once(','))
+                    else {
+                        return err(
+                            self.line,
+                            format!("expected `[label, %value]`, found `{pair}`"),
+                        );
+                    };
+                    incoming.push((label.trim(), v.trim()));
+                }
+                let (r, id) = self.b.phi_with_id(Vec::new());
+                self.phis.push((self.line, id, incoming));
+                Some(r)
+            }
+            "jmp" => {
+                arity(1)?;
+                let target = self.block(ops[0])?;
+                self.b.jmp(target);
+                None
+            }
+            "switch" => {
+                if ops.len() < 2 {
+                    return err(self.line, "expected `switch %index, default, value: label, ...`");
+                }
+                let (index, default) = (self.value(ops[0])?, self.block(ops[1])?);
+                let mut cases = Vec::new();
+                for case in &ops[2..] {
+                    let Some((v, label)) = case.split_once(':') else {
+                        return err(self.line, format!("expected `value: label`, found `{case}`"));
+                    };
+                    let v = self.int(v.trim())?;
+                    if cases.iter().any(|&(c, _)| c == v) {
+                        return err(self.line, format!("switch has two cases for {v}"));
+                    }
+                    cases.push((v, self.block(label.trim())?));
+                }
+                self.b.switch(index, &cases, default);
+                None
+            }
+            "ret" => {
+                arity(1)?;
+                let v = self.value(ops[0])?;
+                self.b.ret(v);
+                None
+            }
+            "unreachable" => {
+                arity(0)?;
+                self.b.unreachable();
+                None
+            }
+            "trap" => {
+                arity(1)?;
+                let Some(code) = TrapCode::parse(ops[0]) else {
+                    return err(self.line, format!("unknown trap code `{}`", ops[0]));
+                };
+                self.b.trap(code);
+                None
+            }
+            "safepoint" => {
+                arity(0)?;
+                self.b.safepoint();
+                None
+            }
+            "patch_point" => {
+                arity(1)?;
+                let len = self.int(ops[0])?;
+                self.b.patch_point(len);
+                None
+            }
+            "breakpoint" => {
+                arity(0)?;
+                self.b.breakpoint();
+                None
+            }
+            "mfence" => {
+                arity(0)?;
+                self.b.mfence();
+                None
+            }
+            _ => return err(self.line, format!("unknown operation `{op}`")),
+        };
+
+        match (dst, result) {
+            (Some(d), Some(r)) => self.define(d, r),
+            (None, _) => Ok(()),
+            (Some(_), None) => err(self.line, format!("`{op}` does not produce a value")),
+        }
+    }
+
+    fn resolve_phis(&mut self) -> Result<(), ParseError> {
+        for (line, id, incoming) in std::mem::take(&mut self.phis) {
+            self.line = line;
+            let incoming = incoming
+                .into_iter()
+                .map(|(label, v)| Ok((self.block(label)?, self.value(v)?)))
+                .collect::<Result<Vec<_>, ParseError>>()?;
+            self.b.set_phi_incoming(id, incoming);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::pipeline;
+    use crate::codegen::tir::{Instruction, PrintOptions, PseudoInstruction};
+
+    const SUM: &str = "
+        ; sum of 0..n
+        func @sum(%n) {
+        entry:
+            %zero = iconst 0
+            jmp head
+        head:
+            %i = phi [entry, %zero], [body, %next]
+            %acc = phi [entry, %zero], [body, %acc2]
+            br ge %i, %n, exit, body
+        body:
+            %acc2 = add %acc, %i
+            %one = iconst 1
+            %next = add %i, %one
+            jmp head
+        exit:
+            ret %acc
+        }
+    ";
+
+    #[test]
+    fn parsed_loop_compiles_and_runs() {
+        let func = parse_func_text(SUM).expect("parses");
+        assert_eq!(func.name(), "sum");
+        assert_eq!(func.blocks_count(), 4);
+        let module = pipeline::jit(func).expect("JIT load");
+        type F = unsafe extern "sysv64" fn(i64) -> i64;
+        // SAFETY: `sum` takes and returns one i64.
+        let f: F = unsafe { module.entry() };
+        assert_eq!(unsafe { f(10) }, 45);
+    }
+
+    #[test]
+    fn symbol_addresses_share_one_interned_id() {
+        let src = "func @f() {\n  %a = addr @g\n  %b = addr @g\n  %c = addr @h\n  ret %c\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let syms: Vec<_> = func
+            .get_block_data(entry)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Target(X64Inst::Mov64rsym { sym, .. }) => Some(*sym),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(syms[0], syms[1]);
+        assert_eq!(func.symbol_name(syms[1]), "g");
+        assert_eq!(func.symbol_name(syms[2]), "h");
+
+        let mut out = Vec::new();
+        func.write_to(&mut out, &PrintOptions { side_tables: true }).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("  ; @h"));
+    }
+
+    #[test]
+    fn errors_carry_the_offending_line() {
+        let src = "func @f(%a) {\n  %b = add %a, %c\n  ret %b\n}\n";
+        assert_eq!(
+            parse_module(src).expect_err("fails"),
+            ParseError {
+                line: 2,
+                msg: "undefined value `%c`".into()
+            }
+        );
+        let src = "func @f() {\n  %x = frob\n}\n";
+        assert_eq!(parse_module(src).expect_err("fails").line, 2);
+    }
+
+    #[test]
+    fn operands_must_be_in_the_op_register_class() {
+        let bad = [
+            ("func @f(%a) {\n  %b = fadd.f64 %a, %a\n  ret %b\n}\n", "`%a` is i64, not f64"),
+            ("func @f(%a: f64) {\n  %b = add %a, %a\n  ret %b\n}\n", "`%a` is f64, not an integer"),
+            (
+                "func @f(%a: f32) {\n  %b = load.i64 %a, 0\n  ret %b\n}\n",
+                "`%a` is f32, not an integer",
+            ),
+            ("func @f(%a) {\n  %b = vadd %a, %a\n  ret %b\n}\n", "`%a` is i64, not a vector"),
+        ];
+        for (src, msg) in bad {
+            assert_eq!(parse_module(src).expect_err(src), ParseError { line: 2, msg: msg.into() });
+        }
+        let src = "func @f(%a: f64, %p) {\n  store.f64 %p, 0, %a\n  ret %p\n}\n";
+        assert!(parse_module(src).is_ok());
+    }
+
+    #[test]
+    fn labels_carry_likelihood_hints() {
+        let src = "func @f(%a) {\nentry:\n  br z %a, %a, slow, fast\nslow: unlikely\n  \
+                   ret %a\nfast: likely\n  ret %a\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let hints: Vec<_> = func.blocks_iter().map(|(b, _)| func.block_hint(b)).collect();
+        assert_eq!(hints, [None, Some(BlockHint::Unlikely), Some(BlockHint::Likely)]);
+        assert!(func.to_string().contains("@1 ; unlikely\n"));
+        let src = "func @f(%a) {\nentry: hot\n  ret %a\n}\n";
+        assert_eq!(
+            parse_module(src).expect_err("fails").msg,
+            "unknown block hint `hot`"
+        );
+    }
+
+    #[test]
+    fn select_suffix_carries_a_hint() {
+        let src = "func @f(%a, %b) {\n  %r = select.likely g %a, %b, %a, %b\n  ret %r\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        assert!(func.get_block_data(entry).iter().any(|inst| matches!(
+            inst,
+            Instruction::Target(X64Inst::Select { cond: Cond::G, hint: Some(BlockHint::Likely), .. })
+        )));
+    }
+
+    #[test]
+    fn switch_lists_default_then_cases() {
+        let src = "func @f(%x) {\n  switch %x, d, 7: a, -1: b\na:\n  ret %x\nb:\n  ret %x\n\
+                   d:\n  ret %x\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let Some(Instruction::Pseudo(PseudoInstruction::Switch { table, .. })) =
+            func.get_block_data(entry).get_terminator()
+        else {
+            panic!("switch expected:\n{func}");
+        };
+        let values: Vec<i64> = func.jump_table(table).cases.iter().map(|c| c.0).collect();
+        assert_eq!(values, [-1, 7]);
+        let dup = "func @f(%x) {\n  switch %x, d, 1: d, 1: d\nd:\n  ret %x\n}\n";
+        assert_eq!(parse_module(dup).expect_err("fails").msg, "switch has two cases for 1");
+    }
+
+    #[test]
+    fn fconst_takes_a_float_literal() {
+        let src = "func @f() {\n  %z = fconst.f64 -0.0\n  %h = fconst.f32 0.5\n  ret %z\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let consts: Vec<X64Inst> = func
+            .get_block_data(entry)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Target(i @ (X64Inst::Fconst32 { .. } | X64Inst::Fconst64 { .. })) => {
+                    Some(*i)
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(consts[0], X64Inst::Fconst64 { bits, .. } if bits == 1 << 63));
+        assert!(matches!(consts[1], X64Inst::Fconst32 { bits: 0x3f00_0000, .. }));
+        let bad = "func @f() {\n  %z = fconst.f64 one\n  ret %z\n}\n";
+        assert_eq!(parse_module(bad).expect_err("fails").msg, "expected a float, found `one`");
+    }
+
+    #[test]
+    fn vector_ops_take_lane_types_and_indices() {
+        let src = "func @f(%p, %w: v128<f32>) {\n  %v = load.v128.i32 %p, 16\n  \
+                   %s = shuffle %v, 3, 2, 1, 0\n  %t = vadd %s, %v\n  store.v128 %p, 0, %t\n  \
+                   %x = extractlane %t, 1\n  ret %x\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let insts: Vec<X64Inst> = func
+            .get_block_data(entry)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Target(t) => Some(*t),
+                Instruction::Pseudo(_) => None,
+            })
+            .collect();
+        assert!(matches!(insts[1], X64Inst::Pshufdrri { order: 0x1b, .. }));
+        assert!(matches!(insts[4], X64Inst::Pextrrri { lanes: ScalarType::I32, lane: 1, .. }));
+        let bad = "func @f(%p) {\n  %v = load.v128.i128 %p, 0\n  ret %p\n}\n";
+        assert_eq!(parse_module(bad).expect_err("fails").msg, "unknown lane type `i128`");
+    }
+
+    #[test]
+    fn literal_lengths_pick_the_unrolled_memory_ops() {
+        let src = "func @f(%d, %s, %n) {\n  memcpy %d, %s, %n\n  memcpy %d, %s, 24\n  \
+                   memset %d, 0, 3\n  ret %n\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let insts: Vec<X64Inst> = func
+            .get_block_data(entry)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Target(t) => Some(*t),
+                Instruction::Pseudo(_) => None,
+            })
+            .collect();
+        assert!(matches!(insts[0], X64Inst::RepMovsb { .. }));
+        assert!(matches!(insts[1], X64Inst::Movdqurm { .. }));
+        assert!(matches!(insts[4], X64Inst::Mov64mr { .. }));
+        assert!(matches!(insts[6], X64Inst::Mov16mr { .. }));
+        assert!(matches!(insts[7], X64Inst::Mov8mr { .. }));
+        assert!(!insts.iter().skip(1).any(|t| matches!(t, X64Inst::RepStosb { .. })));
+        let bad = "func @f(%d, %n) {\n  memset %d, 0, %n\n  ret %n\n}\n";
+        assert_eq!(
+            parse_module(bad).expect_err("fails").msg,
+            "`memset` takes a constant byte and length or neither"
+        );
+    }
+
+    #[test]
+    fn intrinsics_are_called_by_name() {
+        let src = "func @f(%x) {\n  %n = intrinsic x64.popcnt(%x)\n  intrinsic x64.pause()\n  \
+                   ret %n\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        let entry = func.get_entry_block().expect("entry");
+        let names: Vec<_> = func
+            .get_block_data(entry)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Pseudo(PseudoInstruction::Intrinsic { id }) => {
+                    Some(func.intrinsic_operands(*id).decl.name)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, ["x64.popcnt", "x64.pause"]);
+        let bad = "func @f(%x) {\n  %n = intrinsic x64.popcnt(%x, %x)\n  ret %n\n}\n";
+        assert_eq!(
+            parse_module(bad).expect_err("fails").msg,
+            "`x64.popcnt` takes 1 arguments, got 2"
+        );
+        let bad = "func @f(%x) {\n  %n = intrinsic x64.nope(%x)\n  ret %n\n}\n";
+        assert_eq!(parse_module(bad).expect_err("fails").msg, "unknown intrinsic `x64.nope`");
+    }
+
+    #[test]
+    fn attributes_follow_the_argument_list() {
+        let src = "func @f(%a) cold noreturn align(32) patchable(16) section(\".text.f\") {\n  \
+                   unreachable\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        assert_eq!(
+            *func.attrs(),
+            FuncAttrs {
+                cold: true,
+                noreturn: true,
+                naked: false,
+                align: Some(32),
+                section: Some(".text.f".into()),
+                section_flags: None,
+                patchable_entry: Some(16),
+            }
+        );
+        assert!(
+            func.to_string()
+                .starts_with("f: ; cold noreturn align(32) patchable(16) section(\".text.f\")\n")
+        );
+        let src = "func @g() section(\"hooks\",\"axR\") {\n  unreachable\n}\n";
+        let func = parse_func_text(src).expect("parses");
+        assert_eq!(
+            func.attrs().section_flags,
+            Some(SectionFlags {
+                write: false,
+                exec: true,
+                retain: true,
+            })
+        );
+        assert!(func.to_string().starts_with("g: ; section(\"hooks\",\"axR\")\n"));
+        let src = "func @g() section(\"hooks\",\"q\") {\n  unreachable\n}\n";
+        assert_eq!(parse_module(src).expect_err("fails").msg, "bad section flags `q`");
+        let src = "func @f() align(3) {\n  unreachable\n}\n";
+        assert_eq!(
+            parse_module(src).expect_err("fails").msg,
+            "alignment `3` is not a power of two"
+        );
+    }
+}
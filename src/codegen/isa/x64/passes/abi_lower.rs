@@ -14,86 +14,195 @@
 //!
 //! * `Arg { dst, idx }` → a fresh shim vreg pinned to the ABI arg register
 //!   plus `Copy { dst, src: shim }`. The shim's short life plus the dangling
-//!   `Copy` make coalescing in regalloc straightforward.
+//!   `Copy` make coalescing in regalloc straightforward. The copies come
+//!   after the last `Arg`, so none lands in a register still unread. Args
+//!   past the class's register pool become `LoadArgFromStack`; both
+//!   classes draw from one counter of stack eightbytes in declaration
+//!   order, as SysV requires. A scalar takes one eightbyte, a `V128` a
+//!   16-aligned pair; wider vectors aren't passed on the stack.
 //! * `Return { src }` → `Copy { dst: ret_vreg, src }; X64Inst::RawRet` with
 //!   `ret_vreg` pinned to the ABI return register.
+//! * `CallPseudo` → arg copies into pinned shims, `StoreStackArg` for
+//!   overflow args, caller-saved clobbers, `Call64r` (after a
+//!   `PatchPoint` if the call is patchable), and a pinned return shim
+//!   copied into the user's return vreg.
+//! * `RawBytes` → input copies into shims pinned to their pregs, clobber
+//!   markers for every other preg the bytes write, the `RawBytes` itself,
+//!   `Kill`s ending the input shims, and pinned output shims copied into
+//!   the user's vregs.
+//! * By-value aggregates (`Agg`-typed `Arg`s, `Return` sources, call args
+//!   and rets) are classified per `AggregateLayout`. Each eightbyte travels
+//!   as one word: a field alone in it as itself, the others assembled in a
+//!   `StackAlloc` temporary. An aggregate that doesn't fit the remaining
+//!   registers goes to the stack whole. One returned in memory is written
+//!   through a hidden pointer passed in `RDI`, which the callee returns in
+//!   `RAX`. Leftover `MakeAggregate`s are erased.
 
 use std::collections::HashMap;
 
-use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
 use crate::codegen::isa::x64::regs::{
-    R10, R11, RAX, XMM0, XMM1, XMM10, XMM11, XMM12, XMM13, XMM14, XMM15, XMM2, XMM3, XMM4, XMM5,
-    XMM6, XMM7, XMM8, XMM9,
+    R10, R11, RAX, RDI, XMM0, XMM1, XMM10, XMM11, XMM12, XMM13, XMM14, XMM15, XMM2, XMM3, XMM4,
+    XMM5, XMM6, XMM7, XMM8, XMM9, is_xmm,
+};
+use crate::codegen::isa::x64::sysv::{
+    AggregateLayout, EightbyteClass, FP_ARG_REGS, INT_ARG_REGS, SysVAmd64,
+};
+use crate::codegen::passes::{AbiLowering, AbiLowerResult};
+use crate::codegen::tir::{
+    CallTarget, Func, Instruction, PatchKind, PseudoInstruction, RawBytesId, Reg, Type,
 };
-use crate::codegen::isa::x64::sysv::{FP_ARG_REGS, INT_ARG_REGS, SysVAmd64};
-use crate::codegen::passes::{AbiLowering, AbiLowerResult, CallSite};
-use crate::codegen::tir::{CallTarget, Func, Instruction, PseudoInstruction, Reg, Type};
 
 pub struct SysVAmd64Lowering;
 
 impl AbiLowering<X64Inst> for SysVAmd64Lowering {
-    fn lower(&self, func: &mut Func<X64Inst>) -> AbiLowerResult {
+    fn lower(&self, func: &mut Func<X64Inst>) -> Result<AbiLowerResult, CodegenError> {
         let cc = SysVAmd64;
         let mut reg_bind: HashMap<Reg, Reg> = HashMap::new();
-        let mut call_sites: Vec<CallSite> = Vec::new();
         let block_ids: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
 
         // SysV gives integer and FP args separate position counters.
         // Walk entry-block Arg pseudos in declaration order and map
-        // each `idx` into its class-relative counter.
+        // each `idx` into its class-relative counter. Args that overflow
+        // their class's register pool share one stack-eightbyte counter,
+        // in declaration order regardless of class.
         let mut int_pos: u32 = 0;
         let mut fp_pos: u32 = 0;
+        let mut stack_pos: u32 = 0;
+
+        // An aggregate returned in memory goes to a buffer the caller
+        // passes in place of the first integer argument.
+        let sret = returns_in_memory(func).then(|| {
+            int_pos = 1;
+            func.new_typed_vreg(Type::Ptr)
+        });
+        // Copies out of arg registers, stack-arg loads and aggregate
+        // unpacking wait until every `Arg` has pinned its register, so
+        // nothing overwrites one still unread.
+        let mut deferred: Vec<Instruction<X64Inst>> = Vec::new();
+        let entry = func.get_entry_block();
 
         for block in block_ids {
             let old = func.get_block_data_mut(block).take_insts();
-            let mut new: Vec<Instruction<X64Inst>> = Vec::with_capacity(old.len());
-            for inst in old {
+            let mut new = func.inst_buffer(old.len());
+            if let Some(sret) = sret.filter(|_| Some(block) == entry) {
+                let shim = func.new_typed_vreg(Type::Ptr);
+                reg_bind.insert(shim, RDI);
+                new.push(Instruction::Pseudo(PseudoInstruction::RegDef {
+                    vreg: shim,
+                    preg: RDI,
+                }));
+                new.push(Instruction::Pseudo(PseudoInstruction::Copy {
+                    dst: sret,
+                    src: shim,
+                }));
+            }
+            for &inst in &old {
+                if !matches!(inst, Instruction::Pseudo(PseudoInstruction::Arg { .. })) {
+                    new.append(&mut deferred);
+                }
                 match inst {
-                    Instruction::Pseudo(PseudoInstruction::Arg { dst, idx }) => {
-                        let is_fp = func.vreg_type(dst).is_fp_or_vector();
-                        if is_fp {
-                            if let Some(preg) = cc.fp_arg_reg(fp_pos) {
-                                let shim = func.new_typed_vreg(func.vreg_type(dst));
-                                reg_bind.insert(shim, preg);
+                    Instruction::Pseudo(PseudoInstruction::Arg { dst, idx })
+                        if func.vreg_type(dst).is_aggregate() =>
+                    {
+                        let (elems, layout) = aggregate_parts(func, dst);
+                        let in_regs = !layout.in_memory()
+                            && int_pos + layout.count(EightbyteClass::Integer)
+                                <= cc.max_int_args_in_regs()
+                            && fp_pos + layout.count(EightbyteClass::Sse)
+                                <= cc.max_fp_args_in_regs();
+                        let mut words = Vec::with_capacity(layout.eightbytes.len());
+                        for (i, &class) in layout.eightbytes.iter().enumerate() {
+                            let word = func.new_typed_vreg(word_type(func, &elems, &layout, i));
+                            if in_regs {
+                                let preg = match class {
+                                    EightbyteClass::Integer => {
+                                        int_pos += 1;
+                                        cc.int_arg_reg(int_pos - 1)
+                                    }
+                                    EightbyteClass::Sse => {
+                                        fp_pos += 1;
+                                        cc.fp_arg_reg(fp_pos - 1)
+                                    }
+                                };
+                                reg_bind.insert(word, preg.expect("counted against the pool"));
                                 new.push(Instruction::Pseudo(PseudoInstruction::Arg {
-                                    dst: shim,
+                                    dst: word,
                                     idx,
                                 }));
-                                new.push(Instruction::Pseudo(PseudoInstruction::Copy {
-                                    dst,
-                                    src: shim,
-                                }));
-                                fp_pos += 1;
                             } else {
-                                // Stack-passed FP args — unsupported today;
-                                // the frontend should cap FP arg count at 8.
-                                panic!(
-                                    "more than {} FP args: stack-passed FP args unimplemented",
-                                    cc.max_fp_args_in_regs()
-                                );
+                                let stack_idx = stack_slot(&mut stack_pos, func.vreg_type(word))?;
+                                deferred.push(Instruction::Target(X64Inst::LoadArgFromStack {
+                                    dst: word,
+                                    stack_idx,
+                                }));
                             }
-                        } else if let Some(preg) = cc.int_arg_reg(int_pos) {
+                            words.push(word);
+                        }
+                        unpack_words(func, &mut deferred, &words, &elems, &layout);
+                    }
+                    Instruction::Pseudo(PseudoInstruction::Arg { dst, idx }) => {
+                        let preg = if func.vreg_type(dst).is_fp_or_vector() {
+                            let p = cc.fp_arg_reg(fp_pos);
+                            fp_pos += 1;
+                            p
+                        } else {
+                            let p = cc.int_arg_reg(int_pos);
+                            int_pos += 1;
+                            p
+                        };
+                        if let Some(preg) = preg {
                             let shim = func.new_typed_vreg(func.vreg_type(dst));
                             reg_bind.insert(shim, preg);
                             new.push(Instruction::Pseudo(PseudoInstruction::Arg {
                                 dst: shim,
                                 idx,
                             }));
-                            new.push(Instruction::Pseudo(PseudoInstruction::Copy {
+                            deferred.push(Instruction::Pseudo(PseudoInstruction::Copy {
                                 dst,
                                 src: shim,
                             }));
-                            int_pos += 1;
                         } else {
-                            // Stack-passed integer argument.
-                            let stack_idx = int_pos - cc.max_int_args_in_regs();
-                            new.push(Instruction::Target(X64Inst::LoadArgFromStack {
+                            let stack_idx = stack_slot(&mut stack_pos, func.vreg_type(dst))?;
+                            deferred.push(Instruction::Target(X64Inst::LoadArgFromStack {
                                 dst,
                                 stack_idx,
                             }));
-                            int_pos += 1;
                         }
                     }
+                    Instruction::Pseudo(PseudoInstruction::Return { src })
+                        if func.vreg_type(src).is_aggregate() =>
+                    {
+                        let (elems, layout) = aggregate_parts(func, src);
+                        if layout.in_memory() {
+                            let sret = sret.expect("pre-scanned for in-memory returns");
+                            store_fields(func, &mut new, sret, &elems, &layout);
+                            let ret_vreg = func.new_typed_vreg(Type::Ptr);
+                            reg_bind.insert(ret_vreg, cc.int_ret_reg());
+                            new.push(Instruction::Pseudo(PseudoInstruction::Copy {
+                                dst: ret_vreg,
+                                src: sret,
+                            }));
+                        } else {
+                            let words = pack_words(func, &mut new, &elems, &layout);
+                            let mut shims = Vec::with_capacity(words.len());
+                            for (word, preg) in words.into_iter().zip(layout.ret_regs()) {
+                                let shim = func.new_typed_vreg(func.vreg_type(word));
+                                reg_bind.insert(shim, preg);
+                                new.push(Instruction::Pseudo(PseudoInstruction::Copy {
+                                    dst: shim,
+                                    src: word,
+                                }));
+                                shims.push(shim);
+                            }
+                            // Each register must hold its word until the `ret`.
+                            for src in shims {
+                                new.push(Instruction::Pseudo(PseudoInstruction::Kill { src }));
+                            }
+                        }
+                        new.push(Instruction::Target(X64Inst::RawRet));
+                    }
                     Instruction::Pseudo(PseudoInstruction::Return { src }) => {
                         let src_ty = func.vreg_type(src);
                         let (ret_preg, ret_ty) = if src_ty.is_fp_or_vector() {
@@ -110,31 +219,50 @@ impl AbiLowering<X64Inst> for SysVAmd64Lowering {
                         new.push(Instruction::Target(X64Inst::RawRet));
                     }
                     Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) => {
-                        lower_call(
-                            id,
-                            func,
-                            &mut new,
-                            &mut reg_bind,
-                            &mut call_sites,
-                        );
+                        lower_call(id, func, &mut new, &mut reg_bind)?;
+                    }
+                    Instruction::Pseudo(PseudoInstruction::RawBytes { id }) => {
+                        lower_raw_bytes(id, func, &mut new, &mut reg_bind);
                     }
+                    // Everything reading an aggregate has gone through its
+                    // side table by now.
+                    Instruction::Pseudo(PseudoInstruction::MakeAggregate { .. }) => {}
                     other => new.push(other),
                 }
             }
+            new.append(&mut deferred);
+            func.recycle_insts(old);
             func.get_block_data_mut(block).set_insts(new);
         }
 
-        AbiLowerResult { reg_bind, call_sites }
+        Ok(AbiLowerResult { reg_bind })
     }
 }
 
+/// Claim the stack eightbytes of an argument of type `ty` from `next`,
+/// the count claimed so far: one for a scalar, a 16-aligned pair for a
+/// `V128`. Returns the first one's index.
+///
+/// # Errors
+/// `StackVectorArg` for a wider vector, which SysV aligns past what the
+/// outgoing-argument area guarantees.
+fn stack_slot(next: &mut u32, ty: Type) -> Result<u32, CodegenError> {
+    let words = match ty {
+        Type::V128(_) => 2,
+        Type::V256(_) | Type::V512(_) => return Err(CodegenError::StackVectorArg(ty)),
+        _ => 1,
+    };
+    let at = next.next_multiple_of(words);
+    *next = at + words;
+    Ok(at)
+}
+
 fn lower_call(
     id: crate::codegen::tir::CallId,
     func: &mut Func<X64Inst>,
     new: &mut Vec<Instruction<X64Inst>>,
     reg_bind: &mut HashMap<Reg, Reg>,
-    call_sites: &mut Vec<CallSite>,
-) {
+) -> Result<(), CodegenError> {
     // Snapshot the CallData's fields we need; the side-table might be
     // mutated below if we ever add spill vregs.
     let call_data = func.call_operands(id).clone();
@@ -153,42 +281,84 @@ fn lower_call(
     enum ArgSlot {
         IntReg(Reg),
         FpReg(Reg),
-        /// Stack arg index, zero-based from the first stack-passed arg.
-        IntStack(u32),
+        /// Index of the arg's first eightbyte in the stack area, claimed
+        /// by `stack_slot`. Shared by both classes, in argument order.
+        Stack(u32),
     }
 
     let mut slots: Vec<(Reg, ArgSlot)> = Vec::with_capacity(args.len());
     let mut int_pos: u32 = 0;
     let mut fp_pos: u32 = 0;
-    let mut stack_idx_counter: u32 = 0;
+    let mut stack_words: u32 = 0;
+
+    // An aggregate returned in memory lands in a buffer of ours whose
+    // address goes first, ahead of the integer arguments.
+    let ret_parts = rets
+        .first()
+        .filter(|&&r| func.vreg_type(r).is_aggregate())
+        .map(|&r| aggregate_parts(func, r));
+    let sret = match &ret_parts {
+        Some((_, layout)) if layout.in_memory() => {
+            let buf = stack_temp(func, new, layout);
+            slots.push((buf, ArgSlot::IntReg(INT_ARG_REGS[0])));
+            int_pos = 1;
+            Some(buf)
+        }
+        _ => None,
+    };
+
     for &user_arg in &args {
+        if func.vreg_type(user_arg).is_aggregate() {
+            // All eightbytes go in registers or, failing that, all on
+            // the stack; the registers stay free for later args.
+            let (elems, layout) = aggregate_parts(func, user_arg);
+            let words = pack_words(func, new, &elems, &layout);
+            let in_regs = !layout.in_memory()
+                && (int_pos + layout.count(EightbyteClass::Integer)) as usize
+                    <= INT_ARG_REGS.len()
+                && (fp_pos + layout.count(EightbyteClass::Sse)) as usize <= FP_ARG_REGS.len();
+            for (word, class) in words.into_iter().zip(layout.eightbytes) {
+                let slot = match class {
+                    _ if !in_regs => {
+                        ArgSlot::Stack(stack_slot(&mut stack_words, func.vreg_type(word))?)
+                    }
+                    EightbyteClass::Integer => {
+                        int_pos += 1;
+                        ArgSlot::IntReg(INT_ARG_REGS[int_pos as usize - 1])
+                    }
+                    EightbyteClass::Sse => {
+                        fp_pos += 1;
+                        ArgSlot::FpReg(FP_ARG_REGS[fp_pos as usize - 1])
+                    }
+                };
+                slots.push((word, slot));
+            }
+            continue;
+        }
         let is_fp = func.vreg_type(user_arg).is_fp_or_vector();
-        if is_fp {
-            if let Some(preg) = FP_ARG_REGS.get(fp_pos as usize).copied() {
-                slots.push((user_arg, ArgSlot::FpReg(preg)));
+        let reg_slot = if is_fp {
+            FP_ARG_REGS.get(fp_pos as usize).map(|&p| {
                 fp_pos += 1;
-            } else {
-                panic!(
-                    "more than {} FP call args: stack-passed FP args unimplemented",
-                    FP_ARG_REGS.len()
-                );
-            }
-        } else if let Some(preg) = INT_ARG_REGS.get(int_pos as usize).copied() {
-            slots.push((user_arg, ArgSlot::IntReg(preg)));
-            int_pos += 1;
+                ArgSlot::FpReg(p)
+            })
         } else {
-            slots.push((user_arg, ArgSlot::IntStack(stack_idx_counter)));
-            stack_idx_counter += 1;
-        }
+            INT_ARG_REGS.get(int_pos as usize).map(|&p| {
+                int_pos += 1;
+                ArgSlot::IntReg(p)
+            })
+        };
+        let slot = match reg_slot {
+            Some(slot) => slot,
+            None => ArgSlot::Stack(stack_slot(&mut stack_words, func.vreg_type(user_arg))?),
+        };
+        slots.push((user_arg, slot));
     }
 
-    let stack_arg_count = stack_idx_counter as usize;
-
     // Reserve a 16-byte-aligned outgoing-args area. Rsp is 16-aligned
     // on entry to this call (the function prologue established that,
     // and no dynamic RSP motion happens between calls); a padded
     // region keeps the CALL at a 16-aligned Rsp.
-    let raw_bytes = (stack_arg_count * 8) as i32;
+    let raw_bytes = (stack_words * 8) as i32;
     let reserved = (raw_bytes + 15) & !15; // round up to multiple of 16
     if reserved > 0 {
         new.push(Instruction::Target(X64Inst::AdjustRsp { delta: -reserved }));
@@ -196,7 +366,7 @@ fn lower_call(
 
     // Emit stack-arg stores (writes to `[rsp + 8*stack_idx]`).
     for (user_arg, slot) in &slots {
-        if let ArgSlot::IntStack(stack_idx) = slot {
+        if let ArgSlot::Stack(stack_idx) = slot {
             new.push(Instruction::Target(X64Inst::StoreStackArg {
                 src: *user_arg,
                 stack_idx: *stack_idx,
@@ -209,7 +379,7 @@ fn lower_call(
     for (user_arg, slot) in &slots {
         let preg = match *slot {
             ArgSlot::IntReg(p) | ArgSlot::FpReg(p) => p,
-            ArgSlot::IntStack(_) => continue,
+            ArgSlot::Stack(_) => continue,
         };
         let shim = func.new_typed_vreg(func.vreg_type(*user_arg));
         reg_bind.insert(shim, preg);
@@ -237,23 +407,27 @@ fn lower_call(
     }
     emit_clobber(func, new, reg_bind, RAX, Type::I64);
 
-    // Callee address: for direct (symbol) calls we materialize a
-    // placeholder `Mov64ri 0` that the loader patches at load time;
-    // for indirect calls we already have the address in the user's
-    // `fn_ptr` vreg and just thread it into `Call64r` unchanged.
+    // Callee address: for direct (symbol) calls we materialize it with
+    // `Mov64rsym`, whose immediate the loader patches at load time; for
+    // indirect calls we already have the address in the user's `fn_ptr`
+    // vreg and just thread it into `Call64r` unchanged.
     let addr_vreg = match &call_data.callee {
-        CallTarget::Symbol(_) => {
+        CallTarget::Symbol(name) => {
             let v = func.new_vreg();
-            new.push(Instruction::Target(X64Inst::Mov64ri {
-                dst: v,
-                imm: 0, // placeholder, patched at load time
-            }));
+            let sym = func.symbol(name);
+            new.push(Instruction::Target(X64Inst::Mov64rsym { dst: v, sym }));
             v
         }
         CallTarget::Indirect(fn_ptr) => *fn_ptr,
     };
 
-    // Emit the call.
+    // Emit the call, behind its patch sled if it has one.
+    if let Some(len) = call_data.patch {
+        new.push(Instruction::Pseudo(PseudoInstruction::PatchPoint {
+            len,
+            kind: PatchKind::Call,
+        }));
+    }
     new.push(Instruction::Target(X64Inst::Call64r { target: addr_vreg }));
 
     // Reclaim the outgoing-args area before touching RAX / the ret
@@ -262,6 +436,24 @@ fn lower_call(
         new.push(Instruction::Target(X64Inst::AdjustRsp { delta: reserved }));
     }
 
+    // An aggregate return value comes back in its buffer, or in
+    // `RAX`/`RDX`/`XMM0`/`XMM1` one eightbyte each.
+    if let Some((elems, layout)) = ret_parts {
+        if let Some(buf) = sret {
+            load_fields(func, new, buf, &elems, &layout);
+        } else {
+            let mut words = Vec::with_capacity(layout.eightbytes.len());
+            for (i, preg) in layout.ret_regs().into_iter().enumerate() {
+                let shim = func.new_typed_vreg(word_type(func, &elems, &layout, i));
+                reg_bind.insert(shim, preg);
+                new.push(Instruction::Pseudo(PseudoInstruction::RegDef { vreg: shim, preg }));
+                words.push(shim);
+            }
+            unpack_words(func, new, &words, &elems, &layout);
+        }
+        return Ok(());
+    }
+
     // Extract the return value: define ret_shim pinned to RAX (int) or
     // XMM0 (FP), copy into the user's return vreg.
     if let Some(&user_ret) = rets.first() {
@@ -282,13 +474,202 @@ fn lower_call(
             src: ret_shim,
         }));
     }
+    Ok(())
+}
+
+fn lower_raw_bytes(
+    id: RawBytesId,
+    func: &mut Func<X64Inst>,
+    new: &mut Vec<Instruction<X64Inst>>,
+    reg_bind: &mut HashMap<Reg, Reg>,
+) {
+    let data = func.raw_bytes_operands(id).clone();
+    // Input shims hold their preg until the `Kill` after the bytes, so
+    // nothing else can live there across them.
+    let mut shims = Vec::with_capacity(data.uses.len());
+    for &(user, preg) in &data.uses {
+        let shim = func.new_typed_vreg(func.vreg_type(user));
+        reg_bind.insert(shim, preg);
+        new.push(Instruction::Pseudo(PseudoInstruction::Copy { dst: shim, src: user }));
+        shims.push(shim);
+    }
+    let mut clobbered: Vec<Reg> = Vec::new();
+    for preg in data.defs.iter().map(|&(_, p)| p).chain(data.clobbers.iter().copied()) {
+        if !data.uses.iter().any(|&(_, p)| p == preg) && !clobbered.contains(&preg) {
+            clobbered.push(preg);
+        }
+    }
+    for preg in clobbered {
+        let ty = if is_xmm(preg) { Type::F64 } else { Type::I64 };
+        emit_clobber(func, new, reg_bind, preg, ty);
+    }
+    new.push(Instruction::Pseudo(PseudoInstruction::RawBytes { id }));
+    for src in shims {
+        new.push(Instruction::Pseudo(PseudoInstruction::Kill { src }));
+    }
+    for &(user, preg) in &data.defs {
+        let shim = func.new_typed_vreg(func.vreg_type(user));
+        reg_bind.insert(shim, preg);
+        new.push(Instruction::Pseudo(PseudoInstruction::RegDef { vreg: shim, preg }));
+        new.push(Instruction::Pseudo(PseudoInstruction::Copy { dst: user, src: shim }));
+    }
+}
 
-    // Record this call's symbol-patch request.
-    let symbol = match call_data.callee {
-        CallTarget::Symbol(s) => s,
-        CallTarget::Indirect(_) => String::new(), // no patching needed
+/// Whether any `Return` hands back an aggregate in memory.
+fn returns_in_memory(func: &Func<X64Inst>) -> bool {
+    func.blocks_iter().any(|(_, bd)| {
+        bd.iter().any(|inst| match *inst {
+            Instruction::Pseudo(PseudoInstruction::Return { src }) => {
+                func.vreg_type(src).is_aggregate() && aggregate_parts(func, src).1.in_memory()
+            }
+            _ => false,
+        })
+    })
+}
+
+/// The element vregs of aggregate vreg `agg` and their layout.
+fn aggregate_parts(func: &Func<X64Inst>, agg: Reg) -> (Vec<Reg>, AggregateLayout) {
+    let Type::Agg(id) = func.vreg_type(agg) else {
+        panic!("vreg {agg} is not an aggregate");
     };
-    call_sites.push(CallSite { addr_vreg, symbol });
+    let elems = func.aggregate_operands(id).elems.clone();
+    let types: Vec<Type> = elems.iter().map(|&e| func.vreg_type(e)).collect();
+    (elems, AggregateLayout::of(&types))
+}
+
+/// The type of the word carrying eightbyte `i`. A field alone in its
+/// eightbyte travels as itself.
+fn word_type(func: &Func<X64Inst>, elems: &[Reg], layout: &AggregateLayout, i: usize) -> Type {
+    let mut fields = layout.fields_in(i);
+    match (fields.next(), fields.next()) {
+        (Some(f), None) => func.vreg_type(elems[f]),
+        _ => layout.eightbytes[i].word_type(),
+    }
+}
+
+/// The words carrying `elems`, one per eightbyte. Eightbytes holding
+/// several fields are assembled in a stack temporary.
+fn pack_words(
+    func: &mut Func<X64Inst>,
+    out: &mut Vec<Instruction<X64Inst>>,
+    elems: &[Reg],
+    layout: &AggregateLayout,
+) -> Vec<Reg> {
+    let mut temp = None;
+    let mut words = Vec::with_capacity(layout.eightbytes.len());
+    for (i, class) in layout.eightbytes.iter().enumerate() {
+        let fields: Vec<usize> = layout.fields_in(i).collect();
+        if let [f] = fields[..] {
+            words.push(elems[f]);
+            continue;
+        }
+        let base = *temp.get_or_insert_with(|| stack_temp(func, out, layout));
+        for f in fields {
+            let mem = Mem::base_disp(base, layout.offsets[f] as i32);
+            let store = store_inst(func.vreg_type(elems[f]), mem, elems[f]);
+            out.push(Instruction::Target(store));
+        }
+        let word = func.new_typed_vreg(class.word_type());
+        let mem = Mem::base_disp(base, 8 * i as i32);
+        out.push(Instruction::Target(load_inst(class.word_type(), word, mem)));
+        words.push(word);
+    }
+    words
+}
+
+/// The inverse of `pack_words`: define `elems` from `words`.
+fn unpack_words(
+    func: &mut Func<X64Inst>,
+    out: &mut Vec<Instruction<X64Inst>>,
+    words: &[Reg],
+    elems: &[Reg],
+    layout: &AggregateLayout,
+) {
+    let mut temp = None;
+    for (i, &word) in words.iter().enumerate() {
+        let fields: Vec<usize> = layout.fields_in(i).collect();
+        if let [f] = fields[..] {
+            out.push(Instruction::Pseudo(PseudoInstruction::Copy {
+                dst: elems[f],
+                src: word,
+            }));
+            continue;
+        }
+        let base = *temp.get_or_insert_with(|| stack_temp(func, out, layout));
+        let mem = Mem::base_disp(base, 8 * i as i32);
+        out.push(Instruction::Target(store_inst(func.vreg_type(word), mem, word)));
+        for f in fields {
+            let mem = Mem::base_disp(base, layout.offsets[f] as i32);
+            let load = load_inst(func.vreg_type(elems[f]), elems[f], mem);
+            out.push(Instruction::Target(load));
+        }
+    }
+}
+
+/// Store every field of `elems` to its offset from `ptr`.
+fn store_fields(
+    func: &Func<X64Inst>,
+    out: &mut Vec<Instruction<X64Inst>>,
+    ptr: Reg,
+    elems: &[Reg],
+    layout: &AggregateLayout,
+) {
+    for (&e, &off) in elems.iter().zip(&layout.offsets) {
+        let mem = Mem::base_disp(ptr, off as i32);
+        out.push(Instruction::Target(store_inst(func.vreg_type(e), mem, e)));
+    }
+}
+
+/// Load every field of `elems` from its offset from `ptr`.
+fn load_fields(
+    func: &Func<X64Inst>,
+    out: &mut Vec<Instruction<X64Inst>>,
+    ptr: Reg,
+    elems: &[Reg],
+    layout: &AggregateLayout,
+) {
+    for (&e, &off) in elems.iter().zip(&layout.offsets) {
+        let mem = Mem::base_disp(ptr, off as i32);
+        out.push(Instruction::Target(load_inst(func.vreg_type(e), e, mem)));
+    }
+}
+
+/// A fresh frame region big enough for the aggregate, rounded up to whole
+/// eightbytes.
+fn stack_temp(
+    func: &mut Func<X64Inst>,
+    out: &mut Vec<Instruction<X64Inst>>,
+    layout: &AggregateLayout,
+) -> Reg {
+    let dst = func.new_typed_vreg(Type::Ptr);
+    out.push(Instruction::Pseudo(PseudoInstruction::StackAlloc {
+        dst,
+        size: layout.size.next_multiple_of(8),
+        align: 8,
+    }));
+    dst
+}
+
+fn load_inst(ty: Type, dst: Reg, src: Mem) -> X64Inst {
+    match ty {
+        Type::I8 => X64Inst::Mov8rm { dst, src },
+        Type::I16 => X64Inst::Mov16rm { dst, src },
+        Type::I32 => X64Inst::Mov32rm { dst, src },
+        Type::F32 => X64Inst::Movssrm { dst, src },
+        Type::F64 => X64Inst::Movsdrm { dst, src },
+        _ => X64Inst::Mov64rm { dst, src },
+    }
+}
+
+fn store_inst(ty: Type, dst: Mem, src: Reg) -> X64Inst {
+    match ty {
+        Type::I8 => X64Inst::Mov8mr { dst, src },
+        Type::I16 => X64Inst::Mov16mr { dst, src },
+        Type::I32 => X64Inst::Mov32mr { dst, src },
+        Type::F32 => X64Inst::Movssmr { dst, src },
+        Type::F64 => X64Inst::Movsdmr { dst, src },
+        _ => X64Inst::Mov64mr { dst, src },
+    }
 }
 
 /// Emit a caller-saved clobber marker. Caller passes `ty` so the
@@ -329,7 +710,7 @@ mod tests {
     #[test]
     fn lowers_args_to_pinned_shims_and_copies() {
         let mut func = build_simple_add();
-        let res = SysVAmd64Lowering.lower(&mut func);
+        let res = SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         let mut pinned_pregs: Vec<_> = res.reg_bind.values().copied().collect();
         pinned_pregs.sort_unstable();
@@ -341,7 +722,7 @@ mod tests {
     #[test]
     fn lowers_return_to_copy_plus_rawret() {
         let mut func = build_simple_add();
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         let b0 = func.get_entry_block().unwrap();
         let last_two: Vec<_> = func
@@ -363,7 +744,7 @@ mod tests {
     #[test]
     fn no_return_pseudo_remains_after_lowering() {
         let mut func = build_simple_add();
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
         for (_b, bd) in func.blocks_iter() {
             for inst in bd.iter() {
                 assert!(
@@ -377,7 +758,7 @@ mod tests {
     #[test]
     fn arg_pseudo_targets_a_pinned_shim_not_the_original_vreg() {
         let mut func = build_simple_add();
-        let res = SysVAmd64Lowering.lower(&mut func);
+        let res = SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         let b0 = func.get_entry_block().unwrap();
         let mut shim_targets = Vec::new();
@@ -395,6 +776,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn every_arg_pseudo_precedes_the_copies_out_of_arg_registers() {
+        // Interleaved, the allocator may put an earlier arg's copy in a
+        // later arg's register before that `Arg` reads it.
+        let mut func = build_simple_add();
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
+
+        let b0 = func.get_entry_block().unwrap();
+        let insts = func.get_block_data(b0).insts();
+        let is_arg = |i: &Instruction<X64Inst>| {
+            matches!(i, Instruction::Pseudo(PseudoInstruction::Arg { .. }))
+        };
+        let last_arg = insts.iter().rposition(is_arg).unwrap();
+        let first_copy = insts
+            .iter()
+            .position(|i| matches!(i, Instruction::Pseudo(PseudoInstruction::Copy { .. })))
+            .unwrap();
+        assert!(last_arg < first_copy, "{func}");
+    }
+
     #[test]
     fn stack_passed_args_lower_to_load_from_stack() {
         // fn(a0..a7) — a0..a5 are reg-passed, a6 and a7 are stack-passed.
@@ -409,7 +810,7 @@ mod tests {
         }
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::Return { src: dsts[6] });
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         // The two stack-passed Args must have turned into LoadArgFromStack
         // with stack_idx = 0 and 1.
@@ -424,6 +825,69 @@ mod tests {
         assert_eq!(seen[1], (dsts[7], 1));
     }
 
+    #[test]
+    fn fp_and_int_overflow_args_share_the_stack_slot_counter() {
+        // fn(f64 x9, i64 x7): the 9th FP and the 7th int arg both
+        // overflow and take stack slots 0 and 1 in declaration order.
+        let mut func = Func::<X64Inst>::new("mixed".to_string());
+        let b0 = func.add_empty_block();
+        let mut dsts = Vec::new();
+        for i in 0..16 {
+            let ty = if i < 9 { Type::F64 } else { Type::I64 };
+            let v = func.new_typed_vreg(ty);
+            dsts.push(v);
+            func.get_block_data_mut(b0)
+                .push_pseudo_inst(PseudoInstruction::Arg { dst: v, idx: i });
+        }
+        func.get_block_data_mut(b0)
+            .push_pseudo_inst(PseudoInstruction::Return { src: dsts[15] });
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
+
+        let seen: Vec<(Reg, u32)> = func
+            .get_block_data(b0)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Target(X64Inst::LoadArgFromStack { dst, stack_idx }) => {
+                    Some((*dst, *stack_idx))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(seen, vec![(dsts[8], 0), (dsts[15], 1)]);
+    }
+
+    #[test]
+    fn call_with_nine_fp_args_stores_the_last_one_on_the_stack() {
+        use crate::codegen::tir::{CallData, CallTarget};
+        let mut func = Func::<X64Inst>::new("fp_caller".to_string());
+        let b0 = func.add_empty_block();
+        let args: Vec<Reg> = (0..9).map(|_| func.new_typed_vreg(Type::F64)).collect();
+        let ret = func.new_typed_vreg(Type::F64);
+        let id = func.new_call(CallData {
+            callee: CallTarget::Symbol("callee".into()),
+            args: args.clone(),
+            rets: vec![ret],
+            patch: None,
+        });
+        func.get_block_data_mut(b0)
+            .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
+        func.get_block_data_mut(b0)
+            .push_pseudo_inst(PseudoInstruction::Return { src: ret });
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
+
+        let stack_stores: Vec<_> = func
+            .get_block_data(b0)
+            .iter()
+            .filter_map(|i| match i {
+                Instruction::Target(X64Inst::StoreStackArg { src, stack_idx }) => {
+                    Some((*src, *stack_idx))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stack_stores, vec![(args[8], 0)]);
+    }
+
     #[test]
     fn call_with_stack_args_emits_store_and_rsp_adjusts() {
         use crate::codegen::tir::{CallData, CallTarget};
@@ -435,12 +899,13 @@ mod tests {
             callee: CallTarget::Symbol("callee".into()),
             args: args.clone(),
             rets: vec![ret],
+            patch: None,
         });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::Return { src: ret });
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         let insts: Vec<_> = func.get_block_data(b0).iter().copied().collect();
         // Expect: AdjustRsp(-16), then two StoreStackArg, then reg-arg
@@ -482,12 +947,13 @@ mod tests {
             callee: CallTarget::Symbol("callee".into()),
             args,
             rets: vec![ret],
+            patch: None,
         });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::Return { src: ret });
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         for inst in func.get_block_data(b0).iter() {
             assert!(
@@ -514,12 +980,13 @@ mod tests {
             callee: CallTarget::Symbol("callee".into()),
             args,
             rets: vec![ret],
+            patch: None,
         });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::CallPseudo { id });
         func.get_block_data_mut(b0)
             .push_pseudo_inst(PseudoInstruction::Return { src: ret });
-        SysVAmd64Lowering.lower(&mut func);
+        SysVAmd64Lowering.lower(&mut func).expect("lowers");
 
         let adj_neg = func.get_block_data(b0).iter().find_map(|i| match i {
             Instruction::Target(X64Inst::AdjustRsp { delta }) if *delta < 0 => Some(*delta),
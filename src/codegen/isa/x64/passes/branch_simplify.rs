@@ -0,0 +1,141 @@
+//! Branch simplification against the final block layout.
+//!
+//! **Requires:** Block order is final — nothing reorders, inserts, or
+//! deletes blocks between this pass and emission. The emitter lays blocks
+//! out in `func.blocks_iter()` order.
+//!
+//! **Preserves:** Semantics, program points, and operands; safe to run
+//! after register allocation.
+//!
+//! **Effect:** A block whose terminator can fall through to the next block
+//! in layout is reported to the emitter, which then omits the trailing
+//! `jmp`: a `Jmp` to the next block disappears entirely, and a `CondJmp`
+//! whose `not_taken` is the next block emits only its `jcc`. A `CondJmp`
+//! whose `taken` is the next block is first rewritten with the inverted
+//! condition and swapped targets so it falls through the same way.
+//!
+//! A `CondJmp` with neither target next keeps both jumps, but when its
+//! `taken` edge outweighs `not_taken` (profile counts, else block hints;
+//! `block_freq::edge_weight`) it is inverted too, so the conditional jump
+//! goes to the unlikely block and the likely one is reached by `jmp`.
+
+use std::collections::HashSet;
+
+use crate::codegen::analysis::block_freq::edge_weight;
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::tir::{Block, Func, Instruction};
+
+/// Invert conditional branches to favour fallthrough, or to send the
+/// `jcc` to the colder target, and return the blocks whose final jump
+/// the emitter may drop.
+pub fn simplify_branches(func: &mut Func<X64Inst>) -> HashSet<Block> {
+    let order: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut fallthrough = HashSet::new();
+    for (i, &b) in order.iter().enumerate() {
+        let next = order.get(i + 1).copied();
+        let taken_is_hotter = match func.get_block_data(b).get_terminator() {
+            Some(Instruction::Target(X64Inst::CondJmp { taken, not_taken, .. })) => {
+                edge_weight(func, b, taken) > edge_weight(func, b, not_taken)
+            }
+            _ => false,
+        };
+        let Some(Instruction::Target(term)) =
+            func.get_block_data_mut(b).insts_mut().last_mut()
+        else {
+            continue;
+        };
+        match term {
+            X64Inst::Jmp { dst } if Some(*dst) == next => {}
+            X64Inst::CondJmp { not_taken, .. } if Some(*not_taken) == next => {}
+            X64Inst::CondJmp { cond, taken, not_taken } if Some(*taken) == next => {
+                *cond = cond.invert();
+                std::mem::swap(taken, not_taken);
+            }
+            X64Inst::CondJmp { cond, taken, not_taken } if taken_is_hotter => {
+                *cond = cond.invert();
+                std::mem::swap(taken, not_taken);
+                continue;
+            }
+            _ => continue,
+        }
+        fallthrough.insert(b);
+    }
+    fallthrough
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::codegen::tir::{BlockHint, PseudoInstruction};
+
+    #[test]
+    fn cond_jump_to_next_block_is_inverted_and_falls_through() {
+        let mut func = Func::<X64Inst>::new("inv".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::L,
+            taken: b1,
+            not_taken: b2,
+        });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::Jmp { dst: b2 });
+        func.get_block_data_mut(b2)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        let out = simplify_branches(&mut func);
+        assert_eq!(out, HashSet::from([b0, b1]));
+        assert!(matches!(
+            func.get_block_data(b0).get_terminator(),
+            Some(Instruction::Target(X64Inst::CondJmp { cond: Cond::GE, taken, not_taken }))
+                if taken == b2 && not_taken == b1
+        ));
+    }
+
+    #[test]
+    fn backward_and_skipping_branches_are_kept() {
+        let mut func = Func::<X64Inst>::new("keep".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::Jmp { dst: b2 });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b0,
+            not_taken: b0,
+        });
+        func.get_block_data_mut(b2)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        assert!(simplify_branches(&mut func).is_empty());
+    }
+
+    #[test]
+    fn jcc_without_fallthrough_goes_to_the_unlikely_target() {
+        let mut func = Func::<X64Inst>::new("cold".to_string());
+        let b: Vec<Block> = (0..4).map(|_| func.add_empty_block()).collect();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b[0]).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::L,
+            taken: b[2],
+            not_taken: b[3],
+        });
+        for &blk in &b[1..] {
+            func.get_block_data_mut(blk)
+                .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        }
+        func.set_block_hint(b[3], Some(BlockHint::Unlikely));
+
+        assert!(simplify_branches(&mut func).is_empty());
+        assert!(matches!(
+            func.get_block_data(b[0]).get_terminator(),
+            Some(Instruction::Target(X64Inst::CondJmp { cond: Cond::GE, taken, not_taken }))
+                if taken == b[3] && not_taken == b[2]
+        ));
+    }
+}
@@ -0,0 +1,449 @@
+//! Block-local constant folding for the x64 target.
+//!
+//! **Requires:** Phi-free IR (run after `destroy_ssa`); every block
+//! terminated.
+//!
+//! **Preserves:** Semantics. An instruction that sets flags is only
+//! replaced by one that doesn't when no later instruction in the block
+//! reads those flags; flags never cross a block boundary in lancy IR.
+//!
+//! **Invalidates:** Block numbering when unreachable blocks are removed;
+//! any `CFG` computed before the pass.
+//!
+//! **Effect:** Walks each block tracking vregs whose value is a known
+//! constant (seeded by `Mov64ri`). Then:
+//!
+//! * arithmetic, bitwise, shift, and unary ops on constants become
+//!   `Mov64ri`, and copies of constants become `Mov64ri`;
+//! * a constant register operand that fits in 32 bits is turned into the
+//!   `ri32` form, and shift counts into `ri8`;
+//! * identities go away: `x + 0`, `x - 0`, `x | 0`, `x ^ 0`, `x & -1`,
+//!   `x * 1`; `x & 0` and `x * 0` become `0`; `x * 2^n` becomes `x << n`;
+//! * a compare on constants decides the `jcc`, `cmovcc`, or `setcc` that
+//!   reads it. A decided `jcc` becomes a `jmp`, compares whose flags are
+//!   then unread are deleted, and blocks left unreachable are removed.
+
+use std::collections::HashMap;
+
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::passes::remove_unreachable;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg, Type};
+
+/// Fold constants in place. Returns `true` if anything changed.
+pub fn fold_constants(func: &mut Func<X64Inst>) -> bool {
+    let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut changed = false;
+    for b in blocks {
+        let insts = func.get_block_data_mut(b).take_insts();
+        let out = func.inst_buffer(insts.len());
+        let (folded, block_changed) = fold_block(func, &insts, out);
+        func.recycle_insts(insts);
+        func.get_block_data_mut(b).set_insts(folded);
+        changed |= block_changed;
+    }
+    if changed {
+        remove_unreachable(func);
+    }
+    changed
+}
+
+enum Fold {
+    Keep,
+    Delete,
+    Replace(X64Inst),
+}
+
+/// What the folder knows at one point in a block.
+#[derive(Default)]
+struct Facts {
+    consts: HashMap<Reg, i64>,
+    /// The `(lhs, rhs)` the live flags were computed from, in `cmp` terms
+    /// (see `Cond::holds`), when both were constant.
+    flags: Option<(i64, i64)>,
+}
+
+fn fold_block(
+    func: &Func<X64Inst>,
+    insts: &[Instruction<X64Inst>],
+    mut out: Vec<Instruction<X64Inst>>,
+) -> (Vec<Instruction<X64Inst>>, bool) {
+    let mut facts = Facts::default();
+    let mut changed = false;
+    for (i, &inst) in insts.iter().enumerate() {
+        let flags_free = !flags_live_after(insts, i);
+        let mut inst = inst;
+        let mut deleted = false;
+        while let Instruction::Target(t) = inst {
+            match fold_target(t, &facts, flags_free) {
+                Fold::Keep => break,
+                Fold::Delete => {
+                    deleted = true;
+                    break;
+                }
+                Fold::Replace(n) => inst = Instruction::Target(n),
+            }
+            stat!("const-fold", "folded", "instructions folded or deleted");
+            changed = true;
+        }
+        if let Instruction::Pseudo(PseudoInstruction::Copy { dst, src }) = inst
+            && let Some(&v) = facts.consts.get(&src)
+            && matches!(func.vreg_type(dst), Type::I64 | Type::Ptr)
+        {
+            inst = Instruction::Target(X64Inst::Mov64ri { dst, imm: v });
+            stat!("const-fold", "copies", "copies of constants rematerialized");
+            changed = true;
+        }
+        if deleted {
+            continue;
+        }
+        facts.update(&inst);
+        out.push(inst);
+    }
+    // Compares whose every reader was folded away are now dead.
+    let mut i = 0;
+    while i < out.len() {
+        let is_cmp = matches!(
+            out[i],
+            Instruction::Target(
+                X64Inst::Cmp64rr { .. }
+                    | X64Inst::Cmp64ri32 { .. }
+                    | X64Inst::Test64rr { .. }
+                    | X64Inst::Test64ri32 { .. }
+            )
+        );
+        if is_cmp && !flags_live_after(&out, i) {
+            out.remove(i);
+            stat!("const-fold", "dead-cmps", "compares left without readers");
+            changed = true;
+        } else {
+            i += 1;
+        }
+    }
+    (out, changed)
+}
+
+impl Facts {
+    fn get(&self, r: Reg) -> Option<i64> {
+        self.consts.get(&r).copied()
+    }
+
+    /// Record what `inst` leaves behind in registers and flags.
+    fn update(&mut self, inst: &Instruction<X64Inst>) {
+        self.flags = match *inst {
+            Instruction::Target(X64Inst::Cmp64rr { lhs, rhs }) => {
+                self.get(lhs).zip(self.get(rhs))
+            }
+            Instruction::Target(X64Inst::Cmp64ri32 { lhs, imm }) => {
+                self.get(lhs).map(|l| (l, i64::from(imm)))
+            }
+            Instruction::Target(X64Inst::Test64rr { lhs, rhs }) => {
+                self.get(lhs).zip(self.get(rhs)).map(|(l, r)| (l & r, 0))
+            }
+            Instruction::Target(X64Inst::Test64ri32 { lhs, imm }) => {
+                self.get(lhs).map(|l| (l & i64::from(imm), 0))
+            }
+            // Moves leave flags alone; anything else may clobber them.
+            Instruction::Target(
+                X64Inst::Mov64rr { .. } | X64Inst::Mov64ri { .. } | X64Inst::Cmov64rr { .. },
+            )
+            | Instruction::Pseudo(PseudoInstruction::Copy { .. }) => self.flags,
+            _ => None,
+        };
+        for def in inst.get_defs() {
+            self.consts.remove(&def);
+        }
+        if let Instruction::Target(X64Inst::Mov64ri { dst, imm }) = *inst {
+            self.consts.insert(dst, imm);
+        }
+    }
+}
+
+fn fold_target(inst: X64Inst, facts: &Facts, flags_free: bool) -> Fold {
+    let mov = |dst, imm| Fold::Replace(X64Inst::Mov64ri { dst, imm });
+    let imm32 = |r: Reg| facts.get(r).and_then(|v| i32::try_from(v).ok());
+    match inst {
+        // Both operands known: compute. Otherwise a known source becomes
+        // an immediate, which sets flags identically.
+        X64Inst::Add64rr { dst, src }
+        | X64Inst::Sub64rr { dst, src }
+        | X64Inst::Imul64rr { dst, src }
+        | X64Inst::And64rr { dst, src }
+        | X64Inst::Or64rr { dst, src }
+        | X64Inst::Xor64rr { dst, src } => {
+            if flags_free
+                && let (Some(a), Some(b)) = (facts.get(dst), facts.get(src))
+            {
+                return mov(dst, eval_rr(inst, a, b));
+            }
+            if let X64Inst::Imul64rr { .. } = inst {
+                return match facts.get(src) {
+                    Some(1) if flags_free => Fold::Delete,
+                    Some(0) if flags_free => mov(dst, 0),
+                    Some(v) if flags_free && v > 0 && v.count_ones() == 1 => {
+                        Fold::Replace(X64Inst::Shl64ri8 {
+                            dst,
+                            imm: v.trailing_zeros() as u8,
+                        })
+                    }
+                    _ => Fold::Keep,
+                };
+            }
+            let Some(imm) = imm32(src) else {
+                return Fold::Keep;
+            };
+            Fold::Replace(match inst {
+                X64Inst::Add64rr { .. } => X64Inst::Add64ri32 { dst, imm },
+                X64Inst::Sub64rr { .. } => X64Inst::Sub64ri32 { dst, imm },
+                X64Inst::And64rr { .. } => X64Inst::And64ri32 { dst, imm },
+                X64Inst::Or64rr { .. } => X64Inst::Or64ri32 { dst, imm },
+                _ => X64Inst::Xor64ri32 { dst, imm },
+            })
+        }
+        X64Inst::Add64ri32 { dst, imm }
+        | X64Inst::Sub64ri32 { dst, imm }
+        | X64Inst::And64ri32 { dst, imm }
+        | X64Inst::Or64ri32 { dst, imm }
+        | X64Inst::Xor64ri32 { dst, imm } => {
+            if !flags_free {
+                return Fold::Keep;
+            }
+            if let Some(a) = facts.get(dst) {
+                return mov(dst, eval_ri(inst, a, i64::from(imm)));
+            }
+            match (inst, imm) {
+                (X64Inst::And64ri32 { .. }, 0) => mov(dst, 0),
+                (X64Inst::And64ri32 { .. }, -1)
+                | (
+                    X64Inst::Add64ri32 { .. }
+                    | X64Inst::Sub64ri32 { .. }
+                    | X64Inst::Or64ri32 { .. }
+                    | X64Inst::Xor64ri32 { .. },
+                    0,
+                ) => Fold::Delete,
+                _ => Fold::Keep,
+            }
+        }
+        X64Inst::Not64r { dst } | X64Inst::Neg64r { dst } => match facts.get(dst) {
+            Some(a) if flags_free => mov(
+                dst,
+                if let X64Inst::Not64r { .. } = inst { !a } else { a.wrapping_neg() },
+            ),
+            _ => Fold::Keep,
+        },
+        X64Inst::Shl64ri8 { dst, imm }
+        | X64Inst::Shr64ri8 { dst, imm }
+        | X64Inst::Sar64ri8 { dst, imm } => match facts.get(dst) {
+            Some(a) if flags_free => mov(dst, eval_shift(inst, a, u32::from(imm))),
+            _ => Fold::Keep,
+        },
+        X64Inst::Shl64rcl { dst, count }
+        | X64Inst::Shr64rcl { dst, count }
+        | X64Inst::Sar64rcl { dst, count } => {
+            let Some(c) = facts.get(count) else {
+                return Fold::Keep;
+            };
+            // The hardware masks the count to 6 bits.
+            let imm = u8::try_from(c & 63).expect("masked to 6 bits");
+            Fold::Replace(match inst {
+                X64Inst::Shl64rcl { .. } => X64Inst::Shl64ri8 { dst, imm },
+                X64Inst::Shr64rcl { .. } => X64Inst::Shr64ri8 { dst, imm },
+                _ => X64Inst::Sar64ri8 { dst, imm },
+            })
+        }
+        X64Inst::Mov64rr { dst, src } => match facts.get(src) {
+            Some(v) => mov(dst, v),
+            None => Fold::Keep,
+        },
+        X64Inst::Cmp64rr { lhs, rhs } => match imm32(rhs) {
+            Some(imm) => Fold::Replace(X64Inst::Cmp64ri32 { lhs, imm }),
+            None => Fold::Keep,
+        },
+        X64Inst::CondJmp { cond, taken, not_taken } => match facts.flags {
+            Some((l, r)) => Fold::Replace(X64Inst::Jmp {
+                dst: if cond.holds(l, r) { taken } else { not_taken },
+            }),
+            None => Fold::Keep,
+        },
+        X64Inst::Cmov64rr { cond, dst, src } => match facts.flags {
+            Some((l, r)) if cond.holds(l, r) => Fold::Replace(X64Inst::Mov64rr { dst, src }),
+            Some(_) => Fold::Delete,
+            None => Fold::Keep,
+        },
+        X64Inst::Setcc8r { cond, dst } => match facts.flags {
+            Some((l, r)) => Fold::Replace(X64Inst::Mov8ri {
+                dst,
+                imm: i8::from(cond.holds(l, r)),
+            }),
+            None => Fold::Keep,
+        },
+        _ => Fold::Keep,
+    }
+}
+
+fn eval_rr(inst: X64Inst, a: i64, b: i64) -> i64 {
+    match inst {
+        X64Inst::Add64rr { .. } => a.wrapping_add(b),
+        X64Inst::Sub64rr { .. } => a.wrapping_sub(b),
+        X64Inst::Imul64rr { .. } => a.wrapping_mul(b),
+        X64Inst::And64rr { .. } => a & b,
+        X64Inst::Or64rr { .. } => a | b,
+        X64Inst::Xor64rr { .. } => a ^ b,
+        _ => unreachable!("eval_rr on {inst}"),
+    }
+}
+
+fn eval_ri(inst: X64Inst, a: i64, b: i64) -> i64 {
+    match inst {
+        X64Inst::Add64ri32 { .. } => a.wrapping_add(b),
+        X64Inst::Sub64ri32 { .. } => a.wrapping_sub(b),
+        X64Inst::And64ri32 { .. } => a & b,
+        X64Inst::Or64ri32 { .. } => a | b,
+        X64Inst::Xor64ri32 { .. } => a ^ b,
+        _ => unreachable!("eval_ri on {inst}"),
+    }
+}
+
+fn eval_shift(inst: X64Inst, a: i64, n: u32) -> i64 {
+    let n = n & 63;
+    match inst {
+        X64Inst::Shl64ri8 { .. } => a << n,
+        X64Inst::Shr64ri8 { .. } => (a.cast_unsigned() >> n).cast_signed(),
+        X64Inst::Sar64ri8 { .. } => a >> n,
+        _ => unreachable!("eval_shift on {inst}"),
+    }
+}
+
+/// Whether some instruction after `i` in the block reads the flags before
+/// a compare overwrites them. Other flag writers are treated as
+/// transparent, which only errs towards "live".
+fn flags_live_after(insts: &[Instruction<X64Inst>], i: usize) -> bool {
+    for inst in &insts[i + 1..] {
+        match inst {
+            Instruction::Target(t) if t.reads_flags() => return true,
+            Instruction::Target(
+                X64Inst::Cmp64rr { .. }
+                | X64Inst::Cmp64ri32 { .. }
+                | X64Inst::Test64rr { .. }
+                | X64Inst::Test64ri32 { .. },
+            ) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::codegen::tir::Block;
+
+    fn block_insts(func: &Func<X64Inst>, b: Block) -> Vec<String> {
+        func.get_block_data(b)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn arithmetic_on_constants_collapses_to_one_immediate() {
+        let mut func = Func::<X64Inst>::new("k".to_string());
+        let b0 = func.add_empty_block();
+        let (a, b, s) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_target_inst(X64Inst::Mov64ri { dst: a, imm: 6 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: b, imm: 7 });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: s, src: a });
+        bd.push_target_inst(X64Inst::Imul64rr { dst: s, src: b });
+        bd.push_target_inst(X64Inst::Shl64ri8 { dst: s, imm: 1 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: s });
+
+        assert!(fold_constants(&mut func));
+        let insts = func.get_block_data(b0).insts();
+        assert!(matches!(
+            insts[insts.len() - 2],
+            Instruction::Target(X64Inst::Mov64ri { dst, imm: 84 }) if dst == s
+        ));
+    }
+
+    #[test]
+    fn identities_and_power_of_two_multiply_are_simplified() {
+        let mut func = Func::<X64Inst>::new("ids".to_string());
+        let b0 = func.add_empty_block();
+        let (x, k0, k8) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 0 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: k0, imm: 0 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: k8, imm: 8 });
+        bd.push_target_inst(X64Inst::Add64rr { dst: x, src: k0 });
+        bd.push_target_inst(X64Inst::Imul64rr { dst: x, src: k8 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: x });
+
+        assert!(fold_constants(&mut func));
+        let insts = block_insts(&func, b0);
+        assert_eq!(insts.len(), 5, "{insts:?}");
+        assert!(matches!(
+            func.get_block_data(b0).insts()[3],
+            Instruction::Target(X64Inst::Shl64ri8 { imm: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn flags_feeding_a_branch_keep_the_flag_setting_op() {
+        // `add x, 0` would be an identity, but the `jz` reads its flags.
+        let mut func = Func::<X64Inst>::new("flags".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let x = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 0 });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: x, imm: 0 });
+        bd.push_target_inst(X64Inst::CondJmp { cond: Cond::Z, taken: b1, not_taken: b1 });
+        func.get_block_data_mut(b1)
+            .push_pseudo_inst(PseudoInstruction::Return { src: x });
+
+        assert!(!fold_constants(&mut func));
+    }
+
+    #[test]
+    fn carry_into_an_adc_keeps_the_add() {
+        let mut func = Func::<X64Inst>::new("carry".to_string());
+        let b0 = func.add_empty_block();
+        let (lo, hi, k) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: lo, idx: 0 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: hi, idx: 1 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: k, idx: 2 });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: lo, imm: 0 });
+        bd.push_target_inst(X64Inst::Adc64rr { dst: hi, src: k });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: hi });
+
+        assert!(!fold_constants(&mut func));
+    }
+
+    #[test]
+    fn constant_compare_removes_the_dead_branch() {
+        let mut func = Func::<X64Inst>::new("dead".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let (a, b) = (func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_target_inst(X64Inst::Mov64ri { dst: a, imm: -1 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: b, imm: 1 << 40 });
+        bd.push_target_inst(X64Inst::Cmp64rr { lhs: a, rhs: b });
+        bd.push_target_inst(X64Inst::CondJmp { cond: Cond::A, taken: b1, not_taken: b2 });
+        for blk in [b1, b2] {
+            func.get_block_data_mut(blk)
+                .push_pseudo_inst(PseudoInstruction::Return { src: a });
+        }
+
+        assert!(fold_constants(&mut func));
+        // Unsigned: 0xffff.. > 2^40, so b1 survives and b2 is deleted.
+        assert_eq!(func.blocks_count(), 2);
+        let insts = func.get_block_data(b0).insts();
+        assert_eq!(insts.len(), 3);
+        assert!(matches!(insts[2], Instruction::Target(X64Inst::Jmp { dst }) if dst == b1));
+    }
+}
@@ -0,0 +1,124 @@
+//! Block-coverage instrumentation.
+//!
+//! **Requires:** Runs first, on the IR as the frontend built it, so each
+//! counter belongs to a block number `--emit=tir` prints. Phis allowed.
+//!
+//! **Preserves:** Semantics, apart from the counter stores. The increment
+//! is a `lea`, so it doesn't touch flags. Naked functions are untouched.
+//!
+//! **Invalidates:** Nothing structural: no blocks are added or removed.
+//!
+//! **Effect:** Each block, right after its phis (and the entry's `Arg`s),
+//! adds one to its own 8-byte slot of a zeroed, writable counter table
+//! named `counter_table(func)`: slot `i` counts entries into block `@i`.
+//! The table is returned for the caller to emit next to the code;
+//! `coverage_profile` turns the counts read back from it into a
+//! `Profile`.
+
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
+use crate::codegen::module::DataObject;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Instruction, Profile, PseudoInstruction};
+use crate::support::slotmap::Key;
+
+/// Name of the counter table of the function `func`.
+#[must_use]
+pub fn counter_table(func: &str) -> String {
+    format!("__lancy_cov_{func}")
+}
+
+/// Count entries into every block. Returns the counter table, or `None`
+/// for a naked function.
+pub fn instrument_blocks(func: &mut Func<X64Inst>) -> Option<DataObject> {
+    if func.attrs().naked {
+        return None;
+    }
+    let name = counter_table(func.name());
+    let sym = func.symbol(&name);
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let slots = blocks.iter().map(|b| b.index() + 1).max().unwrap_or(0);
+    for b in blocks {
+        let (table, count, next) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let slot = Mem::base_disp(table, i32::try_from(8 * b.index()).expect("block count fits"));
+        let insts = func.get_block_data_mut(b).insts_mut();
+        let at = insts
+            .iter()
+            .position(|inst| {
+                !matches!(
+                    inst,
+                    Instruction::Pseudo(
+                        PseudoInstruction::Arg { .. } | PseudoInstruction::Phi { .. }
+                    )
+                )
+            })
+            .unwrap_or(insts.len());
+        insts.splice(
+            at..at,
+            [
+                Instruction::Target(X64Inst::Mov64rsym { dst: table, sym }),
+                Instruction::Target(X64Inst::Mov64rm { dst: count, src: slot }),
+                Instruction::Target(X64Inst::Lea64rm { dst: next, src: Mem::base_disp(count, 1) }),
+                Instruction::Target(X64Inst::Mov64mr { dst: slot, src: next }),
+            ],
+        );
+        stat!("coverage", "counters", "block counters inserted");
+    }
+    Some(DataObject::zeroed(&name, 8 * slots, 8))
+}
+
+/// A profile of block counts from the counter table's contents.
+#[must_use]
+pub fn coverage_profile(counters: &[u64]) -> Profile {
+    Profile {
+        block_counts: counters.iter().enumerate().map(|(i, &c)| (Block::new(i), c)).collect(),
+        ..Profile::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::Cond;
+
+    #[test]
+    fn every_block_bumps_its_own_slot_after_the_args() {
+        let mut b = FuncBuilder::new("cov");
+        let x = b.arg();
+        let (then, other) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::Z, x, x, then, other);
+        b.switch_to_block(then);
+        b.ret(x);
+        b.switch_to_block(other);
+        b.ret(x);
+        let mut func = b.build();
+        let table = instrument_blocks(&mut func).expect("not naked");
+        assert_eq!(table.name, "__lancy_cov_cov");
+        assert_eq!(table.size(), 24);
+        assert!(table.writable);
+
+        for (blk, bd) in func.blocks_iter() {
+            let insts: Vec<_> = bd.iter().copied().collect();
+            let at = usize::from(blk == Block::new(0));
+            if at == 1 {
+                assert!(matches!(insts[0], Instruction::Pseudo(PseudoInstruction::Arg { .. })));
+            }
+            let Instruction::Target(X64Inst::Mov64rsym { sym, .. }) = insts[at] else {
+                panic!("{blk} starts with the table address, got {}", insts[at]);
+            };
+            assert_eq!(func.symbol_name(sym), "__lancy_cov_cov");
+            let Instruction::Target(X64Inst::Mov64mr { dst, .. }) = insts[at + 3] else {
+                panic!("{blk} stores its count, got {}", insts[at + 3]);
+            };
+            assert_eq!(i64::from(dst.disp), 8 * blk.index() as i64);
+        }
+    }
+
+    #[test]
+    fn counters_become_block_counts() {
+        let p = coverage_profile(&[5, 0, 3]);
+        assert_eq!(p.block_weight(Block::new(0)), Some(5));
+        assert_eq!(p.block_weight(Block::new(1)), Some(0));
+        assert_eq!(p.block_weight(Block::new(2)), Some(3));
+    }
+}
@@ -0,0 +1,222 @@
+//! Jump threading for the x64 target.
+//!
+//! **Requires:** Phi-free IR (run after `destroy_ssa`); every block
+//! terminated.
+//!
+//! **Preserves:** Semantics. Flags are never live across a block boundary
+//! in lancy IR, so skipping a block's compare is unobservable.
+//!
+//! **Invalidates:** Block numbering when unreachable blocks are removed;
+//! any `CFG` computed before the pass.
+//!
+//! **Effect:** For a block `B` that ends in `cmp/test; jcc` on operands
+//! that are constant along an incoming edge `P → B`, redirect that edge
+//! straight to the successor the `jcc` would pick. Constants come from
+//! `Mov64ri` defs in `P` (and in `B`'s prefix, for a `jmp` edge). If `P`
+//! ends in `jmp B`, up to `MAX_DUP_INSTS` of `B`'s leading instructions
+//! are duplicated into `P` so the threaded path still runs them. If `P`
+//! ends in a conditional branch, `B` must hold nothing but the compare
+//! and branch. Blocks left unreachable afterwards are deleted.
+
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::passes::remove_unreachable;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction, Reg};
+
+/// Most non-branch instructions copied into a predecessor per thread.
+const MAX_DUP_INSTS: usize = 4;
+
+/// Thread statically-decided branches in place. Returns `true` if any
+/// edge was redirected.
+pub fn thread_jumps(func: &mut Func<X64Inst>) -> bool {
+    let mut changed = false;
+    // Each thread removes one dynamic branch on some path; bound the
+    // rounds so pathological loops of constant compares can't spin.
+    for _ in 0..func.blocks_count() {
+        let mut round = false;
+        let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+        for p in blocks {
+            round |= thread_from(func, p);
+        }
+        if !round {
+            break;
+        }
+        changed = true;
+    }
+    if changed {
+        remove_unreachable(func);
+    }
+    changed
+}
+
+/// Try to thread each successor edge of `p`. Returns `true` on change.
+fn thread_from(func: &mut Func<X64Inst>, p: Block) -> bool {
+    let Some(Instruction::Target(term)) = func.get_block_data(p).get_terminator() else {
+        return false;
+    };
+    let (succs, is_jmp): (Vec<Block>, bool) = match term {
+        X64Inst::Jmp { dst } => (vec![dst], true),
+        X64Inst::CondJmp { taken, not_taken, .. } => (vec![taken, not_taken], false),
+        _ => return false,
+    };
+    for b in succs {
+        if b == p {
+            continue;
+        }
+        let Some(decision) = decide(func, p, b, is_jmp) else {
+            continue;
+        };
+        let bd = func.get_block_data(b).insts();
+        let prefix: Vec<Instruction<X64Inst>> = bd[..bd.len() - 2].to_vec();
+        let insts = func.get_block_data_mut(p).insts_mut();
+        let mut term = insts.pop().expect("terminated block");
+        insts.extend(prefix);
+        term.rewrite_branch_target(b, decision);
+        insts.push(term);
+        stat!("jump-threading", "threaded", "edges threaded past a decided branch");
+        return true;
+    }
+    false
+}
+
+/// If the edge `p → b` decides `b`'s branch, the block it goes to.
+fn decide(func: &Func<X64Inst>, p: Block, b: Block, is_jmp: bool) -> Option<Block> {
+    let insts = func.get_block_data(b).insts();
+    let n = insts.len();
+    if n < 2 {
+        return None;
+    }
+    let Instruction::Target(X64Inst::CondJmp { cond, taken, not_taken }) = insts[n - 1] else {
+        return None;
+    };
+    let Instruction::Target(cmp) = insts[n - 2] else {
+        return None;
+    };
+    let prefix = &insts[..n - 2];
+    if (!is_jmp && !prefix.is_empty()) || prefix.len() > MAX_DUP_INSTS {
+        return None;
+    }
+    // The prefix runs on the threaded path; it must be plain straight-line
+    // code, and the cmp operands are evaluated after it.
+    if prefix.iter().any(|i| i.is_term() || i.is_call() || is_phi(i)) {
+        return None;
+    }
+    let path: Vec<&Instruction<X64Inst>> = func
+        .get_block_data(p)
+        .insts()
+        .iter()
+        .chain(prefix)
+        .collect();
+    let known = |r: Reg| constant_at_end(&path, r);
+    let holds = match cmp {
+        X64Inst::Cmp64rr { lhs, rhs } => cond.holds(known(lhs)?, known(rhs)?),
+        X64Inst::Cmp64ri32 { lhs, imm } => cond.holds(known(lhs)?, i64::from(imm)),
+        X64Inst::Test64rr { lhs, rhs } => cond.holds(known(lhs)? & known(rhs)?, 0),
+        X64Inst::Test64ri32 { lhs, imm } => cond.holds(known(lhs)? & i64::from(imm), 0),
+        _ => return None,
+    };
+    let dst = if holds { taken } else { not_taken };
+    (dst != b).then_some(dst)
+}
+
+/// The constant `r` holds after `path` runs, if its last def is a
+/// `Mov64ri`.
+fn constant_at_end(path: &[&Instruction<X64Inst>], r: Reg) -> Option<i64> {
+    for inst in path.iter().rev() {
+        if inst.get_defs().contains(&r) {
+            return match inst {
+                Instruction::Target(X64Inst::Mov64ri { imm, .. }) => Some(*imm),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+fn is_phi(inst: &Instruction<X64Inst>) -> bool {
+    matches!(inst, Instruction::Pseudo(PseudoInstruction::Phi { .. }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::support::slotmap::Key;
+
+    /// `b0: v = imm; jmp b1`, `b1: cmp v, 5; jl b2, b3`, both leaves return.
+    fn const_into_compare(imm: i64) -> (Func<X64Inst>, [Block; 4]) {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm });
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Jmp { dst: b1 });
+        func.get_block_data_mut(b1)
+            .push_target_inst(X64Inst::Cmp64ri32 { lhs: v, imm: 5 });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::L,
+            taken: b2,
+            not_taken: b3,
+        });
+        for b in [b2, b3] {
+            func.get_block_data_mut(b)
+                .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        }
+        (func, [b0, b1, b2, b3])
+    }
+
+    fn jmp_target(func: &Func<X64Inst>, b: Block) -> Option<Block> {
+        match func.get_block_data(b).get_terminator()? {
+            Instruction::Target(X64Inst::Jmp { dst }) => Some(dst),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn constant_compare_is_threaded_and_dead_blocks_removed() {
+        let (mut func, [b0, ..]) = const_into_compare(3);
+        assert!(thread_jumps(&mut func));
+        // b1 and b3 become unreachable; survivors are b0 and the old b2.
+        assert_eq!(func.blocks_count(), 2);
+        assert_eq!(jmp_target(&func, b0), Some(Block::new(1)));
+    }
+
+    #[test]
+    fn false_outcome_goes_to_not_taken() {
+        let (mut func, [b0, ..]) = const_into_compare(9);
+        assert!(thread_jumps(&mut func));
+        assert_eq!(func.blocks_count(), 2);
+        // Old b3 is renumbered to 1 after b1 and b2 are deleted.
+        assert_eq!(jmp_target(&func, b0), Some(Block::new(1)));
+        assert!(func.get_block_data(Block::new(1)).get_terminator().unwrap().is_ret());
+    }
+
+    #[test]
+    fn unknown_operand_is_left_alone() {
+        let (mut func, [b0, ..]) = const_into_compare(3);
+        // Clobber v with a non-constant def before the jump.
+        let insts = func.get_block_data_mut(b0).insts_mut();
+        insts.insert(1, Instruction::Target(X64Inst::Add64rr { dst: 0, src: 0 }));
+        assert!(!thread_jumps(&mut func));
+        assert_eq!(func.blocks_count(), 4);
+    }
+
+    #[test]
+    fn small_prefix_is_duplicated_into_the_predecessor() {
+        let (mut func, [b0, b1, ..]) = const_into_compare(3);
+        let w = func.new_vreg();
+        func.get_block_data_mut(b1)
+            .insts_mut()
+            .insert(0, Instruction::Target(X64Inst::Mov64ri { dst: w, imm: 11 }));
+        assert!(thread_jumps(&mut func));
+        let insts = func.get_block_data(b0).insts();
+        assert!(matches!(
+            insts[1],
+            Instruction::Target(X64Inst::Mov64ri { dst, imm: 11 }) if dst == w
+        ));
+    }
+}
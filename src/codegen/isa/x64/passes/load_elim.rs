@@ -0,0 +1,389 @@
+//! Block-local redundant load elimination for the x64 target.
+//!
+//! **Requires:** Phi-free IR (run after `destroy_ssa`), before register
+//! allocation.
+//!
+//! **Preserves:** Semantics, block structure, and `RFLAGS` — the copies
+//! it introduces are flag-neutral moves.
+//!
+//! **Effect:** Walks each block remembering, per address, a vreg that
+//! holds the value last stored to or loaded from it. A later load of the
+//! same address at the same width becomes a register move from that
+//! vreg. Knowledge is dropped when the vreg or the address's base/index
+//! is redefined, when a store may alias the address, and at calls,
+//! atomics, string ops, fences, and other instructions with unknown memory effects.
+//!
+//! Stores only drop the addresses `AliasAnalysis` can't prove disjoint
+//! from theirs. Values live in pre-bound vregs are never reused, so no
+//! pinned live range grows.
+
+use std::collections::HashSet;
+
+use crate::codegen::isa::x64::alias::{AliasAnalysis, MemAccess};
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg};
+
+/// Replace redundant loads with moves. Returns `true` if anything changed.
+pub fn eliminate_redundant_loads(func: &mut Func<X64Inst>) -> bool {
+    let pinned: HashSet<Reg> = func.pre_binds().keys().copied().collect();
+    let aa = AliasAnalysis::compute(func);
+    let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut changed = false;
+    for b in blocks {
+        changed |= eliminate_in_block(func.get_block_data_mut(b).insts_mut(), &pinned, &aa);
+    }
+    changed
+}
+
+/// Access width; decides both the byte range and the move that replaces
+/// a forwarded load.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Width {
+    B8,
+    B16,
+    B32,
+    B64,
+    F32,
+    F64,
+    V128,
+}
+
+impl Width {
+    fn bytes(self) -> u32 {
+        match self {
+            Width::B8 => 1,
+            Width::B16 => 2,
+            Width::B32 | Width::F32 => 4,
+            Width::B64 | Width::F64 => 8,
+            Width::V128 => 16,
+        }
+    }
+
+    /// Register move with the same effect on `dst` as a load of this
+    /// width (a 32-bit move zero-extends just like the load does).
+    fn copy(self, dst: Reg, src: Reg) -> X64Inst {
+        match self {
+            Width::B8 => X64Inst::Mov8rr { dst, src },
+            Width::B16 => X64Inst::Mov16rr { dst, src },
+            Width::B32 => X64Inst::Mov32rr { dst, src },
+            Width::B64 => X64Inst::Mov64rr { dst, src },
+            Width::F32 => X64Inst::Movssrr { dst, src },
+            Width::F64 => X64Inst::Movsdrr { dst, src },
+            Width::V128 => X64Inst::Movapsrr { dst, src },
+        }
+    }
+}
+
+enum Access {
+    Load { dst: Reg, addr: Mem, width: Width },
+    Store { addr: Mem, src: Reg, width: Width },
+}
+
+fn access(t: &X64Inst) -> Option<Access> {
+    use Width::{B8, B16, B32, B64, F32, F64, V128};
+    let load = |dst: Reg, addr: Mem, width| Some(Access::Load { dst, addr, width });
+    let store = |addr: Mem, src: Reg, width| Some(Access::Store { addr, src, width });
+    match *t {
+        X64Inst::Mov64rm { dst, src } => load(dst, src, B64),
+        X64Inst::Mov32rm { dst, src } => load(dst, src, B32),
+        X64Inst::Mov16rm { dst, src } => load(dst, src, B16),
+        X64Inst::Mov8rm { dst, src } => load(dst, src, B8),
+        X64Inst::Movssrm { dst, src } => load(dst, src, F32),
+        X64Inst::Movsdrm { dst, src } => load(dst, src, F64),
+        X64Inst::Movdqurm { dst, src } => load(dst, src, V128),
+        X64Inst::Mov64mr { dst, src } => store(dst, src, B64),
+        X64Inst::Mov32mr { dst, src } => store(dst, src, B32),
+        X64Inst::Mov16mr { dst, src } => store(dst, src, B16),
+        X64Inst::Mov8mr { dst, src } => store(dst, src, B8),
+        X64Inst::Movssmr { dst, src } => store(dst, src, F32),
+        X64Inst::Movsdmr { dst, src } => store(dst, src, F64),
+        X64Inst::Movdqumr { dst, src } => store(dst, src, V128),
+        _ => None,
+    }
+}
+
+/// `true` if the instruction may write memory the pass can't see.
+fn clobbers_memory(inst: &Instruction<X64Inst>) -> bool {
+    match inst {
+        Instruction::Pseudo(p) => !matches!(
+            p,
+            PseudoInstruction::Copy { .. }
+                | PseudoInstruction::ImplicitDef { .. }
+                | PseudoInstruction::Kill { .. }
+                | PseudoInstruction::Arg { .. }
+                | PseudoInstruction::StackAlloc { .. }
+        ),
+        Instruction::Target(t) => matches!(
+            t,
+            X64Inst::Call64r { .. }
+                | X64Inst::LockXadd64mr { .. }
+                | X64Inst::LockCmpxchg64mr { .. }
+                | X64Inst::RepMovsb { .. }
+                | X64Inst::RepStosb { .. }
+                | X64Inst::Mfence
+                | X64Inst::StoreStackArg { .. }
+                | X64Inst::AdjustRsp { .. }
+        ),
+    }
+}
+
+/// A vreg known to hold the contents of `addr`.
+struct Avail {
+    addr: Mem,
+    width: Width,
+    value: Reg,
+}
+
+impl Avail {
+    fn access(&self) -> MemAccess {
+        MemAccess {
+            addr: self.addr,
+            size: self.width.bytes(),
+            store: false,
+        }
+    }
+}
+
+fn eliminate_in_block(
+    insts: &mut [Instruction<X64Inst>],
+    pinned: &HashSet<Reg>,
+    aa: &AliasAnalysis,
+) -> bool {
+    let mut avail: Vec<Avail> = Vec::new();
+    let mut changed = false;
+    for inst in insts.iter_mut() {
+        let acc = match inst {
+            Instruction::Target(t) => access(t),
+            Instruction::Pseudo(_) => None,
+        };
+        let mut forwarded = false;
+        if let Some(Access::Load { dst, addr, width }) = acc
+            && let Some(a) = avail.iter().find(|a| a.addr == addr && a.width == width)
+        {
+            *inst = Instruction::Target(width.copy(dst, a.value));
+            stat!("load-elim", "forwarded", "loads replaced by register moves");
+            forwarded = true;
+        }
+        changed |= forwarded;
+        if clobbers_memory(inst) {
+            avail.clear();
+        }
+        // A `Kill` ends its vreg; reusing it later would revive it.
+        let mut defs = inst.get_defs();
+        if let Instruction::Pseudo(PseudoInstruction::Kill { src }) = *inst {
+            defs.push(src);
+        }
+        avail.retain(|a| {
+            !defs
+                .iter()
+                .any(|d| *d == a.value || a.addr.get_uses().contains(d))
+        });
+        match acc {
+            Some(Access::Store { addr, src, width }) => {
+                let store = MemAccess {
+                    addr,
+                    size: width.bytes(),
+                    store: true,
+                };
+                avail.retain(|a| !aa.may_alias(&a.access(), &store));
+                if !pinned.contains(&src) {
+                    avail.push(Avail {
+                        addr,
+                        width,
+                        value: src,
+                    });
+                }
+            }
+            // Only an untouched load records a new value; a forwarded one
+            // is now a move and the original entry still stands.
+            Some(Access::Load { dst, addr, width })
+                if !forwarded && !pinned.contains(&dst) && !addr.get_uses().contains(&dst) =>
+            {
+                avail.push(Avail {
+                    addr,
+                    width,
+                    value: dst,
+                });
+            }
+            _ => {}
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::tir::Block;
+
+    fn block_insts(func: &Func<X64Inst>, b: Block) -> Vec<String> {
+        func.get_block_data(b)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn load_after_store_and_load_after_load_become_moves() {
+        let mut func = Func::<X64Inst>::new("fwd".to_string());
+        let b0 = func.add_empty_block();
+        let (p, x, y, z) = (
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+        );
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: p, idx: 0 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 1 });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base(p),
+            src: x,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: Mem::base(p),
+        });
+        bd.push_target_inst(X64Inst::Mov32rm {
+            dst: z,
+            src: Mem::base_disp(p, 8),
+        });
+        bd.push_target_inst(X64Inst::Mov32rm {
+            dst: y,
+            src: Mem::base_disp(p, 8),
+        });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: y });
+
+        assert!(eliminate_redundant_loads(&mut func));
+        let insts = block_insts(&func, b0);
+        assert_eq!(insts[3], X64Inst::Mov64rr { dst: y, src: x }.to_string());
+        assert_eq!(insts[5], X64Inst::Mov32rr { dst: y, src: z }.to_string());
+    }
+
+    #[test]
+    fn vector_loads_forward_whole_and_die_with_an_overlapping_store() {
+        use crate::codegen::tir::{ScalarType, Type};
+        let mut func = Func::<X64Inst>::new("vec".to_string());
+        let b0 = func.add_empty_block();
+        let (p, x) = (func.new_vreg(), func.new_vreg());
+        let v = Type::V128(ScalarType::I32);
+        let (a, c, d) = (
+            func.new_typed_vreg(v),
+            func.new_typed_vreg(v),
+            func.new_typed_vreg(v),
+        );
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: p, idx: 0 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 1 });
+        bd.push_target_inst(X64Inst::Movdqurm { dst: a, src: Mem::base(p) });
+        bd.push_target_inst(X64Inst::Movdqurm { dst: c, src: Mem::base(p) });
+        // Lands inside the 16 bytes at `[p]`.
+        bd.push_target_inst(X64Inst::Mov64mr { dst: Mem::base_disp(p, 8), src: x });
+        bd.push_target_inst(X64Inst::Movdqurm { dst: d, src: Mem::base(p) });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: x });
+
+        assert!(eliminate_redundant_loads(&mut func));
+        let insts = block_insts(&func, b0);
+        assert_eq!(insts[3], X64Inst::Movapsrr { dst: c, src: a }.to_string());
+        assert_eq!(insts[5], X64Inst::Movdqurm { dst: d, src: Mem::base(p) }.to_string());
+    }
+
+    #[test]
+    fn store_to_another_stack_slot_keeps_the_value() {
+        let mut func = Func::<X64Inst>::new("slots".to_string());
+        let b0 = func.add_empty_block();
+        let (s1, s2, x, y) = (
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+        );
+        let bd = func.get_block_data_mut(b0);
+        for dst in [s1, s2] {
+            bd.push_pseudo_inst(PseudoInstruction::StackAlloc { dst, size: 8, align: 8 });
+        }
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 0 });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base(s1),
+            src: x,
+        });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base(s2),
+            src: x,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: Mem::base(s1),
+        });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: y });
+
+        assert!(eliminate_redundant_loads(&mut func));
+        assert_eq!(block_insts(&func, b0)[5], format!("mov v{y}, v{x}"));
+    }
+
+    #[test]
+    fn aliasing_stores_and_calls_block_forwarding() {
+        let mut func = Func::<X64Inst>::new("alias".to_string());
+        let b0 = func.add_empty_block();
+        let (p, q, x, y, f) = (
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+        );
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: p, idx: 0 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: q, idx: 1 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 2 });
+        bd.push_pseudo_inst(PseudoInstruction::Arg { dst: f, idx: 3 });
+        // A store through another pointer may hit `[p]`.
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base(p),
+            src: x,
+        });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base(q),
+            src: x,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: Mem::base(p),
+        });
+        // A call may write anything.
+        bd.push_target_inst(X64Inst::Call64r { target: f });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: x,
+            src: Mem::base(p),
+        });
+        // A disjoint store at the same base doesn't hide `[p]` ...
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base_disp(p, 8),
+            src: y,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: Mem::base(p),
+        });
+        // ... but redefining the base does.
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: p, imm: 8 });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: Mem::base(p),
+        });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: y });
+
+        assert!(eliminate_redundant_loads(&mut func));
+        let insts = func.get_block_data(b0).insts();
+        let loads = insts
+            .iter()
+            .filter(|i| matches!(i, Instruction::Target(X64Inst::Mov64rm { .. })))
+            .count();
+        assert_eq!(loads, 3);
+        assert!(matches!(
+            insts[10],
+            Instruction::Target(X64Inst::Mov64rr { dst, src }) if dst == y && src == x
+        ));
+    }
+}
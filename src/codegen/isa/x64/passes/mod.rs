@@ -1 +1,11 @@
 pub mod abi_lower;
+pub mod branch_simplify;
+pub mod const_fold;
+pub mod coverage;
+pub mod jump_threading;
+pub mod load_elim;
+pub mod peephole;
+pub mod schedule;
+pub mod select_lower;
+pub mod stack_protect;
+pub mod switch_lower;
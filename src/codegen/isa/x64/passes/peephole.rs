@@ -0,0 +1,222 @@
+//! x64 peephole rules for the shared `Peephole` driver.
+//!
+//! Every rule here is flag-neutral: the deleting rules only drop
+//! instructions that leave both registers and `RFLAGS` unchanged, and
+//! `SinkCompare` only moves a `cmp`/`test` past instructions that neither
+//! read nor write `RFLAGS` (`X64Inst::writes_flags`), so a rewrite never
+//! separates a `cmp; jcc` pair with a flag clobber.
+
+use std::collections::HashSet;
+
+use smallvec::smallvec;
+
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::passes::peephole::{Peephole, PeepholeRule, Rewrite};
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg};
+
+/// The default x64 rule set, in the order the driver tries them.
+#[must_use]
+pub fn x64_peephole() -> Peephole<X64Inst> {
+    Peephole::new()
+        .with_rule(SelfMove)
+        .with_rule(ZeroShift)
+        .with_rule(MoveBack)
+}
+
+/// `x64_peephole` plus the rules that need to know `func`'s pre-binds.
+#[must_use]
+pub fn x64_peephole_for(func: &Func<X64Inst>) -> Peephole<X64Inst> {
+    x64_peephole().with_rule(SinkCompare::for_func(func))
+}
+
+/// `cmp a, b; X` → `X; cmp a, b` when `X` is flag-neutral and leaves
+/// `a` and `b` alone, so the compare drifts down onto the `jcc` it feeds
+/// and the pair macro-fuses (`X64Inst::fuses_with_jcc`). Typically `X` is
+/// a phi copy SSA destruction put before the terminator. Vregs the
+/// frontend pinned (`Func::pre_binds`) keep their order.
+pub struct SinkCompare {
+    pinned: HashSet<Reg>,
+}
+
+impl SinkCompare {
+    #[must_use]
+    pub fn for_func(func: &Func<X64Inst>) -> Self {
+        Self {
+            pinned: func.pre_binds().keys().copied().collect(),
+        }
+    }
+}
+
+impl PeepholeRule<X64Inst> for SinkCompare {
+    fn name(&self) -> &'static str {
+        "sink-compare"
+    }
+
+    fn window(&self) -> usize {
+        2
+    }
+
+    fn apply(&self, w: &[Instruction<X64Inst>]) -> Option<Rewrite<X64Inst>> {
+        let Instruction::Target(cmp) = w[0] else {
+            return None;
+        };
+        let neutral = match w[1] {
+            Instruction::Target(t) => !t.is_term() && !t.reads_flags() && !t.writes_flags(),
+            Instruction::Pseudo(p) => matches!(p, PseudoInstruction::Copy { .. }),
+        };
+        let uses = cmp.get_uses();
+        let clobbers = w[1].get_defs().iter().any(|d| uses.contains(d));
+        let pinned = w
+            .iter()
+            .flat_map(|i| i.get_uses().into_iter().chain(i.get_defs()))
+            .any(|r| self.pinned.contains(&r));
+        (cmp.fuses_with_jcc() && neutral && !clobbers && !pinned).then(|| smallvec![w[1], w[0]])
+    }
+}
+
+/// `mov r, r` → nothing. Only full-width moves: `mov r32, r32` zeroes
+/// the upper half and is not a no-op.
+pub struct SelfMove;
+
+impl PeepholeRule<X64Inst> for SelfMove {
+    fn name(&self) -> &'static str {
+        "self-move"
+    }
+
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn apply(&self, w: &[Instruction<X64Inst>]) -> Option<Rewrite<X64Inst>> {
+        match w[0] {
+            Instruction::Target(
+                X64Inst::Mov64rr { dst, src } | X64Inst::Movsdrr { dst, src },
+            ) if dst == src => Some(smallvec![]),
+            _ => None,
+        }
+    }
+}
+
+/// Shift by an immediate 0 → nothing. x86 leaves flags untouched when
+/// the masked count is zero.
+pub struct ZeroShift;
+
+impl PeepholeRule<X64Inst> for ZeroShift {
+    fn name(&self) -> &'static str {
+        "zero-shift"
+    }
+
+    fn window(&self) -> usize {
+        1
+    }
+
+    fn apply(&self, w: &[Instruction<X64Inst>]) -> Option<Rewrite<X64Inst>> {
+        match w[0] {
+            Instruction::Target(
+                X64Inst::Shl64ri8 { imm, .. }
+                | X64Inst::Shr64ri8 { imm, .. }
+                | X64Inst::Sar64ri8 { imm, .. },
+            ) if imm % 64 == 0 => Some(smallvec![]),
+            _ => None,
+        }
+    }
+}
+
+/// `mov a, b; mov b, a` → `mov a, b`: after the first move both already
+/// hold the same value.
+pub struct MoveBack;
+
+impl PeepholeRule<X64Inst> for MoveBack {
+    fn name(&self) -> &'static str {
+        "move-back"
+    }
+
+    fn window(&self) -> usize {
+        2
+    }
+
+    fn apply(&self, w: &[Instruction<X64Inst>]) -> Option<Rewrite<X64Inst>> {
+        match (w[0], w[1]) {
+            (
+                Instruction::Target(X64Inst::Mov64rr { dst: a, src: b }),
+                Instruction::Target(X64Inst::Mov64rr { dst: c, src: d }),
+            ) if c == b && d == a => Some(smallvec![w[0]]),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::tir::{Func, PseudoInstruction};
+
+    #[test]
+    fn x64_rules_drop_no_op_moves_and_shifts() {
+        let mut func = Func::<X64Inst>::new("noops".to_string());
+        let b0 = func.add_empty_block();
+        let a = func.new_vreg();
+        let b = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: b, src: a });
+        bd.push_target_inst(X64Inst::Shl64ri8 { dst: b, imm: 0 });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: a, src: b });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: a, src: a });
+        bd.push_target_inst(X64Inst::Shl64ri8 { dst: a, imm: 1 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: a });
+
+        // The zero shift goes first, which exposes the move-back pair.
+        assert_eq!(x64_peephole().run(&mut func), 3);
+        assert_eq!(func.get_block_data(b0).len(), 4);
+    }
+
+    #[test]
+    fn narrow_self_move_is_kept() {
+        let mut func = Func::<X64Inst>::new("zext".to_string());
+        let b0 = func.add_empty_block();
+        let a = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov32rr { dst: a, src: a });
+        assert_eq!(x64_peephole().run(&mut func), 0);
+    }
+
+    #[test]
+    fn compare_sinks_past_copies_onto_its_branch() {
+        use crate::codegen::isa::x64::inst::Cond;
+        use crate::codegen::isa::x64::regs::RCX;
+
+        let mut func = Func::<X64Inst>::new("fuse".to_string());
+        let b0 = func.add_empty_block();
+        let [a, b, c, d, pin] = [(); 5].map(|()| func.new_vreg());
+        func.pre_bind(pin, RCX);
+        let bd = func.get_block_data_mut(b0);
+        bd.push_target_inst(X64Inst::Cmp64rr { lhs: a, rhs: b });
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: c, src: a });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: d, imm: 7 });
+        bd.push_target_inst(X64Inst::CondJmp {
+            cond: Cond::L,
+            taken: b0,
+            not_taken: b0,
+        });
+        assert_eq!(x64_peephole_for(&func).run(&mut func), 2);
+        assert!(matches!(
+            func.get_block_data(b0).insts()[2],
+            Instruction::Target(X64Inst::Cmp64rr { .. })
+        ));
+
+        // Blocked by a def of an operand, a flag write, and a pinned vreg.
+        for blocker in [
+            X64Inst::Mov64ri { dst: a, imm: 0 },
+            X64Inst::Add64ri32 { dst: c, imm: 1 },
+            X64Inst::Mov64ri { dst: pin, imm: 0 },
+        ] {
+            let insts = vec![
+                Instruction::Target(X64Inst::Test64rr { lhs: a, rhs: a }),
+                Instruction::Target(blocker),
+            ];
+            func.replace_insts(b0, insts);
+            assert_eq!(x64_peephole_for(&func).run(&mut func), 0, "{blocker}");
+        }
+    }
+}
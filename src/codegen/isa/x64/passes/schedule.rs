@@ -0,0 +1,510 @@
+//! Pre-allocation list scheduling for the x64 target.
+//!
+//! **Requires:** Phi-free IR (run after `destroy_ssa`), before register
+//! allocation. Only frontend pre-binds (`Func::pre_binds`) are known yet;
+//! run it before ABI lowering adds more.
+//!
+//! **Preserves:** Semantics, block structure, and every instruction's
+//! operands — only the order inside a block changes.
+//!
+//! **Effect:** Builds a dependency DAG per block and list-schedules it.
+//! Edges cover register RAW/WAR/WAW (a `Kill` counts as a def of the
+//! register it ends), `RFLAGS` the same way, and memory (a store stays
+//! ordered with every access `AliasAnalysis` can't prove disjoint from
+//! it; loads may pass loads). Calls,
+//! division, fences, atomics, terminators, and every pseudo other than
+//! `Copy`, `ImplicitDef`, and `Kill` are barriers. Instructions that touch a
+//! pre-bound vreg keep their relative order, so two values pinned to the
+//! same register never become live at once.
+//!
+//! Among ready instructions the scheduler takes the one with the longest
+//! latency-weighted path to the block end, hiding load and multiply
+//! latency. Once `PRESSURE_LIMIT` block-local values are live it switches
+//! to whichever ready instruction grows that count least. A `cmp`/`test`
+//! feeding the block's `jcc` (`X64Inst::fuses_with_jcc`) is held back
+//! while anything else is ready, so it lands right before the branch and
+//! the pair macro-fuses.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::codegen::isa::x64::alias::{AliasAnalysis, MemAccess, mem_access};
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg};
+use crate::support::slotmap::Key;
+
+/// Live block-local values above which the scheduler favours freeing
+/// registers over latency. Below the eleven allocatable GPRs, leaving
+/// room for values live across the block.
+const PRESSURE_LIMIT: usize = 8;
+
+/// Reorder instructions within each block. Returns `true` if any block's
+/// order changed.
+pub fn schedule_blocks(func: &mut Func<X64Inst>) -> bool {
+    // Values read outside the block that defines them never die locally.
+    let mut use_blocks: HashMap<Reg, HashSet<usize>> = HashMap::new();
+    for (b, bd) in func.blocks_iter() {
+        for inst in bd.iter() {
+            for r in inst.get_uses() {
+                use_blocks.entry(r).or_default().insert(b.index());
+            }
+        }
+    }
+    let global: HashSet<Reg> = use_blocks
+        .into_iter()
+        .filter(|(_, bs)| bs.len() > 1)
+        .map(|(r, _)| r)
+        .collect();
+    let pinned: HashSet<Reg> = func.pre_binds().keys().copied().collect();
+    let aa = AliasAnalysis::compute(func);
+
+    let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut changed = false;
+    for b in blocks {
+        let insts = func.get_block_data(b).insts();
+        let order = schedule(insts, &pinned, &global, &aa);
+        if order.iter().enumerate().any(|(i, &o)| i != o) {
+            let mut sorted = func.inst_buffer(order.len());
+            sorted.extend(order.iter().map(|&i| func.get_block_data(b).insts()[i]));
+            func.replace_insts(b, sorted);
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[derive(Default)]
+#[allow(clippy::struct_excessive_bools)]
+struct Effects {
+    reads_flags: bool,
+    writes_flags: bool,
+    barrier: bool,
+}
+
+fn effects(inst: &Instruction<X64Inst>) -> Effects {
+    let t = match inst {
+        Instruction::Pseudo(
+            PseudoInstruction::Copy { .. }
+            | PseudoInstruction::ImplicitDef { .. }
+            | PseudoInstruction::Kill { .. },
+        ) => return Effects::default(),
+        Instruction::Pseudo(_) => {
+            return Effects {
+                barrier: true,
+                ..Effects::default()
+            };
+        }
+        Instruction::Target(t) => t,
+    };
+    let mut e = Effects::default();
+    match t {
+        X64Inst::Call64r { .. }
+        | X64Inst::Jmp { .. }
+        | X64Inst::CondJmp { .. }
+        | X64Inst::Jmp64r { .. }
+        | X64Inst::JmpTable { .. }
+        | X64Inst::Ud2
+        | X64Inst::Int3
+        | X64Inst::Mfence
+        | X64Inst::LoadArgFromStack { .. }
+        | X64Inst::StoreStackArg { .. }
+        | X64Inst::AdjustRsp { .. }
+        | X64Inst::RawRet
+        | X64Inst::LockXadd64mr { .. }
+        | X64Inst::LockCmpxchg64mr { .. }
+        | X64Inst::RepMovsb { .. }
+        | X64Inst::RepStosb { .. }
+        // Division can trap; keep it where the program put it.
+        | X64Inst::Idiv64r { .. }
+        | X64Inst::Div64r { .. } => e.barrier = true,
+        _ => {
+            e.reads_flags = t.reads_flags();
+            e.writes_flags = t.writes_flags();
+        }
+    }
+    e
+}
+
+/// Rough result latency in cycles, used only to rank ready instructions.
+fn latency(inst: &Instruction<X64Inst>) -> u32 {
+    match inst {
+        Instruction::Target(
+            X64Inst::Mov64rm { .. }
+            | X64Inst::Mov32rm { .. }
+            | X64Inst::Mov16rm { .. }
+            | X64Inst::Mov8rm { .. }
+            | X64Inst::Movssrm { .. }
+            | X64Inst::Movsdrm { .. }
+            | X64Inst::Movdqurm { .. },
+        ) => 4,
+        Instruction::Target(
+            X64Inst::Imul64rr { .. }
+            | X64Inst::Addssrr { .. }
+            | X64Inst::Subssrr { .. }
+            | X64Inst::Addsdrr { .. }
+            | X64Inst::Subsdrr { .. },
+        ) => 3,
+        Instruction::Target(
+            X64Inst::Mulssrr { .. } | X64Inst::Mulsdrr { .. } | X64Inst::Vmulrr { .. },
+        ) => 4,
+        Instruction::Target(X64Inst::Divssrr { .. } | X64Inst::Divsdrr { .. }) => 12,
+        _ => 1,
+    }
+}
+
+/// Per-resource bookkeeping while building the DAG: the last writer and
+/// the readers since.
+#[derive(Default)]
+struct Resource {
+    writer: Option<usize>,
+    readers: Vec<usize>,
+}
+
+impl Resource {
+    fn read(&mut self, i: usize, preds: &mut BTreeSet<usize>) {
+        preds.extend(self.writer);
+        self.readers.push(i);
+    }
+
+    fn write(&mut self, i: usize, preds: &mut BTreeSet<usize>) {
+        preds.extend(self.writer);
+        // A two-address op reads its own destination first.
+        preds.extend(self.readers.drain(..).filter(|&r| r != i));
+        self.writer = Some(i);
+    }
+}
+
+/// Defs as the scheduler sees them: a `Kill` "redefines" its register so
+/// no other use of it can move past the kill.
+fn sched_defs(inst: &Instruction<X64Inst>) -> Vec<Reg> {
+    match inst {
+        Instruction::Pseudo(PseudoInstruction::Kill { src }) => vec![*src],
+        _ => inst.get_defs().to_vec(),
+    }
+}
+
+/// Index of the `cmp`/`test` whose flags only the block's closing `jcc`
+/// reads, if it can macro-fuse with it.
+fn fused_compare(insts: &[Instruction<X64Inst>]) -> Option<usize> {
+    let (last, body) = insts.split_last()?;
+    if !matches!(last, Instruction::Target(X64Inst::CondJmp { .. })) {
+        return None;
+    }
+    let (i, producer) = body
+        .iter()
+        .enumerate()
+        .rev()
+        .find(|(_, inst)| {
+            let e = effects(inst);
+            e.writes_flags || e.barrier
+        })?;
+    let Instruction::Target(t) = producer else {
+        return None;
+    };
+    let read_since = body[i + 1..].iter().any(|inst| effects(inst).reads_flags);
+    (t.fuses_with_jcc() && !read_since).then_some(i)
+}
+
+/// New order for `insts`, as indices into it.
+fn schedule(
+    insts: &[Instruction<X64Inst>],
+    pinned: &HashSet<Reg>,
+    global: &HashSet<Reg>,
+    aa: &AliasAnalysis,
+) -> Vec<usize> {
+    let n = insts.len();
+    let mut preds: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); n];
+    let mut regs: HashMap<Reg, Resource> = HashMap::new();
+    let mut flags = Resource::default();
+    // Plain loads and stores since the last barrier.
+    let mut accesses: Vec<(usize, MemAccess)> = Vec::new();
+    let mut last_barrier: Option<usize> = None;
+    let mut since_barrier: Vec<usize> = Vec::new();
+    let mut last_pinned: Option<usize> = None;
+    for (i, inst) in insts.iter().enumerate() {
+        let p = &mut preds[i];
+        let e = effects(inst);
+        let uses = inst.get_uses();
+        let defs = sched_defs(inst);
+        for &r in &uses {
+            regs.entry(r).or_default().read(i, p);
+        }
+        for &r in &defs {
+            regs.entry(r).or_default().write(i, p);
+        }
+        if e.reads_flags {
+            flags.read(i, p);
+        }
+        if e.writes_flags {
+            flags.write(i, p);
+        }
+        if let Instruction::Target(t) = inst
+            && let Some(acc) = mem_access(t)
+        {
+            p.extend(
+                accesses
+                    .iter()
+                    .filter(|(_, prev)| (acc.store || prev.store) && aa.may_alias(&acc, prev))
+                    .map(|&(j, _)| j),
+            );
+            accesses.push((i, acc));
+        }
+        if uses.iter().chain(&defs).any(|r| pinned.contains(r)) {
+            p.extend(last_pinned);
+            last_pinned = Some(i);
+        }
+        p.extend(last_barrier);
+        if e.barrier {
+            p.extend(since_barrier.drain(..));
+            last_barrier = Some(i);
+            accesses.clear();
+        } else {
+            since_barrier.push(i);
+        }
+    }
+
+    let mut succs: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for (i, ps) in preds.iter().enumerate() {
+        for &p in ps {
+            succs[p].push(i);
+        }
+    }
+    let mut height = vec![0u32; n];
+    for i in (0..n).rev() {
+        let below = succs[i].iter().map(|&s| height[s]).max().unwrap_or(0);
+        height[i] = latency(&insts[i]) + below;
+    }
+
+    // Block-local use counts drive the pressure estimate.
+    let mut remaining: HashMap<Reg, usize> = HashMap::new();
+    for inst in insts {
+        for r in inst.get_uses() {
+            *remaining.entry(r).or_default() += 1;
+        }
+    }
+    let fused = fused_compare(insts);
+    let mut live: HashSet<Reg> = HashSet::new();
+    let mut indegree: Vec<usize> = preds.iter().map(BTreeSet::len).collect();
+    let mut ready: BTreeSet<usize> = (0..n).filter(|&i| indegree[i] == 0).collect();
+    let mut order = Vec::with_capacity(n);
+    while !ready.is_empty() {
+        let growth = |i: usize| {
+            let born = insts[i]
+                .get_defs()
+                .iter()
+                .filter(|r| !live.contains(r) && remaining.get(r).is_some_and(|&c| c > 0))
+                .count();
+            let dying = insts[i]
+                .get_uses()
+                .iter()
+                .filter(|r| live.contains(r) && !global.contains(r) && remaining[r] == 1)
+                .count();
+            born as isize - dying as isize
+        };
+        // `ready` iterates in program order, and `max_by_key` keeps the
+        // last maximum, so ties resolve to the earliest instruction.
+        let candidates = ready
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&i| ready.len() == 1 || Some(i) != fused);
+        let pick = if live.len() >= PRESSURE_LIMIT {
+            candidates.max_by_key(|&i| (-growth(i), height[i]))
+        } else {
+            candidates.max_by_key(|&i| height[i])
+        }
+        .expect("ready set is non-empty");
+        ready.remove(&pick);
+        order.push(pick);
+        for r in insts[pick].get_uses() {
+            let c = remaining.get_mut(&r).expect("counted above");
+            *c -= 1;
+            if *c == 0 && !global.contains(&r) {
+                live.remove(&r);
+            }
+        }
+        for r in insts[pick].get_defs() {
+            if remaining.get(&r).is_some_and(|&c| c > 0) {
+                live.insert(r);
+            }
+        }
+        for &s in &succs[pick] {
+            indegree[s] -= 1;
+            if indegree[s] == 0 {
+                ready.insert(s);
+            }
+        }
+    }
+    debug_assert_eq!(order.len(), n, "dependency cycle in block DAG");
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::{Cond, Mem};
+    use crate::codegen::tir::Block;
+
+    fn mem(base: Reg) -> Mem {
+        Mem {
+            base,
+            index: None,
+            scale: 1,
+            disp: 0,
+        }
+    }
+
+    fn position(func: &Func<X64Inst>, b: Block, f: impl Fn(&X64Inst) -> bool) -> usize {
+        func.get_block_data(b)
+            .iter()
+            .position(|i| matches!(i, Instruction::Target(t) if f(t)))
+            .expect("instruction present")
+    }
+
+    #[test]
+    fn independent_load_is_hoisted_above_alu_work() {
+        let mut func = Func::<X64Inst>::new("s".to_string());
+        let b0 = func.add_empty_block();
+        let (p, x, y) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: p });
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: x });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: x, imm: 1 });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: mem(p),
+        });
+        bd.push_target_inst(X64Inst::Add64rr { dst: x, src: y });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: x });
+
+        assert!(schedule_blocks(&mut func));
+        let load = position(&func, b0, |t| matches!(t, X64Inst::Mov64rm { .. }));
+        let add1 = position(&func, b0, |t| matches!(t, X64Inst::Add64ri32 { .. }));
+        assert!(load < add1);
+        assert!(func.get_block_data(b0).get_terminator().unwrap().is_ret());
+    }
+
+    #[test]
+    fn flags_and_stores_keep_their_order() {
+        let mut func = Func::<X64Inst>::new("f".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let (p, a, c) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: p });
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: mem(p),
+            src: a,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: c,
+            src: mem(p),
+        });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: a, imm: 1 });
+        bd.push_target_inst(X64Inst::Cmp64ri32 { lhs: c, imm: 0 });
+        bd.push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b1,
+        });
+        func.get_block_data_mut(b1)
+            .push_pseudo_inst(PseudoInstruction::Return { src: a });
+
+        schedule_blocks(&mut func);
+        let store = position(&func, b0, |t| matches!(t, X64Inst::Mov64mr { .. }));
+        let load = position(&func, b0, |t| matches!(t, X64Inst::Mov64rm { .. }));
+        let cmp = position(&func, b0, |t| matches!(t, X64Inst::Cmp64ri32 { .. }));
+        let add = position(&func, b0, |t| matches!(t, X64Inst::Add64ri32 { .. }));
+        assert!(store < load);
+        // The add writes flags too; it must not move between the compare
+        // and the branch that reads it.
+        assert!(add < cmp);
+        assert_eq!(cmp, func.get_block_data(b0).len() - 2);
+    }
+
+    #[test]
+    fn load_passes_a_store_to_disjoint_bytes() {
+        let mut func = Func::<X64Inst>::new("d".to_string());
+        let b0 = func.add_empty_block();
+        let (p, a, c) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: p });
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: a, imm: 1 });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: mem(p),
+            src: a,
+        });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: c,
+            src: Mem::base_disp(p, 8),
+        });
+        bd.push_target_inst(X64Inst::Add64rr { dst: c, src: a });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: c });
+
+        assert!(schedule_blocks(&mut func));
+        let store = position(&func, b0, |t| matches!(t, X64Inst::Mov64mr { .. }));
+        let load = position(&func, b0, |t| matches!(t, X64Inst::Mov64rm { .. }));
+        assert!(load < store);
+    }
+
+    #[test]
+    fn pinned_vregs_are_not_interleaved() {
+        use crate::codegen::isa::x64::regs::RCX;
+        let mut func = Func::<X64Inst>::new("pins".to_string());
+        let b0 = func.add_empty_block();
+        let (x, c1, c2) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        func.pre_bind(c1, RCX);
+        func.pre_bind(c2, RCX);
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: x });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: c1, imm: 1 });
+        bd.push_target_inst(X64Inst::Shl64rcl { dst: x, count: c1 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: c2, imm: 2 });
+        bd.push_target_inst(X64Inst::Shl64rcl { dst: x, count: c2 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: x });
+
+        schedule_blocks(&mut func);
+        let insts = func.get_block_data(b0).insts();
+        let def2 = insts
+            .iter()
+            .position(|i| i.get_defs().contains(&c2))
+            .unwrap();
+        let use1 = insts
+            .iter()
+            .position(|i| i.get_uses().contains(&c1))
+            .unwrap();
+        assert!(use1 < def2);
+    }
+
+    #[test]
+    fn compare_is_held_back_onto_its_branch() {
+        let mut func = Func::<X64Inst>::new("fuse".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let (p, a, y) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: p });
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Cmp64ri32 { lhs: a, imm: 0 });
+        bd.push_target_inst(X64Inst::Mov64rm {
+            dst: y,
+            src: mem(p),
+        });
+        bd.push_target_inst(X64Inst::Mov64mr {
+            dst: Mem::base_disp(p, 8),
+            src: y,
+        });
+        bd.push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b1,
+        });
+        func.get_block_data_mut(b1)
+            .push_pseudo_inst(PseudoInstruction::Return { src: a });
+
+        assert!(schedule_blocks(&mut func));
+        let cmp = position(&func, b0, |t| matches!(t, X64Inst::Cmp64ri32 { .. }));
+        assert_eq!(cmp, func.get_block_data(b0).len() - 2);
+    }
+}
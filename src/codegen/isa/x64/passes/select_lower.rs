@@ -0,0 +1,398 @@
+//! Cost-based lowering of `X64Inst::Select`.
+//!
+//! **Requires:** Before SSA destruction — a branch diamond merges its two
+//! values with a `Phi`.
+//!
+//! **Preserves:** Semantics. Blocks other than the ones split keep their
+//! contents.
+//!
+//! **Invalidates:** Block numbering when a select becomes a diamond: the
+//! new blocks are laid out right after the block they came from. Any
+//! `CFG` or liveness computed before the pass is stale.
+//!
+//! **Effect:** Each select becomes either `copy dst, fval; cmp; cmov`
+//! (always, when branches are off) or a diamond `cmp; jcc` → two arms →
+//! a join whose phi picks the value. A diamond pays off when the
+//! condition is predictable (the select carries a hint; the arms inherit
+//! it as `BlockHint`s) or when a value operand is costly and only the
+//! select needs it: its definition earlier in the block (a load, by
+//! `cost`) is sunk into its arm, so the path not taken never computes it.
+
+use std::collections::HashMap;
+
+use crate::codegen::isa::x64::alias::mem_access;
+use crate::codegen::isa::x64::inst::X64Inst;
+use crate::codegen::tir::{Block, BlockHint, Func, Inst, Instruction, PseudoInstruction, Reg};
+
+/// Cycles a sunk operand must cost before it justifies a branch.
+const SINK_COST: u32 = 3;
+
+/// Lower every select; with `branches` off, always to `cmov`. Returns
+/// `true` if the function had any.
+pub fn lower_selects(func: &mut Func<X64Inst>, branches: bool) -> bool {
+    let mut uses: HashMap<Reg, usize> = HashMap::new();
+    let mut defs: HashMap<Reg, usize> = HashMap::new();
+    for (_, bd) in func.blocks_iter() {
+        for inst in bd.iter() {
+            for r in inst.get_uses() {
+                *uses.entry(r).or_default() += 1;
+            }
+            for r in inst.get_defs() {
+                *defs.entry(r).or_default() += 1;
+            }
+        }
+    }
+    let single = |r: Reg| uses.get(&r) == Some(&1) && defs.get(&r) == Some(&1);
+
+    let original: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut followers: HashMap<Block, Vec<Block>> = HashMap::new();
+    let mut work = original.clone();
+    let mut changed = false;
+    while let Some(b) = work.pop() {
+        let Some(pos) = func
+            .get_block_data(b)
+            .iter()
+            .position(|inst| matches!(inst, Instruction::Target(X64Inst::Select { .. })))
+        else {
+            continue;
+        };
+        changed = true;
+        let insts = func.get_block_data(b).insts();
+        let Instruction::Target(sel) = insts[pos] else {
+            unreachable!("position matched a target select");
+        };
+        let X64Inst::Select {
+            tval, fval, hint, ..
+        } = sel
+        else {
+            unreachable!("position matched a select");
+        };
+        let sink = |v: Reg| sinkable(insts, pos, v).filter(|_| single(v) && tval != fval);
+        let (t_def, f_def) = (sink(tval), sink(fval));
+        let worth = |d: Option<usize>| d.is_some_and(|i| cost(&insts[i]) >= SINK_COST);
+        if branches && (hint.is_some() || worth(t_def) || worth(f_def)) {
+            let [taken, not_taken, join] = split_into_diamond(func, b, pos, t_def, f_def);
+            // `not_taken` first, so the `jcc` falls into it.
+            followers.insert(b, vec![not_taken, taken, join]);
+            work.push(join);
+        } else {
+            lower_to_cmov(func, b, pos);
+            work.push(b);
+        }
+    }
+    if !followers.is_empty() {
+        let mut order = Vec::with_capacity(func.blocks_count());
+        for b in original {
+            place(b, &followers, &mut order);
+        }
+        func.reorder_blocks(&order);
+    }
+    changed
+}
+
+/// `b`, then the blocks split off it (and off those), depth first.
+pub(super) fn place(b: Block, followers: &HashMap<Block, Vec<Block>>, order: &mut Vec<Block>) {
+    order.push(b);
+    for &f in followers.get(&b).into_iter().flatten() {
+        place(f, followers, order);
+    }
+}
+
+fn lower_to_cmov(func: &mut Func<X64Inst>, b: Block, pos: usize) {
+    let insts = func.get_block_data_mut(b).insts_mut();
+    let Instruction::Target(X64Inst::Select {
+        cond,
+        dst,
+        lhs,
+        rhs,
+        tval,
+        fval,
+        ..
+    }) = insts[pos]
+    else {
+        unreachable!("lower_to_cmov on a non-select");
+    };
+    insts.splice(
+        pos..=pos,
+        [
+            Instruction::Pseudo(PseudoInstruction::Copy { dst, src: fval }),
+            Instruction::Target(X64Inst::Cmp64rr { lhs, rhs }),
+            Instruction::Target(X64Inst::Cmov64rr {
+                cond,
+                dst,
+                src: tval,
+            }),
+        ],
+    );
+}
+
+/// Split `b` at the select at `pos` into `b: cmp; jcc`, a taken and a
+/// not-taken arm holding the sunk definitions `t_def` / `f_def`, and a
+/// join starting with the phi. Returns the arms and the join.
+fn split_into_diamond(
+    func: &mut Func<X64Inst>,
+    b: Block,
+    pos: usize,
+    t_def: Option<usize>,
+    f_def: Option<usize>,
+) -> [Block; 3] {
+    let mut head = std::mem::take(func.get_block_data_mut(b).insts_mut());
+    let mut tail = head.split_off(pos);
+    let Instruction::Target(X64Inst::Select {
+        cond,
+        dst,
+        lhs,
+        rhs,
+        tval,
+        fval,
+        hint,
+    }) = tail.remove(0)
+    else {
+        unreachable!("split_into_diamond on a non-select");
+    };
+    // Later index first, so the earlier one stays valid.
+    let (mut t_inst, mut f_inst) = (None, None);
+    let mut sunk: Vec<(usize, bool)> = [t_def.map(|i| (i, true)), f_def.map(|i| (i, false))]
+        .into_iter()
+        .flatten()
+        .collect();
+    sunk.sort_unstable_by_key(|&(i, _)| std::cmp::Reverse(i));
+    for (i, is_taken) in sunk {
+        let inst = Some(head.remove(i));
+        if is_taken {
+            t_inst = inst;
+        } else {
+            f_inst = inst;
+        }
+    }
+
+    let taken = func.add_empty_block();
+    let not_taken = func.add_empty_block();
+    let join = func.add_empty_block();
+    head.push(Instruction::Target(X64Inst::Cmp64rr { lhs, rhs }));
+    head.push(Instruction::Target(X64Inst::CondJmp {
+        cond,
+        taken,
+        not_taken,
+    }));
+    func.replace_insts(b, head);
+    for (arm, def) in [(taken, t_inst), (not_taken, f_inst)] {
+        let bd = func.get_block_data_mut(arm);
+        if let Some(def) = def {
+            bd.push_inst(def);
+        }
+        bd.push_inst(Instruction::new_jmp(join));
+    }
+    let succs = tail.last().map(|t| func.branch_targets(t)).unwrap_or_default();
+    let id = func.new_phi(vec![(taken, tval), (not_taken, fval)]);
+    tail.insert(0, Instruction::Pseudo(PseudoInstruction::Phi { dst, id }));
+    func.replace_insts(join, tail);
+    for s in succs {
+        retarget_phis(func, s, b, join);
+    }
+    if let Some(h) = hint {
+        let other = match h {
+            BlockHint::Likely => BlockHint::Unlikely,
+            BlockHint::Unlikely => BlockHint::Likely,
+        };
+        func.set_block_hint(taken, Some(h));
+        func.set_block_hint(not_taken, Some(other));
+    }
+    [taken, not_taken, join]
+}
+
+/// Index of the instruction before `pos` defining `v` if it can move down
+/// into an arm: a side-effect-free target op defining only `v`, with no
+/// redefinition of its inputs, flag reader, or (for a load) memory write
+/// between it and the select.
+fn sinkable(insts: &[Instruction<X64Inst>], pos: usize, v: Reg) -> Option<usize> {
+    let i = insts[..pos]
+        .iter()
+        .rposition(|inst| inst.get_defs().contains(&v))?;
+    let Instruction::Target(def) = insts[i] else {
+        return None;
+    };
+    let is_load = mem_access(&def).is_some_and(|a| !a.store);
+    if def.get_defs().len() != 1 || !(is_load || is_pure_alu(&def)) {
+        return None;
+    }
+    let inputs = def.get_uses();
+    let clear = insts[i + 1..pos].iter().all(|inst| {
+        let redefines = inst.get_defs().iter().any(|r| inputs.contains(r));
+        let (reads_flags, writes_memory) = match inst {
+            Instruction::Target(t) => (
+                t.reads_flags(),
+                t.is_call() || mem_access(t).map_or(is_memory_op(t), |a| a.store),
+            ),
+            Instruction::Pseudo(p) => (
+                false,
+                !matches!(
+                    p,
+                    PseudoInstruction::Copy { .. }
+                        | PseudoInstruction::ImplicitDef { .. }
+                        | PseudoInstruction::Kill { .. }
+                ),
+            ),
+        };
+        !(redefines || (def.writes_flags() && reads_flags) || (is_load && writes_memory))
+    });
+    clear.then_some(i)
+}
+
+/// Single-def ops with no effect but their register result. Two-address
+/// ops never qualify: their destination is defined twice.
+fn is_pure_alu(t: &X64Inst) -> bool {
+    matches!(
+        t,
+        X64Inst::Mov64ri { .. }
+            | X64Inst::Mov64rsym { .. }
+            | X64Inst::Lea64rm { .. }
+            | X64Inst::Movsx64r8 { .. }
+            | X64Inst::Movsx64r16 { .. }
+            | X64Inst::Movsxd64r32 { .. }
+            | X64Inst::Movzx64r8 { .. }
+            | X64Inst::Movzx64r16 { .. }
+    )
+}
+
+/// Memory traffic `mem_access` doesn't describe as a plain access.
+fn is_memory_op(t: &X64Inst) -> bool {
+    matches!(
+        t,
+        X64Inst::LockXadd64mr { .. }
+            | X64Inst::LockCmpxchg64mr { .. }
+            | X64Inst::RepMovsb { .. }
+            | X64Inst::RepStosb { .. }
+            | X64Inst::StoreStackArg { .. }
+            | X64Inst::Mfence
+    )
+}
+
+/// Rough cycles an operand's definition costs on the path that skips it.
+fn cost(inst: &Instruction<X64Inst>) -> u32 {
+    match inst {
+        Instruction::Target(t) if mem_access(t).is_some() => 4,
+        _ => 1,
+    }
+}
+
+/// Point `s`'s phi operands that flowed in from `old` at `new`.
+fn retarget_phis(func: &mut Func<X64Inst>, s: Block, old: Block, new: Block) {
+    let ids: Vec<_> = func
+        .get_block_data(s)
+        .iter()
+        .filter_map(|inst| match inst {
+            Instruction::Pseudo(PseudoInstruction::Phi { id, .. }) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    for id in ids {
+        for (pb, _) in &mut func.phi_operands_mut(id).incoming {
+            if *pb == old {
+                *pb = new;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::Cond;
+
+    fn has_load(func: &Func<X64Inst>, b: Block) -> bool {
+        func.get_block_data(b).iter().any(|inst| {
+            matches!(inst, Instruction::Target(t) if mem_access(t).is_some_and(|a| !a.store))
+        })
+    }
+
+    #[test]
+    fn cheap_operands_become_cmov() {
+        let mut b = FuncBuilder::new("cheap");
+        let (x, y) = (b.arg(), b.arg());
+        let r = b.select(Cond::L, x, y, x, y);
+        b.ret(r);
+        let mut func = b.build();
+        assert!(lower_selects(&mut func, true));
+        assert_eq!(func.blocks_count(), 1);
+        let text = func.to_string();
+        assert!(text.contains("cmov"), "{text}");
+        assert!(!text.contains("select"), "{text}");
+    }
+
+    #[test]
+    fn costly_load_sinks_into_its_arm() {
+        let mut b = FuncBuilder::new("load");
+        let (p, x) = (b.arg(), b.arg());
+        let v = b.load_i64(p, 0);
+        let r = b.select(Cond::L, x, p, v, x);
+        b.ret(r);
+        let mut func = b.build();
+        assert!(lower_selects(&mut func, true));
+        let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+        assert_eq!(blocks.len(), 4);
+        let Some(Instruction::Target(X64Inst::CondJmp {
+            taken, not_taken, ..
+        })) = func.get_block_data(blocks[0]).insts().last().copied()
+        else {
+            panic!("head should end in a jcc:\n{func}");
+        };
+        // Falls into `not_taken`, which skips the load.
+        assert_eq!(not_taken, blocks[1]);
+        assert!(!has_load(&func, blocks[0]) && !has_load(&func, not_taken));
+        assert!(has_load(&func, taken));
+        assert!(matches!(
+            func.get_block_data(blocks[3]).insts()[0],
+            Instruction::Pseudo(PseudoInstruction::Phi { dst, .. }) if dst == r
+        ));
+    }
+
+    #[test]
+    fn hint_makes_a_diamond_with_hinted_arms() {
+        let mut b = FuncBuilder::new("hinted");
+        let (x, y) = (b.arg(), b.arg());
+        let r = b.select_hinted(Cond::Z, x, y, x, y, Some(BlockHint::Unlikely));
+        b.ret(r);
+        let mut func = b.build();
+        lower_selects(&mut func, true);
+        let Some(Instruction::Target(X64Inst::CondJmp {
+            taken, not_taken, ..
+        })) = func
+            .get_block_data(func.get_entry_block().expect("entry"))
+            .insts()
+            .last()
+            .copied()
+        else {
+            panic!("hinted select should branch:\n{func}");
+        };
+        assert_eq!(func.block_hint(taken), Some(BlockHint::Unlikely));
+        assert_eq!(func.block_hint(not_taken), Some(BlockHint::Likely));
+    }
+
+    #[test]
+    fn without_branches_everything_is_cmov() {
+        let mut b = FuncBuilder::new("o0");
+        let (p, x) = (b.arg(), b.arg());
+        let v = b.load_i64(p, 0);
+        let r = b.select_hinted(Cond::L, x, p, v, x, Some(BlockHint::Likely));
+        b.ret(r);
+        let mut func = b.build();
+        assert!(lower_selects(&mut func, false));
+        assert_eq!(func.blocks_count(), 1);
+        assert!(func.to_string().contains("cmov"));
+    }
+
+    #[test]
+    fn load_does_not_sink_past_a_store() {
+        let mut b = FuncBuilder::new("clobbered");
+        let (p, x) = (b.arg(), b.arg());
+        let v = b.load_i64(p, 0);
+        b.store_i64(p, 0, x);
+        let r = b.select(Cond::L, x, p, v, x);
+        b.ret(r);
+        let mut func = b.build();
+        lower_selects(&mut func, true);
+        assert_eq!(func.blocks_count(), 1);
+    }
+}
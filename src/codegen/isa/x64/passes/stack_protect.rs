@@ -0,0 +1,181 @@
+//! Stack canaries for functions with stack buffers.
+//!
+//! **Requires:** Phi-free IR, before ABI lowering. Runs after the
+//! optimization passes, which would otherwise forward the canary store
+//! to the reload that checks it.
+//!
+//! **Preserves:** Semantics while no buffer overflows. Functions without
+//! a `StackAlloc`, and naked ones, are untouched.
+//!
+//! **Invalidates:** Block numbering: each returning block is split, the
+//! `ret` laid out right after it and the failure block last. Any `CFG`
+//! or liveness computed before the pass.
+//!
+//! **Effect:** The entry block gets a `StackAlloc` for the canary ahead
+//! of every other one, so it lies between the buffers and the spill
+//! slots, saved registers and return address above them, and stores the
+//! thread's guard value (`x64.stack_guard`) there. Each `Return` first
+//! reloads the canary and compares it with the guard; on a mismatch it
+//! branches to one shared block that calls `handler` and traps, should
+//! the handler return.
+
+use std::collections::HashMap;
+
+use crate::codegen::isa::x64::inst::{Cond, Mem, X64Inst};
+use crate::codegen::isa::x64::passes::select_lower::place;
+use crate::codegen::passes::lower_intrinsics;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{
+    Block, BlockHint, CallData, CallTarget, Func, Inst, Instruction, IntrinsicData,
+    PseudoInstruction, Reg, TrapCode,
+};
+
+/// The intrinsic reading the guard value the canary is checked against.
+const GUARD: &str = "x64.stack_guard";
+
+/// Insert the canary store and checks. Returns `true` if the function
+/// was protected.
+pub fn protect_stack(func: &mut Func<X64Inst>, handler: &str) -> bool {
+    let has_buffers = func.blocks_iter().any(|(_, bd)| {
+        bd.iter()
+            .any(|inst| matches!(inst, Instruction::Pseudo(PseudoInstruction::StackAlloc { .. })))
+    });
+    if func.attrs().naked || !has_buffers {
+        return false;
+    }
+    let original: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let entry = original[0];
+    let slot = func.new_vreg();
+    let guard = read_guard(func);
+    let canary = Mem::base(slot);
+    let insts = func.get_block_data_mut(entry).insts_mut();
+    let at = insts
+        .iter()
+        .position(|inst| !matches!(inst, Instruction::Pseudo(PseudoInstruction::Arg { .. })))
+        .unwrap_or(insts.len());
+    insts.splice(
+        at..at,
+        [
+            Instruction::Pseudo(PseudoInstruction::StackAlloc { dst: slot, size: 8, align: 8 }),
+            guard.0,
+            Instruction::Target(X64Inst::Mov64mr { dst: canary, src: guard.1 }),
+        ],
+    );
+
+    let fail = func.add_empty_block();
+    let call = func.new_call(CallData {
+        callee: CallTarget::Symbol(handler.to_string()),
+        args: vec![],
+        rets: vec![],
+        patch: None,
+    });
+    let bd = func.get_block_data_mut(fail);
+    bd.push_pseudo_inst(PseudoInstruction::CallPseudo { id: call });
+    bd.push_pseudo_inst(PseudoInstruction::Trap { code: TrapCode::Unreachable });
+    func.set_block_hint(fail, Some(BlockHint::Unlikely));
+
+    let mut followers: HashMap<Block, Vec<Block>> = HashMap::new();
+    for &b in &original {
+        let Some(Instruction::Pseudo(PseudoInstruction::Return { src })) =
+            func.get_block_data(b).get_terminator()
+        else {
+            continue;
+        };
+        let (read, expected) = read_guard(func);
+        let (stored, ret) = (func.new_vreg(), func.add_empty_block());
+        let bd = func.get_block_data_mut(b);
+        bd.insts_mut().pop();
+        bd.insts_mut().push(read);
+        bd.push_target_inst(X64Inst::Mov64rm { dst: stored, src: canary });
+        bd.push_target_inst(X64Inst::Cmp64rr { lhs: stored, rhs: expected });
+        bd.push_target_inst(X64Inst::CondJmp { cond: Cond::NZ, taken: fail, not_taken: ret });
+        func.get_block_data_mut(ret).push_pseudo_inst(PseudoInstruction::Return { src });
+        followers.insert(b, vec![ret]);
+        stat!("stack-protect", "checks", "canary checks inserted before returns");
+    }
+    stat!("stack-protect", "functions", "functions given a stack canary");
+    let mut order = Vec::with_capacity(func.blocks_count());
+    for b in original {
+        place(b, &followers, &mut order);
+    }
+    order.push(fail);
+    func.reorder_blocks(&order);
+    // Intrinsic lowering has already run; lower the guard reads here.
+    lower_intrinsics(func);
+    true
+}
+
+/// An `Intrinsic` reading the guard, and the vreg it defines.
+fn read_guard(func: &mut Func<X64Inst>) -> (Instruction<X64Inst>, Reg) {
+    let decl = X64Inst::intrinsics()
+        .iter()
+        .find(|d| d.name == GUARD)
+        .expect("x64 declares the stack guard intrinsic");
+    let dst = func.new_vreg();
+    let id = func.new_intrinsic(IntrinsicData { decl, args: vec![], rets: vec![dst] });
+    (Instruction::Pseudo(PseudoInstruction::Intrinsic { id }), dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+
+    #[test]
+    fn returns_are_checked_against_a_canary_above_the_buffers() {
+        let mut b = FuncBuilder::new("buf");
+        let x = b.arg();
+        let buf = b.stack_alloc(16, 8);
+        let (then, other) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::Z, x, x, then, other);
+        b.switch_to_block(then);
+        b.ret(buf);
+        b.switch_to_block(other);
+        b.ret(x);
+        let mut func = b.build();
+        assert!(protect_stack(&mut func, "__stack_chk_fail"));
+
+        let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+        let slots: Vec<Reg> = func
+            .get_block_data(blocks[0])
+            .iter()
+            .filter_map(|inst| match *inst {
+                Instruction::Pseudo(PseudoInstruction::StackAlloc { dst, .. }) => Some(dst),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(slots.len(), 2);
+        assert_ne!(slots[0], buf, "the canary is allocated first");
+        let fail = *blocks.last().expect("blocks");
+        assert_eq!(func.block_hint(fail), Some(BlockHint::Unlikely));
+        let checks = blocks
+            .iter()
+            .filter(|&&b| {
+                matches!(
+                    func.get_block_data(b).get_terminator(),
+                    Some(Instruction::Target(X64Inst::CondJmp { cond: Cond::NZ, taken, .. }))
+                        if taken == fail
+                )
+            })
+            .count();
+        assert_eq!(checks, 2);
+        let Some(Instruction::Pseudo(PseudoInstruction::CallPseudo { id })) =
+            func.get_block_data(fail).iter().next()
+        else {
+            panic!("the failure block calls the handler");
+        };
+        let callee = &func.call_operands(*id).callee;
+        assert_eq!(*callee, CallTarget::Symbol("__stack_chk_fail".to_string()));
+    }
+
+    #[test]
+    fn functions_without_buffers_are_untouched() {
+        let mut b = FuncBuilder::new("leaf");
+        let x = b.arg();
+        b.ret(x);
+        let mut func = b.build();
+        let before = func.to_string();
+        assert!(!protect_stack(&mut func, "__stack_chk_fail"));
+        assert_eq!(func.to_string(), before);
+    }
+}
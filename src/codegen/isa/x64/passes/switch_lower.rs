@@ -0,0 +1,346 @@
+//! Lowering of `Switch` terminators.
+//!
+//! **Requires:** Before SSA destruction. A phi in a switch target names
+//! the switch's block as the predecessor of that edge.
+//!
+//! **Preserves:** Semantics. Blocks without a switch keep their contents.
+//!
+//! **Invalidates:** Block numbering: blocks split off a switch are laid
+//! out right after it. Any `CFG` or liveness computed before the pass.
+//!
+//! **Effect:** Each `Switch` becomes one of two shapes, chosen per
+//! `SwitchLowering`:
+//!
+//! - a jump table: `index - min` is bounds-checked against the case
+//!   range (`ja default`), then a `JmpTable` dispatches through one entry
+//!   per value in the range, holes going to the default;
+//! - a compare tree: a balanced binary search on the case values, with
+//!   runs of up to `LINEAR_CASES` cases tested one `cmp; je` at a time.
+//!
+//! `Auto` takes the table once a switch has `MIN_TABLE_CASES` cases that
+//! fill at least `MIN_DENSITY_PERCENT` of their range; optimizing for size,
+//! whenever its estimated bytes don't exceed the tree's. A range wider than
+//! `MAX_TABLE_ENTRIES` always gets a tree. Phis in the targets get one
+//! incoming edge per new predecessor.
+
+use std::collections::HashMap;
+
+use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+use crate::codegen::isa::x64::passes::select_lower::place;
+use crate::codegen::options::SwitchLowering;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{
+    Block, Func, Instruction, JumpTableData, JumpTableId, PseudoInstruction, Reg,
+};
+
+/// Fewest cases `Auto` builds a jump table for.
+const MIN_TABLE_CASES: usize = 4;
+/// Share of the case range `Auto` needs covered by cases for a table.
+const MIN_DENSITY_PERCENT: u64 = 40;
+/// Widest case range any strategy builds a table for.
+const MAX_TABLE_ENTRIES: u64 = 1 << 16;
+/// Cases a compare-tree leaf tests one by one instead of splitting.
+const LINEAR_CASES: usize = 3;
+/// Estimated bytes of a table's bounds check and dispatch, and of a
+/// compare tree per case, for `Auto` optimizing for size.
+const TABLE_DISPATCH_BYTES: u64 = 28;
+const TREE_CASE_BYTES: u64 = 10;
+
+/// Lower every `Switch` per `strategy`, `Auto` picking the smaller shape
+/// under `optimize_size`. Returns `true` if the function had any.
+pub fn lower_switches(
+    func: &mut Func<X64Inst>,
+    strategy: SwitchLowering,
+    optimize_size: bool,
+) -> bool {
+    let original: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut followers: HashMap<Block, Vec<Block>> = HashMap::new();
+    for &b in &original {
+        let Some(Instruction::Pseudo(PseudoInstruction::Switch { index, table })) =
+            func.get_block_data(b).get_terminator()
+        else {
+            continue;
+        };
+        func.get_block_data_mut(b).insts_mut().pop();
+        let data = func.jump_table(table).clone();
+        let default = data.default.expect("a switch has a default");
+        let old_targets: Vec<Block> = data.targets().collect();
+        let mut added = Vec::new();
+        if data.cases.is_empty() {
+            func.get_block_data_mut(b).push_target_inst(X64Inst::Jmp { dst: default });
+        } else if use_table(&data, strategy, optimize_size) {
+            lower_to_table(func, b, index, table, &mut added);
+            stat!("switch-lower", "tables", "switches lowered to a jump table");
+        } else {
+            lower_to_tree(func, b, index, &data.cases, default, &mut added);
+            stat!("switch-lower", "trees", "switches lowered to a compare tree");
+        }
+        let mut sources = vec![b];
+        sources.extend(&added);
+        split_phi_edges(func, b, &sources, &old_targets);
+        followers.insert(b, added);
+    }
+    if followers.is_empty() {
+        return false;
+    }
+    let mut order = Vec::with_capacity(func.blocks_count());
+    for b in original {
+        place(b, &followers, &mut order);
+    }
+    func.reorder_blocks(&order);
+    true
+}
+
+fn use_table(data: &JumpTableData, strategy: SwitchLowering, optimize_size: bool) -> bool {
+    let range = data.range();
+    let cases = data.cases.len() as u64;
+    let dense = cases * 100 >= range * MIN_DENSITY_PERCENT;
+    range <= MAX_TABLE_ENTRIES
+        && match strategy {
+            SwitchLowering::Auto if optimize_size => {
+                TABLE_DISPATCH_BYTES + 4 * range <= TREE_CASE_BYTES * cases
+            }
+            SwitchLowering::Auto => cases >= MIN_TABLE_CASES as u64 && dense,
+            SwitchLowering::JumpTable => true,
+            SwitchLowering::CompareTree => false,
+        }
+}
+
+/// `b: idx = index - min; cmp idx, range - 1; ja default`, then a new
+/// block `jmp table[idx]`. The table keeps its default only for holes.
+fn lower_to_table(
+    func: &mut Func<X64Inst>,
+    b: Block,
+    index: Reg,
+    table: JumpTableId,
+    added: &mut Vec<Block>,
+) {
+    let data = func.jump_table(table);
+    let min = data.cases[0].0;
+    let last = i32::try_from(data.range() - 1).expect("table range is capped");
+    let default = data.default.expect("a switch has a default");
+    let holes = data.range() > data.cases.len() as u64;
+    let idx = if min == 0 {
+        index
+    } else {
+        let idx = func.new_vreg();
+        let rebase = if let Ok(imm) = i32::try_from(min) {
+            vec![X64Inst::Sub64ri32 { dst: idx, imm }]
+        } else {
+            let m = func.new_vreg();
+            vec![X64Inst::Mov64ri { dst: m, imm: min }, X64Inst::Sub64rr { dst: idx, src: m }]
+        };
+        let bd = func.get_block_data_mut(b);
+        bd.push_pseudo_inst(PseudoInstruction::Copy { dst: idx, src: index });
+        for inst in rebase {
+            bd.push_target_inst(inst);
+        }
+        idx
+    };
+    let dispatch = func.add_empty_block();
+    let bd = func.get_block_data_mut(b);
+    bd.push_target_inst(X64Inst::Cmp64ri32 { lhs: idx, imm: last });
+    bd.push_target_inst(X64Inst::CondJmp { cond: Cond::A, taken: default, not_taken: dispatch });
+    func.get_block_data_mut(dispatch).push_target_inst(X64Inst::JmpTable { index: idx, table });
+    if !holes {
+        func.jump_table_mut(table).default = None;
+    }
+    added.push(dispatch);
+}
+
+/// Binary search on `cases` from `at`: split at the middle case with a
+/// `jl`, and test runs of `LINEAR_CASES` or fewer for equality in turn.
+fn lower_to_tree(
+    func: &mut Func<X64Inst>,
+    at: Block,
+    index: Reg,
+    cases: &[(i64, Block)],
+    default: Block,
+    added: &mut Vec<Block>,
+) {
+    if cases.len() <= LINEAR_CASES {
+        let mut at = at;
+        for (i, &(v, target)) in cases.iter().enumerate() {
+            let next = if i + 1 == cases.len() {
+                default
+            } else {
+                let next = func.add_empty_block();
+                added.push(next);
+                next
+            };
+            compare(func, at, index, v);
+            func.get_block_data_mut(at).push_target_inst(X64Inst::CondJmp {
+                cond: Cond::Z,
+                taken: target,
+                not_taken: next,
+            });
+            at = next;
+        }
+        return;
+    }
+    let mid = cases.len() / 2;
+    let (lo, hi) = (func.add_empty_block(), func.add_empty_block());
+    compare(func, at, index, cases[mid].0);
+    func.get_block_data_mut(at).push_target_inst(X64Inst::CondJmp {
+        cond: Cond::L,
+        taken: lo,
+        not_taken: hi,
+    });
+    // The upper half first, so `at` falls into it.
+    added.push(hi);
+    lower_to_tree(func, hi, index, &cases[mid..], default, added);
+    added.push(lo);
+    lower_to_tree(func, lo, index, &cases[..mid], default, added);
+}
+
+/// `cmp index, v`, through a register when `v` doesn't fit an `imm32`.
+fn compare(func: &mut Func<X64Inst>, at: Block, index: Reg, v: i64) {
+    let cmp = if let Ok(imm) = i32::try_from(v) {
+        X64Inst::Cmp64ri32 { lhs: index, imm }
+    } else {
+        let k = func.new_vreg();
+        func.get_block_data_mut(at).push_target_inst(X64Inst::Mov64ri { dst: k, imm: v });
+        X64Inst::Cmp64rr { lhs: index, rhs: k }
+    };
+    func.get_block_data_mut(at).push_target_inst(cmp);
+}
+
+/// The switch in `old` now reaches `targets` from the blocks in
+/// `sources`: give each target's phis one incoming edge per source that
+/// branches to it, in place of the edge from `old`.
+fn split_phi_edges(func: &mut Func<X64Inst>, old: Block, sources: &[Block], targets: &[Block]) {
+    let mut preds: HashMap<Block, Vec<Block>> = HashMap::new();
+    for &p in sources {
+        let term = func.get_block_data(p).get_terminator().expect("lowered block terminated");
+        for s in func.branch_targets(&term) {
+            let list = preds.entry(s).or_default();
+            if !list.contains(&p) {
+                list.push(p);
+            }
+        }
+    }
+    let mut targets = targets.to_vec();
+    targets.sort_unstable();
+    targets.dedup();
+    for s in targets {
+        let ids: Vec<_> = func
+            .get_block_data(s)
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Pseudo(PseudoInstruction::Phi { id, .. }) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        for id in ids {
+            let incoming = &mut func.phi_operands_mut(id).incoming;
+            let Some(i) = incoming.iter().position(|&(p, _)| p == old) else {
+                continue;
+            };
+            let (_, v) = incoming.remove(i);
+            for (k, &p) in preds[&s].iter().enumerate() {
+                incoming.insert(i + k, (p, v));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+
+    /// `switch x { 10 → ret 1, 11 → ret 2, …, 10 + n - 1 → ret n, _ → ret 0 }`
+    /// with the case values spaced `step` apart.
+    fn switch_func(n: i64, step: i64) -> Func<X64Inst> {
+        let mut b = FuncBuilder::new("sw");
+        let x = b.arg();
+        let default = b.new_block();
+        let cases: Vec<(i64, Block)> = (0..n).map(|i| (10 + i * step, b.new_block())).collect();
+        b.switch(x, &cases, default);
+        b.switch_to_block(default);
+        let zero = b.iconst64(0);
+        b.ret(zero);
+        for (i, &(_, blk)) in cases.iter().enumerate() {
+            b.switch_to_block(blk);
+            let r = b.iconst64(i as i64 + 1);
+            b.ret(r);
+        }
+        b.build()
+    }
+
+    fn table_jumps(func: &Func<X64Inst>) -> Vec<JumpTableId> {
+        func.blocks_iter()
+            .filter_map(|(_, bd)| match bd.get_terminator() {
+                Some(Instruction::Target(X64Inst::JmpTable { table, .. })) => Some(table),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dense_switch_becomes_a_bounds_checked_table() {
+        let mut func = switch_func(6, 1);
+        assert!(lower_switches(&mut func, SwitchLowering::Auto, false));
+        let [table] = table_jumps(&func)[..] else {
+            panic!("one table jump expected:\n{func}");
+        };
+        // No holes, so the default is only reached through the `ja`.
+        assert_eq!(func.jump_table(table).default, None);
+        let entry = func.get_block_data(func.get_entry_block().expect("entry")).insts();
+        assert!(matches!(
+            entry[entry.len() - 2..],
+            [
+                Instruction::Target(X64Inst::Cmp64ri32 { imm: 5, .. }),
+                Instruction::Target(X64Inst::CondJmp { cond: Cond::A, .. })
+            ]
+        ));
+    }
+
+    #[test]
+    fn sparse_switch_becomes_a_compare_tree() {
+        let mut func = switch_func(8, 1000);
+        assert!(lower_switches(&mut func, SwitchLowering::Auto, false));
+        assert!(table_jumps(&func).is_empty());
+        let compares = func
+            .blocks_iter()
+            .flat_map(|(_, bd)| bd.iter())
+            .filter(|inst| matches!(inst, Instruction::Target(X64Inst::Cmp64ri32 { .. })))
+            .count();
+        // One split at the root and at each half, then a `cmp` per case.
+        assert_eq!(compares, 3 + 8);
+    }
+
+    #[test]
+    fn strategy_knob_overrides_density() {
+        let mut func = switch_func(8, 1000);
+        lower_switches(&mut func, SwitchLowering::JumpTable, false);
+        let [table] = table_jumps(&func)[..] else {
+            panic!("forced table expected:\n{func}");
+        };
+        assert_eq!(func.jump_table(table).dense_targets().len(), 7001);
+
+        let mut func = switch_func(6, 1);
+        lower_switches(&mut func, SwitchLowering::CompareTree, false);
+        assert!(table_jumps(&func).is_empty());
+    }
+
+    #[test]
+    fn phis_get_an_edge_per_new_predecessor() {
+        let mut b = FuncBuilder::new("phi");
+        let x = b.arg();
+        let join = b.new_block();
+        let one = b.iconst64(1);
+        b.switch(x, &[(1, join), (5, join), (9, join)], join);
+        b.switch_to_block(join);
+        let (r, id) = b.phi_with_id(Vec::new());
+        b.ret(r);
+        let mut func = b.build();
+        let entry = func.get_entry_block().expect("entry");
+        func.phi_operands_mut(id).incoming = vec![(entry, one)];
+        lower_switches(&mut func, SwitchLowering::CompareTree, false);
+        // A three-case run: three `cmp; je` blocks, all reaching `join`.
+        let incoming = &func.phi_operands(id).incoming;
+        assert_eq!(incoming.len(), 3, "{func}");
+        assert!(incoming.iter().all(|&(_, v)| v == one));
+    }
+}
@@ -1,22 +1,62 @@
 //! End-to-end x64 compilation pipeline.
 //!
-//! `compile(func)` runs every required pass in order and returns the emitted
-//! machine-code bytes. `jit(func)` additionally loads the bytes into an
-//! executable mapping.
+//! `compile_function(func, target, options)` runs every required pass in
+//! order — aggregate lowering, SSA destruction, ABI lowering, CFG, register
+//! allocation, and MC emission (prologue/epilogue, spill code, encoding) —
+//! and returns the emitted bytes plus relocations. `compile(func)` and
+//! `jit(func)` are default-option shorthands; `jit` additionally loads the
+//! bytes into an executable mapping. `compile_module` compiles a batch of
+//! functions, on every core with the `parallel` feature.
 
 use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::analysis::layout::BlockLayout;
+use crate::codegen::analysis::liveness::LiveRanges;
+use crate::codegen::analysis::verify::verify;
+use crate::codegen::dot::{cfg_to_dot, dom_tree_to_dot, interference_to_dot};
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::features::select_for_features;
+use crate::codegen::isa::x64::format::FormatContext;
+use crate::codegen::isa::x64::frame::FrameLayout;
 use crate::codegen::isa::x64::inst::X64Inst;
-use crate::codegen::isa::x64::mc::emit_mc::FnMCWriter;
+use crate::codegen::isa::x64::mc::emit_mc::{
+    ConstantSite, FnMCWriter, JumpTableSite, PatchSite, TrapSite,
+};
 use crate::codegen::isa::x64::passes::abi_lower::SysVAmd64Lowering;
+use crate::codegen::isa::x64::passes::branch_simplify::simplify_branches;
+use crate::codegen::isa::x64::passes::const_fold::fold_constants;
+use crate::codegen::isa::x64::passes::coverage::instrument_blocks;
+use crate::codegen::isa::x64::passes::jump_threading::thread_jumps;
+use crate::codegen::isa::x64::passes::load_elim::eliminate_redundant_loads;
+use crate::codegen::isa::x64::passes::peephole::x64_peephole_for;
+use crate::codegen::isa::x64::passes::select_lower::lower_selects;
+use crate::codegen::isa::x64::passes::stack_protect::protect_stack;
+use crate::codegen::isa::x64::passes::switch_lower::lower_switches;
+use crate::codegen::isa::x64::passes::schedule::schedule_blocks;
 use crate::codegen::isa::x64::regs::{
     R10, R11, R12, R13, R14, R15, R8, R9, RAX, RBX, RCX, RDI, RDX, RSI, XMM0, XMM1, XMM10,
     XMM11, XMM12, XMM13, XMM14, XMM15, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7, XMM8, XMM9,
+    preg_name,
 };
 use crate::codegen::jit::{Module, Relocation};
-use crate::codegen::passes::{AbiLowering, destroy_ssa, lower_aggregates};
-use crate::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocator};
-use crate::codegen::tir::{Func, Reg};
-use std::collections::HashMap;
+use crate::codegen::module::DataObject;
+use crate::codegen::options::{CodegenOptions, OptLevel, RegAllocKind, SpeculationHardening};
+use crate::codegen::passes::{
+    AbiLowering, InlineConfig, TailDupConfig, destroy_ssa, duplicate_tails,
+    find_redundant_moves, forward_empty_blocks, inline_calls, layout_blocks, linearize_blocks,
+    lower_aggregates, lower_intrinsics, merge_blocks,
+};
+use crate::codegen::regalloc::checker::check_allocation;
+use crate::codegen::regalloc::spill::plan_spills;
+use crate::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocResult, RegAllocator};
+#[cfg(feature = "regalloc2")]
+use crate::codegen::regalloc::Regalloc2;
+use crate::codegen::timing::PassTimings;
+use crate::codegen::tir::{Func, FuncAttrs, PrintOptions, Reg, TrapCode};
+use crate::codegen::value_locations::{StackMap, ValueLocationMap};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 
 /// Build the default `SysV`-flavored `RegAllocConfig`. The allocatable pool is
 /// the nine caller-saved integer registers (`RAX/RCX/RDX/RSI/RDI/R8..R11`) plus
@@ -47,49 +87,297 @@ pub fn default_ra_config(reg_bind: HashMap<Reg, Reg>) -> RegAllocConfig {
         ],
         scratch_fp_regs: vec![XMM14, XMM15],
         reg_bind,
+        coalesce: true,
+        spilled: HashSet::new(),
+        // No REX prefix needed: RAX..RDI and XMM0..XMM7.
+        compact_regs: vec![
+            RAX, RCX, RDX, RSI, RDI, XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7,
+        ],
+        prefer_compact_regs: false,
     }
 }
 
 /// Output of the full compile pipeline: executable bytes plus any
 /// call-site relocations requiring symbol resolution at load time.
-pub struct Compiled {
+#[derive(Clone)]
+pub struct CompiledCode {
     pub name: String,
     pub bytes: Vec<u8>,
     pub relocations: Vec<Relocation>,
+    /// The function's attributes, for the object writer's section choice.
+    pub attrs: FuncAttrs,
+    /// Alignment of the function's start: its `align` attribute, but at
+    /// least `FUNC_ALIGN` unless optimizing for size.
+    pub align: u32,
+    /// Which preg or frame slot holds each vreg over `bytes`.
+    pub value_locations: ValueLocationMap,
+    /// Offset and code of every `Trap`, in ascending offset order.
+    pub traps: Vec<TrapSite>,
+    /// One stack map per `Safepoint`, in ascending offset order.
+    pub stack_maps: Vec<StackMap>,
+    /// Every NOP sled reserved for patching — the `patchable` entry,
+    /// patchable calls and `PatchPoint`s — in ascending offset order.
+    pub patch_sites: Vec<PatchSite>,
+    /// Every jump table, in ascending offset order. The tables sit after
+    /// the instructions and the constant pool.
+    pub jump_tables: Vec<JumpTableSite>,
+    /// The `f64`s RIP-relative `movsd`s load, in ascending offset order.
+    /// The pool sits right after the instructions.
+    pub constants: Vec<ConstantSite>,
+    /// Whether the code opens with `endbr64` and pads its indirect-branch
+    /// targets (`CodegenOptions::cet`).
+    pub endbr: bool,
+    /// Whether indirect branches go through retpoline thunks, which a
+    /// shadow stack would reject.
+    pub retpolines: bool,
+    /// The block counter table the code increments, under
+    /// `CodegenOptions::coverage`; the object writers emit it after the
+    /// module's own data.
+    pub coverage: Option<DataObject>,
+    /// Per-pass wall times; empty unless `CodegenOptions::time_passes`.
+    pub timings: PassTimings,
+}
+
+impl CompiledCode {
+    /// The code of the `Trap` whose instruction starts at `offset` — for
+    /// a fault handler holding the faulting PC minus the code's base.
+    #[must_use]
+    pub fn trap_code(&self, offset: u32) -> Option<TrapCode> {
+        let i = self.traps.binary_search_by_key(&offset, |t| t.offset).ok()?;
+        Some(self.traps[i].code)
+    }
+
+    /// The stack maps of the safepoints at `offset` — the PC a runtime
+    /// stopped this function's thread at, minus the code's base.
+    #[must_use]
+    pub fn stack_maps_at(&self, offset: u32) -> &[StackMap] {
+        let lo = self.stack_maps.partition_point(|m| m.offset < offset);
+        let hi = self.stack_maps.partition_point(|m| m.offset <= offset);
+        &self.stack_maps[lo..hi]
+    }
+
+    /// Length of the instructions at the start of `bytes`; the constant
+    /// pool and jump tables follow them.
+    #[must_use]
+    pub fn code_len(&self) -> usize {
+        let pool = self.constants.first().map(|c| c.offset);
+        let tables = self.jump_tables.first().map(|t| t.offset);
+        pool.or(tables).map_or(self.bytes.len(), |at| at as usize)
+    }
 }
 
+/// Default start alignment of a function, so its first instructions
+/// share a fetch block.
+pub const FUNC_ALIGN: u32 = 16;
+
 /// Compile a function end-to-end. Returns the emitted bytes.
 #[must_use]
 pub fn compile(func: Func<X64Inst>) -> Vec<u8> {
     compile_full(func).bytes
 }
 
-/// Full compile pipeline including call-site relocation capture.
+/// Full compile pipeline including call-site relocation capture, with
+/// the default target and options.
+#[must_use]
+pub fn compile_full(func: Func<X64Inst>) -> CompiledCode {
+    compile_function(func, Target::X64SysV, &CodegenOptions::default())
+}
+
+/// Run every pass from intrinsic lowering to MC emission on `func` for
+/// `target`, in the order the passes require.
+///
+/// # Panics
+/// On any error `try_compile_function` would return.
 #[must_use]
-pub fn compile_full(mut func: Func<X64Inst>) -> Compiled {
+pub fn compile_function(
+    func: Func<X64Inst>,
+    target: Target,
+    options: &CodegenOptions,
+) -> CompiledCode {
+    try_compile_function(func, target, options).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// `compile_function`, reporting failures instead of panicking.
+///
+/// # Errors
+/// Located in the function (and block and instruction where known):
+/// `Verify` if `options.verify` is set and a pass leaves broken IR, `Tir`
+/// if the CFG can't be built, `ConflictingPreBind` if the ABI and the
+/// frontend pin a vreg to different pregs, `Allocation` if
+/// `options.check_regalloc` is set and the allocation fails its check,
+/// and `Encoding` if the code doesn't encode.
+pub fn try_compile_function(
+    mut func: Func<X64Inst>,
+    target: Target,
+    options: &CodegenOptions,
+) -> Result<CompiledCode, CodegenError> {
     let name = func.name().to_string();
-    // Aggregate pseudos first: they rewrite into plain Copies, which
+    let mut timings = PassTimings::new(options.time_passes);
+    let dump = options.print_after_all_enabled();
+    let dump_seq = Cell::new(0);
+    let dump_with = |func: &Func<X64Inst>, pass: &str, names: &FormatContext| {
+        if dump {
+            dump_seq.set(dump_seq.get() + 1);
+            dump_func(func, names, pass, dump_seq.get(), options);
+        }
+        if options.verify {
+            verify(func).map_err(|error| {
+                CodegenError::Verify { pass: pass.to_string(), error }.in_func(func.name())
+            })?;
+        }
+        Ok::<_, CodegenError>(())
+    };
+    let dump_after = |func: &Func<X64Inst>, pass: &str| {
+        dump_with(func, pass, &FormatContext::default())
+    };
+    // Counters first, so each belongs to a block as the frontend built it.
+    let coverage = if options.coverage {
+        let table = timings.time(&name, "instrument_blocks", || instrument_blocks(&mut func));
+        dump_after(&func, "instrument_blocks")?;
+        table
+    } else {
+        None
+    };
+    // Fallbacks for missing CPU features before intrinsics lower, as
+    // they may swap an intrinsic's implementation.
+    timings.time(&name, "select_for_features", || {
+        select_for_features(&mut func, options.cpu_features)
+    })
+    .map_err(|e| e.in_func(&name))?;
+    dump_after(&func, "select_for_features")?;
+    // Intrinsics next: they become raw bytes and calls
+    // whose operands aggregate lowering and the ABI pass then handle.
+    timings.time(&name, "lower_intrinsics", || lower_intrinsics(&mut func));
+    dump_after(&func, "lower_intrinsics")?;
+    // Aggregate pseudos next: they rewrite into plain Copies, which
     // every later pass already understands. Must run before SSA
     // destruction so the aggregate vregs don't leak into phi lists.
-    lower_aggregates(&mut func);
+    timings.time(&name, "lower_aggregates", || lower_aggregates(&mut func));
+    dump_after(&func, "lower_aggregates")?;
+    // Selects next: a branch diamond merges through a phi, so this must
+    // precede SSA destruction. `-O0` and `-Os` always take the `cmov`.
+    let branchy_selects = options.opt_level == OptLevel::Default;
+    timings.time(&name, "lower_selects", || lower_selects(&mut func, branchy_selects));
+    dump_after(&func, "lower_selects")?;
+    // Switches split their block and fan phi edges out to the new
+    // predecessors, so they too go before SSA destruction.
+    let (strategy, size) = (options.switch_lowering, options.opt_level == OptLevel::Size);
+    timings.time(&name, "lower_switches", || lower_switches(&mut func, strategy, size));
+    dump_after(&func, "lower_switches")?;
     // Phi → parallel Copies before anything else. Subsequent passes
     // assume the IR is phi-free.
-    destroy_ssa(&mut func);
-    let abi = SysVAmd64Lowering.lower(&mut func);
-    let cfg = CFG::compute(&func).expect("CFG compute on valid function");
+    timings.time(&name, "destroy_ssa", || destroy_ssa(&mut func));
+    dump_after(&func, "destroy_ssa")?;
+    if options.opt_level > OptLevel::None {
+        timings.time(&name, "fold_constants", || fold_constants(&mut func));
+        dump_after(&func, "fold_constants")?;
+        timings.time(&name, "thread_jumps", || thread_jumps(&mut func));
+        dump_after(&func, "thread_jumps")?;
+        timings.time(&name, "forward_empty_blocks", || forward_empty_blocks(&mut func));
+        dump_after(&func, "forward_empty_blocks")?;
+        // Tail duplication trades size for speed, which neither a cold
+        // function nor `-Os` wants.
+        if !func.attrs().cold && options.opt_level != OptLevel::Size {
+            timings.time(&name, "duplicate_tails", || {
+                duplicate_tails(&mut func, &TailDupConfig::default())
+            });
+            dump_after(&func, "duplicate_tails")?;
+        }
+        timings.time(&name, "merge_blocks", || merge_blocks(&mut func));
+        dump_after(&func, "merge_blocks")?;
+        timings.time(&name, "load_elim", || eliminate_redundant_loads(&mut func));
+        dump_after(&func, "load_elim")?;
+        timings.time(&name, "peephole", || x64_peephole_for(&func).run(&mut func));
+        dump_after(&func, "peephole")?;
+        timings.time(&name, "layout_blocks", || layout_blocks(&mut func));
+        dump_after(&func, "layout_blocks")?;
+        timings.time(&name, "schedule", || schedule_blocks(&mut func));
+        dump_after(&func, "schedule")?;
+    }
+    // After the optimizations, so load elimination can't forward the
+    // canary store to the reload that checks it.
+    if let Some(handler) = &options.stack_protector {
+        timings.time(&name, "protect_stack", || protect_stack(&mut func, handler));
+        dump_after(&func, "protect_stack")?;
+    }
+    // Program points follow block order; make it run forward through the
+    // CFG before anything numbers them.
+    timings.time(&name, "linearize_blocks", || linearize_blocks(&mut func));
+    dump_after(&func, "linearize_blocks")?;
+    let abi = timings
+        .time(&name, "abi_lower", || match target {
+            Target::X64SysV => SysVAmd64Lowering.lower(&mut func),
+        })
+        .map_err(|e| e.in_func(&name))?;
+    dump_after(&func, "abi_lower")?;
+    let cfg = timings
+        .time(&name, "cfg", || CFG::compute(&func))
+        .map_err(|e| e.in_func(&name))?;
     let mut reg_bind = abi.reg_bind;
     for (&v, &p) in func.pre_binds() {
-        match reg_bind.insert(v, p) {
-            Some(prev) if prev != p => panic!(
-                "vreg {v} pre-bound to two different pregs: {prev} (from ABI) vs {p} (from frontend)"
-            ),
-            _ => {}
+        // The ABI's binding is `first`, the frontend's `second`.
+        if let Some(prev) = reg_bind.insert(v, p)
+            && prev != p
+        {
+            let e = CodegenError::ConflictingPreBind { vreg: v, first: prev, second: p };
+            return Err(e.in_func(&name));
         }
     }
-    let ra_cfg = default_ra_config(reg_bind);
-    let ra_res = LinearScan::allocate(&func, &cfg, &ra_cfg);
-    let mut w = FnMCWriter::new(&func, &ra_cfg, &ra_res);
-    let emitted = w.emit_fn_with_relocs(&abi.call_sites);
+    let mut ra_cfg = default_ra_config(reg_bind);
+    ra_cfg.coalesce = options.coalesce;
+    // 32-bit and SSE ops on r8-r15 / xmm8-xmm15 take a REX byte.
+    ra_cfg.prefer_compact_regs = options.opt_level == OptLevel::Size;
+    if options.plan_spills {
+        // Adds only straight-line copies; the CFG stays valid.
+        ra_cfg.spilled = timings
+            .time(&name, "plan_spills", || plan_spills(&mut func, &cfg, &ra_cfg))
+            .map_err(|e| e.in_func(&name))?;
+        dump_after(&func, "plan_spills")?;
+    }
+    let ra_res = timings.time(&name, "regalloc", || match options.regalloc {
+        RegAllocKind::LinearScan => LinearScan::allocate(&func, &cfg, &ra_cfg),
+        #[cfg(feature = "regalloc2")]
+        RegAllocKind::Regalloc2 => Regalloc2::allocate(&func, &cfg, &ra_cfg),
+    });
+    if options.check_regalloc {
+        check_allocation(&func, &cfg, &ra_res).map_err(|e| CodegenError::from(e).in_func(&name))?;
+    }
+    if options.dump_dot {
+        dump_dot(&func, &cfg, &ra_res, options);
+    }
+    let (elided, fallthrough) = if options.opt_level > OptLevel::None {
+        let elided = timings.time(&name, "redundant_moves", || {
+            find_redundant_moves(&func, &BlockLayout::compute(&func), &ra_res)
+        });
+        // Layout is final from here on; only terminators change, so the
+        // allocation's program points stay valid.
+        let fallthrough = timings.time(&name, "simplify_branches", || simplify_branches(&mut func));
+        if dump {
+            let frame = FrameLayout::compute(&func, &ra_cfg, &ra_res);
+            let names = FormatContext { alloc: Some(&ra_res), frame: Some(&frame) };
+            dump_with(&func, "simplify_branches", &names)?;
+        } else {
+            dump_after(&func, "simplify_branches")?;
+        }
+        (elided, fallthrough)
+    } else {
+        (HashSet::new(), HashSet::new())
+    };
+    let emitted = timings.time(&name, "emit", || {
+        FnMCWriter::new(&func, &ra_cfg, &ra_res)
+            .with_elided_moves(elided)
+            .with_fallthrough(fallthrough)
+            .with_stack_probes(options.stack_probes)
+            .with_speculation_hardening(options.speculation_hardening)
+            .with_red_zone(options.red_zone)
+            .with_frame_pointer(options.frame_pointer)
+            .with_endbr(options.cet)
+            .with_optimize_size(options.opt_level == OptLevel::Size)
+            .emit_fn_with_relocs()
+    })
+    .map_err(|e| e.in_func(&name))?;
+    let padded = if options.opt_level == OptLevel::Size { 1 } else { FUNC_ALIGN };
+    let align = func.attrs().align.map_or(padded, |a| a.max(padded));
     let relocations = emitted
         .relocations
         .into_iter()
@@ -98,13 +386,209 @@ pub fn compile_full(mut func: Func<X64Inst>) -> Compiled {
             symbol: r.symbol,
         })
         .collect();
-    Compiled {
+    Ok(CompiledCode {
         name,
         bytes: emitted.bytes,
         relocations,
+        attrs: func.attrs().clone(),
+        align,
+        value_locations: emitted.value_locations,
+        traps: emitted.traps,
+        stack_maps: emitted.stack_maps,
+        patch_sites: emitted.patch_sites,
+        jump_tables: emitted.jump_tables,
+        constants: emitted.constants,
+        endbr: options.cet,
+        retpolines: options.speculation_hardening == SpeculationHardening::Retpoline,
+        coverage,
+        timings,
+    })
+}
+
+/// `-print-after-all` style dump: to `options.dump_dir` if set, stderr
+/// otherwise, with operands named through `names`. A failed write is
+/// reported but never fails compilation.
+fn dump_func(
+    func: &Func<X64Inst>,
+    names: &FormatContext,
+    pass: &str,
+    seq: usize,
+    options: &CodegenOptions,
+) {
+    let mut text = format!("*** IR dump after {pass} ***\n").into_bytes();
+    func.write_with(&mut text, &PrintOptions::default(), names)
+        .expect("writing to a Vec cannot fail");
+    match &options.dump_dir {
+        Some(dir) => {
+            let path = dir.join(format!("{}.{seq:02}.{pass}.tir", func.name()));
+            if let Err(e) = std::fs::write(&path, text) {
+                eprintln!("lancy: failed to write IR dump {}: {e}", path.display());
+            }
+        }
+        None => eprint!("{}", String::from_utf8_lossy(&text)),
     }
 }
 
+/// GraphViz dumps of the function regalloc just ran on; see
+/// `CodegenOptions::dump_dot`. Failed analyses and writes are reported
+/// like `dump_func`'s.
+fn dump_dot(func: &Func<X64Inst>, cfg: &CFG, ra: &RegAllocResult, options: &CodegenOptions) {
+    let analyses = LiveRanges::compute(func, cfg, &BlockLayout::compute(func))
+        .and_then(|ranges| Ok((ranges, DomTree::compute(cfg)?)));
+    let (ranges, dom) = match analyses {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("lancy: no GraphViz dumps for `{}`: {e}", func.name());
+            return;
+        }
+    };
+    let graphs = [
+        ("cfg", cfg_to_dot(func, cfg)),
+        ("domtree", dom_tree_to_dot(func, &dom)),
+        (
+            "interference",
+            interference_to_dot(func, &ranges, ra, |p| preg_name(p).to_string()),
+        ),
+    ];
+    let dir = options.dump_dir.clone().unwrap_or_default();
+    for (kind, text) in graphs {
+        let path = dir.join(format!("{}.{kind}.dot", func.name()));
+        if let Err(e) = std::fs::write(&path, text) {
+            eprintln!("lancy: failed to write {}: {e}", path.display());
+        }
+    }
+}
+
+/// Compile every function of a module, returning the results in input
+/// order. Above `-O0`, small direct callees are first inlined across the
+/// module. With the `parallel` feature the functions are spread over
+/// scoped worker threads, one per core; the output is identical either
+/// way. A panic in any function's pipeline is re-raised on the caller.
+///
+/// # Panics
+/// On any error `try_compile_module` would return.
+#[must_use]
+pub fn compile_module(
+    funcs: Vec<Func<X64Inst>>,
+    target: Target,
+    options: &CodegenOptions,
+) -> Vec<CompiledCode> {
+    try_compile_module(funcs, target, options).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// `compile_module`, reporting failures instead of panicking.
+///
+/// # Errors
+/// The first function's error, in input order, that `try_compile_function`
+/// would return, or a `Verify` error if inlining left broken IR.
+pub fn try_compile_module(
+    mut funcs: Vec<Func<X64Inst>>,
+    target: Target,
+    options: &CodegenOptions,
+) -> Result<Vec<CompiledCode>, CodegenError> {
+    inline_module(&mut funcs, options)?;
+    compile_each(funcs, target, options).into_iter().collect()
+}
+
+/// Above `-O0`, inline small direct callees across `funcs`.
+pub(crate) fn inline_module(
+    funcs: &mut [Func<X64Inst>],
+    options: &CodegenOptions,
+) -> Result<(), CodegenError> {
+    let inline = match options.opt_level {
+        OptLevel::None => None,
+        OptLevel::Default => Some(InlineConfig::default()),
+        OptLevel::Size => Some(InlineConfig::size()),
+    };
+    if let Some(config) = inline
+        && inline_calls(funcs, &config) > 0
+    {
+        for func in funcs.iter() {
+            if options.verify
+                && let Err(error) = verify(func)
+            {
+                let pass = "inline_calls".to_string();
+                return Err(CodegenError::Verify { pass, error }.in_func(func.name()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run each of `funcs` through the pipeline, in parallel under the
+/// `parallel` feature, returning the results in input order.
+pub(crate) fn compile_each(
+    funcs: Vec<Func<X64Inst>>,
+    target: Target,
+    options: &CodegenOptions,
+) -> Vec<Result<CompiledCode, CodegenError>> {
+    #[cfg(feature = "parallel")]
+    {
+        let threads = std::thread::available_parallelism().map_or(1, std::num::NonZero::get);
+        if threads > 1 && funcs.len() > 1 {
+            return compile_parallel(funcs, target, options, threads);
+        }
+    }
+    funcs
+        .into_iter()
+        .map(|f| try_compile_function(f, target, options))
+        .collect()
+}
+
+/// Workers claim the next uncompiled function from a shared counter, so
+/// one large function doesn't hold up a whole pre-assigned share.
+#[cfg(feature = "parallel")]
+fn compile_parallel(
+    funcs: Vec<Func<X64Inst>>,
+    target: Target,
+    options: &CodegenOptions,
+    threads: usize,
+) -> Vec<Result<CompiledCode, CodegenError>> {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let n = funcs.len();
+    let inputs: Vec<Mutex<Option<Func<X64Inst>>>> =
+        funcs.into_iter().map(|f| Mutex::new(Some(f))).collect();
+    let outputs: Vec<Mutex<Option<Result<CompiledCode, CodegenError>>>> =
+        (0..n).map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(n))
+            .map(|_| {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= n {
+                            break;
+                        }
+                        let func = inputs[i]
+                            .lock()
+                            .expect("input slot poisoned")
+                            .take()
+                            .expect("each function is claimed once");
+                        let code = try_compile_function(func, target, options);
+                        *outputs[i].lock().expect("output slot poisoned") = Some(code);
+                    }
+                })
+            })
+            .collect();
+        for w in workers {
+            if let Err(payload) = w.join() {
+                std::panic::resume_unwind(payload);
+            }
+        }
+    });
+    outputs
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .expect("output slot poisoned")
+                .expect("every function compiled")
+        })
+        .collect()
+}
+
 /// Compile a function and load the resulting bytes into an executable mapping.
 /// Returns the `Module` (which must outlive any derived function pointers).
 ///
@@ -119,7 +603,11 @@ pub fn jit(func: Func<X64Inst>) -> std::io::Result<Module> {
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
 mod tests {
     use super::*;
-    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::builder::{FuncBuilder, I128};
+    use crate::codegen::isa::x64::passes::coverage::{counter_table, coverage_profile};
+    use crate::codegen::options::{FramePointer, SwitchLowering};
+    use crate::codegen::tir::{Block, BlockHint};
+    use crate::support::slotmap::Key;
 
     #[allow(non_camel_case_types)]
     type FnI64_I64 = unsafe extern "sysv64" fn(i64) -> i64;
@@ -249,6 +737,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn value_locations_cover_spilled_values_inside_the_code() {
+        use crate::codegen::value_locations::ValueLocation;
+
+        let mut b = FuncBuilder::new("located");
+        let a = b.arg();
+        let c = b.arg();
+        let mut vals = vec![a, c];
+        for _ in 0..15 {
+            let s = b.add(vals[vals.len() - 1], vals[vals.len() - 2]);
+            vals.push(s);
+        }
+        let mut acc = vals[0];
+        for v in &vals[1..] {
+            acc = b.add(acc, *v);
+        }
+        b.ret(acc);
+        let out = compile_full(b.build());
+        let map = &out.value_locations;
+        let len = out.bytes.len() as u32;
+        assert!(map.ranges().iter().all(|r| r.start < r.end && r.end <= len));
+        for w in map.ranges().windows(2) {
+            assert!(w[0].vreg < w[1].vreg || w[0].end <= w[1].start, "{w:?}");
+        }
+        assert!(map.ranges().iter().any(|r| matches!(
+            r.location,
+            ValueLocation::Frame(off) if off < 0 && off % 8 == 0
+        )));
+    }
+
     #[test]
     fn jit_deep_chain_forces_spills_to_stack() {
         let mut b = FuncBuilder::new("many_sums");
@@ -283,6 +801,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn jit_planned_spills_keep_values_live_across_a_loop() {
+        use crate::codegen::isa::x64::inst::Cond;
+
+        // Fourteen values live through a loop that uses one of them: more
+        // than the GPRs, so the planner must send some to memory.
+        let mut b = FuncBuilder::new("planned");
+        let a = b.arg();
+        let c = b.arg();
+        let mut vals = vec![a, c];
+        for _ in 0..12 {
+            let s = b.add(vals[vals.len() - 1], vals[vals.len() - 2]);
+            vals.push(s);
+        }
+        let acc = b.iconst64(0);
+        let i = b.iconst64(0);
+        let head = b.new_block();
+        let body = b.new_block();
+        let exit = b.new_block();
+        b.jmp(head);
+        b.switch_to_block(head);
+        let n = b.iconst64(5);
+        b.branch_icmp(Cond::GE, i, n, exit, body);
+        b.switch_to_block(body);
+        let t = b.add(acc, vals[2]);
+        b.copy_into(acc, t);
+        let one = b.iconst64(1);
+        let next = b.add(i, one);
+        b.copy_into(i, next);
+        b.jmp(head);
+        b.switch_to_block(exit);
+        let mut sum = acc;
+        for &v in &vals {
+            sum = b.add(sum, v);
+        }
+        b.ret(sum);
+
+        fn reference(a: i64, c: i64) -> i64 {
+            let mut v = vec![a, c];
+            for _ in 0..12 {
+                let s = v[v.len() - 1].wrapping_add(v[v.len() - 2]);
+                v.push(s);
+            }
+            let acc = v[2].wrapping_mul(5);
+            v.iter().copied().fold(acc, i64::wrapping_add)
+        }
+
+        let opts = CodegenOptions {
+            plan_spills: true,
+            check_regalloc: true,
+            ..CodegenOptions::default()
+        };
+        let compiled = compile_function(b.build(), Target::X64SysV, &opts);
+        let m = Module::load_with_relocs(&compiled.bytes, &compiled.relocations, &compiled.name)
+            .unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        for (x, y) in [(1_i64, 1_i64), (2, 3), (-1, -2), (7, -11)] {
+            assert_eq!(unsafe { f(x, y) }, reference(x, y), "f({x},{y})");
+        }
+    }
+
     #[test]
     fn jit_uses_all_six_sysv_arg_registers() {
         #[allow(non_camel_case_types)]
@@ -399,6 +978,367 @@ mod tests {
         assert!(!a.is_empty());
     }
 
+    #[test]
+    fn compile_function_with_defaults_matches_compile() {
+        let build = || {
+            let mut b = FuncBuilder::new("add");
+            let x = b.arg();
+            let y = b.arg();
+            let s = b.add(x, y);
+            b.ret(s);
+            b.build()
+        };
+        let out = compile_function(build(), Target::X64SysV, &CodegenOptions::default());
+        assert_eq!(out.name, "add");
+        assert!(out.relocations.is_empty());
+        assert_eq!(out.bytes, compile(build()));
+    }
+
+    #[test]
+    fn time_passes_records_every_pipeline_stage() {
+        let mut b = FuncBuilder::new("timed");
+        let x = b.arg();
+        b.ret(x);
+        let opts = CodegenOptions {
+            time_passes: true,
+            ..CodegenOptions::default()
+        };
+        let out = compile_function(b.build(), Target::X64SysV, &opts);
+        let passes: Vec<_> = out.timings.records().iter().map(|r| r.pass).collect();
+        assert_eq!(
+            passes,
+            [
+                "select_for_features",
+                "lower_intrinsics",
+                "lower_aggregates",
+                "lower_selects",
+                "lower_switches",
+                "destroy_ssa",
+                "fold_constants",
+                "thread_jumps",
+                "forward_empty_blocks",
+                "duplicate_tails",
+                "merge_blocks",
+                "load_elim",
+                "peephole",
+                "layout_blocks",
+                "schedule",
+                "linearize_blocks",
+                "abi_lower",
+                "cfg",
+                "regalloc",
+                "redundant_moves",
+                "simplify_branches",
+                "emit",
+            ]
+        );
+        assert!(out.timings.records().iter().all(|r| r.func == "timed"));
+    }
+
+    /// `f{i}` multiplies its argument by `i`, `i` times.
+    fn module_func(i: i64) -> Func<X64Inst> {
+        let mut b = FuncBuilder::new(format!("f{i}"));
+        let mut acc = b.arg();
+        for _ in 0..i {
+            let k = b.iconst64(i);
+            acc = b.imul(acc, k);
+        }
+        b.ret(acc);
+        b.build()
+    }
+
+    fn assert_module_output(out: &[CompiledCode], opts: &CodegenOptions) {
+        assert_eq!(out.len(), 16);
+        for (i, code) in (0..16).zip(out) {
+            let expected = compile_function(module_func(i), Target::X64SysV, opts);
+            assert_eq!(code.name, expected.name);
+            assert_eq!(code.bytes, expected.bytes);
+        }
+    }
+
+    #[test]
+    fn compile_module_inlines_small_callees() {
+        use crate::codegen::isa::x64::inst::Cond;
+
+        let mut b = FuncBuilder::new("max");
+        let x = b.arg();
+        let y = b.arg();
+        let (t, e) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::G, x, y, t, e);
+        b.switch_to_block(t);
+        b.ret(x);
+        b.switch_to_block(e);
+        b.ret(y);
+        let max = b.build();
+        let mut b = FuncBuilder::new("max3");
+        let x = b.arg();
+        let y = b.arg();
+        let z = b.arg();
+        let m = b.call_sym("max", &[x, y]);
+        let m = b.call_sym("max", &[m, z]);
+        b.ret(m);
+
+        let out = compile_module(vec![b.build(), max], Target::X64SysV, &CodegenOptions::default());
+        assert!(out[0].relocations.is_empty());
+        let m = Module::load(&out[0].bytes).unwrap();
+        let f: unsafe extern "sysv64" fn(i64, i64, i64) -> i64 = unsafe { m.entry() };
+        for (x, y, z) in [(1, 2, 3), (3, 2, 1), (-5, 7, 0), (4, 4, -4)] {
+            assert_eq!(unsafe { f(x, y, z) }, x.max(y).max(z));
+        }
+    }
+
+    #[test]
+    fn compile_module_keeps_input_order() {
+        let opts = CodegenOptions::default();
+        let out = compile_module((0..16).map(module_func).collect(), Target::X64SysV, &opts);
+        assert_module_output(&out, &opts);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_workers_match_sequential_output() {
+        let opts = CodegenOptions::default();
+        let funcs = (0..16).map(module_func).collect();
+        let out: Result<Vec<_>, _> =
+            compile_parallel(funcs, Target::X64SysV, &opts, 4).into_iter().collect();
+        let out = out.expect("compiles");
+        assert_module_output(&out, &opts);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn worker_errors_stay_with_their_function() {
+        let funcs = vec![module_func(1), Func::new("empty".into())];
+        let out = compile_parallel(funcs, Target::X64SysV, &CodegenOptions::default(), 2);
+        assert!(out[0].is_ok());
+        let err = out[1].as_ref().err().expect("an empty function fails");
+        assert_eq!(err.location().expect("located").func, "empty", "{err}");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn panic_in_a_worker_reaches_the_caller() {
+        // A vreg of another function is out of range in this one, which
+        // only the verifier turns into an error.
+        let mut other = FuncBuilder::new("other");
+        let (_, foreign) = (other.arg(), other.arg());
+        let mut b = FuncBuilder::new("foreign");
+        b.ret(foreign);
+        let funcs = vec![module_func(1), b.build()];
+        let opts = CodegenOptions { verify: false, ..CodegenOptions::default() };
+        let caught =
+            std::panic::catch_unwind(|| compile_parallel(funcs, Target::X64SysV, &opts, 2));
+        assert!(caught.is_err());
+    }
+
+    #[test]
+    fn cold_function_skips_tail_duplication_and_keeps_its_attrs() {
+        let mut b = FuncBuilder::new("cold");
+        let x = b.arg();
+        b.ret(x);
+        let mut func = b.build();
+        func.attrs_mut().cold = true;
+        let opts = CodegenOptions {
+            time_passes: true,
+            ..CodegenOptions::default()
+        };
+        let out = compile_function(func, Target::X64SysV, &opts);
+        assert!(out.timings.records().iter().all(|r| r.pass != "duplicate_tails"));
+        assert!(out.attrs.cold);
+    }
+
+    #[test]
+    fn print_after_all_writes_one_dump_per_ir_pass_into_dump_dir() {
+        let dir = std::env::temp_dir().join(format!("lancy-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut b = FuncBuilder::new("dumped");
+        let x = b.arg();
+        b.ret(x);
+        let opts = CodegenOptions {
+            print_after_all: true,
+            dump_dir: Some(dir.clone()),
+            ..CodegenOptions::default()
+        };
+        let _ = compile_function(b.build(), Target::X64SysV, &opts);
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "dumped.01.select_for_features.tir",
+                "dumped.02.lower_intrinsics.tir",
+                "dumped.03.lower_aggregates.tir",
+                "dumped.04.lower_selects.tir",
+                "dumped.05.lower_switches.tir",
+                "dumped.06.destroy_ssa.tir",
+                "dumped.07.fold_constants.tir",
+                "dumped.08.thread_jumps.tir",
+                "dumped.09.forward_empty_blocks.tir",
+                "dumped.10.duplicate_tails.tir",
+                "dumped.11.merge_blocks.tir",
+                "dumped.12.load_elim.tir",
+                "dumped.13.peephole.tir",
+                "dumped.14.layout_blocks.tir",
+                "dumped.15.schedule.tir",
+                "dumped.16.linearize_blocks.tir",
+                "dumped.17.abi_lower.tir",
+                "dumped.18.simplify_branches.tir",
+            ]
+        );
+        let last = std::fs::read_to_string(dir.join(&names[16])).unwrap();
+        assert!(last.starts_with("*** IR dump after abi_lower ***"));
+        assert!(last.contains("dumped:"));
+        // After allocation, operands print as the registers they got.
+        let allocated = std::fs::read_to_string(dir.join(&names[17])).unwrap();
+        assert!(allocated.contains("rax = copy rdi"), "{allocated}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dump_dot_writes_the_three_graphs_into_dump_dir() {
+        let dir = std::env::temp_dir().join(format!("lancy-dot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut b = FuncBuilder::new("graphs");
+        let x = b.arg();
+        let y = b.arg();
+        let s = b.add(x, y);
+        b.ret(s);
+        let opts = CodegenOptions {
+            dump_dot: true,
+            dump_dir: Some(dir.clone()),
+            ..CodegenOptions::default()
+        };
+        let _ = compile_function(b.build(), Target::X64SysV, &opts);
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["graphs.cfg.dot", "graphs.domtree.dot", "graphs.interference.dot"]
+        );
+        let interference = std::fs::read_to_string(dir.join(&names[2])).unwrap();
+        assert!(interference.starts_with("graph \"graphs.interference\" {"));
+        assert!(interference.contains(" -- "), "{interference}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn disabling_coalescing_keeps_copies_as_real_moves() {
+        let build = || {
+            let mut b = FuncBuilder::new("chain");
+            let x = b.arg();
+            let y = b.arg();
+            let s = b.add(x, y);
+            let t = b.add(s, x);
+            b.ret(t);
+            b.build()
+        };
+        let on = compile_function(build(), Target::X64SysV, &CodegenOptions::default());
+        let off = compile_function(
+            build(),
+            Target::X64SysV,
+            &CodegenOptions {
+                coalesce: false,
+                ..CodegenOptions::default()
+            },
+        );
+        assert!(off.bytes.len() > on.bytes.len());
+        let m = Module::load(&off.bytes).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(3, 4) }, 10);
+    }
+
+    #[test]
+    fn fallthrough_branches_shrink_code_and_keep_semantics() {
+        use crate::codegen::isa::x64::inst::Cond;
+        let build = || {
+            let mut b = FuncBuilder::new("max");
+            let a = b.arg();
+            let c = b.arg();
+            let then_blk = b.new_block();
+            let else_blk = b.new_block();
+            b.branch_icmp(Cond::GE, a, c, then_blk, else_blk);
+            b.switch_to_block(then_blk);
+            b.ret(a);
+            b.switch_to_block(else_blk);
+            b.ret(c);
+            b.build()
+        };
+        let opt = compile_function(build(), Target::X64SysV, &CodegenOptions::default());
+        let plain = compile_function(
+            build(),
+            Target::X64SysV,
+            &CodegenOptions {
+                opt_level: OptLevel::None,
+                ..CodegenOptions::default()
+            },
+        );
+        assert!(opt.bytes.len() < plain.bytes.len());
+        let m = Module::load(&opt.bytes).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(5, 3) }, 5);
+        assert_eq!(unsafe { f(3, 5) }, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "IR verification failed after select_for_features")]
+    fn verifier_reports_the_pass_that_left_broken_ir() {
+        let mut b = FuncBuilder::new("unterminated");
+        let _ = b.arg();
+        let opts = CodegenOptions {
+            verify: true,
+            ..CodegenOptions::default()
+        };
+        let _ = compile_function(b.build(), Target::X64SysV, &opts);
+    }
+
+    #[test]
+    fn try_compile_module_reports_the_failing_function() {
+        let ok = {
+            let mut b = FuncBuilder::new("ok");
+            let x = b.arg();
+            b.ret(x);
+            b.build()
+        };
+        let mut b = FuncBuilder::new("unterminated");
+        let _ = b.arg();
+        let funcs = vec![ok, b.build()];
+        let Err(err) = try_compile_module(funcs, Target::X64SysV, &CodegenOptions::default())
+        else {
+            panic!("an unterminated block fails");
+        };
+        assert_eq!(err.location().expect("located").func, "unterminated", "{err}");
+    }
+
+    #[test]
+    fn try_compile_reports_where_verification_failed() {
+        let mut b = FuncBuilder::new("unterminated");
+        let _ = b.arg();
+        let opts = CodegenOptions {
+            verify: true,
+            ..CodegenOptions::default()
+        };
+        let Err(err) = try_compile_function(b.build(), Target::X64SysV, &opts) else {
+            panic!("an unterminated block fails verification");
+        };
+        let loc = err.location().expect("the error carries its function");
+        assert_eq!(loc.func, "unterminated");
+        assert!(loc.block.is_some(), "{err}");
+        assert!(
+            matches!(
+                err.root(),
+                CodegenError::Verify { pass, .. } if pass == "select_for_features"
+            ),
+            "{err}"
+        );
+        assert!(err.to_string().starts_with("`unterminated` @"), "{err}");
+    }
+
     #[test]
     fn compile_emits_prologue_and_epilogue_markers() {
         let mut b = FuncBuilder::new("t");
@@ -620,25 +1560,554 @@ mod tests {
             let want = (a % b) as i64;
             assert_eq!(unsafe { f(a as i64, b as i64) }, want, "({a},{b})");
         }
-    }
+    }
+
+    #[test]
+    fn jit_select_picks_true_or_false_value() {
+        use crate::codegen::isa::x64::inst::Cond;
+        let mut b = FuncBuilder::new("select_lt");
+        let x = b.arg();
+        let y = b.arg();
+        // result = if x < y { 100 } else { 200 }
+        let t = b.iconst64(100);
+        let f = b.iconst64(200);
+        let r = b.select(Cond::L, x, y, t, f);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(1, 2) }, 100);
+        assert_eq!(unsafe { f(2, 1) }, 200);
+        assert_eq!(unsafe { f(5, 5) }, 200);
+        assert_eq!(unsafe { f(-5, -3) }, 100);
+    }
+
+    // -------------- 128-bit integers --------------
+
+    #[allow(non_camel_case_types)]
+    type FnI128I128_I128 = unsafe extern "sysv64" fn(i128, i128) -> i128;
+
+    type I128Op = fn(&mut FuncBuilder, I128, I128) -> I128;
+
+    /// `f(a, b) = op(a, b)` at `opt_level`, checking the allocation.
+    fn jit_i128_binop(opt_level: OptLevel, op: I128Op) -> Module {
+        let mut b = FuncBuilder::new("i128_op");
+        let (x, y) = (b.arg_i128(), b.arg_i128());
+        let r = op(&mut b, x, y);
+        b.ret_i128(r);
+        let opts = CodegenOptions {
+            opt_level,
+            check_regalloc: true,
+            ..CodegenOptions::default()
+        };
+        let out = compile_function(b.build(), Target::X64SysV, &opts);
+        Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).expect("load")
+    }
+
+    const I128_SAMPLES: [i128; 9] = [
+        0,
+        1,
+        -1,
+        u64::MAX as i128,
+        1 << 64,
+        i64::MIN as i128,
+        i128::MAX,
+        i128::MIN,
+        0x1234_5678_9abc_def0_0fed_cba9_8765_4321,
+    ];
+
+    #[test]
+    fn jit_i128_arithmetic_carries_between_halves() {
+        let check = |build: I128Op, want: fn(i128, i128) -> i128| {
+            for opt_level in [OptLevel::None, OptLevel::Default] {
+                let m = jit_i128_binop(opt_level, build);
+                let f: FnI128I128_I128 = unsafe { m.entry() };
+                for a in I128_SAMPLES {
+                    for b in I128_SAMPLES {
+                        assert_eq!(unsafe { f(a, b) }, want(a, b), "{a:#x}, {b:#x}");
+                    }
+                }
+            }
+        };
+        check(FuncBuilder::add_i128, i128::wrapping_add);
+        check(FuncBuilder::sub_i128, i128::wrapping_sub);
+        check(FuncBuilder::mul_i128, i128::wrapping_mul);
+    }
+
+    #[test]
+    fn jit_i128_compares_match_rust() {
+        use crate::codegen::isa::x64::inst::Cond;
+        type F = unsafe extern "sysv64" fn(i128, i128) -> i64;
+        let conds = [
+            Cond::Z,
+            Cond::NZ,
+            Cond::L,
+            Cond::LE,
+            Cond::G,
+            Cond::GE,
+            Cond::B,
+            Cond::BE,
+            Cond::A,
+            Cond::AE,
+        ];
+        for cond in conds {
+            let mut b = FuncBuilder::new("i128_cmp");
+            let (x, y) = (b.arg_i128(), b.arg_i128());
+            let r = b.icmp_i128(cond, x, y);
+            b.ret(r);
+            let m = jit(b.build()).expect("jit");
+            let f: F = unsafe { m.entry() };
+            for a in I128_SAMPLES {
+                for c in I128_SAMPLES {
+                    let (ua, uc) = (a.cast_unsigned(), c.cast_unsigned());
+                    let want = match cond {
+                        Cond::Z => a == c,
+                        Cond::NZ => a != c,
+                        Cond::L => a < c,
+                        Cond::LE => a <= c,
+                        Cond::G => a > c,
+                        Cond::GE => a >= c,
+                        Cond::B => ua < uc,
+                        Cond::BE => ua <= uc,
+                        Cond::A => ua > uc,
+                        Cond::AE => ua >= uc,
+                    };
+                    assert_eq!(unsafe { f(a, c) }, i64::from(want), "{a:#x} {cond} {c:#x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jit_i128_carry_survives_spills_around_it() {
+        // More values live across the `add`/`adc` than there are
+        // registers, so some of them spill around the pair.
+        let mut b = FuncBuilder::new("i128_pressure");
+        let (x, y) = (b.arg_i128(), b.arg_i128());
+        let live: Vec<Reg> = (0..16).map(|_| b.add(x.lo, y.hi)).collect();
+        let sum = b.add_i128(x, y);
+        let mut acc = sum.hi;
+        for v in live {
+            acc = b.add(acc, v);
+        }
+        b.ret_i128(I128 { lo: sum.lo, hi: acc });
+        let opts = CodegenOptions {
+            check_regalloc: true,
+            ..CodegenOptions::default()
+        };
+        let out = compile_function(b.build(), Target::X64SysV, &opts);
+        let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).expect("load");
+        let f: FnI128I128_I128 = unsafe { m.entry() };
+        for (a, c) in [(i128::from(u64::MAX), 1), (-1, -1), (i128::MAX, 1 << 64)] {
+            let s = a.wrapping_add(c);
+            let extra = 16 * ((a as i64).wrapping_add((c >> 64) as i64));
+            let hi = ((s >> 64) as i64).wrapping_add(extra);
+            let want = i128::from(s as u64) | i128::from(hi) << 64;
+            assert_eq!(unsafe { f(a, c) }, want, "{a:#x} + {c:#x}");
+        }
+    }
+
+    #[test]
+    fn jit_select_of_a_load_only_loads_on_its_arm() {
+        use crate::codegen::isa::x64::inst::Cond;
+        // if x < 0 { *p } else { x }: the load sinks into the taken arm,
+        // so a null `p` is fine whenever x >= 0.
+        let mut b = FuncBuilder::new("select_load");
+        let p = b.arg();
+        let x = b.arg();
+        let zero = b.iconst64(0);
+        let v = b.load_i64(p, 0);
+        let r = b.select(Cond::L, x, zero, v, x);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        let cell = 42i64;
+        assert_eq!(unsafe { f(std::ptr::addr_of!(cell) as i64, -1) }, 42);
+        assert_eq!(unsafe { f(0, 7) }, 7);
+    }
+
+    #[test]
+    fn jit_hinted_select_branches_to_the_same_values() {
+        use crate::codegen::isa::x64::inst::Cond;
+        let mut b = FuncBuilder::new("select_hinted");
+        let x = b.arg();
+        let y = b.arg();
+        let r = b.select_hinted(Cond::G, x, y, x, y, Some(BlockHint::Unlikely));
+        let one = b.iconst64(1);
+        let r = b.add(r, one);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(9, 3) }, 10);
+        assert_eq!(unsafe { f(3, 9) }, 10);
+        assert_eq!(unsafe { f(-4, -4) }, -3);
+    }
+
+    /// `match x { -2 → 20, 0 | 1 → x + 100, 3 → 30, 4 → 40, 9 → 90, _ → -1 }`,
+    /// the `0 | 1` arm merging through a phi.
+    fn switch_func() -> Func<X64Inst> {
+        let mut b = FuncBuilder::new("switch");
+        let x = b.arg();
+        let default = b.new_block();
+        let join = b.new_block();
+        let arms: Vec<(i64, Block)> = [-2, 3, 4, 9].iter().map(|&v| (v, b.new_block())).collect();
+        let mut cases = arms.clone();
+        cases.extend([(0, join), (1, join)]);
+        let hundred = b.iconst64(100);
+        b.switch(x, &cases, default);
+        b.switch_to_block(default);
+        let m1 = b.iconst64(-1);
+        b.ret(m1);
+        b.switch_to_block(join);
+        let entry = b.func().get_entry_block().expect("entry");
+        let h = b.phi(vec![(entry, hundred)]);
+        let r = b.add(x, h);
+        b.ret(r);
+        for (v, blk) in arms {
+            b.switch_to_block(blk);
+            let r = b.iconst64(v * 10);
+            b.ret(r);
+        }
+        b.build()
+    }
+
+    #[test]
+    fn jit_switch_lowerings_agree() {
+        for switch_lowering in
+            [SwitchLowering::Auto, SwitchLowering::JumpTable, SwitchLowering::CompareTree]
+        {
+            for opt_level in [OptLevel::None, OptLevel::Default] {
+                let opts = CodegenOptions {
+                    opt_level,
+                    switch_lowering,
+                    ..CodegenOptions::default()
+                };
+                let out = compile_function(switch_func(), Target::X64SysV, &opts);
+                assert_eq!(
+                    out.jump_tables.len(),
+                    usize::from(switch_lowering != SwitchLowering::CompareTree),
+                    "{switch_lowering:?}"
+                );
+                let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name)
+                    .expect("load");
+                let f: FnI64_I64 = unsafe { m.entry() };
+                for (x, want) in [
+                    (-3, -1),
+                    (-2, -20),
+                    (-1, -1),
+                    (0, 100),
+                    (1, 101),
+                    (2, -1),
+                    (3, 30),
+                    (9, 90),
+                    (10, -1),
+                    (i64::MIN, -1),
+                    (i64::MAX, -1),
+                ] {
+                    assert_eq!(unsafe { f(x) }, want, "{switch_lowering:?} {opt_level:?} x={x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jit_optimize_for_size_trades_the_table_and_padding_for_bytes() {
+        let compile = |opt_level| {
+            let opts = CodegenOptions { opt_level, ..CodegenOptions::default() };
+            compile_function(switch_func(), Target::X64SysV, &opts)
+        };
+        let (fast, small) = (compile(OptLevel::Default), compile(OptLevel::Size));
+        assert_eq!((fast.align, small.align), (FUNC_ALIGN, 1));
+        // Twelve table entries outweigh six compares.
+        assert_eq!((fast.jump_tables.len(), small.jump_tables.len()), (1, 0));
+        assert!(small.bytes.len() < fast.bytes.len(), "{} bytes", small.bytes.len());
+        let m = Module::load_with_relocs(&small.bytes, &small.relocations, &small.name)
+            .expect("load");
+        let f: FnI64_I64 = unsafe { m.entry() };
+        for (x, want) in [(-2, -20), (0, 100), (1, 101), (3, 30), (9, 90), (10, -1)] {
+            assert_eq!(unsafe { f(x) }, want, "x={x}");
+        }
+    }
+
+    #[test]
+    fn jit_coverage_counts_every_block_entry() {
+        let opts = CodegenOptions { coverage: true, ..CodegenOptions::default() };
+        let mut out = compile_function(switch_func(), Target::X64SysV, &opts);
+        let table = out.coverage.take().expect("counter table");
+        assert_eq!(table.name, counter_table("switch"));
+        let mut counters = vec![0u64; table.size() / 8];
+        // Bind the table to `counters` by hand; the rest resolve as usual.
+        let addr = counters.as_mut_ptr() as u64;
+        out.relocations.retain(|r| {
+            if r.symbol != table.name {
+                return true;
+            }
+            out.bytes[r.offset..r.offset + 8].copy_from_slice(&addr.to_le_bytes());
+            false
+        });
+        let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).expect("load");
+        let f: FnI64_I64 = unsafe { m.entry() };
+        for x in [-2, 0, 1, 3, 10] {
+            unsafe { f(x) };
+        }
+        // Entry, default, the `0 | 1` join, then the -2, 3, 4 and 9 arms.
+        assert_eq!(counters, [5, 1, 2, 1, 1, 0, 0]);
+        let profile = coverage_profile(&counters);
+        assert_eq!(profile.block_weight(Block::new(2)), Some(2));
+    }
+
+    #[cfg(feature = "regalloc2")]
+    #[test]
+    fn jit_regalloc2_allocates_loops_calls_and_spills() {
+        use crate::codegen::isa::x64::parser::parse_func_text;
+        use crate::codegen::options::RegAllocKind;
+
+        let opts =
+            CodegenOptions { regalloc: RegAllocKind::Regalloc2, ..CodegenOptions::default() };
+        let load = |func| {
+            let out = compile_function(func, Target::X64SysV, &opts);
+            Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).expect("load")
+        };
+
+        let m = load(switch_func());
+        let f: FnI64_I64 = unsafe { m.entry() };
+        for (x, want) in [(-2, -20), (0, 100), (1, 101), (3, 30), (9, 90), (10, -1)] {
+            assert_eq!(unsafe { f(x) }, want, "x={x}");
+        }
+
+        // Phis carried around a loop, `udiv` pinned to rax/rdx, and
+        // `labs` clobbering the caller-saved registers.
+        let m = load(
+            parse_func_text(
+                "func @f(%a, %d) {
+                 entry:
+                     %n = iconst 3
+                     %zero = iconst 0
+                     jmp head
+                 head:
+                     %i = phi [entry, %zero], [body, %i2]
+                     %acc = phi [entry, %a], [body, %acc2]
+                     br ge %i, %n, exit, body
+                 body:
+                     %q = udiv %acc, %d
+                     %acc2 = add %acc, %q
+                     %one = iconst 1
+                     %i2 = add %i, %one
+                     jmp head
+                 exit:
+                     %abs = call @labs(%acc)
+                     %r = add %abs, %d
+                     ret %r
+                 }",
+            )
+            .expect("parses"),
+        );
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        for (a, d) in [(100, 3), (7, 1), (0, 5)] {
+            let mut acc = a;
+            for _ in 0..3 {
+                acc += acc / d;
+            }
+            assert_eq!(unsafe { f(a, d) }, acc + d, "a={a} d={d}");
+        }
+
+        let mut b = FuncBuilder::new("many_sums");
+        let mut vals = vec![b.arg(), b.arg()];
+        for _ in 0..15 {
+            let s = b.add(vals[vals.len() - 1], vals[vals.len() - 2]);
+            vals.push(s);
+        }
+        let mut acc = vals[0];
+        for v in &vals[1..] {
+            acc = b.add(acc, *v);
+        }
+        b.ret(acc);
+        let m = load(b.build());
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        // Fibonacci-like: the sum of 17 terms is F(19) - 1 for (1, 1).
+        assert_eq!(unsafe { f(1, 1) }, 4180);
+    }
+
+    #[test]
+    fn jit_cet_pads_the_entry_and_every_jump_table_target() {
+        const ENDBR64: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFA];
+        let opts = CodegenOptions {
+            switch_lowering: SwitchLowering::JumpTable,
+            cet: true,
+            ..CodegenOptions::default()
+        };
+        let out = compile_function(switch_func(), Target::X64SysV, &opts);
+        assert!(out.endbr);
+        assert_eq!(out.bytes[..4], ENDBR64);
+        let targets = &out.jump_tables[0].targets;
+        for &t in targets {
+            assert_eq!(out.bytes[t as usize..t as usize + 4], ENDBR64, "target {t:#x}");
+        }
+        let pads = out.bytes.windows(4).filter(|w| *w == ENDBR64).count();
+        let distinct: HashSet<u32> = targets.iter().copied().collect();
+        assert_eq!(pads, 1 + distinct.len(), "only the entry and table targets");
+        let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).expect("load");
+        let f: FnI64_I64 = unsafe { m.entry() };
+        for (x, want) in [(-2, -20), (0, 100), (3, 30), (10, -1)] {
+            assert_eq!(unsafe { f(x) }, want, "x={x}");
+        }
+    }
+
+    #[test]
+    fn jit_retpoline_jump_tables_keep_leaf_locals_out_of_the_red_zone() {
+        // A leaf whose small frame holds a live word at its top, right
+        // where the retpoline thunk's `call` pushes a return address.
+        // Seven other live values lay the frame out so a live slot sits there.
+        let leaf = || {
+            let mut b = FuncBuilder::new("leaf");
+            let x = b.arg();
+            let extras: Vec<_> = (1..=7)
+                .map(|i| {
+                    let c = b.iconst64(i);
+                    b.add(x, c)
+                })
+                .collect();
+            let p = b.stack_alloc(16, 8);
+            let v = b.iconst64(77);
+            b.store_i64(p, 8, v);
+            let (join, default) = (b.new_block(), b.new_block());
+            let arms: Vec<_> = (0..8).map(|i| (i, b.new_block())).collect();
+            b.switch(x, &arms, default);
+            let mut incoming = Vec::new();
+            for &(i, blk) in arms.iter().chain([(-1, default)].iter()) {
+                b.switch_to_block(blk);
+                incoming.push((blk, b.iconst64(i * 10)));
+                b.jmp(join);
+            }
+            b.switch_to_block(join);
+            let arm = b.phi(incoming);
+            let w = b.load_i64(p, 8);
+            let mut r = b.add(arm, w);
+            for e in extras {
+                r = b.add(r, e);
+            }
+            b.ret(r);
+            b.build()
+        };
+        for (red_zone, speculation_hardening) in [
+            (true, SpeculationHardening::Retpoline),
+            (false, SpeculationHardening::Retpoline),
+            (true, SpeculationHardening::Off),
+        ] {
+            let opts = CodegenOptions {
+                red_zone,
+                speculation_hardening,
+                switch_lowering: SwitchLowering::JumpTable,
+                ..CodegenOptions::default()
+            };
+            let out = compile_function(leaf(), Target::X64SysV, &opts);
+            assert_eq!(out.jump_tables.len(), 1);
+            let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).unwrap();
+            let f: FnI64_I64 = unsafe { m.entry() };
+            for x in [0, 2, 7, 9] {
+                let arm = if (0..8).contains(&x) { x * 10 } else { -10 };
+                let want = 77 + arm + (1..=7).map(|i| x + i).sum::<i64>();
+                assert_eq!(unsafe { f(x) }, want, "{speculation_hardening:?} x={x}");
+            }
+        }
+    }
+
+    #[test]
+    fn jit_omitted_frame_pointer_addresses_leaf_frames_off_rsp() {
+        const PUSH_RBP: u8 = 0x55;
+        // `n` values live at once spill some of them, and push an odd or
+        // even number of callee-saved registers depending on `n`.
+        let leaf = |n: i64| {
+            let mut b = FuncBuilder::new("leaf");
+            let args: Vec<_> = (0..7).map(|_| b.arg()).collect();
+            let stk = args[6]; // 7th arg — stack-passed
+            let p = b.stack_alloc(32, 16);
+            b.store_i64(p, 16, stk);
+            let vals: Vec<_> = (0..n)
+                .map(|i| {
+                    let c = b.iconst64(i);
+                    b.add(stk, c)
+                })
+                .collect();
+            let fifteen = b.iconst64(15);
+            let misaligned = b.and(p, fifteen);
+            let mut r = b.load_i64(p, 16);
+            r = b.add(r, misaligned);
+            for v in vals {
+                r = b.add(r, v);
+            }
+            b.ret(r);
+            b.build()
+        };
+        type Fn7 = unsafe extern "sysv64" fn(i64, i64, i64, i64, i64, i64, i64) -> i64;
+        for n in [2, 12, 13, 24] {
+            for (frame_pointer, red_zone) in [
+                (FramePointer::Omit, true),
+                (FramePointer::Omit, false),
+                (FramePointer::Always, true),
+            ] {
+                let opts = CodegenOptions { frame_pointer, red_zone, ..CodegenOptions::default() };
+                let out = compile_function(leaf(n), Target::X64SysV, &opts);
+                assert_eq!(out.bytes[0] == PUSH_RBP, frame_pointer == FramePointer::Always);
+                let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).unwrap();
+                let f: Fn7 = unsafe { m.entry() };
+                for x in [0, 5, -9] {
+                    let want = x + (0..n).map(|i| x + i).sum::<i64>();
+                    assert_eq!(unsafe { f(1, 2, 3, 4, 5, 6, x) }, want, "{frame_pointer:?} n={n}");
+                }
+            }
+        }
 
-    #[test]
-    fn jit_select_picks_true_or_false_value() {
-        use crate::codegen::isa::x64::inst::Cond;
-        let mut b = FuncBuilder::new("select_lt");
+        // A function that calls keeps its frame pointer.
+        let mut b = FuncBuilder::new("calls");
         let x = b.arg();
-        let y = b.arg();
-        // result = if x < y { 100 } else { 200 }
-        let t = b.iconst64(100);
-        let f = b.iconst64(200);
-        let r = b.select(Cond::L, x, y, t, f);
+        let r = b.call_sym("labs", &[x]);
         b.ret(r);
-        let m = jit(b.build()).unwrap();
-        let f: FnI64I64_I64 = unsafe { m.entry() };
-        assert_eq!(unsafe { f(1, 2) }, 100);
-        assert_eq!(unsafe { f(2, 1) }, 200);
-        assert_eq!(unsafe { f(5, 5) }, 200);
-        assert_eq!(unsafe { f(-5, -3) }, 100);
+        let opts = CodegenOptions { frame_pointer: FramePointer::Omit, ..CodegenOptions::default() };
+        let out = compile_function(b.build(), Target::X64SysV, &opts);
+        assert_eq!(out.bytes[0], PUSH_RBP);
+    }
+
+    #[test]
+    fn jit_speculation_hardening_keeps_indirect_branches_working() {
+        const CALL_R11: [u8; 3] = [0x41, 0xFF, 0xD3];
+        const LFENCE: [u8; 3] = [0x0F, 0xAE, 0xE8];
+        let calls = || {
+            let mut b = FuncBuilder::new("calls");
+            let x = b.arg();
+            let a = b.call_sym("labs", &[x]);
+            let r = b.call_sym("labs", &[a]);
+            b.ret(r);
+            b.build()
+        };
+        let count =
+            |bytes: &[u8], pat: &[u8]| bytes.windows(pat.len()).filter(|w| *w == pat).count();
+        for mode in [SpeculationHardening::Retpoline, SpeculationHardening::Lfence] {
+            let opts = CodegenOptions {
+                speculation_hardening: mode,
+                switch_lowering: SwitchLowering::JumpTable,
+                ..CodegenOptions::default()
+            };
+            let out = compile_function(calls(), Target::X64SysV, &opts);
+            let code = &out.bytes[..out.code_len()];
+            if mode == SpeculationHardening::Retpoline {
+                // One shared thunk for r11, with the only `lfence`.
+                assert_eq!(count(code, &CALL_R11), 0);
+                assert_eq!(count(code, &LFENCE), 1);
+            } else {
+                assert_eq!(count(code, &[LFENCE, CALL_R11].concat()), 2);
+            }
+            let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).unwrap();
+            let f: FnI64_I64 = unsafe { m.entry() };
+            assert_eq!(unsafe { f(-42) }, 42, "{mode:?}");
+
+            let out = compile_function(switch_func(), Target::X64SysV, &opts);
+            assert_eq!(out.jump_tables.len(), 1);
+            let m = Module::load_with_relocs(&out.bytes, &out.relocations, &out.name).unwrap();
+            let f: FnI64_I64 = unsafe { m.entry() };
+            for (x, want) in [(-2, -20), (0, 100), (3, 30), (10, -1)] {
+                assert_eq!(unsafe { f(x) }, want, "{mode:?} x={x}");
+            }
+        }
     }
 
     // -------------- Phi / SSA destruction coverage --------------
@@ -727,6 +2196,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn jit_value_split_inside_a_loop_survives_the_back_edge() {
+        // `udiv` needs rdx, which holds `r` — live around the whole loop.
+        // Splitting `r` at the division must not leave the back edge
+        // jumping to a header that still reads rdx.
+        let func = crate::codegen::isa::x64::parser::parse_func_text(
+            "func @f(%a, %d) {
+             entry:
+                 %sq = imul %a, %a
+                 %r = srem %sq, %d
+                 %n = iconst 3
+                 %zero = iconst 0
+                 jmp head
+             head:
+                 %i = phi [entry, %zero], [body, %i2]
+                 %acc = phi [entry, %r], [body, %acc2]
+                 br ge %i, %n, exit, body
+             body:
+                 %q = udiv %r, %a
+                 %acc2 = add %acc, %q
+                 %one = iconst 1
+                 %i2 = add %i, %one
+                 jmp head
+             exit:
+                 ret %acc
+             }",
+        )
+        .expect("parses");
+        let m = jit(func).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        // r = 9 % 5 = 4; three iterations add 4 / 3 = 1.
+        assert_eq!(unsafe { f(3, 5) }, 7);
+    }
+
     // -------------- Conversion coverage --------------
 
     #[test]
@@ -825,6 +2328,105 @@ mod tests {
         }
     }
 
+    #[test]
+    fn raw_bytes_run_with_pinned_operands_and_clobbers() {
+        use crate::codegen::isa::x64::regs::{R8, R9, R10, R11, RDI, RSI};
+
+        let mut b = FuncBuilder::new("raw");
+        let x = b.arg();
+        let y = b.arg();
+        // Enough values live across the bytes that some would otherwise
+        // sit in the clobbered registers.
+        let live: Vec<Reg> = (1..=8).map(|i| b.iconst64(i)).collect();
+        // add rax, rcx
+        let sum = b.raw_bytes(&[0x48, 0x01, 0xC8], &[(x, RAX), (y, RCX)], &[RAX], &[]);
+        // xor edx, esi, edi, r8d, r9d, r10d, r11d with themselves
+        let zeroing = [
+            0x31, 0xD2, 0x31, 0xF6, 0x31, 0xFF, 0x45, 0x31, 0xC0, 0x45, 0x31, 0xC9, 0x45, 0x31,
+            0xD2, 0x45, 0x31, 0xDB,
+        ];
+        b.raw_bytes(&zeroing, &[], &[], &[RDX, RSI, RDI, R8, R9, R10, R11]);
+        let mut acc = b.add(sum[0], x);
+        for &v in &live {
+            acc = b.add(acc, v);
+        }
+        b.ret(acc);
+        let m = jit(b.build()).expect("jit");
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        // (x + y) + x + (1 + ... + 8)
+        assert_eq!(unsafe { f(5, 7) }, 17 + 36);
+        assert_eq!(unsafe { f(-3, 100) }, 94 + 36);
+    }
+
+    #[test]
+    fn large_frames_touch_every_guard_page() {
+        // `mov qword ptr [rsp], 0`
+        const PROBE: [u8; 8] = [0x48, 0xC7, 0x04, 0x24, 0, 0, 0, 0];
+        let build = |size: u32| {
+            let mut b = FuncBuilder::new("big_frame");
+            let x = b.arg();
+            let buf = b.stack_alloc(size, 8);
+            b.store_i64(buf, size as i32 - 8, x);
+            let y = b.load_i64(buf, size as i32 - 8);
+            b.ret(y);
+            b.build()
+        };
+        let probes = |func, stack_probes| {
+            let options = CodegenOptions {
+                stack_probes,
+                ..CodegenOptions::default()
+            };
+            let code = compile_function(func, Target::X64SysV, &options);
+            code.bytes.windows(PROBE.len()).filter(|w| *w == PROBE).count()
+        };
+        assert_eq!(probes(build(1024), true), 0);
+        assert_eq!(probes(build(3 * 4096), true), 3, "unrolled per page");
+        assert_eq!(probes(build(64 * 4096), true), 1, "one probe inside a loop");
+        assert_eq!(probes(build(64 * 4096), false), 0);
+
+        for size in [3 * 4096 + 40, 64 * 4096 + 40] {
+            let m = jit(build(size)).expect("jit");
+            let f: FnI64_I64 = unsafe { m.entry() };
+            assert_eq!(unsafe { f(77) }, 77, "size={size}");
+        }
+    }
+
+    #[test]
+    fn jit_stack_protector_calls_the_handler_on_an_overflow() {
+        // Fills `len` bytes of a 16-byte buffer; past 16 the canary, which
+        // sits right above it, is overwritten.
+        let mut b = FuncBuilder::new("fill");
+        let (byte, len) = (b.arg(), b.arg());
+        let buf = b.stack_alloc(16, 8);
+        b.memset(buf, byte, len);
+        let first = b.load_i64(buf, 0);
+        b.ret(first);
+        let options = CodegenOptions {
+            stack_protector: Some("abort".to_string()),
+            ..CodegenOptions::default()
+        };
+        let code = compile_function(b.build(), Target::X64SysV, &options);
+        assert!(code.relocations.iter().any(|r| r.symbol == "abort"));
+        let m = Module::load_with_relocs(&code.bytes, &code.relocations, &code.name).unwrap();
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(0x41, 16) }, 0x4141_4141_4141_4141);
+
+        // The overflow aborts, so run it in a child.
+        // SAFETY: the child only calls the JITed code and exits.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            unsafe {
+                f(0x41, 24);
+                libc::_exit(0);
+            }
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &raw mut status, 0) }, pid);
+        assert!(libc::WIFSIGNALED(status), "status {status:#x}");
+        assert_eq!(libc::WTERMSIG(status), libc::SIGABRT);
+    }
+
     #[test]
     fn jit_alloca_two_slots_store_load() {
         let mut b = FuncBuilder::new("alloca_two");
@@ -882,6 +2484,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn jit_reloads_after_stores_see_the_latest_value() {
+        // slot[0] = x; slot[1] = y; slot[0] = slot[1] + slot[0];
+        // return slot[0] - slot[1].
+        let mut b = FuncBuilder::new("reload");
+        let x = b.arg();
+        let y = b.arg();
+        let slot = b.stack_alloc(2 * 8, 8);
+        b.store_i64(slot, 0, x);
+        b.store_i64(slot, 8, y);
+        let a = b.load_i64(slot, 8);
+        let c = b.load_i64(slot, 0);
+        let s = b.add(a, c);
+        b.store_i64(slot, 0, s);
+        let d = b.load_i64(slot, 0);
+        let e = b.load_i64(slot, 8);
+        let r = b.sub(d, e);
+        b.ret(r);
+        let m = jit(b.build()).expect("jit");
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(5, 7) }, 5);
+        assert_eq!(unsafe { f(-3, 40) }, -3);
+    }
+
     // -------------- Call coverage --------------
 
     #[test]
@@ -898,6 +2524,116 @@ mod tests {
         }
     }
 
+    #[test]
+    fn jit_symbol_address_is_patched_by_the_loader() {
+        // fn(x) -> (&labs)(x)
+        let mut b = FuncBuilder::new("labs_by_addr");
+        let x = b.arg();
+        let p = b.symbol_addr("labs");
+        let r = b.call_indirect(p, &[x]);
+        b.ret(r);
+        let compiled = compile_full(b.build());
+        let relocs: Vec<&str> = compiled.relocations.iter().map(|r| r.symbol.as_str()).collect();
+        assert_eq!(relocs, ["labs"]);
+        let m = Module::load_with_relocs(&compiled.bytes, &compiled.relocations, &compiled.name)
+            .expect("load");
+        let f: FnI64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(-9) }, 9);
+    }
+
+    #[test]
+    fn bounds_check_trap_is_recorded_at_its_ud2() {
+        use crate::codegen::isa::x64::inst::Cond;
+        // fn(i) -> if i < 10 { i } else { trap bounds_check }
+        let mut b = FuncBuilder::new("checked");
+        let i = b.arg();
+        let len = b.iconst64(10);
+        let (ok, fail) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::B, i, len, ok, fail);
+        b.switch_to_block(ok);
+        b.breakpoint();
+        b.ret(i);
+        b.switch_to_block(fail);
+        b.trap(TrapCode::BoundsCheck);
+        let compiled = compile_full(b.build());
+
+        let [site] = compiled.traps[..] else {
+            panic!("expected one trap, got {:?}", compiled.traps);
+        };
+        let at = site.offset as usize;
+        assert_eq!(compiled.bytes[at..at + 2], [0x0F, 0x0B]);
+        assert_eq!(compiled.trap_code(site.offset), Some(TrapCode::BoundsCheck));
+        assert_eq!(compiled.trap_code(site.offset + 1), None);
+        assert!(compiled.bytes.contains(&0xCC));
+    }
+
+    #[test]
+    fn patch_sleds_are_nops_recorded_at_their_sites() {
+        use crate::codegen::tir::PatchKind;
+        use iced_x86::{Decoder, DecoderOptions, Mnemonic};
+        // fn(x) patchable(11) { patch_point 3; labs(x) patchable(5) }
+        let mut b = FuncBuilder::new("hooked");
+        let x = b.arg();
+        b.patch_point(3);
+        let r = b.call_sym_patchable("labs", &[x], 5);
+        b.ret(r);
+        let mut func = b.build();
+        func.attrs_mut().patchable_entry = Some(11);
+        let compiled = compile_full(func);
+
+        let kinds: Vec<(PatchKind, u32)> =
+            compiled.patch_sites.iter().map(|s| (s.kind, s.len)).collect();
+        assert_eq!(
+            kinds,
+            [(PatchKind::Entry, 11), (PatchKind::Point, 3), (PatchKind::Call, 5)]
+        );
+        assert_eq!(compiled.patch_sites[0].offset, 0);
+        for site in &compiled.patch_sites {
+            let (start, end) = (site.offset as usize, (site.offset + site.len) as usize);
+            let mut decoder =
+                Decoder::with_ip(64, &compiled.bytes[start..], start as u64, DecoderOptions::NONE);
+            let mut at = start;
+            while at < end {
+                let inst = decoder.decode();
+                assert_eq!(inst.mnemonic(), Mnemonic::Nop, "{site:?} at {at:#x}");
+                at += inst.len();
+            }
+            assert_eq!(at, end, "{site:?} ends mid-instruction");
+            if site.kind == PatchKind::Call {
+                assert_eq!(decoder.decode().mnemonic(), Mnemonic::Call, "{site:?}");
+            }
+        }
+
+        let m = Module::load_with_relocs(&compiled.bytes, &compiled.relocations, &compiled.name)
+            .expect("load");
+        let f: FnI64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(-4) }, 4);
+    }
+
+    #[test]
+    fn safepoint_stack_map_lists_only_live_references() {
+        use crate::codegen::tir::Type;
+        // fn(keep: ref, dead: ref, x) { safepoint; labs(x); return keep }
+        let mut b = FuncBuilder::new("gc");
+        let keep = b.arg_typed(Type::Ref);
+        let dead = b.arg_typed(Type::Ref);
+        let x = b.arg();
+        let sum = b.add(dead, x);
+        b.safepoint();
+        let _ = b.call_sym("labs", &[sum]);
+        b.ret(keep);
+        let compiled = compile_full(b.build());
+
+        let [ref map] = compiled.stack_maps[..] else {
+            panic!("expected one stack map, got {:?}", compiled.stack_maps);
+        };
+        // Only `keep` is live; `dead` died at the `add`.
+        assert_eq!(map.refs.len(), 1, "{map:?}");
+        let keep_ranges = compiled.value_locations.for_vreg(keep);
+        assert!(keep_ranges.iter().any(|r| r.location == map.refs[0]), "{map:?}");
+        assert_eq!(compiled.stack_maps_at(map.offset), std::slice::from_ref(map));
+    }
+
     #[test]
     fn jit_self_recursive_factorial() {
         use crate::codegen::isa::x64::inst::Cond;
@@ -1555,11 +3291,31 @@ mod tests {
         assert_eq!(unsafe { f(40, 100) }, -60);
     }
 
+    #[test]
+    fn jit_uncoalesced_arg_copies_leave_later_arg_registers_alone() {
+        // Without coalescing, each arg is copied out of its register. A
+        // copy placed before a later `Arg` may land in that arg's register
+        // (d into r8) and clobber it before it is read.
+        let mut b = FuncBuilder::new("sub6");
+        let args: Vec<Reg> = (0..6).map(|_| b.arg()).collect();
+        let mut acc = args[0];
+        for &a in &args[1..] {
+            acc = b.sub(acc, a);
+        }
+        b.ret(acc);
+        let opts = CodegenOptions { coalesce: false, ..CodegenOptions::default() };
+        let out = compile_function(b.build(), Target::X64SysV, &opts);
+        let m = Module::load(&out.bytes).expect("load");
+        type Fn6 = unsafe extern "sysv64" fn(i64, i64, i64, i64, i64, i64) -> i64;
+        let f: Fn6 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(1000, 1, 20, 300, 4000, 50000) }, 1000 - 54321);
+    }
+
     // -----------------------------------------------------------------
     // Floating-point, atomic, and aggregate JIT tests.
     // -----------------------------------------------------------------
 
-    use crate::codegen::tir::Type;
+    use crate::codegen::tir::{ScalarType, Type};
 
     #[allow(non_camel_case_types)]
     type FnF64F64_F64 = unsafe extern "sysv64" fn(f64, f64) -> f64;
@@ -1609,6 +3365,290 @@ mod tests {
         assert!((unsafe { f(0.0, 1.0) } + 1.0).abs() < 1e-6);
     }
 
+    #[test]
+    fn jit_float_constants_keep_their_bits() {
+        // +0.0 takes `xorps`, 1 and all-ones NaN a GPR round-trip, the
+        // rest the constant pool.
+        let values = [0.0, -0.0, 1.5, -2.5e300, f64::from_bits(1), f64::from_bits(u64::MAX)];
+        for v in values {
+            let mut b = FuncBuilder::new("c64");
+            let c = b.fconst_f64(v);
+            b.ret(c);
+            let m = jit(b.build()).unwrap();
+            let f: unsafe extern "sysv64" fn() -> f64 = unsafe { m.entry() };
+            assert_eq!(unsafe { f() }.to_bits(), v.to_bits(), "{v:?}");
+        }
+        for v in [0.0f32, -0.0, 0.1, f32::NAN, f32::NEG_INFINITY] {
+            let mut b = FuncBuilder::new("c32");
+            let c = b.fconst_f32(v);
+            b.ret(c);
+            let m = jit(b.build()).unwrap();
+            let f: unsafe extern "sysv64" fn() -> f32 = unsafe { m.entry() };
+            assert_eq!(unsafe { f() }.to_bits(), v.to_bits(), "{v:?}");
+        }
+    }
+
+    #[test]
+    fn jit_pooled_constants_are_shared_and_survive_spills() {
+        // More live constants than XMM registers: some spill after
+        // their load. Equal values share one pool entry.
+        let mut b = FuncBuilder::new("pool");
+        let x = b.arg_typed(Type::F64);
+        let consts: Vec<Reg> = (0..24).map(|i| b.fconst_f64(1.25 + f64::from(i % 12))).collect();
+        let mut acc = x;
+        for &c in consts.iter().rev() {
+            acc = b.fadd_f64(acc, c);
+        }
+        b.ret(acc);
+        let code = compile_full(b.build());
+        assert_eq!(code.constants.len(), 12);
+        assert!(code.code_len() < code.bytes.len());
+        let m = Module::load_with_relocs(&code.bytes, &code.relocations, &code.name).unwrap();
+        let f: unsafe extern "sysv64" fn(f64) -> f64 = unsafe { m.entry() };
+        let want = 0.5 + (0..24).map(|i| 1.25 + f64::from(i % 12)).sum::<f64>();
+        assert_eq!(unsafe { f(0.5) }.to_bits(), want.to_bits());
+    }
+
+    /// `out = op(a, b)` over 16-byte buffers of `lanes` lanes.
+    fn jit_v128_binop(lanes: ScalarType, op: fn(&mut FuncBuilder, Reg, Reg) -> Reg) -> Module {
+        let mut b = FuncBuilder::new("vop");
+        let (pa, pb, out) = (b.arg(), b.arg(), b.arg());
+        let va = b.load_v128(lanes, pa, 0);
+        let vb = b.load_v128(lanes, pb, 0);
+        let r = op(&mut b, va, vb);
+        b.store_v128(out, 0, r);
+        let z = b.iconst64(0);
+        b.ret(z);
+        jit(b.build()).unwrap()
+    }
+
+    type FnV128 = unsafe extern "sysv64" fn(*const u8, *const u8, *mut u8) -> i64;
+
+    #[test]
+    fn jit_v128_add_and_mul_are_lane_wise() {
+        let m = jit_v128_binop(ScalarType::I32, FuncBuilder::vadd);
+        let f: FnV128 = unsafe { m.entry() };
+        let (a, c) = ([1i32, -2, i32::MAX, 40], [10i32, 20, 1, -40]);
+        let mut out = [0i32; 4];
+        unsafe { f(a.as_ptr().cast(), c.as_ptr().cast(), out.as_mut_ptr().cast()) };
+        assert_eq!(out, [11, 18, i32::MIN, 0]);
+
+        let m = jit_v128_binop(ScalarType::I32, FuncBuilder::vmul);
+        let f: FnV128 = unsafe { m.entry() };
+        unsafe { f(a.as_ptr().cast(), c.as_ptr().cast(), out.as_mut_ptr().cast()) };
+        assert_eq!(out, [10, -40, i32::MAX, -1600]);
+
+        let m = jit_v128_binop(ScalarType::I8, FuncBuilder::vadd);
+        let f: FnV128 = unsafe { m.entry() };
+        let a: [u8; 16] = std::array::from_fn(|i| i as u8 * 17);
+        let c: [u8; 16] = std::array::from_fn(|i| 200 - i as u8);
+        let mut out = [0u8; 16];
+        unsafe { f(a.as_ptr(), c.as_ptr(), out.as_mut_ptr()) };
+        assert_eq!(out, std::array::from_fn(|i| a[i].wrapping_add(c[i])));
+
+        let m = jit_v128_binop(ScalarType::I16, FuncBuilder::vmul);
+        let f: FnV128 = unsafe { m.entry() };
+        let a: [i16; 8] = [1, -2, 300, 400, 5, 6, -7, 8];
+        let c: [i16; 8] = [9, 9, 300, -400, 0, 1, -1, 4096];
+        let mut out = [0i16; 8];
+        unsafe { f(a.as_ptr().cast(), c.as_ptr().cast(), out.as_mut_ptr().cast()) };
+        assert_eq!(out, std::array::from_fn(|i| a[i].wrapping_mul(c[i])));
+
+        let m = jit_v128_binop(ScalarType::F64, FuncBuilder::vmul);
+        let f: FnV128 = unsafe { m.entry() };
+        let (a, c) = ([1.5f64, -2.0], [4.0f64, 0.25]);
+        let mut out = [0f64; 2];
+        unsafe { f(a.as_ptr().cast(), c.as_ptr().cast(), out.as_mut_ptr().cast()) };
+        assert_eq!(out.map(f64::to_bits), [6.0f64, -0.5].map(f64::to_bits));
+
+        let m = jit_v128_binop(ScalarType::F32, FuncBuilder::vadd);
+        let f: FnV128 = unsafe { m.entry() };
+        let (a, c) = ([1.5f32, -2.0, 0.25, 8.0], [1.0f32, 2.0, 0.5, -16.0]);
+        let mut out = [0f32; 4];
+        unsafe { f(a.as_ptr().cast(), c.as_ptr().cast(), out.as_mut_ptr().cast()) };
+        assert_eq!(out.map(f32::to_bits), [2.5f32, 0.0, 0.75, -8.0].map(f32::to_bits));
+    }
+
+    #[test]
+    fn jit_v128_shuffle_and_extract_lanes() {
+        // Reverse the lanes, then return lane `k` of the result.
+        let extract = |lanes: ScalarType, order: &[u8], k: u8| {
+            let mut b = FuncBuilder::new("lane");
+            let p = b.arg();
+            let v = b.load_v128(lanes, p, 0);
+            let s = b.shuffle(v, order);
+            let r = b.extract_lane(s, k);
+            b.ret(r);
+            jit(b.build()).unwrap()
+        };
+        let data = [0x11u32, 0x2222, 0x33_3333, 0x4444_4444];
+        for k in 0..4u8 {
+            let m = extract(ScalarType::I32, &[3, 2, 1, 0], k);
+            let f: unsafe extern "sysv64" fn(*const u32) -> u64 = unsafe { m.entry() };
+            assert_eq!(unsafe { f(data.as_ptr()) }, u64::from(data[3 - usize::from(k)]));
+        }
+        let wide = [u64::MAX, 7];
+        for k in 0..2u8 {
+            let m = extract(ScalarType::I64, &[1, 0], k);
+            let f: unsafe extern "sysv64" fn(*const u64) -> u64 = unsafe { m.entry() };
+            assert_eq!(unsafe { f(wide.as_ptr()) }, wide[1 - usize::from(k)]);
+        }
+        let floats = [1.5f64, -3.25];
+        let m = extract(ScalarType::F64, &[1, 1], 0);
+        let f: unsafe extern "sysv64" fn(*const f64) -> f64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(floats.as_ptr()) }.to_bits(), (-3.25f64).to_bits());
+        let singles = [1.0f32, 2.0, 3.0, 4.0];
+        let m = extract(ScalarType::F32, &[0, 3, 3, 1], 2);
+        let f: unsafe extern "sysv64" fn(*const f32) -> f32 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(singles.as_ptr()) }.to_bits(), 4.0f32.to_bits());
+        let bytes: [u8; 16] = std::array::from_fn(|i| 0xf0 + i as u8);
+        let mut b = FuncBuilder::new("byte");
+        let p = b.arg();
+        let v = b.load_v128(ScalarType::I8, p, 0);
+        let r = b.extract_lane(v, 13);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn(*const u8) -> u64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(bytes.as_ptr()) }, 0xfd);
+    }
+
+    #[test]
+    fn jit_v128_passes_and_returns_in_xmm() {
+        use std::arch::x86_64::{__m128i, _mm_set_epi32, _mm_storeu_si128};
+        let mut b = FuncBuilder::new("vargs");
+        let x = b.arg_typed(Type::V128(ScalarType::I32));
+        let y = b.arg_typed(Type::V128(ScalarType::I32));
+        let r = b.vadd(x, y);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn(__m128i, __m128i) -> __m128i = unsafe { m.entry() };
+        let mut out = [0i32; 4];
+        unsafe {
+            let r = f(_mm_set_epi32(4, 3, 2, 1), _mm_set_epi32(40, 30, 20, 10));
+            _mm_storeu_si128(out.as_mut_ptr().cast(), r);
+        }
+        assert_eq!(out, [11, 22, 33, 44]);
+    }
+
+    #[test]
+    fn jit_spilled_vectors_keep_all_sixteen_bytes() {
+        // More live vectors than XMM registers: some spill, and a spill
+        // that kept only the low eight bytes would lose lanes 2 and 3.
+        let n = 24;
+        let mut b = FuncBuilder::new("vspill");
+        let (p, out) = (b.arg(), b.arg());
+        let vs: Vec<Reg> = (0..n).map(|i| b.load_v128(ScalarType::I32, p, 16 * i)).collect();
+        let mut acc = vs[0];
+        for &v in &vs[1..] {
+            acc = b.vadd(acc, v);
+        }
+        for &v in vs.iter().rev() {
+            acc = b.vadd(acc, v);
+        }
+        b.store_v128(out, 0, acc);
+        let z = b.iconst64(0);
+        b.ret(z);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn(*const i32, *mut i32) -> i64 = unsafe { m.entry() };
+        let data: Vec<i32> = (0..4 * n).map(|i| i * 7 - 50).collect();
+        let mut got = [0i32; 4];
+        unsafe { f(data.as_ptr(), got.as_mut_ptr()) };
+        let want: [i32; 4] = std::array::from_fn(|l| {
+            2 * (0..n as usize).map(|i| data[4 * i + l]).sum::<i32>()
+        });
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn jit_rep_string_ops_keep_their_arguments_live() {
+        // `dst` and `len` arrive in RDI and RDX; both are read again after
+        // `rep movsb` / `rep stosb` have advanced RDI and zeroed RCX.
+        let mut b = FuncBuilder::new("repmov");
+        let (dst, src, len, byte) = (b.arg(), b.arg(), b.arg(), b.arg());
+        b.memcpy(dst, src, len);
+        let tail = b.add(dst, len);
+        b.memset(tail, byte, len);
+        let end = b.add(tail, len);
+        b.ret(end);
+        let m = jit(b.build()).unwrap();
+        type F = unsafe extern "sysv64" fn(*mut u8, *const u8, usize, i64) -> *mut u8;
+        let f: F = unsafe { m.entry() };
+        let src: Vec<u8> = (0..100).collect();
+        let mut buf = [0u8; 256];
+        let end = unsafe { f(buf.as_mut_ptr(), src.as_ptr(), 100, 0x1EE) };
+        assert_eq!(end, buf.as_mut_ptr().wrapping_add(200));
+        assert_eq!(buf[..100], src[..]);
+        assert!(buf[100..200].iter().all(|&x| x == 0xEE));
+        assert!(buf[200..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn jit_constant_length_copies_and_fills_touch_exactly_len_bytes() {
+        // Both sides of `INLINE_MEM_BYTES`, and every tail width.
+        for len in [0u64, 1, 3, 7, 15, 16, 31, 33, 63, 64, 65, 200] {
+            let mut b = FuncBuilder::new("memconst");
+            let (dst, src, fill) = (b.arg(), b.arg(), b.arg());
+            b.memcpy_const(dst, src, len);
+            b.memset_const(fill, 0xA5, len);
+            let z = b.iconst64(0);
+            b.ret(z);
+            let m = jit(b.build()).unwrap();
+            type F = unsafe extern "sysv64" fn(*mut u8, *const u8, *mut u8) -> i64;
+            let f: F = unsafe { m.entry() };
+            let src: Vec<u8> = (0..256).map(|i| (i * 37 + 1) as u8).collect();
+            let (mut copied, mut filled) = ([0u8; 256], [0u8; 256]);
+            unsafe { f(copied.as_mut_ptr(), src.as_ptr(), filled.as_mut_ptr()) };
+            let n = len as usize;
+            assert_eq!(copied[..n], src[..n], "len {len}");
+            assert!(copied[n..].iter().all(|&x| x == 0), "len {len}");
+            assert!(filled[..n].iter().all(|&x| x == 0xA5), "len {len}");
+            assert!(filled[n..].iter().all(|&x| x == 0), "len {len}");
+        }
+    }
+
+    #[test]
+    fn jit_intrinsics_lower_to_bytes_and_libm_calls() {
+        // `popcnt` wants RDI, `bswap` RAX: both are live across the other.
+        let mut b = FuncBuilder::new("intr");
+        let (x, y) = (b.arg(), b.arg());
+        let n = b.intrinsic("x64.popcnt", &[x])[0];
+        let s = b.intrinsic("x64.bswap", &[y])[0];
+        let sum = b.add(n, s);
+        let r = b.add(sum, x);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn(i64, i64) -> i64 = unsafe { m.entry() };
+        for (x, y) in [(0i64, 0i64), (0xF0F0, 0x0102_0304_0506_0708), (-1, 7)] {
+            let want = i64::from(x.count_ones())
+                .wrapping_add(y.swap_bytes())
+                .wrapping_add(x);
+            assert_eq!(unsafe { f(x, y) }, want);
+        }
+
+        let mut b = FuncBuilder::new("hyp");
+        let (x, y) = (b.arg_typed(Type::F64), b.arg_typed(Type::F64));
+        let two = b.fconst_f64(2.0);
+        let xx = b.intrinsic("math.pow.f64", &[x, two])[0];
+        let yy = b.fmul_f64(y, y);
+        let sum = b.fadd_f64(xx, yy);
+        let h = b.intrinsic("math.sqrt.f64", &[sum])[0];
+        b.ret(h);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn(f64, f64) -> f64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(3.0, 4.0) }.to_bits(), 5.0f64.to_bits());
+        assert_eq!(unsafe { f(-5.0, 12.0) }.to_bits(), 13.0f64.to_bits());
+
+        let mut b = FuncBuilder::new("tsc");
+        b.intrinsic("x64.pause", &[]);
+        let t0 = b.intrinsic("x64.rdtsc", &[])[0];
+        let t1 = b.intrinsic("x64.rdtsc", &[])[0];
+        let d = b.sub(t1, t0);
+        b.ret(d);
+        let m = jit(b.build()).unwrap();
+        let f: unsafe extern "sysv64" fn() -> i64 = unsafe { m.entry() };
+        assert!(unsafe { f() } >= 0);
+    }
+
     #[test]
     fn jit_atomic_fetch_add_on_caller_slot() {
         // Callee takes `base` pointer and `delta`, returns old value;
@@ -1713,7 +3753,7 @@ mod tests {
         // Make a reduction across many FP vregs that all need to be
         // simultaneously live just before the final sum, creating
         // pressure past the 14-XMM allocatable pool. The allocator
-        // should spill one or more via SplitMove, the emitter's fixed
+        // should spill one or more via a split store, the emitter's fixed
         // FP split-store path must survive, and the final value must
         // match the oracle.
         //
@@ -1799,6 +3839,146 @@ mod tests {
         assert!((got - 12.5).abs() < 1e-9, "fp call returned {got}");
     }
 
+    #[test]
+    fn jit_sysv_fp_args_past_xmm7_come_from_stack() {
+        // Ten f64 args: the last two overflow XMM0..XMM7 and land in
+        // the caller's outgoing-args area.
+        let mut b = FuncBuilder::new("fsum10");
+        let args: Vec<Reg> = (0..10).map(|_| b.arg_typed(Type::F64)).collect();
+        let mut acc = args[0];
+        for a in &args[1..] {
+            acc = b.fadd_f64(acc, *a);
+        }
+        b.ret(acc);
+        let m = jit(b.build()).unwrap();
+        type F10 = unsafe extern "sysv64" fn(
+            f64, f64, f64, f64, f64, f64, f64, f64, f64, f64,
+        ) -> f64;
+        let f: F10 = unsafe { m.entry() };
+        let got = unsafe { f(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.5, 10.25) };
+        assert!((got - 55.75).abs() < 1e-9, "got {got}");
+    }
+
+    #[test]
+    fn jit_sysv_mixed_class_stack_args_share_one_slot_sequence() {
+        // Nine f64s then seven i64s: the 9th f64 takes stack slot 0 and
+        // the 7th i64 takes stack slot 1. Returning the i64 catches a
+        // lowering that numbers stack slots per class.
+        let mut b = FuncBuilder::new("mixed_stack");
+        for _ in 0..9 {
+            b.arg_typed(Type::F64);
+        }
+        let ints: Vec<Reg> = (0..7).map(|_| b.arg()).collect();
+        b.ret(ints[6]);
+        let m = jit(b.build()).unwrap();
+        type F16 = unsafe extern "sysv64" fn(
+            f64, f64, f64, f64, f64, f64, f64, f64, f64,
+            i64, i64, i64, i64, i64, i64, i64,
+        ) -> i64;
+        let f: F16 = unsafe { m.entry() };
+        let got = unsafe { f(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5, 0, 0, 0, 0, 0, 0, 77) };
+        assert_eq!(got, 77);
+    }
+
+    #[test]
+    fn jit_outgoing_call_passes_fp_args_on_the_stack() {
+        extern "sysv64" fn weighted(
+            a: f64, b: f64, c: f64, d: f64, e: f64, f: f64, g: f64, h: f64, i: f64, j: f64,
+        ) -> f64 {
+            a + b + c + d + e + f + g + h + 10.0 * i + 100.0 * j
+        }
+        let mut b = FuncBuilder::new("fp_stack_caller");
+        let fn_ptr = b.arg();
+        let args: Vec<Reg> = (0..10).map(|_| b.arg_typed(Type::F64)).collect();
+        let r = b.call_indirect_typed(fn_ptr, &args, Type::F64);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        type F = unsafe extern "sysv64" fn(
+            *const std::ffi::c_void,
+            f64, f64, f64, f64, f64, f64, f64, f64, f64, f64,
+        ) -> f64;
+        let f: F = unsafe { m.entry() };
+        let addr = weighted as *const std::ffi::c_void;
+        let got = unsafe { f(addr, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 3.0) };
+        assert!((got - 328.0).abs() < 1e-9, "got {got}");
+    }
+
+    #[test]
+    #[allow(improper_ctypes_definitions)]
+    fn jit_vector_stack_args_take_an_aligned_pair_of_eightbytes() {
+        use std::arch::x86_64::{__m128i, _mm_set_epi64x};
+        // Nine f64s, a vector, seven i64s: the 9th f64 takes eightbyte 0,
+        // the vector 2 and 3 (skipping 1 to stay 16-aligned), the 7th i64
+        // eightbyte 4.
+        type F = unsafe extern "sysv64" fn(
+            f64, f64, f64, f64, f64, f64, f64, f64, f64, __m128i,
+            i64, i64, i64, i64, i64, i64, i64,
+        ) -> i64;
+        extern "sysv64" fn native(
+            _: f64, _: f64, _: f64, _: f64, _: f64, _: f64, _: f64, _: f64, x: f64, v: __m128i,
+            _: i64, _: i64, _: i64, _: i64, _: i64, _: i64, n: i64,
+        ) -> i64 {
+            let lanes: [i64; 2] = unsafe { std::mem::transmute(v) };
+            x.to_bits().cast_signed() + lanes[0] + lanes[1] + n
+        }
+        let v = unsafe { _mm_set_epi64x(20_000, 1_000) };
+        let want = 1.5_f64.to_bits().cast_signed() + 21_000 + 7;
+
+        // Incoming: the same arguments read by lancy.
+        let mut b = FuncBuilder::new("vec_stack");
+        let fps: Vec<Reg> = (0..9).map(|_| b.arg_typed(Type::F64)).collect();
+        let vec = b.arg_typed(Type::V128(ScalarType::I64));
+        let ints: Vec<Reg> = (0..7).map(|_| b.arg()).collect();
+        let slot = b.stack_alloc(8, 8);
+        b.store_f64(slot, 0, fps[8]);
+        let x = b.load_i64(slot, 0);
+        let (lo, hi) = (b.extract_lane(vec, 0), b.extract_lane(vec, 1));
+        let mut r = b.add(x, lo);
+        r = b.add(r, hi);
+        r = b.add(r, ints[6]);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        let f: F = unsafe { m.entry() };
+        let got = unsafe { f(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5, v, 0, 0, 0, 0, 0, 0, 7) };
+        assert_eq!(got, want);
+        let f: F = native;
+        let got = unsafe { f(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.5, v, 0, 0, 0, 0, 0, 0, 7) };
+        assert_eq!(got, want);
+
+        // Outgoing: lancy passes them to the native callee.
+        let mut b = FuncBuilder::new("vec_stack_caller");
+        let (fn_ptr, p, x, n) = (b.arg(), b.arg(), b.arg_typed(Type::F64), b.arg());
+        let vec = b.load_v128(ScalarType::I64, p, 0);
+        let zero = b.fconst_f64(0.0);
+        let mut args = vec![zero; 8];
+        args.extend([x, vec]);
+        args.extend(std::iter::repeat_n(n, 7));
+        let r = b.call_indirect(fn_ptr, &args);
+        b.ret(r);
+        let m = jit(b.build()).unwrap();
+        type Caller = unsafe extern "sysv64" fn(F, *const __m128i, f64, i64) -> i64;
+        let f: Caller = unsafe { m.entry() };
+        assert_eq!(unsafe { f(native, &raw const v, 1.5, 7) }, want);
+    }
+
+    #[test]
+    fn wide_vector_stack_args_are_rejected() {
+        let mut b = FuncBuilder::new("wide");
+        for _ in 0..9 {
+            b.arg_typed(Type::V256(ScalarType::F32));
+        }
+        let x = b.arg();
+        b.ret(x);
+        let Err(err) = try_compile_function(b.build(), Target::X64SysV, &CodegenOptions::default())
+        else {
+            panic!("a V256 past the registers has no stack slot");
+        };
+        assert!(
+            matches!(err.root(), CodegenError::StackVectorArg(Type::V256(_))),
+            "{err}"
+        );
+    }
+
     #[test]
     fn jit_aggregate_insert_preserves_unchanged_element() {
         // Build {x, y, z}, insert w at index 1, return element 2 → z.
@@ -1816,4 +3996,131 @@ mod tests {
         let f: F4 = unsafe { m.entry() };
         assert_eq!(unsafe { f(1, 2, 3, 4) }, 3);
     }
+
+    // -------------- By-value struct ABI --------------
+
+    /// `{ int; float; double }`: one INTEGER and one SSE eightbyte.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Pair {
+        a: i32,
+        b: f32,
+        c: f64,
+    }
+
+    /// `{ long; double; float }`: 24 bytes, so passed in memory.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Triple {
+        x: i64,
+        y: f64,
+        z: f32,
+    }
+
+    const PAIR: [Type; 3] = [Type::I32, Type::F32, Type::F64];
+
+    #[test]
+    fn jit_struct_returns_of_libc_div_come_back_in_registers() {
+        // fn(a, b) -> ldiv(a, b).quot * 1000 + div(a, b).rem
+        // `ldiv_t` spans RAX:RDX; `div_t` packs both ints into RAX.
+        let mut b = FuncBuilder::new("divs");
+        let x = b.arg();
+        let y = b.arg();
+        let l = b.call_sym_struct("ldiv", &[x, y], &[Type::I64, Type::I64]);
+        let quot = b.extract_value(l, 0, Type::I64);
+        let x32 = b.trunc_to_i32(x);
+        let y32 = b.trunc_to_i32(y);
+        let d = b.call_sym_struct("div", &[x32, y32], &[Type::I32, Type::I32]);
+        let rem32 = b.extract_value(d, 1, Type::I32);
+        let rem = b.sext_i32_to_i64(rem32);
+        let k = b.iconst64(1000);
+        let scaled = b.imul(quot, k);
+        let r = b.add(scaled, rem);
+        b.ret(r);
+        let m = jit(b.build()).expect("jit");
+        let f: FnI64I64_I64 = unsafe { m.entry() };
+        assert_eq!(unsafe { f(47, 5) }, 9002);
+        assert_eq!(unsafe { f(-47, 5) }, -9002);
+    }
+
+    #[test]
+    fn jit_struct_arg_in_registers_and_struct_return_through_sret() {
+        // fn(p: Pair, k) -> Triple { p.a + k, p.c, p.b + p.b }
+        let mut b = FuncBuilder::new("make_triple");
+        let p = b.arg_struct(&PAIR);
+        let k = b.arg();
+        let a32 = b.extract_value(p, 0, Type::I32);
+        let fb = b.extract_value(p, 1, Type::F32);
+        let c = b.extract_value(p, 2, Type::F64);
+        let a = b.sext_i32_to_i64(a32);
+        let x = b.add(a, k);
+        let z = b.fadd_f32(fb, fb);
+        let t = b.make_aggregate(vec![x, c, z]);
+        b.ret(t);
+        let m = jit(b.build()).expect("jit");
+        type F = unsafe extern "sysv64" fn(Pair, i64) -> Triple;
+        let f: F = unsafe { m.entry() };
+        let got = unsafe { f(Pair { a: -7, b: 1.25, c: 3.5 }, 100) };
+        assert_eq!(got, Triple { x: 93, y: 3.5, z: 2.5 });
+    }
+
+    #[test]
+    fn jit_call_passes_structs_in_registers_and_memory() {
+        extern "sysv64" fn mix(p: Pair, big: Triple, q: Pair) -> Pair {
+            Pair {
+                a: p.a - q.a + i32::try_from(big.x).unwrap(),
+                b: p.b * q.b,
+                c: big.y + f64::from(big.z),
+            }
+        }
+        // fn(f, a: i32, fb: f32, c: f64, x, y: i32)
+        //     -> f({a, fb, c}, {x, c, fb}, {y, fb, c})
+        let mut b = FuncBuilder::new("call_mix");
+        let fn_ptr = b.arg();
+        let a = b.arg_typed(Type::I32);
+        let fb = b.arg_typed(Type::F32);
+        let c = b.arg_typed(Type::F64);
+        let x = b.arg();
+        let y = b.arg_typed(Type::I32);
+        let p = b.make_aggregate(vec![a, fb, c]);
+        let big = b.make_aggregate(vec![x, c, fb]);
+        let q = b.insert_value(p, y, 0);
+        let r = b.call_indirect_struct(fn_ptr, &[p, big, q], &PAIR);
+        b.ret(r);
+        let m = jit(b.build()).expect("jit");
+        type F = unsafe extern "sysv64" fn(*const (), i32, f32, f64, i64, i32) -> Pair;
+        let f: F = unsafe { m.entry() };
+        let got = unsafe { f(mix as *const (), 50, 1.5, 0.25, 8, 8) };
+        assert_eq!(got, Pair { a: 50, b: 2.25, c: 1.75 });
+    }
+
+    #[test]
+    fn jit_struct_that_misses_the_registers_travels_on_the_stack_whole() {
+        extern "sysv64" fn sink(a: i64, b: i64, c: i64, d: i64, e: i64, f: i64, p: Pair) -> f64 {
+            let ints = i32::try_from(a + b + c + d + e + f).unwrap() + p.a;
+            f64::from(ints) + f64::from(p.b) + p.c
+        }
+        // fn(g, a..e, p: Pair) -> g(a, b, c, d, e, e, p). `p`'s INTEGER
+        // eightbyte finds no register left on either side, so its SSE
+        // one goes to the stack with it.
+        let mut b = FuncBuilder::new("fwd");
+        let g = b.arg();
+        let ints: Vec<Reg> = (0..5).map(|_| b.arg()).collect();
+        let p = b.arg_struct(&PAIR);
+        let mut args = ints.clone();
+        args.push(ints[4]);
+        args.push(p);
+        let r = b.call_indirect_typed(g, &args, Type::F64);
+        b.ret(r);
+        let m = jit(b.build()).expect("jit");
+        type F = unsafe extern "sysv64" fn(*const (), i64, i64, i64, i64, i64, Pair) -> f64;
+        let f: F = unsafe { m.entry() };
+        let p = Pair {
+            a: 100,
+            b: 20.0,
+            c: 3000.0,
+        };
+        let got = unsafe { f(sink as *const (), 1, 2, 3, 4, 5, p) };
+        assert!((got - 3140.0).abs() < 1e-9, "got {got}");
+    }
 }
@@ -0,0 +1,433 @@
+//! Byte-driven program generator and failure shrinker for fuzzing the
+//! register allocator.
+//!
+//! `generate` turns an arbitrary byte string into a well-formed text-IR
+//! function: straight-line integer and float arithmetic, fixed-register
+//! ops (division, variable shifts), calls that clobber the caller-saved
+//! set, stack slots, and nested diamonds and counted loops joined by phis.
+//! Operands are drawn from everything still in scope, so long inputs keep
+//! many values live at once and force spilling and splitting.
+//!
+//! `check` compiles the text with the IR verifier and the symbolic
+//! allocation checker enabled and turns any pipeline panic into an error;
+//! `shrink` then deletes lines while the failure persists, leaving a small
+//! reproducer that drops straight into `tests/filecheck/`. The generated
+//! code is never executed — without a TIR interpreter there is no oracle
+//! to compare against, so the checker is the whole verdict.
+
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::parser::parse_module;
+use crate::codegen::isa::x64::pipeline::try_compile_function;
+use crate::codegen::options::CodegenOptions;
+
+/// Upper bound on generated items, so one input can't run away.
+const MAX_ITEMS: usize = 96;
+/// Nesting limit for diamonds and loops.
+const MAX_DEPTH: usize = 3;
+
+const INT_OPS: &[&str] = &[
+    "add", "sub", "imul", "and", "or", "xor", "sdiv", "srem", "udiv", "urem", "shl", "sar",
+];
+const FLOAT_OPS: &[&str] = &["fadd.f64", "fsub.f64", "fmul.f64", "fdiv.f64"];
+const CONDS: &[&str] = &["z", "nz", "l", "le", "g", "ge", "b", "be", "a", "ae"];
+
+struct Gen<'a> {
+    data: &'a [u8],
+    pos: usize,
+    items: usize,
+    out: String,
+    /// Integer values in scope, innermost last.
+    ints: Vec<String>,
+    floats: Vec<String>,
+    next_value: usize,
+    next_label: usize,
+    /// Label of the block currently being filled.
+    block: String,
+}
+
+impl Gen<'_> {
+    /// Next input byte; zero once the input is exhausted.
+    fn byte(&mut self) -> u8 {
+        let b = self.data.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        b
+    }
+
+    fn pick<'s>(&mut self, from: &'s [String]) -> &'s str {
+        &from[usize::from(self.byte()) % from.len()]
+    }
+
+    fn pick_int(&mut self) -> String {
+        let ints = std::mem::take(&mut self.ints);
+        let v = self.pick(&ints).to_string();
+        self.ints = ints;
+        v
+    }
+
+    fn choose<'s>(&mut self, from: &[&'s str]) -> &'s str {
+        from[usize::from(self.byte()) % from.len()]
+    }
+
+    fn exhausted(&self) -> bool {
+        self.pos >= self.data.len() || self.items >= MAX_ITEMS
+    }
+
+    fn fresh_value(&mut self) -> String {
+        self.next_value += 1;
+        format!("%v{}", self.next_value)
+    }
+
+    fn fresh_label(&mut self, kind: &str) -> String {
+        self.next_label += 1;
+        format!("{kind}{}", self.next_label)
+    }
+
+    fn line(&mut self, text: &str) {
+        writeln!(self.out, "    {text}").expect("writing to a String");
+    }
+
+    fn label(&mut self, label: String) {
+        writeln!(self.out, "{label}:").expect("writing to a String");
+        self.block = label;
+    }
+
+    /// Emit `%v = <rhs>` and bring the result into scope.
+    fn def_int(&mut self, rhs: &str) -> String {
+        let v = self.fresh_value();
+        self.line(&format!("{v} = {rhs}"));
+        self.ints.push(v.clone());
+        v
+    }
+
+    /// Fill the current block (and any nested structure) until the input
+    /// runs out or `budget` items have been emitted.
+    fn body(&mut self, depth: usize, budget: usize) {
+        let stop = self.items + budget;
+        while !self.exhausted() && self.items < stop {
+            self.items += 1;
+            match self.byte() % 16 {
+                0..=5 => {
+                    let op = self.choose(INT_OPS);
+                    let (a, c) = (self.pick_int(), self.pick_int());
+                    self.def_int(&format!("{op} {a}, {c}"));
+                }
+                6 => {
+                    let imm = self.byte() % 64;
+                    let op = self.choose(&["shl", "shr", "sar"]);
+                    let a = self.pick_int();
+                    self.def_int(&format!("{op} {a}, {imm}"));
+                }
+                7 => {
+                    let imm = i64::from(self.byte() as i8);
+                    self.def_int(&format!("iconst {imm}"));
+                }
+                8 => {
+                    let cond = self.choose(CONDS);
+                    let (a, c) = (self.pick_int(), self.pick_int());
+                    if self.byte().is_multiple_of(2) {
+                        self.def_int(&format!("icmp {cond} {a}, {c}"));
+                    } else {
+                        let (t, f) = (self.pick_int(), self.pick_int());
+                        self.def_int(&format!("select {cond} {a}, {c}, {t}, {f}"));
+                    }
+                }
+                9 if !self.floats.is_empty() => {
+                    let op = self.choose(FLOAT_OPS);
+                    let floats = std::mem::take(&mut self.floats);
+                    let (a, c) = (self.pick(&floats), self.pick(&floats));
+                    let v = self.fresh_value();
+                    self.line(&format!("{v} = {op} {a}, {c}"));
+                    self.floats = floats;
+                    self.floats.push(v);
+                }
+                10 => {
+                    let n = self.byte() % 4;
+                    let args: Vec<String> = (0..n).map(|_| self.pick_int()).collect();
+                    self.def_int(&format!("call @ext({})", args.join(", ")));
+                }
+                11 => {
+                    let slot = self.fresh_value();
+                    self.line(&format!("{slot} = stackalloc 8, 8"));
+                    let val = self.pick_int();
+                    self.line(&format!("store.i64 {slot}, 0, {val}"));
+                    self.def_int(&format!("load.i64 {slot}, 0"));
+                }
+                12 | 13 if depth < MAX_DEPTH => self.diamond(depth),
+                14 | 15 if depth < MAX_DEPTH => self.counted_loop(depth),
+                _ => {
+                    let a = self.pick_int();
+                    self.def_int(&format!("neg {a}"));
+                }
+            }
+        }
+    }
+
+    /// `br` into two arms that rejoin with a phi over one value from each.
+    fn diamond(&mut self, depth: usize) {
+        let (then, els, join) = (
+            self.fresh_label("then"),
+            self.fresh_label("else"),
+            self.fresh_label("join"),
+        );
+        let cond = self.choose(CONDS);
+        let (a, c) = (self.pick_int(), self.pick_int());
+        self.line(&format!("br {cond} {a}, {c}, {then}, {els}"));
+
+        let scope = (self.ints.len(), self.floats.len());
+        let mut arms = Vec::new();
+        for arm in [then, els] {
+            self.label(arm);
+            let budget = 1 + usize::from(self.byte() % 8);
+            self.body(depth + 1, budget);
+            let v = self.pick_int();
+            arms.push(format!("[{}, {v}]", self.block));
+            self.line(&format!("jmp {join}"));
+            self.ints.truncate(scope.0);
+            self.floats.truncate(scope.1);
+        }
+        self.label(join);
+        self.def_int(&format!("phi {}", arms.join(", ")));
+    }
+
+    /// A loop running a small constant trip count that threads an
+    /// accumulator through the back edge.
+    fn counted_loop(&mut self, depth: usize) {
+        let (head, body, exit) = (
+            self.fresh_label("head"),
+            self.fresh_label("body"),
+            self.fresh_label("exit"),
+        );
+        let trips = 1 + self.byte() % 4;
+        let n = self.def_int(&format!("iconst {trips}"));
+        let zero = self.def_int("iconst 0");
+        let init = self.pick_int();
+        let pre = self.block.clone();
+        self.line(&format!("jmp {head}"));
+
+        let (i, acc, next, acc2) = (
+            self.fresh_value(),
+            self.fresh_value(),
+            self.fresh_value(),
+            self.fresh_value(),
+        );
+        self.label(head.clone());
+        // The latch label is only known once the body is generated; patch
+        // it in afterwards.
+        let phi_at = self.out.len();
+        self.line(&format!("br ge {i}, {n}, {exit}, {body}"));
+
+        let scope = (self.ints.len(), self.floats.len());
+        self.ints.push(i.clone());
+        self.ints.push(acc.clone());
+        self.label(body);
+        let budget = 1 + usize::from(self.byte() % 8);
+        self.body(depth + 1, budget);
+        let last = self.pick_int();
+        self.line(&format!("{acc2} = add {acc}, {last}"));
+        let one = self.fresh_value();
+        self.line(&format!("{one} = iconst 1"));
+        self.line(&format!("{next} = add {i}, {one}"));
+        self.line(&format!("jmp {head}"));
+        let latch = self.block.clone();
+        self.ints.truncate(scope.0);
+        self.floats.truncate(scope.1);
+
+        let phis = format!(
+            "    {i} = phi [{pre}, {zero}], [{latch}, {next}]\n    \
+             {acc} = phi [{pre}, {init}], [{latch}, {acc2}]\n"
+        );
+        self.out.insert_str(phi_at, &phis);
+        self.label(exit);
+        self.ints.push(i);
+        self.ints.push(acc);
+    }
+}
+
+/// Build a function named `fuzz` from `data`. Every input yields text
+/// that parses and verifies.
+#[must_use]
+pub fn generate(data: &[u8]) -> String {
+    let mut g = Gen {
+        data,
+        pos: 0,
+        items: 0,
+        out: String::new(),
+        ints: Vec::new(),
+        floats: Vec::new(),
+        next_value: 0,
+        next_label: 0,
+        block: String::new(),
+    };
+    // Up to eight integer args, so some arrive on the stack.
+    let n_ints = 1 + g.byte() % 8;
+    let n_floats = g.byte() % 3;
+    let mut params: Vec<String> = (0..n_ints).map(|i| format!("%a{i}")).collect();
+    params.extend((0..n_floats).map(|i| format!("%f{i}: f64")));
+    g.ints = (0..n_ints).map(|i| format!("%a{i}")).collect();
+    g.floats = (0..n_floats).map(|i| format!("%f{i}")).collect();
+
+    writeln!(g.out, "func @fuzz({}) {{", params.join(", ")).expect("writing to a String");
+    g.label("entry".to_string());
+    g.body(0, MAX_ITEMS);
+    let v = g.ints.last().expect("args are always in scope").clone();
+    g.line(&format!("ret {v}"));
+    g.out.push_str("}\n");
+    g.out
+}
+
+/// Compile `src` with IR verification and allocation checking on. A parse
+/// error, a compile error (without its location, so it reads the same
+/// for a shrunk input) or any pipeline panic comes back as its message.
+///
+/// Swaps out the process panic hook while compiling, so a failure is
+/// reported through the return value rather than printed (or, under
+/// libFuzzer, turned into an abort before it can be caught).
+pub fn check(src: &str) -> Result<(), String> {
+    let funcs = parse_module(src).map_err(|e| format!("parse error: {e}"))?;
+    let options = CodegenOptions {
+        verify: true,
+        check_regalloc: true,
+        ..CodegenOptions::default()
+    };
+    let compiled = quietly(|| {
+        funcs.into_iter().try_for_each(|func| {
+            try_compile_function(func, Target::X64SysV, &options).map(drop)
+        })
+    })?;
+    compiled.map_err(|e| e.root().to_string())
+}
+
+/// Run `f`, turning a panic into its message without printing it.
+pub(crate) fn quietly<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result.map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| (*s).to_string()))
+            .unwrap_or_else(|| "non-string panic".to_string())
+    })
+}
+
+/// The part of a failure message that names what went wrong, without the
+/// function or value names that shrinking may change.
+#[must_use]
+pub fn failure_kind(msg: &str) -> &str {
+    msg.split(['`', ':']).next().unwrap_or(msg)
+}
+
+/// Delete lines of `src` while `fails` still holds, largest chunks first,
+/// until no single line can go. Labels and the function header and footer
+/// stay, so every candidate keeps its block structure.
+#[must_use]
+pub fn shrink(src: &str, mut fails: impl FnMut(&str) -> bool) -> String {
+    let mut lines: Vec<&str> = src.lines().collect();
+    let removable = |l: &str| l.starts_with("    ");
+    let mut chunk = lines.len().next_power_of_two();
+    while chunk > 0 {
+        let mut removed = false;
+        let mut start = 0;
+        while start < lines.len() {
+            let end = (start + chunk).min(lines.len());
+            if lines[start..end].iter().any(|l| removable(l)) {
+                let candidate: Vec<&str> = lines[..start]
+                    .iter()
+                    .chain(lines[start..end].iter().filter(|l| !removable(l)))
+                    .chain(&lines[end..])
+                    .copied()
+                    .collect();
+                let text = candidate.join("\n") + "\n";
+                if candidate.len() < lines.len() && fails(&text) {
+                    lines = candidate;
+                    removed = true;
+                    continue;
+                }
+            }
+            start = end;
+        }
+        // Single-line passes repeat until nothing more goes: a deletion
+        // can free an earlier line that only fed it.
+        if chunk > 1 || !removed {
+            chunk /= 2;
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_programs_pass_the_allocation_checker() {
+        let mut data = Vec::new();
+        for seed in 0..64u32 {
+            // Cheap deterministic bytes; libFuzzer supplies real ones.
+            let mut x = seed.wrapping_mul(0x9E37_79B9) | 1;
+            data.clear();
+            data.extend((0..(seed * 37) % 480).map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x.to_le_bytes()[0]
+            }));
+            let src = generate(&data);
+            if let Err(e) = check(&src) {
+                panic!("seed {seed}: {e}\n{src}");
+            }
+        }
+    }
+
+    /// Reduced from generated inputs: a float arg split by a call's
+    /// clobbers in one arm of a diamond must still reach its slot along
+    /// the other arm.
+    #[test]
+    fn split_in_one_arm_is_stored_on_the_other() {
+        let src = "\
+func @fuzz(%a0, %a1, %a2, %a3, %f0: f64, %f1: f64) {
+entry:
+    br g %a0, %a3, then1, else2
+then1:
+    %v5 = call @ext(%a2, %a2, %a0)
+    jmp join3
+else2:
+    jmp join3
+join3:
+    %v22 = fsub.f64 %f1, %f0
+    %v27 = iconst -77
+    ret %v27
+}
+";
+        assert_eq!(check(src), Ok(()));
+    }
+
+    #[test]
+    fn shrink_leaves_a_one_minimal_reproducer() {
+        let src = generate(&[10; 64]);
+        let still = |s: &str| check(s).is_ok() && s.contains("call @ext");
+        assert!(still(&src), "input exercises calls:\n{src}");
+        let small = shrink(&src, still);
+        assert!(small.lines().count() < src.lines().count());
+        let lines: Vec<&str> = small.lines().collect();
+        for (i, l) in lines.iter().enumerate() {
+            if l.starts_with("    ") {
+                let mut fewer = lines.clone();
+                fewer.remove(i);
+                assert!(
+                    !still(&(fewer.join("\n") + "\n")),
+                    "`{l}` is removable:\n{small}"
+                );
+            }
+        }
+        assert_eq!(
+            failure_kind("Register allocation check failed: @1 inst 0: vreg 3 has no location"),
+            "Register allocation check failed"
+        );
+    }
+}
@@ -45,3 +45,14 @@ pub fn is_xmm(r: Reg) -> bool {
     r >= XMM_BASE
 }
 
+
+/// Assembly name of physical register `r`.
+#[must_use]
+pub fn preg_name(r: Reg) -> &'static str {
+    const NAMES: [&str; 32] = [
+        "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rsp", "rbp", "r8", "r9", "r10", "r11", "r12",
+        "r13", "r14", "r15", "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+        "xmm8", "xmm9", "xmm10", "xmm11", "xmm12", "xmm13", "xmm14", "xmm15",
+    ];
+    NAMES[r as usize]
+}
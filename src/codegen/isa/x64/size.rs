@@ -0,0 +1,537 @@
+//! Machine-code size of x64 instructions ahead of encoding.
+//!
+//! `encoded_size` is the exact length of what the MC emitter produces for
+//! an instruction once every operand sits in a register; `worst_case_size`
+//! bounds what it can produce under any allocation, spill reloads and
+//! stores included. Branch relaxation, alignment padding and jump-table
+//! layout can size code with these before the final encoding.
+//!
+//! Both follow the forms `CodeAssembler` picks: `imm8` where the
+//! immediate fits, the short `RAX` forms, `rel32` branches (the assembler
+//! may shrink them to `rel8`, never grow them). `CondJmp` counts its
+//! trailing `jmp`. Split stores the allocator places between instructions
+//! are not part of any instruction's size.
+
+use crate::codegen::isa::x64::inst::{Mem, X64Inst};
+use crate::codegen::isa::x64::regs::{R12, R13, RAX, RBP, RDI, RSI, RSP, is_xmm};
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction, Reg, ScalarType};
+
+/// `mov r64, [rbp + disp32]` (or the matching store): one spill reload.
+const RELOAD: u32 = 7;
+/// `movsd xmm8+, [rbp + disp32]`.
+const FP_RELOAD: u32 = 9;
+/// `add rsp, imm32`, five callee-saved pops, `pop rbp`, `ret`.
+const EPILOGUE: u32 = 7 + 1 + 4 * 2 + 1 + 1;
+
+/// Whether `r` needs a REX extension bit (r8–r15, xmm8–xmm15).
+fn ext(r: Reg) -> bool {
+    r % 16 >= 8
+}
+
+/// REX prefix bytes: one if `w` is set or any of `regs` is extended.
+fn rex(w: bool, regs: &[Reg]) -> u32 {
+    u32::from(w || regs.iter().any(|&r| ext(r)))
+}
+
+/// Byte-register operands: `spl`/`bpl`/`sil`/`dil` need a REX as well.
+fn rex8(byte_regs: &[Reg], others: &[Reg]) -> u32 {
+    let low = |r: Reg| matches!(r, RSP | RBP | RSI | RDI);
+    u32::from(byte_regs.iter().any(|&r| ext(r) || low(r)) || others.iter().any(|&r| ext(r)))
+}
+
+fn disp_len(disp: i32, needs_disp: bool) -> u32 {
+    if disp == 0 && !needs_disp {
+        0
+    } else if i8::try_from(disp).is_ok() {
+        1
+    } else {
+        4
+    }
+}
+
+/// ModRM, SIB and displacement bytes for `m`, and the pregs it names.
+fn addr(m: &Mem, preg: &dyn Fn(Reg) -> Reg) -> (u32, [Reg; 2]) {
+    let base = preg(m.base);
+    let index = m.index.map(preg);
+    let sib = u32::from(index.is_some() || matches!(base, RSP | R12));
+    let len = 1 + sib + disp_len(m.disp, matches!(base, RBP | R13));
+    // An absent index can't set REX.X; `base` stands in for it.
+    (len, [base, index.unwrap_or(base)])
+}
+
+/// `op r64, imm32` in the form `CodeAssembler` picks.
+fn alu_ri(dst: Reg, imm: i32) -> u32 {
+    if dst == RAX {
+        6
+    } else if i8::try_from(imm).is_ok() {
+        4
+    } else {
+        7
+    }
+}
+
+pub(crate) fn encoded_size(inst: &X64Inst, preg: &dyn Fn(Reg) -> Reg) -> Option<u32> {
+    let p = preg;
+    let size = match *inst {
+        X64Inst::Mov64rr { .. }
+        | X64Inst::Add64rr { .. }
+        | X64Inst::Sub64rr { .. }
+        | X64Inst::And64rr { .. }
+        | X64Inst::Or64rr { .. }
+        | X64Inst::Xor64rr { .. }
+        | X64Inst::Cmp64rr { .. }
+        | X64Inst::Test64rr { .. }
+        | X64Inst::Movsxd64r32 { .. }
+        | X64Inst::Not64r { .. }
+        | X64Inst::Neg64r { .. }
+        | X64Inst::Shl64rcl { .. }
+        | X64Inst::Shr64rcl { .. }
+        | X64Inst::Sar64rcl { .. }
+        | X64Inst::Adc64rr { .. }
+        | X64Inst::Sbb64rr { .. }
+        | X64Inst::Idiv64r { .. }
+        | X64Inst::Div64r { .. }
+        | X64Inst::Mul64r { .. } => 3,
+        X64Inst::Imul64rr { .. }
+        | X64Inst::Cmov64rr { .. }
+        | X64Inst::Movsx64r8 { .. }
+        | X64Inst::Movsx64r16 { .. }
+        | X64Inst::Movzx64r8 { .. }
+        | X64Inst::Movzx64r16 { .. } => 4,
+        X64Inst::Mov64ri { .. } | X64Inst::Mov64rsym { .. } => 10,
+        X64Inst::Mov64rm { src: m, .. }
+        | X64Inst::Mov64mr { dst: m, .. }
+        | X64Inst::Lea64rm { src: m, .. } => 2 + addr(&m, p).0,
+
+        X64Inst::Mov32rr { dst, src } => 2 + rex(false, &[p(dst), p(src)]),
+        X64Inst::Mov32ri { dst, .. } => 5 + rex(false, &[p(dst)]),
+        X64Inst::Mov32rm { dst: r, src: m } | X64Inst::Mov32mr { dst: m, src: r } => {
+            let (len, regs) = addr(&m, p);
+            1 + rex(false, &[p(r), regs[0], regs[1]]) + len
+        }
+        X64Inst::Mov16rr { dst, src } => 3 + rex(false, &[p(dst), p(src)]),
+        X64Inst::Mov16ri { dst, .. } => 4 + rex(false, &[p(dst)]),
+        X64Inst::Mov16rm { dst: r, src: m } | X64Inst::Mov16mr { dst: m, src: r } => {
+            let (len, regs) = addr(&m, p);
+            2 + rex(false, &[p(r), regs[0], regs[1]]) + len
+        }
+        X64Inst::Mov8rr { dst, src } => 2 + rex8(&[p(dst), p(src)], &[]),
+        X64Inst::Mov8ri { dst, .. } => 2 + rex8(&[p(dst)], &[]),
+        X64Inst::Mov8rm { dst: r, src: m } | X64Inst::Mov8mr { dst: m, src: r } => {
+            let (len, regs) = addr(&m, p);
+            1 + rex8(&[p(r)], &regs) + len
+        }
+
+        X64Inst::Add64ri32 { dst, imm }
+        | X64Inst::Sub64ri32 { dst, imm }
+        | X64Inst::And64ri32 { dst, imm }
+        | X64Inst::Or64ri32 { dst, imm }
+        | X64Inst::Xor64ri32 { dst, imm }
+        | X64Inst::Cmp64ri32 { lhs: dst, imm } => alu_ri(p(dst), imm),
+        X64Inst::Test64ri32 { lhs, .. } => {
+            if p(lhs) == RAX {
+                6
+            } else {
+                7
+            }
+        }
+        X64Inst::Shl64ri8 { imm, .. }
+        | X64Inst::Shr64ri8 { imm, .. }
+        | X64Inst::Sar64ri8 { imm, .. } => {
+            if imm == 1 {
+                3
+            } else {
+                4
+            }
+        }
+        X64Inst::Setcc8r { dst, .. } => 3 + rex8(&[p(dst)], &[]),
+
+        X64Inst::Call64r { target } | X64Inst::Jmp64r { target } => {
+            2 + rex(false, &[p(target)])
+        }
+        X64Inst::Jmp { .. } => 5,
+        X64Inst::CondJmp { .. } => 6 + 5,
+        X64Inst::Ud2 => 2,
+        X64Inst::Int3 => 1,
+        X64Inst::Mfence => 3,
+        X64Inst::RepMovsb { .. } | X64Inst::RepStosb { .. } => 2,
+
+        // The argument's offset depends on the callee-saved pushes, `ret`
+        // on the epilogue in front of it, an `rsp` adjustment on whether
+        // the frame already holds the outgoing arguments, and a table
+        // jump on which scratch registers it dispatches through. An XMM
+        // stack argument is a `movsd` or a shorter `movups` by its type.
+        X64Inst::LoadArgFromStack { .. }
+        | X64Inst::RawRet
+        | X64Inst::AdjustRsp { .. }
+        | X64Inst::JmpTable { .. } => {
+            return None;
+        }
+        X64Inst::StoreStackArg { src, .. } if is_xmm(p(src)) => return None,
+        X64Inst::StoreStackArg { stack_idx, .. } => 4 + disp_len(8 * stack_idx as i32, false),
+        X64Inst::Movssrr { dst, src }
+        | X64Inst::Movsdrr { dst, src }
+        | X64Inst::Addssrr { dst, src }
+        | X64Inst::Subssrr { dst, src }
+        | X64Inst::Mulssrr { dst, src }
+        | X64Inst::Divssrr { dst, src }
+        | X64Inst::Addsdrr { dst, src }
+        | X64Inst::Subsdrr { dst, src }
+        | X64Inst::Mulsdrr { dst, src }
+        | X64Inst::Divsdrr { dst, src }
+        | X64Inst::Ucomisdrr { lhs: dst, rhs: src } => 4 + rex(false, &[p(dst), p(src)]),
+        X64Inst::Ucomissrr { lhs, rhs } => 3 + rex(false, &[p(lhs), p(rhs)]),
+        X64Inst::Movssrm { dst: r, src: m }
+        | X64Inst::Movssmr { dst: m, src: r }
+        | X64Inst::Movsdrm { dst: r, src: m }
+        | X64Inst::Movsdmr { dst: m, src: r }
+        | X64Inst::Movdqurm { dst: r, src: m }
+        | X64Inst::Movdqumr { dst: m, src: r } => {
+            let (len, regs) = addr(&m, p);
+            3 + rex(false, &[p(r), regs[0], regs[1]]) + len
+        }
+        X64Inst::Movapsrr { dst, src } => 3 + rex(false, &[p(dst), p(src)]),
+        // Packed single has no mandatory prefix; everything else has `66`,
+        // and `pmulld` a three-byte opcode.
+        X64Inst::Vaddrr { lanes: ScalarType::F32, dst, src }
+        | X64Inst::Vmulrr { lanes: ScalarType::F32, dst, src } => 3 + rex(false, &[p(dst), p(src)]),
+        X64Inst::Vmulrr { lanes: ScalarType::I32, dst, src } => 5 + rex(false, &[p(dst), p(src)]),
+        X64Inst::Vmulrr { lanes: ScalarType::I8 | ScalarType::I64 | ScalarType::Ptr, .. } => {
+            return None;
+        }
+        X64Inst::Vaddrr { dst, src, .. } | X64Inst::Vmulrr { dst, src, .. } => {
+            4 + rex(false, &[p(dst), p(src)])
+        }
+        X64Inst::Pshufdrri { dst, src, .. }
+        | X64Inst::Pextrrri { lanes: ScalarType::I16, dst, src, .. } => {
+            5 + rex(false, &[p(dst), p(src)])
+        }
+        X64Inst::Pextrrri { lanes: ScalarType::I64 | ScalarType::F64 | ScalarType::Ptr, .. } => 7,
+        X64Inst::Pextrrri { dst, src, .. } => 6 + rex(false, &[p(dst), p(src)]),
+        // `xorps`; else `mov ebx, imm32` then `movd`/`movq`, or a `movsd`
+        // from the pool (its 8 data bytes sit after the code).
+        X64Inst::Fconst32 { dst, bits: 0 } | X64Inst::Fconst64 { dst, bits: 0 } => {
+            3 + rex(false, &[p(dst)])
+        }
+        X64Inst::Fconst32 { dst, .. } => 5 + 4 + rex(false, &[p(dst)]),
+        X64Inst::Fconst64 { bits, .. } if u32::try_from(bits).is_ok() => 5 + 5,
+        X64Inst::Fconst64 { bits, .. } if i32::try_from(bits as i64).is_ok() => 7 + 5,
+        X64Inst::Fconst64 { dst, .. } => 8 + rex(false, &[p(dst)]),
+
+        X64Inst::LockXadd64mr { dst: m, .. } | X64Inst::LockCmpxchg64mr { dst: m, .. } => {
+            4 + addr(&m, p).0
+        }
+        // Lowered by `lower_selects` into code of its own.
+        X64Inst::Select { .. } => return None,
+    };
+    Some(size)
+}
+
+/// `(GPR, XMM)` operands the emitter may have to reload from or store
+/// back to a spill slot around `inst`. Pinned operands never spill.
+fn spill_traffic(inst: &X64Inst) -> (u32, u32) {
+    let index = |m: &Mem| u32::from(m.index.is_some());
+    match *inst {
+        X64Inst::Add64rr { .. }
+        | X64Inst::Sub64rr { .. }
+        | X64Inst::Adc64rr { .. }
+        | X64Inst::Sbb64rr { .. }
+        | X64Inst::Imul64rr { .. }
+        | X64Inst::And64rr { .. }
+        | X64Inst::Or64rr { .. }
+        | X64Inst::Xor64rr { .. }
+        | X64Inst::Cmov64rr { .. } => (3, 0),
+        X64Inst::Mov64rr { .. }
+        | X64Inst::Mov32rr { .. }
+        | X64Inst::Mov16rr { .. }
+        | X64Inst::Mov8rr { .. }
+        | X64Inst::Movsx64r8 { .. }
+        | X64Inst::Movsx64r16 { .. }
+        | X64Inst::Movsxd64r32 { .. }
+        | X64Inst::Movzx64r8 { .. }
+        | X64Inst::Movzx64r16 { .. }
+        | X64Inst::Add64ri32 { .. }
+        | X64Inst::Sub64ri32 { .. }
+        | X64Inst::And64ri32 { .. }
+        | X64Inst::Or64ri32 { .. }
+        | X64Inst::Xor64ri32 { .. }
+        | X64Inst::Not64r { .. }
+        | X64Inst::Neg64r { .. }
+        | X64Inst::Shl64ri8 { .. }
+        | X64Inst::Shr64ri8 { .. }
+        | X64Inst::Sar64ri8 { .. }
+        | X64Inst::Shl64rcl { .. }
+        | X64Inst::Shr64rcl { .. }
+        | X64Inst::Sar64rcl { .. }
+        | X64Inst::Cmp64rr { .. }
+        | X64Inst::Test64rr { .. } => (2, 0),
+        X64Inst::Mov64ri { .. }
+        | X64Inst::Mov64rsym { .. }
+        | X64Inst::Mov32ri { .. }
+        | X64Inst::Mov16ri { .. }
+        | X64Inst::Mov8ri { .. }
+        | X64Inst::Setcc8r { .. }
+        | X64Inst::Cmp64ri32 { .. }
+        | X64Inst::Test64ri32 { .. }
+        | X64Inst::Idiv64r { .. }
+        | X64Inst::Div64r { .. }
+        | X64Inst::Mul64r { .. }
+        | X64Inst::Call64r { .. }
+        | X64Inst::Jmp64r { .. }
+        | X64Inst::JmpTable { .. } => (1, 0),
+        X64Inst::Mov64rm { src: m, .. }
+        | X64Inst::Mov32rm { src: m, .. }
+        | X64Inst::Mov16rm { src: m, .. }
+        | X64Inst::Mov8rm { src: m, .. }
+        | X64Inst::Lea64rm { src: m, .. }
+        | X64Inst::Mov64mr { dst: m, .. }
+        | X64Inst::Mov32mr { dst: m, .. }
+        | X64Inst::Mov16mr { dst: m, .. }
+        | X64Inst::Mov8mr { dst: m, .. }
+        | X64Inst::LockXadd64mr { dst: m, .. }
+        | X64Inst::LockCmpxchg64mr { dst: m, .. } => (2 + index(&m), 0),
+        X64Inst::Movssrm { src: m, .. }
+        | X64Inst::Movsdrm { src: m, .. }
+        | X64Inst::Movdqurm { src: m, .. }
+        | X64Inst::Movssmr { dst: m, .. }
+        | X64Inst::Movsdmr { dst: m, .. }
+        | X64Inst::Movdqumr { dst: m, .. } => (1 + index(&m), 1),
+        X64Inst::Pextrrri { .. } => (1, 1),
+        X64Inst::Movssrr { .. }
+        | X64Inst::Movsdrr { .. }
+        | X64Inst::Movapsrr { .. }
+        | X64Inst::Pshufdrri { .. }
+        | X64Inst::Ucomissrr { .. }
+        | X64Inst::Ucomisdrr { .. } => (0, 2),
+        X64Inst::Fconst32 { .. } | X64Inst::Fconst64 { .. } => (0, 1),
+        X64Inst::Addssrr { .. }
+        | X64Inst::Subssrr { .. }
+        | X64Inst::Mulssrr { .. }
+        | X64Inst::Divssrr { .. }
+        | X64Inst::Addsdrr { .. }
+        | X64Inst::Subsdrr { .. }
+        | X64Inst::Mulsdrr { .. }
+        | X64Inst::Divsdrr { .. }
+        | X64Inst::Vaddrr { .. }
+        | X64Inst::Vmulrr { .. } => (0, 3),
+        X64Inst::Jmp { .. }
+        | X64Inst::CondJmp { .. }
+        | X64Inst::Ud2
+        | X64Inst::Int3
+        | X64Inst::Mfence
+        | X64Inst::RepMovsb { .. }
+        | X64Inst::RepStosb { .. }
+        | X64Inst::AdjustRsp { .. }
+        | X64Inst::LoadArgFromStack { .. }
+        | X64Inst::StoreStackArg { .. }
+        | X64Inst::RawRet
+        | X64Inst::Select { .. } => (0, 0),
+    }
+}
+
+pub(crate) fn worst_case_size(inst: &X64Inst) -> Option<u32> {
+    let size = match *inst {
+        // `movsd xmm8+, [rbp + disp32]`, then the def's spill store.
+        X64Inst::LoadArgFromStack { .. } => 2 * FP_RELOAD,
+        X64Inst::StoreStackArg { stack_idx, .. } => {
+            FP_RELOAD + 6 + disp_len(8 * stack_idx as i32, false)
+        }
+        X64Inst::RawRet => EPILOGUE,
+        X64Inst::AdjustRsp { delta } => match delta {
+            0 => 0,
+            _ if i8::try_from(delta.unsigned_abs()).is_ok() => 4,
+            _ => 7,
+        },
+        X64Inst::Select { .. } => return None,
+        // `lea` rip-relative, `movsxd` with a possible disp8 base, `add`,
+        // `jmp r`, and a reload of a spilled index. The table itself
+        // follows the function's code.
+        X64Inst::JmpTable { .. } => 7 + 5 + 3 + 3 + RELOAD,
+        _ => {
+            // Extended regs maximize prefixes and (as r13) displacements;
+            // RAX picks the long `op rax, imm32` forms.
+            let longest = [R13, RAX]
+                .into_iter()
+                .filter_map(|r| encoded_size(inst, &|_| r))
+                .max()
+                .expect("sized without context");
+            let (gprs, xmms) = spill_traffic(inst);
+            longest + gprs * RELOAD + xmms * FP_RELOAD
+        }
+    };
+    Some(size)
+}
+
+/// Upper bound on the bytes `block` emits, pseudos included. `None` while
+/// it still holds pseudos that lower to code of unknown size (phis, calls,
+/// frame markers, aggregates, selects).
+#[must_use]
+pub fn worst_case_block_size(func: &Func<X64Inst>, block: Block) -> Option<u32> {
+    func.get_block_data(block)
+        .iter()
+        .map(|inst| match *inst {
+            // Reload, then a `movsd` straight into the destination slot.
+            Instruction::Pseudo(PseudoInstruction::Copy { .. }) => Some(2 * FP_RELOAD),
+            // `lea r, [rbp + disp32]` and the def's spill store.
+            Instruction::Pseudo(PseudoInstruction::StackAlloc { .. }) => Some(2 * RELOAD),
+            Instruction::Pseudo(PseudoInstruction::Trap { .. }) => Some(2),
+            Instruction::Pseudo(PseudoInstruction::RawBytes { id }) => {
+                Some(func.raw_bytes_operands(id).bytes.len() as u32)
+            }
+            ref other => other.worst_case_size(),
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::codegen::isa::x64::mc::emit_mc::FnMCWriter;
+    use crate::codegen::isa::x64::regs::*;
+    use crate::codegen::regalloc::{AllocatedSlot, Assignment, RegAllocConfig, RegAllocResult};
+    use crate::support::slotmap::{Key, SecondaryMap};
+
+    /// Bytes `inst` adds to a `noreturn` function whose vregs `0..` live in
+    /// `slots` for the whole body.
+    fn emitted_len(inst: X64Inst, slots: &[AllocatedSlot]) -> u32 {
+        let cfg = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX],
+            scratch_regs: vec![RBX, R14, R15],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: false,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let mut ra = RegAllocResult {
+            assignments: SecondaryMap::new(0),
+            frame_layout: Vec::new(),
+            frame_size: 8 * slots.len() as u32,
+            edits: Vec::new(),
+        };
+        for (v, &slot) in slots.iter().enumerate() {
+            ra.assignments.set(v as Reg, Assignment::uniform(slot, 0, 100));
+        }
+        let emit = |body: &[X64Inst]| {
+            let mut func = Func::<X64Inst>::new("t".to_string());
+            let b = func.add_empty_block();
+            for _ in slots {
+                func.new_vreg();
+            }
+            for &i in body {
+                func.get_block_data_mut(b).push_target_inst(i);
+            }
+            func.get_block_data_mut(b).push_target_inst(X64Inst::Ud2);
+            func.attrs_mut().noreturn = true;
+            FnMCWriter::new(&func, &cfg, &ra).emit_fn().len() as u32
+        };
+        emit(&[inst]) - emit(&[])
+    }
+
+    fn in_regs(pregs: &[Reg]) -> Vec<AllocatedSlot> {
+        pregs.iter().map(|&p| AllocatedSlot::Reg(p)).collect()
+    }
+
+    #[test]
+    fn encoded_size_matches_the_emitter() {
+        let mem = |base| Mem { base, index: Some(1), scale: 4, disp: 0 };
+        let insts = [
+            X64Inst::Mov64rr { dst: 0, src: 1 },
+            X64Inst::Mov64ri { dst: 0, imm: 1 },
+            X64Inst::Mov64rm { dst: 0, src: Mem::base(1) },
+            X64Inst::Mov64mr { dst: Mem::base_disp(0, 8), src: 1 },
+            X64Inst::Mov64rm { dst: 2, src: mem(0) },
+            X64Inst::Lea64rm { dst: 0, src: Mem::base_disp(1, 1000) },
+            X64Inst::Mov32rr { dst: 0, src: 1 },
+            X64Inst::Mov32ri { dst: 0, imm: -1 },
+            X64Inst::Mov32mr { dst: Mem::base(0), src: 1 },
+            X64Inst::Mov16rr { dst: 0, src: 1 },
+            X64Inst::Mov16ri { dst: 0, imm: 7 },
+            X64Inst::Mov16rm { dst: 0, src: Mem::base(1) },
+            X64Inst::Mov8rr { dst: 0, src: 1 },
+            X64Inst::Mov8ri { dst: 0, imm: 7 },
+            X64Inst::Mov8mr { dst: Mem::base_disp(0, -4), src: 1 },
+            X64Inst::Movsx64r8 { dst: 0, src: 1 },
+            X64Inst::Movsxd64r32 { dst: 0, src: 1 },
+            X64Inst::Movzx64r16 { dst: 0, src: 1 },
+            X64Inst::Add64rr { dst: 0, src: 1 },
+            X64Inst::Sbb64rr { dst: 0, src: 1 },
+            X64Inst::Imul64rr { dst: 0, src: 1 },
+            X64Inst::Add64ri32 { dst: 0, imm: 8 },
+            X64Inst::Sub64ri32 { dst: 0, imm: 1 << 20 },
+            X64Inst::Cmp64ri32 { lhs: 0, imm: -3 },
+            X64Inst::Test64ri32 { lhs: 0, imm: 1 },
+            X64Inst::Not64r { dst: 0 },
+            X64Inst::Shl64ri8 { dst: 0, imm: 1 },
+            X64Inst::Sar64ri8 { dst: 0, imm: 5 },
+            X64Inst::Cmov64rr { cond: Cond::L, dst: 0, src: 1 },
+            X64Inst::Setcc8r { cond: Cond::Z, dst: 0 },
+            X64Inst::Call64r { target: 0 },
+            X64Inst::Mfence,
+            X64Inst::LockXadd64mr { dst: Mem::base(0), src: 1 },
+        ];
+        let gpr_sets = [[RAX, RCX, RDX], [R13, R12, R8], [RSI, RDI, R13], [RDX, R12, RAX]];
+        for inst in insts {
+            for pregs in gpr_sets {
+                let expected = emitted_len(inst, &in_regs(&pregs));
+                assert_eq!(inst.encoded_size(&|v| pregs[v as usize]), Some(expected), "{inst:?}");
+            }
+        }
+
+        let fp = [
+            X64Inst::Movsdrr { dst: 0, src: 1 },
+            X64Inst::Fconst32 { dst: 0, bits: 1.5f32.to_bits() },
+            X64Inst::Fconst64 { dst: 0, bits: 0 },
+            X64Inst::Fconst64 { dst: 0, bits: 1 },
+            X64Inst::Fconst64 { dst: 0, bits: u64::MAX },
+            X64Inst::Movapsrr { dst: 0, src: 1 },
+            X64Inst::Vaddrr { lanes: ScalarType::I8, dst: 0, src: 1 },
+            X64Inst::Vaddrr { lanes: ScalarType::F32, dst: 0, src: 1 },
+            X64Inst::Vmulrr { lanes: ScalarType::I32, dst: 0, src: 1 },
+            X64Inst::Vmulrr { lanes: ScalarType::F64, dst: 0, src: 1 },
+            X64Inst::Pshufdrri { dst: 0, src: 1, order: 0x1b },
+            X64Inst::Addssrr { dst: 0, src: 1 },
+            X64Inst::Ucomissrr { lhs: 0, rhs: 1 },
+            X64Inst::Ucomisdrr { lhs: 0, rhs: 1 },
+        ];
+        for inst in fp {
+            for pregs in [[XMM0, XMM1], [XMM9, XMM2], [XMM3, XMM12]] {
+                let expected = emitted_len(inst, &in_regs(&pregs));
+                assert_eq!(inst.encoded_size(&|v| pregs[v as usize]), Some(expected), "{inst:?}");
+            }
+        }
+
+        for lanes in [ScalarType::I8, ScalarType::I16, ScalarType::I32, ScalarType::I64] {
+            let inst = X64Inst::Pextrrri { lanes, dst: 0, src: 1, lane: 1 };
+            for pregs in [[RAX, XMM1], [R12, XMM2], [RCX, XMM10]] {
+                let expected = emitted_len(inst, &in_regs(&pregs));
+                assert_eq!(inst.encoded_size(&|v| pregs[v as usize]), Some(expected), "{inst:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn worst_case_covers_registers_and_spills() {
+        let spilled: Vec<_> = (0..3).map(|s| AllocatedSlot::Stack(s + 20)).collect();
+        for inst in [
+            X64Inst::Add64ri32 { dst: 0, imm: 1 << 20 },
+            X64Inst::Imul64rr { dst: 0, src: 1 },
+            X64Inst::Mov32mr { dst: Mem { base: 0, index: Some(1), scale: 2, disp: 300 }, src: 2 },
+            X64Inst::Setcc8r { cond: Cond::G, dst: 0 },
+        ] {
+            let worst = inst.worst_case_size().unwrap();
+            for p in [RAX, RCX, R13] {
+                assert!(inst.encoded_size(&|_| p).unwrap() <= worst, "{inst:?}");
+            }
+            assert!(emitted_len(inst, &spilled) <= worst, "{inst:?}");
+        }
+        let jcc = X64Inst::CondJmp { cond: Cond::Z, taken: Block::new(0), not_taken: Block::new(1) };
+        assert_eq!(jcc.worst_case_size(), Some(11));
+    }
+}
@@ -0,0 +1,341 @@
+//! `proptest` strategies for x64 IR.
+//!
+//! Strategies don't generate instructions directly but a recipe
+//! (`FuncSpec`: regions of `Op`s) that `FuncSpec::build` turns into a
+//! `Func<X64Inst>` through `FuncBuilder`. Operands are indices taken modulo
+//! the values in scope, so every recipe, and every step `proptest` shrinks
+//! it by, builds valid SSA: shrinking drops ops and regions and pulls
+//! indices and constants toward 0. `FuncSpec::eval` is the matching
+//! oracle, for comparing against what the compiled function returns.
+//!
+//! ```text
+//! proptest! {
+//!     fn compiles(spec in any::<FuncSpec>()) {
+//!         let func = spec.build("f");
+//!         ...
+//!     }
+//! }
+//! ```
+
+use proptest::collection::{SizeRange, vec};
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use crate::codegen::isa::x64::builder::FuncBuilder;
+use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+use crate::codegen::tir::{Func, Reg};
+
+/// Most arguments a `FuncSpec` takes; all fit SysV argument registers.
+pub const MAX_ARGS: usize = 6;
+
+/// A two-operand integer operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    And,
+    Or,
+    Xor,
+}
+
+impl BinOp {
+    fn eval(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::And => a & b,
+            BinOp::Or => a | b,
+            BinOp::Xor => a ^ b,
+        }
+    }
+
+    fn build(self, b: &mut FuncBuilder, x: Reg, y: Reg) -> Reg {
+        match self {
+            BinOp::Add => b.add(x, y),
+            BinOp::Sub => b.sub(x, y),
+            BinOp::Mul => b.imul(x, y),
+            BinOp::And => b.and(x, y),
+            BinOp::Or => b.or(x, y),
+            BinOp::Xor => b.xor(x, y),
+        }
+    }
+}
+
+/// One instruction of a sequence, defining a new value. Operands index
+/// the values in scope, modulo their count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Const(i64),
+    Bin(BinOp, usize, usize),
+    Not(usize),
+    Neg(usize),
+}
+
+/// A piece of control flow. Values an arm or a loop body defines are
+/// local to it; only the region's result joins the enclosing scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Ops in the current block; each value joins the scope.
+    Straight(Vec<Op>),
+    /// `lhs cond rhs` picks an arm; the result is a phi of each arm's
+    /// last value.
+    Diamond { cond: Cond, lhs: usize, rhs: usize, then: Vec<Op>, els: Vec<Op> },
+    /// A single-block loop run `trips` times, threading an accumulator
+    /// (starting at `init`) through `body`. Inside, the scope also holds
+    /// the accumulator and the trip counter; the result is the final
+    /// accumulator.
+    Loop { trips: u8, init: usize, body: Vec<Op> },
+}
+
+/// A whole function: `args` arguments, then `regions` in order,
+/// returning the last value in scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuncSpec {
+    pub args: usize,
+    pub regions: Vec<Region>,
+}
+
+fn pick(scope: &[Reg], idx: usize) -> Reg {
+    scope[idx % scope.len()]
+}
+
+fn pick_val(scope: &[i64], idx: usize) -> i64 {
+    scope[idx % scope.len()]
+}
+
+fn build_ops(b: &mut FuncBuilder, scope: &mut Vec<Reg>, ops: &[Op]) {
+    for &op in ops {
+        let v = match op {
+            Op::Const(imm) => b.iconst64(imm),
+            Op::Bin(bin, x, y) => bin.build(b, pick(scope, x), pick(scope, y)),
+            Op::Not(x) => b.not(pick(scope, x)),
+            Op::Neg(x) => b.neg(pick(scope, x)),
+        };
+        scope.push(v);
+    }
+}
+
+fn eval_ops(scope: &mut Vec<i64>, ops: &[Op]) {
+    for &op in ops {
+        let v = match op {
+            Op::Const(imm) => imm,
+            Op::Bin(bin, x, y) => bin.eval(pick_val(scope, x), pick_val(scope, y)),
+            Op::Not(x) => !pick_val(scope, x),
+            Op::Neg(x) => pick_val(scope, x).wrapping_neg(),
+        };
+        scope.push(v);
+    }
+}
+
+impl Region {
+    fn build(&self, b: &mut FuncBuilder, scope: &mut Vec<Reg>) {
+        match self {
+            Region::Straight(ops) => build_ops(b, scope, ops),
+            Region::Diamond { cond, lhs, rhs, then, els } => {
+                let (then_b, else_b, join) = (b.new_block(), b.new_block(), b.new_block());
+                b.branch_icmp(*cond, pick(scope, *lhs), pick(scope, *rhs), then_b, else_b);
+                let mut incoming = Vec::new();
+                for (block, ops) in [(then_b, then), (else_b, els)] {
+                    b.switch_to_block(block);
+                    let mut arm = scope.clone();
+                    build_ops(b, &mut arm, ops);
+                    incoming.push((block, *arm.last().expect("scope holds the arguments")));
+                    b.jmp(join);
+                }
+                b.switch_to_block(join);
+                scope.push(b.phi(incoming));
+            }
+            Region::Loop { trips, init, body } => {
+                let pre = b.current_block();
+                let init = pick(scope, *init);
+                let zero = b.iconst64(0);
+                let (header, exit) = (b.new_block(), b.new_block());
+                b.jmp(header);
+                b.switch_to_block(header);
+                let (acc, acc_id) = b.phi_with_id(vec![(pre, init)]);
+                let (i, i_id) = b.phi_with_id(vec![(pre, zero)]);
+                let mut inner = scope.clone();
+                inner.extend([acc, i]);
+                build_ops(b, &mut inner, body);
+                let next = *inner.last().expect("the loop scope holds the accumulator");
+                let one = b.iconst64(1);
+                let i_next = b.add(i, one);
+                let n = b.iconst64(i64::from(*trips));
+                b.branch_icmp(Cond::L, i_next, n, header, exit);
+                b.set_phi_incoming(acc_id, vec![(pre, init), (header, next)]);
+                b.set_phi_incoming(i_id, vec![(pre, zero), (header, i_next)]);
+                b.switch_to_block(exit);
+                scope.push(next);
+            }
+        }
+    }
+
+    fn eval(&self, scope: &mut Vec<i64>) {
+        match self {
+            Region::Straight(ops) => eval_ops(scope, ops),
+            Region::Diamond { cond, lhs, rhs, then, els } => {
+                let taken = cond.holds(pick_val(scope, *lhs), pick_val(scope, *rhs));
+                let mut arm = scope.clone();
+                eval_ops(&mut arm, if taken { then } else { els });
+                scope.push(*arm.last().expect("scope holds the arguments"));
+            }
+            Region::Loop { trips, init, body } => {
+                let mut acc = pick_val(scope, *init);
+                let mut i = 0;
+                loop {
+                    let mut inner = scope.clone();
+                    inner.extend([acc, i]);
+                    eval_ops(&mut inner, body);
+                    acc = *inner.last().expect("the loop scope holds the accumulator");
+                    i += 1;
+                    if i >= i64::from(*trips) {
+                        break;
+                    }
+                }
+                scope.push(acc);
+            }
+        }
+    }
+}
+
+impl FuncSpec {
+    /// Build the function through `FuncBuilder`, in pre-ABI SSA form.
+    #[must_use]
+    pub fn build(&self, name: &str) -> Func<X64Inst> {
+        let mut b = FuncBuilder::new(name);
+        let mut scope: Vec<Reg> = (0..self.args.max(1)).map(|_| b.arg()).collect();
+        for region in &self.regions {
+            region.build(&mut b, &mut scope);
+        }
+        b.ret(*scope.last().expect("scope holds the arguments"));
+        b.build()
+    }
+
+    /// What the built function returns for `args` (missing ones read as 0).
+    #[must_use]
+    pub fn eval(&self, args: &[i64]) -> i64 {
+        let mut scope: Vec<i64> =
+            (0..self.args.max(1)).map(|i| args.get(i).copied().unwrap_or(0)).collect();
+        for region in &self.regions {
+            region.eval(&mut scope);
+        }
+        *scope.last().expect("scope holds the arguments")
+    }
+}
+
+/// Operand indices stay small so shrinking lands on readable cases; they
+/// wrap around the scope anyway.
+fn operand() -> impl Strategy<Value = usize> {
+    0..16usize
+}
+
+impl Arbitrary for BinOp {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<BinOp>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        select(&[BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::And, BinOp::Or, BinOp::Xor][..]).boxed()
+    }
+}
+
+impl Arbitrary for Op {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Op>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        prop_oneof![
+            1 => any::<i64>().prop_map(Op::Const),
+            4 => (any::<BinOp>(), operand(), operand()).prop_map(|(op, x, y)| Op::Bin(op, x, y)),
+            1 => operand().prop_map(Op::Not),
+            1 => operand().prop_map(Op::Neg),
+        ]
+        .boxed()
+    }
+}
+
+/// An instruction sequence of `len` ops.
+pub fn ops(len: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Op>> {
+    vec(any::<Op>(), len)
+}
+
+/// One region whose op sequences each have `len` ops.
+pub fn region(len: impl Into<SizeRange>) -> impl Strategy<Value = Region> {
+    let len = len.into();
+    let conds = select(
+        &[
+            Cond::Z,
+            Cond::NZ,
+            Cond::L,
+            Cond::LE,
+            Cond::G,
+            Cond::GE,
+            Cond::B,
+            Cond::BE,
+            Cond::A,
+            Cond::AE,
+        ][..],
+    );
+    prop_oneof![
+        ops(len.clone()).prop_map(Region::Straight),
+        (conds, operand(), operand(), ops(len.clone()), ops(len.clone()))
+            .prop_map(|(cond, lhs, rhs, then, els)| Region::Diamond { cond, lhs, rhs, then, els }),
+        (1..=4u8, operand(), ops(len)).prop_map(|(trips, init, body)| Region::Loop {
+            trips,
+            init,
+            body
+        }),
+    ]
+}
+
+/// A function of `regions` regions, each op sequence `len` ops long.
+pub fn func_spec(
+    regions: impl Into<SizeRange>,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = FuncSpec> {
+    (1..=MAX_ARGS, vec(region(len), regions)).prop_map(|(args, regions)| FuncSpec { args, regions })
+}
+
+impl Arbitrary for FuncSpec {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FuncSpec>;
+
+    fn arbitrary_with((): ()) -> Self::Strategy {
+        func_spec(0..6, 0..8).boxed()
+    }
+}
+
+/// Built functions, named `name`; see `FuncSpec` to also get the oracle.
+pub fn func(name: &'static str) -> impl Strategy<Value = Func<X64Inst>> {
+    any::<FuncSpec>().prop_map(move |spec| spec.build(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::verify::verify;
+    use crate::codegen::isa::x64::pipeline::jit;
+
+    type Fn6 = unsafe extern "sysv64" fn(i64, i64, i64, i64, i64, i64) -> i64;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn built_functions_verify(func in func("f")) {
+            prop_assert!(verify(&func).is_ok(), "{func}");
+        }
+
+        #[test]
+        fn compiled_functions_match_the_oracle(
+            spec in any::<FuncSpec>(),
+            args in proptest::array::uniform6(any::<i64>()),
+        ) {
+            let module = jit(spec.build("f")).expect("jit load");
+            let f: Fn6 = unsafe { module.entry() };
+            let got = unsafe { f(args[0], args[1], args[2], args[3], args[4], args[5]) };
+            prop_assert_eq!(got, spec.eval(&args));
+        }
+    }
+}
@@ -3,6 +3,8 @@
 //!
 //! * Integer/pointer args: `RDI, RSI, RDX, RCX, R8, R9`.
 //! * Integer return: `RAX`.
+//! * By-value aggregates of at most 16 bytes travel in registers, one per
+//!   eightbyte (`AggregateLayout`); larger ones travel in memory.
 //! * Callee-saved: `RBX, RBP, R12..R15, RSP`.
 //! * Caller-saved (volatile): `RAX, RCX, RDX, RSI, RDI, R8..R11`.
 //! * 16-byte stack alignment *at the call instruction*; inside a callee,
@@ -15,20 +17,137 @@ use crate::codegen::isa::x64::regs::{
     R10, R11, R12, R13, R14, R15, R8, R9, RAX, RBP, RBX, RCX, RDI, RDX, RSI, XMM0, XMM1, XMM2,
     XMM3, XMM4, XMM5, XMM6, XMM7,
 };
-use crate::codegen::tir::Reg;
+use crate::codegen::tir::{Reg, Type};
 
 pub const INT_ARG_REGS: &[Reg] = &[RDI, RSI, RDX, RCX, R8, R9];
 pub const INT_RET_REG: Reg = RAX;
+/// Integer-class eightbytes of a returned aggregate, in order.
+pub const INT_RET_REGS: &[Reg] = &[RAX, RDX];
 
 /// XMM registers used for floating-point/vector arguments under SysV.
 pub const FP_ARG_REGS: &[Reg] = &[XMM0, XMM1, XMM2, XMM3, XMM4, XMM5, XMM6, XMM7];
 /// Floating-point return register: the first XMM.
 pub const FP_RET_REG: Reg = XMM0;
+/// SSE-class eightbytes of a returned aggregate, in order.
+pub const FP_RET_REGS: &[Reg] = &[XMM0, XMM1];
 
 pub const CALLEE_SAVED: &[Reg] = &[RBX, RBP, R12, R13, R14, R15];
 pub const CALLER_SAVED: &[Reg] = &[RAX, RCX, RDX, RSI, RDI, R8, R9, R10, R11];
 
 pub const STACK_ALIGN: u32 = 16;
+/// Bytes below `rsp` that signal and interrupt handlers leave alone, so a
+/// function that calls nothing may use them without moving `rsp`.
+pub const RED_ZONE: u32 = 128;
+
+/// SysV class of one eightbyte of a by-value aggregate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EightbyteClass {
+    /// Passed in a general-purpose register.
+    Integer,
+    /// Passed in an XMM register: every field in it is floating-point.
+    Sse,
+}
+
+impl EightbyteClass {
+    /// The type of a register holding the whole eightbyte.
+    #[must_use]
+    pub fn word_type(self) -> Type {
+        match self {
+            EightbyteClass::Integer => Type::I64,
+            EightbyteClass::Sse => Type::F64,
+        }
+    }
+}
+
+/// C layout and SysV classification of a by-value aggregate whose fields
+/// are scalars, in declaration order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AggregateLayout {
+    /// Byte offset of each field.
+    pub offsets: Vec<u32>,
+    /// Total size, padded to the alignment.
+    pub size: u32,
+    pub align: u32,
+    /// Class of each eightbyte, in order.
+    pub eightbytes: Vec<EightbyteClass>,
+}
+
+impl AggregateLayout {
+    /// Lay `fields` out at their natural alignment, as a C struct would.
+    /// Panics on a non-scalar field.
+    #[must_use]
+    pub fn of(fields: &[Type]) -> Self {
+        let mut offsets = Vec::with_capacity(fields.len());
+        let mut size: u32 = 0;
+        let mut align = 1;
+        for &ty in fields {
+            let bytes = ty
+                .scalar_bytes()
+                .unwrap_or_else(|| panic!("by-value aggregate field of type {ty} is not scalar"));
+            size = size.next_multiple_of(bytes);
+            offsets.push(size);
+            size += bytes;
+            align = align.max(bytes);
+        }
+        let size = size.next_multiple_of(align);
+        // Fields never straddle an eightbyte at natural alignment, so each
+        // is `Sse` exactly when all the fields in it are floats.
+        let eightbytes = (0..size.div_ceil(8))
+            .map(|i| {
+                let all_fp = fields
+                    .iter()
+                    .zip(&offsets)
+                    .filter(|&(_, &off)| off / 8 == i)
+                    .all(|(ty, _)| ty.is_fp_or_vector());
+                if all_fp {
+                    EightbyteClass::Sse
+                } else {
+                    EightbyteClass::Integer
+                }
+            })
+            .collect();
+        Self {
+            offsets,
+            size,
+            align,
+            eightbytes,
+        }
+    }
+
+    /// Whether the aggregate is passed in memory: on the stack as an
+    /// argument, through a hidden pointer in `RDI` as a return value,
+    /// which the callee hands back in `RAX`.
+    #[must_use]
+    pub fn in_memory(&self) -> bool {
+        self.size > 16
+    }
+
+    /// The register each eightbyte comes back in when the aggregate is
+    /// returned in registers. Each class counts through its own pair.
+    #[must_use]
+    pub fn ret_regs(&self) -> Vec<Reg> {
+        let (mut int, mut fp) = (INT_RET_REGS.iter(), FP_RET_REGS.iter());
+        self.eightbytes
+            .iter()
+            .map(|class| match class {
+                EightbyteClass::Integer => int.next(),
+                EightbyteClass::Sse => fp.next(),
+            })
+            .map(|r| *r.expect("register-returned aggregates have at most two eightbytes"))
+            .collect()
+    }
+
+    /// How many eightbytes are of `class`.
+    #[must_use]
+    pub fn count(&self, class: EightbyteClass) -> u32 {
+        self.eightbytes.iter().filter(|&&c| c == class).count() as u32
+    }
+
+    /// Indices of the fields in eightbyte `i`.
+    pub fn fields_in(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.offsets.len()).filter(move |&f| self.offsets[f] as usize / 8 == i)
+    }
+}
 
 /// Handle to the SysV AMD64 calling convention. Zero-sized — exists so the
 /// compilation pipeline can talk about "which CC to use" without the ABI
@@ -104,4 +223,25 @@ mod tests {
         assert_eq!(cc.max_int_args_in_regs(), 6);
         assert_eq!(cc.int_ret_reg(), RAX);
     }
+
+    #[test]
+    fn aggregates_classify_per_eightbyte() {
+        use EightbyteClass::{Integer, Sse};
+        // struct { int a; float b; double c; }: a and b share an eightbyte.
+        let mixed = AggregateLayout::of(&[Type::I32, Type::F32, Type::F64]);
+        assert_eq!(mixed.offsets, [0, 4, 8]);
+        assert_eq!(mixed.eightbytes, [Integer, Sse]);
+        assert!(!mixed.in_memory());
+        assert_eq!(mixed.ret_regs(), [RAX, XMM0]);
+
+        // struct { char c; float x, y; }: padded to 12 bytes.
+        let padded = AggregateLayout::of(&[Type::I8, Type::F32, Type::F32]);
+        assert_eq!((padded.offsets.as_slice(), padded.size), (&[0, 4, 8][..], 12));
+        assert_eq!(padded.eightbytes, [Integer, Sse]);
+        assert_eq!(padded.fields_in(0).collect::<Vec<_>>(), [0, 1]);
+
+        let big = AggregateLayout::of(&[Type::I64, Type::I64, Type::I32]);
+        assert_eq!(big.size, 24);
+        assert!(big.in_memory());
+    }
 }
@@ -0,0 +1,94 @@
+//! `tir!`: a `Func<X64Inst>` written out block by block.
+//!
+//! For tests that need exact machine-level instructions (fixed vregs,
+//! `Arg`/`Return` pseudos, hand-placed `Jmp`s), which the text parser
+//! and `FuncBuilder` don't give. Instead of creating blocks and vregs by
+//! hand and pushing one instruction per line:
+//!
+//! ```text
+//! tir! {
+//!     let func = "loop";
+//!     vregs v0, v1, x: Type::F64;
+//!     b0: [Mov64ri { dst: v0, imm: 0 }, Mov64ri { dst: v1, imm: 1 }, Jmp { dst: b1 }];
+//!     b1: [Add64rr { dst: v0, src: v1 }, Jmp { dst: b2 }];
+//!     b2: [Return { src: v0 }];
+//! }
+//! ```
+//!
+//! binds `func`, each vreg and each block in the enclosing scope. The
+//! `let` takes any pattern, so `let mut func` works. Vregs are numbered
+//! in the order listed (`vregs;` for none); a `name: ty` one gets that
+//! type, the rest the default. Blocks are created in order, the first
+//! being the entry. Instructions are `X64Inst` or `PseudoInstruction`
+//! variants, named bare; anything else they need (`Cond`, `Mem`, `Type`)
+//! comes from the caller.
+
+/// Build a `Func<X64Inst>`; see the module docs.
+#[macro_export]
+macro_rules! tir {
+    (@vreg $func:ident) => {
+        $func.new_vreg()
+    };
+    (@vreg $func:ident $ty:expr) => {
+        $func.new_typed_vreg($ty)
+    };
+    (
+        let $pat:pat = $name:expr;
+        vregs $($v:ident $(: $ty:expr)?),* ;
+        $($block:ident: [$($inst:expr),* $(,)?]);+ $(;)?
+    ) => {
+        // Blocks are often only named by the instructions inside.
+        #[allow(unused_variables)]
+        let ($pat, $($v,)* $($block,)+) = {
+            let mut func = $crate::codegen::tir::Func::<
+                $crate::codegen::isa::x64::inst::X64Inst,
+            >::new(::std::string::ToString::to_string($name));
+            $(let $v = $crate::tir!(@vreg func $($ty)?);)*
+            $(let $block = func.add_empty_block();)+
+            {
+                #[allow(unused_imports)]
+                use $crate::codegen::isa::x64::inst::X64Inst::*;
+                #[allow(unused_imports)]
+                use $crate::codegen::tir::PseudoInstruction::*;
+                $(
+                    func.get_block_data_mut($block).set_insts(vec![
+                        $($crate::codegen::tir::Instruction::from($inst)),*
+                    ]);
+                )+
+            }
+            (func, $($v,)* $($block,)+)
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+    use crate::codegen::tir::{Inst, Instruction, PseudoInstruction, Type};
+
+    #[test]
+    fn builds_blocks_vregs_and_both_instruction_kinds() {
+        tir! {
+            let mut func = "t";
+            vregs v0, x: Type::F64;
+            b0: [
+                Arg { dst: v0, idx: 0 },
+                Cmp64ri32 { lhs: v0, imm: 0 },
+                CondJmp { cond: Cond::NZ, taken: b1, not_taken: b1 },
+            ];
+            b1: [Return { src: v0 }];
+        }
+        assert_eq!(func.name(), "t");
+        assert_eq!((v0, x), (0, 1));
+        assert_eq!(func.vreg_type(x), Type::F64);
+        assert_eq!(func.blocks_count(), 2);
+        let b0_insts = func.get_block_data(b0).insts();
+        assert_eq!(b0_insts[0], Instruction::Pseudo(PseudoInstruction::Arg { dst: v0, idx: 0 }));
+        assert_eq!(b0_insts[2].get_branch_targets().as_slice(), [b1, b1]);
+        assert!(matches!(
+            func.get_block_data(b1).insts(),
+            [Instruction::Pseudo(PseudoInstruction::Return { src })] if *src == v0
+        ));
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::Ud2);
+    }
+}
@@ -1,6 +1,14 @@
 pub mod analysis;
+pub mod dot;
+pub mod error;
 pub mod isa;
 pub mod jit;
+pub mod module;
+pub mod object;
+pub mod options;
 pub mod passes;
 pub mod regalloc;
+pub mod stats;
+pub mod timing;
 pub mod tir;
+pub mod value_locations;
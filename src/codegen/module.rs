@@ -0,0 +1,448 @@
+//! A compilation unit: functions plus the data objects and function
+//! declarations they link with.
+//!
+//! Data objects are named byte blobs the object writer places in
+//! `.rodata`, `.data` or `.bss`. Initialized ones may hold absolute
+//! pointers to other symbols — functions or data, in this module or
+//! external — as relocations the linker fills in. Declared functions are
+//! call targets by `FuncRef`; imports become undefined symbols the call
+//! relocations bind to at link time.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+
+use crate::codegen::analysis::call_graph::CallGraph;
+use crate::codegen::error::CodegenError;
+use crate::codegen::tir::{Func, Inst, PrintOptions, SectionFlags, Type};
+use crate::slotmap_key;
+use crate::support::slotmap::Key;
+
+slotmap_key!(DataId(u32));
+slotmap_key!(FuncRef(u32));
+
+impl Display for DataId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "data#{}", self.0)
+    }
+}
+
+impl Debug for DataId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for FuncRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fn#{}", self.0)
+    }
+}
+
+impl Debug for FuncRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Where a function symbol is defined and who may see it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Linkage {
+    /// Defined outside the module, e.g. in libc or a runtime library.
+    Import,
+    /// Defined in the module and visible to the linker.
+    #[default]
+    Export,
+    /// Defined in the module and private to its object file.
+    Local,
+}
+
+/// Parameter and return types of a function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    /// `None` for a function returning nothing.
+    pub ret: Option<Type>,
+}
+
+/// A function the module calls or defines under a known signature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuncDecl {
+    pub name: String,
+    pub signature: Signature,
+    pub linkage: Linkage,
+}
+
+/// What a data object starts out holding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataContents {
+    /// These bytes.
+    Bytes(Vec<u8>),
+    /// This many zero bytes, taking no space in the object file.
+    Zeroed(usize),
+}
+
+/// An 8-byte absolute address of `symbol + addend`, written at `offset`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataReloc {
+    pub offset: usize,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// A named, aligned block of initialized or zeroed bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataObject {
+    pub name: String,
+    pub contents: DataContents,
+    /// Start alignment in bytes; a power of two.
+    pub align: u32,
+    /// Mutable at run time; read-only objects go to `.rodata`, or
+    /// `.data.rel.ro` when they hold relocations.
+    pub writable: bool,
+    /// Pointers into other symbols; only allowed in `Bytes` contents.
+    pub relocs: Vec<DataReloc>,
+    /// Object-file section, overriding the one `writable` and the
+    /// contents pick. Only sections named `.bss*` hold zeroed objects
+    /// without file space; `Bytes` may not go there.
+    pub section: Option<String>,
+    /// Flags of `section`; `aw` for writable objects, else `a`.
+    pub section_flags: Option<SectionFlags>,
+}
+
+/// Whether a section named `name` is zero-fill (`@nobits`), as GAS
+/// decides by default.
+#[must_use]
+pub fn is_bss_section(name: &str) -> bool {
+    name == ".bss" || name.starts_with(".bss.")
+}
+
+impl DataObject {
+    /// Read-only initialized data with no relocations.
+    #[must_use]
+    pub fn bytes(name: &str, bytes: Vec<u8>, align: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            contents: DataContents::Bytes(bytes),
+            align,
+            writable: false,
+            relocs: Vec::new(),
+            section: None,
+            section_flags: None,
+        }
+    }
+
+    /// Writable zero-initialized data.
+    #[must_use]
+    pub fn zeroed(name: &str, size: usize, align: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            contents: DataContents::Zeroed(size),
+            align,
+            writable: true,
+            relocs: Vec::new(),
+            section: None,
+            section_flags: None,
+        }
+    }
+
+    #[must_use]
+    pub fn size(&self) -> usize {
+        match &self.contents {
+            DataContents::Bytes(b) => b.len(),
+            DataContents::Zeroed(n) => *n,
+        }
+    }
+
+    /// Section the object writer places the object in.
+    #[must_use]
+    pub fn section_name(&self) -> &str {
+        if let Some(s) = &self.section {
+            return s;
+        }
+        match (&self.contents, self.writable) {
+            (DataContents::Zeroed(_), _) => ".bss",
+            (DataContents::Bytes(_), true) => ".data",
+            // Read-only after the dynamic linker applies relocations.
+            (DataContents::Bytes(_), false) if !self.relocs.is_empty() => ".data.rel.ro",
+            (DataContents::Bytes(_), false) => ".rodata",
+        }
+    }
+
+    /// Flags of the object's section.
+    #[must_use]
+    pub fn section_flags(&self) -> SectionFlags {
+        self.section_flags.unwrap_or(SectionFlags {
+            write: self.writable,
+            ..SectionFlags::default()
+        })
+    }
+
+    pub(crate) fn verify(&self) -> Result<(), CodegenError> {
+        if matches!(self.contents, DataContents::Bytes(_)) && is_bss_section(self.section_name()) {
+            return Err(CodegenError::BytesInBss {
+                name: self.name.clone(),
+                section: self.section_name().to_string(),
+            });
+        }
+        if !self.align.is_power_of_two() {
+            return Err(CodegenError::BadDataAlign {
+                name: self.name.clone(),
+                align: self.align,
+            });
+        }
+        let len = match &self.contents {
+            DataContents::Bytes(b) => b.len(),
+            DataContents::Zeroed(_) => 0,
+        };
+        match self.relocs.iter().find(|r| r.offset.checked_add(8).is_none_or(|end| end > len)) {
+            Some(r) => Err(CodegenError::DataRelocOutOfBounds {
+                name: self.name.clone(),
+                offset: r.offset,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Everything a module holds besides function bodies: what the object
+/// writer needs next to the compiled code.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleDecls {
+    /// Data objects, in definition order.
+    pub data: Vec<DataObject>,
+    /// Function declarations, indexed by `FuncRef`.
+    pub funcs: Vec<FuncDecl>,
+}
+
+impl ModuleDecls {
+    /// Linkage of the function named `name`: as declared, else `Export`.
+    #[must_use]
+    pub fn linkage(&self, name: &str) -> Linkage {
+        self.funcs
+            .iter()
+            .find(|d| d.name == name)
+            .map_or(Linkage::Export, |d| d.linkage)
+    }
+}
+
+/// Functions, data objects and function declarations compiled and linked
+/// as one unit. Symbol names are unique across functions and data; a
+/// defined function may also be declared, but not as an `Import`.
+pub struct Module<I: Inst> {
+    funcs: Vec<Func<I>>,
+    decls: ModuleDecls,
+}
+
+impl<I: Inst> Default for Module<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Inst> Module<I> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            funcs: Vec::new(),
+            decls: ModuleDecls::default(),
+        }
+    }
+
+    fn check_unique(&self, name: &str) -> Result<(), CodegenError> {
+        let taken = self.funcs.iter().any(|f| f.name() == name)
+            || self.decls.data.iter().any(|d| d.name == name);
+        if taken {
+            return Err(CodegenError::DuplicateSymbol(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Add a function body, rejecting a name already defined or declared
+    /// as an import.
+    pub fn add_func(&mut self, func: Func<I>) -> Result<(), CodegenError> {
+        self.check_unique(func.name())?;
+        if self.decls.linkage(func.name()) == Linkage::Import {
+            return Err(CodegenError::DuplicateSymbol(func.name().to_string()));
+        }
+        self.funcs.push(func);
+        Ok(())
+    }
+
+    /// Declare `name` as a call target. Declaring the same function again
+    /// returns its existing ref; a different signature or linkage, or an
+    /// import of something the module defines, is an error.
+    pub fn declare_function(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        linkage: Linkage,
+    ) -> Result<FuncRef, CodegenError> {
+        if let Some(i) = self.decls.funcs.iter().position(|d| d.name == name) {
+            let d = &self.decls.funcs[i];
+            if d.signature != signature || d.linkage != linkage {
+                return Err(CodegenError::ConflictingDeclaration(name.to_string()));
+            }
+            return Ok(FuncRef::new(i));
+        }
+        let defined = self.funcs.iter().any(|f| f.name() == name);
+        if self.decls.data.iter().any(|d| d.name == name) || (defined && linkage == Linkage::Import)
+        {
+            return Err(CodegenError::DuplicateSymbol(name.to_string()));
+        }
+        self.decls.funcs.push(FuncDecl {
+            name: name.to_string(),
+            signature,
+            linkage,
+        });
+        Ok(FuncRef::new(self.decls.funcs.len() - 1))
+    }
+
+    /// Add `obj`, rejecting a non-power-of-two alignment or a relocation
+    /// that doesn't fit inside its initialized bytes.
+    pub fn define_data(&mut self, obj: DataObject) -> Result<DataId, CodegenError> {
+        self.check_unique(&obj.name)?;
+        if self.decls.funcs.iter().any(|d| d.name == obj.name) {
+            return Err(CodegenError::DuplicateSymbol(obj.name));
+        }
+        obj.verify()?;
+        self.decls.data.push(obj);
+        Ok(DataId::new(self.decls.data.len() - 1))
+    }
+
+    #[must_use]
+    pub fn funcs(&self) -> &[Func<I>] {
+        &self.funcs
+    }
+
+    #[must_use]
+    pub fn data(&self, id: DataId) -> &DataObject {
+        &self.decls.data[id.index()]
+    }
+
+    #[must_use]
+    pub fn func_decl(&self, f: FuncRef) -> &FuncDecl {
+        &self.decls.funcs[f.index()]
+    }
+
+    #[must_use]
+    pub fn decls(&self) -> &ModuleDecls {
+        &self.decls
+    }
+
+    /// Call graph over the module's function bodies, indexed like `funcs()`.
+    #[must_use]
+    pub fn call_graph(&self) -> CallGraph {
+        CallGraph::compute(&self.funcs)
+    }
+
+    /// Write every function's listing to `w`, each followed by a blank
+    /// line.
+    pub fn write_to(&self, w: &mut impl io::Write, opts: &PrintOptions) -> io::Result<()> {
+        for func in &self.funcs {
+            func.write_to(w, opts)?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Functions to compile and the declarations to write next to them.
+    #[must_use]
+    pub fn into_parts(self) -> (Vec<Func<I>>, ModuleDecls) {
+        (self.funcs, self.decls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::X64Inst;
+
+    #[test]
+    fn data_objects_are_checked_and_pick_their_section() {
+        let mut m: Module<X64Inst> = Module::new();
+        let mut b = FuncBuilder::new("main");
+        let x = b.arg();
+        b.ret(x);
+        m.add_func(b.build()).expect("fresh name");
+
+        let table = m
+            .define_data(DataObject {
+                relocs: vec![DataReloc {
+                    offset: 8,
+                    symbol: "main".into(),
+                    addend: 0,
+                }],
+                writable: true,
+                ..DataObject::bytes("table", vec![0; 16], 8)
+            })
+            .expect("valid object");
+        let msg = m.define_data(DataObject::bytes("msg", b"hi\0".to_vec(), 1)).expect("valid");
+        let buf = m.define_data(DataObject::zeroed("buf", 4096, 64)).expect("valid");
+        assert_eq!(m.data(table).section_name(), ".data");
+        assert_eq!(m.data(msg).section_name(), ".rodata");
+        assert_eq!(m.data(buf).section_name(), ".bss");
+        assert_eq!(m.data(buf).size(), 4096);
+
+        assert!(matches!(
+            m.define_data(DataObject::zeroed("main", 8, 8)),
+            Err(CodegenError::DuplicateSymbol(n)) if n == "main"
+        ));
+        assert!(matches!(
+            m.define_data(DataObject::bytes("odd", vec![0; 8], 3)),
+            Err(CodegenError::BadDataAlign { align: 3, .. })
+        ));
+        let past_end = DataObject {
+            relocs: vec![DataReloc {
+                offset: 4,
+                symbol: "main".into(),
+                addend: 0,
+            }],
+            ..DataObject::bytes("short", vec![0; 8], 8)
+        };
+        assert!(matches!(
+            m.define_data(past_end),
+            Err(CodegenError::DataRelocOutOfBounds { offset: 4, .. })
+        ));
+        let misplaced = DataObject {
+            section: Some(".bss.init".into()),
+            ..DataObject::bytes("init", vec![1; 8], 8)
+        };
+        assert!(matches!(m.define_data(misplaced), Err(CodegenError::BytesInBss { .. })));
+        assert_eq!(m.decls().data.len(), 3);
+    }
+
+    #[test]
+    fn declarations_are_deduplicated_and_checked_against_definitions() {
+        let mut m: Module<X64Inst> = Module::new();
+        let sig = Signature {
+            params: vec![Type::Ptr],
+            ret: Some(Type::I32),
+        };
+        let puts = m.declare_function("puts", sig.clone(), Linkage::Import).expect("fresh");
+        assert_eq!(m.declare_function("puts", sig.clone(), Linkage::Import).ok(), Some(puts));
+        assert!(matches!(
+            m.declare_function("puts", Signature::default(), Linkage::Import),
+            Err(CodegenError::ConflictingDeclaration(_))
+        ));
+        assert_eq!(m.func_decl(puts).signature, sig);
+
+        let mut b = FuncBuilder::new("puts");
+        let x = b.arg();
+        b.ret(x);
+        assert!(matches!(m.add_func(b.build()), Err(CodegenError::DuplicateSymbol(_))));
+
+        let helper = m.declare_function("helper", Signature::default(), Linkage::Local);
+        let mut b = FuncBuilder::new("helper");
+        let x = b.arg();
+        b.ret(x);
+        m.add_func(b.build()).expect("declared locally, defined once");
+        assert!(helper.is_ok());
+        assert_eq!(m.decls().linkage("helper"), Linkage::Local);
+        assert_eq!(m.decls().linkage("undeclared"), Linkage::Export);
+        assert!(matches!(
+            m.declare_function("helper", Signature::default(), Linkage::Import),
+            Err(CodegenError::ConflictingDeclaration(_))
+        ));
+    }
+}
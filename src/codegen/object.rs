@@ -0,0 +1,524 @@
+//! Relocatable object-file output.
+//!
+//! Packs compiled functions into one ELF `.o`: each function becomes a
+//! global text symbol in the section its attributes name — `.text`,
+//! `.text.unlikely` for cold code, or an explicit `section(...)` — aligned
+//! to `CompiledCode::align`, and each call-site relocation becomes an
+//! absolute 64-bit relocation against its callee — a function from the
+//! same batch, or an undefined symbol for the linker to resolve. Data
+//! objects become global data symbols in the section
+//! `DataObject::section_name` picks, their pointers relocated the same
+//! way. Sections carry the flags their first occupant asks for; a later
+//! occupant asking for different ones is an error.
+//!
+//! An object whose functions were all compiled with `endbr64` pads gets a
+//! `.note.gnu.property` marking it IBT and, unless it uses retpolines,
+//! shadow-stack compatible, so a CET-enforcing linker and loader keep the
+//! protection on.
+
+use std::collections::HashMap;
+
+use object::write::{
+    Object, Relocation, SectionId, StandardSection, StandardSegment, Symbol, SymbolId,
+    SymbolSection,
+};
+use object::{
+    Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationFlags, RelocationKind,
+    SectionKind, SymbolFlags, SymbolKind, SymbolScope, elf,
+};
+
+use crate::codegen::error::CodegenError;
+use crate::codegen::isa::Target;
+use crate::codegen::isa::x64::pipeline::CompiledCode;
+use crate::codegen::module::{DataContents, DataObject, Linkage, ModuleDecls, is_bss_section};
+use crate::codegen::tir::SectionFlags;
+
+/// Serialize `funcs` and `decls`' data objects, then the functions'
+/// coverage counter tables, as a relocatable object for `target`.
+/// Functions `decls` declares `Local` get file-local symbols.
+pub fn write_object(
+    target: Target,
+    funcs: &[CompiledCode],
+    decls: &ModuleDecls,
+) -> Result<Vec<u8>, CodegenError> {
+    let data: Vec<&DataObject> =
+        decls.data.iter().chain(funcs.iter().filter_map(|f| f.coverage.as_ref())).collect();
+    let (format, arch) = match target {
+        Target::X64SysV => (BinaryFormat::Elf, Architecture::X86_64),
+    };
+    let mut obj = Object::new(format, arch, Endianness::Little);
+    let mut sections = Sections::default();
+    // Without this marker, linkers assume the object needs an executable
+    // stack.
+    obj.add_section(
+        Vec::new(),
+        b".note.GNU-stack".to_vec(),
+        SectionKind::Elf(elf::SHT_PROGBITS),
+    );
+    if !funcs.is_empty() && funcs.iter().all(|f| f.endbr) {
+        let shstk = if funcs.iter().any(|f| f.retpolines) {
+            0
+        } else {
+            elf::GNU_PROPERTY_X86_FEATURE_1_SHSTK
+        };
+        obj.add_elf_gnu_property_u32(
+            elf::GNU_PROPERTY_X86_FEATURE_1_AND,
+            elf::GNU_PROPERTY_X86_FEATURE_1_IBT | shstk,
+        );
+    }
+
+    // Define every function before resolving relocations so calls
+    // between them bind locally instead of to undefined imports.
+    let mut placed = Vec::with_capacity(funcs.len());
+    for code in funcs {
+        let section =
+            sections.get(&mut obj, code.attrs.section_name(), code.attrs.section_flags())?;
+        let align = u64::from(code.align);
+        let sym = obj.add_symbol(Symbol {
+            name: code.name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: match decls.linkage(&code.name) {
+                Linkage::Local => SymbolScope::Compilation,
+                Linkage::Import | Linkage::Export => SymbolScope::Dynamic,
+            },
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        placed.push((section, obj.add_symbol_data(sym, section, &code.bytes, align)));
+    }
+
+    let mut placed_data = Vec::with_capacity(data.len());
+    for &d in &data {
+        d.verify()?;
+        let name = d.section_name();
+        let section = sections.get(&mut obj, name, d.section_flags())?;
+        let sym = obj.add_symbol(Symbol {
+            name: d.name.as_bytes().to_vec(),
+            value: 0,
+            size: d.size() as u64,
+            kind: SymbolKind::Data,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        let align = u64::from(d.align);
+        let base = match &d.contents {
+            DataContents::Bytes(b) => obj.add_symbol_data(sym, section, b, align),
+            DataContents::Zeroed(n) if is_bss_section(name) => {
+                obj.add_symbol_bss(sym, section, *n as u64, align)
+            }
+            // Zeroed objects placed among initialized data take file space.
+            DataContents::Zeroed(n) => obj.add_symbol_data(sym, section, &vec![0; *n], align),
+        };
+        placed_data.push((section, base));
+    }
+
+    for (code, (section, base)) in funcs.iter().zip(placed) {
+        for reloc in &code.relocations {
+            add_abs64(&mut obj, section, base + reloc.offset as u64, &reloc.symbol, 0)?;
+        }
+    }
+    for (d, (section, base)) in data.into_iter().zip(placed_data) {
+        for reloc in &d.relocs {
+            add_abs64(&mut obj, section, base + reloc.offset as u64, &reloc.symbol, reloc.addend)?;
+        }
+    }
+    Ok(obj.write()?)
+}
+
+/// Sections created so far, by name, with the flags they were created
+/// with.
+#[derive(Default)]
+struct Sections {
+    ids: HashMap<String, (SectionId, SectionFlags)>,
+}
+
+impl Sections {
+    /// The section `name`, created on first use. Standard names with
+    /// their usual flags map to the writer's standard sections; anything
+    /// else gets exactly `flags`.
+    fn get(
+        &mut self,
+        obj: &mut Object<'_>,
+        name: &str,
+        flags: SectionFlags,
+    ) -> Result<SectionId, CodegenError> {
+        if let Some(&(id, first)) = self.ids.get(name) {
+            if first != flags {
+                return Err(CodegenError::SectionFlagsConflict {
+                    section: name.to_string(),
+                    first,
+                    second: flags,
+                });
+            }
+            return Ok(id);
+        }
+        let aw = SectionFlags {
+            write: true,
+            ..SectionFlags::default()
+        };
+        let standard = match name {
+            ".text" if flags == SectionFlags::CODE => Some(StandardSection::Text),
+            ".data" if flags == aw => Some(StandardSection::Data),
+            ".data.rel.ro" if flags == aw => Some(StandardSection::ReadOnlyDataWithRel),
+            ".bss" if flags == aw => Some(StandardSection::UninitializedData),
+            ".rodata" if flags == SectionFlags::default() => Some(StandardSection::ReadOnlyData),
+            _ => None,
+        };
+        let id = if let Some(std) = standard {
+            obj.section_id(std)
+        } else {
+            let (segment, kind) = if is_bss_section(name) {
+                (StandardSegment::Data, SectionKind::UninitializedData)
+            } else if flags.exec {
+                (StandardSegment::Text, SectionKind::Text)
+            } else if flags.write {
+                (StandardSegment::Data, SectionKind::Data)
+            } else {
+                (StandardSegment::Data, SectionKind::ReadOnlyData)
+            };
+            let segment = obj.segment_name(segment).to_vec();
+            let id = obj.add_section(segment, name.as_bytes().to_vec(), kind);
+            let mut sh_flags = elf::SHF_ALLOC;
+            for (set, bit) in [
+                (flags.write, elf::SHF_WRITE),
+                (flags.exec, elf::SHF_EXECINSTR),
+                (flags.retain, elf::SHF_GNU_RETAIN),
+            ] {
+                if set {
+                    sh_flags |= bit;
+                }
+            }
+            obj.section_mut(id).flags = object::SectionFlags::Elf {
+                sh_flags: u64::from(sh_flags),
+            };
+            id
+        };
+        self.ids.insert(name.to_string(), (id, flags));
+        Ok(id)
+    }
+}
+
+/// Relocate the 8 bytes at `offset` in `section` to `symbol + addend`.
+fn add_abs64(
+    obj: &mut Object<'_>,
+    section: SectionId,
+    offset: u64,
+    symbol: &str,
+    addend: i64,
+) -> object::write::Result<()> {
+    let symbol = symbol_for(obj, symbol);
+    obj.add_relocation(
+        section,
+        Relocation {
+            offset,
+            symbol,
+            addend,
+            flags: RelocationFlags::Generic {
+                kind: RelocationKind::Absolute,
+                encoding: RelocationEncoding::Generic,
+                size: 64,
+            },
+        },
+    )
+}
+
+/// The symbol named `name`, declaring it undefined on first use.
+fn symbol_for(obj: &mut Object<'_>, name: &str) -> SymbolId {
+    obj.symbol_id(name.as_bytes()).unwrap_or_else(|| {
+        obj.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Unknown,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::isa::x64::pipeline::compile_full;
+    use crate::codegen::tir::FuncAttrs;
+
+    #[test]
+    fn object_holds_every_function_and_its_callees() {
+        let mut b = FuncBuilder::new("twice");
+        let x = b.arg();
+        let s = b.add(x, x);
+        b.ret(s);
+        let twice = compile_full(b.build());
+
+        let mut b = FuncBuilder::new("caller");
+        let x = b.arg();
+        let r = b.call_sym("twice", &[x]);
+        let r = b.call_sym("puts", &[r]);
+        b.ret(r);
+        let caller = compile_full(b.build());
+        assert_eq!(caller.relocations.len(), 2);
+
+        let bytes = write_object(Target::X64SysV, &[twice, caller], &ModuleDecls::default()).expect("object writes");
+        assert_eq!(&bytes[..4], b"\x7fELF");
+        for name in [&b"twice"[..], b"caller", b"puts"] {
+            assert!(bytes.windows(name.len()).any(|w| w == name));
+        }
+    }
+
+    #[test]
+    fn attributes_pick_the_section_and_alignment() {
+        use object::{Object as _, ObjectSection, ObjectSymbol};
+
+        let compile = |name: &str, attrs: FuncAttrs| {
+            let mut b = FuncBuilder::new(name);
+            let x = b.arg();
+            b.ret(x);
+            let mut func = b.build();
+            *func.attrs_mut() = attrs;
+            compile_full(func)
+        };
+        let funcs = [
+            compile("hot", FuncAttrs::default()),
+            compile(
+                "unlikely",
+                FuncAttrs {
+                    cold: true,
+                    ..FuncAttrs::default()
+                },
+            ),
+            compile(
+                "placed",
+                FuncAttrs {
+                    cold: true,
+                    align: Some(64),
+                    section: Some(".text.placed".into()),
+                    ..FuncAttrs::default()
+                },
+            ),
+        ];
+        let bytes = write_object(Target::X64SysV, &funcs, &ModuleDecls::default()).expect("object writes");
+        let file = object::File::parse(&*bytes).expect("object parses");
+        let section_of = |sym: &str| {
+            let s = file.symbol_by_name(sym).expect("symbol defined");
+            let idx = s.section_index().expect("symbol in a section");
+            let sec = file.section_by_index(idx).expect("section exists");
+            (sec.name().expect("utf-8 name").to_string(), sec.align())
+        };
+        assert_eq!(section_of("hot"), (".text".into(), 16));
+        assert_eq!(section_of("unlikely"), (".text.unlikely".into(), 16));
+        assert_eq!(section_of("placed"), (".text.placed".into(), 64));
+    }
+
+    #[test]
+    fn data_objects_land_in_their_sections_with_relocations() {
+        use crate::codegen::module::{DataObject, DataReloc};
+        use object::{Object as _, ObjectSection, ObjectSymbol, RelocationTarget};
+
+        let mut b = FuncBuilder::new("f");
+        let x = b.arg();
+        b.ret(x);
+        let f = compile_full(b.build());
+        let data = [
+            DataObject::bytes("msg", b"hello\0".to_vec(), 1),
+            DataObject {
+                relocs: vec![
+                    DataReloc {
+                        offset: 0,
+                        symbol: "f".into(),
+                        addend: 0,
+                    },
+                    DataReloc {
+                        offset: 8,
+                        symbol: "msg".into(),
+                        addend: 2,
+                    },
+                ],
+                writable: true,
+                ..DataObject::bytes("table", vec![0; 16], 8)
+            },
+            DataObject::zeroed("buf", 256, 32),
+        ];
+        let decls = ModuleDecls {
+            data: data.to_vec(),
+            ..ModuleDecls::default()
+        };
+        let bytes = write_object(Target::X64SysV, &[f], &decls).expect("object writes");
+        let file = object::File::parse(&*bytes).expect("object parses");
+        let placed = |sym: &str| {
+            let s = file.symbol_by_name(sym).expect("symbol defined");
+            let sec = file
+                .section_by_index(s.section_index().expect("symbol in a section"))
+                .expect("section exists");
+            (sec.name().expect("utf-8 name").to_string(), s.size(), sec.align())
+        };
+        assert_eq!(placed("msg"), (".rodata".into(), 6, 1));
+        assert_eq!(placed("table"), (".data".into(), 16, 8));
+        assert_eq!(placed("buf"), (".bss".into(), 256, 32));
+
+        let data_sec = file.section_by_name(".data").expect(".data exists");
+        let relocs: Vec<(u64, String, i64)> = data_sec
+            .relocations()
+            .map(|(off, r)| {
+                let RelocationTarget::Symbol(idx) = r.target() else {
+                    panic!("symbol relocation expected");
+                };
+                let name = file.symbol_by_index(idx).expect("symbol").name().expect("utf-8");
+                (off, name.to_string(), r.addend())
+            })
+            .collect();
+        assert_eq!(relocs, [(0, "f".into(), 0), (8, "msg".into(), 2)]);
+    }
+
+    #[test]
+    fn declared_functions_bind_calls_and_pick_symbol_scope() {
+        use crate::codegen::module::{Module, Signature};
+        use crate::codegen::tir::Type;
+        use object::{Object as _, ObjectSymbol};
+
+        let mut m: Module<X64Inst> = Module::new();
+        let sig = Signature {
+            params: vec![Type::I64],
+            ret: Some(Type::I64),
+        };
+        let abs = m.declare_function("labs", sig.clone(), Linkage::Import).expect("fresh");
+        let helper = m.declare_function("helper", sig, Linkage::Local).expect("fresh");
+
+        let mut b = FuncBuilder::new("helper");
+        let x = b.arg();
+        b.ret(x);
+        m.add_func(b.build()).expect("declared locally");
+        let mut b = FuncBuilder::new("entry");
+        let x = b.arg();
+        let r = b.call(&m, abs, &[x]);
+        let r = b.call(&m, helper, &[r]);
+        b.ret(r);
+        m.add_func(b.build()).expect("fresh name");
+
+        let (funcs, decls) = m.into_parts();
+        let compiled: Vec<CompiledCode> = funcs.into_iter().map(compile_full).collect();
+        let relocs: Vec<&str> = compiled[1].relocations.iter().map(|r| r.symbol.as_str()).collect();
+        assert_eq!(relocs, ["labs", "helper"]);
+
+        let bytes = write_object(Target::X64SysV, &compiled, &decls).expect("object writes");
+        let file = object::File::parse(&*bytes).expect("object parses");
+        let sym = |name: &str| file.symbol_by_name(name).expect("symbol present");
+        assert!(sym("labs").is_undefined());
+        assert!(sym("helper").is_local());
+        assert!(sym("entry").is_global());
+    }
+
+    #[test]
+    fn cet_objects_carry_the_ibt_and_shstk_properties() {
+        use crate::codegen::isa::x64::pipeline::compile_function;
+        use crate::codegen::options::{CodegenOptions, SpeculationHardening};
+        use object::{Object as _, ObjectSection};
+
+        let compile = |name: &str, cet: bool| {
+            let mut b = FuncBuilder::new(name);
+            let x = b.arg();
+            b.ret(x);
+            let options = CodegenOptions { cet, ..CodegenOptions::default() };
+            compile_function(b.build(), Target::X64SysV, &options)
+        };
+        let retpolined = |name: &str| {
+            let mut b = FuncBuilder::new(name);
+            let x = b.arg();
+            b.ret(x);
+            let options = CodegenOptions {
+                cet: true,
+                speculation_hardening: SpeculationHardening::Retpoline,
+                ..CodegenOptions::default()
+            };
+            compile_function(b.build(), Target::X64SysV, &options)
+        };
+        let note = |funcs: &[CompiledCode]| {
+            let bytes = write_object(Target::X64SysV, funcs, &ModuleDecls::default())
+                .expect("object writes");
+            let file = object::File::parse(&*bytes).expect("object parses");
+            file.section_by_name(".note.gnu.property")
+                .map(|sec| sec.data().expect("note data").to_vec())
+        };
+        let data = note(&[compile("a", true), compile("b", true)]).expect("marked");
+        // The property: `GNU_PROPERTY_X86_FEATURE_1_AND`, 4 bytes, IBT | SHSTK.
+        let property = [0xc000_0002u32, 4, 3].map(u32::to_le_bytes).concat();
+        assert!(data.windows(property.len()).any(|w| w == property), "{data:x?}");
+        assert_eq!(note(&[compile("a", true), compile("b", false)]), None);
+        // A retpoline's `ret` would trip a shadow stack: IBT only.
+        let data = note(&[compile("a", true), retpolined("b")]).expect("marked");
+        let property = [0xc000_0002u32, 4, 1].map(u32::to_le_bytes).concat();
+        assert!(data.windows(property.len()).any(|w| w == property), "{data:x?}");
+    }
+
+    #[test]
+    fn custom_sections_carry_their_flags() {
+        use crate::codegen::module::DataObject;
+        use object::{Object as _, ObjectSection, SectionKind as ReadKind};
+
+        let mut b = FuncBuilder::new("hook");
+        let x = b.arg();
+        b.ret(x);
+        let mut func = b.build();
+        func.attrs_mut().section = Some("hooks".into());
+        func.attrs_mut().section_flags = SectionFlags::parse("axR");
+        let hook = compile_full(func);
+        let decls = ModuleDecls {
+            data: vec![
+                DataObject {
+                    section: Some(".data.hot".into()),
+                    writable: true,
+                    ..DataObject::bytes("counter", vec![0; 8], 8)
+                },
+                DataObject {
+                    section: Some(".bss.big".into()),
+                    ..DataObject::zeroed("arena", 1 << 16, 4096)
+                },
+                DataObject {
+                    section: Some("consts".into()),
+                    ..DataObject::zeroed("zeros", 16, 8)
+                },
+            ],
+            ..ModuleDecls::default()
+        };
+        let bytes = write_object(Target::X64SysV, &[hook], &decls).expect("object writes");
+        let file = object::File::parse(&*bytes).expect("object parses");
+        let flags = |name: &str| {
+            let sec = file.section_by_name(name).expect("section exists");
+            let object::SectionFlags::Elf { sh_flags } = sec.flags() else {
+                panic!("ELF flags expected");
+            };
+            (sh_flags as u32, sec.kind())
+        };
+        let (a, w, x, r) = (elf::SHF_ALLOC, elf::SHF_WRITE, elf::SHF_EXECINSTR, elf::SHF_GNU_RETAIN);
+        assert_eq!(flags("hooks"), (a | x | r, ReadKind::Text));
+        assert_eq!(flags(".data.hot"), (a | w, ReadKind::Data));
+        assert_eq!(flags(".bss.big"), (a | w, ReadKind::UninitializedData));
+        // Zeroed, but in a section that isn't zero-fill: stored as bytes.
+        assert_eq!(flags("consts"), (a | w, ReadKind::Data));
+        assert_eq!(file.section_by_name("consts").expect("exists").size(), 16);
+
+        let clash = ModuleDecls {
+            data: vec![DataObject {
+                section: Some(".text".into()),
+                ..DataObject::bytes("table", vec![0; 8], 8)
+            }],
+            ..ModuleDecls::default()
+        };
+        let mut b = FuncBuilder::new("f");
+        let x = b.arg();
+        b.ret(x);
+        let f = compile_full(b.build());
+        assert!(matches!(
+            write_object(Target::X64SysV, &[f], &clash),
+            Err(CodegenError::SectionFlagsConflict { .. })
+        ));
+    }
+}
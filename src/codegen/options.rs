@@ -0,0 +1,169 @@
+//! Pipeline-wide codegen knobs, threaded through `compile_function`.
+
+use std::path::PathBuf;
+
+use crate::codegen::isa::x64::features::CpuFeatures;
+
+/// How hard the pipeline works on code quality. Optional cleanup passes
+/// run only above `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OptLevel {
+    /// Required passes only; fastest compile.
+    None,
+    #[default]
+    Default,
+    /// The `Default` passes, with every speed-for-size trade turned the
+    /// other way: no tail duplication and only tiny inlines, `cmov` for
+    /// every select, switches lowered to whichever shape is smaller,
+    /// shorter immediate encodings and no function alignment padding.
+    Size,
+}
+
+/// Register allocator `compile_function` runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RegAllocKind {
+    #[default]
+    LinearScan,
+    /// The `regalloc2` crate's allocator, through `regalloc::ra2`.
+    #[cfg(feature = "regalloc2")]
+    Regalloc2,
+}
+
+/// How `Switch` terminators are lowered. `Auto` picks per switch by case
+/// count and density; the others force one shape, e.g. to test it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SwitchLowering {
+    #[default]
+    Auto,
+    /// Bounds check plus an indirect jump through a table of block
+    /// offsets. Switches whose case range is too wide for a table still
+    /// get a compare tree.
+    JumpTable,
+    /// Balanced binary search over the case values.
+    CompareTree,
+}
+
+/// How indirect calls and jumps are hardened against branch-target
+/// injection (Spectre v2), for kernels and hypervisors that require it.
+/// Calls to symbols are indirect too: they go through `r11`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SpeculationHardening {
+    #[default]
+    Off,
+    /// Branch through a per-register retpoline thunk, whose `ret` only
+    /// ever speculates into a `pause; lfence` loop. Incompatible with
+    /// shadow stacks.
+    Retpoline,
+    /// `lfence` right before each indirect branch, so it isn't predicted
+    /// until its target register is resolved.
+    Lfence,
+}
+
+/// Whether functions set up `rbp` as a frame pointer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum FramePointer {
+    /// Every function pushes `rbp` and points it at its frame, so
+    /// profilers and debuggers can walk the stack through it.
+    #[default]
+    Always,
+    /// Leaf functions skip `push rbp; mov rbp, rsp` and address their
+    /// frame off `rsp`, which doesn't move while they run. Functions that
+    /// call keep the frame pointer.
+    Omit,
+}
+
+/// Options controlling how `compile_function` compiles a function. The
+/// `Default` value reproduces the fixed pipeline.
+#[derive(Clone, Debug, Hash)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CodegenOptions {
+    pub opt_level: OptLevel,
+    /// Bias `Copy` destinations onto their source's register so the move
+    /// is erased. Off leaves every copy as a real `mov`.
+    pub coalesce: bool,
+    pub regalloc: RegAllocKind,
+    /// Decide spills up front with `regalloc::spill::plan_spills`, from
+    /// next-use distances and loop structure, and leave the allocator only
+    /// values that fit.
+    pub plan_spills: bool,
+    pub switch_lowering: SwitchLowering,
+    pub speculation_hardening: SpeculationHardening,
+    pub frame_pointer: FramePointer,
+    /// CPU features the code may use. Defaults to `CpuFeatures::V2`, what
+    /// the backend has always assumed; `BASELINE` swaps in SSE2 fallbacks
+    /// and rejects what has none.
+    pub cpu_features: CpuFeatures,
+    /// Allocate frames larger than the 4 KiB guard page a page at a time,
+    /// touching each, so deep frames fault on the guard instead of
+    /// skipping it.
+    pub stack_probes: bool,
+    /// Let a function that calls nothing keep a frame of up to 128 bytes
+    /// in the SysV red zone below `rsp` rather than moving `rsp`. Turn off
+    /// for code whose stack interrupts may write below `rsp`, like a
+    /// kernel's.
+    pub red_zone: bool,
+    /// Guard functions with `StackAlloc` buffers with a canary checked
+    /// before every return. `Some(handler)` turns it on and names the
+    /// symbol called, with no arguments, when the canary was clobbered;
+    /// normally `__stack_chk_fail`.
+    pub stack_protector: Option<String>,
+    /// Intel CET: open every function and indirect-branch target with
+    /// `endbr64`, and mark objects built only from such functions as
+    /// IBT and shadow-stack compatible.
+    pub cet: bool,
+    /// Count entries into every block in a per-function counter table
+    /// (`CompiledCode::coverage`), for coverage and profile collection.
+    pub coverage: bool,
+    /// Run the structural IR verifier after every IR-rewriting pass and
+    /// panic with the pass name on the first violation.
+    pub verify: bool,
+    /// Replay the register allocation symbolically and panic if any use
+    /// reads a location that no longer holds its vreg.
+    pub check_regalloc: bool,
+    /// Record per-pass wall time into `CompiledCode::timings`.
+    pub time_passes: bool,
+    /// Print the function after every IR-rewriting pass, tagged with the
+    /// pass name. Also enabled by the `LANCY_PRINT_AFTER_ALL` env var.
+    pub print_after_all: bool,
+    /// Write the `print_after_all` dumps to `<dir>/<func>.<NN>.<pass>.tir`
+    /// instead of stderr.
+    pub dump_dir: Option<PathBuf>,
+    /// Write GraphViz dumps of the CFG, dominator tree and interference
+    /// graph as the allocator saw them to `<dir>/<func>.<graph>.dot`, with
+    /// `dir` the `dump_dir` or the current directory.
+    pub dump_dot: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::default(),
+            coalesce: true,
+            regalloc: RegAllocKind::default(),
+            plan_spills: false,
+            switch_lowering: SwitchLowering::default(),
+            speculation_hardening: SpeculationHardening::default(),
+            frame_pointer: FramePointer::default(),
+            cpu_features: CpuFeatures::V2,
+            stack_probes: true,
+            red_zone: true,
+            stack_protector: None,
+            cet: false,
+            coverage: false,
+            verify: cfg!(debug_assertions),
+            check_regalloc: cfg!(debug_assertions),
+            time_passes: false,
+            print_after_all: false,
+            dump_dir: None,
+            dump_dot: false,
+        }
+    }
+}
+
+impl CodegenOptions {
+    /// Whether after-pass dumps are on, via the flag or the env var.
+    #[must_use]
+    pub fn print_after_all_enabled(&self) -> bool {
+        self.print_after_all || std::env::var_os("LANCY_PRINT_AFTER_ALL").is_some()
+    }
+}
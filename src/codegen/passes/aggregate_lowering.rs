@@ -2,10 +2,16 @@
 //! by tracking each aggregate vreg's current element list and
 //! rewriting `ExtractValue` into a scalar `Copy`. Must run before
 //! regalloc — aggregate vregs carry no machine value.
+//!
+//! By-value aggregates crossing a call boundary are left for ABI lowering:
+//! an aggregate passed to a call or returned gets a fresh `MakeAggregate`
+//! listing its current elements right before the use, and the elements of
+//! an aggregate `Arg` or call result are the ones its `Agg` type lists,
+//! defined later by ABI lowering.
 
 use std::collections::HashMap;
 
-use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg};
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg, Type};
 
 /// Lower aggregate pseudos in place. See module docs for the contract.
 pub fn lower_aggregates<I: Inst>(func: &mut Func<I>) {
@@ -18,8 +24,8 @@ pub fn lower_aggregates<I: Inst>(func: &mut Func<I>) {
     let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
     for block in blocks {
         let old = func.get_block_data_mut(block).take_insts();
-        let mut new: Vec<Instruction<I>> = Vec::with_capacity(old.len());
-        for inst in old {
+        let mut new = func.inst_buffer(old.len());
+        for &inst in &old {
             match inst {
                 Instruction::Pseudo(PseudoInstruction::MakeAggregate { dst, id }) => {
                     let v = func.aggregate_operands(id).elems.clone();
@@ -58,13 +64,59 @@ pub fn lower_aggregates<I: Inst>(func: &mut Func<I>) {
                     let src = v[i];
                     new.push(Instruction::Pseudo(PseudoInstruction::Copy { dst, src }));
                 }
+                Instruction::Pseudo(PseudoInstruction::Arg { dst, .. }) => {
+                    if let Type::Agg(id) = func.vreg_type(dst) {
+                        elems.insert(dst, func.aggregate_operands(id).elems.clone());
+                    }
+                    new.push(inst);
+                }
+                Instruction::Pseudo(PseudoInstruction::Return { src })
+                    if func.vreg_type(src).is_aggregate() =>
+                {
+                    let src = rebuild(func, &mut new, &elems, src);
+                    new.push(Instruction::Pseudo(PseudoInstruction::Return { src }));
+                }
+                Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) => {
+                    let data = func.call_operands(id).clone();
+                    for (i, &arg) in data.args.iter().enumerate() {
+                        if func.vreg_type(arg).is_aggregate() {
+                            let arg = rebuild(func, &mut new, &elems, arg);
+                            func.call_operands_mut(id).args[i] = arg;
+                        }
+                    }
+                    for &ret in &data.rets {
+                        if let Type::Agg(agg) = func.vreg_type(ret) {
+                            elems.insert(ret, func.aggregate_operands(agg).elems.clone());
+                        }
+                    }
+                    new.push(inst);
+                }
                 other => new.push(other),
             }
         }
+        func.recycle_insts(old);
         func.get_block_data_mut(block).set_insts(new);
     }
 }
 
+/// A fresh aggregate holding `agg`'s current elements, defined by a
+/// `MakeAggregate` pushed onto `new`.
+fn rebuild<I: Inst>(
+    func: &mut Func<I>,
+    new: &mut Vec<Instruction<I>>,
+    elems: &HashMap<Reg, Vec<Reg>>,
+    agg: Reg,
+) -> Reg {
+    let v = elems
+        .get(&agg)
+        .cloned()
+        .expect("aggregate passed by value before its definition");
+    let id = func.new_aggregate(v);
+    let dst = func.new_typed_vreg(Type::Agg(id));
+    new.push(Instruction::Pseudo(PseudoInstruction::MakeAggregate { dst, id }));
+    dst
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -240,4 +292,43 @@ mod tests {
             other => panic!("expected Copy, got {other:?}"),
         }
     }
+
+    #[test]
+    fn returned_aggregate_is_rebuilt_from_its_current_elements() {
+        // agg0 = {v0, v1}; agg1 = insertvalue agg0[1] <- v2; return agg1
+        // => the Return reads a fresh make_aggregate {v0, v2}.
+        let mut func = Func::<X64Inst>::new("t".into());
+        let b0 = func.add_empty_block();
+        let v0 = func.new_vreg();
+        let v1 = func.new_vreg();
+        let v2 = func.new_vreg();
+        let id = func.new_aggregate(vec![v0, v1]);
+        let agg0 = func.new_typed_vreg(Type::Agg(id));
+        let agg1 = func.new_typed_vreg(Type::Agg(id));
+        {
+            let bd = func.get_block_data_mut(b0);
+            bd.push_pseudo_inst(PseudoInstruction::MakeAggregate { dst: agg0, id });
+            bd.push_pseudo_inst(PseudoInstruction::InsertValue {
+                dst: agg1,
+                agg: agg0,
+                val: v2,
+                idx: 1,
+            });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: agg1 });
+        }
+
+        lower_aggregates(&mut func);
+
+        let insts: Vec<_> = func.get_block_data(b0).iter().copied().collect();
+        let [
+            Instruction::Pseudo(PseudoInstruction::MakeAggregate { dst, id }),
+            Instruction::Pseudo(PseudoInstruction::Return { src }),
+        ] = insts[..]
+        else {
+            panic!("expected make_aggregate; return, got {insts:?}");
+        };
+        assert_eq!(src, dst);
+        assert_eq!(func.vreg_type(dst), Type::Agg(id));
+        assert_eq!(func.aggregate_operands(id).elems, [v0, v2]);
+    }
 }
@@ -0,0 +1,137 @@
+//! Empty block forwarding.
+//!
+//! **Requires:** Every block terminated. A block whose jump target starts
+//! with a `Phi` is left alone (the phi names it as an incoming
+//! predecessor), so in practice this runs after `destroy_ssa`.
+//!
+//! **Preserves:** Semantics and the entry block's identity.
+//!
+//! **Invalidates:** Block numbering — survivors are renumbered densely by
+//! `Func::remove_blocks`. Any `CFG`, layout, or liveness computed before
+//! the pass is stale.
+//!
+//! **Effect:** A non-entry block holding nothing but an unconditional
+//! direct jump is a pure forwarder. Every branch into it is redirected to
+//! its final destination (following chains of forwarders), and the
+//! forwarders are deleted. Typical sources are the landing blocks SSA
+//! destruction creates on critical edges that ended up needing no copies.
+
+use std::collections::{HashMap, HashSet};
+
+use super::block_merging::{sole_jump_target, starts_with_phi};
+use crate::codegen::tir::{Block, Func, Inst};
+use crate::codegen::stats::stat;
+
+/// Forward branches past jump-only blocks and delete them. Returns `true`
+/// if anything changed.
+pub fn forward_empty_blocks<I: Inst>(func: &mut Func<I>) -> bool {
+    let Some(entry) = func.get_entry_block() else {
+        return false;
+    };
+    let mut forward: HashMap<Block, Block> = HashMap::new();
+    for (b, bd) in func.blocks_iter() {
+        if b != entry
+            && bd.len() == 1
+            && let Some(t) = sole_jump_target(func, b)
+            && t != b
+            && !starts_with_phi(func, t)
+        {
+            forward.insert(b, t);
+        }
+    }
+    // Resolve chains up front. A cycle made only of forwarders is an
+    // infinite loop that must stay one; its members keep their jumps.
+    let mut dest: HashMap<Block, Block> = HashMap::new();
+    for &b in forward.keys() {
+        let mut t = forward[&b];
+        let mut seen = HashSet::from([b]);
+        while let Some(&n) = forward.get(&t) {
+            if !seen.insert(t) {
+                break;
+            }
+            t = n;
+        }
+        if !forward.contains_key(&t) {
+            dest.insert(b, t);
+        }
+    }
+    if dest.is_empty() {
+        return false;
+    }
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    for b in blocks {
+        if dest.contains_key(&b) {
+            continue;
+        }
+        let Some(term) = func.get_block_data(b).get_terminator() else {
+            continue;
+        };
+        for old in func.branch_targets(&term) {
+            if let Some(&new) = dest.get(&old) {
+                func.rewrite_branch_target(b, old, new);
+            }
+        }
+    }
+    let dead: HashSet<Block> = dest.into_keys().collect();
+    stat!("block-forwarding", "forwarded", "jump-only blocks forwarded", dead.len());
+    func.remove_blocks(&dead);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::cfg::CFG;
+    use crate::codegen::tir::PseudoInstruction;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+    use crate::support::slotmap::Key;
+
+    fn jmp(func: &mut Func<X64Inst>, from: Block, to: Block) {
+        func.get_block_data_mut(from)
+            .push_target_inst(X64Inst::Jmp { dst: to });
+    }
+
+    #[test]
+    fn chain_of_forwarders_is_skipped_and_deleted() {
+        // b0: jz b1, b3; b1: jmp b2; b2: jmp b3; b3: ret
+        let mut func = Func::<X64Inst>::new("fwd".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b3,
+        });
+        jmp(&mut func, b1, b2);
+        jmp(&mut func, b2, b3);
+        func.get_block_data_mut(b3)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        assert!(forward_empty_blocks(&mut func));
+        assert_eq!(func.blocks_count(), 2);
+        let ret = Block::new(1);
+        let term = func.get_block_data(b0).get_terminator().unwrap();
+        assert_eq!(term.get_branch_targets().as_slice(), &[ret, ret]);
+        assert!(CFG::compute(&func).is_ok());
+    }
+
+    #[test]
+    fn forwarder_cycle_and_entry_are_kept() {
+        // b0: jmp b1; b1: jmp b2; b2: jmp b1 — an intentional infinite loop.
+        let mut func = Func::<X64Inst>::new("spin".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        jmp(&mut func, b0, b1);
+        jmp(&mut func, b1, b2);
+        jmp(&mut func, b2, b1);
+
+        assert!(!forward_empty_blocks(&mut func));
+        assert_eq!(func.blocks_count(), 3);
+    }
+}
@@ -0,0 +1,223 @@
+//! Profile- and hint-guided block layout.
+//!
+//! **Requires:** Every block terminated. Does nothing unless the function
+//! carries a `Profile` (`Func::set_profile`) or block hints
+//! (`Func::set_block_hint`).
+//!
+//! **Preserves:** Semantics and the entry block's position.
+//!
+//! **Invalidates:** Block numbering — blocks are renumbered to their new
+//! positions by `Func::reorder_blocks`. Any `CFG`, layout, or liveness
+//! computed before the pass is stale.
+//!
+//! **Effect:** Builds the hot path greedily: starting at the entry, keep
+//! appending the unplaced successor reached by the heaviest edge, so the
+//! likely path falls through. When the chain ends, restart from the
+//! heaviest unplaced block. Blocks the profile shows never ran (weight 0),
+//! or that hints put on a cold path, are moved to the end of the
+//! function, in their original order. Without a profile, edge weights
+//! come from the hints (`block_freq::edge_weight`).
+//!
+//! `linearize_blocks` is the pipeline's fallback for every level: when a
+//! reachable block sits before its immediate dominator — a frontend that
+//! appends loop headers late, or a layout that sank a cold dominator — it
+//! puts the blocks in reverse post-order, unreachable ones last, so the
+//! allocator's program points run forward through the CFG.
+
+use crate::codegen::analysis::block_freq::{BlockFrequency, edge_weight};
+use crate::codegen::analysis::cfg::{CFG, reverse_post_order};
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst};
+use crate::support::bitset::FixedBitSet;
+use crate::support::slotmap::Key;
+
+/// Put the blocks in reverse post-order unless every reachable block
+/// already follows its immediate dominator. Returns `true` if the order
+/// changed.
+pub fn linearize_blocks<I: Inst>(func: &mut Func<I>) -> bool {
+    let Ok(cfg) = CFG::compute(func) else {
+        return false;
+    };
+    let Ok(dt) = DomTree::compute(&cfg) else {
+        return false;
+    };
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let mut pos = vec![0; blocks.len()];
+    for (i, b) in blocks.iter().enumerate() {
+        pos[b.index()] = i;
+    }
+    if blocks.iter().all(|&b| dt.idom(b).is_none_or(|d| pos[d.index()] < pos[b.index()])) {
+        return false;
+    }
+    let mut order = reverse_post_order(&cfg);
+    let mut placed = FixedBitSet::zeroes(blocks.len());
+    for b in &order {
+        placed.add(b.index());
+    }
+    order.extend(blocks.iter().copied().filter(|b| !placed.has(b.index())));
+    stat!("block_layout", "linearized", "functions put in reverse post-order");
+    func.reorder_blocks(&order);
+    true
+}
+
+/// Reorder blocks by profile weight or hints. Returns `true` if the order
+/// changed.
+pub fn layout_blocks<I: Inst>(func: &mut Func<I>) -> bool {
+    if func.profile().is_none() && !func.has_block_hints() {
+        return false;
+    }
+    let Ok(cfg) = CFG::compute(func) else {
+        return false;
+    };
+    let Ok(dt) = DomTree::compute(&cfg) else {
+        return false;
+    };
+    let bf = BlockFrequency::for_func(func, &cfg, &dt);
+    let profile = func.profile();
+    let entry = cfg.get_entry_block();
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    let cold = |b: Block| {
+        b != entry && (bf.freq(b) == 0 || profile.is_some_and(|p| p.block_weight(b) == Some(0)))
+    };
+    let block_weight = |b: Block| match profile {
+        Some(p) => p.block_weight(b).unwrap_or(0),
+        None => u64::from(bf.freq(b)),
+    };
+    let mut placed = FixedBitSet::zeroes(blocks.len());
+    let mut order = Vec::with_capacity(blocks.len());
+    let mut next = Some(entry);
+    while let Some(b) = next {
+        placed.add(b.index());
+        order.push(b);
+        // Ties go to the successor earliest in the current layout
+        // (`max_by_key` returns the last maximum, so iterate in reverse).
+        next = cfg
+            .succs(b)
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&s| !placed.has(s.index()) && !cold(s))
+            .max_by_key(|&s| edge_weight(func, b, s))
+            .or_else(|| {
+                blocks
+                    .iter()
+                    .rev()
+                    .copied()
+                    .filter(|&s| !placed.has(s.index()) && !cold(s))
+                    .max_by_key(|&s| block_weight(s))
+            });
+    }
+    order.extend(blocks.iter().copied().filter(|b| !placed.has(b.index())));
+    if order == blocks {
+        return false;
+    }
+    func.reorder_blocks(&order);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+    use crate::codegen::tir::{Instruction, Profile, PseudoInstruction};
+    use std::collections::HashMap;
+
+    /// `b0: jz b1, b2`; `b1: ret`; `b2: ret`.
+    fn two_way() -> Func<X64Inst> {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b2,
+        });
+        func.get_block_data_mut(b1)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        func.get_block_data_mut(b2)
+            .push_target_inst(X64Inst::Add64ri32 { dst: v, imm: 1 });
+        func.get_block_data_mut(b2)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        func
+    }
+
+    #[test]
+    fn hot_successor_is_placed_next_and_targets_follow() {
+        let mut func = two_way();
+        let b = |i| Block::new(i);
+        func.set_profile(Profile {
+            block_counts: HashMap::new(),
+            edge_counts: HashMap::from([((b(0), b(1)), 3), ((b(0), b(2)), 97)]),
+        });
+        assert!(layout_blocks(&mut func));
+        // Old b2 (the hot arm) is now b1.
+        assert_eq!(func.get_block_data(b(1)).len(), 2);
+        let Some(Instruction::Target(X64Inst::CondJmp { taken, not_taken, .. })) =
+            func.get_block_data(b(0)).get_terminator()
+        else {
+            panic!("entry lost its branch");
+        };
+        assert_eq!((taken, not_taken), (b(2), b(1)));
+        assert_eq!(func.profile().unwrap().edge_counts[&(b(0), b(1))], 97);
+    }
+
+    #[test]
+    fn never_executed_blocks_sink_to_the_end() {
+        let mut func = Func::<X64Inst>::new("cold".to_string());
+        let b: Vec<Block> = (0..3).map(|_| func.add_empty_block()).collect();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b[0])
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b[0]).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b[1],
+            not_taken: b[2],
+        });
+        func.get_block_data_mut(b[1]).push_target_inst(X64Inst::Ud2);
+        func.get_block_data_mut(b[2])
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        func.set_profile(Profile {
+            block_counts: HashMap::from([(b[0], 10), (b[1], 0), (b[2], 10)]),
+            edge_counts: HashMap::new(),
+        });
+        assert!(layout_blocks(&mut func));
+        assert!(func.get_block_data(b[1]).get_terminator().unwrap().is_ret());
+    }
+
+    #[test]
+    fn no_profile_means_no_change() {
+        let mut func = two_way();
+        assert!(!layout_blocks(&mut func));
+    }
+
+    #[test]
+    fn a_header_placed_after_its_loop_body_moves_ahead_of_it() {
+        // b0: jmp b2; b1 (body): jmp b2; b2 (header): jz b1, b3; b3: ret.
+        let mut func = Func::<X64Inst>::new("loop".to_string());
+        let b: Vec<Block> = (0..4).map(|_| func.add_empty_block()).collect();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b[0]).push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b[0]).push_target_inst(X64Inst::Jmp { dst: b[2] });
+        func.get_block_data_mut(b[1]).push_target_inst(X64Inst::Jmp { dst: b[2] });
+        func.get_block_data_mut(b[2]).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b[1],
+            not_taken: b[3],
+        });
+        func.get_block_data_mut(b[3]).push_pseudo_inst(PseudoInstruction::Return { src: v });
+        assert!(linearize_blocks(&mut func));
+        // The header is now b1, right after the entry.
+        let Some(Instruction::Target(X64Inst::CondJmp { .. })) =
+            func.get_block_data(b[1]).get_terminator()
+        else {
+            panic!("header not second");
+        };
+        assert!(!linearize_blocks(&mut func));
+        assert!(!linearize_blocks(&mut two_way()));
+    }
+}
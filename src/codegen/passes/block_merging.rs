@@ -0,0 +1,190 @@
+//! Straight-line block merging.
+//!
+//! **Requires:** Every block terminated. Phis, if any, only in blocks this
+//! pass leaves alone (a block that starts with a `Phi` is never folded
+//! into its predecessor).
+//!
+//! **Preserves:** Semantics and the entry block's identity.
+//!
+//! **Invalidates:** Block numbering — survivors are renumbered densely by
+//! `Func::remove_blocks`. Any `CFG`, layout, or liveness computed before
+//! the pass is stale.
+//!
+//! **Effect:** While some block `A` ends in an unconditional direct jump
+//! to `B`, `B` is not the entry, and `A` is `B`'s only predecessor, drop
+//! the jump and append `B`'s instructions to `A`. Chains collapse in one
+//! run; `B` is then deleted, and phis that named `B` as an incoming
+//! predecessor name `A` instead.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction};
+
+/// Merge straight-line block chains in place. Returns `true` if anything
+/// changed.
+pub fn merge_blocks<I: Inst>(func: &mut Func<I>) -> bool {
+    let Ok(cfg) = CFG::compute(func) else {
+        return false;
+    };
+    let Some(entry) = func.get_entry_block() else {
+        return false;
+    };
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    // `merged_into[b]` is the block that absorbed `b`. The CFG isn't
+    // updated as we go, so a stale predecessor is resolved through this
+    // map to the block that now holds its terminator.
+    let mut merged_into: HashMap<Block, Block> = HashMap::new();
+    let owner = |merged_into: &HashMap<Block, Block>, mut b: Block| {
+        while let Some(&o) = merged_into.get(&b) {
+            b = o;
+        }
+        b
+    };
+    for a in blocks {
+        if merged_into.contains_key(&a) {
+            continue;
+        }
+        while let Some(b) = sole_jump_target(func, a)
+            && b != a
+            && b != entry
+            && !merged_into.contains_key(&b)
+            && let [p] = cfg.preds(b)
+            && owner(&merged_into, *p) == a
+            && !starts_with_phi(func, b)
+        {
+            let tail = func.get_block_data_mut(b).take_insts();
+            let insts = func.get_block_data_mut(a).insts_mut();
+            insts.pop();
+            insts.extend_from_slice(&tail);
+            func.recycle_insts(tail);
+            merged_into.insert(b, a);
+        }
+    }
+    if merged_into.is_empty() {
+        return false;
+    }
+    // A phi in a successor of an absorbed block names it as the incoming
+    // predecessor; that edge now leaves the absorbing block.
+    let phi_ids: Vec<_> = func
+        .blocks_iter()
+        .flat_map(|(_, bd)| bd.iter())
+        .filter_map(|inst| match inst {
+            Instruction::Pseudo(PseudoInstruction::Phi { id, .. }) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    for id in phi_ids {
+        for (pred, _) in &mut func.phi_operands_mut(id).incoming {
+            *pred = owner(&merged_into, *pred);
+        }
+    }
+    let dead: HashSet<Block> = merged_into.into_keys().collect();
+    stat!("block-merging", "merged", "blocks merged into their sole predecessor", dead.len());
+    func.remove_blocks(&dead);
+    true
+}
+
+/// The target of `b`'s terminator if it is an unconditional direct jump:
+/// a branch with exactly one block target and no register operands.
+pub(super) fn sole_jump_target<I: Inst>(func: &Func<I>, b: Block) -> Option<Block> {
+    let term = func.get_block_data(b).get_terminator()?;
+    let targets = term.get_branch_targets();
+    (term.is_branch() && targets.len() == 1 && term.get_uses().is_empty()).then(|| targets[0])
+}
+
+pub(super) fn starts_with_phi<I: Inst>(func: &Func<I>, b: Block) -> bool {
+    matches!(
+        func.get_block_data(b).insts().first(),
+        Some(Instruction::Pseudo(PseudoInstruction::Phi { .. }))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+
+    fn jmp(func: &mut Func<X64Inst>, from: Block, to: Block) {
+        func.get_block_data_mut(from)
+            .push_target_inst(X64Inst::Jmp { dst: to });
+    }
+
+    #[test]
+    fn chain_of_jumps_collapses_into_the_entry_block() {
+        let mut func = Func::<X64Inst>::new("chain".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 1 });
+        jmp(&mut func, b0, b1);
+        func.get_block_data_mut(b1)
+            .push_target_inst(X64Inst::Add64ri32 { dst: v, imm: 2 });
+        jmp(&mut func, b1, b2);
+        func.get_block_data_mut(b2)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        assert!(merge_blocks(&mut func));
+        assert_eq!(func.blocks_count(), 1);
+        let insts = func.get_block_data(b0).insts();
+        assert_eq!(insts.len(), 3);
+        assert!(insts[2].is_ret());
+    }
+
+    #[test]
+    fn join_block_with_two_predecessors_is_kept() {
+        let mut func = Func::<X64Inst>::new("diamond".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b2,
+        });
+        jmp(&mut func, b1, b3);
+        jmp(&mut func, b2, b3);
+        func.get_block_data_mut(b3)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        assert!(!merge_blocks(&mut func));
+        assert_eq!(func.blocks_count(), 4);
+    }
+
+    #[test]
+    fn merged_block_renumbering_fixes_later_branch_targets() {
+        // b0 -> b1 (merged away), b1 -> cond(b2, b3); after the merge the
+        // survivors are renumbered and b0's new terminator must follow.
+        let mut func = Func::<X64Inst>::new("renumber".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 0 });
+        jmp(&mut func, b0, b1);
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b2,
+            not_taken: b3,
+        });
+        func.get_block_data_mut(b2)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        func.get_block_data_mut(b3)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        assert!(merge_blocks(&mut func));
+        assert_eq!(func.blocks_count(), 3);
+        let term = func.get_block_data(b0).get_terminator().unwrap();
+        assert_eq!(term.get_branch_targets().as_slice(), &[b1, b2]);
+        assert!(CFG::compute(&func).is_ok());
+    }
+}
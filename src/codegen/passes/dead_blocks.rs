@@ -0,0 +1,44 @@
+//! Unreachable block removal.
+//!
+//! **Requires:** Every reachable block terminated; phis (if any) must not
+//! name a removed block as an incoming predecessor.
+//!
+//! **Invalidates:** Block numbering (see `Func::remove_blocks`) whenever a
+//! block is removed.
+//!
+//! **Effect:** Deletes every block no branch path from the entry reaches.
+//! Passes that rewrite branches call this to sweep up what they orphaned.
+
+use std::collections::HashSet;
+
+use crate::codegen::tir::{Block, Func, Inst};
+use crate::codegen::stats::stat;
+
+/// Delete unreachable blocks. Returns `true` if any were removed.
+pub fn remove_unreachable<I: Inst>(func: &mut Func<I>) -> bool {
+    let Some(entry) = func.get_entry_block() else {
+        return false;
+    };
+    let mut seen: HashSet<Block> = HashSet::from([entry]);
+    let mut stack = vec![entry];
+    while let Some(b) = stack.pop() {
+        if let Some(term) = func.get_block_data(b).get_terminator() {
+            for s in func.branch_targets(&term) {
+                if seen.insert(s) {
+                    stack.push(s);
+                }
+            }
+        }
+    }
+    let dead: HashSet<Block> = func
+        .blocks_iter()
+        .map(|(b, _)| b)
+        .filter(|b| !seen.contains(b))
+        .collect();
+    if dead.is_empty() {
+        return false;
+    }
+    stat!("dead-blocks", "removed", "unreachable blocks removed", dead.len());
+    func.remove_blocks(&dead);
+    true
+}
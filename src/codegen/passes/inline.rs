@@ -0,0 +1,452 @@
+//! Function inlining.
+//!
+//! **Requires:** SSA IR before `destroy_ssa` (phis intact), the whole
+//! module at once so callee bodies can be found by symbol.
+//!
+//! **Preserves:** Semantics, every callee's own body, the entry block
+//! of every caller.
+//!
+//! **Invalidates:** Any `CFG` or `CallGraph` computed before the pass.
+//!
+//! **Effect:** A direct call to a small function the module defines is
+//! replaced by a copy of the callee's blocks. The calling block is split
+//! at the call: the front jumps to the copied entry, each copied `return`
+//! jumps to the back half, and the call's result becomes a phi of the
+//! returned values. Callee vregs and blocks are renumbered into the
+//! caller, `arg` pseudos become copies of the call's arguments, and the
+//! callee's `stackalloc`s join the caller's frame.
+//!
+//! Callers are visited bottom-up over the call graph, so a callee has
+//! already absorbed its own small callees when its size is judged. Calls
+//! within one strongly connected component (recursion) are never
+//! inlined; an outside caller may still take one layer of a recursive
+//! callee. A callee's cost is its instruction count minus what the call
+//! sequence itself costs; call sites inside loops get the larger
+//! `hot_max_insts` limit, and each caller's total growth is capped.
+
+use std::collections::HashMap;
+
+use crate::codegen::analysis::block_freq::BlockFrequency;
+use crate::codegen::analysis::call_graph::CallGraph;
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{
+    Block, CallData, CallId, CallTarget, Func, Inst, Instruction, PseudoInstruction,
+    IntrinsicData, RawBytesData, Reg,
+};
+
+/// Size limits for `inline_calls`.
+#[derive(Clone, Copy, Debug)]
+pub struct InlineConfig {
+    /// Largest net callee size inlined at a call site outside loops.
+    pub max_insts: usize,
+    /// Largest net callee size inlined at a call site inside a loop.
+    pub hot_max_insts: usize,
+    /// Most instructions inlining may add to one caller in total.
+    pub growth_budget: usize,
+}
+
+impl Default for InlineConfig {
+    fn default() -> Self {
+        Self {
+            max_insts: 12,
+            hot_max_insts: 40,
+            growth_budget: 256,
+        }
+    }
+}
+
+impl InlineConfig {
+    /// Limits for optimizing for size: only callees about as small as
+    /// the call sequence they replace.
+    #[must_use]
+    pub fn size() -> Self {
+        Self {
+            max_insts: 3,
+            hot_max_insts: 3,
+            growth_budget: 16,
+        }
+    }
+}
+
+/// Inline small direct callees across `funcs`. Returns the number of call
+/// sites replaced.
+pub fn inline_calls<I: Inst>(funcs: &mut [Func<I>], config: &InlineConfig) -> usize {
+    let cg = CallGraph::compute(funcs);
+    let mut inlined = 0;
+    for caller in cg.bottom_up().collect::<Vec<_>>() {
+        if funcs[caller].attrs().cold || cg.callees(caller).is_empty() {
+            continue;
+        }
+        let Some(mut freq) = block_freqs(&funcs[caller]) else {
+            continue;
+        };
+        let mut budget = config.growth_budget;
+        let mut work: Vec<Block> = freq.keys().copied().collect();
+        work.sort_unstable();
+        while let Some(b) = work.pop() {
+            let Some((pos, callee, size)) =
+                next_site(funcs, &cg, caller, b, freq[&b], config, budget)
+            else {
+                continue;
+            };
+            let (caller_fn, callee_fn) = pair(funcs, caller, callee);
+            let cont = inline_site(caller_fn, callee_fn, b, pos);
+            budget -= size;
+            inlined += 1;
+            stat!("inline", "inlined", "call sites inlined");
+            stat!("inline", "insts", "instructions added by inlining", size);
+            // The rest of `b` moved to `cont`; both may hold more calls.
+            freq.insert(cont, freq[&b]);
+            work.push(b);
+            work.push(cont);
+        }
+    }
+    inlined
+}
+
+/// Frequency of every block, measured if profiled, or `None` if the function has no
+/// well-formed CFG to inline into.
+fn block_freqs<I: Inst>(func: &Func<I>) -> Option<HashMap<Block, u32>> {
+    let cfg = CFG::compute(func).ok()?;
+    let dt = DomTree::compute(&cfg).ok()?;
+    let bf = BlockFrequency::for_func(func, &cfg, &dt);
+    Some(func.blocks_iter().map(|(b, _)| (b, bf.freq(b))).collect())
+}
+
+/// First inlinable call in `b`: its position, the callee index and the
+/// net instructions inlining it adds.
+fn next_site<I: Inst>(
+    funcs: &[Func<I>],
+    cg: &CallGraph,
+    caller: usize,
+    b: Block,
+    freq: u32,
+    config: &InlineConfig,
+    budget: usize,
+) -> Option<(usize, usize, usize)> {
+    let func = &funcs[caller];
+    let limit = if freq > 1 {
+        config.hot_max_insts
+    } else {
+        config.max_insts
+    };
+    func.get_block_data(b).iter().enumerate().find_map(|(pos, inst)| {
+        let Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) = inst else {
+            return None;
+        };
+        let data = func.call_operands(*id);
+        let CallTarget::Symbol(sym) = &data.callee else {
+            return None;
+        };
+        let callee = cg.func_index(sym)?;
+        if cg.scc_of(callee) == cg.scc_of(caller) || !inlinable(&funcs[callee], data) {
+            return None;
+        }
+        let body: usize = funcs[callee].blocks_iter().map(|(_, bd)| bd.len()).sum();
+        let size = body.saturating_sub(1 + data.args.len() + data.rets.len());
+        (size <= limit && size <= budget).then_some((pos, callee, size))
+    })
+}
+
+/// The callee must return, agree with the call on arity, hold only
+/// pseudos the copy knows how to renumber, and take and return no
+/// aggregates by value: their `Agg` types name the callee's side table.
+fn inlinable<I: Inst>(callee: &Func<I>, call: &CallData) -> bool {
+    let attrs = callee.attrs();
+    if attrs.cold || attrs.naked || attrs.noreturn || call.rets.len() > 1 {
+        return false;
+    }
+    let Some(entry) = callee.get_entry_block() else {
+        return false;
+    };
+    let mut returns = false;
+    for (b, bd) in callee.blocks_iter() {
+        for inst in bd.iter() {
+            let Instruction::Pseudo(p) = inst else {
+                continue;
+            };
+            match p {
+                PseudoInstruction::Arg { idx, .. } if *idx as usize >= call.args.len() => {
+                    return false;
+                }
+                // The copied entry gains the caller as a predecessor.
+                PseudoInstruction::Phi { .. } if b == entry => return false,
+                PseudoInstruction::Arg { dst: v, .. } | PseudoInstruction::Return { src: v }
+                    if callee.vreg_type(*v).is_aggregate() =>
+                {
+                    return false;
+                }
+                PseudoInstruction::Return { .. } => returns = true,
+                PseudoInstruction::FrameSetup
+                | PseudoInstruction::FrameDestroy
+                | PseudoInstruction::RegDef { .. } => return false,
+                _ => {}
+            }
+        }
+    }
+    returns
+}
+
+fn pair<I: Inst>(funcs: &mut [Func<I>], a: usize, b: usize) -> (&mut Func<I>, &Func<I>) {
+    if a < b {
+        let (lo, hi) = funcs.split_at_mut(b);
+        (&mut lo[a], &hi[0])
+    } else {
+        let (lo, hi) = funcs.split_at_mut(a);
+        (&mut hi[0], &lo[b])
+    }
+}
+
+/// Replace the call at `b[pos]` with a copy of `callee`. Returns the
+/// block holding the instructions that followed the call.
+fn inline_site<I: Inst>(caller: &mut Func<I>, callee: &Func<I>, b: Block, pos: usize) -> Block {
+    let Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) =
+        caller.get_block_data(b).insts()[pos]
+    else {
+        panic!("inline site is not a call");
+    };
+    let call = caller.call_operands(id).clone();
+
+    let vmap: Vec<Reg> = (0..callee.get_regs_count() as Reg)
+        .map(|r| caller.new_typed_vreg(callee.vreg_type(r)))
+        .collect();
+    for (&v, &p) in callee.pre_binds() {
+        caller.pre_bind(vmap[v as usize], p);
+    }
+    let bmap: HashMap<Block, Block> = callee
+        .blocks_iter()
+        .map(|(cb, _)| cb)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|cb| (cb, caller.add_empty_block()))
+        .collect();
+    for (&cb, &nb) in &bmap {
+        caller.set_block_hint(nb, callee.block_hint(cb));
+    }
+
+    // Split `b` after the call; the back half's successors now see it,
+    // not `b`, as their predecessor.
+    let cont = caller.add_empty_block();
+    let mut tail = caller.get_block_data_mut(b).insts_mut().split_off(pos);
+    tail.remove(0);
+    let succs: Vec<Block> =
+        tail.last().map(|t| caller.branch_targets(t)).unwrap_or_default().to_vec();
+    caller.replace_insts(cont, tail);
+    for s in succs {
+        retarget_phis(caller, s, b, cont);
+    }
+    let entry = callee.get_entry_block().expect("inlinable callee has an entry");
+    caller.get_block_data_mut(b).push_inst(Instruction::new_jmp(bmap[&entry]));
+
+    let mut returns: Vec<(Block, Reg)> = Vec::new();
+    for (cb, bd) in callee.blocks_iter() {
+        let nb = bmap[&cb];
+        let mut insts = Vec::with_capacity(bd.len());
+        for inst in bd.iter() {
+            let mut inst = *inst;
+            if let Instruction::Pseudo(p) = &mut inst {
+                match *p {
+                    PseudoInstruction::Arg { dst, idx } => {
+                        *p = PseudoInstruction::Copy {
+                            dst: vmap[dst as usize],
+                            src: call.args[idx as usize],
+                        };
+                        insts.push(inst);
+                        continue;
+                    }
+                    PseudoInstruction::Return { src } => {
+                        returns.push((nb, vmap[src as usize]));
+                        insts.push(Instruction::new_jmp(cont));
+                        continue;
+                    }
+                    PseudoInstruction::Phi { ref mut id, .. } => {
+                        let incoming = callee
+                            .phi_operands(*id)
+                            .incoming
+                            .iter()
+                            .map(|&(pb, r)| (bmap[&pb], vmap[r as usize]))
+                            .collect();
+                        *id = caller.new_phi(incoming);
+                    }
+                    PseudoInstruction::CallPseudo { ref mut id } => {
+                        *id = copy_call(caller, callee.call_operands(*id), &vmap);
+                    }
+                    PseudoInstruction::RawBytes { ref mut id } => {
+                        let d = callee.raw_bytes_operands(*id);
+                        let pin = |ops: &[(Reg, Reg)]| {
+                            ops.iter().map(|&(v, p)| (vmap[v as usize], p)).collect()
+                        };
+                        *id = caller.new_raw_bytes(RawBytesData {
+                            bytes: d.bytes.clone(),
+                            uses: pin(&d.uses),
+                            defs: pin(&d.defs),
+                            clobbers: d.clobbers.clone(),
+                        });
+                    }
+                    PseudoInstruction::Intrinsic { ref mut id } => {
+                        let d = callee.intrinsic_operands(*id);
+                        let map = |rs: &[Reg]| rs.iter().map(|&r| vmap[r as usize]).collect();
+                        *id = caller.new_intrinsic(IntrinsicData {
+                            decl: d.decl,
+                            args: map(&d.args),
+                            rets: map(&d.rets),
+                        });
+                    }
+                    PseudoInstruction::MakeAggregate { ref mut id, .. } => {
+                        let elems = callee
+                            .aggregate_operands(*id)
+                            .elems
+                            .iter()
+                            .map(|&r| vmap[r as usize])
+                            .collect();
+                        *id = caller.new_aggregate(elems);
+                    }
+                    _ => {}
+                }
+            }
+            inst.map_regs(&mut |r| vmap[r as usize]);
+            inst.map_branch_targets(&mut |t| bmap[&t]);
+            inst.map_jump_table(&mut |id| {
+                let mut table = callee.jump_table(id).clone();
+                for t in table.targets_mut() {
+                    *t = bmap[t];
+                }
+                caller.new_jump_table(table)
+            });
+            inst.map_symbols(&mut |sym| caller.symbol(callee.symbol_name(sym)));
+            insts.push(inst);
+        }
+        caller.replace_insts(nb, insts);
+    }
+
+    if let Some(&ret) = call.rets.first() {
+        let def = match returns.as_slice() {
+            [(_, src)] => PseudoInstruction::Copy { dst: ret, src: *src },
+            _ => PseudoInstruction::Phi {
+                dst: ret,
+                id: caller.new_phi(returns),
+            },
+        };
+        caller.get_block_data_mut(cont).insts_mut().insert(0, Instruction::Pseudo(def));
+    }
+    cont
+}
+
+fn copy_call<I: Inst>(caller: &mut Func<I>, data: &CallData, vmap: &[Reg]) -> CallId {
+    let map = |regs: &[Reg]| regs.iter().map(|&r| vmap[r as usize]).collect();
+    let callee = match &data.callee {
+        CallTarget::Symbol(s) => CallTarget::Symbol(s.clone()),
+        CallTarget::Indirect(r) => CallTarget::Indirect(vmap[*r as usize]),
+    };
+    caller.new_call(CallData {
+        callee,
+        args: map(&data.args),
+        rets: map(&data.rets),
+        patch: data.patch,
+    })
+}
+
+/// Point `s`'s phi operands that flowed in from `old` at `new`.
+fn retarget_phis<I: Inst>(func: &mut Func<I>, s: Block, old: Block, new: Block) {
+    let ids: Vec<_> = func
+        .get_block_data(s)
+        .iter()
+        .filter_map(|inst| match inst {
+            Instruction::Pseudo(PseudoInstruction::Phi { id, .. }) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    for id in ids {
+        for (pb, _) in &mut func.phi_operands_mut(id).incoming {
+            if *pb == old {
+                *pb = new;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::verify::verify;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+
+    /// `max(a, b)` with two returns.
+    fn max() -> Func<X64Inst> {
+        let mut b = FuncBuilder::new("max");
+        let x = b.arg();
+        let y = b.arg();
+        let (t, e) = (b.new_block(), b.new_block());
+        b.branch_icmp(Cond::G, x, y, t, e);
+        b.switch_to_block(t);
+        b.ret(x);
+        b.switch_to_block(e);
+        b.ret(y);
+        b.build()
+    }
+
+    fn calls<I: Inst>(func: &Func<I>) -> Vec<String> {
+        func.blocks_iter()
+            .flat_map(|(_, bd)| bd.iter())
+            .filter_map(|inst| match inst {
+                Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) => {
+                    match &func.call_operands(*id).callee {
+                        CallTarget::Symbol(s) => Some(s.clone()),
+                        CallTarget::Indirect(_) => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_callees_are_copied_but_recursion_is_not() {
+        // `fact`'s outer layer is inlined into `clamp3`; its self-call
+        // stays a call in both places.
+        let mut b = FuncBuilder::new("clamp3");
+        let x = b.arg();
+        let y = b.arg();
+        let m = b.call_sym("max", &[x, y]);
+        let n = b.call_sym("max", &[m, x]);
+        let r = b.call_sym("fact", &[n]);
+        b.ret(r);
+        let mut f = FuncBuilder::new("fact");
+        let n = f.arg();
+        let r = f.call_sym("fact", &[n]);
+        f.ret(r);
+        let mut funcs = vec![b.build(), max(), f.build()];
+
+        assert_eq!(inline_calls(&mut funcs, &InlineConfig::default()), 3);
+        assert_eq!(calls(&funcs[0]), ["fact"]);
+        assert_eq!(calls(&funcs[2]), ["fact"]);
+        for func in &funcs {
+            verify(func).expect("inlined IR verifies");
+        }
+    }
+
+    #[test]
+    fn size_limit_and_budget_keep_calls() {
+        let mut b = FuncBuilder::new("twice");
+        let x = b.arg();
+        let m = b.call_sym("max", &[x, x]);
+        let n = b.call_sym("max", &[m, x]);
+        b.ret(n);
+        let mut funcs = vec![b.build(), max()];
+        let tight = InlineConfig {
+            max_insts: 0,
+            ..InlineConfig::default()
+        };
+        assert_eq!(inline_calls(&mut funcs, &tight), 0);
+        // `max` costs 2 net instructions per copy.
+        let one = InlineConfig {
+            growth_budget: 3,
+            ..InlineConfig::default()
+        };
+        assert_eq!(inline_calls(&mut funcs, &one), 1);
+        assert_eq!(calls(&funcs[0]), ["max"]);
+    }
+}
@@ -0,0 +1,190 @@
+//! Expands `Intrinsic` pseudos per the target's `IntrinsicDecl`: a
+//! `Bytes` intrinsic becomes `RawBytes` with its operands pinned to the
+//! declared pregs, a `Call` intrinsic a `CallPseudo` to its symbol. Both
+//! are then lowered by the ABI pass like any other raw bytes or call.
+//!
+//! A `Pure` intrinsic none of whose results is read anywhere — instruction
+//! operands or side tables — is dropped instead.
+//!
+//! Must run before SSA destruction and aggregate lowering, so the
+//! operands it moves into other side tables are still in their original
+//! form.
+
+use std::collections::HashSet;
+
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{
+    CallData, CallTarget, Func, Inst, Instruction, IntrinsicEffects, IntrinsicLowering,
+    PseudoInstruction, RawBytesData, Reg,
+};
+
+/// Lower every `Intrinsic` pseudo in place. See module docs.
+pub fn lower_intrinsics<I: Inst>(func: &mut Func<I>) {
+    let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+    let has_intrinsics = blocks.iter().any(|&b| {
+        func.get_block_data(b).iter().any(|inst| {
+            matches!(
+                inst,
+                Instruction::Pseudo(PseudoInstruction::Intrinsic { .. })
+            )
+        })
+    });
+    if !has_intrinsics {
+        return;
+    }
+    let read = read_regs(func);
+    for block in blocks {
+        let old = func.get_block_data_mut(block).take_insts();
+        let mut new = func.inst_buffer(old.len());
+        for &inst in &old {
+            let Instruction::Pseudo(PseudoInstruction::Intrinsic { id }) = inst else {
+                new.push(inst);
+                continue;
+            };
+            let data = func.intrinsic_operands(id).clone();
+            let decl = data.decl;
+            if decl.effects == IntrinsicEffects::Pure && !data.rets.iter().any(|r| read.contains(r))
+            {
+                stat!(
+                    "intrinsics",
+                    "dropped",
+                    "pure intrinsics with unread results dropped"
+                );
+                continue;
+            }
+            stat!("intrinsics", "lowered", "intrinsic uses lowered");
+            let lowered = match decl.lowering {
+                IntrinsicLowering::Bytes {
+                    bytes,
+                    args,
+                    rets,
+                    clobbers,
+                } => {
+                    let pin = |vregs: &[Reg], pregs: &[Reg]| {
+                        vregs.iter().copied().zip(pregs.iter().copied()).collect()
+                    };
+                    let id = func.new_raw_bytes(RawBytesData {
+                        bytes: bytes.to_vec(),
+                        uses: pin(&data.args, args),
+                        defs: pin(&data.rets, rets),
+                        clobbers: clobbers.to_vec(),
+                    });
+                    PseudoInstruction::RawBytes { id }
+                }
+                IntrinsicLowering::Call { symbol } => {
+                    let id = func.new_call(CallData {
+                        callee: CallTarget::Symbol(symbol.to_string()),
+                        args: data.args,
+                        rets: data.rets,
+                        patch: None,
+                    });
+                    PseudoInstruction::CallPseudo { id }
+                }
+            };
+            new.push(Instruction::Pseudo(lowered));
+        }
+        func.recycle_insts(old);
+        func.get_block_data_mut(block).set_insts(new);
+    }
+}
+
+/// Every vreg some instruction or side table reads.
+fn read_regs<I: Inst>(func: &Func<I>) -> HashSet<Reg> {
+    let mut read = HashSet::new();
+    for (_, bd) in func.blocks_iter() {
+        for inst in bd.iter() {
+            read.extend(inst.get_uses());
+            let Instruction::Pseudo(p) = inst else {
+                continue;
+            };
+            match *p {
+                PseudoInstruction::Phi { id, .. } => {
+                    read.extend(func.phi_operands(id).incoming.iter().map(|&(_, r)| r));
+                }
+                PseudoInstruction::CallPseudo { id } => {
+                    let call = func.call_operands(id);
+                    read.extend(&call.args);
+                    if let CallTarget::Indirect(r) = call.callee {
+                        read.insert(r);
+                    }
+                }
+                PseudoInstruction::RawBytes { id } => {
+                    read.extend(func.raw_bytes_operands(id).uses.iter().map(|&(r, _)| r));
+                }
+                PseudoInstruction::Intrinsic { id } => {
+                    read.extend(&func.intrinsic_operands(id).args);
+                }
+                PseudoInstruction::MakeAggregate { id, .. } => {
+                    read.extend(&func.aggregate_operands(id).elems);
+                }
+                _ => {}
+            }
+        }
+    }
+    read
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::tir::IntrinsicData;
+
+    fn decl(name: &str) -> &'static crate::codegen::tir::IntrinsicDecl {
+        X64Inst::intrinsics()
+            .iter()
+            .find(|d| d.name == name)
+            .expect("declared intrinsic")
+    }
+
+    #[test]
+    fn bytes_pin_operands_calls_keep_them_and_unread_pure_uses_go() {
+        let mut func = Func::<X64Inst>::new("intr".to_string());
+        let b0 = func.add_empty_block();
+        let (x, count, unread, sine) = (
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+            func.new_vreg(),
+        );
+        let push = |func: &mut Func<X64Inst>, name, args: Vec<Reg>, rets: Vec<Reg>| {
+            let id = func.new_intrinsic(IntrinsicData {
+                decl: decl(name),
+                args,
+                rets,
+            });
+            func.get_block_data_mut(b0)
+                .push_pseudo_inst(PseudoInstruction::Intrinsic { id });
+        };
+        func.get_block_data_mut(b0)
+            .push_pseudo_inst(PseudoInstruction::Arg { dst: x, idx: 0 });
+        push(&mut func, "x64.popcnt", vec![x], vec![count]);
+        push(&mut func, "x64.popcnt", vec![x], vec![unread]);
+        push(&mut func, "math.sin.f64", vec![x], vec![sine]);
+        func.get_block_data_mut(b0)
+            .push_pseudo_inst(PseudoInstruction::Return { src: count });
+        lower_intrinsics(&mut func);
+
+        let insts: Vec<_> = func.get_block_data(b0).iter().copied().collect();
+        // `sin` may set `errno`, so only the unread popcnt goes.
+        assert_eq!(insts.len(), 4);
+        let Instruction::Pseudo(PseudoInstruction::RawBytes { id }) = insts[1] else {
+            panic!("popcnt lowers to raw bytes, got {}", insts[1]);
+        };
+        let raw = func.raw_bytes_operands(id);
+        let IntrinsicLowering::Bytes { args, rets, .. } = decl("x64.popcnt").lowering else {
+            unreachable!("popcnt is declared as bytes");
+        };
+        assert_eq!(raw.uses, vec![(x, args[0])]);
+        assert_eq!(raw.defs, vec![(count, rets[0])]);
+        let Instruction::Pseudo(PseudoInstruction::CallPseudo { id }) = insts[2] else {
+            panic!("sin lowers to a call, got {}", insts[2]);
+        };
+        let call = func.call_operands(id);
+        assert_eq!(call.callee, CallTarget::Symbol("sin".to_string()));
+        assert_eq!(
+            (call.args.as_slice(), call.rets.as_slice()),
+            ([x].as_slice(), [sine].as_slice())
+        );
+    }
+}
@@ -6,34 +6,42 @@
 //! coexist and be compared.
 
 pub mod aggregate_lowering;
+pub mod block_forwarding;
+pub mod block_layout;
+pub mod block_merging;
+pub mod dead_blocks;
+pub mod inline;
+pub mod intrinsic_lowering;
+pub mod peephole;
+pub mod redundant_moves;
 pub mod ssa_destruction;
+pub mod tail_duplication;
 
 pub use aggregate_lowering::lower_aggregates;
+pub use block_forwarding::forward_empty_blocks;
+pub use block_layout::{layout_blocks, linearize_blocks};
+pub use block_merging::merge_blocks;
+pub use dead_blocks::remove_unreachable;
+pub use inline::{InlineConfig, inline_calls};
+pub use intrinsic_lowering::lower_intrinsics;
+pub use peephole::{Peephole, PeepholeRule};
+pub use redundant_moves::find_redundant_moves;
 pub use ssa_destruction::destroy_ssa;
+pub use tail_duplication::{TailDupConfig, duplicate_tails};
 
 use std::collections::HashMap;
 
+use crate::codegen::error::CodegenError;
 use crate::codegen::tir::{Func, Inst, Reg};
 
 /// Output of an ABI-lowering pass.
 ///
 /// `reg_bind` maps the pinned shim / return vregs introduced during lowering
 /// to their ABI-fixed physical registers. The regalloc consumes this as its
-/// pre-bind constraint set. `call_sites` records each CallPseudo's
-/// synthesized call-target `Mov64ri` so later stages can patch its
-/// 64-bit immediate with a real symbol address.
+/// pre-bind constraint set. Direct calls reference their callee through a
+/// symbol operand, which emission turns into a relocation.
 pub struct AbiLowerResult {
     pub reg_bind: HashMap<Reg, Reg>,
-    pub call_sites: Vec<CallSite>,
-}
-
-/// One expanded call site. `addr_vreg` is the vreg that holds the
-/// callee's address (set via `Mov64ri` with a placeholder). `symbol`
-/// is the target symbol name; an empty string means indirect call.
-#[derive(Clone, Debug)]
-pub struct CallSite {
-    pub addr_vreg: Reg,
-    pub symbol: String,
 }
 
 /// Lowers target-neutral ABI pseudos (`Arg`, `Return`, eventually
@@ -44,5 +52,7 @@ pub struct CallSite {
 /// / return registers). A given CC on a given ISA is one implementor, e.g.
 /// `SysVAmd64Lowering: AbiLowering<X64Inst>`.
 pub trait AbiLowering<I: Inst> {
-    fn lower(&self, func: &mut Func<I>) -> AbiLowerResult;
+    /// # Errors
+    /// If the function passes or receives a value the convention can't.
+    fn lower(&self, func: &mut Func<I>) -> Result<AbiLowerResult, CodegenError>;
 }
@@ -0,0 +1,160 @@
+//! Target-parameterized peephole driver.
+//!
+//! **Requires:** Nothing beyond well-formed blocks; the rules decide what
+//! they can match.
+//!
+//! **Preserves:** Block structure. Rules only rewrite instructions inside
+//! one block.
+//!
+//! **Effect:** Slides a window over each block and offers it to every
+//! registered `PeepholeRule`, in registration order. The first rule that
+//! fires replaces the window's prefix it matched, and the scan backs up so
+//! the rewritten code can combine with what precedes it. Each ISA supplies
+//! its own rule set; the driver is shared.
+
+use smallvec::SmallVec;
+
+use crate::codegen::tir::{Func, Inst, Instruction};
+use crate::codegen::stats::stat;
+
+/// Replacement for a matched window. Usually zero, one, or two insts.
+pub type Rewrite<I> = SmallVec<[Instruction<I>; 2]>;
+
+/// One local rewrite. `apply` sees exactly `window()` instructions and
+/// returns their replacement, or `None` if it does not match. A rewrite
+/// must make progress (fewer or strictly cheaper instructions) so the
+/// driver terminates.
+pub trait PeepholeRule<I: Inst> {
+    fn name(&self) -> &'static str;
+    fn window(&self) -> usize;
+    fn apply(&self, window: &[Instruction<I>]) -> Option<Rewrite<I>>;
+}
+
+/// An ordered set of rules plus the driver that runs them.
+pub struct Peephole<I: Inst> {
+    rules: Vec<Box<dyn PeepholeRule<I>>>,
+}
+
+impl<I: Inst> Default for Peephole<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Inst> Peephole<I> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    #[must_use]
+    pub fn with_rule(mut self, rule: impl PeepholeRule<I> + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    #[must_use]
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|r| r.name()).collect()
+    }
+
+    /// Run every rule to a fixpoint over each block. Returns the number of
+    /// rewrites applied.
+    pub fn run(&self, func: &mut Func<I>) -> usize {
+        let back = self
+            .rules
+            .iter()
+            .map(|r| r.window())
+            .max()
+            .unwrap_or(1)
+            .saturating_sub(1);
+        let blocks: Vec<_> = func.blocks_iter().map(|(b, _)| b).collect();
+        let mut rewrites = 0;
+        for b in blocks {
+            let insts = func.get_block_data_mut(b).insts_mut();
+            let mut i = 0;
+            while i < insts.len() {
+                let hit = self.rules.iter().find_map(|r| {
+                    let w = r.window();
+                    let window = insts.get(i..i + w)?;
+                    Some((w, r.apply(window)?))
+                });
+                match hit {
+                    Some((w, repl)) => {
+                        insts.splice(i..i + w, repl);
+                        stat!("peephole", "rewrites", "peephole rewrites applied");
+                        rewrites += 1;
+                        i = i.saturating_sub(back);
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+        rewrites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::tir::PseudoInstruction;
+    use smallvec::smallvec;
+
+    /// `add r, a; add r, b` → `add r, a + b`.
+    struct FoldAdds;
+
+    impl PeepholeRule<X64Inst> for FoldAdds {
+        fn name(&self) -> &'static str {
+            "fold-adds"
+        }
+        fn window(&self) -> usize {
+            2
+        }
+        fn apply(&self, w: &[Instruction<X64Inst>]) -> Option<Rewrite<X64Inst>> {
+            match (w[0], w[1]) {
+                (
+                    Instruction::Target(X64Inst::Add64ri32 { dst: a, imm: x }),
+                    Instruction::Target(X64Inst::Add64ri32 { dst: b, imm: y }),
+                ) if a == b => Some(smallvec![Instruction::Target(X64Inst::Add64ri32 {
+                    dst: a,
+                    imm: x.checked_add(y)?,
+                })]),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn rewrites_cascade_backwards_to_a_fixpoint() {
+        let mut func = Func::<X64Inst>::new("adds".to_string());
+        let b0 = func.add_empty_block();
+        let v = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: v });
+        for imm in [1, 2, 3, 4] {
+            bd.push_target_inst(X64Inst::Add64ri32 { dst: v, imm });
+        }
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: v });
+
+        let pp = Peephole::new().with_rule(FoldAdds);
+        assert_eq!(pp.rule_names(), ["fold-adds"]);
+        assert_eq!(pp.run(&mut func), 3);
+        let insts = func.get_block_data(b0).insts();
+        assert_eq!(insts.len(), 3);
+        assert!(matches!(
+            insts[1],
+            Instruction::Target(X64Inst::Add64ri32 { imm: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn window_never_reaches_past_the_block_end() {
+        let mut func = Func::<X64Inst>::new("short".to_string());
+        let b0 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Add64ri32 { dst: v, imm: 1 });
+        assert_eq!(Peephole::new().with_rule(FoldAdds).run(&mut func), 0);
+    }
+}
@@ -0,0 +1,186 @@
+//! Post-allocation redundant move elimination.
+//!
+//! **Requires:** Regalloc complete; `ra` is the result for `func` and
+//! `func` has not been edited since (program points must still line up).
+//!
+//! **Preserves:** The IR — this is an analysis over the allocated function.
+//! The emitter consults the result and skips the listed moves.
+//!
+//! **Effect:** Walks each block tracking which physical locations (pregs
+//! and spill slots) hold the same value. A move (`Inst::is_move`) is
+//! redundant when its destination location already holds its source's
+//! value: a self-move `mov rX, rX`, the second half of a back-to-back
+//! inverse pair `mov rA, rB; mov rB, rA`, or a re-copy of a value that
+//! was already copied there and not clobbered since. Facts are dropped
+//! at block entry, on every def of a location, on split stores into a
+//! slot and reloads into a preg, and wholesale at calls.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::analysis::layout::{BlockLayout, ProgramPoint};
+use crate::codegen::regalloc::{AllocatedSlot, Edit, RegAllocResult};
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Func, Inst};
+
+/// Use points of every move the emitter can drop.
+pub fn find_redundant_moves<I: Inst>(
+    func: &Func<I>,
+    layout: &BlockLayout,
+    ra: &RegAllocResult,
+) -> HashSet<ProgramPoint> {
+    let mut redundant = HashSet::new();
+    for (block, bd) in func.blocks_iter() {
+        let mut facts = CopyFacts::default();
+        for (i, inst) in bd.iter().enumerate() {
+            let use_pt = layout.use_pt(block, i as u32);
+            let def_pt = layout.def_pt(block, i as u32);
+            for &(_, edit) in ra.edits_at(use_pt..=def_pt) {
+                facts.clobber(match edit {
+                    Edit::Store { to, .. } => AllocatedSlot::Stack(to),
+                    Edit::Reload { to, .. } | Edit::Move { to, .. } => AllocatedSlot::Reg(to),
+                });
+            }
+            if inst.is_call() {
+                facts.clear();
+                continue;
+            }
+            if let Some((dst, src)) = inst.is_move()
+                && let (Some(d), Some(s)) = (ra.at(dst, def_pt), ra.at(src, use_pt))
+            {
+                if facts.same(d, s) {
+                    redundant.insert(use_pt);
+                } else {
+                    facts.copy(d, s);
+                }
+                continue;
+            }
+            for def in inst.get_defs() {
+                match ra.at(def, def_pt) {
+                    Some(slot) => facts.clobber(slot),
+                    // A def without a location writes somewhere we can't
+                    // see; forget everything rather than guess.
+                    None => facts.clear(),
+                }
+            }
+        }
+    }
+    stat!("redundant-moves", "dropped", "moves found redundant after regalloc", redundant.len());
+    redundant
+}
+
+/// Equivalence classes of locations known to hold the same value.
+#[derive(Default)]
+struct CopyFacts {
+    class: HashMap<AllocatedSlot, u32>,
+    next: u32,
+}
+
+impl CopyFacts {
+    fn same(&self, a: AllocatedSlot, b: AllocatedSlot) -> bool {
+        a == b || matches!((self.class.get(&a), self.class.get(&b)), (Some(x), Some(y)) if x == y)
+    }
+
+    fn clobber(&mut self, loc: AllocatedSlot) {
+        self.class.remove(&loc);
+    }
+
+    fn copy(&mut self, dst: AllocatedSlot, src: AllocatedSlot) {
+        self.class.remove(&dst);
+        let next = &mut self.next;
+        let c = *self.class.entry(src).or_insert_with(|| {
+            *next += 1;
+            *next
+        });
+        self.class.insert(dst, c);
+    }
+
+    fn clear(&mut self) {
+        self.class.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::cfg::CFG;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::isa::x64::regs::{RAX, RCX, RDX};
+    use crate::codegen::regalloc::{LinearScan, RegAllocConfig, RegAllocator};
+    use crate::codegen::tir::{PseudoInstruction, Reg};
+
+    fn config(binds: &[(Reg, Reg)]) -> RegAllocConfig {
+        RegAllocConfig {
+            preg_count: 16,
+            allocatable_regs: vec![RAX, RCX, RDX],
+            scratch_regs: vec![],
+            allocatable_fp_regs: vec![],
+            scratch_fp_regs: vec![],
+            reg_bind: binds.iter().copied().collect(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        }
+    }
+
+    fn run(func: &Func<X64Inst>, binds: &[(Reg, Reg)]) -> HashSet<ProgramPoint> {
+        let cfg = CFG::compute(func).unwrap();
+        let ra = LinearScan::allocate(func, &cfg, &config(binds));
+        find_redundant_moves(func, &BlockLayout::compute(func), &ra)
+    }
+
+    #[test]
+    fn back_to_back_inverse_move_is_redundant() {
+        // a(RAX) -> b(RCX) -> c(RAX): the second move restores a value
+        // RAX still holds.
+        let mut func = Func::<X64Inst>::new("inv".to_string());
+        let b0 = func.add_empty_block();
+        let a = func.new_vreg();
+        let b = func.new_vreg();
+        let c = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: b, src: a });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: c, src: b });
+        bd.push_target_inst(X64Inst::Add64rr { dst: c, src: b });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: c });
+        let out = run(&func, &[(a, RAX), (b, RCX), (c, RAX)]);
+        let layout = BlockLayout::compute(&func);
+        assert_eq!(out, HashSet::from([layout.use_pt(b0, 2)]));
+    }
+
+    #[test]
+    fn intervening_def_of_the_source_location_keeps_the_move() {
+        let mut func = Func::<X64Inst>::new("clobbered".to_string());
+        let b0 = func.add_empty_block();
+        let a = func.new_vreg();
+        let b = func.new_vreg();
+        let c = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: b, src: a });
+        bd.push_target_inst(X64Inst::Add64ri32 { dst: b, imm: 1 });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: c, src: b });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: c });
+        assert!(run(&func, &[(a, RAX), (b, RCX), (c, RAX)]).is_empty());
+    }
+
+    #[test]
+    fn facts_do_not_survive_a_call() {
+        let mut func = Func::<X64Inst>::new("call".to_string());
+        let b0 = func.add_empty_block();
+        let a = func.new_vreg();
+        let b = func.new_vreg();
+        let f = func.new_vreg();
+        let c = func.new_vreg();
+        let bd = func.get_block_data_mut(b0);
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: a });
+        bd.push_pseudo_inst(PseudoInstruction::ImplicitDef { dst: f });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: b, src: a });
+        bd.push_target_inst(X64Inst::Call64r { target: f });
+        bd.push_target_inst(X64Inst::Mov64rr { dst: c, src: b });
+        bd.push_target_inst(X64Inst::Add64rr { dst: c, src: b });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: c });
+        assert!(run(&func, &[(a, RAX), (b, RCX), (f, RDX), (c, RAX)]).is_empty());
+    }
+}
@@ -8,8 +8,8 @@
 //! get a fresh intermediate block).
 //!
 //! **Invalidates:** The `Phi` pseudo — all phi instructions are removed
-//! after this pass. SSA no longer holds: a vreg may be defined in
-//! multiple predecessors.
+//! after this pass, and their operand lists freed (`Func::remove_phi`).
+//! SSA no longer holds: a vreg may be defined in multiple predecessors.
 //!
 //! **Effect:** For each phi `dst = phi [(pred_i, src_i)...]`:
 //!
@@ -41,16 +41,16 @@ pub fn destroy_ssa<I: Inst>(func: &mut Func<I>) {
     let mut phi_headers: HashMap<Block, Vec<StrippedPhi>> = HashMap::new();
     for b in &blocks {
         let insts = func.get_block_data_mut(*b).take_insts();
-        let mut kept = Vec::with_capacity(insts.len());
+        let mut kept = func.inst_buffer(insts.len());
         let mut here: Vec<StrippedPhi> = Vec::new();
-        for inst in insts {
+        for &inst in &insts {
             if let Instruction::Pseudo(PseudoInstruction::Phi { dst, id }) = inst {
-                let incoming = func.phi_operands(id).incoming.clone();
-                here.push((dst, incoming));
+                here.push((dst, func.remove_phi(id).incoming));
             } else {
                 kept.push(inst);
             }
         }
+        func.recycle_insts(insts);
         func.get_block_data_mut(*b).set_insts(kept);
         if !here.is_empty() {
             phi_headers.insert(*b, here);
@@ -72,13 +72,10 @@ pub fn destroy_ssa<I: Inst>(func: &mut Func<I>) {
     for b in &blocks {
         let bd = func.get_block_data(*b);
         let count = bd.get_terminator().map_or(0, |term| {
-            let t = term.get_branch_targets();
-            match t.as_slice() {
-                [] => 0,
-                [_] => 1,
-                [x, y] if x == y => 1,
-                _ => t.len(),
-            }
+            let mut t = func.branch_targets(&term);
+            t.sort_unstable();
+            t.dedup();
+            t.len()
         });
         succ_count.insert(*b, count);
     }
@@ -94,11 +91,7 @@ pub fn destroy_ssa<I: Inst>(func: &mut Func<I>) {
             // need phis), so the edge is critical iff pred has >1 succ.
             let insertion_block = if pred_has_multi_succ {
                 let landing = func.add_empty_block();
-                if let Some(last) = func.get_block_data_mut(pred).insts_mut().last_mut()
-                    && last.is_term()
-                {
-                    last.rewrite_branch_target(target, landing);
-                }
+                func.rewrite_branch_target(pred, target, landing);
                 func.get_block_data_mut(landing)
                     .push_inst(Instruction::new_jmp(target));
                 succ_count.insert(landing, 1);
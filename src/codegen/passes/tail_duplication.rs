@@ -0,0 +1,182 @@
+//! Tail duplication.
+//!
+//! **Requires:** Phi-free IR (run after `destroy_ssa`); every block
+//! terminated. Vregs may have several defs afterwards, which every pass
+//! past SSA destruction already allows.
+//!
+//! **Preserves:** Semantics and the entry block's identity.
+//!
+//! **Invalidates:** Block numbering when a fully duplicated block is
+//! removed; any `CFG` computed before the pass.
+//!
+//! **Effect:** A small block `B` with several predecessors is copied into
+//! each predecessor `P` that ends in `jmp B`, replacing the jump. `P` then
+//! runs straight into `B`'s terminator — a `ret` or a branch of its own —
+//! saving the jump and letting layout fall through. How large a `B` is
+//! worth copying depends on `P`'s frequency: hot (in-loop, or run more
+//! than the entry per the profile) predecessors get `hot_max_insts`, the
+//! rest `cold_max_insts`. Total
+//! growth per function is capped by `growth_budget`. Blocks containing
+//! calls or frame-sensitive pseudos are never copied.
+
+use crate::codegen::analysis::block_freq::BlockFrequency;
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::passes::block_merging::sole_jump_target;
+use crate::codegen::passes::remove_unreachable;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction};
+
+/// Size limits for `duplicate_tails`.
+#[derive(Clone, Copy, Debug)]
+pub struct TailDupConfig {
+    /// Most non-terminator instructions copied into a cold predecessor.
+    pub cold_max_insts: usize,
+    /// Most non-terminator instructions copied into a predecessor inside
+    /// a loop.
+    pub hot_max_insts: usize,
+    /// Most instructions the pass may add to one function in total.
+    pub growth_budget: usize,
+}
+
+impl Default for TailDupConfig {
+    fn default() -> Self {
+        Self {
+            cold_max_insts: 2,
+            hot_max_insts: 6,
+            growth_budget: 64,
+        }
+    }
+}
+
+/// Duplicate small multi-predecessor blocks into their jumping
+/// predecessors. Returns `true` if anything changed.
+pub fn duplicate_tails<I: Inst>(func: &mut Func<I>, config: &TailDupConfig) -> bool {
+    let Ok(cfg) = CFG::compute(func) else {
+        return false;
+    };
+    let Ok(dt) = DomTree::compute(&cfg) else {
+        return false;
+    };
+    let freq = BlockFrequency::for_func(func, &cfg, &dt);
+    let entry = cfg.get_entry_block();
+    let mut budget = config.growth_budget;
+    let mut changed = false;
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    for b in blocks {
+        if b == entry || cfg.preds(b).len() < 2 || !duplicable(func, b) {
+            continue;
+        }
+        let body = func.get_block_data(b).len() - 1;
+        for &p in cfg.preds(b) {
+            let limit = if freq.freq(p) > 1 {
+                config.hot_max_insts
+            } else {
+                config.cold_max_insts
+            };
+            if p == b || body > limit || body + 1 > budget {
+                continue;
+            }
+            // The CFG is a snapshot; `p` may already have absorbed a
+            // different tail, so re-check its terminator.
+            if sole_jump_target(func, p) != Some(b) {
+                continue;
+            }
+            let tail = func.get_block_data(b).insts().to_vec();
+            let insts = func.get_block_data_mut(p).insts_mut();
+            insts.pop();
+            insts.extend(tail);
+            budget -= body + 1;
+            stat!("tail-dup", "duplicated", "tails copied into a predecessor");
+            stat!("tail-dup", "insts", "instructions added by duplication", body);
+            changed = true;
+        }
+    }
+    if changed {
+        remove_unreachable(func);
+    }
+    changed
+}
+
+/// Only plain straight-line code with an ordinary terminator may be
+/// copied: calls, stack allocations, frame pseudos and jump tables carry
+/// identity that later passes key on.
+fn duplicable<I: Inst>(func: &Func<I>, b: Block) -> bool {
+    let bd = func.get_block_data(b);
+    bd.get_terminator().is_some()
+        && bd.iter().all(|inst| match inst {
+            Instruction::Target(t) => !t.is_call() && t.jump_table().is_none(),
+            Instruction::Pseudo(p) => matches!(
+                p,
+                PseudoInstruction::Copy { .. }
+                    | PseudoInstruction::Return { .. }
+                    | PseudoInstruction::Kill { .. }
+            ),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+
+    /// `b0: jz b1, b2`; `b1, b2: jmp b3`; `b3: <extra adds>; ret`.
+    fn diamond(extra: usize) -> Func<X64Inst> {
+        let mut func = Func::<X64Inst>::new("d".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        let v = func.new_vreg();
+        func.get_block_data_mut(b0)
+            .push_target_inst(X64Inst::Mov64ri { dst: v, imm: 1 });
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: Cond::Z,
+            taken: b1,
+            not_taken: b2,
+        });
+        for (p, imm) in [(b1, 2), (b2, 3)] {
+            func.get_block_data_mut(p)
+                .push_target_inst(X64Inst::Add64ri32 { dst: v, imm });
+            func.get_block_data_mut(p)
+                .push_target_inst(X64Inst::Jmp { dst: b3 });
+        }
+        for _ in 0..extra {
+            func.get_block_data_mut(b3)
+                .push_target_inst(X64Inst::Add64ri32 { dst: v, imm: 1 });
+        }
+        func.get_block_data_mut(b3)
+            .push_pseudo_inst(PseudoInstruction::Return { src: v });
+        func
+    }
+
+    #[test]
+    fn small_join_is_copied_into_both_arms_and_removed() {
+        let mut func = diamond(1);
+        assert!(duplicate_tails(&mut func, &TailDupConfig::default()));
+        assert_eq!(func.blocks_count(), 3);
+        for (_, bd) in func.blocks_iter().skip(1) {
+            assert_eq!(bd.len(), 3);
+            assert!(bd.get_terminator().unwrap().is_ret());
+        }
+    }
+
+    #[test]
+    fn join_over_the_cold_limit_is_kept() {
+        let mut func = diamond(3);
+        assert!(!duplicate_tails(&mut func, &TailDupConfig::default()));
+        assert_eq!(func.blocks_count(), 4);
+    }
+
+    #[test]
+    fn growth_budget_stops_after_the_first_copy() {
+        let mut func = diamond(1);
+        let config = TailDupConfig {
+            growth_budget: 2,
+            ..TailDupConfig::default()
+        };
+        assert!(duplicate_tails(&mut func, &config));
+        // One arm took the tail; the other still jumps to the join.
+        assert_eq!(func.blocks_count(), 4);
+    }
+}
@@ -0,0 +1,286 @@
+//! Symbolic checker for register-allocation results.
+//!
+//! **Requires:** `ra` was computed for `func` as it stands (program points
+//! line up with `BlockLayout::compute(func)`), and `cfg` is `func`'s CFG.
+//!
+//! **Effect:** Replays the function abstractly, tracking for every
+//! location (preg or spill slot) the set of vregs whose current value it
+//! holds. Instruction `k` first runs the allocator's edits pending at its
+//! use- and def-point (a reload is preg := slot, a split store
+//! slot := preg, a move preg := preg), then reads each use from the location the
+//! allocation names at the use-point — which must hold that vreg — and
+//! finally writes each def into its def-point location, evicting the
+//! vreg from everywhere else. A `Copy` carries its source's set into the
+//! destination, so a coalesced copy keeps both names. Block-entry state is
+//! the intersection of the predecessors' exit states, iterated to a
+//! fixpoint over a worklist; the final pass reports the first read of a
+//! stale or missing value. This validates the allocator independently of
+//! how it made its decisions.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use thiserror::Error;
+
+use crate::codegen::analysis::cfg::{CFG, reverse_post_order};
+use crate::codegen::analysis::layout::{BlockLayout, ProgramPoint};
+use crate::codegen::regalloc::{AllocatedSlot, Edit, RegAllocResult};
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction, Reg};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CheckError {
+    #[error("{block} inst {inst}: vreg {vreg} has no location at point {pt}")]
+    NoLocation {
+        block: Block,
+        inst: usize,
+        vreg: Reg,
+        pt: ProgramPoint,
+    },
+
+    #[error("{block} inst {inst}: vreg {vreg} read from {slot:?}, which holds {found:?}")]
+    StaleValue {
+        block: Block,
+        inst: usize,
+        vreg: Reg,
+        slot: AllocatedSlot,
+        found: Vec<Reg>,
+    },
+}
+
+impl CheckError {
+    /// The block and index of the instruction that read the bad value.
+    #[must_use]
+    pub fn position(&self) -> (Block, usize) {
+        match *self {
+            Self::NoLocation { block, inst, .. } | Self::StaleValue { block, inst, .. } => {
+                (block, inst)
+            }
+        }
+    }
+}
+
+/// Location → vregs whose value it currently holds.
+type State = HashMap<AllocatedSlot, HashSet<Reg>>;
+
+/// Check that every use in `func` reads its own value under `ra`.
+pub fn check_allocation<I: Inst>(
+    func: &Func<I>,
+    cfg: &CFG,
+    ra: &RegAllocResult,
+) -> Result<(), CheckError> {
+    let layout = BlockLayout::compute(func);
+    let checker = Checker {
+        func,
+        ra,
+        layout: &layout,
+    };
+
+    // Blocks missing from `out` are unreached: the top of the lattice.
+    let rpo = reverse_post_order(cfg);
+    let mut out: HashMap<Block, State> = HashMap::new();
+    let mut worklist: VecDeque<Block> = rpo.iter().copied().collect();
+    let mut queued: HashSet<Block> = rpo.iter().copied().collect();
+    while let Some(b) = worklist.pop_front() {
+        queued.remove(&b);
+        let mut state = entry_state(cfg, b, &out);
+        checker.run_block(b, &mut state, false)?;
+        if out.get(&b) != Some(&state) {
+            out.insert(b, state);
+            for &s in cfg.succs(b) {
+                if queued.insert(s) {
+                    worklist.push_back(s);
+                }
+            }
+        }
+    }
+    for &b in &rpo {
+        let mut state = entry_state(cfg, b, &out);
+        checker.run_block(b, &mut state, true)?;
+    }
+    Ok(())
+}
+
+/// Meet of the reached predecessors' exit states.
+fn entry_state(cfg: &CFG, b: Block, out: &HashMap<Block, State>) -> State {
+    let mut reached = cfg.preds(b).iter().filter_map(|p| out.get(p));
+    let Some(first) = reached.next() else {
+        return State::new();
+    };
+    let mut state = first.clone();
+    for other in reached {
+        state.retain(|loc, vregs| {
+            let Some(theirs) = other.get(loc) else {
+                return false;
+            };
+            vregs.retain(|v| theirs.contains(v));
+            !vregs.is_empty()
+        });
+    }
+    state
+}
+
+struct Checker<'a, I: Inst> {
+    func: &'a Func<I>,
+    ra: &'a RegAllocResult,
+    layout: &'a BlockLayout,
+}
+
+impl<I: Inst> Checker<'_, I> {
+    fn location(
+        &self,
+        block: Block,
+        inst: usize,
+        vreg: Reg,
+        pt: ProgramPoint,
+    ) -> Result<AllocatedSlot, CheckError> {
+        self.ra.at(vreg, pt).ok_or(CheckError::NoLocation {
+            block,
+            inst,
+            vreg,
+            pt,
+        })
+    }
+
+    /// Step `state` through `block`. Reads are only validated when
+    /// `report` is set; the fixpoint iterations just propagate.
+    fn run_block(&self, block: Block, state: &mut State, report: bool) -> Result<(), CheckError> {
+        for (idx, inst) in self.func.get_block_data(block).iter().enumerate() {
+            let use_pt = self.layout.use_pt(block, idx as u32);
+            let def_pt = self.layout.def_pt(block, idx as u32);
+
+            for &(_, edit) in self.ra.edits_at(use_pt..=def_pt) {
+                let (from, to) = match edit {
+                    Edit::Store { from, to } => {
+                        (AllocatedSlot::Reg(from), AllocatedSlot::Stack(to))
+                    }
+                    Edit::Reload { from, to } => {
+                        (AllocatedSlot::Stack(from), AllocatedSlot::Reg(to))
+                    }
+                    Edit::Move { from, to } => (AllocatedSlot::Reg(from), AllocatedSlot::Reg(to)),
+                };
+                let held = state.get(&from).cloned().unwrap_or_default();
+                state.insert(to, held);
+            }
+
+            for v in inst.get_uses() {
+                let slot = self.location(block, idx, v, use_pt)?;
+                if report && !state.get(&slot).is_some_and(|s| s.contains(&v)) {
+                    let mut found: Vec<Reg> =
+                        state.get(&slot).into_iter().flatten().copied().collect();
+                    found.sort_unstable();
+                    return Err(CheckError::StaleValue {
+                        block,
+                        inst: idx,
+                        vreg: v,
+                        slot,
+                        found,
+                    });
+                }
+            }
+
+            let carried = match inst {
+                Instruction::Pseudo(PseudoInstruction::Copy { src, .. }) => self
+                    .ra
+                    .at(*src, use_pt)
+                    .and_then(|s| state.get(&s))
+                    .cloned(),
+                _ => None,
+            };
+            for v in inst.get_defs() {
+                let slot = self.location(block, idx, v, def_pt)?;
+                for held in state.values_mut() {
+                    held.remove(&v);
+                }
+                let mut held = carried.clone().unwrap_or_default();
+                held.insert(v);
+                state.insert(slot, held);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::liveness::Segment;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::codegen::regalloc::Assignment;
+    use crate::support::slotmap::{Key, SecondaryMap};
+
+    /// `v0 = 1; v1 = 2; add v0, v1; ret v0` with v0 in preg 0 and v1 in
+    /// `v1_slot`.
+    fn check_with_v1_in(v1_slot: AllocatedSlot) -> Result<(), CheckError> {
+        check_with(&[(1, 7, 0)], v1_slot, Vec::new())
+    }
+
+    /// The same function with v0 in the `(start, end, preg)` pieces and
+    /// `edits` spliced in.
+    fn check_with(
+        v0_pieces: &[(u32, u32, Reg)],
+        v1_slot: AllocatedSlot,
+        edits: Vec<(ProgramPoint, Edit)>,
+    ) -> Result<(), CheckError> {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b = func.add_empty_block();
+        let (v0, v1) = (func.new_vreg(), func.new_vreg());
+        let bd = func.get_block_data_mut(b);
+        bd.push_target_inst(X64Inst::Mov64ri { dst: v0, imm: 1 });
+        bd.push_target_inst(X64Inst::Mov64ri { dst: v1, imm: 2 });
+        bd.push_target_inst(X64Inst::Add64rr { dst: v0, src: v1 });
+        bd.push_pseudo_inst(PseudoInstruction::Return { src: v0 });
+        let cfg = CFG::compute(&func).expect("valid CFG");
+
+        let mut assignments = SecondaryMap::new(2);
+        let seg = |start, end| Segment { start, end };
+        assignments.set(
+            v0,
+            Assignment {
+                pieces: v0_pieces
+                    .iter()
+                    .map(|&(start, end, p)| (seg(start, end), AllocatedSlot::Reg(p)))
+                    .collect(),
+            },
+        );
+        assignments.set(
+            v1,
+            Assignment {
+                pieces: [(seg(3, 5), v1_slot)].into_iter().collect(),
+            },
+        );
+        let ra = RegAllocResult {
+            assignments,
+            frame_layout: Vec::new(),
+            frame_size: 0,
+            edits,
+        };
+        check_allocation(&func, &cfg, &ra)
+    }
+
+    #[test]
+    fn disjoint_registers_pass_and_a_shared_register_is_caught() {
+        assert_eq!(check_with_v1_in(AllocatedSlot::Reg(1)), Ok(()));
+        // v1's def overwrites v0's register before the add reads v0.
+        assert_eq!(
+            check_with_v1_in(AllocatedSlot::Reg(0)),
+            Err(CheckError::StaleValue {
+                block: Block::new(0),
+                inst: 2,
+                vreg: 0,
+                slot: AllocatedSlot::Reg(0),
+                found: vec![1],
+            })
+        );
+    }
+
+    #[test]
+    fn a_move_edit_carries_the_value_to_its_new_preg() {
+        // v0 moves from preg 0 to preg 2 before v1's def.
+        let pieces = [(1, 3, 0), (3, 7, 2)];
+        let mv = vec![(3, Edit::Move { from: 0, to: 2 })];
+        assert_eq!(check_with(&pieces, AllocatedSlot::Reg(1), mv), Ok(()));
+        assert!(matches!(
+            check_with(&pieces, AllocatedSlot::Reg(1), Vec::new()),
+            Err(CheckError::StaleValue { inst: 2, vreg: 0, .. })
+        ));
+    }
+}
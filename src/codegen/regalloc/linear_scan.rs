@@ -16,39 +16,71 @@
 //! `u` to hand its preg to a higher-priority `v`, `u` does not go to the
 //! stack for its whole life. Instead we split `u` at `v`'s first point:
 //! `u` keeps its preg for `[u.first_start, split_point)` and moves to a
-//! stack slot for `[split_point, u.last_end)`. A `SplitMove` is recorded
+//! stack slot for `[split_point, u.last_end)`. An `Edit::Store` is recorded
 //! so the emitter can save the preg into the slot immediately before `v`'s
 //! defining instruction. Later uses of `u` inside the Reg piece still load
 //! from the preg (fast); uses inside the Stack piece load from the slot.
 //! This dramatically cuts memory traffic compared to whole-vreg spilling.
+//! Edges that jump from the Reg piece into the Stack piece get a store of
+//! their own, and a split that a loop's back edge would cross is hoisted
+//! to the loop header, since pieces are positional and nothing reloads
+//! the preg on the way back.
+//!
+//! **Loop-aware spill placement.** A split hoisted to a loop header puts
+//! the store in the preheader (as an edge store) rather than inside the
+//! loop. Once past the loop the vreg is requeued: at the first block
+//! after it that control can only reach through the loop's Stack piece,
+//! it gets a preg back for the rest of its life if one is free there, and
+//! an `Edit::Reload` loads it from the slot. So a value the loop pushed out
+//! is read from memory inside the loop only, not for the rest of the
+//! function. Defs inside the loop write the slot, so the reload sees them.
 //!
 //! The allocator also does:
 //!
-//! * **Hint-based Copy coalescing.** On a `PseudoInstruction::Copy { dst,
-//!   src }`, the dst's hint is src's preg; if available for dst's full
-//!   range, assigned. Eliminates the copy in `pseudo_cleanup`.
+//! * **Hint-based Copy coalescing.** Vregs joined by
+//!   `PseudoInstruction::Copy` chains form one class (a union-find); a
+//!   vreg's hint is the preg last given to its class, assigned if
+//!   available for the vreg's full range. Eliminates the copy in
+//!   `pseudo_cleanup`.
 //! * **Pre-binds enforced by eviction.** When a vreg is pre-bound (e.g. an
 //!   ABI arg shim), any active or inactive vreg blocking the target preg
 //!   across the pre-bound vreg's range is split/evicted.
-//! * **Farthest-endpoint spill heuristic.** When choosing who to evict,
-//!   prefer an interval that outlives `v` (more future uses on the stack
-//!   is still cheaper than stalling `v`).
+//! * **Coldest-use, farthest-endpoint spill heuristic.** When choosing
+//!   who to evict, only intervals that outlive `v` qualify (more future
+//!   uses on the stack is still cheaper than stalling `v`). Among them,
+//!   prefer the one whose remaining uses run least often per
+//!   `BlockFrequency` — measured when the function carries a profile —
+//!   then the one ending farthest away.
+//! * **Compact registers on request.** Under
+//!   `RegAllocConfig::prefer_compact_regs`, a preg from `compact_regs`
+//!   beats the rest of the pool whenever one is free for the whole range.
+//! * **Planned spills honored.** A vreg in `RegAllocConfig::spilled` goes
+//!   straight to a stack slot; `spill::plan_spills` already left room in
+//!   registers for everything else.
 
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
+use crate::codegen::analysis::block_freq::BlockFrequency;
 use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::dom_tree::DomTree;
 use crate::codegen::analysis::layout::{BlockLayout, ProgramPoint};
 use crate::codegen::analysis::liveness::{LiveRanges, Segment};
+use crate::codegen::regalloc::range_index::RangeIndex;
+use crate::codegen::stats::stat;
 use crate::codegen::regalloc::{
-    AllocatedSlot, Assignment, RegAllocConfig, RegAllocResult, RegAllocator, SplitMove, StackSlot,
+    AllocatedSlot, Assignment, Edit, RegAllocConfig, RegAllocResult, RegAllocator, StackSlot,
 };
 use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg, Type};
 use crate::support::slotmap::SecondaryMap;
+use crate::support::trace::{debug_event, enter_span};
+use crate::support::union_find::UnionFind;
 
 pub struct LinearScan;
 
 impl<I: Inst> RegAllocator<I> for LinearScan {
     fn allocate(func: &Func<I>, cfg: &CFG, config: &RegAllocConfig) -> RegAllocResult {
+        enter_span!("regalloc", func = func.name());
         let layout = BlockLayout::compute(func);
         Allocator::new(func, cfg, &layout, config).run()
     }
@@ -56,9 +88,14 @@ impl<I: Inst> RegAllocator<I> for LinearScan {
 
 struct Allocator<'a, I: Inst> {
     func: &'a Func<I>,
+    cfg: &'a CFG,
+    layout: &'a BlockLayout,
     config: &'a RegAllocConfig,
     ranges: LiveRanges,
-    copy_src: SecondaryMap<Reg, Option<Reg>>,
+    /// Vregs joined by `Copy`s, transitively: the coalescing classes.
+    copy_classes: UnionFind<Reg>,
+    /// Preg most recently given to a member of each class, by class root.
+    class_preg: HashMap<Reg, Reg>,
 
     /// Merged view of `config.reg_bind` + in-stream `RegDef` pseudos.
     /// Both sources contribute whole-life pins; if a vreg is pinned from
@@ -76,11 +113,22 @@ struct Allocator<'a, I: Inst> {
     /// Accumulator for the final output.
     assignments: SecondaryMap<Reg, Assignment>,
 
+    /// Each vreg's use points, in order, with the frequency of their
+    /// block.
+    use_freqs: Vec<Vec<(ProgramPoint, u32)>>,
+
     active: Vec<Reg>,
     inactive: Vec<Reg>,
+    /// Segments of the vregs currently holding each preg, by
+    /// `(is_fp, preg)`. Answers `compute_blocked_at` without walking
+    /// `active` / `inactive`.
+    occupancy: HashMap<(bool, Reg), RangeIndex>,
 
     frame_layout: Vec<usize>,
-    split_moves: Vec<SplitMove>,
+    edits: Vec<(ProgramPoint, Edit)>,
+    /// Vregs split to the stack around a loop, by the point after it at
+    /// which they may get a preg back.
+    pending_reloads: BinaryHeap<Reverse<(ProgramPoint, Reg)>>,
 }
 
 impl<'a, I: Inst> Allocator<'a, I> {
@@ -90,8 +138,9 @@ impl<'a, I: Inst> Allocator<'a, I> {
         layout: &'a BlockLayout,
         config: &'a RegAllocConfig,
     ) -> Self {
-        let ranges = LiveRanges::compute(func, cfg, layout);
-        let copy_src = collect_copy_src(func);
+        let ranges = LiveRanges::compute(func, cfg, layout)
+            .unwrap_or_else(|e| panic!("liveness failed in `{}`: {e}", func.name()));
+        let copy_classes = collect_copy_classes(func);
         let effective_binds = merge_pre_binds(config, func);
         let n = func.get_regs_count();
         let mut assignments = SecondaryMap::new(n);
@@ -106,19 +155,37 @@ impl<'a, I: Inst> Allocator<'a, I> {
                 "aggregate vreg {i} reached regalloc — lower_aggregates must run first"
             );
         }
+        let freq = DomTree::compute(cfg)
+            .ok()
+            .map(|dt| BlockFrequency::for_func(func, cfg, &dt));
+        let mut use_freqs = vec![Vec::new(); n];
+        for &b in &layout.order {
+            let f = freq.as_ref().map_or(1, |bf| bf.freq(b));
+            for (i, inst) in func.get_block_data(b).insts().iter().enumerate() {
+                for r in inst.get_uses() {
+                    use_freqs[r as usize].push((layout.use_pt(b, i as u32), f));
+                }
+            }
+        }
         Self {
             func,
+            cfg,
+            layout,
             config,
             ranges,
-            copy_src,
+            copy_classes,
+            class_preg: HashMap::new(),
             effective_binds,
             current_slot: vec![None; n],
             current_piece_start: vec![0; n],
             assignments,
             active: Vec::new(),
             inactive: Vec::new(),
+            use_freqs,
+            occupancy: HashMap::new(),
             frame_layout: Vec::new(),
-            split_moves: Vec::new(),
+            edits: Vec::new(),
+            pending_reloads: BinaryHeap::new(),
         }
     }
 
@@ -155,6 +222,7 @@ impl<'a, I: Inst> Allocator<'a, I> {
 
         for v in order {
             let position = self.ranges[v].first_start().unwrap();
+            self.reload_until(position);
             self.advance(position);
             self.allocate(v, position);
             if let Some(AllocatedSlot::Reg(_)) = self.current_slot[v as usize] {
@@ -162,6 +230,8 @@ impl<'a, I: Inst> Allocator<'a, I> {
             }
         }
 
+        self.reload_until(ProgramPoint::MAX);
+
         // Finalize: close every open piece at its vreg's last_end.
         for v_idx in 0..self.current_slot.len() {
             if self.current_slot[v_idx].is_none() {
@@ -173,11 +243,13 @@ impl<'a, I: Inst> Allocator<'a, I> {
         }
 
         let frame_size = (self.frame_layout.len() * 8) as u32;
+        // Stable: edits at one point keep the order they were recorded in.
+        self.edits.sort_by_key(|&(at, _)| at);
         RegAllocResult {
             assignments: self.assignments,
             frame_layout: self.frame_layout,
             frame_size,
-            split_moves: self.split_moves,
+            edits: self.edits,
         }
     }
 
@@ -213,35 +285,62 @@ impl<'a, I: Inst> Allocator<'a, I> {
     fn allocate(&mut self, v: Reg, position: ProgramPoint) {
         if let Some(&target) = self.effective_binds.get(&v) {
             self.evict_conflicts_on(target, v, position);
+            debug_event!(vreg = v, preg = target, "assign: pre-bound");
+            stat!("regalloc", "pre-bound", "vregs given their pre-bound preg");
             self.assign_fresh_reg(v, target);
             return;
         }
+        if self.config.spilled.contains(&v) {
+            debug_event!(vreg = v, "spill: planned before allocation");
+            stat!("regalloc", "pre-spilled", "vregs the spill planner put in memory");
+            self.assign_fresh_stack(v);
+            return;
+        }
 
         let v_end = self.ranges[v].last_end().unwrap();
         let blocked_at = self.compute_blocked_at(v, position);
 
-        if let Some(hint) = self.copy_hint(v)
+        if self.config.coalesce
+            && let Some(hint) = self.copy_hint(v)
             && self.pool_for(v).contains(&hint)
             && blocked_at.get(&hint).copied().unwrap_or(0) >= v_end
         {
+            debug_event!(vreg = v, preg = hint, "assign: copy hint");
+            stat!("regalloc", "hinted", "vregs given their copy-hint preg");
             self.assign_fresh_reg(v, hint);
             return;
         }
 
+        // Among pregs free for the whole range, a compact one if wanted;
+        // otherwise whichever stays free longest.
+        let compact =
+            |p: Reg| self.config.prefer_compact_regs && self.config.compact_regs.contains(&p);
         let best = self
             .pool_for(v)
             .iter()
             .map(|&p| (p, blocked_at.get(&p).copied().unwrap_or(0)))
-            .max_by_key(|&(_, fu)| fu);
+            .max_by_key(|&(p, fu)| (fu >= v_end && compact(p), fu));
 
         if let Some((p, fu)) = best
             && fu >= v_end
         {
+            debug_event!(vreg = v, preg = p, "assign: free for the whole range");
+            stat!("regalloc", "assigned", "vregs given a preg free for the whole range");
             self.assign_fresh_reg(v, p);
             return;
         }
 
         if let Some((u, p)) = self.pick_eviction_candidate(v, position, v_end) {
+            debug_event!(
+                vreg = v,
+                preg = p,
+                evicted = u,
+                needed_until = v_end,
+                best_free_until = ?best.map(|(_, fu)| fu),
+                "assign: no preg free for the whole range; evicting its sole holder, \
+                 which lives longer"
+            );
+            stat!("regalloc", "evicted", "vregs split to free a preg for a shorter range");
             self.evict_to_stack(u, position);
             self.active.retain(|&x| x != u);
             self.inactive.retain(|&x| x != u);
@@ -249,39 +348,31 @@ impl<'a, I: Inst> Allocator<'a, I> {
             return;
         }
 
+        debug_event!(
+            vreg = v,
+            needed_until = v_end,
+            best_free_until = ?best.map(|(_, fu)| fu),
+            "spill: no preg free for the whole range and no single holder outlives it"
+        );
+        stat!("regalloc", "spilled", "vregs spilled for their whole range");
         self.assign_fresh_stack(v);
     }
 
+    /// First point `>= position` at which each preg of `v`'s pool is held
+    /// by another vreg while `v` is live; `ProgramPoint::MAX` if never.
     fn compute_blocked_at(&self, v: Reg, position: ProgramPoint) -> HashMap<Reg, ProgramPoint> {
-        let mut blocked_at: HashMap<Reg, ProgramPoint> = HashMap::new();
-        for &p in self.pool_for(v) {
-            blocked_at.insert(p, ProgramPoint::MAX);
-        }
-        for &u in &self.active {
-            if !self.same_class(u, v) {
-                continue;
-            }
-            let p = self.current_preg(u);
-            if blocked_at.contains_key(&p) {
-                blocked_at.insert(p, position);
-            }
-        }
+        let fp = self.is_fp(v);
         let v_range = &self.ranges[v];
-        for &u in &self.inactive {
-            if !self.same_class(u, v) {
-                continue;
-            }
-            let p = self.current_preg(u);
-            if !blocked_at.contains_key(&p) {
-                continue;
-            }
-            let u_range = &self.ranges[u];
-            if let Some(pt) = u_range.next_intersection_at_or_after(v_range, position) {
-                let prev = blocked_at[&p];
-                blocked_at.insert(p, prev.min(pt));
-            }
-        }
-        blocked_at
+        self.pool_for(v)
+            .iter()
+            .map(|&p| {
+                let blocked = self
+                    .occupancy
+                    .get(&(fp, p))
+                    .and_then(|idx| idx.next_overlap(v_range, position));
+                (p, blocked.unwrap_or(ProgramPoint::MAX))
+            })
+            .collect()
     }
 
     fn pick_eviction_candidate(
@@ -291,7 +382,7 @@ impl<'a, I: Inst> Allocator<'a, I> {
         v_end: ProgramPoint,
     ) -> Option<(Reg, Reg)> {
         let v_range = &self.ranges[v];
-        let mut best: Option<(Reg, Reg, ProgramPoint)> = None;
+        let mut best: Option<(Reg, Reg, (Reverse<u32>, ProgramPoint))> = None;
 
         for &p in self.pool_for(v) {
             // Sole-blocker detection: count and keep the last witness.
@@ -339,14 +430,22 @@ impl<'a, I: Inst> Allocator<'a, I> {
             if u_end <= v_end {
                 continue;
             }
-            if best.is_none() || u_end > best.unwrap().2 {
-                best = Some((u, p, u_end));
+            let key = (Reverse(self.hottest_use_from(u, position)), u_end);
+            if best.is_none_or(|(_, _, k)| key > k) {
+                best = Some((u, p, key));
             }
         }
 
         best.map(|(u, p, _)| (u, p))
     }
 
+    /// Frequency of the most often run use of `u` at or after `position`.
+    fn hottest_use_from(&self, u: Reg, position: ProgramPoint) -> u32 {
+        let uses = &self.use_freqs[u as usize];
+        let from = uses.partition_point(|&(pt, _)| pt < position);
+        uses[from..].iter().map(|&(_, f)| f).max().unwrap_or(0)
+    }
+
     fn evict_conflicts_on(&mut self, target: Reg, v: Reg, position: ProgramPoint) {
         let v_range = &self.ranges[v];
         let mut seen = std::collections::HashSet::new();
@@ -373,6 +472,8 @@ impl<'a, I: Inst> Allocator<'a, I> {
             }
         }
         for u in conflicts {
+            debug_event!(vreg = u, preg = target, for_vreg = v, "evict: preg is pre-bound");
+            stat!("regalloc", "evicted-pre-bind", "vregs split off a pre-bound preg");
             assert!(
                 !self.effective_binds.contains_key(&u),
                 "pre-bind conflict: vreg {u} also pre-bound to preg {target}, can't evict for vreg {v}"
@@ -383,32 +484,10 @@ impl<'a, I: Inst> Allocator<'a, I> {
         }
     }
 
-    /// Walk the `Copy` chain from `v` and return the preg of the nearest
-    /// ancestor already in a Reg slot; `None` if the chain bottoms out on
-    /// a stack/unallocated link or cycles.
-    fn copy_hint(&self, v: Reg) -> Option<Reg> {
-        let mut cur = self.copy_src.get(v).copied().flatten()?;
-        let mut visited = [u32::MAX; 16];
-        visited[0] = v;
-        let mut n = 1;
-        let pool = self.pool_for(v);
-        while n < visited.len() {
-            if visited[..n].contains(&cur) {
-                return None;
-            }
-            visited[n] = cur;
-            n += 1;
-            if let Some(AllocatedSlot::Reg(p)) = self
-                .current_slot
-                .get(cur as usize)
-                .and_then(|s| s.as_ref())
-                && pool.contains(p)
-            {
-                return Some(*p);
-            }
-            cur = self.copy_src.get(cur).copied().flatten()?;
-        }
-        None
+    /// Preg last assigned to a member of `v`'s copy class.
+    fn copy_hint(&mut self, v: Reg) -> Option<Reg> {
+        let root = self.copy_classes.find(v);
+        self.class_preg.get(&root).copied()
     }
 
     // ---- Piece-lifecycle helpers ----
@@ -419,6 +498,12 @@ impl<'a, I: Inst> Allocator<'a, I> {
         let start = self.ranges[v].first_start().unwrap();
         self.current_slot[v as usize] = Some(AllocatedSlot::Reg(p));
         self.current_piece_start[v as usize] = start;
+        self.occupancy
+            .entry((self.is_fp(v), p))
+            .or_default()
+            .insert(v, &self.ranges[v]);
+        let root = self.copy_classes.find(v);
+        self.class_preg.insert(root, p);
     }
 
     /// First-time Stack assignment: the vreg never gets a preg.
@@ -433,15 +518,16 @@ impl<'a, I: Inst> Allocator<'a, I> {
     /// Closes its Reg piece, opens a Stack piece, records the store
     /// moves.
     ///
-    /// **Correctness across branches.** If u has multiple live-range
-    /// segments (e.g. post-SSA-destruction phi destinations with
-    /// Copies in several predecessors, or a value live in and live
-    /// out of a diamond), placing a SplitMove only at `split_pt`
-    /// leaves the stack slot uninitialized on paths that bypass
-    /// `split_pt`. We emit an additional SplitMove at the start of
-    /// every segment that starts on or before `split_pt`, so whichever
-    /// path is taken, the preg's value is mirrored to the stack slot
-    /// before any later read from the slot.
+    /// **Correctness across branches.** Control can enter the Stack piece
+    /// either by running through `split_pt` or along a CFG edge that
+    /// jumps over it (out of a diamond arm that never reaches the split,
+    /// or around a loop). A store at `split_pt` covers the first; every
+    /// edge from a block ending in the Reg piece to a block starting in
+    /// the Stack piece with `u` live in gets its own store just before
+    /// the predecessor's terminator. Edges the other way — a latch in the
+    /// Stack piece jumping back to a header in the Reg piece — can't be
+    /// repaired with a store, so `loop_safe_split_point` first moves the
+    /// split up to the outermost such header.
     fn evict_to_stack(&mut self, u: Reg, split_pt: ProgramPoint) {
         let AllocatedSlot::Reg(p) = self
             .current_slot[u as usize]
@@ -449,65 +535,158 @@ impl<'a, I: Inst> Allocator<'a, I> {
         else {
             panic!("evict_to_stack called on vreg already on stack");
         };
+        let split_pt = self.loop_safe_split_point(u, split_pt);
         self.close_piece(u, split_pt);
+        if let Some(idx) = self.occupancy.get_mut(&(self.is_fp(u), p)) {
+            idx.truncate(u, &self.ranges[u], split_pt);
+        }
         let s = self.fresh_slot();
         self.current_slot[u as usize] = Some(AllocatedSlot::Stack(s));
         self.current_piece_start[u as usize] = split_pt;
-        // Primary SplitMove at split_pt.
-        self.split_moves.push(SplitMove {
-            at_point: split_pt,
-            from_preg: p,
-            to_slot: s,
-        });
-        // When `u` has more than one segment, the primary SplitMove
-        // at `split_pt` only fires on paths that actually reach that
-        // point. For segments that don't reach `split_pt` (e.g. a
-        // branch-side def whose uses later read the stack slot), we
-        // emit an additional SplitMove that captures the preg's value
-        // immediately after the segment becomes live. Program points
-        // alternate `use_pt` (even) / `def_pt` (odd), and the
-        // emitter only fires SplitMoves that match an instruction's
-        // `def_pt`. So we map each segment start to the next `def_pt`
-        // where the preg has settled with `u`'s value:
-        //
-        // * segment starts at a block-entry `use_pt` (live-in): the
-        //   preg already carries `u` when the block opens — save
-        //   right before the first instruction runs, i.e. at that
-        //   instruction's `def_pt = start + 1`.
-        // * segment starts at a `def_pt` (u is produced by this
-        //   instruction): the preg holds `u` *after* the instruction
-        //   finishes — save just before the next instruction, i.e.
-        //   at `def_pt + 2` (which is the next instruction's `def_pt`).
-        let segs = self.ranges[u].segments().to_vec();
-        if segs.len() > 1 {
-            for seg in &segs {
-                if seg.start >= split_pt {
-                    break;
-                }
-                let save_pt = if seg.start % 2 == 0 {
-                    // Live-in segment: save at this block's first
-                    // instruction's def_pt.
-                    seg.start + 1
-                } else {
-                    // Def segment: save after the defining instruction
-                    // completes (i.e., at the next instruction's
-                    // def_pt).
-                    seg.start + 2
-                };
-                if save_pt >= split_pt {
-                    continue;
-                }
-                // Don't emit past the segment's own end — if the
-                // segment is just one point wide (dead def), there's
-                // no later instruction to save before.
-                if save_pt >= seg.end {
-                    continue;
+        // Primary store at split_pt. Stores only fire at def points; a split hoisted to a block start relies on the
+        // edge stores alone.
+        if split_pt % 2 == 1 {
+            self.edits.push((split_pt, Edit::Store { from: p, to: s }));
+        }
+        let layout = self.layout;
+        let mut edge_saves: Vec<ProgramPoint> = layout
+            .order
+            .iter()
+            .filter(|&&t| {
+                let start = layout.block_start_pt(t);
+                start >= split_pt && self.ranges[u].covers(start)
+            })
+            .flat_map(|&t| self.cfg.preds(t))
+            .map(|&q| layout.block_end_pt(q))
+            .filter(|&end| end <= split_pt)
+            // `end - 1` is the terminator's def point.
+            .map(|end| end - 1)
+            .collect();
+        edge_saves.sort_unstable();
+        edge_saves.dedup();
+        debug_event!(
+            vreg = u,
+            preg = p,
+            slot = s,
+            at = split_pt,
+            edge_stores = edge_saves.len(),
+            "split: stack from here on"
+        );
+        stat!("regalloc", "edge-stores", "split stores placed on incoming edges", edge_saves.len());
+        self.edits.extend(edge_saves.into_iter().map(|at| (at, Edit::Store { from: p, to: s })));
+        if let Some(at) = self.reload_point(u, split_pt) {
+            self.pending_reloads.push(Reverse((at, u)));
+        }
+    }
+
+    /// Where `u`, moved to the stack at `split_pt`, may take a preg again:
+    /// the start of the first block past a loop headed at `split_pt` that
+    /// `u` is live into and that only the Stack piece can jump to. No edge
+    /// with `u` live at its target may cross that point in either
+    /// direction — not a back edge into the Stack piece (the reloaded preg
+    /// would not be stored again) and not a jump over it from before
+    /// (which would skip the reload). `None` if `split_pt` is not a loop
+    /// header or no block qualifies.
+    fn reload_point(&self, u: Reg, split_pt: ProgramPoint) -> Option<ProgramPoint> {
+        let layout = self.layout;
+        let range = &self.ranges[u];
+        let header = layout.order.iter().find(|&&b| layout.block_start_pt(b) == split_pt)?;
+        if !self.cfg.preds(*header).iter().any(|&q| layout.block_end_pt(q) > split_pt) {
+            return None;
+        }
+        let crossed = |at: ProgramPoint| {
+            self.cfg.edges().any(|(q, t)| {
+                let (end, start) = (layout.block_end_pt(q), layout.block_start_pt(t));
+                let crosses = if end > at { start <= at } else { start > at };
+                crosses && start >= split_pt && range.covers(start)
+            })
+        };
+        layout
+            .order
+            .iter()
+            .map(|&b| layout.block_start_pt(b))
+            .filter(|&start| start > split_pt && range.covers(start))
+            .find(|&start| !crossed(start))
+    }
+
+    /// Give a preg back to every vreg whose reload point is at or before
+    /// `position`.
+    fn reload_until(&mut self, position: ProgramPoint) {
+        while let Some(&Reverse((at, u))) = self.pending_reloads.peek()
+            && at <= position
+        {
+            self.pending_reloads.pop();
+            self.advance(at);
+            self.reload(u, at);
+        }
+    }
+
+    /// Move `u` from its slot into a preg free for the rest of its range
+    /// from `at`, preferring its copy hint. Leaves it on the stack if none
+    /// is.
+    fn reload(&mut self, u: Reg, at: ProgramPoint) {
+        let Some(AllocatedSlot::Stack(s)) = self.current_slot[u as usize] else {
+            panic!("reload of vreg {u}, which is not on the stack");
+        };
+        let u_end = self.ranges[u].last_end().unwrap();
+        let blocked_at = self.compute_blocked_at(u, at);
+        let free = |p: &Reg| blocked_at.get(p).copied().unwrap_or(0) >= u_end;
+        let hint = self.copy_hint(u).filter(|h| self.pool_for(u).contains(h) && free(h));
+        let Some(p) = hint.or_else(|| self.pool_for(u).iter().copied().find(|p| free(p))) else {
+            debug_event!(vreg = u, slot = s, at, "reload: no preg free past the loop");
+            return;
+        };
+        debug_event!(vreg = u, slot = s, preg = p, at, "reload: preg again past the loop");
+        stat!("regalloc", "reloaded", "vregs given a preg again after a loop");
+        self.close_piece(u, at);
+        self.current_slot[u as usize] = Some(AllocatedSlot::Reg(p));
+        self.current_piece_start[u as usize] = at;
+        self.occupancy
+            .entry((self.is_fp(u), p))
+            .or_default()
+            .insert_from(u, &self.ranges[u], at);
+        let root = self.copy_classes.find(u);
+        self.class_preg.insert(root, p);
+        self.active.push(u);
+        self.edits.push((at, Edit::Reload { from: s, to: p }));
+    }
+
+    /// Earliest point at or before `split_pt` that `u` can move to the
+    /// stack at without a back edge carrying it from the Stack piece into
+    /// the Reg piece. If a loop header inside the Reg piece has `u` live
+    /// in and a latch at or past `split_pt`, the latch would jump back with
+    /// `u` only in the slot while the header still reads the preg, so the
+    /// split moves up to that header — repeatedly, for enclosing loops.
+    fn loop_safe_split_point(&self, u: Reg, mut split_pt: ProgramPoint) -> ProgramPoint {
+        loop {
+            let header = self
+                .layout
+                .order
+                .iter()
+                .map(|&b| (b, self.layout.block_start_pt(b)))
+                .filter(|&(b, start)| {
+                    start < split_pt
+                        && self.ranges[u].covers(start)
+                        && self
+                            .cfg
+                            .preds(b)
+                            .iter()
+                            .any(|&q| self.layout.block_end_pt(q) > split_pt)
+                })
+                .map(|(_, start)| start)
+                .min();
+            match header {
+                Some(start) => {
+                    debug_event!(
+                        vreg = u,
+                        from = split_pt,
+                        to = start,
+                        "split: hoisted to loop header"
+                    );
+                    stat!("regalloc", "hoisted", "splits hoisted to a loop header");
+                    split_pt = start;
                 }
-                self.split_moves.push(SplitMove {
-                    at_point: save_pt,
-                    from_preg: p,
-                    to_slot: s,
-                });
+                None => return split_pt,
             }
         }
     }
@@ -575,23 +754,25 @@ impl<'a, I: Inst> Allocator<'a, I> {
     }
 }
 
-fn collect_copy_src<I: Inst>(func: &Func<I>) -> SecondaryMap<Reg, Option<Reg>> {
-    let mut m = SecondaryMap::new(func.get_regs_count());
-    m.fill(None);
+fn collect_copy_classes<I: Inst>(func: &Func<I>) -> UnionFind<Reg> {
+    let mut classes = UnionFind::new(func.get_regs_count());
     for (_b, bd) in func.blocks_iter() {
         for inst in bd.iter() {
             if let Instruction::Pseudo(PseudoInstruction::Copy { dst, src }) = inst {
-                m.set(*dst, Some(*src));
+                classes.union(*dst, *src);
             }
         }
     }
-    m
+    classes
 }
 
 /// Build the allocator's effective pre-bind map by merging `config.reg_bind`
 /// with in-stream `RegDef` pseudos. Both sources pin a vreg to a preg for
 /// its whole life; a vreg that appears in both must agree on the same preg.
-fn merge_pre_binds<I: Inst>(config: &RegAllocConfig, func: &Func<I>) -> HashMap<Reg, Reg> {
+pub(super) fn merge_pre_binds<I: Inst>(
+    config: &RegAllocConfig,
+    func: &Func<I>,
+) -> HashMap<Reg, Reg> {
     let mut out: HashMap<Reg, Reg> = config.reg_bind.clone();
     for (_b, bd) in func.blocks_iter() {
         for inst in bd.iter() {
@@ -615,7 +796,7 @@ mod tests {
     use crate::codegen::isa::x64::inst::X64Inst;
     use crate::codegen::isa::x64::regs::*;
     use crate::codegen::tir::PseudoInstruction;
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
 
     fn cfg4(reg_bind: HashMap<Reg, Reg>) -> RegAllocConfig {
         RegAllocConfig {
@@ -625,6 +806,10 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind,
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         }
     }
 
@@ -656,6 +841,10 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind,
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         };
         let res = LinearScan::allocate(&func, &cfg, &cfg_cfg);
         assert_eq!(uniform(&res, v0), AllocatedSlot::Reg(RDI));
@@ -688,6 +877,10 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind,
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         };
         let res = LinearScan::allocate(&func, &cfg, &cfg_cfg);
         assert_eq!(uniform(&res, v0), AllocatedSlot::Reg(RDI));
@@ -722,7 +915,7 @@ mod tests {
         // v0 defined first, grabs RAX (only allocatable). Then v1 is
         // pre-bound to RAX while v0 is still live. v0's life should be
         // split: Reg(RAX) for the prefix up to v1's first point, Stack(_)
-        // for the rest. A SplitMove must be recorded.
+        // for the rest. A store must be recorded.
         let mut func = Func::<X64Inst>::new("evict".into());
         let b0 = func.add_empty_block();
         let v0 = func.new_vreg();
@@ -746,6 +939,10 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind,
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         };
         let res = LinearScan::allocate(&func, &cfg, &cfg_cfg);
         assert_eq!(uniform(&res, v1), AllocatedSlot::Reg(RAX));
@@ -757,8 +954,11 @@ mod tests {
         );
         assert!(matches!(v0_pieces[0].1, AllocatedSlot::Reg(RAX)));
         assert!(matches!(v0_pieces[1].1, AllocatedSlot::Stack(_)));
-        assert_eq!(res.split_moves.len(), 1);
-        assert_eq!(res.split_moves[0].from_preg, RAX);
+        let [(at, Edit::Store { from: RAX, .. })] = res.edits[..] else {
+            panic!("expected one store out of RAX, got {:?}", res.edits);
+        };
+        assert_eq!(res.edits_at(at..=at), &res.edits[..]);
+        assert!(res.edits_at(at + 2..=at + 2).is_empty());
     }
 
     #[test]
@@ -784,6 +984,10 @@ mod tests {
             allocatable_fp_regs: Vec::new(),
             scratch_fp_regs: Vec::new(),
             reg_bind: HashMap::new(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
         };
         let res = LinearScan::allocate(&func, &cfg, &cfg_cfg);
         // At least one vreg should end up with a Stack piece somewhere.
@@ -795,6 +999,229 @@ mod tests {
         assert!(any_on_stack, "expected at least one spill under 2-reg pressure");
     }
 
+    #[test]
+    fn eviction_spares_the_value_the_profile_says_is_used_hot() {
+        use crate::codegen::isa::x64::builder::FuncBuilder;
+        use crate::codegen::isa::x64::inst::Cond;
+        use crate::codegen::tir::Profile;
+
+        // a, c live into the branch; v needs a third register for one
+        // instruction. `a` is used in `hot`, laid out last, `c` in `cold`.
+        let mut fb = FuncBuilder::new("p");
+        let a = fb.iconst64(1);
+        let c = fb.iconst64(2);
+        let v = fb.iconst64(3);
+        let cold = fb.new_block();
+        let hot = fb.new_block();
+        fb.branch_icmp(Cond::Z, v, v, cold, hot);
+        for (blk, x) in [(cold, c), (hot, a)] {
+            fb.switch_to_block(blk);
+            let r = fb.add(x, x);
+            fb.ret(r);
+        }
+        let mut func = fb.build();
+        let cfg_cfg = RegAllocConfig {
+            allocatable_regs: vec![RAX, RBX],
+            ..cfg4(HashMap::new())
+        };
+        let spilled = |func: &Func<X64Inst>| {
+            let cfg = CFG::compute(func).unwrap();
+            let res = LinearScan::allocate(func, &cfg, &cfg_cfg);
+            let on_stack = |x: Reg| {
+                res.assignments[x]
+                    .slots()
+                    .any(|s| matches!(s, AllocatedSlot::Stack(_)))
+            };
+            [a, c].into_iter().filter(|&x| on_stack(x)).collect::<Vec<_>>()
+        };
+
+        // Statically both arms run as often; the farthest end goes.
+        assert_eq!(spilled(&func), [a]);
+        let mut profile = Profile::default();
+        profile.block_counts.insert(func.get_entry_block().unwrap(), 100);
+        profile.block_counts.insert(hot, 100);
+        profile.block_counts.insert(cold, 0);
+        func.set_profile(profile);
+        assert_eq!(spilled(&func), [c]);
+    }
+
+    #[test]
+    fn compact_regs_win_ties_when_preferred() {
+        use crate::codegen::isa::x64::builder::FuncBuilder;
+
+        let mut fb = FuncBuilder::new("c");
+        let v = fb.iconst64(1);
+        let w = fb.add(v, v);
+        fb.ret(w);
+        let func = fb.build();
+        let cfg = CFG::compute(&func).unwrap();
+        let mut config = RegAllocConfig {
+            allocatable_regs: vec![RAX, R8, R9],
+            compact_regs: vec![RAX],
+            ..cfg4(HashMap::new())
+        };
+        let res = LinearScan::allocate(&func, &cfg, &config);
+        assert_eq!(uniform(&res, v), AllocatedSlot::Reg(R9));
+        config.prefer_compact_regs = true;
+        let res = LinearScan::allocate(&func, &cfg, &config);
+        assert_eq!(uniform(&res, v), AllocatedSlot::Reg(RAX));
+    }
+
+    #[test]
+    fn value_pushed_out_by_a_loop_gets_a_register_back_after_it() {
+        use crate::codegen::isa::x64::builder::FuncBuilder;
+        use crate::codegen::isa::x64::inst::Cond;
+        use crate::codegen::regalloc::checker::check_allocation;
+
+        // `a` lives across the loop without being used in it; the loop
+        // needs both registers, so `a` goes to the stack at the header.
+        let mut fb = FuncBuilder::new("l");
+        let a = fb.iconst64(7);
+        let i = fb.iconst64(0);
+        let head = fb.new_block();
+        let body = fb.new_block();
+        let exit = fb.new_block();
+        fb.jmp(head);
+        fb.switch_to_block(head);
+        let n = fb.iconst64(3);
+        fb.branch_icmp(Cond::GE, i, n, exit, body);
+        fb.switch_to_block(body);
+        let next = fb.add(i, i);
+        fb.copy_into(i, next);
+        fb.jmp(head);
+        fb.switch_to_block(exit);
+        let r = fb.add(a, i);
+        fb.ret(r);
+        let func = fb.build();
+
+        let cfg = CFG::compute(&func).unwrap();
+        let config = RegAllocConfig {
+            allocatable_regs: vec![RAX, RBX],
+            ..cfg4(HashMap::new())
+        };
+        let res = LinearScan::allocate(&func, &cfg, &config);
+        let layout = BlockLayout::compute(&func);
+        let pieces = &res.assignments[a].pieces;
+        assert!(
+            matches!(
+                pieces.iter().map(|&(_, s)| s).collect::<Vec<_>>()[..],
+                [AllocatedSlot::Reg(_), AllocatedSlot::Stack(_), AllocatedSlot::Reg(_)]
+            ),
+            "{pieces:?}"
+        );
+        assert_eq!(pieces[1].0.start, layout.block_start_pt(head));
+        let reloads: Vec<_> =
+            res.edits.iter().filter(|(_, e)| matches!(e, Edit::Reload { .. })).collect();
+        assert_eq!(reloads.len(), 1);
+        let exit_pt = layout.block_start_pt(exit);
+        assert_eq!(reloads[0].0, exit_pt);
+        assert_eq!(res.edits_at(exit_pt..=exit_pt), &[*reloads[0]]);
+        check_allocation(&func, &cfg, &res).unwrap();
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_explains_why_a_value_left_its_register() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().expect("unpoisoned").extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut func = Func::<X64Inst>::new("p".into());
+        let b0 = func.add_empty_block();
+        let vs: Vec<Reg> = (0..3).map(|_| func.new_vreg()).collect();
+        {
+            let bd = func.get_block_data_mut(b0);
+            for (i, &v) in vs.iter().enumerate() {
+                bd.push_target_inst(X64Inst::Mov64ri { dst: v, imm: i as i64 });
+            }
+            bd.push_target_inst(X64Inst::Add64rr { dst: vs[2], src: vs[0] });
+            bd.push_target_inst(X64Inst::Add64rr { dst: vs[2], src: vs[1] });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: vs[2] });
+        }
+        let cfg = CFG::compute(&func).unwrap();
+        let config = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RBX],
+            scratch_regs: vec![R12, R13],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let out = Capture::default();
+        let writer = out.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let _ = LinearScan::allocate(&func, &cfg, &config);
+        });
+        let log = String::from_utf8(out.0.lock().expect("unpoisoned").clone()).expect("utf-8");
+        assert!(log.contains("regalloc{func=\"p\"}"), "{log}");
+        // v2 needs a preg until its last use, but both are taken until
+        // v0 / v1 die.
+        assert!(log.contains("spill: no preg free for the whole range"), "{log}");
+        assert!(log.contains("vreg=2 needed_until=11 best_free_until=Some(5)"), "{log}");
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats_count_whole_range_spills() {
+        use crate::codegen::stats;
+
+        // Other tests bump the same process-wide counters concurrently,
+        // so only a lower bound on the delta is meaningful.
+        let spilled = || {
+            stats::snapshot()
+                .into_iter()
+                .find(|s| (s.group, s.name) == ("regalloc", "spilled"))
+                .map_or(0, |s| s.value)
+        };
+        let mut func = Func::<X64Inst>::new("p".into());
+        let b0 = func.add_empty_block();
+        let vs: Vec<Reg> = (0..3).map(|_| func.new_vreg()).collect();
+        {
+            let bd = func.get_block_data_mut(b0);
+            for (i, &v) in vs.iter().enumerate() {
+                bd.push_target_inst(X64Inst::Mov64ri { dst: v, imm: i as i64 });
+            }
+            bd.push_target_inst(X64Inst::Add64rr { dst: vs[2], src: vs[0] });
+            bd.push_target_inst(X64Inst::Add64rr { dst: vs[2], src: vs[1] });
+            bd.push_pseudo_inst(PseudoInstruction::Return { src: vs[2] });
+        }
+        let cfg = CFG::compute(&func).unwrap();
+        let config = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RBX],
+            scratch_regs: vec![R12, R13],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let before = spilled();
+        let _ = LinearScan::allocate(&func, &cfg, &config);
+        assert!(spilled() > before);
+        assert!(stats::report().contains("vregs spilled for their whole range"));
+    }
+
     #[test]
     fn in_stream_regdef_pins_vreg_same_as_reg_bind() {
         // Same behavior as pre_bind_eviction_splits_the_incumbent_live_range,
@@ -1,12 +1,14 @@
 //! Register allocation.
 //!
 //! Shared types (`AllocatedSlot`, `Assignment`, `RegAllocConfig`,
-//! `RegAllocResult`, `StackSlot`, `SplitMove`) and the `RegAllocator` trait.
+//! `RegAllocResult`, `StackSlot`, `Edit`) and the
+//! `RegAllocator` trait.
 //! Concrete allocators live in submodules and plug in by implementing the
 //! trait; the pipeline can swap algorithms for comparison or benchmarking
 //! without rewiring emission.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
 
 use smallvec::SmallVec;
 
@@ -27,12 +29,12 @@ pub enum AllocatedSlot {
 /// Per-vreg assignment. Most vregs have a single piece spanning their entire
 /// live range. Vregs that were evicted mid-life carry multiple pieces — a
 /// `Reg(p)` piece up to the split point followed by a `Stack(s)` piece after,
-/// with a corresponding `SplitMove` in the result to save `p` into `s`.
+/// with a corresponding `Edit::Store` in the result to save `p` into `s`.
 ///
 /// Pieces are sorted by segment start and are non-overlapping. The emitter
 /// queries `at(program_point)` for each use / def it emits to figure out
 /// where the value is *at that specific point*.
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Assignment {
     pub pieces: SmallVec<[(Segment, AllocatedSlot); 1]>,
 }
@@ -83,26 +85,38 @@ impl Assignment {
     }
 }
 
-/// A store of a live value from a preg into a stack slot, to be inserted by
-/// the emitter immediately before the instruction whose early point equals
-/// `at_point`. Generated whenever the allocator splits a live range: the
-/// vreg held `from_preg` up to `at_point`, after which it lives in
-/// `to_slot` — so the preg's value must be preserved before the reuse.
-#[derive(Clone, Copy, Debug)]
-pub struct SplitMove {
-    pub at_point: ProgramPoint,
-    pub from_preg: Reg,
-    pub to_slot: StackSlot,
+/// A move the emitter inserts between instructions to carry out the
+/// allocation. Each sits at a program point in `RegAllocResult::edits` and
+/// runs immediately before the instruction that point belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Edit {
+    /// Save preg `from` into slot `to`. Generated whenever the allocator
+    /// splits a live range: the vreg held `from` up to the edit's (def)
+    /// point and lives in `to` after it, so the preg's value must be
+    /// preserved before the reuse.
+    Store { from: Reg, to: StackSlot },
+    /// Load slot `from` into preg `to`. Generated when a vreg split to the
+    /// stack around a loop gets a register again after it: the vreg lived
+    /// in `from` up to the edit's (use) point and in `to` from there on.
+    Reload { from: StackSlot, to: Reg },
+    /// Copy preg `from` into preg `to`.
+    Move { from: Reg, to: Reg },
 }
 
-/// Per-function output of a `RegAllocator`. Consumed by `pseudo_cleanup` and
-/// the MC emitter. `frame_layout[s]` is the byte offset of slot `s` from the
-/// frame pointer (see the MC emitter); slots are dense `0..frame_size/8`.
+/// Per-function output of a `RegAllocator`. Never written back into the IR:
+/// the MC emitter resolves operands through it while rendering and splices
+/// in the edits, so applying it costs no copy of the function.
+/// `frame_layout[s]` is slot `s`'s byte offset within the spill area;
+/// slots are dense `0..frame_size/8`. Their final place in the frame is
+/// the target's to decide (x64: `FrameLayout`). `edits` is sorted by
+/// program point, edits at the same point in the order they must run;
+/// look them up with `edits_at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RegAllocResult {
     pub assignments: SecondaryMap<Reg, Assignment>,
     pub frame_layout: Vec<usize>,
     pub frame_size: u32,
-    pub split_moves: Vec<SplitMove>,
+    pub edits: Vec<(ProgramPoint, Edit)>,
 }
 
 impl RegAllocResult {
@@ -112,6 +126,16 @@ impl RegAllocResult {
     pub fn at(&self, vreg: Reg, pt: ProgramPoint) -> Option<AllocatedSlot> {
         self.assignments.get(vreg).and_then(|a| a.at(pt))
     }
+
+    /// The edits at points `pts`, in the order they run. An instruction
+    /// runs those in `use_pt..=def_pt` before itself: reloads at its use
+    /// point, then split stores at its def point.
+    #[must_use]
+    pub fn edits_at(&self, pts: RangeInclusive<ProgramPoint>) -> &[(ProgramPoint, Edit)] {
+        let lo = self.edits.partition_point(|&(at, _)| at < *pts.start());
+        let hi = self.edits.partition_point(|&(at, _)| at <= *pts.end());
+        &self.edits[lo..hi]
+    }
 }
 
 /// Target-neutral inputs to allocation.
@@ -129,6 +153,16 @@ impl RegAllocResult {
 ///   disjointness rule.
 /// * `reg_bind` — pre-binds: `vreg -> preg` constraints. The allocator must
 ///   honor these even if it means evicting.
+/// * `coalesce` — let the allocator bias a `Copy` destination onto its
+///   source's preg so the move can be erased.
+/// * `spilled` — vregs a spilling pre-pass (`spill::plan_spills`) already
+///   put in memory: each gets a stack slot for its whole life. Allocators
+///   that can't honor this may treat it as a hint.
+/// * `compact_regs` — pregs with a shorter encoding than the rest of their
+///   pool (x64: the ones that need no REX prefix).
+/// * `prefer_compact_regs` — when several pregs are free for a vreg's whole
+///   range, take a `compact_regs` one to shrink the code.
+#[derive(Debug, Clone)]
 pub struct RegAllocConfig {
     pub preg_count: usize,
     pub allocatable_regs: Vec<Reg>,
@@ -136,6 +170,10 @@ pub struct RegAllocConfig {
     pub allocatable_fp_regs: Vec<Reg>,
     pub scratch_fp_regs: Vec<Reg>,
     pub reg_bind: HashMap<Reg, Reg>,
+    pub coalesce: bool,
+    pub spilled: HashSet<Reg>,
+    pub compact_regs: Vec<Reg>,
+    pub prefer_compact_regs: bool,
 }
 
 /// A register-allocation algorithm. Static-dispatch trait — callers pick the
@@ -144,5 +182,13 @@ pub trait RegAllocator<I: Inst> {
     fn allocate(func: &Func<I>, cfg: &CFG, config: &RegAllocConfig) -> RegAllocResult;
 }
 
+pub mod checker;
 pub mod linear_scan;
+#[cfg(feature = "regalloc2")]
+pub mod ra2;
+pub mod range_index;
+pub mod scavenger;
+pub mod spill;
 pub use linear_scan::LinearScan;
+#[cfg(feature = "regalloc2")]
+pub use ra2::Regalloc2;
@@ -0,0 +1,431 @@
+//! Adapter running the `regalloc2` crate's backtracking allocator behind
+//! `RegAllocator`, to compare its choices with the native allocators'.
+//!
+//! **Input.** regalloc2 wants SSA with block parameters and no critical
+//! edges; after SSA destruction a vreg may have several defs (phi copies,
+//! two-operand `dst`s, clobber markers). Each def of such a vreg becomes a
+//! fresh regalloc2 vreg, and every block it is live into gets a parameter
+//! for it, passed the version live at the end of each predecessor. An
+//! edge into a block with several predecessors goes through a synthetic
+//! block holding one operand-less branch. Uses are read early and defs
+//! written late, as `analysis::layout` numbers them; a def of a vreg the
+//! instruction also reads reuses that use's register; pre-bound vregs are
+//! fixed to their preg at every operand.
+//!
+//! **Output.** `RegAllocResult` gives each vreg positional pieces and can
+//! only split a register into a stack slot, so regalloc2's moves aren't
+//! carried over. A vreg keeps the register regalloc2 put every operand of
+//! every version in, unless that collides with a register already kept
+//! over its live range (pre-bound vregs are kept first); every other vreg
+//! lives in a stack slot of its own for its whole range. `coalesce` has
+//! no effect.
+
+use std::collections::HashMap;
+
+use regalloc2::{
+    Allocation, Block as RaBlock, Function, Inst as RaInst, InstRange, MachineEnv, Operand, Output,
+    PReg, PRegSet, RegClass, RegallocOptions, VReg,
+};
+
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::layout::BlockLayout;
+use crate::codegen::analysis::liveness::LiveRanges;
+use crate::codegen::regalloc::linear_scan::merge_pre_binds;
+use crate::codegen::regalloc::range_index::RangeIndex;
+use crate::codegen::regalloc::{
+    AllocatedSlot, Assignment, RegAllocConfig, RegAllocResult, RegAllocator, StackSlot,
+};
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst, Reg};
+use crate::support::slotmap::SecondaryMap;
+use crate::support::trace::enter_span;
+
+pub struct Regalloc2;
+
+impl<I: Inst> RegAllocator<I> for Regalloc2 {
+    fn allocate(func: &Func<I>, cfg: &CFG, config: &RegAllocConfig) -> RegAllocResult {
+        enter_span!("regalloc", func = func.name());
+        let layout = BlockLayout::compute(func);
+        let ranges = LiveRanges::compute(func, cfg, &layout)
+            .unwrap_or_else(|e| panic!("liveness failed in `{}`: {e}", func.name()));
+        let binds = merge_pre_binds(config, func);
+        let input = Input::build(func, cfg, &layout, &ranges, &binds);
+        let output = regalloc2::run(&input, &machine_env(config), &RegallocOptions::default())
+            .unwrap_or_else(|e| panic!("regalloc2 failed in `{}`: {e:?}", func.name()));
+        convert(func, &ranges, &binds, &input, &output)
+    }
+}
+
+fn class_of<I: Inst>(func: &Func<I>, v: Reg) -> RegClass {
+    if func.vreg_type(v).is_fp_or_vector() { RegClass::Float } else { RegClass::Int }
+}
+
+fn to_preg(p: Reg, class: RegClass) -> PReg {
+    PReg::new(p as usize, class)
+}
+
+fn machine_env(config: &RegAllocConfig) -> MachineEnv {
+    let pool = |regs: &[Reg], class| regs.iter().map(|&p| to_preg(p, class)).collect();
+    MachineEnv {
+        preferred_regs_by_class: [
+            pool(&config.allocatable_regs, RegClass::Int),
+            pool(&config.allocatable_fp_regs, RegClass::Float),
+            PRegSet::empty(),
+        ],
+        non_preferred_regs_by_class: [PRegSet::empty(); 3],
+        scratch_by_class: [None; 3],
+        fixed_stack_slots: Vec::new(),
+    }
+}
+
+/// One regalloc2 block: a real one, or the synthetic block splitting a
+/// critical edge.
+#[derive(Default)]
+struct RaBlockData {
+    insts: Vec<RaInst>,
+    succs: Vec<RaBlock>,
+    preds: Vec<RaBlock>,
+    params: Vec<VReg>,
+    /// Arguments passed to each successor's parameters, by successor.
+    args: Vec<Vec<VReg>>,
+}
+
+/// `func` in regalloc2's input model.
+struct Input {
+    blocks: Vec<RaBlockData>,
+    /// Operands of each instruction; the synthetic branches have none.
+    operands: Vec<Vec<Operand>>,
+    /// Whether each instruction ends a block with no successors.
+    rets: Vec<bool>,
+    /// Whether each instruction ends a block with successors.
+    branches: Vec<bool>,
+    /// The `func` vreg each regalloc2 vreg is a version of.
+    origin: Vec<Reg>,
+    /// Instructions of the real blocks; the synthetic branches follow.
+    real_insts: usize,
+}
+
+impl Input {
+    fn build<I: Inst>(
+        func: &Func<I>,
+        cfg: &CFG,
+        layout: &BlockLayout,
+        ranges: &LiveRanges,
+        binds: &HashMap<Reg, Reg>,
+    ) -> Self {
+        let n_regs = func.get_regs_count();
+        let mut defs = vec![0u32; n_regs];
+        for (_, bd) in func.blocks_iter() {
+            for inst in bd.iter() {
+                let mut seen = inst.get_defs();
+                seen.sort_unstable();
+                seen.dedup();
+                for d in seen {
+                    defs[d as usize] += 1;
+                }
+            }
+        }
+        let multi = |v: Reg| defs[v as usize] > 1;
+        let mut origin: Vec<Reg> = (0..n_regs as Reg).collect();
+        let fresh = |origin: &mut Vec<Reg>, v: Reg| {
+            origin.push(v);
+            VReg::new(origin.len() - 1, class_of(func, v))
+        };
+
+        let order = &layout.order;
+        let index_of: HashMap<Block, usize> =
+            order.iter().enumerate().map(|(i, &b)| (b, i)).collect();
+        let mut blocks: Vec<RaBlockData> = order.iter().map(|_| RaBlockData::default()).collect();
+        // Parameters first, so every predecessor can name them.
+        let mut param_of: Vec<Vec<Reg>> = vec![Vec::new(); order.len()];
+        for (i, &b) in order.iter().enumerate() {
+            let start = layout.block_start_pt(b);
+            for (v, range) in ranges.iter() {
+                if multi(v) && range.covers(start) {
+                    param_of[i].push(v);
+                    blocks[i].params.push(fresh(&mut origin, v));
+                }
+            }
+        }
+
+        let mut operands = Vec::new();
+        let (mut rets, mut branches) = (Vec::new(), Vec::new());
+        let mut ends: Vec<HashMap<Reg, VReg>> = Vec::with_capacity(order.len());
+        for (i, &b) in order.iter().enumerate() {
+            let mut cur: HashMap<Reg, VReg> =
+                param_of[i].iter().copied().zip(blocks[i].params.iter().copied()).collect();
+            let bd = func.get_block_data(b);
+            let has_succs = !cfg.succs(b).is_empty();
+            for (k, inst) in bd.iter().enumerate() {
+                let version = |cur: &HashMap<Reg, VReg>, v: Reg| {
+                    cur.get(&v).copied().unwrap_or_else(|| VReg::new(v as usize, class_of(func, v)))
+                };
+                let mut ops = Vec::new();
+                let mut uses = inst.get_uses();
+                uses.dedup();
+                let mut read: Vec<Reg> = Vec::new();
+                for u in uses {
+                    if read.contains(&u) {
+                        continue;
+                    }
+                    read.push(u);
+                    let vr = version(&cur, u);
+                    ops.push(match binds.get(&u) {
+                        Some(&p) => Operand::reg_fixed_use(vr, to_preg(p, vr.class())),
+                        None => Operand::reg_use(vr),
+                    });
+                }
+                let mut written: Vec<Reg> = Vec::new();
+                for d in inst.get_defs() {
+                    if written.contains(&d) {
+                        continue;
+                    }
+                    written.push(d);
+                    let vr = if multi(d) {
+                        let vr = fresh(&mut origin, d);
+                        cur.insert(d, vr);
+                        vr
+                    } else {
+                        version(&cur, d)
+                    };
+                    ops.push(match (binds.get(&d), read.iter().position(|&u| u == d)) {
+                        (Some(&p), _) => Operand::reg_fixed_def(vr, to_preg(p, vr.class())),
+                        (None, Some(idx)) => Operand::reg_reuse_def(vr, idx),
+                        (None, None) => Operand::reg_def(vr),
+                    });
+                }
+                blocks[i].insts.push(RaInst::new(operands.len()));
+                operands.push(ops);
+                let last = k + 1 == bd.len();
+                rets.push(last && !has_succs);
+                branches.push(last && has_succs);
+            }
+            ends.push(cur);
+        }
+        let real_insts = operands.len();
+
+        // Edges, splitting the critical ones.
+        let entry = order[0];
+        for (i, &b) in order.iter().enumerate() {
+            for &s in cfg.succs(b) {
+                let j = index_of[&s];
+                let args: Vec<VReg> = param_of[j]
+                    .iter()
+                    .map(|v| {
+                        *ends[i].get(v).unwrap_or_else(|| {
+                            panic!("vreg {v} is live into {s} but not out of {b}")
+                        })
+                    })
+                    .collect();
+                let shared = cfg.preds(s).len() + usize::from(s == entry) > 1;
+                if shared {
+                    let e = RaBlock::new(blocks.len());
+                    blocks.push(RaBlockData {
+                        insts: vec![RaInst::new(operands.len())],
+                        succs: vec![RaBlock::new(j)],
+                        preds: vec![RaBlock::new(i)],
+                        params: Vec::new(),
+                        args: vec![args],
+                    });
+                    operands.push(Vec::new());
+                    rets.push(false);
+                    branches.push(true);
+                    blocks[i].succs.push(e);
+                    blocks[i].args.push(Vec::new());
+                    blocks[j].preds.push(e);
+                } else {
+                    blocks[i].succs.push(RaBlock::new(j));
+                    blocks[i].args.push(args);
+                    blocks[j].preds.push(RaBlock::new(i));
+                }
+            }
+        }
+        Self { blocks, operands, rets, branches, origin, real_insts }
+    }
+}
+
+impl Function for Input {
+    fn num_insts(&self) -> usize {
+        self.operands.len()
+    }
+
+    fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn entry_block(&self) -> RaBlock {
+        RaBlock::new(0)
+    }
+
+    fn block_insns(&self, block: RaBlock) -> InstRange {
+        let insts = &self.blocks[block.index()].insts;
+        let first = insts.first().expect("blocks are never empty");
+        InstRange::new(*first, RaInst::new(first.index() + insts.len()))
+    }
+
+    fn block_succs(&self, block: RaBlock) -> &[RaBlock] {
+        &self.blocks[block.index()].succs
+    }
+
+    fn block_preds(&self, block: RaBlock) -> &[RaBlock] {
+        &self.blocks[block.index()].preds
+    }
+
+    fn block_params(&self, block: RaBlock) -> &[VReg] {
+        &self.blocks[block.index()].params
+    }
+
+    fn is_ret(&self, insn: RaInst) -> bool {
+        self.rets[insn.index()]
+    }
+
+    fn is_branch(&self, insn: RaInst) -> bool {
+        self.branches[insn.index()]
+    }
+
+    fn branch_blockparams(&self, block: RaBlock, _insn: RaInst, succ_idx: usize) -> &[VReg] {
+        &self.blocks[block.index()].args[succ_idx]
+    }
+
+    fn inst_operands(&self, insn: RaInst) -> &[Operand] {
+        &self.operands[insn.index()]
+    }
+
+    fn inst_clobbers(&self, _insn: RaInst) -> PRegSet {
+        // Clobbers are pre-bound vregs, fixed like any other.
+        PRegSet::empty()
+    }
+
+    fn num_vregs(&self) -> usize {
+        self.origin.len()
+    }
+
+    fn spillslot_size(&self, _regclass: RegClass) -> usize {
+        1
+    }
+}
+
+/// Turn regalloc2's per-operand allocations into whole-range slots. See
+/// module docs.
+fn convert<I: Inst>(
+    func: &Func<I>,
+    ranges: &LiveRanges,
+    binds: &HashMap<Reg, Reg>,
+    input: &Input,
+    output: &Output,
+) -> RegAllocResult {
+    /// What every operand of a vreg's versions was given so far.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Seen {
+        Nothing,
+        Reg(Reg),
+        Mixed,
+    }
+    let n = func.get_regs_count();
+    let mut seen = vec![Seen::Nothing; n];
+    for i in 0..input.real_insts {
+        let inst = RaInst::new(i);
+        for (op, alloc) in input.operands[i].iter().zip(output.inst_allocs(inst)) {
+            let v = input.origin[op.vreg().vreg()] as usize;
+            let here = match alloc.as_reg() {
+                Some(p) if *alloc != Allocation::none() => Seen::Reg(p.hw_enc() as Reg),
+                _ => Seen::Mixed,
+            };
+            seen[v] = match seen[v] {
+                Seen::Nothing => here,
+                prev if prev == here => prev,
+                _ => Seen::Mixed,
+            };
+        }
+    }
+
+    let mut assignments = SecondaryMap::new(n);
+    assignments.fill(Assignment::default());
+    let mut occupancy: HashMap<Reg, RangeIndex> = HashMap::new();
+    let mut frame_layout = Vec::new();
+    let live: Vec<Reg> = (0..n as Reg).filter(|&v| ranges[v].first_start().is_some()).collect();
+    // Pre-bound vregs claim their preg first; the rest keep regalloc2's
+    // register where it doesn't collide.
+    let (bound, free): (Vec<Reg>, Vec<Reg>) = live.into_iter().partition(|v| binds.contains_key(v));
+    for v in bound.into_iter().chain(free) {
+        let range = &ranges[v];
+        let (start, end) = (range.first_start(), range.last_end());
+        let (start, end) = (start.expect("live"), end.expect("live"));
+        let preg = binds.get(&v).copied().or(match seen[v as usize] {
+            Seen::Reg(p) => Some(p),
+            Seen::Nothing | Seen::Mixed => None,
+        });
+        let index = preg.map(|p| occupancy.entry(p).or_default());
+        let slot = match (preg, index) {
+            (Some(p), Some(index))
+                if binds.contains_key(&v) || index.next_overlap(range, 0).is_none() =>
+            {
+                index.insert(v, range);
+                AllocatedSlot::Reg(p)
+            }
+            _ => {
+                stat!("regalloc2", "spilled", "vregs regalloc2 split or collided, spilled");
+                let s = frame_layout.len() as StackSlot;
+                frame_layout.push(s as usize * 8);
+                AllocatedSlot::Stack(s)
+            }
+        };
+        *assignments.get_mut(v).expect("sized to the vreg count") =
+            Assignment::uniform(slot, start, end);
+    }
+    let frame_size = (frame_layout.len() * 8) as u32;
+    RegAllocResult {
+        assignments,
+        frame_layout,
+        frame_size,
+        edits: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::Cond;
+    use crate::codegen::isa::x64::regs::*;
+    use crate::codegen::passes::ssa_destruction::destroy_ssa;
+    use crate::codegen::regalloc::checker::check_allocation;
+
+    #[test]
+    fn phi_copies_join_into_registers_around_a_diamond() {
+        let mut b = FuncBuilder::new("diamond");
+        let x = b.arg();
+        let (then, other, join) = (b.new_block(), b.new_block(), b.new_block());
+        b.branch_icmp(Cond::Z, x, x, then, other);
+        b.switch_to_block(then);
+        let one = b.add(x, x);
+        b.jmp(join);
+        b.switch_to_block(other);
+        let two = b.sub(x, x);
+        b.jmp(join);
+        b.switch_to_block(join);
+        let r = b.phi(vec![(then, one), (other, two)]);
+        b.ret(r);
+        let mut func = b.build();
+        destroy_ssa(&mut func);
+        let cfg = CFG::compute(&func).expect("cfg");
+        let config = RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RCX, RDX, RSI],
+            scratch_regs: vec![R12, R13],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::from([(x, RDI)]),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        };
+        let res = Regalloc2::allocate(&func, &cfg, &config);
+        check_allocation(&func, &cfg, &res).expect("valid allocation");
+        assert_eq!(res.assignments[x].uniform_slot(), Some(AllocatedSlot::Reg(RDI)));
+        // Two copies into `r` and one value each side: nothing to spill.
+        assert_eq!(res.frame_size, 0);
+    }
+}
@@ -0,0 +1,151 @@
+//! Occupancy index for one physical register.
+//!
+//! Holds the live-range segments of every vreg currently assigned to the
+//! preg, keyed by start point. Segments in one index never overlap, so
+//! their ends are sorted too and both "is the preg live at `pt`" and
+//! "where does a live range first collide with the preg" are answered
+//! with a couple of ordered-map lookups per queried segment instead of a
+//! walk over every vreg sharing the preg.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::codegen::analysis::layout::ProgramPoint;
+use crate::codegen::analysis::liveness::{LiveRange, Segment};
+use crate::codegen::tir::Reg;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RangeIndex {
+    /// Segment start → (segment end, owning vreg).
+    segments: BTreeMap<ProgramPoint, (ProgramPoint, Reg)>,
+}
+
+impl RangeIndex {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Record `owner` as occupying the preg over each of `range`'s segments.
+    pub fn insert(&mut self, owner: Reg, range: &LiveRange) {
+        self.insert_from(owner, range, 0);
+    }
+
+    /// `insert`, for the part of `range` from `at` onward.
+    pub fn insert_from(&mut self, owner: Reg, range: &LiveRange, at: ProgramPoint) {
+        for seg in range.segments().iter().filter(|s| s.end > at) {
+            let seg = Segment { start: seg.start.max(at), end: seg.end };
+            debug_assert!(
+                self.first_overlap(seg).is_none(),
+                "vreg {owner} overlaps an occupant of its preg at {seg:?}"
+            );
+            self.segments.insert(seg.start, (seg.end, owner));
+        }
+    }
+
+    /// Release `owner`'s occupancy from `at` onward; the part of a segment
+    /// before `at` is kept. A segment may be held in pieces (one inserted
+    /// with `insert_from` after an earlier one was truncated).
+    pub fn truncate(&mut self, owner: Reg, range: &LiveRange, at: ProgramPoint) {
+        for seg in range.segments().iter().filter(|s| s.end > at) {
+            let held: Vec<ProgramPoint> = self
+                .segments
+                .range(seg.start..seg.end)
+                .filter(|&(_, &(_, o))| o == owner)
+                .map(|(&start, _)| start)
+                .collect();
+            for start in held {
+                if start >= at {
+                    self.segments.remove(&start);
+                } else if let Some(entry) = self.segments.get_mut(&start) {
+                    entry.0 = entry.0.min(at);
+                }
+            }
+        }
+    }
+
+    /// Vreg occupying the preg at `pt`, if any. O(log n).
+    #[must_use]
+    pub fn occupant_at(&self, pt: ProgramPoint) -> Option<Reg> {
+        let (_, &(end, owner)) = self.segments.range(..=pt).next_back()?;
+        (pt < end).then_some(owner)
+    }
+
+    /// First point `>= from` at which `range` is live while the preg is
+    /// occupied, or `None` if they never collide past `from`.
+    #[must_use]
+    pub fn next_overlap(&self, range: &LiveRange, from: ProgramPoint) -> Option<ProgramPoint> {
+        let segs = range.segments();
+        let first = segs.partition_point(|s| s.end <= from);
+        segs[first..].iter().find_map(|s| {
+            self.first_overlap(Segment {
+                start: s.start.max(from),
+                end: s.end,
+            })
+        })
+    }
+
+    /// First point of `seg` the preg is occupied at.
+    fn first_overlap(&self, seg: Segment) -> Option<ProgramPoint> {
+        if self.occupant_at(seg.start).is_some() {
+            return Some(seg.start);
+        }
+        let (&next, _) = self
+            .segments
+            .range((Bound::Excluded(seg.start), Bound::Unbounded))
+            .next()?;
+        (next < seg.end).then_some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(segs: &[(ProgramPoint, ProgramPoint)]) -> LiveRange {
+        let mut r = LiveRange::default();
+        for &(start, end) in segs {
+            r.add(Segment { start, end });
+        }
+        r
+    }
+
+    #[test]
+    fn queries_find_occupants_and_first_collisions() {
+        let mut idx = RangeIndex::new();
+        let a = range(&[(2, 6), (20, 30)]);
+        idx.insert(1, &a);
+        idx.insert(2, &range(&[(10, 14)]));
+
+        assert_eq!(idx.occupant_at(2), Some(1));
+        assert_eq!(idx.occupant_at(6), None);
+        assert_eq!(idx.occupant_at(13), Some(2));
+
+        let v = range(&[(7, 9), (12, 25)]);
+        assert_eq!(idx.next_overlap(&v, 0), Some(12));
+        assert_eq!(idx.next_overlap(&v, 14), Some(20));
+        assert_eq!(idx.next_overlap(&range(&[(6, 10)]), 0), None);
+
+        // Evicting vreg 1 at 24 frees the rest of its last segment.
+        idx.truncate(1, &a, 24);
+        assert_eq!(idx.occupant_at(23), Some(1));
+        assert_eq!(idx.next_overlap(&v, 24), None);
+        idx.truncate(1, &a, 0);
+        assert_eq!(idx.occupant_at(3), None);
+
+        // Reloading it at 22 takes the preg back from there only.
+        idx.insert_from(1, &a, 22);
+        assert_eq!(idx.occupant_at(21), None);
+        assert_eq!(idx.occupant_at(22), Some(1));
+        assert_eq!(idx.next_overlap(&v, 0), Some(12));
+        assert_eq!(idx.next_overlap(&v, 14), Some(22));
+        idx.truncate(1, &a, 26);
+        assert_eq!(idx.occupant_at(25), Some(1));
+        assert_eq!(idx.occupant_at(26), None);
+    }
+}
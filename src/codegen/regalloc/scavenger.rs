@@ -0,0 +1,152 @@
+//! Post-allocation register scavenging.
+//!
+//! Late passes that need a temporary register at one spot (frame setup,
+//! branch-relaxation thunks) ask the scavenger for a preg nothing holds
+//! there instead of reserving one for the whole function. Occupancy comes
+//! straight from the allocation: every `Reg` piece of every assignment,
+//! plus the point of each `Edit::Store` or `Edit::Move`, where the source
+//! preg is still read by the injected edit.
+//!
+//! Callee-saved regs the allocation never touched are not handed out:
+//! using one would need a prologue save the frame doesn't have.
+
+use std::collections::HashMap;
+
+use crate::codegen::analysis::layout::ProgramPoint;
+use crate::codegen::analysis::liveness::Segment;
+use crate::codegen::regalloc::{AllocatedSlot, Edit, RegAllocConfig, RegAllocResult};
+use crate::codegen::tir::Reg;
+
+#[derive(Clone, Debug, Default)]
+pub struct RegScavenger {
+    /// Preg → sorted, merged segments it holds a live value over.
+    busy: HashMap<Reg, Vec<Segment>>,
+    gprs: Vec<Reg>,
+    fp_regs: Vec<Reg>,
+}
+
+impl RegScavenger {
+    /// Build the occupancy of `ra_res`. Candidates are `ra_cfg`'s
+    /// allocatable pools in preference order; `callee_saved` lists the
+    /// target's callee-saved regs, which are only candidates if already
+    /// saved because the allocation uses them.
+    #[must_use]
+    pub fn new(ra_cfg: &RegAllocConfig, ra_res: &RegAllocResult, callee_saved: &[Reg]) -> Self {
+        let mut busy: HashMap<Reg, Vec<Segment>> = HashMap::new();
+        for asn in ra_res.assignments.values() {
+            for (seg, slot) in &asn.pieces {
+                if let AllocatedSlot::Reg(p) = slot {
+                    busy.entry(*p).or_default().push(*seg);
+                }
+            }
+        }
+        for &(at, edit) in &ra_res.edits {
+            if let Edit::Store { from, .. } | Edit::Move { from, .. } = edit {
+                busy.entry(from).or_default().push(Segment { start: at, end: at + 1 });
+            }
+        }
+        for segs in busy.values_mut() {
+            segs.sort_by_key(|s| s.start);
+            let mut merged: Vec<Segment> = Vec::with_capacity(segs.len());
+            for s in segs.drain(..) {
+                match merged.last_mut() {
+                    Some(last) if s.start <= last.end => last.end = last.end.max(s.end),
+                    _ => merged.push(s),
+                }
+            }
+            *segs = merged;
+        }
+        let usable = |r: &&Reg| !callee_saved.contains(r) || busy.contains_key(r);
+        Self {
+            gprs: ra_cfg.allocatable_regs.iter().filter(usable).copied().collect(),
+            fp_regs: ra_cfg.allocatable_fp_regs.iter().filter(usable).copied().collect(),
+            busy,
+        }
+    }
+
+    /// `true` iff `preg` holds no live value anywhere in `[start, end)`.
+    #[must_use]
+    pub fn is_free(&self, preg: Reg, start: ProgramPoint, end: ProgramPoint) -> bool {
+        let Some(segs) = self.busy.get(&preg) else {
+            return true;
+        };
+        let i = segs.partition_point(|s| s.end <= start);
+        segs.get(i).is_none_or(|s| s.start >= end)
+    }
+
+    /// A GPR free over `[start, end)`, marked busy there so a second
+    /// request over the same span gets a different one.
+    pub fn scavenge_gpr(&mut self, start: ProgramPoint, end: ProgramPoint) -> Option<Reg> {
+        let preg = self.gprs.iter().copied().find(|&r| self.is_free(r, start, end))?;
+        self.claim(preg, start, end);
+        Some(preg)
+    }
+
+    /// An XMM reg free over `[start, end)`; see `scavenge_gpr`.
+    pub fn scavenge_fp(&mut self, start: ProgramPoint, end: ProgramPoint) -> Option<Reg> {
+        let preg = self.fp_regs.iter().copied().find(|&r| self.is_free(r, start, end))?;
+        self.claim(preg, start, end);
+        Some(preg)
+    }
+
+    fn claim(&mut self, preg: Reg, start: ProgramPoint, end: ProgramPoint) {
+        let segs = self.busy.entry(preg).or_default();
+        let i = segs.partition_point(|s| s.end <= start);
+        segs.insert(i, Segment { start, end });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::regalloc::Assignment;
+    use crate::support::slotmap::SecondaryMap;
+    use std::collections::HashSet;
+
+    const A: Reg = 0;
+    const B: Reg = 1;
+    const SAVED: Reg = 2;
+    const SAVED_UNUSED: Reg = 3;
+
+    fn config() -> RegAllocConfig {
+        RegAllocConfig {
+            preg_count: 8,
+            allocatable_regs: vec![A, B, SAVED, SAVED_UNUSED],
+            scratch_regs: vec![],
+            allocatable_fp_regs: vec![],
+            scratch_fp_regs: vec![],
+            reg_bind: HashMap::new(),
+            coalesce: false,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        }
+    }
+
+    #[test]
+    fn hands_out_pregs_free_at_the_requested_span() {
+        let mut res = RegAllocResult {
+            assignments: SecondaryMap::new(0),
+            frame_layout: vec![0],
+            frame_size: 8,
+            edits: vec![(9, Edit::Store { from: B, to: 0 })],
+        };
+        res.assignments.set(10, Assignment::uniform(AllocatedSlot::Reg(A), 0, 8));
+        res.assignments.set(11, Assignment::uniform(AllocatedSlot::Reg(B), 4, 6));
+        res.assignments.set(12, Assignment::uniform(AllocatedSlot::Reg(SAVED), 0, 20));
+        let mut s = RegScavenger::new(&config(), &res, &[SAVED, SAVED_UNUSED]);
+
+        assert!(s.is_free(A, 8, 12));
+        assert!(!s.is_free(A, 7, 9));
+        // B is still read by the split store at 9.
+        assert!(!s.is_free(B, 9, 10));
+        assert_eq!(s.scavenge_gpr(2, 4), Some(B));
+        // The claim sticks, and the unused callee-saved reg stays off limits.
+        assert_eq!(s.scavenge_gpr(2, 3), None);
+        assert_eq!(s.scavenge_gpr(8, 9), Some(A));
+        assert_eq!(s.scavenge_gpr(20, 22), Some(A));
+        assert_eq!(s.scavenge_gpr(20, 22), Some(B));
+        assert_eq!(s.scavenge_gpr(20, 22), Some(SAVED));
+        assert_eq!(s.scavenge_gpr(20, 22), None);
+    }
+}
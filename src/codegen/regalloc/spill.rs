@@ -0,0 +1,519 @@
+//! Spilling ahead of allocation, after Braun & Hack, "Register Spilling
+//! and Live-Range Splitting for SSA-Form Programs" (CC 2009).
+//!
+//! **Requires:** `cfg` is `func`'s CFG; ABI lowering has run, so
+//! `config.reg_bind` and in-stream `RegDef`s name every fixed register.
+//!
+//! **Preserves:** The CFG. Program points shift.
+//!
+//! **Effect:** Decides, per register class, which values sit in a
+//! register at each instruction so that never more than the class's
+//! allocatable registers do, then rewrites `func` to match. Within a block
+//! this is Belady's MIN: a use not in a register is reloaded, and when too
+//! many values are, the one used again farthest away is evicted. Next-use
+//! distances are global; an edge to a shallower loop depth adds
+//! `LOOP_EXIT_DISTANCE`, so values a loop doesn't touch go first. A block
+//! starts with the values its predecessors agree on, topped up by
+//! distance. A loop header starts with the values the loop uses, and
+//! keeps values merely live through it only if the loop's own pressure
+//! leaves room.
+//!
+//! A vreg `v` evicted while it still has a use is spilled: `v` names the
+//! memory copy from then on (it goes in the returned set, for
+//! `RegAllocConfig::spilled`) and a fresh vreg takes over its register
+//! occurrences. Every def of `v` is followed by a `Copy` storing it and
+//! every reload is a `Copy` back. Stores go right after the defs rather
+//! than at the evictions, so memory holds the value everywhere and no
+//! edge needs a store. A reload an edge needs goes at the top of the
+//! target if the edge is its only way in, else before the source's
+//! terminator (the load is harmless on the source's other edges).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::codegen::analysis::block_freq::BlockFrequency;
+use crate::codegen::analysis::cfg::{CFG, reverse_post_order};
+use crate::codegen::analysis::dom_tree::DomTree;
+use crate::codegen::analysis::liveness::BlockLiveness;
+use crate::codegen::error::CodegenError;
+use crate::codegen::regalloc::RegAllocConfig;
+use crate::codegen::regalloc::linear_scan::merge_pre_binds;
+use crate::codegen::stats::stat;
+use crate::codegen::tir::{Block, Func, Inst, Instruction, PseudoInstruction, Reg, Type};
+use crate::support::slotmap::Key;
+use crate::support::trace::{debug_event, enter_span};
+
+/// Added to the distance of a next use reached only by leaving a loop.
+pub const LOOP_EXIT_DISTANCE: u32 = 100_000;
+
+/// Distance of a value with no next use.
+const NEVER: u32 = u32::MAX;
+
+/// Spill `func` down to `config`'s register budget and return the vregs
+/// left naming memory copies.
+///
+/// # Errors
+/// Whatever `BlockLiveness::compute` or `DomTree::compute` report for a
+/// malformed `func`.
+pub fn plan_spills<I: Inst>(
+    func: &mut Func<I>,
+    cfg: &CFG,
+    config: &RegAllocConfig,
+) -> Result<HashSet<Reg>, CodegenError> {
+    enter_span!("plan_spills", func = func.name());
+    let liveness: BlockLiveness = BlockLiveness::compute(func, cfg)?;
+    let dt = DomTree::compute(cfg)?;
+    let freq = BlockFrequency::compute(cfg, &dt);
+    let plan = Planner::new(func, cfg, config, &liveness).run(&dt, &freq);
+    Ok(rewrite(func, plan))
+}
+
+/// Where the planner wants reloads, by instruction index within a block.
+#[derive(Default)]
+struct Plan {
+    spilled: HashSet<Reg>,
+    /// Per block: `(idx, v)` reloads `v` right before instruction `idx`.
+    reloads: HashMap<Block, Vec<(u32, Reg)>>,
+    /// Per block: reloads before its terminator, for its out-edges.
+    exit_reloads: HashMap<Block, Vec<Reg>>,
+}
+
+struct Planner<'a, I: Inst> {
+    func: &'a Func<I>,
+    cfg: &'a CFG,
+    liveness: &'a BlockLiveness,
+    /// Allocatable registers of each class: GPR, then FP.
+    capacity: [usize; 2],
+    /// Vregs that must stay in registers: pre-bound ones and those defined
+    /// by a terminator, after which a store has no place to go.
+    pinned: HashSet<Reg>,
+    /// Per block: each vreg's operand indices in order, flagged whether
+    /// the instruction reads it.
+    events: Vec<HashMap<Reg, Vec<(u32, bool)>>>,
+    /// Per block: next-use distance of each live-out vreg from the end.
+    exit_dist: Vec<HashMap<Reg, u32>>,
+}
+
+impl<'a, I: Inst> Planner<'a, I> {
+    fn new(
+        func: &'a Func<I>,
+        cfg: &'a CFG,
+        config: &RegAllocConfig,
+        liveness: &'a BlockLiveness,
+    ) -> Self {
+        let mut pinned: HashSet<Reg> = merge_pre_binds(config, func).into_keys().collect();
+        let mut events = vec![HashMap::new(); func.blocks_count()];
+        for (b, bd) in func.blocks_iter() {
+            let evs: &mut HashMap<Reg, Vec<(u32, bool)>> = &mut events[b.index()];
+            for (idx, inst) in bd.iter().enumerate() {
+                let uses = inst.get_uses();
+                for &u in &uses {
+                    evs.entry(u).or_default().push((idx as u32, true));
+                }
+                for d in inst.get_defs() {
+                    if inst.is_term() || matches!(func.vreg_type(d), Type::Agg(_)) {
+                        pinned.insert(d);
+                    }
+                    if !uses.contains(&d) {
+                        evs.entry(d).or_default().push((idx as u32, false));
+                    }
+                }
+            }
+        }
+        Self {
+            func,
+            cfg,
+            liveness,
+            capacity: [config.allocatable_regs.len(), config.allocatable_fp_regs.len()],
+            pinned,
+            events,
+            exit_dist: Vec::new(),
+        }
+    }
+
+    fn class(&self, v: Reg) -> usize {
+        usize::from(self.func.vreg_type(v).is_fp_or_vector())
+    }
+
+    fn len(&self, b: Block) -> u32 {
+        self.func.get_block_data(b).len() as u32
+    }
+
+    /// Instructions from index `from` of `b` to `v`'s next use, `NEVER` if
+    /// its current value has none.
+    fn next_use(&self, b: Block, v: Reg, from: u32) -> u32 {
+        if let Some(evs) = self.events[b.index()].get(&v) {
+            let k = evs.partition_point(|&(idx, _)| idx < from);
+            if let Some(&(idx, is_use)) = evs.get(k) {
+                return if is_use { idx - from } else { NEVER };
+            }
+        }
+        self.exit_dist[b.index()].get(&v).map_or(NEVER, |&d| d.saturating_add(self.len(b) - from))
+    }
+
+    /// Next-use distance of every live-out vreg of `b` from its end, given
+    /// the successors' entry distances.
+    fn exit_from(
+        &self,
+        b: Block,
+        entry: &[HashMap<Reg, u32>],
+        freq: &BlockFrequency,
+    ) -> HashMap<Reg, u32> {
+        let mut out: HashMap<Reg, u32> = HashMap::new();
+        for &s in self.cfg.succs(b) {
+            let exits = freq.loop_depth(s) < freq.loop_depth(b);
+            let penalty = if exits { LOOP_EXIT_DISTANCE } else { 0 };
+            for (&v, &d) in &entry[s.index()] {
+                let d = d.saturating_add(penalty);
+                out.entry(v).and_modify(|x| *x = (*x).min(d)).or_insert(d);
+            }
+        }
+        out
+    }
+
+    /// Solve the entry distances backwards to a fixpoint and keep each
+    /// block's exit distances.
+    fn solve_distances(&mut self, rpo: &[Block], freq: &BlockFrequency) {
+        let mut entry: Vec<HashMap<Reg, u32>> = vec![HashMap::new(); self.func.blocks_count()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in rpo.iter().rev() {
+                let len = self.len(b);
+                let mut dist: HashMap<Reg, u32> = self
+                    .exit_from(b, &entry, freq)
+                    .into_iter()
+                    .map(|(v, d)| (v, d.saturating_add(len)))
+                    .collect();
+                for (idx, inst) in self.func.get_block_data(b).insts().iter().enumerate().rev() {
+                    for d in inst.get_defs() {
+                        dist.remove(&d);
+                    }
+                    for u in inst.get_uses() {
+                        dist.insert(u, idx as u32);
+                    }
+                }
+                if dist != entry[b.index()] {
+                    entry[b.index()] = dist;
+                    changed = true;
+                }
+            }
+        }
+        self.exit_dist = (0..self.func.blocks_count())
+            .map(|i| self.exit_from(Block::new(i), &entry, freq))
+            .collect();
+    }
+
+    /// Most values of each class live at once anywhere in `b`.
+    fn block_pressure(&self, b: Block) -> [usize; 2] {
+        let mut live: HashSet<Reg> = self.liveness.live_out_regs(b).collect();
+        let mut count = [0; 2];
+        for &v in &live {
+            count[self.class(v)] += 1;
+        }
+        let mut max = count;
+        for inst in self.func.get_block_data(b).insts().iter().rev() {
+            for d in inst.get_defs() {
+                if live.remove(&d) {
+                    count[self.class(d)] -= 1;
+                }
+            }
+            for u in inst.get_uses() {
+                if live.insert(u) {
+                    count[self.class(u)] += 1;
+                }
+            }
+            max = [max[0].max(count[0]), max[1].max(count[1])];
+        }
+        max
+    }
+
+    /// Pressure of the natural loop headed by `h`: the most values of each
+    /// class live at once in any of its blocks. Just `h`'s own if no back
+    /// edge enters it (an irreducible entry).
+    fn loop_pressure(&self, h: Block, dt: &DomTree) -> [usize; 2] {
+        let mut body = HashSet::from([h]);
+        let mut stack: Vec<Block> =
+            self.cfg.preds(h).iter().copied().filter(|&p| dt.dominates(h, p)).collect();
+        while let Some(b) = stack.pop() {
+            if body.insert(b) {
+                stack.extend(self.cfg.preds(b));
+            }
+        }
+        body.iter()
+            .map(|&b| self.block_pressure(b))
+            .fold([0; 2], |acc, p| [acc[0].max(p[0]), acc[1].max(p[1])])
+    }
+
+    /// Live-in vregs of `b` of class `class` that may leave registers,
+    /// with their distance from the top of `b`.
+    fn spillable_live_in(&self, b: Block, class: usize) -> Vec<(u32, Reg)> {
+        self.liveness
+            .live_in_regs(b)
+            .filter(|v| !self.pinned.contains(v) && self.class(*v) == class)
+            .map(|v| (self.next_use(b, v, 0), v))
+            .collect()
+    }
+
+    fn pinned_live_in(&self, b: Block) -> HashSet<Reg> {
+        self.liveness.live_in_regs(b).filter(|v| self.pinned.contains(v)).collect()
+    }
+
+    /// Entry set of a block entered over an edge not yet planned: a loop
+    /// header (or an irreducible entry).
+    fn loop_entry(&self, b: Block, dt: &DomTree) -> HashSet<Reg> {
+        let mut w = self.pinned_live_in(b);
+        let pressure = self.loop_pressure(b, dt);
+        for (class, pressure) in pressure.into_iter().enumerate() {
+            let cap = self.capacity[class]
+                .saturating_sub(w.iter().filter(|&&v| self.class(v) == class).count());
+            let (mut used, mut through): (Vec<_>, Vec<_>) = self
+                .spillable_live_in(b, class)
+                .into_iter()
+                .partition(|&(d, _)| d < LOOP_EXIT_DISTANCE);
+            used.sort_unstable();
+            through.sort_unstable();
+            let take = used.len().min(cap);
+            w.extend(used[..take].iter().map(|&(_, v)| v));
+            // The loop's pressure counts every value live through it; each
+            // one kept is a register the body can't have.
+            let room = (self.capacity[class] + through.len()).saturating_sub(pressure);
+            let keep = room.min(cap - take).min(through.len());
+            w.extend(through[..keep].iter().map(|&(_, v)| v));
+        }
+        w
+    }
+
+    /// Entry set of a block whose predecessors are all planned: what they
+    /// all hold in registers first, then what some hold, nearest first.
+    fn usual_entry(&self, b: Block, exits: &HashMap<Block, HashSet<Reg>>) -> HashSet<Reg> {
+        let mut w = self.pinned_live_in(b);
+        let preds: Vec<&HashSet<Reg>> =
+            self.cfg.preds(b).iter().filter_map(|p| exits.get(p)).collect();
+        for class in 0..2 {
+            let cap = self.capacity[class]
+                .saturating_sub(w.iter().filter(|&&v| self.class(v) == class).count());
+            let mut cands: Vec<(bool, u32, Reg)> = self
+                .spillable_live_in(b, class)
+                .into_iter()
+                .filter_map(|(d, v)| {
+                    let holding = preds.iter().filter(|s| s.contains(&v)).count();
+                    (holding > 0).then_some((holding < preds.len(), d, v))
+                })
+                .collect();
+            cands.sort_unstable();
+            w.extend(cands.iter().take(cap).map(|&(_, _, v)| v));
+        }
+        w
+    }
+
+    /// Evict from `w` until each class fits its registers less `room`,
+    /// farthest next use (from instruction `at` of `b`) first.
+    fn limit(
+        &self,
+        b: Block,
+        w: &mut HashSet<Reg>,
+        at: u32,
+        room: [usize; 2],
+        spilled: &mut HashSet<Reg>,
+    ) {
+        for (class, room) in room.into_iter().enumerate() {
+            let cap = self.capacity[class].saturating_sub(room);
+            let mut members: Vec<(bool, u32, Reg)> = w
+                .iter()
+                .filter(|&&v| self.class(v) == class)
+                .map(|&v| {
+                    let pinned = self.pinned.contains(&v);
+                    (!pinned, if pinned { 0 } else { self.next_use(b, v, at) }, v)
+                })
+                .collect();
+            if members.len() <= cap {
+                continue;
+            }
+            members.sort_unstable();
+            for &(spillable, dist, v) in &members[cap..] {
+                if !spillable {
+                    continue;
+                }
+                w.remove(&v);
+                if dist != NEVER && spilled.insert(v) {
+                    debug_event!(vreg = v, block = %b, next_use = dist, "plan: spilled");
+                }
+            }
+        }
+    }
+
+    fn run(mut self, dt: &DomTree, freq: &BlockFrequency) -> Plan {
+        let rpo = reverse_post_order(self.cfg);
+        self.solve_distances(&rpo, freq);
+        let mut plan = Plan::default();
+        let mut entries: HashMap<Block, HashSet<Reg>> = HashMap::new();
+        let mut exits: HashMap<Block, HashSet<Reg>> = HashMap::new();
+        for &b in &rpo {
+            let revisited = self.cfg.preds(b).iter().any(|p| !exits.contains_key(p));
+            let entry = if revisited && !self.cfg.preds(b).is_empty() {
+                self.loop_entry(b, dt)
+            } else {
+                self.usual_entry(b, &exits)
+            };
+            // Whatever is live in but not kept must come from memory.
+            plan.spilled.extend(self.liveness.live_in_regs(b).filter(|v| !entry.contains(v)));
+            let mut w = entry.clone();
+            for (idx, inst) in self.func.get_block_data(b).iter().enumerate() {
+                let i = idx as u32;
+                let uses = inst.get_uses();
+                for &u in &uses {
+                    if w.insert(u) {
+                        plan.reloads.entry(b).or_default().push((i, u));
+                    }
+                }
+                self.limit(b, &mut w, i, [0; 2], &mut plan.spilled);
+                let mut room = [0; 2];
+                let defs = inst.get_defs();
+                for &d in defs.iter().filter(|d| !uses.contains(d)) {
+                    room[self.class(d)] += 1;
+                }
+                self.limit(b, &mut w, i + 1, room, &mut plan.spilled);
+                w.extend(defs);
+            }
+            entries.insert(b, entry);
+            exits.insert(b, w);
+        }
+        for (p, b) in self.cfg.edges() {
+            let (Some(exit), Some(entry)) = (exits.get(&p), entries.get(&b)) else {
+                continue;
+            };
+            let mut missing: Vec<Reg> = entry.difference(exit).copied().collect();
+            missing.sort_unstable();
+            for v in missing {
+                if self.cfg.preds(b).len() == 1 {
+                    plan.reloads.entry(b).or_default().insert(0, (0, v));
+                } else {
+                    plan.exit_reloads.entry(p).or_default().push(v);
+                }
+            }
+        }
+        plan
+    }
+}
+
+/// Apply `plan` to `func`; returns the spilled vregs.
+fn rewrite<I: Inst>(func: &mut Func<I>, mut plan: Plan) -> HashSet<Reg> {
+    let mut spilled: Vec<Reg> = plan.spilled.iter().copied().collect();
+    spilled.sort_unstable();
+    let in_reg: HashMap<Reg, Reg> =
+        spilled.iter().map(|&v| (v, func.new_typed_vreg(func.vreg_type(v)))).collect();
+    let reload = |v: Reg| {
+        stat!("spill", "reloads", "reloads the spill planner inserted");
+        Instruction::Pseudo(PseudoInstruction::Copy { dst: in_reg[&v], src: v })
+    };
+    let blocks: Vec<Block> = func.blocks_iter().map(|(b, _)| b).collect();
+    for b in blocks {
+        let mut reloads = plan.reloads.remove(&b).unwrap_or_default();
+        reloads.sort_by_key(|&(idx, _)| idx);
+        let mut reloads = reloads.into_iter().peekable();
+        let exit_reloads = plan.exit_reloads.remove(&b).unwrap_or_default();
+        let old = std::mem::take(func.get_block_data_mut(b).insts_mut());
+        let len = old.len();
+        let mut new = Vec::with_capacity(len);
+        for (idx, mut inst) in old.into_iter().enumerate() {
+            if idx + 1 == len && inst.is_term() {
+                new.extend(exit_reloads.iter().map(|&v| reload(v)));
+            }
+            while let Some((_, v)) = reloads.next_if(|&(at, _)| at as usize == idx) {
+                new.push(reload(v));
+            }
+            let stores: Vec<Reg> =
+                inst.get_defs().into_iter().filter(|d| in_reg.contains_key(d)).collect();
+            inst.map_regs(&mut |r| in_reg.get(&r).copied().unwrap_or(r));
+            new.push(inst);
+            new.extend(
+                stores.into_iter().map(|v| {
+                    Instruction::Pseudo(PseudoInstruction::Copy { dst: v, src: in_reg[&v] })
+                }),
+            );
+        }
+        *func.get_block_data_mut(b).insts_mut() = new;
+    }
+    stat!("spill", "spilled", "vregs the spill planner put in memory", spilled.len());
+    plan.spilled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::{Cond, X64Inst};
+    use crate::codegen::isa::x64::regs::*;
+    use crate::codegen::regalloc::checker::check_allocation;
+    use crate::codegen::regalloc::{AllocatedSlot, LinearScan, RegAllocator};
+
+    fn two_regs() -> RegAllocConfig {
+        RegAllocConfig {
+            preg_count: 32,
+            allocatable_regs: vec![RAX, RCX],
+            scratch_regs: vec![R12, R13],
+            allocatable_fp_regs: Vec::new(),
+            scratch_fp_regs: Vec::new(),
+            reg_bind: HashMap::new(),
+            coalesce: true,
+            spilled: HashSet::new(),
+            compact_regs: Vec::new(),
+            prefer_compact_regs: false,
+        }
+    }
+
+    fn reads(func: &Func<X64Inst>, b: Block, v: Reg) -> bool {
+        func.get_block_data(b).iter().any(|inst| inst.get_uses().contains(&v))
+    }
+
+    #[test]
+    fn value_live_through_a_full_loop_is_reloaded_after_it() {
+        // `a` is only used after the loop, which needs both registers.
+        let mut fb = FuncBuilder::new("l");
+        let a = fb.iconst64(7);
+        let i = fb.iconst64(0);
+        let head = fb.new_block();
+        let body = fb.new_block();
+        let exit = fb.new_block();
+        fb.jmp(head);
+        fb.switch_to_block(head);
+        let n = fb.iconst64(3);
+        fb.branch_icmp(Cond::GE, i, n, exit, body);
+        fb.switch_to_block(body);
+        let next = fb.add(i, i);
+        fb.copy_into(i, next);
+        fb.jmp(head);
+        fb.switch_to_block(exit);
+        let r = fb.add(a, i);
+        fb.ret(r);
+        let mut func = fb.build();
+
+        let cfg = CFG::compute(&func).unwrap();
+        let mut config = two_regs();
+        config.spilled = plan_spills(&mut func, &cfg, &config).unwrap();
+        assert_eq!(config.spilled, HashSet::from([a]));
+        assert!(!reads(&func, head, a) && !reads(&func, body, a));
+        assert!(reads(&func, exit, a));
+
+        let res = LinearScan::allocate(&func, &cfg, &config);
+        assert_eq!(res.assignments[a].uniform_slot(), Some(AllocatedSlot::Stack(0)));
+        assert!(res.edits.is_empty());
+        check_allocation(&func, &cfg, &res).unwrap();
+    }
+
+    #[test]
+    fn nothing_spills_when_everything_fits() {
+        let mut fb = FuncBuilder::new("f");
+        let a = fb.iconst64(1);
+        let b = fb.iconst64(2);
+        let r = fb.add(a, b);
+        fb.ret(r);
+        let mut func = fb.build();
+        let before = func.get_block_data(Block::new(0)).len();
+
+        let cfg = CFG::compute(&func).unwrap();
+        let config = RegAllocConfig { allocatable_regs: vec![RAX, RCX, RDX], ..two_regs() };
+        assert!(plan_spills(&mut func, &cfg, &config).unwrap().is_empty());
+        assert_eq!(func.get_block_data(Block::new(0)).len(), before);
+    }
+}
@@ -0,0 +1,148 @@
+//! Named statistics counters, in the spirit of LLVM's `-stats`.
+//!
+//! A pass bumps a counter with `stat!("regalloc", "evictions", "vregs
+//! evicted from their preg")`, or adds `n` with a fourth argument. With
+//! the `stats` feature each call site owns a static atomic that registers
+//! itself on first use; `report()` sums the counters by `(group, name)`
+//! across every function compiled so far in the process. Without the
+//! feature `stat!` expands to nothing and the count expression is never
+//! evaluated.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+/// One `stat!` call site.
+pub struct Counter {
+    group: &'static str,
+    name: &'static str,
+    desc: &'static str,
+    value: AtomicU64,
+    registered: Once,
+}
+
+static REGISTRY: Mutex<Vec<&'static Counter>> = Mutex::new(Vec::new());
+
+impl Counter {
+    #[must_use]
+    pub const fn new(group: &'static str, name: &'static str, desc: &'static str) -> Self {
+        Self {
+            group,
+            name,
+            desc,
+            value: AtomicU64::new(0),
+            registered: Once::new(),
+        }
+    }
+
+    pub fn add(&'static self, n: usize) {
+        self.registered.call_once(|| {
+            REGISTRY.lock().expect("stats registry poisoned").push(self);
+        });
+        self.value.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// A counter's total over every call site sharing its `(group, name)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Statistic {
+    pub group: &'static str,
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub value: u64,
+}
+
+/// Every counter bumped so far, sorted by group then name. Counters that
+/// were reset to zero are left out.
+#[must_use]
+pub fn snapshot() -> Vec<Statistic> {
+    let mut merged: BTreeMap<(&'static str, &'static str), Statistic> = BTreeMap::new();
+    for c in REGISTRY.lock().expect("stats registry poisoned").iter() {
+        let value = c.value.load(Ordering::Relaxed);
+        merged
+            .entry((c.group, c.name))
+            .or_insert(Statistic {
+                group: c.group,
+                name: c.name,
+                desc: c.desc,
+                value: 0,
+            })
+            .value += value;
+    }
+    merged.into_values().filter(|s| s.value != 0).collect()
+}
+
+/// Zero every counter, e.g. between the files of a corpus run.
+pub fn reset() {
+    for c in REGISTRY.lock().expect("stats registry poisoned").iter() {
+        c.value.store(0, Ordering::Relaxed);
+    }
+}
+
+/// LLVM-style table: one line per counter with its value, group and
+/// description.
+#[must_use]
+pub fn report() -> String {
+    let stats = snapshot();
+    let value_w = stats
+        .iter()
+        .map(|s| s.value.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let group_w = stats.iter().map(|s| s.group.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    let _ = writeln!(out, "===- statistics collected -===");
+    for s in &stats {
+        let _ = writeln!(
+            out,
+            "{:>value_w$} {:<group_w$} - {}",
+            s.value, s.group, s.desc
+        );
+    }
+    out
+}
+
+/// Bump a statistics counter; see the module docs.
+#[cfg(feature = "stats")]
+macro_rules! stat {
+    ($group:literal, $name:literal, $desc:literal) => {
+        $crate::codegen::stats::stat!($group, $name, $desc, 1)
+    };
+    ($group:literal, $name:literal, $desc:literal, $n:expr) => {{
+        static COUNTER: $crate::codegen::stats::Counter =
+            $crate::codegen::stats::Counter::new($group, $name, $desc);
+        COUNTER.add($n);
+    }};
+}
+#[cfg(not(feature = "stats"))]
+macro_rules! stat {
+    ($($arg:tt)*) => {};
+}
+
+pub(crate) use stat;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_sites_sharing_a_name_are_summed_in_the_report() {
+        static A: Counter = Counter::new("stats-test", "hits", "test hits");
+        static B: Counter = Counter::new("stats-test", "hits", "test hits");
+        static C: Counter = Counter::new("stats-test", "misses", "test misses");
+        A.add(2);
+        B.add(3);
+        C.add(0);
+        let ours: Vec<Statistic> = snapshot()
+            .into_iter()
+            .filter(|s| s.group == "stats-test")
+            .collect();
+        assert_eq!(ours.len(), 1, "zero counters are omitted: {ours:?}");
+        assert_eq!((ours[0].name, ours[0].value), ("hits", 5));
+        // Other tests' groups may widen the group column.
+        assert!(report().lines().any(|l| {
+            l.trim_start().starts_with("5 stats-test") && l.ends_with(" - test hits")
+        }));
+    }
+}
@@ -0,0 +1,145 @@
+//! Per-pass wall-clock timing. `compile_function` records one
+//! `PassTiming` per pass when `CodegenOptions::time_passes` is set;
+//! callers merge the per-function records and print `report()` to see
+//! where compile time goes.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Wall time spent in one pass on one function.
+#[derive(Clone, Debug)]
+pub struct PassTiming {
+    pub func: String,
+    pub pass: &'static str,
+    pub elapsed: Duration,
+}
+
+/// Collected `PassTiming`s. A disabled collector runs passes without
+/// reading the clock and records nothing.
+#[derive(Clone, Debug, Default)]
+pub struct PassTimings {
+    enabled: bool,
+    records: Vec<PassTiming>,
+}
+
+impl PassTimings {
+    #[must_use]
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            records: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Run `f` as pass `pass` of function `func`, recording its wall time
+    /// if enabled.
+    pub fn time<R>(&mut self, func: &str, pass: &'static str, f: impl FnOnce() -> R) -> R {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let out = f();
+        self.records.push(PassTiming {
+            func: func.to_string(),
+            pass,
+            elapsed: start.elapsed(),
+        });
+        out
+    }
+
+    #[must_use]
+    pub fn records(&self) -> &[PassTiming] {
+        &self.records
+    }
+
+    /// Append another collector's records, e.g. to aggregate across
+    /// the functions of a module.
+    pub fn merge(&mut self, other: &PassTimings) {
+        self.records.extend(other.records.iter().cloned());
+    }
+
+    /// Total time per pass, in first-seen pass order.
+    #[must_use]
+    pub fn totals(&self) -> Vec<(&'static str, Duration, usize)> {
+        let mut index: HashMap<&'static str, usize> = HashMap::new();
+        let mut totals: Vec<(&'static str, Duration, usize)> = Vec::new();
+        for r in &self.records {
+            let i = *index.entry(r.pass).or_insert_with(|| {
+                totals.push((r.pass, Duration::ZERO, 0));
+                totals.len() - 1
+            });
+            totals[i].1 += r.elapsed;
+            totals[i].2 += 1;
+        }
+        totals
+    }
+
+    /// Human-readable aggregate table: one line per pass with total
+    /// time, share of the grand total, and the number of runs.
+    #[must_use]
+    pub fn report(&self) -> String {
+        let totals = self.totals();
+        let grand: Duration = totals.iter().map(|t| t.1).sum();
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<20} {:>12} {:>7} {:>6}", "pass", "time", "%", "runs");
+        for (pass, elapsed, runs) in &totals {
+            let pct = if grand.is_zero() {
+                0.0
+            } else {
+                elapsed.as_secs_f64() / grand.as_secs_f64() * 100.0
+            };
+            let _ = writeln!(
+                out,
+                "{:<20} {:>12} {:>6.1}% {:>6}",
+                pass,
+                format!("{elapsed:.2?}"),
+                pct,
+                runs
+            );
+        }
+        let _ = writeln!(out, "{:<20} {:>12}", "total", format!("{grand:.2?}"));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_collector_records_nothing_but_still_runs_the_pass() {
+        let mut t = PassTimings::new(false);
+        let r = t.time("f", "p", || 7);
+        assert_eq!(r, 7);
+        assert!(t.records().is_empty());
+    }
+
+    #[test]
+    fn totals_aggregate_by_pass_across_functions() {
+        let mut a = PassTimings::new(true);
+        a.time("f", "regalloc", || ());
+        a.time("f", "emit", || ());
+        let mut b = PassTimings::new(true);
+        b.time("g", "regalloc", || ());
+        a.merge(&b);
+        let totals = a.totals();
+        assert_eq!(totals.len(), 2);
+        assert_eq!((totals[0].0, totals[0].2), ("regalloc", 2));
+        assert_eq!((totals[1].0, totals[1].2), ("emit", 1));
+    }
+
+    #[test]
+    fn report_lists_every_pass_and_a_total_line() {
+        let mut t = PassTimings::new(true);
+        t.time("f", "liveness", || ());
+        let r = t.report();
+        assert!(r.contains("liveness"));
+        assert!(r.lines().last().unwrap().starts_with("total"));
+    }
+}
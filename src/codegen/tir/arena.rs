@@ -0,0 +1,48 @@
+use super::{Inst, Instruction};
+
+/// Most spare buffers kept; beyond this, recycled buffers are dropped.
+const MAX_SPARE: usize = 64;
+
+/// Recycling arena for instruction buffers, owned by each `Func`.
+///
+/// Passes that rewrite a block build the new instruction list in a fresh
+/// buffer and drop the old one. Routing both through the arena
+/// (`Func::inst_buffer`, `Func::replace_insts`) hands the old buffer's
+/// allocation to the next block instead, so a pipeline of rewriting
+/// passes over a large function settles into reusing a fixed set of
+/// allocations rather than freeing and reallocating per block per pass.
+pub struct InstArena<I: Inst> {
+    spare: Vec<Vec<Instruction<I>>>,
+}
+
+impl<I: Inst> Default for InstArena<I> {
+    fn default() -> Self {
+        Self { spare: Vec::new() }
+    }
+}
+
+impl<I: Inst> InstArena<I> {
+    /// An empty buffer with room for at least `capacity` instructions,
+    /// reusing a spare one when any is large enough.
+    pub fn buffer(&mut self, capacity: usize) -> Vec<Instruction<I>> {
+        match self.spare.iter().position(|v| v.capacity() >= capacity) {
+            Some(i) => self.spare.swap_remove(i),
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Return a buffer for reuse. Its contents are discarded.
+    pub fn recycle(&mut self, mut buf: Vec<Instruction<I>>) {
+        if buf.capacity() == 0 || self.spare.len() >= MAX_SPARE {
+            return;
+        }
+        buf.clear();
+        self.spare.push(buf);
+    }
+
+    /// Number of spare buffers waiting for reuse.
+    #[must_use]
+    pub fn spare_count(&self) -> usize {
+        self.spare.len()
+    }
+}
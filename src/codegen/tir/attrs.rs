@@ -0,0 +1,127 @@
+use std::fmt::Display;
+
+/// Function-level attributes, attached with `Func::attrs_mut` and honored
+/// from the optimizer down to the object writer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FuncAttrs {
+    /// Rarely executed: skip code-growing passes and place the function
+    /// in `.text.unlikely` unless `section` says otherwise.
+    pub cold: bool,
+    /// Never returns to its caller. The verifier rejects any return, and
+    /// the prologue doesn't save callee-saved registers.
+    pub noreturn: bool,
+    /// Emit the body without prologue or epilogue. The function must not
+    /// need a frame: no spills, stack arguments or callee-saved registers.
+    pub naked: bool,
+    /// Minimum start alignment in bytes; a power of two.
+    pub align: Option<u32>,
+    /// Object-file section, overriding the `.text` / `.text.unlikely`
+    /// default.
+    pub section: Option<String>,
+    /// Flags of `section`, `ax` unless given.
+    pub section_flags: Option<SectionFlags>,
+    /// Bytes of NOPs to reserve at the entry, before the prologue, for a
+    /// runtime to patch into a jump or a tracing hook.
+    pub patchable_entry: Option<u32>,
+}
+
+/// ELF flags of a section, spelled as in GAS's `.section name, "flags"`.
+/// Every section lancy writes is allocated (`a`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SectionFlags {
+    /// `w`: writable at run time.
+    pub write: bool,
+    /// `x`: executable.
+    pub exec: bool,
+    /// `R`: kept by `--gc-sections` even when unreferenced.
+    pub retain: bool,
+}
+
+impl SectionFlags {
+    /// Executable code: `ax`.
+    pub const CODE: Self = Self {
+        write: false,
+        exec: true,
+        retain: false,
+    };
+
+    /// Parse a GAS flag string such as `"awx"`; `None` on any letter
+    /// other than `a`, `w`, `x` or `R`.
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut flags = Self::default();
+        for c in s.chars() {
+            match c {
+                'a' => {}
+                'w' => flags.write = true,
+                'x' => flags.exec = true,
+                'R' => flags.retain = true,
+                _ => return None,
+            }
+        }
+        Some(flags)
+    }
+}
+
+impl Display for SectionFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a")?;
+        for (set, c) in [(self.write, "w"), (self.exec, "x"), (self.retain, "R")] {
+            if set {
+                f.write_str(c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FuncAttrs {
+    /// Section the object writer places the function in.
+    #[must_use]
+    pub fn section_name(&self) -> &str {
+        match &self.section {
+            Some(s) => s,
+            None if self.cold => ".text.unlikely",
+            None => ".text",
+        }
+    }
+
+    /// Flags of the function's section.
+    #[must_use]
+    pub fn section_flags(&self) -> SectionFlags {
+        self.section_flags.unwrap_or(SectionFlags::CODE)
+    }
+}
+
+/// Space-separated, in the text IR's syntax, e.g. `cold align(32)`.
+impl Display for FuncAttrs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut sep = "";
+        for (set, word) in [
+            (self.cold, "cold"),
+            (self.noreturn, "noreturn"),
+            (self.naked, "naked"),
+        ] {
+            if set {
+                write!(f, "{sep}{word}")?;
+                sep = " ";
+            }
+        }
+        if let Some(a) = self.align {
+            write!(f, "{sep}align({a})")?;
+            sep = " ";
+        }
+        if let Some(n) = self.patchable_entry {
+            write!(f, "{sep}patchable({n})")?;
+            sep = " ";
+        }
+        if let Some(s) = &self.section {
+            write!(f, "{sep}section(\"{s}\"")?;
+            if let Some(flags) = self.section_flags {
+                write!(f, ",\"{flags}\"")?;
+            }
+            f.write_str(")")?;
+        }
+        Ok(())
+    }
+}
@@ -3,7 +3,15 @@ use std::fmt::{Debug, Display};
 
 use super::{Inst, Instruction, PseudoInstruction};
 
-slotmap_key!(Block(u16));
+/// Integer behind `Block`. `u32` by default; the `block-u16` feature
+/// halves every block reference for users whose functions stay under
+/// 65,535 blocks.
+#[cfg(not(feature = "block-u16"))]
+pub type BlockIndex = u32;
+#[cfg(feature = "block-u16")]
+pub type BlockIndex = u16;
+
+slotmap_key!(Block(BlockIndex));
 
 impl Display for Block {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -17,7 +25,7 @@ impl Debug for Block {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct BlockData<I: Inst> {
     insts: Vec<Instruction<I>>,
 }
@@ -36,6 +44,11 @@ impl<I: Inst> BlockData<I> {
         BlockData { insts: Vec::new() }
     }
 
+    /// Make room for `additional` more instructions without reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        self.insts.reserve(additional);
+    }
+
     pub fn push_target_inst(&mut self, inst: I) {
         self.insts.push(Instruction::Target(inst));
     }
@@ -90,6 +103,12 @@ impl<I: Inst> BlockData<I> {
     }
 }
 
+impl<I: Inst> Extend<Instruction<I>> for BlockData<I> {
+    fn extend<T: IntoIterator<Item = Instruction<I>>>(&mut self, iter: T) {
+        self.insts.extend(iter);
+    }
+}
+
 impl<I: Inst> Display for BlockData<I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for inst in &self.insts {
@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::codegen::tir::Block;
+use crate::codegen::tir::{Block, Reg};
 
 #[derive(Error, Debug)]
 pub enum TirError {
@@ -9,4 +9,31 @@ pub enum TirError {
 
     #[error("Function body is empty")]
     EmptyFunctionBody,
+
+    #[error("Block {0} has a terminator before its last instruction")]
+    TerminatorNotAtEnd(Block),
+
+    #[error("Block {0} branches to nonexistent block {1}")]
+    InvalidBranchTarget(Block, Block),
+
+    #[error("Block {0} references vreg {1}, which this function never allocated")]
+    VregOutOfRange(Block, Reg),
+
+    #[error("Block {0} returns from a noreturn function")]
+    ReturnInNoreturn(Block),
+}
+
+impl TirError {
+    /// The block the error is in, if it names one.
+    #[must_use]
+    pub fn block(&self) -> Option<Block> {
+        match *self {
+            Self::BlockNotTerminated(b)
+            | Self::TerminatorNotAtEnd(b)
+            | Self::InvalidBranchTarget(b, _)
+            | Self::VregOutOfRange(b, _)
+            | Self::ReturnInNoreturn(b) => Some(b),
+            Self::EmptyFunctionBody => None,
+        }
+    }
 }
@@ -1,10 +1,16 @@
-use std::collections::HashMap;
-use std::fmt::Display;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::hash::{DefaultHasher, Hash, Hasher};
 
-use crate::support::slotmap::{Key, PrimaryMap};
+use smallvec::SmallVec;
 
+use crate::support::slotmap::{Key, PrimaryMap, SecondaryMap};
+
+use super::journal::{save, Journal};
 use super::{
-    AggregateData, AggregateId, Block, BlockData, CallData, CallId, Inst, PhiData, PhiId, Type,
+    AggregateData, AggregateId, Block, BlockData, BlockHint, CallData, CallId, CallTarget,
+    FuncAttrs, Inst, InstArena, Instruction, IntrinsicData, IntrinsicId, JumpTableData,
+    JumpTableId, PhiData, PhiId, Profile, RawBytesData, RawBytesId, SymbolId, Type,
 };
 
 pub type Reg = u32;
@@ -14,7 +20,12 @@ pub struct Func<I: Inst> {
     blocks: PrimaryMap<Block, BlockData<I>>,
     phis: PrimaryMap<PhiId, PhiData>,
     calls: PrimaryMap<CallId, CallData>,
+    raw_bytes: PrimaryMap<RawBytesId, RawBytesData>,
+    intrinsics: PrimaryMap<IntrinsicId, IntrinsicData>,
     aggregates: PrimaryMap<AggregateId, AggregateData>,
+    jump_tables: PrimaryMap<JumpTableId, JumpTableData>,
+    /// Names of the symbols target instructions refer to, interned.
+    symbols: PrimaryMap<SymbolId, String>,
     regs_count: u32,
     /// Type of each vreg, indexed by reg id. Populated by `new_vreg`.
     /// Regalloc consults this to pick the correct physical-register
@@ -27,6 +38,24 @@ pub struct Func<I: Inst> {
     /// lowering. The pipeline merges these with `AbiLowerResult::reg_bind`
     /// before handing the config to the regalloc.
     pre_binds: HashMap<Reg, Reg>,
+    /// Optional execution counts; see `set_profile`.
+    profile: Option<Profile>,
+    /// Frontend likelihood hints; see `set_block_hint`.
+    block_hints: HashMap<Block, BlockHint>,
+    attrs: FuncAttrs,
+    /// Spare instruction buffers; see `inst_buffer`.
+    arena: InstArena<I>,
+    /// Undo record while a `checkpoint` is open.
+    journal: Option<Box<Journal<I>>>,
+}
+
+/// Save `$self.$field` into the open journal, if any, before it's edited.
+macro_rules! journal {
+    ($self:ident, $field:ident) => {
+        if let Some(j) = &mut $self.journal {
+            save(&mut j.$field, &$self.$field);
+        }
+    };
 }
 
 impl<I: Inst> Func<I> {
@@ -38,12 +67,31 @@ impl<I: Inst> Func<I> {
             blocks: PrimaryMap::new(),
             phis: PrimaryMap::new(),
             calls: PrimaryMap::new(),
+            raw_bytes: PrimaryMap::new(),
+            intrinsics: PrimaryMap::new(),
             aggregates: PrimaryMap::new(),
+            jump_tables: PrimaryMap::new(),
+            symbols: PrimaryMap::new(),
             reg_types: Vec::new(),
             pre_binds: HashMap::new(),
+            profile: None,
+            block_hints: HashMap::new(),
+            attrs: FuncAttrs::default(),
+            arena: InstArena::default(),
+            journal: None,
         }
     }
 
+    /// `new` with room for `blocks` blocks and `vregs` vregs, so a frontend
+    /// that knows its sizes up front never regrows either table.
+    #[must_use]
+    pub fn with_capacity(name: String, blocks: usize, vregs: usize) -> Self {
+        let mut func = Self::new(name);
+        func.blocks.reserve(blocks);
+        func.reg_types.reserve(vregs);
+        func
+    }
+
     pub fn add_block(&mut self, data: BlockData<I>) -> Block {
         self.blocks.insert(data)
     }
@@ -52,7 +100,33 @@ impl<I: Inst> Func<I> {
         self.blocks.insert(BlockData::default())
     }
 
+    /// An empty instruction buffer with room for `capacity`, taken from
+    /// this function's arena when a recycled one fits. Pair with
+    /// `replace_insts` when rebuilding a block.
+    pub fn inst_buffer(&mut self, capacity: usize) -> Vec<Instruction<I>> {
+        self.arena.buffer(capacity)
+    }
+
+    /// Install `insts` as `block`'s instructions, recycling the buffer
+    /// they replace.
+    pub fn replace_insts(&mut self, block: Block, insts: Vec<Instruction<I>>) {
+        self.journal_block(block);
+        let old = std::mem::replace(self.blocks[block].insts_mut(), insts);
+        self.arena.recycle(old);
+    }
+
+    /// Give a buffer that's no longer needed back to the arena.
+    pub fn recycle_insts(&mut self, buf: Vec<Instruction<I>>) {
+        self.arena.recycle(buf);
+    }
+
+    #[must_use]
+    pub fn arena(&self) -> &InstArena<I> {
+        &self.arena
+    }
+
     pub fn get_block_data_mut(&mut self, block: Block) -> &mut BlockData<I> {
+        self.journal_block(block);
         &mut self.blocks[block]
     }
 
@@ -104,6 +178,128 @@ impl<I: Inst> Func<I> {
         }
     }
 
+    /// Delete every block in `dead` and renumber the survivors densely,
+    /// keeping their relative order. Branch targets and phi incoming
+    /// edges are remapped; the caller must already have removed every
+    /// edge into a deleted block. Returns the old-index → new-block map
+    /// (`None` for deleted blocks).
+    pub fn remove_blocks(&mut self, dead: &HashSet<Block>) -> Vec<Option<Block>> {
+        assert!(
+            self.get_entry_block().is_none_or(|e| !dead.contains(&e)),
+            "cannot remove the entry block"
+        );
+        self.journal_renumber();
+        let old = std::mem::take(&mut self.blocks);
+        let mut remap: Vec<Option<Block>> = vec![None; old.len()];
+        for (b, mut data) in old {
+            if dead.contains(&b) {
+                self.arena.recycle(data.take_insts());
+            } else {
+                remap[b.index()] = Some(self.blocks.insert(data));
+            }
+        }
+        self.renumber(&remap);
+        remap
+    }
+
+    /// Lay the blocks out in `order`, which must be a permutation of the
+    /// current blocks starting with the entry. Blocks are renumbered to
+    /// their new positions; branch targets, phi incoming edges, and the
+    /// profile and block hints follow. Returns the old-index → new-block map.
+    pub fn reorder_blocks(&mut self, order: &[Block]) -> Vec<Option<Block>> {
+        assert_eq!(order.len(), self.blocks.len(), "order must list every block once");
+        assert_eq!(order.first().copied(), self.get_entry_block(), "entry must stay first");
+        self.journal_renumber();
+        let mut old: Vec<Option<BlockData<I>>> =
+            std::mem::take(&mut self.blocks).into_iter().map(|(_, d)| Some(d)).collect();
+        let mut remap: Vec<Option<Block>> = vec![None; old.len()];
+        for &b in order {
+            let data = old[b.index()].take().expect("order lists a block twice");
+            remap[b.index()] = Some(self.blocks.insert(data));
+        }
+        self.renumber(&remap);
+        remap
+    }
+
+    /// Rewrite every block reference after the blocks were moved per
+    /// `remap` (old index → new block).
+    fn renumber(&mut self, remap: &[Option<Block>]) {
+        let new_of = |b: Block| {
+            remap[b.index()].unwrap_or_else(|| panic!("edge into removed block {b}"))
+        };
+        let keys: Vec<Block> = self.blocks.keys().collect();
+        for b in keys {
+            for inst in self.blocks[b].insts_mut() {
+                remap_targets(inst, new_of);
+            }
+        }
+        let phi_ids: Vec<PhiId> = self.phis.keys().collect();
+        for id in phi_ids {
+            for (pred, _) in &mut self.phis[id].incoming {
+                if let Some(nb) = remap[pred.index()] {
+                    *pred = nb;
+                }
+            }
+        }
+        let table_ids: Vec<JumpTableId> = self.jump_tables.keys().collect();
+        for id in table_ids {
+            for t in self.jump_tables[id].targets_mut() {
+                if let Some(nb) = remap[t.index()] {
+                    *t = nb;
+                }
+            }
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.remap(remap);
+        }
+        self.block_hints = std::mem::take(&mut self.block_hints)
+            .into_iter()
+            .filter_map(|(b, h)| Some((remap.get(b.index()).copied().flatten()?, h)))
+            .collect();
+    }
+
+    /// Attach execution counts, keyed by the current block numbering.
+    pub fn set_profile(&mut self, profile: Profile) {
+        journal!(self, profile);
+        self.profile = Some(profile);
+    }
+
+    #[must_use]
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Mark `block` likely or unlikely to run; `None` clears the hint.
+    /// Follows the block through renumbering.
+    pub fn set_block_hint(&mut self, block: Block, hint: Option<BlockHint>) {
+        journal!(self, block_hints);
+        match hint {
+            Some(h) => self.block_hints.insert(block, h),
+            None => self.block_hints.remove(&block),
+        };
+    }
+
+    #[must_use]
+    pub fn block_hint(&self, block: Block) -> Option<BlockHint> {
+        self.block_hints.get(&block).copied()
+    }
+
+    /// Whether any block carries a hint.
+    #[must_use]
+    pub fn has_block_hints(&self) -> bool {
+        !self.block_hints.is_empty()
+    }
+
+    #[must_use]
+    pub fn attrs(&self) -> &FuncAttrs {
+        &self.attrs
+    }
+
+    pub fn attrs_mut(&mut self) -> &mut FuncAttrs {
+        journal!(self, attrs);
+        &mut self.attrs
+    }
+
     pub fn blocks_iter(&self) -> impl Iterator<Item=(Block, &BlockData<I>)> {
         self.blocks.iter()
     }
@@ -116,6 +312,7 @@ impl<I: Inst> Func<I> {
     /// Register a phi node's incoming operands and return an opaque id
     /// to stamp into `PseudoInstruction::Phi { id }`.
     pub fn new_phi(&mut self, incoming: Vec<(Block, Reg)>) -> PhiId {
+        journal!(self, phis);
         self.phis.insert(PhiData { incoming })
     }
 
@@ -125,12 +322,67 @@ impl<I: Inst> Func<I> {
     }
 
     pub fn phi_operands_mut(&mut self, id: PhiId) -> &mut PhiData {
+        journal!(self, phis);
         &mut self.phis[id]
     }
 
+    /// Drop a phi's operand list once its `Phi` pseudo is gone; the id
+    /// may be reissued by a later `new_phi`.
+    pub fn remove_phi(&mut self, id: PhiId) -> PhiData {
+        journal!(self, phis);
+        self.phis.remove(id).expect("phi already removed")
+    }
+
+    /// Register a multi-way branch's cases and return an id to stamp
+    /// into `PseudoInstruction::Switch { table }` or a target table jump.
+    pub fn new_jump_table(&mut self, data: JumpTableData) -> JumpTableId {
+        journal!(self, jump_tables);
+        self.jump_tables.insert(data)
+    }
+
+    #[must_use]
+    pub fn jump_table(&self, id: JumpTableId) -> &JumpTableData {
+        &self.jump_tables[id]
+    }
+
+    pub fn jump_table_mut(&mut self, id: JumpTableId) -> &mut JumpTableData {
+        journal!(self, jump_tables);
+        &mut self.jump_tables[id]
+    }
+
+    /// Every block `inst` can branch to: its own targets, then those of
+    /// its jump table. Duplicates are kept.
+    #[must_use]
+    pub fn branch_targets(&self, inst: &Instruction<I>) -> SmallVec<[Block; 2]> {
+        let mut targets = inst.get_branch_targets();
+        if let Some(id) = inst.jump_table() {
+            targets.extend(self.jump_tables[id].targets());
+        }
+        targets
+    }
+
+    /// Point `b`'s terminator, jump table included, at `new` wherever it
+    /// branched to `old`.
+    pub fn rewrite_branch_target(&mut self, b: Block, old: Block, new: Block) {
+        self.journal_block(b);
+        let Some(term) = self.blocks[b].insts_mut().last_mut().filter(|t| t.is_term()) else {
+            return;
+        };
+        term.rewrite_branch_target(old, new);
+        if let Some(id) = term.jump_table() {
+            journal!(self, jump_tables);
+            for t in self.jump_tables[id].targets_mut() {
+                if *t == old {
+                    *t = new;
+                }
+            }
+        }
+    }
+
     /// Register a call's callee / args / rets and return an id to
     /// stamp into `PseudoInstruction::CallPseudo { id }`.
     pub fn new_call(&mut self, data: CallData) -> CallId {
+        journal!(self, calls);
         self.calls.insert(data)
     }
 
@@ -140,14 +392,59 @@ impl<I: Inst> Func<I> {
     }
 
     pub fn call_operands_mut(&mut self, id: CallId) -> &mut CallData {
+        journal!(self, calls);
         &mut self.calls[id]
     }
 
+    /// Register a raw-bytes payload and return an id to stamp into
+    /// `PseudoInstruction::RawBytes { id }`.
+    pub fn new_raw_bytes(&mut self, data: RawBytesData) -> RawBytesId {
+        journal!(self, raw_bytes);
+        self.raw_bytes.insert(data)
+    }
+
+    #[must_use]
+    pub fn raw_bytes_operands(&self, id: RawBytesId) -> &RawBytesData {
+        &self.raw_bytes[id]
+    }
+
+    /// Register an intrinsic use and return an id to stamp into
+    /// `PseudoInstruction::Intrinsic { id }`.
+    pub fn new_intrinsic(&mut self, data: IntrinsicData) -> IntrinsicId {
+        journal!(self, intrinsics);
+        self.intrinsics.insert(data)
+    }
+
+    #[must_use]
+    pub fn intrinsic_operands(&self, id: IntrinsicId) -> &IntrinsicData {
+        &self.intrinsics[id]
+    }
+
+    pub fn intrinsic_operands_mut(&mut self, id: IntrinsicId) -> &mut IntrinsicData {
+        &mut self.intrinsics[id]
+    }
+
+    /// The id of symbol `name`, interned on first use so each name gets
+    /// one id per function.
+    pub fn symbol(&mut self, name: &str) -> SymbolId {
+        if let Some((id, _)) = self.symbols.iter().find(|(_, n)| *n == name) {
+            return id;
+        }
+        journal!(self, symbols);
+        self.symbols.insert(name.to_string())
+    }
+
+    #[must_use]
+    pub fn symbol_name(&self, id: SymbolId) -> &str {
+        &self.symbols[id]
+    }
+
     /// Declare a frontend-level pre-bind: `vreg` must occupy physical
     /// register `preg` for its entire live range. Disagreement with any
     /// later source (ABI lowering, `RegDef` pseudo) triggers the
     /// allocator's pre-bind conflict check.
     pub fn pre_bind(&mut self, vreg: Reg, preg: Reg) {
+        journal!(self, pre_binds);
         if let Some(prev) = self.pre_binds.insert(vreg, preg)
             && prev != preg
         {
@@ -167,6 +464,7 @@ impl<I: Inst> Func<I> {
     /// `InsertValue { agg_id }`. Element vregs should already exist and
     /// are referenced by value — the aggregate doesn't take ownership.
     pub fn new_aggregate(&mut self, elems: Vec<Reg>) -> AggregateId {
+        journal!(self, aggregates);
         self.aggregates.insert(AggregateData { elems })
     }
 
@@ -179,14 +477,244 @@ impl<I: Inst> Func<I> {
     pub fn has_aggregates(&self) -> bool {
         !self.aggregates.is_empty()
     }
+
+    /// Rename vregs throughout in one walk: each `r` with an entry in
+    /// `map` becomes `map[r]`, in every instruction, in the phi, call,
+    /// raw-bytes, intrinsic and aggregate side tables, and in the
+    /// pre-binds. Vregs without an entry are kept; pregs are never
+    /// touched.
+    pub fn rewrite_regs(&mut self, map: &SecondaryMap<Reg, Reg>) {
+        let f = |r: Reg| map.get(r).copied().unwrap_or(r);
+        let blocks: Vec<Block> = self.blocks.keys().collect();
+        for b in blocks {
+            for inst in self.get_block_data_mut(b).insts_mut() {
+                inst.map_regs(&mut |r| f(r));
+            }
+        }
+        journal!(self, phis);
+        journal!(self, calls);
+        journal!(self, raw_bytes);
+        journal!(self, intrinsics);
+        journal!(self, aggregates);
+        journal!(self, pre_binds);
+        let phi_ids: Vec<PhiId> = self.phis.keys().collect();
+        for id in phi_ids {
+            for (_, r) in &mut self.phis[id].incoming {
+                *r = f(*r);
+            }
+        }
+        let call_ids: Vec<CallId> = self.calls.keys().collect();
+        for id in call_ids {
+            let call = &mut self.calls[id];
+            if let CallTarget::Indirect(r) = &mut call.callee {
+                *r = f(*r);
+            }
+            for r in call.args.iter_mut().chain(&mut call.rets) {
+                *r = f(*r);
+            }
+        }
+        let raw_ids: Vec<RawBytesId> = self.raw_bytes.keys().collect();
+        for id in raw_ids {
+            let raw = &mut self.raw_bytes[id];
+            for (v, _) in raw.uses.iter_mut().chain(&mut raw.defs) {
+                *v = f(*v);
+            }
+        }
+        let intrinsic_ids: Vec<IntrinsicId> = self.intrinsics.keys().collect();
+        for id in intrinsic_ids {
+            let data = &mut self.intrinsics[id];
+            for r in data.args.iter_mut().chain(&mut data.rets) {
+                *r = f(*r);
+            }
+        }
+        let aggregate_ids: Vec<AggregateId> = self.aggregates.keys().collect();
+        for id in aggregate_ids {
+            for r in &mut self.aggregates[id].elems {
+                *r = f(*r);
+            }
+        }
+        for (v, p) in std::mem::take(&mut self.pre_binds) {
+            self.pre_bind(f(v), p);
+        }
+    }
+
+    /// Start recording edits, so a speculative transform can `rollback`
+    /// when it turns out unprofitable. Checkpoints don't nest.
+    pub fn checkpoint(&mut self) {
+        assert!(self.journal.is_none(), "checkpoint while one is already open");
+        let journal = Journal::new(self.regs_count, self.blocks.key_bound());
+        self.journal = Some(Box::new(journal));
+    }
+
+    /// Whether a `checkpoint` is open.
+    #[must_use]
+    pub fn has_checkpoint(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Keep every edit since `checkpoint`.
+    pub fn commit(&mut self) {
+        self.journal.take().expect("commit without a checkpoint");
+    }
+
+    /// Undo every edit since `checkpoint`: blocks, vregs and side tables
+    /// are as they were then. Ids issued since are dangling again.
+    pub fn rollback(&mut self) {
+        let j = *self.journal.take().expect("rollback without a checkpoint");
+        self.regs_count = j.regs_count;
+        self.reg_types.truncate(j.regs_count as usize);
+        if let Some(all) = j.all_blocks {
+            for (_, mut data) in std::mem::replace(&mut self.blocks, all) {
+                self.arena.recycle(data.take_insts());
+            }
+        } else {
+            let added: Vec<Block> =
+                self.blocks.keys().filter(|b| b.index() >= j.block_bound).collect();
+            for b in added {
+                let mut data = self.blocks.remove(b).expect("live key");
+                self.arena.recycle(data.take_insts());
+            }
+            self.blocks.truncate(j.block_bound);
+            for (b, data) in j.blocks {
+                let mut edited = std::mem::replace(&mut self.blocks[b], data);
+                self.arena.recycle(edited.take_insts());
+            }
+        }
+        if let Some(t) = j.phis {
+            self.phis = t;
+        }
+        if let Some(t) = j.calls {
+            self.calls = t;
+        }
+        if let Some(t) = j.raw_bytes {
+            self.raw_bytes = t;
+        }
+        if let Some(t) = j.intrinsics {
+            self.intrinsics = t;
+        }
+        if let Some(t) = j.aggregates {
+            self.aggregates = t;
+        }
+        if let Some(t) = j.jump_tables {
+            self.jump_tables = t;
+        }
+        if let Some(t) = j.symbols {
+            self.symbols = t;
+        }
+        if let Some(t) = j.pre_binds {
+            self.pre_binds = t;
+        }
+        if let Some(t) = j.profile {
+            self.profile = t;
+        }
+        if let Some(t) = j.block_hints {
+            self.block_hints = t;
+        }
+        if let Some(t) = j.attrs {
+            self.attrs = t;
+        }
+    }
+
+    /// Save `block` into the open journal, if any, before it's edited.
+    fn journal_block(&mut self, block: Block) {
+        if let Some(j) = &mut self.journal {
+            j.save_block(block, &self.blocks);
+        }
+    }
+
+    /// Save everything a renumbering rewrites into the open journal.
+    fn journal_renumber(&mut self) {
+        if let Some(j) = &mut self.journal {
+            j.save_all_blocks(&self.blocks);
+        }
+        journal!(self, phis);
+        journal!(self, jump_tables);
+        journal!(self, profile);
+        journal!(self, block_hints);
+    }
+}
+
+impl<I: Inst + Hash> Func<I> {
+    /// A hash of everything compiling the function reads: name,
+    /// attributes, blocks, side tables, vreg types, pre-binds, profile and
+    /// hints. Stable within one build of lancy, not across builds.
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        fn values<K: Key, V: Hash>(map: &PrimaryMap<K, V>, h: &mut DefaultHasher) {
+            map.len().hash(h);
+            for (_, v) in map.iter() {
+                v.hash(h);
+            }
+        }
+        fn by_key<K: Ord + Hash, V: Hash>(mut entries: Vec<(K, V)>, h: &mut DefaultHasher) {
+            entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            entries.hash(h);
+        }
+        let mut h = DefaultHasher::new();
+        self.name.hash(&mut h);
+        self.attrs.hash(&mut h);
+        values(&self.blocks, &mut h);
+        values(&self.phis, &mut h);
+        values(&self.calls, &mut h);
+        values(&self.raw_bytes, &mut h);
+        values(&self.intrinsics, &mut h);
+        values(&self.aggregates, &mut h);
+        values(&self.jump_tables, &mut h);
+        values(&self.symbols, &mut h);
+        self.regs_count.hash(&mut h);
+        self.reg_types.hash(&mut h);
+        by_key(self.pre_binds.iter().collect(), &mut h);
+        by_key(self.block_hints.iter().collect(), &mut h);
+        self.profile.is_some().hash(&mut h);
+        if let Some(p) = &self.profile {
+            by_key(p.block_counts.iter().collect(), &mut h);
+            by_key(p.edge_counts.iter().collect(), &mut h);
+        }
+        h.finish()
+    }
+}
+
+/// Apply `new_of` to every branch target of `inst`. Targets are rewritten
+/// one at a time, so a target is only moved once nothing else still waits
+/// to move onto its new index; a cycle (e.g. a swap) is broken by parking
+/// one target on `Block::NONE_VAL`.
+fn remap_targets<I: Inst>(inst: &mut Instruction<I>, new_of: impl Fn(Block) -> Block) {
+    let mut targets = inst.get_branch_targets();
+    targets.sort_unstable();
+    targets.dedup();
+    let mut pending: Vec<(Block, Block)> = targets
+        .into_iter()
+        .map(|t| (t, new_of(t)))
+        .filter(|(o, n)| o != n)
+        .collect();
+    while !pending.is_empty() {
+        let free = pending
+            .iter()
+            .position(|&(_, n)| pending.iter().all(|&(o, _)| o != n));
+        if let Some(i) = free {
+            let (o, n) = pending.swap_remove(i);
+            inst.rewrite_branch_target(o, n);
+        } else {
+            let (o, n) = pending[0];
+            inst.rewrite_branch_target(o, Block::NONE_VAL);
+            pending[0] = (Block::NONE_VAL, n);
+        }
+    }
 }
 
 impl<I: Inst> Display for Func<I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}:", self.name)?;
+        if self.attrs == FuncAttrs::default() {
+            writeln!(f, "{}:", self.name)?;
+        } else {
+            writeln!(f, "{}: ; {}", self.name, self.attrs)?;
+        }
 
         for (id, data) in self.blocks.iter() {
             write!(f, "{id}")?;
+            if let Some(hint) = self.block_hint(id) {
+                write!(f, " ; {hint}")?;
+            }
             write!(f, "\n{data}")?;
         }
 
@@ -194,12 +722,110 @@ impl<I: Inst> Display for Func<I> {
     }
 }
 
+/// The printed IR, so a `Func` can show up in assertion and `proptest`
+/// failure messages.
+impl<I: Inst> Debug for Func<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::codegen::isa::x64::inst::X64Inst;
     use crate::codegen::tir::CallData;
     use crate::codegen::tir::CallTarget;
+    use crate::codegen::tir::PseudoInstruction;
+
+    #[test]
+    fn remove_blocks_renumbers_survivors_and_their_branch_targets() {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        let b3 = func.add_empty_block();
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: crate::codegen::isa::x64::inst::Cond::Z,
+            taken: b3,
+            not_taken: b2,
+        });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::Ud2);
+        func.get_block_data_mut(b2).push_target_inst(X64Inst::Jmp { dst: b3 });
+        func.get_block_data_mut(b3).push_target_inst(X64Inst::RawRet);
+
+        let remap = func.remove_blocks(&HashSet::from([b1]));
+        assert_eq!(remap, vec![Some(b0), None, Some(b1), Some(b2)]);
+        assert_eq!(func.blocks_count(), 3);
+        let term = func.get_block_data(b0).get_terminator().unwrap();
+        assert_eq!(term.get_branch_targets().as_slice(), &[b2, b1]);
+        let term = func.get_block_data(b1).get_terminator().unwrap();
+        assert_eq!(term.get_branch_targets().as_slice(), &[b2]);
+    }
+
+    #[test]
+    fn rebuilt_blocks_reuse_recycled_buffers() {
+        let mut func = Func::<X64Inst>::new("arena".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let v = func.new_vreg();
+        for b in [b0, b1] {
+            for imm in 0..8 {
+                func.get_block_data_mut(b)
+                    .push_target_inst(X64Inst::Mov64ri { dst: v, imm });
+            }
+        }
+        let mut fresh = func.inst_buffer(8);
+        fresh.push(Instruction::Target(X64Inst::Mov64ri { dst: v, imm: 9 }));
+        func.replace_insts(b0, fresh);
+        assert_eq!(func.arena().spare_count(), 1);
+        // b0's old buffer is big enough for b1's rewrite.
+        let reused = func.inst_buffer(8);
+        assert!(reused.capacity() >= 8 && reused.is_empty());
+        assert_eq!(func.arena().spare_count(), 0);
+        func.replace_insts(b1, reused);
+        assert!(func.get_block_data(b1).is_empty());
+        assert_eq!(func.get_block_data(b0).len(), 1);
+    }
+
+    #[test]
+    fn presized_func_fills_without_regrowing() {
+        let mut func = Func::<X64Inst>::with_capacity("big".to_string(), 2, 64);
+        let b0 = func.add_empty_block();
+        let vregs: Vec<Reg> = (0..64).map(|_| func.new_vreg()).collect();
+        let bd = func.get_block_data_mut(b0);
+        bd.reserve(vregs.len());
+        let buf = bd.insts().as_ptr();
+        bd.extend(
+            vregs
+                .iter()
+                .map(|&dst| Instruction::Target(X64Inst::Mov64ri { dst, imm: 1 })),
+        );
+        assert_eq!(bd.len(), 64);
+        assert_eq!(bd.insts().as_ptr(), buf);
+        assert_eq!(func.get_regs_count(), 64);
+    }
+
+    #[test]
+    fn reorder_blocks_handles_swapped_branch_targets() {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b0 = func.add_empty_block();
+        let b1 = func.add_empty_block();
+        let b2 = func.add_empty_block();
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: crate::codegen::isa::x64::inst::Cond::Z,
+            taken: b1,
+            not_taken: b2,
+        });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::Ud2);
+        func.get_block_data_mut(b2).push_target_inst(X64Inst::RawRet);
+
+        let remap = func.reorder_blocks(&[b0, b2, b1]);
+        assert_eq!(remap, vec![Some(b0), Some(b2), Some(b1)]);
+        let term = func.get_block_data(b0).get_terminator().unwrap();
+        assert_eq!(term.get_branch_targets().as_slice(), &[b2, b1]);
+        assert!(func.get_block_data(b1).get_terminator().unwrap().is_ret());
+    }
 
     #[test]
     fn new_phi_round_trips_incoming_edges() {
@@ -247,6 +873,7 @@ mod tests {
             callee: CallTarget::Symbol("puts".to_string()),
             args: vec![v0, v1],
             rets: vec![ret],
+            patch: None,
         });
         let data = func.call_operands(id);
         assert!(matches!(&data.callee, CallTarget::Symbol(s) if s == "puts"));
@@ -281,6 +908,109 @@ mod tests {
         assert_eq!(func.aggregate_operands(id).elems, vec![v0, v1]);
     }
 
+    /// `b0: jz b1, b2`, `b1: ud2`, `b2: ret`.
+    fn three_blocks() -> Func<X64Inst> {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let (b0, b1, b2) = (func.add_empty_block(), func.add_empty_block(), func.add_empty_block());
+        func.get_block_data_mut(b0).push_target_inst(X64Inst::CondJmp {
+            cond: crate::codegen::isa::x64::inst::Cond::Z,
+            taken: b1,
+            not_taken: b2,
+        });
+        func.get_block_data_mut(b1).push_target_inst(X64Inst::Ud2);
+        func.get_block_data_mut(b2).push_target_inst(X64Inst::RawRet);
+        func
+    }
+
+    #[test]
+    fn rollback_undoes_block_edits_new_blocks_vregs_and_phis() {
+        let mut func = three_blocks();
+        let v = func.new_vreg();
+        let phi = func.new_phi(vec![(Block::new(0), v)]);
+        let before = func.to_string();
+
+        func.checkpoint();
+        let b3 = func.add_empty_block();
+        let w = func.new_vreg();
+        func.get_block_data_mut(b3).push_target_inst(X64Inst::Mov64ri { dst: w, imm: 7 });
+        func.get_block_data_mut(Block::new(1)).insts_mut().clear();
+        func.rewrite_branch_target(Block::new(0), Block::new(2), b3);
+        func.phi_operands_mut(phi).incoming.push((b3, w));
+        func.remove_phi(phi);
+        func.rollback();
+
+        assert!(!func.has_checkpoint());
+        assert_eq!(func.to_string(), before);
+        assert_eq!(func.blocks_count(), 3);
+        assert_eq!(func.get_regs_count(), 1);
+        assert_eq!(func.phi_operands(phi).incoming, vec![(Block::new(0), v)]);
+        assert_eq!(func.add_empty_block(), Block::new(3));
+    }
+
+    #[test]
+    fn rollback_undoes_a_renumbering() {
+        let mut func = three_blocks();
+        func.set_block_hint(Block::new(1), Some(BlockHint::Unlikely));
+        let before = func.to_string();
+        func.checkpoint();
+        func.get_block_data_mut(Block::new(2)).insts_mut().clear();
+        func.reorder_blocks(&[Block::new(0), Block::new(2), Block::new(1)]);
+        func.rewrite_branch_target(Block::new(0), Block::new(2), Block::new(1));
+        func.remove_blocks(&HashSet::from([Block::new(2)]));
+        func.rollback();
+        assert_eq!(func.to_string(), before);
+        assert_eq!(func.block_hint(Block::new(1)), Some(BlockHint::Unlikely));
+    }
+
+    #[test]
+    fn commit_keeps_the_edits() {
+        let mut func = three_blocks();
+        func.checkpoint();
+        func.get_block_data_mut(Block::new(1)).insts_mut().clear();
+        func.commit();
+        assert!(!func.has_checkpoint());
+        assert!(func.get_block_data(Block::new(1)).is_empty());
+    }
+
+    #[test]
+    fn rewrite_regs_renames_instructions_and_side_tables_at_once() {
+        let mut func = Func::<X64Inst>::new("t".to_string());
+        let b0 = func.add_empty_block();
+        let (a, b, c) = (func.new_vreg(), func.new_vreg(), func.new_vreg());
+        let phi = func.new_phi(vec![(b0, a), (b0, b)]);
+        let call = func.new_call(CallData {
+            callee: CallTarget::Indirect(a),
+            args: vec![b, c],
+            rets: vec![a],
+            patch: None,
+        });
+        func.pre_bind(a, 7);
+        {
+            let bd = func.get_block_data_mut(b0);
+            bd.push_pseudo_inst(PseudoInstruction::Copy { dst: a, src: b });
+            bd.push_pseudo_inst(PseudoInstruction::Copy { dst: b, src: a });
+        }
+        // A swap: one walk, so `a -> b` can't be renamed again by `b -> a`.
+        let mut map = SecondaryMap::new(3);
+        map.set(a, b);
+        map.set(b, a);
+        func.rewrite_regs(&map);
+
+        let insts: Vec<_> = func.get_block_data(b0).iter().copied().collect();
+        assert!(matches!(
+            insts[..],
+            [
+                Instruction::Pseudo(PseudoInstruction::Copy { dst: d0, src: s0 }),
+                Instruction::Pseudo(PseudoInstruction::Copy { dst: d1, src: s1 }),
+            ] if (d0, s0, d1, s1) == (b, a, a, b)
+        ));
+        assert_eq!(func.phi_operands(phi).incoming, vec![(b0, b), (b0, a)]);
+        let data = func.call_operands(call);
+        assert_eq!(data.callee, CallTarget::Indirect(b));
+        assert_eq!((data.args.clone(), data.rets.clone()), (vec![a, c], vec![b]));
+        assert_eq!(func.pre_binds(), &HashMap::from([(b, 7)]));
+    }
+
     #[test]
     fn new_call_round_trips_indirect_target() {
         let mut func = Func::<X64Inst>::new("t".to_string());
@@ -289,10 +1019,33 @@ mod tests {
             callee: CallTarget::Indirect(fn_ptr),
             args: Vec::new(),
             rets: Vec::new(),
+            patch: None,
         });
         match &func.call_operands(id).callee {
             CallTarget::Indirect(r) => assert_eq!(*r, fn_ptr),
             CallTarget::Symbol(_) => panic!("expected indirect callee"),
         }
     }
+
+    #[test]
+    fn content_hash_sees_state_the_listing_leaves_out() {
+        let build = || {
+            let mut func = Func::<X64Inst>::new("t".to_string());
+            let b0 = func.add_empty_block();
+            let v = func.new_vreg();
+            func.get_block_data_mut(b0).push_target_inst(X64Inst::Mov64ri { dst: v, imm: 1 });
+            func.get_block_data_mut(b0).push_target_inst(X64Inst::RawRet);
+            (func, b0, v)
+        };
+        let (a, _, _) = build();
+        let (mut b, b0, v) = build();
+        assert_eq!(a.content_hash(), b.content_hash());
+        b.pre_bind(v, 0);
+        assert_eq!(a.to_string(), b.to_string());
+        assert_ne!(a.content_hash(), b.content_hash());
+        let (mut c, _, _) = build();
+        c.set_profile(Profile { block_counts: HashMap::from([(b0, 5)]), ..Profile::default() });
+        assert_eq!(a.to_string(), c.to_string());
+        assert_ne!(a.content_hash(), c.content_hash());
+    }
 }
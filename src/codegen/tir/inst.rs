@@ -1,7 +1,8 @@
 use smallvec::{smallvec, SmallVec};
+use std::cell::RefCell;
 use std::fmt::{Debug, Display, Formatter};
 
-use super::{AggregateId, Reg};
+use super::{AggregateId, Reg, Type};
 use crate::codegen::tir::Block;
 use crate::slotmap_key;
 
@@ -16,6 +17,29 @@ pub trait Inst: Sized + Copy + Display {
     fn get_uses(&self) -> SmallVec<[Reg; 2]>;
     fn get_defs(&self) -> SmallVec<[Reg; 1]>;
 
+    /// Replace every register operand `r` stored in the instruction, use
+    /// or def, with `f(r)`. Operands in `Func` side tables are untouched.
+    fn map_regs(&mut self, f: &mut dyn FnMut(Reg) -> Reg);
+
+    /// `Some((dst, src))` if this instruction is a plain full-width copy
+    /// of one register-class value into another, with no other effect.
+    /// Post-allocation cleanups use this to spot moves they can delete.
+    fn is_move(&self) -> Option<(Reg, Reg)> {
+        None
+    }
+
+    /// Whether this instruction transfers control to another function,
+    /// clobbering whatever the calling convention doesn't preserve.
+    fn is_call(&self) -> bool {
+        false
+    }
+
+    /// Whether this instruction moves the stack pointer, leaving the
+    /// frame's bottom somewhere else than the prologue put it.
+    fn moves_stack_pointer(&self) -> bool {
+        false
+    }
+
     fn get_branch_targets(&self) -> SmallVec<[Block; 2]>;
 
     /// If this instruction is a branch whose target list contains
@@ -23,6 +47,46 @@ pub trait Inst: Sized + Copy + Display {
     /// non-branch instructions.
     fn rewrite_branch_target(&mut self, old: Block, new: Block);
 
+    /// Replace every branch target `t` with `f(t)`, all targets at once —
+    /// unlike repeated `rewrite_branch_target`, a renaming that maps one
+    /// target onto another's old name is safe.
+    fn map_branch_targets(&mut self, f: &mut dyn FnMut(Block) -> Block);
+
+    /// Replace every symbol operand `s` with `f(s)`. Passes that move
+    /// instructions between functions re-intern symbols through this.
+    fn map_symbols(&mut self, _f: &mut dyn FnMut(SymbolId) -> SymbolId) {}
+
+    /// The case list of a multi-way branch. Its blocks are successors
+    /// `get_branch_targets` leaves out; `Func::branch_targets` has both.
+    fn jump_table(&self) -> Option<JumpTableId> {
+        None
+    }
+
+    /// Replace the jump-table operand `t` with `f(t)`. Passes that copy
+    /// instructions clone the table through this.
+    fn map_jump_table(&mut self, _f: &mut dyn FnMut(JumpTableId) -> JumpTableId) {}
+
+    /// Exact machine-code bytes of this instruction once each register
+    /// operand `r` is in preg `preg(r)`, with no spill traffic around it.
+    /// `None` if the size depends on more than the operands (the frame,
+    /// a `Func` side table) or the target doesn't say.
+    fn encoded_size(&self, _preg: &dyn Fn(Reg) -> Reg) -> Option<u32> {
+        None
+    }
+
+    /// Upper bound on the bytes the emitter produces for this instruction
+    /// under any allocation, spill reloads and stores included. `None` as
+    /// for `encoded_size`.
+    fn worst_case_size(&self) -> Option<u32> {
+        None
+    }
+
+    /// The intrinsics this target implements, for `IntrinsicData` to
+    /// name. `lower_intrinsics` expands each use per its declaration.
+    fn intrinsics() -> &'static [IntrinsicDecl] {
+        &[]
+    }
+
     /// Target-specific factory for an unconditional jump. Used by
     /// generic passes (critical-edge splitting in SSA destruction) that
     /// need to synthesize a terminator without knowing the target ISA.
@@ -31,6 +95,10 @@ pub trait Inst: Sized + Copy + Display {
 
 slotmap_key!(PhiId(u32));
 slotmap_key!(CallId(u32));
+slotmap_key!(RawBytesId(u32));
+slotmap_key!(SymbolId(u32));
+slotmap_key!(JumpTableId(u32));
+slotmap_key!(IntrinsicId(u32));
 
 impl Display for PhiId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -56,20 +124,135 @@ impl Debug for CallId {
     }
 }
 
+impl Display for RawBytesId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "raw#{}", self.0)
+    }
+}
+
+impl Debug for RawBytesId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for IntrinsicId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "intrinsic#{}", self.0)
+    }
+}
+
+impl Debug for IntrinsicId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for JumpTableId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "jt#{}", self.0)
+    }
+}
+
+impl Debug for JumpTableId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Display for SymbolId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sym#{}", self.0)
+    }
+}
+
+impl Debug for SymbolId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Why a `Trap` fires. Emission records it at the trapping instruction's
+/// offset, so a runtime's fault handler can tell a failed bounds check
+/// from reaching `unreachable`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrapCode {
+    /// Control reached code the frontend declared unreachable.
+    Unreachable,
+    /// An index failed its bounds check.
+    BoundsCheck,
+    /// Checked arithmetic overflowed.
+    IntegerOverflow,
+    /// Integer division by zero.
+    DivideByZero,
+    /// Frontend-defined.
+    User(u16),
+}
+
+impl TrapCode {
+    /// Inverse of `Display`.
+    #[must_use]
+    pub fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "unreachable" => TrapCode::Unreachable,
+            "bounds_check" => TrapCode::BoundsCheck,
+            "integer_overflow" => TrapCode::IntegerOverflow,
+            "divide_by_zero" => TrapCode::DivideByZero,
+            _ => TrapCode::User(s.strip_prefix("user(")?.strip_suffix(')')?.parse().ok()?),
+        })
+    }
+}
+
+impl Display for TrapCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrapCode::Unreachable => f.write_str("unreachable"),
+            TrapCode::BoundsCheck => f.write_str("bounds_check"),
+            TrapCode::IntegerOverflow => f.write_str("integer_overflow"),
+            TrapCode::DivideByZero => f.write_str("divide_by_zero"),
+            TrapCode::User(n) => write!(f, "user({n})"),
+        }
+    }
+}
+
+/// What a patchable NOP sled sits in front of. Runtimes that hot-patch or
+/// trace code look sites up by kind in the compiled code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PatchKind {
+    /// The function's first instruction, before the prologue.
+    Entry,
+    /// A call: the sled directly precedes the `call` itself.
+    Call,
+    /// Anywhere else the frontend asked for one.
+    Point,
+}
+
+impl Display for PatchKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PatchKind::Entry => "entry",
+            PatchKind::Call => "call",
+            PatchKind::Point => "point",
+        })
+    }
+}
+
 /// Target-neutral pseudo instructions. Closed set.
 ///
 /// Most pseudos are erased (`Kill`, `ImplicitDef`), lowered to targets
-/// (`Arg`, `Return`, `CallPseudo`, `Phi`, `StackAlloc`, `FrameSetup`,
-/// `FrameDestroy`), or honored as regalloc constraints (`RegDef`) by
+/// (`Arg`, `Return`, `CallPseudo`, `RawBytes`, `Intrinsic`, `Phi`,
+/// `StackAlloc`, `FrameSetup`, `FrameDestroy`), or honored as regalloc constraints (`RegDef`) by
 /// earlier passes before machine-code emission. Two exceptions are
 /// `Copy` (survives as a MOV candidate) and `Arg` (stays as a pinned
 /// def shim after ABI lowering).
 ///
-/// Variable-length operands — phi incoming edges and call arg/result
-/// lists — live in side tables on `Func`, keyed by `PhiId` / `CallId`.
+/// Variable-length operands — phi incoming edges, call and intrinsic
+/// arg/result lists, raw-bytes payloads and switch cases — live in side
+/// tables on `Func`, keyed by `PhiId` / `CallId` / `IntrinsicId` /
+/// `RawBytesId` / `JumpTableId`.
 /// The enum itself stays `Copy` so instruction arrays can be moved and
 /// pattern-matched cheaply.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PseudoInstruction {
     /// Incoming argument `idx`. Lowered by the ABI pass.
     Arg { dst: Reg, idx: u32 },
@@ -88,6 +271,14 @@ pub enum PseudoInstruction {
     /// Abstract call. Variable-length arg/result lists live at
     /// `Func::call_operands(id)`. Lowered by the ABI pass.
     CallPseudo { id: CallId },
+    /// Literal machine code with register operands at
+    /// `Func::raw_bytes_operands(id)`. The ABI pass pins the operands to
+    /// their pregs around it; emission copies the bytes verbatim.
+    RawBytes { id: RawBytesId },
+    /// Use of a target intrinsic, with its operands at
+    /// `Func::intrinsic_operands(id)`. `lower_intrinsics` turns it into
+    /// `RawBytes` or a `CallPseudo`, as the declaration says.
+    Intrinsic { id: IntrinsicId },
     /// Marker for prologue insertion. Erased by prologue/epilogue pass.
     FrameSetup,
     /// Marker for epilogue insertion. Erased by prologue/epilogue pass.
@@ -120,6 +311,26 @@ pub enum PseudoInstruction {
     /// pass: the destination gets a fresh element list that reuses
     /// every unchanged element vreg and substitutes `val` at `idx`.
     InsertValue { dst: Reg, agg: Reg, val: Reg, idx: u32 },
+
+    /// Abort with `code`. A terminator with no successors; the target
+    /// emits its trap instruction (`ud2` on x64) and records `code` at
+    /// its offset.
+    Trap { code: TrapCode },
+
+    /// A point where the garbage collector may run. Emits nothing; the
+    /// compiled code records where each `Type::Ref` vreg live here is,
+    /// keyed by the offset of the code that follows.
+    Safepoint,
+
+    /// Reserve `len` bytes of NOPs for code a runtime writes later. The
+    /// compiled code records the sled's offset, length and `kind`.
+    PatchPoint { len: u32, kind: PatchKind },
+
+    /// Multi-way branch on `index`: to the case at
+    /// `Func::jump_table(table)` whose value equals it, else to the
+    /// table's default. Lowered by the target to a compare tree or an
+    /// indirect jump through a table of block addresses.
+    Switch { index: Reg, table: JumpTableId },
 }
 
 impl Display for PseudoInstruction {
@@ -139,6 +350,8 @@ impl Display for PseudoInstruction {
                 write!(f, "{} = stackalloc size={size} align={align}", reg_name(*dst))
             }
             PseudoInstruction::CallPseudo { id } => write!(f, "call {id}"),
+            PseudoInstruction::RawBytes { id } => write!(f, "raw_bytes {id}"),
+            PseudoInstruction::Intrinsic { id } => write!(f, "intrinsic {id}"),
             PseudoInstruction::FrameSetup => f.write_str("frame_setup"),
             PseudoInstruction::FrameDestroy => f.write_str("frame_destroy"),
             PseudoInstruction::ImplicitDef { dst } => {
@@ -164,42 +377,62 @@ impl Display for PseudoInstruction {
                 reg_name(*agg),
                 reg_name(*val)
             ),
+            PseudoInstruction::Trap { code } => write!(f, "trap {code}"),
+            PseudoInstruction::Safepoint => f.write_str("safepoint"),
+            PseudoInstruction::PatchPoint { len, kind: PatchKind::Point } => {
+                write!(f, "patch_point {len}")
+            }
+            PseudoInstruction::PatchPoint { len, kind } => write!(f, "patch_point {kind} {len}"),
+            PseudoInstruction::Switch { index, table } => {
+                write!(f, "switch {}, {table}", reg_name(*index))
+            }
         }
     }
 }
 
 impl Inst for PseudoInstruction {
     fn is_branch(&self) -> bool {
-        false
+        matches!(self, PseudoInstruction::Switch { .. })
     }
 
     fn is_ret(&self) -> bool {
         matches!(self, PseudoInstruction::Return { .. })
     }
 
+    fn is_term(&self) -> bool {
+        self.is_branch() || self.is_ret() || matches!(self, PseudoInstruction::Trap { .. })
+    }
+
     fn get_uses(&self) -> SmallVec<[Reg; 2]> {
         match self {
             PseudoInstruction::Copy { src, .. } | PseudoInstruction::Return { src } => {
                 smallvec![*src]
             }
             PseudoInstruction::Kill { src } => smallvec![*src],
+            PseudoInstruction::Switch { index, .. } => smallvec![*index],
             PseudoInstruction::ExtractValue { agg, .. } => smallvec![*agg],
             PseudoInstruction::InsertValue { agg, val, .. } => smallvec![*agg, *val],
-            // Phi, CallPseudo, and MakeAggregate uses live in side tables
-            // on `Func`. Callers that need those operands (SSA destruction,
-            // ABI lowering, aggregate lowering) consult
-            // `Func::phi_operands` / `call_operands` /
-            // `aggregate_operands` directly rather than going through
-            // `get_uses`.
+            // Phi, CallPseudo, RawBytes, Intrinsic and MakeAggregate uses
+            // live in side tables on `Func`. Callers that need those
+            // operands (SSA destruction, ABI lowering, aggregate and
+            // intrinsic lowering) consult `Func::phi_operands` /
+            // `call_operands` / `raw_bytes_operands` /
+            // `intrinsic_operands` / `aggregate_operands` directly rather
+            // than going through `get_uses`.
             PseudoInstruction::Arg { .. }
             | PseudoInstruction::Phi { .. }
             | PseudoInstruction::StackAlloc { .. }
             | PseudoInstruction::CallPseudo { .. }
+            | PseudoInstruction::RawBytes { .. }
+            | PseudoInstruction::Intrinsic { .. }
             | PseudoInstruction::FrameSetup
             | PseudoInstruction::FrameDestroy
             | PseudoInstruction::ImplicitDef { .. }
             | PseudoInstruction::RegDef { .. }
-            | PseudoInstruction::MakeAggregate { .. } => smallvec![],
+            | PseudoInstruction::MakeAggregate { .. }
+            | PseudoInstruction::Trap { .. }
+            | PseudoInstruction::Safepoint
+            | PseudoInstruction::PatchPoint { .. } => smallvec![],
         }
     }
 
@@ -216,18 +449,103 @@ impl Inst for PseudoInstruction {
             PseudoInstruction::RegDef { vreg, .. } => smallvec![*vreg],
             PseudoInstruction::Return { .. }
             | PseudoInstruction::CallPseudo { .. }
+            | PseudoInstruction::RawBytes { .. }
+            | PseudoInstruction::Intrinsic { .. }
+            | PseudoInstruction::FrameSetup
+            | PseudoInstruction::FrameDestroy
+            | PseudoInstruction::Kill { .. }
+            | PseudoInstruction::Trap { .. }
+            | PseudoInstruction::Safepoint
+            | PseudoInstruction::PatchPoint { .. }
+            | PseudoInstruction::Switch { .. } => smallvec![],
+        }
+    }
+
+    fn map_regs(&mut self, f: &mut dyn FnMut(Reg) -> Reg) {
+        match self {
+            PseudoInstruction::Copy { dst, src } => {
+                *dst = f(*dst);
+                *src = f(*src);
+            }
+            PseudoInstruction::Arg { dst, .. }
+            | PseudoInstruction::Phi { dst, .. }
+            | PseudoInstruction::StackAlloc { dst, .. }
+            | PseudoInstruction::ImplicitDef { dst }
+            | PseudoInstruction::MakeAggregate { dst, .. } => *dst = f(*dst),
+            PseudoInstruction::Return { src } | PseudoInstruction::Kill { src } => *src = f(*src),
+            PseudoInstruction::RegDef { vreg, .. } => *vreg = f(*vreg),
+            PseudoInstruction::Switch { index, .. } => *index = f(*index),
+            PseudoInstruction::ExtractValue { dst, agg, .. } => {
+                *dst = f(*dst);
+                *agg = f(*agg);
+            }
+            PseudoInstruction::InsertValue { dst, agg, val, .. } => {
+                *dst = f(*dst);
+                *agg = f(*agg);
+                *val = f(*val);
+            }
+            PseudoInstruction::CallPseudo { .. }
+            | PseudoInstruction::RawBytes { .. }
+            | PseudoInstruction::Intrinsic { .. }
             | PseudoInstruction::FrameSetup
             | PseudoInstruction::FrameDestroy
-            | PseudoInstruction::Kill { .. } => smallvec![],
+            | PseudoInstruction::Trap { .. }
+            | PseudoInstruction::Safepoint
+            | PseudoInstruction::PatchPoint { .. } => {}
+        }
+    }
+
+    fn is_move(&self) -> Option<(Reg, Reg)> {
+        match self {
+            PseudoInstruction::Copy { dst, src } => Some((*dst, *src)),
+            _ => None,
         }
     }
 
+    fn is_call(&self) -> bool {
+        matches!(self, PseudoInstruction::CallPseudo { .. })
+    }
+
     fn get_branch_targets(&self) -> SmallVec<[Block; 2]> {
         smallvec![]
     }
 
     fn rewrite_branch_target(&mut self, _old: Block, _new: Block) {
-        // Pseudos never branch.
+        // The only branching pseudo, `Switch`, keeps its targets in a
+        // jump table.
+    }
+
+    fn map_branch_targets(&mut self, _f: &mut dyn FnMut(Block) -> Block) {}
+
+    fn jump_table(&self) -> Option<JumpTableId> {
+        match self {
+            PseudoInstruction::Switch { table, .. } => Some(*table),
+            _ => None,
+        }
+    }
+
+    fn map_jump_table(&mut self, f: &mut dyn FnMut(JumpTableId) -> JumpTableId) {
+        if let PseudoInstruction::Switch { table, .. } = self {
+            *table = f(*table);
+        }
+    }
+
+    fn encoded_size(&self, _preg: &dyn Fn(Reg) -> Reg) -> Option<u32> {
+        self.worst_case_size()
+    }
+
+    fn worst_case_size(&self) -> Option<u32> {
+        // Allocation-only markers emit nothing; everything else is lowered
+        // to target code, or sized from a side table, by the target.
+        match self {
+            PseudoInstruction::Arg { .. }
+            | PseudoInstruction::ImplicitDef { .. }
+            | PseudoInstruction::Kill { .. }
+            | PseudoInstruction::RegDef { .. }
+            | PseudoInstruction::Safepoint => Some(0),
+            PseudoInstruction::PatchPoint { len, .. } => Some(*len),
+            _ => None,
+        }
     }
 
     fn new_jmp(_target: Block) -> Self {
@@ -237,12 +555,18 @@ impl Inst for PseudoInstruction {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Instruction<I: Inst> {
     Target(I),
     Pseudo(PseudoInstruction),
 }
 
+impl<I: Inst> From<PseudoInstruction> for Instruction<I> {
+    fn from(inst: PseudoInstruction) -> Self {
+        Instruction::Pseudo(inst)
+    }
+}
+
 impl<I: Inst> Display for Instruction<I> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -288,6 +612,34 @@ impl<I: Inst> Inst for Instruction<I> {
         }
     }
 
+    fn map_regs(&mut self, f: &mut dyn FnMut(Reg) -> Reg) {
+        match self {
+            Instruction::Target(inst) => inst.map_regs(f),
+            Instruction::Pseudo(inst) => inst.map_regs(f),
+        }
+    }
+
+    fn is_move(&self) -> Option<(Reg, Reg)> {
+        match self {
+            Instruction::Target(inst) => inst.is_move(),
+            Instruction::Pseudo(inst) => inst.is_move(),
+        }
+    }
+
+    fn is_call(&self) -> bool {
+        match self {
+            Instruction::Target(inst) => inst.is_call(),
+            Instruction::Pseudo(inst) => inst.is_call(),
+        }
+    }
+
+    fn moves_stack_pointer(&self) -> bool {
+        match self {
+            Instruction::Target(inst) => inst.moves_stack_pointer(),
+            Instruction::Pseudo(inst) => inst.moves_stack_pointer(),
+        }
+    }
+
     fn get_branch_targets(&self) -> SmallVec<[Block; 2]> {
         match self {
             Instruction::Target(inst) => inst.get_branch_targets(),
@@ -302,31 +654,195 @@ impl<I: Inst> Inst for Instruction<I> {
         }
     }
 
+    fn map_branch_targets(&mut self, f: &mut dyn FnMut(Block) -> Block) {
+        match self {
+            Instruction::Target(inst) => inst.map_branch_targets(f),
+            Instruction::Pseudo(inst) => inst.map_branch_targets(f),
+        }
+    }
+
+    fn map_symbols(&mut self, f: &mut dyn FnMut(SymbolId) -> SymbolId) {
+        if let Instruction::Target(inst) = self {
+            inst.map_symbols(f);
+        }
+    }
+
+    fn jump_table(&self) -> Option<JumpTableId> {
+        match self {
+            Instruction::Target(inst) => inst.jump_table(),
+            Instruction::Pseudo(inst) => inst.jump_table(),
+        }
+    }
+
+    fn map_jump_table(&mut self, f: &mut dyn FnMut(JumpTableId) -> JumpTableId) {
+        match self {
+            Instruction::Target(inst) => inst.map_jump_table(f),
+            Instruction::Pseudo(inst) => inst.map_jump_table(f),
+        }
+    }
+
+    fn encoded_size(&self, preg: &dyn Fn(Reg) -> Reg) -> Option<u32> {
+        match self {
+            Instruction::Target(inst) => inst.encoded_size(preg),
+            Instruction::Pseudo(inst) => inst.encoded_size(preg),
+        }
+    }
+
+    fn worst_case_size(&self) -> Option<u32> {
+        match self {
+            Instruction::Target(inst) => inst.worst_case_size(),
+            Instruction::Pseudo(inst) => inst.worst_case_size(),
+        }
+    }
+
+    fn intrinsics() -> &'static [IntrinsicDecl] {
+        I::intrinsics()
+    }
+
     fn new_jmp(target: Block) -> Self {
         Instruction::Target(I::new_jmp(target))
     }
 }
 
 /// Side-table payload for `PseudoInstruction::Phi`. Owned by `Func`.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct PhiData {
     /// `(predecessor_block, incoming_reg)` pairs — one per predecessor
     /// edge. Order matches predecessors in CFG iteration.
     pub incoming: Vec<(Block, Reg)>,
 }
 
+/// Side-table payload of a multi-way branch (`PseudoInstruction::Switch`
+/// or a target's table jump). Owned by `Func`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct JumpTableData {
+    /// `(value, destination)` pairs in ascending value order, values
+    /// distinct.
+    pub cases: Vec<(i64, Block)>,
+    /// Where a value no case names goes. A `Switch` always has one; a
+    /// dense table jump only if its case range has holes.
+    pub default: Option<Block>,
+}
+
+impl JumpTableData {
+    /// Every block the branch can reach: the cases, then the default.
+    /// Duplicates are kept.
+    pub fn targets(&self) -> impl Iterator<Item = Block> + '_ {
+        self.cases.iter().map(|&(_, b)| b).chain(self.default)
+    }
+
+    /// Mutable access to every destination, default included.
+    pub fn targets_mut(&mut self) -> impl Iterator<Item = &mut Block> + '_ {
+        self.cases.iter_mut().map(|(_, b)| b).chain(self.default.as_mut())
+    }
+
+    /// How many values lie between the lowest and the highest case,
+    /// both included; 0 without cases. Saturates for the full `i64` range.
+    #[must_use]
+    pub fn range(&self) -> u64 {
+        match (self.cases.first(), self.cases.last()) {
+            (Some(&(lo, _)), Some(&(hi, _))) => hi.abs_diff(lo).saturating_add(1),
+            _ => 0,
+        }
+    }
+
+    /// One destination per value from the lowest case up to the highest:
+    /// the case's block, or the default for a value no case names.
+    ///
+    /// # Panics
+    /// If the range has a hole and there's no default.
+    #[must_use]
+    pub fn dense_targets(&self) -> Vec<Block> {
+        let Some(&(lo, _)) = self.cases.first() else {
+            return Vec::new();
+        };
+        let mut dense = Vec::with_capacity(usize::try_from(self.range()).unwrap_or(0));
+        for &(v, b) in &self.cases {
+            let at = usize::try_from(v.abs_diff(lo)).expect("dense table fits in memory");
+            if at > dense.len() {
+                dense.resize(at, self.default.expect("jump table with holes needs a default"));
+            }
+            dense.push(b);
+        }
+        dense
+    }
+}
+
 /// Side-table payload for `PseudoInstruction::CallPseudo`. Owned by
 /// `Func`. `callee` is either a direct symbol name (`CallTarget::Symbol`,
 /// resolved by the JIT at load time) or an indirect register holding a
 /// function pointer (`CallTarget::Indirect`).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct CallData {
     pub callee: CallTarget,
     pub args: Vec<Reg>,
     pub rets: Vec<Reg>,
+    /// Bytes of NOPs to reserve right before the `call`, recorded as a
+    /// `PatchKind::Call` site.
+    pub patch: Option<u32>,
+}
+
+/// Side-table payload for `PseudoInstruction::RawBytes`: machine code
+/// the ISA doesn't model, with the registers it touches. Every preg in
+/// `uses`, `defs` and `clobbers` may hold garbage afterwards.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RawBytesData {
+    pub bytes: Vec<u8>,
+    /// `(vreg, preg)`: `vreg`'s value is in `preg` when the bytes run.
+    pub uses: Vec<(Reg, Reg)>,
+    /// `(vreg, preg)`: `vreg` takes the value the bytes leave in `preg`.
+    pub defs: Vec<(Reg, Reg)>,
+    /// Further pregs the bytes overwrite.
+    pub clobbers: Vec<Reg>,
+}
+
+/// What an intrinsic does besides computing its results.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IntrinsicEffects {
+    /// Results depend on the arguments alone: a use nobody reads is
+    /// dropped.
+    Pure,
+    /// May read memory, but writes nothing.
+    ReadsMemory,
+    /// Anything else — memory writes, timers, hints to the processor.
+    SideEffects,
+}
+
+/// How a target implements an intrinsic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IntrinsicLowering {
+    /// Literal machine code (see `RawBytesData`) taking its arguments in
+    /// `args` and leaving its results in `rets`, one preg per operand.
+    Bytes {
+        bytes: &'static [u8],
+        args: &'static [Reg],
+        rets: &'static [Reg],
+        clobbers: &'static [Reg],
+    },
+    /// A call to the runtime helper or library function `symbol`.
+    Call { symbol: &'static str },
+}
+
+/// A target's declaration of one intrinsic, listed by `Inst::intrinsics`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IntrinsicDecl {
+    /// Name frontends look the intrinsic up by, e.g. `x64.popcnt`.
+    pub name: &'static str,
+    pub params: &'static [Type],
+    pub results: &'static [Type],
+    pub effects: IntrinsicEffects,
+    pub lowering: IntrinsicLowering,
+}
+
+/// Side-table payload for `PseudoInstruction::Intrinsic`. Owned by `Func`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IntrinsicData {
+    pub decl: &'static IntrinsicDecl,
+    pub args: Vec<Reg>,
+    pub rets: Vec<Reg>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum CallTarget {
     /// Direct call resolved by symbol name at JIT load time.
     Symbol(String),
@@ -334,9 +850,27 @@ pub enum CallTarget {
     Indirect(Reg),
 }
 
+thread_local! {
+    /// Names `reg_name` prints in place of `v{reg}`; see `with_reg_names`.
+    static REG_NAMES: RefCell<Vec<(Reg, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// `v{reg}`, or the name `with_reg_names` gave `reg`.
 #[must_use]
 pub fn reg_name(reg: Reg) -> String {
-    format!("v{reg}")
+    REG_NAMES
+        .with_borrow(|names| names.iter().find(|(r, _)| *r == reg).map(|(_, n)| n.clone()))
+        .unwrap_or_else(|| format!("v{reg}"))
+}
+
+/// Run `f` with `reg_name` printing the registers in `names` by their
+/// given names, so an instruction's `Display` can show its operands as
+/// allocated. Restores the previous names afterwards.
+pub(super) fn with_reg_names<R>(names: Vec<(Reg, String)>, f: impl FnOnce() -> R) -> R {
+    let saved = REG_NAMES.replace(names);
+    let result = f();
+    REG_NAMES.set(saved);
+    result
 }
 
 #[cfg(test)]
@@ -397,6 +931,16 @@ mod tests {
         assert!(p.get_uses().is_empty());
     }
 
+    #[test]
+    fn only_pseudo_copy_is_a_move_and_only_callpseudo_is_a_call() {
+        let copy = PseudoInstruction::Copy { dst: 1, src: 2 };
+        assert_eq!(copy.is_move(), Some((1, 2)));
+        assert!(!copy.is_call());
+        let call = PseudoInstruction::CallPseudo { id: CallId::new(0) };
+        assert_eq!(call.is_move(), None);
+        assert!(call.is_call());
+    }
+
     #[test]
     fn pseudo_frame_markers_have_no_uses_or_defs() {
         let a = PseudoInstruction::FrameSetup;
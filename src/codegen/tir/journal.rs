@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use super::{
+    AggregateData, AggregateId, Block, BlockData, BlockHint, CallData, CallId, FuncAttrs, Inst,
+    IntrinsicData, IntrinsicId, JumpTableData, JumpTableId, PhiData, PhiId, Profile, RawBytesData,
+    RawBytesId, Reg, SymbolId,
+};
+use crate::support::slotmap::{Key, PrimaryMap};
+
+/// What a `Func` looked like at `Func::checkpoint`, recorded lazily: a
+/// block is copied the first time it's handed out mutably, a side table
+/// the first time it's edited. Anything never touched is never copied,
+/// so a speculative edit of a few blocks costs a few block clones.
+pub(super) struct Journal<I: Inst> {
+    pub regs_count: u32,
+    /// Blocks at or past this index were added since the checkpoint.
+    pub block_bound: usize,
+    /// Original contents of each older block edited since.
+    pub blocks: HashMap<Block, BlockData<I>>,
+    /// Every block as it was, once a renumbering made per-block saves
+    /// meaningless; `blocks` is empty from then on.
+    pub all_blocks: Option<PrimaryMap<Block, BlockData<I>>>,
+    pub phis: Option<PrimaryMap<PhiId, PhiData>>,
+    pub calls: Option<PrimaryMap<CallId, CallData>>,
+    pub raw_bytes: Option<PrimaryMap<RawBytesId, RawBytesData>>,
+    pub intrinsics: Option<PrimaryMap<IntrinsicId, IntrinsicData>>,
+    pub aggregates: Option<PrimaryMap<AggregateId, AggregateData>>,
+    pub jump_tables: Option<PrimaryMap<JumpTableId, JumpTableData>>,
+    pub symbols: Option<PrimaryMap<SymbolId, String>>,
+    pub pre_binds: Option<HashMap<Reg, Reg>>,
+    /// `Some(None)`: there was no profile.
+    #[allow(clippy::option_option)]
+    pub profile: Option<Option<Profile>>,
+    pub block_hints: Option<HashMap<Block, BlockHint>>,
+    pub attrs: Option<FuncAttrs>,
+}
+
+impl<I: Inst> Journal<I> {
+    pub fn new(regs_count: u32, block_bound: usize) -> Self {
+        Self {
+            regs_count,
+            block_bound,
+            blocks: HashMap::new(),
+            all_blocks: None,
+            phis: None,
+            calls: None,
+            raw_bytes: None,
+            intrinsics: None,
+            aggregates: None,
+            jump_tables: None,
+            symbols: None,
+            pre_binds: None,
+            profile: None,
+            block_hints: None,
+            attrs: None,
+        }
+    }
+
+    /// Save `b`'s contents unless it's new or already saved.
+    pub fn save_block(&mut self, b: Block, current: &PrimaryMap<Block, BlockData<I>>) {
+        if self.all_blocks.is_none() && b.index() < self.block_bound {
+            self.blocks.entry(b).or_insert_with(|| current[b].clone());
+        }
+    }
+
+    /// Save every block ahead of a renumbering.
+    pub fn save_all_blocks(&mut self, current: &PrimaryMap<Block, BlockData<I>>) {
+        if self.all_blocks.is_some() {
+            return;
+        }
+        let mut all = current.clone();
+        all.truncate(self.block_bound);
+        for (b, data) in self.blocks.drain() {
+            all[b] = data;
+        }
+        self.all_blocks = Some(all);
+    }
+}
+
+/// Save `current` into `slot` unless it already holds the original.
+pub(super) fn save<T: Clone>(slot: &mut Option<T>, current: &T) {
+    if slot.is_none() {
+        *slot = Some(current.clone());
+    }
+}
@@ -1,11 +1,20 @@
+mod arena;
+mod attrs;
 mod block;
 mod errors;
 mod func;
 mod inst;
+mod journal;
+mod printer;
+mod profile;
 mod types;
 
+pub use arena::*;
+pub use attrs::*;
 pub use block::*;
 pub use errors::*;
 pub use func::*;
 pub use inst::*;
+pub use printer::*;
+pub use profile::*;
 pub use types::*;
@@ -0,0 +1,290 @@
+//! Streaming text dump of a function.
+//!
+//! `Func::write_to` prints the same text as `Display`, one instruction at a
+//! time straight into an `io::Write`, so dumping a huge function never
+//! builds the whole listing in memory. `PrintOptions` adds detail the
+//! `Display` form leaves out. `Func::printer` configures more: an
+//! `OperandFormat` naming each operand (e.g. by the register or stack slot
+//! allocation gave it), and any number of `Annotate` hooks through which an
+//! analysis appends its own comments to block and instruction lines.
+
+use std::fmt::{self, Display};
+use std::io;
+
+use super::inst::with_reg_names;
+use super::{
+    Block, CallTarget, Func, FuncAttrs, Inst, Instruction, PseudoInstruction, Reg, reg_name,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrintOptions {
+    /// Follow phi, call, raw-bytes, aggregate, jump-table and symbol ids
+    /// into their side tables and print the operands as a trailing `;` comment.
+    pub side_tables: bool,
+}
+
+/// How `Func::write_with` names operands.
+pub trait OperandFormat {
+    /// What to print for `reg` where it is read (an instruction's early
+    /// program point `pt`) or written (its late point), or `None` for the
+    /// plain `v{reg}`. Points are numbered like `BlockLayout::compute`.
+    fn operand(&self, reg: Reg, pt: u32) -> Option<String>;
+}
+
+/// Comments an analysis attaches to a listing, each printed after a `;`
+/// at the end of its line.
+pub trait Annotate<I: Inst> {
+    /// Comment for the header line of `block`.
+    fn block(&self, _block: Block) -> Option<String> {
+        None
+    }
+
+    /// Comment for `inst`, the `idx`-th instruction of `block`.
+    fn inst(&self, _block: Block, _idx: usize, _inst: &Instruction<I>) -> Option<String> {
+        None
+    }
+}
+
+/// A listing of one function with everything `Func::printer` can add.
+/// Prints through `write_to` or `Display`.
+pub struct FuncPrinter<'a, I: Inst> {
+    func: &'a Func<I>,
+    opts: PrintOptions,
+    names: Option<&'a dyn OperandFormat>,
+    annotators: Vec<&'a dyn Annotate<I>>,
+}
+
+impl<I: Inst> Func<I> {
+    /// A listing of this function, plain until configured.
+    #[must_use]
+    pub fn printer(&self) -> FuncPrinter<'_, I> {
+        FuncPrinter {
+            func: self,
+            opts: PrintOptions::default(),
+            names: None,
+            annotators: Vec::new(),
+        }
+    }
+
+    /// Write this function's listing to `w`. With default options the
+    /// output matches `Display`.
+    pub fn write_to(&self, w: &mut impl io::Write, opts: &PrintOptions) -> io::Result<()> {
+        self.printer().with_options(*opts).write_to(w)
+    }
+
+    /// `write_to`, printing every instruction operand as `names` says.
+    pub fn write_with(
+        &self,
+        w: &mut impl io::Write,
+        opts: &PrintOptions,
+        names: &dyn OperandFormat,
+    ) -> io::Result<()> {
+        self.printer().with_options(*opts).with_operands(names).write_to(w)
+    }
+
+    fn write_side_table(&self, w: &mut impl io::Write, inst: &Instruction<I>) -> io::Result<()> {
+        let regs = |rs: &[Reg]| rs.iter().map(|&r| reg_name(r)).collect::<Vec<_>>().join(", ");
+        if let Some(table) = inst.jump_table() {
+            let data = self.jump_table(table);
+            let mut cases: Vec<String> =
+                data.cases.iter().map(|(v, b)| format!("{v}: {b}")).collect();
+            cases.extend(data.default.map(|b| format!("default: {b}")));
+            return write!(w, "  ; [{}]", cases.join(", "));
+        }
+        let Instruction::Pseudo(p) = inst else {
+            let mut names = Vec::new();
+            inst.clone().map_symbols(&mut |sym| {
+                names.push(self.symbol_name(sym));
+                sym
+            });
+            if !names.is_empty() {
+                write!(w, "  ; @{}", names.join(", @"))?;
+            }
+            return Ok(());
+        };
+        match *p {
+            PseudoInstruction::Phi { id, .. } => {
+                let incoming: Vec<String> = self
+                    .phi_operands(id)
+                    .incoming
+                    .iter()
+                    .map(|&(b, r)| format!("{b}: {}", reg_name(r)))
+                    .collect();
+                write!(w, "  ; [{}]", incoming.join(", "))
+            }
+            PseudoInstruction::CallPseudo { id } => {
+                let call = self.call_operands(id);
+                match &call.callee {
+                    CallTarget::Symbol(s) => write!(w, "  ; {s}")?,
+                    CallTarget::Indirect(r) => write!(w, "  ; *{}", reg_name(*r))?,
+                }
+                write!(w, "({})", regs(&call.args))?;
+                if !call.rets.is_empty() {
+                    write!(w, " -> {}", regs(&call.rets))?;
+                }
+                if let Some(n) = call.patch {
+                    write!(w, " patchable({n})")?;
+                }
+                Ok(())
+            }
+            PseudoInstruction::RawBytes { id } => {
+                let data = self.raw_bytes_operands(id);
+                write!(w, "  ; {} bytes", data.bytes.len())
+            }
+            PseudoInstruction::Intrinsic { id } => {
+                let data = self.intrinsic_operands(id);
+                write!(w, "  ; {}({})", data.decl.name, regs(&data.args))?;
+                if !data.rets.is_empty() {
+                    write!(w, " -> {}", regs(&data.rets))?;
+                }
+                Ok(())
+            }
+            PseudoInstruction::MakeAggregate { id, .. } => {
+                write!(w, "  ; {{{}}}", regs(&self.aggregate_operands(id).elems))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'a, I: Inst> FuncPrinter<'a, I> {
+    #[must_use]
+    pub fn with_options(mut self, opts: PrintOptions) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    /// Name operands through `names` instead of as `v{reg}`.
+    #[must_use]
+    pub fn with_operands(mut self, names: &'a dyn OperandFormat) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    /// Add `annotator`'s comments, after those of annotators added before.
+    #[must_use]
+    pub fn with_annotations(mut self, annotator: &'a dyn Annotate<I>) -> Self {
+        self.annotators.push(annotator);
+        self
+    }
+
+    /// Stream the listing into `w`.
+    pub fn write_to(&self, w: &mut impl io::Write) -> io::Result<()> {
+        let func = self.func;
+        if *func.attrs() == FuncAttrs::default() {
+            writeln!(w, "{}:", func.name())?;
+        } else {
+            writeln!(w, "{}: ; {}", func.name(), func.attrs())?;
+        }
+        let mut pt = 0;
+        for (block, bd) in func.blocks_iter() {
+            write!(w, "{block}")?;
+            if let Some(hint) = func.block_hint(block) {
+                write!(w, " ; {hint}")?;
+            }
+            for note in self.annotators.iter().filter_map(|a| a.block(block)) {
+                write!(w, " ; {note}")?;
+            }
+            writeln!(w)?;
+            for (idx, inst) in bd.iter().enumerate() {
+                let mut line = || {
+                    write!(w, "    {inst}")?;
+                    if self.opts.side_tables {
+                        func.write_side_table(w, inst)?;
+                    }
+                    for note in self.annotators.iter().filter_map(|a| a.inst(block, idx, inst)) {
+                        write!(w, "  ; {note}")?;
+                    }
+                    writeln!(w)
+                };
+                match self.names {
+                    Some(names) => with_reg_names(operand_names(names, inst, pt), line)?,
+                    None => line()?,
+                }
+                pt += 2;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<I: Inst> Display for FuncPrinter<'_, I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut text = Vec::new();
+        self.write_to(&mut text).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&text))
+    }
+}
+
+/// `inst`'s operands as `names` prints them: uses at its early point
+/// `pt`, defs at its late point.
+fn operand_names<I: Inst>(
+    names: &dyn OperandFormat,
+    inst: &Instruction<I>,
+    pt: u32,
+) -> Vec<(Reg, String)> {
+    let uses = inst.get_uses();
+    let defs = inst.get_defs().into_iter().filter(|r| !uses.contains(r)).map(|r| (r, pt + 1));
+    uses.iter()
+        .map(|&r| (r, pt))
+        .chain(defs)
+        .filter_map(|(r, at)| names.operand(r, at).map(|n| (r, n)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::isa::x64::builder::FuncBuilder;
+    use crate::codegen::isa::x64::inst::X64Inst;
+    use crate::support::slotmap::Key;
+
+    #[test]
+    fn default_options_match_display_and_side_tables_expand_calls() {
+        let mut b = FuncBuilder::new("f");
+        let a = b.arg();
+        let r = b.call_sym("g", &[a, a]);
+        b.ret(r);
+        let func = b.build();
+
+        let mut out = Vec::new();
+        func.write_to(&mut out, &PrintOptions::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), func.to_string());
+
+        let mut out = Vec::new();
+        func.write_to(&mut out, &PrintOptions { side_tables: true }).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains(&format!("; g(v{a}, v{a}) -> v{r}")), "{text}");
+    }
+
+    #[test]
+    fn annotators_append_comments_in_the_order_they_were_added() {
+        struct Positions;
+        impl Annotate<X64Inst> for Positions {
+            fn block(&self, block: Block) -> Option<String> {
+                Some(format!("block {}", block.index()))
+            }
+
+            fn inst(&self, _: Block, idx: usize, _: &Instruction<X64Inst>) -> Option<String> {
+                (idx == 0).then(|| "first".to_string())
+            }
+        }
+        struct Tag;
+        impl Annotate<X64Inst> for Tag {
+            fn block(&self, _: Block) -> Option<String> {
+                Some("tagged".to_string())
+            }
+        }
+
+        let mut b = FuncBuilder::new("f");
+        let a = b.arg();
+        b.ret(a);
+        let func = b.build();
+        let text = func.printer().with_annotations(&Positions).with_annotations(&Tag).to_string();
+        assert_eq!(
+            text,
+            format!("f:\n@0 ; block 0 ; tagged\n    v{a} = arg 0  ; first\n    return v{a}\n")
+        );
+        assert_eq!(func.printer().to_string(), func.to_string());
+    }
+}
@@ -0,0 +1,216 @@
+//! Execution profiles: per-block and per-edge counts, and a text format
+//! holding them for a whole module.
+//!
+//! ```text
+//! # counts from a training run
+//! func fib
+//!   @0 10
+//!   @1 -> @2 880
+//! ```
+//!
+//! Each `func <name>` starts a function's section. `@B N` records block
+//! `B` running `N` times, `@A -> @B N` the edge from `A` to `B` taken `N`
+//! times. Block numbers are those `--emit=tir` prints; `#` starts a
+//! comment.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use thiserror::Error;
+
+use crate::support::slotmap::Key;
+
+use super::{Block, Func, Inst};
+
+/// Execution counts for a function's blocks and CFG edges, e.g. from an
+/// instrumented run. Either map may be partial; missing block counts are
+/// derived from incoming edge counts where possible. Attached with
+/// `Func::set_profile` and kept in sync when blocks are renumbered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub block_counts: HashMap<Block, u64>,
+    pub edge_counts: HashMap<(Block, Block), u64>,
+}
+
+impl Profile {
+    /// Count for `b`, or the sum of its recorded incoming edges.
+    #[must_use]
+    pub fn block_weight(&self, b: Block) -> Option<u64> {
+        if let Some(&c) = self.block_counts.get(&b) {
+            return Some(c);
+        }
+        let mut incoming = self
+            .edge_counts
+            .iter()
+            .filter(|((_, to), _)| *to == b)
+            .map(|(_, &c)| c)
+            .peekable();
+        incoming.peek()?;
+        Some(incoming.sum())
+    }
+
+    /// Count for the edge `from → to`, falling back to `to`'s weight.
+    #[must_use]
+    pub fn edge_weight(&self, from: Block, to: Block) -> Option<u64> {
+        self.edge_counts
+            .get(&(from, to))
+            .copied()
+            .or_else(|| self.block_weight(to))
+    }
+
+    /// Rename blocks after `Func` renumbering; entries for deleted blocks
+    /// are dropped.
+    pub(super) fn remap(&mut self, remap: &[Option<Block>]) {
+        let new_of = |b: Block| remap.get(b.index()).copied().flatten();
+        self.block_counts = self
+            .block_counts
+            .drain()
+            .filter_map(|(b, c)| Some((new_of(b)?, c)))
+            .collect();
+        self.edge_counts = self
+            .edge_counts
+            .drain()
+            .filter_map(|((f, t), c)| Some(((new_of(f)?, new_of(t)?), c)))
+            .collect();
+    }
+}
+
+/// Frontend expectation about a block, for code with no profile: entering
+/// it is likely or unlikely relative to its predecessor's other successors
+/// (`__builtin_expect` on the branch that leads there). Attached with
+/// `Func::set_block_hint`; measured counts take precedence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockHint {
+    Likely,
+    Unlikely,
+}
+
+impl Display for BlockHint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BlockHint::Likely => "likely",
+            BlockHint::Unlikely => "unlikely",
+        })
+    }
+}
+
+/// Profiles of a module's functions, by function name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleProfile {
+    pub funcs: HashMap<String, Profile>,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("profile line {line}: {msg}")]
+pub struct ProfileError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl ModuleProfile {
+    /// Parse the text format in the module docs.
+    pub fn parse(src: &str) -> Result<Self, ProfileError> {
+        let mut funcs: HashMap<String, Profile> = HashMap::new();
+        let mut current: Option<&mut Profile> = None;
+        for (i, raw) in src.lines().enumerate() {
+            let line = i + 1;
+            let err = |msg: String| ProfileError { line, msg };
+            let text = raw.split('#').next().unwrap_or_default().trim();
+            if text.is_empty() {
+                continue;
+            }
+            if let Some(name) = text.strip_prefix("func ") {
+                let name = name.trim();
+                if funcs.contains_key(name) {
+                    return Err(err(format!("function `{name}` listed twice")));
+                }
+                current = Some(funcs.entry(name.to_string()).or_default());
+                continue;
+            }
+            let profile = current
+                .as_deref_mut()
+                .ok_or_else(|| err("count before the first `func`".into()))?;
+            let block = |s: &str| {
+                s.strip_prefix('@')
+                    .and_then(|n| n.parse().ok())
+                    .map(Block::new)
+                    .ok_or_else(|| err(format!("expected a block like `@0`, got `{s}`")))
+            };
+            let count = |s: &str| {
+                s.parse::<u64>()
+                    .map_err(|_| err(format!("expected a count, got `{s}`")))
+            };
+            match *text.split_whitespace().collect::<Vec<_>>() {
+                [b, n] => {
+                    profile.block_counts.insert(block(b)?, count(n)?);
+                }
+                [from, "->", to, n] => {
+                    profile.edge_counts.insert((block(from)?, block(to)?), count(n)?);
+                }
+                _ => return Err(err(format!("expected `@B N` or `@A -> @B N`, got `{text}`"))),
+            }
+        }
+        Ok(Self { funcs })
+    }
+
+    /// Attach each function's profile to the function of that name.
+    /// Returns how many functions got one.
+    pub fn apply<I: Inst>(&self, funcs: &mut [Func<I>]) -> usize {
+        let mut applied = 0;
+        for func in funcs {
+            if let Some(profile) = self.funcs.get(func.name()) {
+                func.set_profile(profile.clone());
+                applied += 1;
+            }
+        }
+        applied
+    }
+}
+
+/// The text format, functions and counts sorted so output is stable.
+impl Display for ModuleProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.funcs.keys().collect();
+        names.sort();
+        for name in names {
+            let profile = &self.funcs[name];
+            writeln!(f, "func {name}")?;
+            let mut blocks: Vec<_> = profile.block_counts.iter().collect();
+            blocks.sort();
+            for (b, n) in blocks {
+                writeln!(f, "  {b} {n}")?;
+            }
+            let mut edges: Vec<_> = profile.edge_counts.iter().collect();
+            edges.sort();
+            for ((from, to), n) in edges {
+                writeln!(f, "  {from} -> {to} {n}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_profile_round_trips_through_text() {
+        let src = "# training run\nfunc f\n  @0 10\n  @1 -> @2 7 # hot\n\nfunc g\n  @0 0\n";
+        let mp = ModuleProfile::parse(src).expect("parse");
+        let f = &mp.funcs["f"];
+        assert_eq!(f.block_weight(Block::new(0)), Some(10));
+        assert_eq!(f.edge_weight(Block::new(1), Block::new(2)), Some(7));
+        assert_eq!(f.block_weight(Block::new(2)), Some(7));
+        assert_eq!(ModuleProfile::parse(&mp.to_string()), Ok(mp));
+    }
+
+    #[test]
+    fn module_profile_errors_name_the_line() {
+        let err = |src| ModuleProfile::parse(src).unwrap_err();
+        assert_eq!(err("@0 1").line, 1);
+        assert_eq!(err("func f\n  @0 many").line, 2);
+        assert_eq!(err("func f\n  0 1").line, 2);
+        assert_eq!(err("func f\nfunc f").line, 2);
+    }
+}
@@ -83,6 +83,9 @@ pub enum Type {
     F32,
     F64,
     Ptr,
+    /// Pointer to a GC-managed object. Allocated like `Ptr`, but every
+    /// `Safepoint`'s stack map lists where each live `Ref` is.
+    Ref,
     /// 128-bit vector of lanes.
     V128(ScalarType),
     /// 256-bit vector of lanes.
@@ -137,7 +140,7 @@ impl Type {
             Type::I8 => Some(1),
             Type::I16 => Some(2),
             Type::I32 | Type::F32 => Some(4),
-            Type::I64 | Type::F64 | Type::Ptr => Some(8),
+            Type::I64 | Type::F64 | Type::Ptr | Type::Ref => Some(8),
             Type::V128(_) | Type::V256(_) | Type::V512(_) | Type::Agg(_) => None,
         }
     }
@@ -153,6 +156,7 @@ impl Display for Type {
             Type::F32 => f.write_str("f32"),
             Type::F64 => f.write_str("f64"),
             Type::Ptr => f.write_str("ptr"),
+            Type::Ref => f.write_str("ref"),
             Type::V128(s) => write!(f, "v128<{s}>"),
             Type::V256(s) => write!(f, "v256<{s}>"),
             Type::V512(s) => write!(f, "v512<{s}>"),
@@ -166,7 +170,7 @@ impl Display for Type {
 /// `elems[i]` is the vreg carrying aggregate element `i`. All element
 /// vregs must be scalar (non-aggregate) — nested aggregates are modeled
 /// by listing their leaves directly.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct AggregateData {
     pub elems: Vec<crate::codegen::tir::Reg>,
 }
@@ -198,6 +202,7 @@ mod tests {
         assert!(Type::V128(ScalarType::I32).is_fp_or_vector());
         assert!(!Type::I64.is_fp_or_vector());
         assert!(!Type::Ptr.is_fp_or_vector());
+        assert!(!Type::Ref.is_fp_or_vector());
     }
 
     #[test]
@@ -0,0 +1,226 @@
+//! Where each vreg lives in the emitted code, for debuggers,
+//! deoptimization and GC stack walking.
+//!
+//! The allocator places each vreg piecewise over program points; emission
+//! turns those points into code offsets. A range covers the machine code
+//! of every IR instruction whose use or def point its piece touches, so a
+//! value defined by an instruction is reported from that instruction's
+//! first byte. Frame slots are given as a byte offset from the frame
+//! pointer, or `rsp` where it is omitted; either stays fixed for the
+//! whole body.
+//!
+//! Stack maps are the GC's slice of the same information: at each
+//! `Safepoint`, where every live `Type::Ref` vreg is.
+
+use std::collections::HashMap;
+
+use crate::codegen::analysis::cfg::CFG;
+use crate::codegen::analysis::layout::{BlockLayout, POINTS_PER_INST, ProgramPoint};
+use crate::codegen::analysis::liveness::LazyLiveRanges;
+use crate::codegen::regalloc::{AllocatedSlot, RegAllocResult, StackSlot};
+use crate::codegen::tir::{Func, Inst, Instruction, PseudoInstruction, Reg, Type};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueLocation {
+    /// A physical register.
+    Reg(Reg),
+    /// The 8 bytes at this offset from the frame pointer, or from `rsp`
+    /// in a leaf compiled without one (`FramePointer::Omit`).
+    Frame(i32),
+}
+
+/// `vreg` is in `location` while the program counter is in
+/// `[start, end)`, as byte offsets from the function start.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LocationRange {
+    pub vreg: Reg,
+    pub start: u32,
+    pub end: u32,
+    pub location: ValueLocation,
+}
+
+/// Every vreg's locations over the emitted code, sorted by vreg then
+/// start; a vreg's ranges never overlap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValueLocationMap {
+    ranges: Vec<LocationRange>,
+}
+
+impl ValueLocationMap {
+    /// Translate `ra`'s pieces to code ranges. `inst_offsets[i]` is the
+    /// code offset of the `i`-th instruction in layout order, with one
+    /// extra entry for the end of the body; `slot_offset` gives a stack
+    /// slot's offset as `ValueLocation::Frame` reports it.
+    #[must_use]
+    pub fn build(
+        ra: &RegAllocResult,
+        inst_offsets: &[u32],
+        slot_offset: impl Fn(StackSlot) -> i32,
+    ) -> Self {
+        let last = inst_offsets.len().saturating_sub(1);
+        let pc = |inst: ProgramPoint| inst_offsets[(inst as usize).min(last)];
+        let mut ranges: Vec<LocationRange> = Vec::new();
+        for (vreg, asn) in &ra.assignments {
+            for (seg, slot) in &asn.pieces {
+                let location = match *slot {
+                    AllocatedSlot::Reg(p) => ValueLocation::Reg(p),
+                    AllocatedSlot::Stack(s) => ValueLocation::Frame(slot_offset(s)),
+                };
+                let start = pc(seg.start / POINTS_PER_INST);
+                let end = pc(seg.end.div_ceil(POINTS_PER_INST));
+                if start >= end {
+                    continue;
+                }
+                if let Some(prev) = ranges.last_mut()
+                    && prev.vreg == vreg
+                    && prev.end >= start
+                {
+                    // Pieces of the same slot on either side of a block
+                    // boundary read as one range.
+                    if prev.location == location {
+                        prev.end = prev.end.max(end);
+                        continue;
+                    }
+                    // A split at a def point shares that instruction with
+                    // the piece before it; the new location wins.
+                    prev.end = start;
+                    if prev.start == prev.end {
+                        ranges.pop();
+                    }
+                }
+                ranges.push(LocationRange {
+                    vreg,
+                    start,
+                    end,
+                    location,
+                });
+            }
+        }
+        Self { ranges }
+    }
+
+    /// Every range, sorted by vreg then start.
+    #[must_use]
+    pub fn ranges(&self) -> &[LocationRange] {
+        &self.ranges
+    }
+
+    /// `vreg`'s ranges in code order.
+    #[must_use]
+    pub fn for_vreg(&self, vreg: Reg) -> &[LocationRange] {
+        let lo = self.ranges.partition_point(|r| r.vreg < vreg);
+        let hi = self.ranges.partition_point(|r| r.vreg <= vreg);
+        &self.ranges[lo..hi]
+    }
+
+    /// Where `vreg` is when the program counter is at `offset`.
+    #[must_use]
+    pub fn at(&self, vreg: Reg, offset: u32) -> Option<ValueLocation> {
+        self.for_vreg(vreg)
+            .iter()
+            .find(|r| r.start <= offset && offset < r.end)
+            .map(|r| r.location)
+    }
+}
+
+/// The GC references live at one `Safepoint`, reached when the program
+/// counter is at `offset`. `refs` is sorted and free of duplicates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackMap {
+    pub offset: u32,
+    pub refs: Vec<ValueLocation>,
+}
+
+/// The `Type::Ref` vregs live at each `Safepoint`, keyed by its use point.
+/// Empty when the function has no safepoints.
+///
+/// # Panics
+/// If `func` has no valid CFG.
+#[must_use]
+pub fn live_refs_at_safepoints<I: Inst>(
+    func: &Func<I>,
+    layout: &BlockLayout,
+) -> HashMap<ProgramPoint, Vec<Reg>> {
+    let mut points = Vec::new();
+    for (block, bd) in func.blocks_iter() {
+        for (i, inst) in bd.iter().enumerate() {
+            if matches!(inst, Instruction::Pseudo(PseudoInstruction::Safepoint)) {
+                points.push(layout.use_pt(block, i as u32));
+            }
+        }
+    }
+    if points.is_empty() {
+        return HashMap::new();
+    }
+    let refs: Vec<Reg> = (0..func.get_regs_count() as Reg)
+        .filter(|&r| func.vreg_type(r) == Type::Ref)
+        .collect();
+    let cfg = CFG::compute(func).expect("CFG of a function with safepoints");
+    let mut live = LazyLiveRanges::new(func, &cfg, layout).expect("liveness at safepoints");
+    points
+        .into_iter()
+        .map(|pt| (pt, refs.iter().copied().filter(|&r| live.is_live_at(r, pt)).collect()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::analysis::liveness::Segment;
+    use crate::codegen::regalloc::Assignment;
+    use crate::support::slotmap::SecondaryMap;
+
+    #[test]
+    fn pieces_become_code_ranges_and_split_vregs_change_location() {
+        // v0 lives in preg 3 over insts 0..2, then in slot 1 over 2..4;
+        // v1 in preg 5 over inst 3 only.
+        let mut assignments = SecondaryMap::new(2);
+        assignments.fill(Assignment::default());
+        assignments[0].pieces.push((Segment { start: 1, end: 4 }, AllocatedSlot::Reg(3)));
+        assignments[0].pieces.push((Segment { start: 4, end: 8 }, AllocatedSlot::Stack(1)));
+        assignments[1] = Assignment::uniform(AllocatedSlot::Reg(5), 7, 8);
+        let ra = RegAllocResult {
+            assignments,
+            frame_layout: vec![0, 8],
+            frame_size: 16,
+            edits: Vec::new(),
+        };
+        let offsets = [4, 7, 10, 12, 13];
+        let map = ValueLocationMap::build(&ra, &offsets, |s| -8 * (s as i32 + 1));
+
+        assert_eq!(map.for_vreg(0).len(), 2);
+        assert_eq!(map.at(0, 4), Some(ValueLocation::Reg(3)));
+        assert_eq!(map.at(0, 9), Some(ValueLocation::Reg(3)));
+        assert_eq!(map.at(0, 10), Some(ValueLocation::Frame(-16)));
+        assert_eq!(map.at(0, 13), None);
+        assert_eq!(
+            map.for_vreg(1),
+            [LocationRange {
+                vreg: 1,
+                start: 12,
+                end: 13,
+                location: ValueLocation::Reg(5)
+            }]
+        );
+        assert_eq!(map.at(1, 11), None);
+    }
+
+    #[test]
+    fn split_inside_an_instruction_hands_it_to_the_new_location() {
+        let mut assignments = SecondaryMap::new(1);
+        assignments.fill(Assignment::default());
+        assignments[0].pieces.push((Segment { start: 1, end: 5 }, AllocatedSlot::Reg(3)));
+        assignments[0].pieces.push((Segment { start: 5, end: 8 }, AllocatedSlot::Stack(0)));
+        let ra = RegAllocResult {
+            assignments,
+            frame_layout: vec![0],
+            frame_size: 8,
+            edits: Vec::new(),
+        };
+        let map = ValueLocationMap::build(&ra, &[0, 1, 2, 3, 4], |_| -8);
+        assert_eq!(map.at(0, 1), Some(ValueLocation::Reg(3)));
+        assert_eq!(map.at(0, 2), Some(ValueLocation::Frame(-8)));
+        let r = map.for_vreg(0);
+        assert!(r.windows(2).all(|w| w[0].end <= w[1].start), "{r:?}");
+    }
+}
@@ -21,5 +21,7 @@
     clippy::similar_names,
     clippy::unreadable_literal,
 )]
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod codegen;
 pub mod support;
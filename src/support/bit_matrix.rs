@@ -0,0 +1,86 @@
+//! Symmetric boolean relation over `0..n`, stored as a lower triangle.
+//!
+//! Interference is symmetric, so only the pairs `(i, j)` with `j <= i`
+//! are stored: `n * (n + 1) / 2` bits in one `FixedBitSet`. Queries and
+//! updates are an index computation and a single bit test, with no
+//! hashing.
+
+use crate::support::bitset::FixedBitSet;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TriangularBitMatrix {
+    size: usize,
+    bits: FixedBitSet,
+}
+
+impl TriangularBitMatrix {
+    /// An empty relation over `0..size`.
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            bits: FixedBitSet::zeroes(size * (size + 1) / 2),
+        }
+    }
+
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn index(&self, a: usize, b: usize) -> usize {
+        assert!(
+            a < self.size && b < self.size,
+            "pair ({a}, {b}) out of range for size {}",
+            self.size
+        );
+        let (hi, lo) = if a >= b { (a, b) } else { (b, a) };
+        hi * (hi + 1) / 2 + lo
+    }
+
+    /// Relate `a` and `b` (in both directions).
+    pub fn add(&mut self, a: usize, b: usize) {
+        let i = self.index(a, b);
+        self.bits.add(i);
+    }
+
+    pub fn del(&mut self, a: usize, b: usize) {
+        let i = self.index(a, b);
+        self.bits.del(i);
+    }
+
+    #[must_use]
+    pub fn has(&self, a: usize, b: usize) -> bool {
+        self.bits.has(self.index(a, b))
+    }
+
+    /// Every `b` related to `a`, ascending.
+    pub fn row(&self, a: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.size).filter(move |&b| self.has(a, b))
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_are_symmetric() {
+        let mut m = TriangularBitMatrix::new(5);
+        m.add(3, 1);
+        m.add(4, 4);
+        m.add(0, 2);
+        assert!(m.has(1, 3) && m.has(3, 1));
+        assert!(m.has(2, 0) && m.has(4, 4));
+        assert!(!m.has(1, 1) && !m.has(3, 4));
+        assert_eq!(m.row(1).collect::<Vec<_>>(), vec![3]);
+        m.del(1, 3);
+        assert!(!m.has(3, 1));
+        m.clear();
+        assert!(!m.has(0, 2));
+    }
+}
@@ -1,8 +1,52 @@
 use smallvec::SmallVec;
 
-type Word = u64;
+pub(super) type Word = u64;
 
-#[derive(Clone)]
+/// Words processed together by the whole-set operations. A fixed-length
+/// inner loop over each chunk vectorizes at whatever SIMD width the target
+/// has, without a per-word bounds check; leftover words go one at a time.
+const CHUNK: usize = 4;
+
+/// `dst[i] = f(dst[i], src[i])` over the common prefix, a chunk at a time.
+#[inline]
+fn zip_words(dst: &mut [Word], src: &[Word], f: impl Fn(Word, Word) -> Word) {
+    let (dc, dt) = dst.as_chunks_mut::<CHUNK>();
+    let (sc, st) = src.as_chunks::<CHUNK>();
+    for (d, s) in dc.iter_mut().zip(sc) {
+        *d = std::array::from_fn(|k| f(d[k], s[k]));
+    }
+    for (x, &y) in dt.iter_mut().zip(st) {
+        *x = f(*x, y);
+    }
+}
+
+/// Whether `f(a[i], b[i])` is nonzero for any word, OR-reducing each chunk
+/// before testing it.
+#[inline]
+fn any_words(a: &[Word], b: &[Word], f: impl Fn(Word, Word) -> Word) -> bool {
+    let (ac, at) = a.as_chunks::<CHUNK>();
+    let (bc, bt) = b.as_chunks::<CHUNK>();
+    at.iter().zip(bt).any(|(&x, &y)| f(x, y) != 0)
+        || ac.iter().zip(bc).any(|(x, y)| (0..CHUNK).fold(0, |acc, k| acc | f(x[k], y[k])) != 0)
+}
+
+/// The operations dataflow analyses need from a set of small integers,
+/// so they can run over either the dense `FixedBitSet` or the chunked
+/// `SparseBitSet`.
+pub trait BitSet: Clone {
+    /// An empty set able to hold `0..size`.
+    fn empty(size: usize) -> Self;
+    fn add(&mut self, index: usize);
+    fn has(&self, index: usize) -> bool;
+    fn union(&mut self, other: &Self);
+    /// `self |= other`; returns `true` if any bit was added.
+    fn union_with(&mut self, other: &Self) -> bool;
+    fn difference(&mut self, other: &Self);
+    /// Set bits, ascending.
+    fn iter_ones(&self) -> impl Iterator<Item = usize> + '_;
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
 pub struct FixedBitSet {
     buckets: SmallVec<[Word; 4]>,
 }
@@ -36,34 +80,61 @@ impl FixedBitSet {
 
     pub fn intersect(&mut self, other: &FixedBitSet) {
         debug_assert_eq!(self.buckets.len(), other.buckets.len());
-        for (i, bucket) in self.buckets.iter_mut().enumerate() {
-            *bucket &= other.buckets[i];
-        }
+        zip_words(&mut self.buckets, &other.buckets, |a, b| a & b);
     }
 
     pub fn union(&mut self, other: &FixedBitSet) {
         debug_assert_eq!(self.buckets.len(), other.buckets.len());
-        for (i, bucket) in self.buckets.iter_mut().enumerate() {
-            *bucket |= other.buckets[i];
+        zip_words(&mut self.buckets, &other.buckets, |a, b| a | b);
+    }
+
+    /// `self |= other`; returns `true` if any bit was added.
+    pub fn union_with(&mut self, other: &FixedBitSet) -> bool {
+        debug_assert_eq!(self.buckets.len(), other.buckets.len());
+        // A single running accumulator: LLVM already vectorizes this loop,
+        // and splitting it into per-lane accumulators measured slower.
+        let mut added = 0;
+        for (bucket, &o) in self.buckets.iter_mut().zip(&other.buckets) {
+            added |= o & !*bucket;
+            *bucket |= o;
         }
+        added != 0
+    }
+
+    /// `dst = a | b`, reusing `dst`'s storage.
+    pub fn union_into(dst: &mut FixedBitSet, a: &FixedBitSet, b: &FixedBitSet) {
+        debug_assert_eq!(a.buckets.len(), b.buckets.len());
+        dst.buckets.clear();
+        dst.buckets
+            .extend(a.buckets.iter().zip(&b.buckets).map(|(&x, &y)| x | y));
+    }
+
+    #[must_use]
+    pub fn is_subset_of(&self, other: &FixedBitSet) -> bool {
+        debug_assert_eq!(self.buckets.len(), other.buckets.len());
+        !any_words(&self.buckets, &other.buckets, |a, b| a & !b)
     }
 
     #[must_use]
     pub fn is_superset_of(&self, other: &FixedBitSet) -> bool {
+        other.is_subset_of(self)
+    }
+
+    #[must_use]
+    pub fn is_disjoint(&self, other: &FixedBitSet) -> bool {
         debug_assert_eq!(self.buckets.len(), other.buckets.len());
-        for (i, &bucket) in self.buckets.iter().enumerate() {
-            if (bucket & other.buckets[i]) == other.buckets[i] {
-                return false;
-            }
-        }
-        true
+        !any_words(&self.buckets, &other.buckets, |a, b| a & b)
+    }
+
+    /// `self ^= other`: keep the bits set in exactly one of the two.
+    pub fn symmetric_difference(&mut self, other: &FixedBitSet) {
+        debug_assert_eq!(self.buckets.len(), other.buckets.len());
+        zip_words(&mut self.buckets, &other.buckets, |a, b| a ^ b);
     }
 
     pub fn difference(&mut self, other: &FixedBitSet) {
         debug_assert_eq!(self.buckets.len(), other.buckets.len());
-        for (i, bucket) in self.buckets.iter_mut().enumerate() {
-            *bucket &= !other.buckets[i];
-        }
+        zip_words(&mut self.buckets, &other.buckets, |a, b| a & !b);
     }
 
     pub fn add(&mut self, index: usize) {
@@ -103,20 +174,29 @@ impl FixedBitSet {
         true
     }
 
-    pub fn iter_ones(&self) -> impl Iterator<Item=usize> + '_ {
-        self.buckets.iter().enumerate().flat_map(|(i, &bucket)| {
-            (0..Self::bits_in_bucket())
-                .filter(move |j| bucket & ((1 as Word) << j) != 0)
-                .map(move |j| i * Self::bits_in_bucket() + j)
-        })
+    /// Indices of set bits, ascending. Empty words are skipped whole;
+    /// within a word each step jumps straight to the next set bit.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, &bucket)| bucket != 0)
+            .flat_map(|(i, &bucket)| Ones {
+                word: bucket,
+                base: i * Self::bits_in_bucket(),
+            })
     }
 
-    pub fn iter_zeroes(&self) -> impl Iterator<Item=usize> + '_ {
-        self.buckets.iter().enumerate().flat_map(|(i, &bucket)| {
-            (0..Self::bits_in_bucket())
-                .filter(move |j| bucket & ((1 as Word) << j) == 0)
-                .map(move |j| i * Self::bits_in_bucket() + j)
-        })
+    /// Indices of clear bits, ascending, up to the end of the last word.
+    pub fn iter_zeroes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, &bucket)| bucket != Word::MAX)
+            .flat_map(|(i, &bucket)| Ones {
+                word: !bucket,
+                base: i * Self::bits_in_bucket(),
+            })
     }
 
     pub fn clear(&mut self) {
@@ -125,6 +205,68 @@ impl FixedBitSet {
         }
     }
 }
+/// Prints the set bits, e.g. `{1, 3, 32}`.
+impl std::fmt::Debug for FixedBitSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter_ones()).finish()
+    }
+}
+
+impl BitSet for FixedBitSet {
+    fn empty(size: usize) -> Self {
+        Self::zeroes(size)
+    }
+
+    fn add(&mut self, index: usize) {
+        FixedBitSet::add(self, index);
+    }
+
+    fn has(&self, index: usize) -> bool {
+        FixedBitSet::has(self, index)
+    }
+
+    fn union(&mut self, other: &Self) {
+        FixedBitSet::union(self, other);
+    }
+
+    fn union_with(&mut self, other: &Self) -> bool {
+        FixedBitSet::union_with(self, other)
+    }
+
+    fn difference(&mut self, other: &Self) {
+        FixedBitSet::difference(self, other);
+    }
+
+    fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        FixedBitSet::iter_ones(self)
+    }
+}
+
+/// Set bits of one word, lowest first, offset by `base`.
+pub(super) struct Ones {
+    pub(super) word: Word,
+    pub(super) base: usize,
+}
+
+impl Iterator for Ones {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.word == 0 {
+            return None;
+        }
+        let bit = self.word.trailing_zeros() as usize;
+        // Clear the lowest set bit.
+        self.word &= self.word - 1;
+        Some(self.base + bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.word.count_ones() as usize;
+        (n, Some(n))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -214,4 +356,99 @@ mod tests {
         let ones: Vec<usize> = bs.iter_ones().collect();
         assert_eq!(ones, vec![1, 3, 32]);
     }
+
+    #[test]
+    fn test_iter_skips_full_and_empty_words() {
+        let mut bs = FixedBitSet::zeroes(256);
+        bs.add(0);
+        bs.add(63);
+        bs.add(200);
+        assert_eq!(bs.iter_ones().collect::<Vec<_>>(), vec![0, 63, 200]);
+
+        let mut full = FixedBitSet::ones(192);
+        full.del(65);
+        full.del(191);
+        assert_eq!(full.iter_zeroes().collect::<Vec<_>>(), vec![65, 191]);
+        assert_eq!(full.iter_ones().count(), 190);
+    }
+
+    #[test]
+    fn test_union_with_reports_growth() {
+        let mut a = FixedBitSet::zeroes(128);
+        let mut b = FixedBitSet::zeroes(128);
+        a.add(3);
+        b.add(3);
+        assert!(!a.union_with(&b));
+        b.add(100);
+        assert!(a.union_with(&b));
+        assert!(a.has(100));
+        assert!(!a.union_with(&b));
+    }
+
+    #[test]
+    fn test_subset_disjoint_and_symmetric_difference() {
+        let mut a = FixedBitSet::zeroes(96);
+        let mut b = FixedBitSet::zeroes(96);
+        a.add(1);
+        b.add(1);
+        b.add(70);
+        assert!(a.is_subset_of(&b));
+        assert!(!b.is_subset_of(&a));
+        assert!(b.is_superset_of(&a));
+        assert!(!a.is_disjoint(&b));
+
+        let mut c = FixedBitSet::zeroes(96);
+        FixedBitSet::union_into(&mut c, &a, &b);
+        assert_eq!(c.iter_ones().collect::<Vec<_>>(), vec![1, 70]);
+
+        a.add(2);
+        a.symmetric_difference(&b);
+        assert_eq!(a.iter_ones().collect::<Vec<_>>(), vec![2, 70]);
+        a.del(70);
+        assert!(a.is_disjoint(&b));
+    }
+
+    #[test]
+    fn test_whole_set_ops_cover_full_chunks_and_the_tail() {
+        // Five words: one full chunk, then a leftover word.
+        const N: usize = 5 * 64;
+        let set = |pred: fn(usize) -> bool| {
+            let mut bs = FixedBitSet::zeroes(N);
+            (0..N).filter(|&i| pred(i)).for_each(|i| bs.add(i));
+            bs
+        };
+        let ones = |bs: &FixedBitSet| bs.iter_ones().collect::<Vec<_>>();
+        let naive = |pred: &dyn Fn(usize) -> bool| (0..N).filter(|&i| pred(i)).collect::<Vec<_>>();
+        let (a, b) = (set(|i| i % 3 == 0), set(|i| i % 5 == 0 || i == N - 1));
+
+        let mut x = a.clone();
+        x.union(&b);
+        assert_eq!(ones(&x), naive(&|i| i % 3 == 0 || i % 5 == 0 || i == N - 1));
+        let mut x = a.clone();
+        x.intersect(&b);
+        assert_eq!(ones(&x), naive(&|i| i % 15 == 0));
+        let mut x = a.clone();
+        x.difference(&b);
+        assert_eq!(ones(&x), naive(&|i| i % 3 == 0 && i % 5 != 0 && i != N - 1));
+
+        // A change only in the leftover word is still reported.
+        let mut x = a.clone();
+        x.union(&set(|i| i % 5 == 0));
+        assert!(x.union_with(&b));
+        assert!(!x.union_with(&b));
+        assert!(b.is_subset_of(&x));
+        assert!(!set(|i| i == N - 1).is_disjoint(&b));
+    }
+
+    #[test]
+    fn test_std_traits() {
+        let mut a = FixedBitSet::zeroes(64);
+        a.add(1);
+        a.add(32);
+        assert_eq!(format!("{a:?}"), "{1, 32}");
+        let b = a.clone();
+        assert_eq!(a, b);
+        let set: std::collections::HashSet<FixedBitSet> = [a, b].into_iter().collect();
+        assert_eq!(set.len(), 1);
+    }
 }
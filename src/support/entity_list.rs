@@ -0,0 +1,198 @@
+//! Compact variable-length lists of keys in a shared pool.
+//!
+//! An `EntityList` is a single `u32` handle into a `ListPool`, so a node
+//! carrying several short lists (CFG successors and predecessors, say)
+//! stays small and building thousands of them doesn't allocate per list.
+//! The pool hands out blocks in power-of-two size classes; each block
+//! holds its length in the first slot followed by the elements. A list
+//! outgrowing its block moves to the next class and frees the old one
+//! for reuse.
+//!
+//! Handles are only meaningful with the pool that created them, and
+//! copying a handle aliases the storage: mutate through one copy only.
+
+use std::marker::PhantomData;
+
+use crate::support::slotmap::Key;
+
+/// Slots in the smallest block, length slot included.
+const MIN_BLOCK: usize = 4;
+
+/// Size class whose block fits `len` elements plus the length slot.
+fn class_for(len: usize) -> usize {
+    (len + 1)
+        .div_ceil(MIN_BLOCK)
+        .next_power_of_two()
+        .trailing_zeros() as usize
+}
+
+fn class_size(class: usize) -> usize {
+    MIN_BLOCK << class
+}
+
+#[derive(Clone, Debug)]
+pub struct ListPool<K: Key> {
+    data: Vec<K>,
+    /// Start indices of free blocks, per size class.
+    free: Vec<Vec<usize>>,
+}
+
+impl<K: Key> Default for ListPool<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key> ListPool<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Drop every list at once. Existing handles become invalid.
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.free.clear();
+    }
+
+    /// Slots in use or free, across all blocks.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    fn alloc(&mut self, class: usize) -> usize {
+        if let Some(start) = self.free.get_mut(class).and_then(Vec::pop) {
+            return start;
+        }
+        let start = self.data.len();
+        self.data.resize(start + class_size(class), K::NONE_VAL);
+        start
+    }
+
+    fn release(&mut self, start: usize, class: usize) {
+        if self.free.len() <= class {
+            self.free.resize_with(class + 1, Vec::new);
+        }
+        self.free[class].push(start);
+    }
+}
+
+/// A list of keys stored in a `ListPool`. The default value is the empty
+/// list, which owns no storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityList<K: Key> {
+    /// Block start + 1; 0 means empty.
+    index: u32,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key> Default for EntityList<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key> EntityList<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            _key: PhantomData,
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index == 0
+    }
+
+    fn start(self) -> Option<usize> {
+        (self.index as usize).checked_sub(1)
+    }
+
+    #[must_use]
+    pub fn len(&self, pool: &ListPool<K>) -> usize {
+        self.start().map_or(0, |s| pool.data[s].index())
+    }
+
+    #[must_use]
+    pub fn as_slice<'p>(&self, pool: &'p ListPool<K>) -> &'p [K] {
+        match self.start() {
+            Some(s) => &pool.data[s + 1..=s + pool.data[s].index()],
+            None => &[],
+        }
+    }
+
+    pub fn as_mut_slice<'p>(&self, pool: &'p mut ListPool<K>) -> &'p mut [K] {
+        match self.start() {
+            Some(s) => {
+                let len = pool.data[s].index();
+                &mut pool.data[s + 1..=s + len]
+            }
+            None => &mut [],
+        }
+    }
+
+    pub fn push(&mut self, k: K, pool: &mut ListPool<K>) {
+        let len = self.len(pool);
+        let class = class_for(len + 1);
+        let start = match self.start() {
+            Some(s) if class_for(len) == class => s,
+            old => {
+                let s = pool.alloc(class);
+                if let Some(o) = old {
+                    pool.data.copy_within(o + 1..=o + len, s + 1);
+                    pool.release(o, class_for(len));
+                }
+                self.index = u32::try_from(s + 1).expect("list pool exceeds u32 slots");
+                s
+            }
+        };
+        pool.data[start] = K::new(len + 1);
+        pool.data[start + 1 + len] = k;
+    }
+
+    /// Empty the list and give its block back to the pool.
+    pub fn clear(&mut self, pool: &mut ListPool<K>) {
+        if let Some(s) = self.start() {
+            pool.release(s, class_for(pool.data[s].index()));
+            self.index = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_grow_across_size_classes_and_reuse_freed_blocks() {
+        let mut pool = ListPool::<u32>::new();
+        let mut a = EntityList::new();
+        let mut b = EntityList::new();
+        for i in 0..10 {
+            a.push(i, &mut pool);
+            b.push(100 + i, &mut pool);
+        }
+        assert_eq!(a.as_slice(&pool), (0..10).collect::<Vec<_>>());
+        assert_eq!(b.len(&pool), 10);
+        assert_eq!(b.as_slice(&pool)[9], 109);
+
+        let before = pool.capacity();
+        a.clear(&mut pool);
+        assert!(a.is_empty());
+        // Same size class as the cleared list: no new storage.
+        let mut c = EntityList::new();
+        for i in 0..10 {
+            c.push(i, &mut pool);
+        }
+        assert_eq!(pool.capacity(), before);
+        c.as_mut_slice(&mut pool)[0] = 7;
+        assert_eq!(c.as_slice(&pool)[..2], [7, 1]);
+        assert_eq!(b.as_slice(&pool)[0], 100);
+    }
+}
@@ -1,2 +1,7 @@
+pub mod bit_matrix;
 pub mod bitset;
+pub mod entity_list;
 pub mod slotmap;
+pub mod sparse_bitset;
+pub mod trace;
+pub mod union_find;
@@ -15,8 +15,14 @@ pub trait Key: Sized + Copy + PartialEq {
     }
 }
 
+/// Owning map that hands out keys. Removed slots go on a free list and
+/// their keys are reissued by later inserts, so a key must not be used
+/// after its entry is removed.
+#[derive(Clone, Debug)]
 pub struct PrimaryMap<K: Key, V> {
     values: Vec<Option<V>>,
+    /// Indices of removed slots, reused last-in first-out.
+    free: Vec<usize>,
     _key: PhantomData<K>,
 }
 
@@ -31,23 +37,72 @@ impl<K: Key, V> PrimaryMap<K, V> {
     pub fn new() -> Self {
         Self {
             values: Vec::new(),
+            free: Vec::new(),
             _key: PhantomData,
         }
     }
 
+    #[must_use]
+    pub fn with_capacity(cap: usize) -> Self {
+        Self {
+            values: Vec::with_capacity(cap),
+            free: Vec::new(),
+            _key: PhantomData,
+        }
+    }
+
+    /// Make room for `additional` more inserts without reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        self.values.reserve(additional);
+    }
+
     pub fn insert(&mut self, val: V) -> K {
+        if let Some(i) = self.free.pop() {
+            self.values[i] = Some(val);
+            return K::new(i);
+        }
         self.values.push(Some(val));
         K::new(self.values.len() - 1)
     }
 
+    /// Drop every entry keyed `len` or above, as if they were never
+    /// issued.
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+        self.free.retain(|&i| i < len);
+    }
+
+    /// Take the entry out and free its key for reuse. `None` if `key` was
+    /// never issued or is already removed.
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let val = self.values.get_mut(key.index())?.take()?;
+        self.free.push(key.index());
+        Some(val)
+    }
+
+    #[must_use]
+    pub fn contains(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    #[must_use]
+    pub fn get(&self, key: K) -> Option<&V> {
+        self.values.get(key.index())?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        self.values.get_mut(key.index())?.as_mut()
+    }
+
     #[must_use]
     pub fn iter(&self) -> PrimaryMapIter<'_, K, V> {
         PrimaryMapIter { map: self, idx: 0 }
     }
 
+    /// Number of live entries.
     #[must_use]
     pub fn len(&self) -> usize {
-        self.values.len()
+        self.values.len() - self.free.len()
     }
 
     #[must_use]
@@ -55,8 +110,20 @@ impl<K: Key, V> PrimaryMap<K, V> {
         self.len() == 0
     }
 
+    /// One past the largest key ever issued — the size a `SecondaryMap`
+    /// needs to cover every key. Equals `len()` while nothing is removed.
+    #[must_use]
+    pub fn key_bound(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Live keys, ascending.
     pub fn keys(&self) -> impl Iterator<Item=K> {
-        (0..self.values.len()).map(K::new)
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.is_some())
+            .map(|(i, _)| K::new(i))
     }
 }
 
@@ -64,13 +131,29 @@ impl<K: Key, V> Index<K> for PrimaryMap<K, V> {
     type Output = V;
 
     fn index(&self, index: K) -> &Self::Output {
-        self.values[index.index()].as_ref().unwrap()
+        self.values[index.index()].as_ref().expect("key was removed")
     }
 }
 
 impl<K: Key, V> IndexMut<K> for PrimaryMap<K, V> {
     fn index_mut(&mut self, index: K) -> &mut Self::Output {
-        self.values[index.index()].as_mut().unwrap()
+        self.values[index.index()].as_mut().expect("key was removed")
+    }
+}
+
+impl<K: Key, V> IntoIterator for PrimaryMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::iter::FilterMap<
+        std::iter::Enumerate<std::vec::IntoIter<Option<V>>>,
+        fn((usize, Option<V>)) -> Option<(K, V)>,
+    >;
+
+    /// Consume the map, yielding live entries in key order.
+    fn into_iter(self) -> Self::IntoIter {
+        self.values
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| (K::new(i), v)))
     }
 }
 
@@ -130,7 +213,10 @@ macro_rules! slotmap_key {
             const NONE_VAL: Self = Self(<$inner_type>::MAX);
 
             fn new(v: usize) -> Self {
-                Self(v as $inner_type)
+                Self(<$inner_type>::try_from(v).expect(concat!(
+                    stringify!($key),
+                    " index overflows its index type"
+                )))
             }
 
             fn index(&self) -> usize {
@@ -140,7 +226,7 @@ macro_rules! slotmap_key {
     };
 }
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SecondaryMap<K: Key, V> {
     values: Vec<Option<V>>,
     phantom: PhantomData<K>,
@@ -160,11 +246,21 @@ impl<K: Key, V: Clone> SecondaryMap<K, V> {
         }
     }
 
+    /// Store `val` at `key`, growing the map if `key` is past the end.
     pub fn set(&mut self, key: K, val: V) -> K {
+        self.grow_to(key.index() + 1);
         self.values[key.index()] = Some(val);
         key
     }
 
+    /// Make room for keys below `len`; new slots start empty. Never
+    /// shrinks.
+    pub fn grow_to(&mut self, len: usize) {
+        if self.values.len() < len {
+            self.values.resize_with(len, || None);
+        }
+    }
+
     pub fn remove(&mut self, key: K) {
         self.values[key.index()] = None;
     }
@@ -194,20 +290,26 @@ impl<K: Key, V: Clone> SecondaryMap<K, V> {
         self.values.len()
     }
 
-    pub fn iter(&self) -> impl Iterator<Item=(K, &V)> {
-        self.values.iter().enumerate().filter_map(|(i, v)| {
-            v.as_ref().map(|v| (K::new(i), v))
-        })
+    pub fn keys(&self) -> impl Iterator<Item=K> {
+        (0..self.values.len()).map(K::new)
     }
+}
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item=(K, &mut V)> {
-        self.values.iter_mut().enumerate().filter_map(|(i, v)| {
-            v.as_mut().map(|v| (K::new(i), v))
-        })
+impl<K: Key, V> SecondaryMap<K, V> {
+    /// Occupied entries as `(key, &value)`, in key order.
+    pub fn iter(&self) -> SecondaryMapIter<'_, K, V> {
+        SecondaryMapIter {
+            inner: self.values.iter().enumerate(),
+            _key: PhantomData,
+        }
     }
 
-    pub fn keys(&self) -> impl Iterator<Item=K> {
-        (0..self.values.len()).map(K::new)
+    /// Occupied entries as `(key, &mut value)`, in key order.
+    pub fn iter_mut(&mut self) -> SecondaryMapIterMut<'_, K, V> {
+        SecondaryMapIterMut {
+            inner: self.values.iter_mut().enumerate(),
+            _key: PhantomData,
+        }
     }
 
     pub fn values(&self) -> impl Iterator<Item=&V> {
@@ -219,13 +321,71 @@ impl<K: Key, V: Clone> SecondaryMap<K, V> {
     }
 }
 
+pub struct SecondaryMapIter<'a, K: Key, V> {
+    inner: std::iter::Enumerate<std::slice::Iter<'a, Option<V>>>,
+    _key: PhantomData<K>,
+}
+
+impl<'a, K: Key, V> Iterator for SecondaryMapIter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .by_ref()
+            .find_map(|(i, v)| v.as_ref().map(|v| (K::new(i), v)))
+    }
+}
+
+pub struct SecondaryMapIterMut<'a, K: Key, V> {
+    inner: std::iter::Enumerate<std::slice::IterMut<'a, Option<V>>>,
+    _key: PhantomData<K>,
+}
+
+impl<'a, K: Key, V> Iterator for SecondaryMapIterMut<'a, K, V> {
+    type Item = (K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .by_ref()
+            .find_map(|(i, v)| v.as_mut().map(|v| (K::new(i), v)))
+    }
+}
+
+impl<'a, K: Key, V> IntoIterator for &'a SecondaryMap<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = SecondaryMapIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Key, V> IntoIterator for &'a mut SecondaryMap<K, V> {
+    type Item = (K, &'a mut V);
+    type IntoIter = SecondaryMapIterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<K: Key, V: Default> SecondaryMap<K, V> {
+    /// The value at `key`, inserting `V::default()` first if the slot is
+    /// empty or past the end of the map.
+    pub fn get_or_default(&mut self, key: K) -> &mut V {
+        let i = key.index();
+        if i >= self.values.len() {
+            self.values.resize_with(i + 1, || None);
+        }
+        self.values[i].get_or_insert_with(V::default)
+    }
+}
+
+/// Mutable indexing never fails: missing slots, including ones past the
+/// end, are filled with `V::default()` (see `get_or_default`).
 impl<K: Key, V: Default> IndexMut<K> for SecondaryMap<K, V> {
     fn index_mut(&mut self, index: K) -> &mut Self::Output {
-        assert!(index.index() < self.values.len());
-        if self.values[index.index()].is_none() {
-            self.values[index.index()] = Some(Default::default());
-        }
-        self.values[index.index()].as_mut().unwrap()
+        self.get_or_default(index)
     }
 }
 
@@ -260,6 +420,27 @@ mod tests {
         assert_eq!(map[key], "value");
     }
 
+    #[test]
+    fn test_primary_map_remove_reuses_keys() {
+        let mut map = PrimaryMap::new();
+        let a: K = map.insert("a");
+        let b: K = map.insert("b");
+        let c: K = map.insert("c");
+        assert_eq!(map.remove(b), Some("b"));
+        assert_eq!(map.remove(b), None);
+        assert!(!map.contains(b));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.key_bound(), 3);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![a, c]);
+        assert_eq!(map.iter().count(), 2);
+
+        let d: K = map.insert("d");
+        assert_eq!(d, b);
+        assert_eq!(map[d], "d");
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.key_bound(), 3);
+    }
+
     #[test]
     fn test_secondary_map() {
         let mut map = SecondaryMap::new(10);
@@ -300,4 +481,33 @@ mod tests {
         assert_eq!(iter.next(), Some((K::new(0), &mut "value")));
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_secondary_map_grows_on_demand() {
+        let mut map: SecondaryMap<K, u32> = SecondaryMap::new(2);
+        map[K::new(5)] += 3;
+        assert_eq!(map.capacity(), 6);
+        assert_eq!(map[K::new(5)], 3);
+        assert!(!map.contains(K::new(4)));
+
+        map.set(K::new(9), 1);
+        assert_eq!(map.capacity(), 10);
+        *map.get_or_default(K::new(4)) += 2;
+        assert_eq!(map.get(K::new(4)), Some(&2));
+
+        map.grow_to(3);
+        assert_eq!(map.capacity(), 10);
+    }
+
+    #[test]
+    fn test_secondary_map_for_loops_skip_empty_slots() {
+        let mut map: SecondaryMap<K, u32> = SecondaryMap::new(5);
+        map.set(K::new(1), 10);
+        map.set(K::new(3), 30);
+        for (k, v) in &mut map {
+            *v += k.0;
+        }
+        let seen: Vec<(K, u32)> = (&map).into_iter().map(|(k, &v)| (k, v)).collect();
+        assert_eq!(seen, vec![(K::new(1), 11), (K::new(3), 33)]);
+    }
 }
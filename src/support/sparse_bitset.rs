@@ -0,0 +1,198 @@
+//! Chunked sparse bitset.
+//!
+//! Stores only the non-zero 64-bit words, as `(word index, word)` pairs
+//! sorted by index. Memory is proportional to the number of populated
+//! words rather than the universe size, which is what per-block liveness
+//! sets want in large functions: each block sees a small fraction of
+//! all vregs. Set operations are linear merges over the two pair lists.
+
+use std::cmp::Ordering;
+
+use smallvec::SmallVec;
+
+use crate::support::bitset::{BitSet, Ones, Word};
+
+const BITS: usize = Word::BITS as usize;
+
+#[derive(Clone, Default, Debug, PartialEq, Eq, Hash)]
+pub struct SparseBitSet {
+    chunks: SmallVec<[(usize, Word); 2]>,
+}
+
+impl SparseBitSet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn find(&self, chunk: usize) -> Result<usize, usize> {
+        self.chunks.binary_search_by_key(&chunk, |&(c, _)| c)
+    }
+
+    pub fn add(&mut self, index: usize) {
+        let (chunk, bit) = (index / BITS, 1 << (index % BITS));
+        match self.find(chunk) {
+            Ok(pos) => self.chunks[pos].1 |= bit,
+            Err(pos) => self.chunks.insert(pos, (chunk, bit)),
+        }
+    }
+
+    pub fn del(&mut self, index: usize) {
+        if let Ok(pos) = self.find(index / BITS) {
+            self.chunks[pos].1 &= !(1 << (index % BITS));
+            if self.chunks[pos].1 == 0 {
+                self.chunks.remove(pos);
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn has(&self, index: usize) -> bool {
+        self.find(index / BITS)
+            .is_ok_and(|pos| self.chunks[pos].1 & (1 << (index % BITS)) != 0)
+    }
+
+    #[must_use]
+    pub fn ones_count(&self) -> usize {
+        self.chunks
+            .iter()
+            .map(|(_, w)| w.count_ones() as usize)
+            .sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Number of words actually stored.
+    #[must_use]
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// `self |= other`; returns `true` if any bit was added.
+    pub fn union_with(&mut self, other: &SparseBitSet) -> bool {
+        let mut merged = SmallVec::with_capacity(self.chunks.len().max(other.chunks.len()));
+        let (mut i, mut j) = (0, 0);
+        let mut changed = false;
+        while i < self.chunks.len() && j < other.chunks.len() {
+            let ((ca, wa), (cb, wb)) = (self.chunks[i], other.chunks[j]);
+            match ca.cmp(&cb) {
+                Ordering::Less => {
+                    merged.push((ca, wa));
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    merged.push((cb, wb));
+                    j += 1;
+                    changed = true;
+                }
+                Ordering::Equal => {
+                    changed |= wb & !wa != 0;
+                    merged.push((ca, wa | wb));
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        merged.extend_from_slice(&self.chunks[i..]);
+        changed |= j < other.chunks.len();
+        merged.extend_from_slice(&other.chunks[j..]);
+        self.chunks = merged;
+        changed
+    }
+
+    /// `self &= !other`.
+    pub fn difference(&mut self, other: &SparseBitSet) {
+        let mut j = 0;
+        self.chunks.retain(|(c, w)| {
+            while j < other.chunks.len() && other.chunks[j].0 < *c {
+                j += 1;
+            }
+            if let Some(&(oc, ow)) = other.chunks.get(j)
+                && oc == *c
+            {
+                *w &= !ow;
+            }
+            *w != 0
+        });
+    }
+
+    /// Set bits, ascending.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.chunks.iter().flat_map(|&(c, w)| Ones {
+            word: w,
+            base: c * BITS,
+        })
+    }
+}
+
+impl BitSet for SparseBitSet {
+    fn empty(_size: usize) -> Self {
+        Self::new()
+    }
+
+    fn add(&mut self, index: usize) {
+        SparseBitSet::add(self, index);
+    }
+
+    fn has(&self, index: usize) -> bool {
+        SparseBitSet::has(self, index)
+    }
+
+    fn union(&mut self, other: &Self) {
+        SparseBitSet::union_with(self, other);
+    }
+
+    fn union_with(&mut self, other: &Self) -> bool {
+        SparseBitSet::union_with(self, other)
+    }
+
+    fn difference(&mut self, other: &Self) {
+        SparseBitSet::difference(self, other);
+    }
+
+    fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        SparseBitSet::iter_ones(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(bits: &[usize]) -> SparseBitSet {
+        let mut s = SparseBitSet::new();
+        for &b in bits {
+            s.add(b);
+        }
+        s
+    }
+
+    #[test]
+    fn only_populated_words_are_stored() {
+        let mut s = set(&[3, 1_000_000, 5, 64]);
+        assert_eq!(s.chunk_count(), 3);
+        assert!(s.has(1_000_000) && s.has(64) && !s.has(65));
+        assert_eq!(s.iter_ones().collect::<Vec<_>>(), vec![3, 5, 64, 1_000_000]);
+        s.del(64);
+        assert_eq!(s.chunk_count(), 2);
+        assert_eq!(s.ones_count(), 3);
+    }
+
+    #[test]
+    fn union_and_difference_merge_chunk_lists() {
+        let mut a = set(&[1, 200, 5000]);
+        let b = set(&[2, 200, 9000]);
+        assert!(a.union_with(&b));
+        assert!(!a.union_with(&b));
+        assert_eq!(
+            a.iter_ones().collect::<Vec<_>>(),
+            vec![1, 2, 200, 5000, 9000]
+        );
+        a.difference(&set(&[1, 2, 9000, 70_000]));
+        assert_eq!(a.iter_ones().collect::<Vec<_>>(), vec![200, 5000]);
+        assert_eq!(a.chunk_count(), 2);
+    }
+}
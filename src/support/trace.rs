@@ -0,0 +1,42 @@
+//! Feature-gated `tracing` instrumentation.
+//!
+//! With the `tracing` feature these forward to the `tracing` crate; without
+//! it they expand to nothing, so instrumented code pays no cost and needs
+//! no `cfg` of its own. Arguments use `tracing`'s field syntax
+//! (`vreg = v, range = ?segs, "message"`) and are only type-checked when
+//! the feature is on — keep them to plain expressions the surrounding code
+//! already computes.
+
+/// `tracing::debug!` — decisions worth explaining (assign, evict, spill).
+#[cfg(feature = "tracing")]
+macro_rules! debug_event {
+    ($($arg:tt)*) => { ::tracing::debug!($($arg)*) };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug_event {
+    ($($arg:tt)*) => {};
+}
+
+/// `tracing::trace!` — per-item detail (every live range, every idom).
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($($arg:tt)*) => { ::tracing::trace!($($arg)*) };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($($arg:tt)*) => {};
+}
+
+/// Enter a `debug`-level span for the rest of the enclosing block.
+#[cfg(feature = "tracing")]
+macro_rules! enter_span {
+    ($($arg:tt)*) => {
+        let _span = ::tracing::debug_span!($($arg)*).entered();
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! enter_span {
+    ($($arg:tt)*) => {};
+}
+
+pub(crate) use {debug_event, enter_span, trace_event};
@@ -0,0 +1,120 @@
+//! Disjoint-set forest over dense keys.
+//!
+//! Groups keys into equivalence classes with near-constant-time `union`
+//! and `find` (union by rank plus path halving). Used to merge vregs
+//! related by copies into one class so they can share a register.
+
+use std::marker::PhantomData;
+
+use crate::support::slotmap::Key;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UnionFind<K: Key> {
+    parent: Vec<u32>,
+    rank: Vec<u8>,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key> Default for UnionFind<K> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl<K: Key> UnionFind<K> {
+    /// `len` singleton classes, one per key below `len`.
+    #[must_use]
+    pub fn new(len: usize) -> Self {
+        let mut uf = Self {
+            parent: Vec::new(),
+            rank: Vec::new(),
+            _key: PhantomData,
+        };
+        uf.grow_to(len);
+        uf
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+    /// Add singleton classes for every key below `len`.
+    pub fn grow_to(&mut self, len: usize) {
+        let start = self.parent.len();
+        if len > start {
+            self.parent.extend(
+                (start..len).map(|i| u32::try_from(i).expect("union-find exceeds u32 keys")),
+            );
+            self.rank.resize(len, 0);
+        }
+    }
+
+    /// Representative of `k`'s class. Keys past the end are singletons.
+    pub fn find(&mut self, k: K) -> K {
+        let mut x = k.index();
+        if x >= self.parent.len() {
+            return k;
+        }
+        while self.parent[x] as usize != x {
+            let grandparent = self.parent[self.parent[x] as usize];
+            self.parent[x] = grandparent;
+            x = grandparent as usize;
+        }
+        K::new(x)
+    }
+
+    /// Merge the classes of `a` and `b`; returns `false` if they were
+    /// already one class.
+    pub fn union(&mut self, a: K, b: K) -> bool {
+        self.grow_to(a.index().max(b.index()) + 1);
+        let (ra, rb) = (self.find(a).index(), self.find(b).index());
+        if ra == rb {
+            return false;
+        }
+        let (child, root) = if self.rank[ra] < self.rank[rb] {
+            (ra, rb)
+        } else {
+            (rb, ra)
+        };
+        self.parent[child] = u32::try_from(root).expect("union-find exceeds u32 keys");
+        if self.rank[ra] == self.rank[rb] {
+            self.rank[root] += 1;
+        }
+        true
+    }
+
+    #[must_use]
+    pub fn same_set(&mut self, a: K, b: K) -> bool {
+        self.find(a) == self.find(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unions_merge_classes_transitively() {
+        let mut uf = UnionFind::<u32>::new(6);
+        assert!(uf.union(0, 1));
+        assert!(uf.union(2, 3));
+        assert!(uf.union(1, 3));
+        assert!(!uf.union(0, 2));
+        assert!(uf.same_set(0, 3));
+        assert!(!uf.same_set(0, 4));
+        assert_eq!(uf.find(5), 5);
+
+        // Keys past the end are singletons until merged.
+        assert_eq!(uf.find(9), 9);
+        assert!(uf.union(9, 4));
+        assert_eq!(uf.len(), 10);
+        assert!(uf.same_set(4, 9));
+        assert!(!uf.same_set(4, 0));
+    }
+}
@@ -0,0 +1,197 @@
+//! Golden tests: every `tests/filecheck/*.tir` file is compiled and its
+//! output matched against the file's embedded directives.
+//!
+//! * `; RUN: <flags>` — how to compile: `--emit=tir` (the parsed IR) or
+//!   `--emit=asm` (the disassembly, default), plus `-O0`, `-Os`,
+//!   `--no-coalesce`, `--switch-lowering=table|tree`,
+//!   `--speculation-hardening=retpoline|lfence`, `--stack-protector`,
+//!   `--frame-pointer=omit`, `--cet` and `--coverage`.
+//! * `; CHECK: <text>` — a later output line contains `<text>`.
+//!   `CHECK-LABEL` behaves the same and marks a function boundary.
+//! * `; CHECK-NEXT: <text>` — the line right after the previous match
+//!   contains `<text>`.
+//! * `; CHECK-NOT: <text>` — no line between the surrounding matches
+//!   contains `<text>`.
+//!
+//! Matching is substring-based with runs of whitespace collapsed on both
+//! sides. Adding a regression test is dropping a new file in the
+//! directory.
+
+use std::path::Path;
+
+use lancy::codegen::isa::Target;
+use lancy::codegen::isa::x64::mc::disasm::disassemble;
+use lancy::codegen::isa::x64::parser::parse_module;
+use lancy::codegen::isa::x64::pipeline::compile_function;
+use lancy::codegen::options::{
+    CodegenOptions, FramePointer, OptLevel, SpeculationHardening, SwitchLowering,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Check,
+    Next,
+    Not,
+}
+
+struct Directive {
+    kind: Kind,
+    pattern: String,
+    line: usize,
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn directives(src: &str) -> (Vec<String>, Vec<Directive>) {
+    let mut run = Vec::new();
+    let mut checks = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let Some(comment) = line.split_once(';').map(|(_, c)| c.trim()) else {
+            continue;
+        };
+        let Some((tag, rest)) = comment.split_once(':') else {
+            continue;
+        };
+        let kind = match tag {
+            "RUN" => {
+                run.extend(rest.split_whitespace().map(str::to_string));
+                continue;
+            }
+            "CHECK" | "CHECK-LABEL" => Kind::Check,
+            "CHECK-NEXT" => Kind::Next,
+            "CHECK-NOT" => Kind::Not,
+            _ => continue,
+        };
+        checks.push(Directive {
+            kind,
+            pattern: normalize(rest),
+            line: i + 1,
+        });
+    }
+    (run, checks)
+}
+
+fn compile(src: &str, run: &[String]) -> Result<String, String> {
+    let mut options = CodegenOptions::default();
+    let mut emit_tir = false;
+    for flag in run {
+        match flag.as_str() {
+            "--emit=tir" => emit_tir = true,
+            "--emit=asm" => emit_tir = false,
+            "-O0" => options.opt_level = OptLevel::None,
+            "-Os" => options.opt_level = OptLevel::Size,
+            "--no-coalesce" => options.coalesce = false,
+            "--switch-lowering=table" => options.switch_lowering = SwitchLowering::JumpTable,
+            "--switch-lowering=tree" => options.switch_lowering = SwitchLowering::CompareTree,
+            "--stack-protector" => options.stack_protector = Some("__stack_chk_fail".into()),
+            "--cet" => options.cet = true,
+            "--coverage" => options.coverage = true,
+            "--speculation-hardening=retpoline" => {
+                options.speculation_hardening = SpeculationHardening::Retpoline;
+            }
+            "--speculation-hardening=lfence" => {
+                options.speculation_hardening = SpeculationHardening::Lfence;
+            }
+            "--frame-pointer=omit" => options.frame_pointer = FramePointer::Omit,
+            _ => return Err(format!("unsupported RUN flag `{flag}`")),
+        }
+    }
+    let funcs = parse_module(src).map_err(|e| e.to_string())?;
+    Ok(funcs
+        .into_iter()
+        .map(|f| {
+            if emit_tir {
+                f.to_string()
+            } else {
+                disassemble(&compile_function(f, Target::X64SysV, &options))
+            }
+        })
+        .collect())
+}
+
+/// Match `checks` against `output`; the first failure as a message.
+fn check(output: &str, checks: &[Directive]) -> Result<(), String> {
+    let lines: Vec<String> = output.lines().map(normalize).collect();
+    let mut cursor = 0;
+    let mut pending_not: Vec<&Directive> = Vec::new();
+    for d in checks {
+        let found = match d.kind {
+            Kind::Not => {
+                pending_not.push(d);
+                continue;
+            }
+            Kind::Check => (cursor..lines.len()).find(|&i| lines[i].contains(&d.pattern)),
+            Kind::Next => {
+                (cursor < lines.len() && lines[cursor].contains(&d.pattern)).then_some(cursor)
+            }
+        };
+        let Some(at) = found else {
+            let what = if d.kind == Kind::Next {
+                "CHECK-NEXT"
+            } else {
+                "CHECK"
+            };
+            return Err(format!(
+                "line {}: {what} `{}` not found after output line {cursor}",
+                d.line, d.pattern
+            ));
+        };
+        check_not(&lines[cursor..at], &mut pending_not)?;
+        cursor = at + 1;
+    }
+    check_not(&lines[cursor..], &mut pending_not)
+}
+
+fn check_not(region: &[String], pending: &mut Vec<&Directive>) -> Result<(), String> {
+    for d in pending.drain(..) {
+        if let Some(hit) = region.iter().find(|l| l.contains(&d.pattern)) {
+            return Err(format!(
+                "line {}: CHECK-NOT `{}` matched `{hit}`",
+                d.line, d.pattern
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn filecheck_golden_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/filecheck");
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .expect("tests/filecheck exists")
+        .map(|e| e.expect("readable dir entry").path())
+        .filter(|p| p.extension().is_some_and(|e| e == "tir"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no .tir files in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let src = std::fs::read_to_string(path).expect("readable test file");
+        let (run, checks) = directives(&src);
+        let result = if checks.is_empty() {
+            Err("no CHECK directives".to_string())
+        } else {
+            compile(&src, &run).and_then(|out| {
+                check(&out, &checks).map_err(|e| format!("{e}\n--- output ---\n{out}"))
+            })
+        };
+        if let Err(e) = result {
+            failures.push(format!("{}: {e}", path.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn check_directives_follow_filecheck_ordering() {
+    let out = "f:\n  mov r11, 2\n  add r11, r10\n  ret\n";
+    let (_, ok) = directives("; CHECK: mov r11, 2\n; CHECK-NEXT: add\n; CHECK-NOT: call\n");
+    assert!(check(out, &ok).is_ok());
+    let (_, next) = directives("; CHECK: f:\n; CHECK-NEXT: add\n");
+    assert!(check(out, &next).is_err());
+    let (_, not) = directives("; CHECK: f:\n; CHECK-NOT: add\n; CHECK: ret\n");
+    assert!(check(out, &not).is_err());
+}